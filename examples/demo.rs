@@ -0,0 +1,218 @@
+use std::{collections::HashMap, rc::Rc};
+
+use anyhow::Result;
+use cgmath::prelude::*;
+use serde::Deserialize;
+use wgpu_demo::{prelude::*, render_pipeline, weather};
+
+fn load_model<P>(
+    obj_file: &str,
+    mtl_file: Option<&str>,
+    positions: &[P],
+    gpu_state: &GpuState,
+) -> model::Model
+where
+    P: Into<Point3> + Copy,
+{
+    let instances: Vec<_> = positions
+        .iter()
+        .map(|p| model::Instance::new((*p).into(), Quat::from_axis_angle(Vec3::unit_z(), deg(0.0))))
+        .collect();
+
+    resources::load_model_sync(
+        obj_file,
+        mtl_file,
+        &gpu_state.device,
+        &gpu_state.queue,
+        &instances,
+        false,
+        false,
+    )
+    .unwrap()
+}
+
+// A scene manifest, see --scene, which replaces the demo's floor of cubes with its models,
+// e.g. examples/demo_scene.json. The lights, camera and weather stay as they are.
+#[derive(Deserialize)]
+struct SceneManifest {
+    models: Vec<ManifestModel>,
+}
+
+// A model to load from the res directory, with an instance at each of `positions`
+#[derive(Deserialize)]
+struct ManifestModel {
+    obj: String,
+    mtl: Option<String>,
+    positions: Vec<[f32; 3]>,
+}
+
+impl SceneManifest {
+    fn read(path: &std::path::Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+const ID_LIGHT_AMBIENT: usize = 0;
+const ID_LIGHT_PRIMARY: usize = 1;
+const ID_LIGHT_POINT: usize = 2;
+const ID_LIGHT_SPOT: usize = 3;
+
+const ID_MODEL_CUBE_FLOOR: usize = 0;
+
+fn main() {
+    env_logger::init();
+
+    let config = app::AppConfig::from_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, app::AppConfig::USAGE);
+        std::process::exit(1);
+    });
+    let scene_manifest = config.scene_manifest.as_ref().map(|path| {
+        SceneManifest::read(path).unwrap_or_else(|e| {
+            eprintln!(
+                "Unable to read scene manifest \"{}\": {}",
+                path.display(),
+                e
+            );
+            std::process::exit(1);
+        })
+    });
+
+    pollster::block_on(app::run(
+        config,
+        |_window, gpu_state| {
+            let environment_map = Rc::new(
+                resources::load_cubemap_texture_sync(
+                    "env-map.dds",
+                    &gpu_state.device,
+                    &gpu_state.queue,
+                )
+                .unwrap(),
+            );
+
+            let models = match &scene_manifest {
+                Some(manifest) => manifest
+                    .models
+                    .iter()
+                    .enumerate()
+                    .map(|(id, model)| {
+                        (
+                            id,
+                            load_model(
+                                &model.obj,
+                                model.mtl.as_deref(),
+                                &model.positions,
+                                gpu_state,
+                            ),
+                        )
+                    })
+                    .collect(),
+                None => {
+                    let mut positions = vec![];
+                    for x in 0..50 {
+                        for z in 0..50 {
+                            positions.push((x as f32 * 2.5, 0_f32, z as f32 * 2.5))
+                        }
+                    }
+                    HashMap::from([(
+                        ID_MODEL_CUBE_FLOOR,
+                        load_model("cube.obj", Some("untextured.mtl"), &positions, gpu_state),
+                    )])
+                }
+            };
+
+            let ambient_light = light::Light::new_ambient(
+                &gpu_state.device,
+                &light::AmbientLightDescriptor {
+                    ambient: [0.05; 3].into(),
+                },
+            );
+
+            let point_light = light::Light::new_point(
+                &gpu_state.device,
+                &light::PointLightDescriptor {
+                    position: (62.5, 4.0, 62.5).into(),
+                    ambient: (0.0, 0.0, 0.0).into(),
+                    color: (1.0, 0.0, 0.0).into(),
+                    constant_attenuation: 1_f32,
+                    linear_attenuation: 0_f32,
+                    exponential_attenuation: 0.05_f32,
+                },
+            );
+
+            let mut directional_light = light::Light::new_directional(
+                &gpu_state.device,
+                &light::DirectionalLightDescriptor {
+                    direction: (1.0, 1.0, 0.0).into(),
+                    ambient: (0.0, 0.0, 0.0).into(),
+                    color: (0.0, 0.0, 1.0).into(),
+                    constant_attenuation: 1.0,
+                    shadows: Some(shadow::ShadowDescriptor {
+                        range: 70.0,
+                        ..Default::default()
+                    }),
+                },
+            );
+            // center the directional light's shadow volume on the cube floor
+            directional_light.set_position((62.5, 0.0, 62.5));
+
+            let spot_light = light::Light::new_spot(
+                &gpu_state.device,
+                &light::SpotLightDescriptor {
+                    position: (62.5, 4.0, 62.5).into(),
+                    direction: (1.0, -1.0, 0.0).into(),
+                    ambient: (0.0, 0.0, 0.0).into(),
+                    color: (0.0, 1.0, 0.0).into(),
+                    constant_attenuation: 1_f32,
+                    linear_attenuation: 0_f32,
+                    exponential_attenuation: 0_f32,
+                    spot_breadth: deg(75_f32),
+                    shadows: Some(shadow::ShadowDescriptor {
+                        range: 30.0,
+                        mode: shadow::ShadowMode::Variance,
+                        ..Default::default()
+                    }),
+                },
+            );
+
+            let lights = HashMap::from([
+                (ID_LIGHT_AMBIENT, ambient_light),
+                (ID_LIGHT_PRIMARY, directional_light),
+                (ID_LIGHT_POINT, point_light),
+                (ID_LIGHT_SPOT, spot_light),
+            ]);
+
+            let mut camera = camera::Camera::new(gpu_state, deg(45.0), 0.5, 500.0);
+            camera.look_at((60.0, 4.0, 60.0), (62.5, 0.0, 62.5), (0.0, 1.0, 0.0));
+            // the far plane is distant enough for standard depth to z-fight
+            camera.set_depth_mode(render_pipeline::DepthMode::Reversed);
+
+            let mut scene = scene::Scene::new(gpu_state, camera, environment_map, lights, models);
+            scene.weather = Some(weather::Weather::new(
+                gpu_state,
+                &scene.camera.render_buffers,
+                &weather::WeatherDescriptor {
+                    kind: weather::WeatherKind::Rain,
+                    intensity: 0.5,
+                    seed: scene.random.next_u32(),
+                    ..Default::default()
+                },
+            ));
+            let wind = scene.environment.wind_mut();
+            wind.set_strength(1.5);
+            wind.set_gust_strength(2.0);
+            scene
+        },
+        |scene| {
+            let seconds = scene.time().as_secs_f32();
+            let cycle = (seconds).cos();
+
+            if let Some(point_light) = scene.lights.get_mut(&ID_LIGHT_POINT) {
+                let mut light_pos = point_light.position();
+                light_pos.y = 4.0 + cycle * 3.0;
+
+                point_light.set_position(light_pos);
+            }
+        },
+    ));
+}
@@ -0,0 +1,146 @@
+#include "shaders/material.wgsl"
+
+//
+//  Uniforms
+//
+
+struct BakeUniform {
+    // x: curvature scale
+    params: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> material: Material;
+
+#ifdef HAS_DIFFUSE_TEXTURE
+@group(0) @binding(3)
+var diffuse_texture: texture_2d<f32>;
+
+@group(0) @binding(4)
+var diffuse_sampler: sampler;
+#endif
+
+#ifdef HAS_NORMAL_TEXTURE
+@group(0) @binding(5)
+var normal_texture: texture_2d<f32>;
+
+@group(0) @binding(6)
+var normal_sampler: sampler;
+#endif
+
+#ifdef HAS_AMBIENT_OCCLUSION_TEXTURE
+@group(0) @binding(9)
+var ambient_occlusion_texture: texture_2d<f32>;
+
+@group(0) @binding(10)
+var ambient_occlusion_sampler: sampler;
+#endif
+
+@group(1) @binding(0)
+var<uniform> bake: BakeUniform;
+
+//
+//  Vertex
+//
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) tangent: vec3<f32>,
+    @location(3) bitangent: vec3<f32>,
+    @location(4) tex_coords: vec2<f32>,
+};
+
+// Matches model.wgsl's transform_tex_coords
+fn transform_tex_coords(tex_coords: vec2<f32>) -> vec2<f32> {
+    let scaled = tex_coords * material.uv_transform.zw;
+    let c = cos(material.uv_rotation);
+    let s = sin(material.uv_rotation);
+    return vec2<f32>(c * scaled.x - s * scaled.y, s * scaled.x + c * scaled.y) + material.uv_transform.xy;
+}
+
+// Places each vertex at its texture coordinates, so the mesh is rasterized in its UV layout,
+// with v running down the texture as it does when sampling. Coordinates outside [0,1] fall
+// off the texture; they can't be wrapped per vertex without tearing triangles apart.
+@vertex
+fn vs_main_bake(vertex: VertexInput) -> VertexOutput {
+    let uv = vertex.tex_coords;
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.position = vertex.position;
+    out.normal = vertex.normal;
+    out.tangent = vertex.tangent;
+    out.bitangent = vertex.bitangent;
+    out.tex_coords = transform_tex_coords(vertex.tex_coords);
+    return out;
+}
+
+//
+//  Fragment
+//
+
+fn surface_normal(in: VertexOutput) -> vec3<f32> {
+#ifdef HAS_NORMAL_TEXTURE
+    let tangent_to_model = mat3x3<f32>(in.tangent, in.bitangent, in.normal);
+    let tangent_normal = textureSample(normal_texture, normal_sampler, in.tex_coords).xyz * 2.0 - 1.0;
+    return normalize(tangent_to_model * tangent_normal);
+#else
+    return normalize(in.normal);
+#endif
+}
+
+@fragment
+fn fs_main_bake_albedo(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef HAS_DIFFUSE_TEXTURE
+    return material.diffuse * textureSample(diffuse_texture, diffuse_sampler, in.tex_coords);
+#else
+    return material.diffuse;
+#endif
+}
+
+// The model space normal, mapped from [-1,1] to [0,1]
+@fragment
+fn fs_main_bake_normal(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(surface_normal(in) * 0.5 + 0.5, 1.0);
+}
+
+@fragment
+fn fs_main_bake_ambient_occlusion(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef HAS_AMBIENT_OCCLUSION_TEXTURE
+    let occlusion = textureSample(ambient_occlusion_texture, ambient_occlusion_sampler, in.tex_coords).r;
+#else
+    let occlusion = 1.0;
+#endif
+    return vec4<f32>(vec3<f32>(occlusion), 1.0);
+}
+
+// How fast the normal turns across the surface relative to how far the surface moves, from
+// their derivatives across neighboring texels. Convex curvature is brighter than 0.5, concave
+// darker; flat surfaces are 0.5.
+@fragment
+fn fs_main_bake_curvature(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = surface_normal(in);
+    let dn_dx = dpdx(normal);
+    let dn_dy = dpdy(normal);
+    let dp_dx = dpdx(in.position);
+    let dp_dy = dpdy(in.position);
+    let distance = length(dp_dx) + length(dp_dy);
+    var curvature = 0.0;
+    if (distance > 0.0) {
+        // normals spread apart along the surface on convex regions
+        let turn = length(dn_dx) + length(dn_dy);
+        let convex = dot(dn_dx, dp_dx) + dot(dn_dy, dp_dy) >= 0.0;
+        curvature = select(-turn, turn, convex) / distance;
+    }
+    let value = clamp(0.5 + 0.5 * curvature * bake.params.x, 0.0, 1.0);
+    return vec4<f32>(vec3<f32>(value), 1.0);
+}
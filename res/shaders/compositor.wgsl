@@ -1,3 +1,6 @@
+#include "shaders/depth_linearization.wgsl"
+#include "shaders/fog_volume.wgsl"
+
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
@@ -6,17 +9,43 @@ struct VertexOutput {
 };
 
 struct CompositorUniform {
-    // x: z_near, y: z_far, z: width in pixels, w: height in pixels
+    // x: z_near, y: z_far (0 for an infinite far plane), z: width in pixels, w: height in pixels
     @location(0) camera_z_near_far_width_height: vec4<f32>,
+    // x: 1.0 if the camera uses reversed depth, otherwise 0.0
+    @location(1) camera_depth_mode: vec4<f32>,
+    // x: linear exposure scale
+    @location(2) camera_exposure: vec4<f32>,
+    // x: the debug view, one of DEBUG_VIEW_*
+    @location(3) debug_view: vec4<f32>,
+    // the camera's view projection as of the previous frame
+    @location(4) previous_view_proj: mat4x4<f32>,
+    // x: 1.0 if the background is transparent, otherwise 0.0
+    @location(5) background: vec4<f32>,
+    // x: 1.0 to dither, otherwise 0.0, y: 1.0 if the target's format encodes to sRGB, otherwise
+    // 0.0, z: the target's quantization steps per channel
+    @location(6) dither: vec4<f32>,
+    // x: brightness, y: contrast, z: saturation, w: gamma
+    @location(7) color_adjustment: vec4<f32>,
+    // x: the transfer function output is encoded with, one of TRANSFER_*, y: for TRANSFER_PQ,
+    // the brightness of scene white as a fraction of PQ's 10000 nit peak
+    @location(8) output_transfer: vec4<f32>,
 }
 
 struct CameraUniform {
-    view_pos: vec4<f32>,
+    // view_proj leads so depth.wgsl can bind the camera uniform
     view_proj: mat4x4<f32>,
+    view_pos: vec4<f32>,
     proj_inverse: mat4x4<f32>,
     view_inverse: mat4x4<f32>,
 };
 
+struct EnvironmentUniform {
+    // rotates world directions into the environment map's frame
+    rotation: mat4x4<f32>,
+    // x: intensity
+    intensity: vec4<f32>,
+};
+
 @group(0) @binding(0)
 var color_attachment_texture: texture_2d<f32>;
 
@@ -29,12 +58,15 @@ var depth_attachment_texture: texture_2d<f32>;
 @group(0) @binding(3)
 var depth_attachment_sampler: sampler;
 
+// see volumetric_fog.wgsl
 @group(0) @binding(4)
-var environment_map_texture: texture_cube<f32>;
+var<uniform> fog: FogUniform;
 
 @group(0) @binding(5)
-var environment_map_sampler: sampler;
+var fog_volume_texture: texture_3d<f32>;
 
+@group(0) @binding(6)
+var fog_volume_sampler: sampler;
 
 @group(1) @binding(0)
 var<uniform> compositor: CompositorUniform;
@@ -42,6 +74,29 @@ var<uniform> compositor: CompositorUniform;
 @group(2) @binding(0)
 var<uniform> camera: CameraUniform;
 
+@group(3) @binding(0)
+var environment_map_texture: texture_cube<f32>;
+
+@group(3) @binding(1)
+var environment_map_sampler: sampler;
+
+@group(3) @binding(2)
+var<uniform> environment: EnvironmentUniform;
+
+// must match compositor::DebugView::index
+let DEBUG_VIEW_WORLD_NORMALS: f32 = 1.0;
+let DEBUG_VIEW_VELOCITY: f32 = 2.0;
+let DEBUG_VIEW_DEPTH: f32 = 3.0;
+let DEBUG_VIEW_LIGHT_COMPLEXITY: f32 = 4.0;
+
+// must match compositor::TransferFunction::index
+let TRANSFER_LINEAR: f32 = 0.0;
+let TRANSFER_SRGB: f32 = 1.0;
+let TRANSFER_PQ: f32 = 2.0;
+
+// light counts at or above this are drawn hottest; must match COUNT_MAX in depth.wgsl
+let LIGHT_COMPLEXITY_MAX: f32 = 8.0;
+
 fn hsv_to_rgb(hsv: vec3<f32>) -> vec3<f32> {
     // https://github.com/hughsk/glsl-hsv2rgb/blob/master/index.glsl
     let K = vec4<f32>(1.0, 2.0 / 3.0, 1.0 / 3.0, 3.0);
@@ -68,36 +123,200 @@ fn compositor_vs_main(
     return out;
 }
 
+// The camera's depth linearization params, see depth_linearization.wgsl
+fn depth_params() -> vec3<f32> {
+    return vec3<f32>(compositor.camera_z_near_far_width_height.xy, compositor.camera_depth_mode.x);
+}
+
+// Samples the depth attachment, as stored
+fn sample_raw_depth(in: VertexOutput) -> f32 {
+    return textureSample(depth_attachment_texture, depth_attachment_sampler, in.tex_coord).r;
+}
+
+// Samples the depth attachment, remapped so that 0 is near and 1 is far regardless of depth mode
+fn sample_depth(in: VertexOutput) -> f32 {
+    return standard_depth(sample_raw_depth(in), depth_params());
+}
+
 // Samples the rendered scene, adding the sky environment
 fn scene(in: VertexOutput) -> vec4<f32> {
     var color = textureSample(color_attachment_texture, color_attachment_sampler, in.tex_coord);
-    let depth = textureSample(depth_attachment_texture, depth_attachment_sampler, in.tex_coord).r;
-    let sky_color = textureSampleBias(environment_map_texture, environment_map_sampler, normalize(in.view_dir), 0.0);
+    let depth = sample_depth(in);
+    let sky_dir = (environment.rotation * vec4<f32>(normalize(in.view_dir), 0.0)).xyz;
+    let sky_color = textureSampleBias(environment_map_texture, environment_map_sampler, sky_dir, 0.0) * vec4<f32>(vec3<f32>(environment.intensity.x), 1.0);
 
     if (depth < 1.0) {
+        // opaque geometry, whatever alpha its materials wrote
+        return vec4<f32>(color.rgb, 1.0);
+    } else {
+        // a transparent background shows through as premultiplied black
+        return mix(vec4<f32>(sky_color.rgb, 1.0), vec4<f32>(0.0), compositor.background.x);
+    }
+}
+
+// Attenuates scene color by the volumetric fog between it and the camera, and adds the light
+// the fog scatters toward the camera; the sky is fogged as if at the fog's distance. Each fog
+// volume slice holds the fog up to its far side, see volumetric_fog.wgsl.
+fn apply_fog(in: VertexOutput, color: vec4<f32>) -> vec4<f32> {
+    if (fog.grid.w == 0.0) {
+        return color;
+    }
+    let view_depth = linear_depth(sample_raw_depth(in), depth_params());
+    let slice = fog_slice_coordinate(view_depth, fog) - 0.5 / fog.grid.z;
+    let fogged = textureSampleLevel(fog_volume_texture, fog_volume_sampler, vec3<f32>(in.tex_coord, slice), 0.0);
+    // premultiplied, so a transparent background stays transparent
+    return vec4<f32>(color.rgb * fogged.a + fogged.rgb * color.a, color.a);
+}
+
+// Homogeneous world position of the scene at the fragment; w is 0 at an infinite far plane
+fn world_position(in: VertexOutput) -> vec4<f32> {
+    return world_position_from_depth(in.tex_coord, sample_raw_depth(in), camera.view_inverse * camera.proj_inverse);
+}
+
+fn debug_world_normals(in: VertexOutput) -> vec4<f32> {
+    let position = world_position(in);
+    let p = position.xyz / position.w;
+    var normal = normalize(cross(dpdy(p), dpdx(p)));
+    if (dot(normal, in.view_dir) > 0.0) {
+        normal = -normal;
+    }
+    if (sample_depth(in) >= 1.0) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    return vec4<f32>(normal * 0.5 + 0.5, 1.0);
+}
+
+fn debug_velocity(in: VertexOutput) -> vec4<f32> {
+    // homogeneous, so the sky (at infinity) reprojects too
+    let previous_clip = compositor.previous_view_proj * world_position(in);
+    let previous_tex_coord = vec2<f32>(previous_clip.x, -previous_clip.y) / previous_clip.w * 0.5 + 0.5;
+    // in pixels, scaled so a few pixels of motion is visible
+    let velocity = (in.tex_coord - previous_tex_coord) * compositor.camera_z_near_far_width_height.zw;
+    return vec4<f32>(clamp(velocity * 0.05 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0)), 0.0, 1.0);
+}
+
+// The scene's color attachment holds a light count in red, scaled by 1 / LIGHT_COMPLEXITY_MAX,
+// see depth.wgsl
+fn debug_light_complexity(in: VertexOutput) -> vec4<f32> {
+    let count = round(textureSample(color_attachment_texture, color_attachment_sampler, in.tex_coord).r * LIGHT_COMPLEXITY_MAX);
+    if (count < 0.5) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    // hue runs from blue to red
+    let heat = clamp((count - 1.0) / (LIGHT_COMPLEXITY_MAX - 1.0), 0.0, 1.0);
+    return vec4<f32>(hsv_to_rgb(vec3<f32>((1.0 - heat) * 0.66, 1.0, 1.0)), 1.0);
+}
+
+// Maps scene color to display color
+fn tonemap(color: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(color.rgb * compositor.camera_exposure.x, color.a);
+}
+
+fn linear_to_srgb(rgb: vec3<f32>) -> vec3<f32> {
+    let low = rgb * 12.92;
+    let high = 1.055 * pow(rgb, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, rgb <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(rgb: vec3<f32>) -> vec3<f32> {
+    let low = rgb / 12.92;
+    let high = pow((rgb + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, rgb <= vec3<f32>(0.04045));
+}
+
+// Applies the compositor's brightness, contrast, saturation and gamma to tonemapped color.
+// These work on sRGB encoded values, so mid grey is perceptually mid grey whatever the surface
+// format; premultiplied color is adjusted without its alpha.
+fn adjust_color(color: vec4<f32>) -> vec4<f32> {
+    let adjustment = compositor.color_adjustment;
+    if (color.a <= 0.0 || all(adjustment == vec4<f32>(0.0, 1.0, 1.0, 1.0))) {
+        return color;
+    }
+    var encoded = linear_to_srgb(clamp(color.rgb / color.a, vec3<f32>(0.0), vec3<f32>(1.0)));
+    encoded = pow(encoded, vec3<f32>(1.0 / adjustment.w));
+    encoded = (encoded - 0.5) * adjustment.y + 0.5 + adjustment.x;
+    let luminance = dot(encoded, vec3<f32>(0.2126, 0.7152, 0.0722));
+    encoded = mix(vec3<f32>(luminance), encoded, adjustment.z);
+    let rgb = srgb_to_linear(clamp(encoded, vec3<f32>(0.0), vec3<f32>(1.0)));
+    return vec4<f32>(rgb * color.a, color.a);
+}
+
+// SMPTE ST 2084 (PQ) encoding of BT.2020 light, in units of PQ's 10000 nit peak
+fn linear_to_pq(rgb: vec3<f32>) -> vec3<f32> {
+    let m1 = 0.1593017578125;
+    let m2 = 78.84375;
+    let c1 = 0.8359375;
+    let c2 = 18.8515625;
+    let c3 = 18.6875;
+    let p = pow(clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(m1));
+    return pow((c1 + c2 * p) / (1.0 + c3 * p), vec3<f32>(m2));
+}
+
+// Encodes linear display color for the target, per compositor.output_transfer. Targets with
+// sRGB formats encode as they're written, so take linear color as is; premultiplied color is
+// encoded without its alpha.
+fn encode_output(color: vec4<f32>) -> vec4<f32> {
+    let transfer = compositor.output_transfer.x;
+    if (transfer == TRANSFER_LINEAR || color.a <= 0.0) {
         return color;
+    }
+    let rgb = max(color.rgb / color.a, vec3<f32>(0.0));
+    var encoded: vec3<f32>;
+    if (transfer == TRANSFER_PQ) {
+        // BT.709 to BT.2020 primaries, columns are the BT.709 primaries in BT.2020
+        let bt709_to_bt2020 = mat3x3<f32>(
+            vec3<f32>(0.6274, 0.0691, 0.0164),
+            vec3<f32>(0.3293, 0.9195, 0.0880),
+            vec3<f32>(0.0433, 0.0114, 0.8956)
+        );
+        encoded = linear_to_pq(bt709_to_bt2020 * rgb * compositor.output_transfer.y);
     } else {
-        return sky_color;
+        encoded = linear_to_srgb(min(rgb, vec3<f32>(1.0)));
     }
+    return vec4<f32>(encoded * color.a, color.a);
 }
 
-// linear depth of scene, normalized to [0,1]
-fn normalized_linear_depth(in: VertexOutput) -> f32 {
-    let depth = textureSample(depth_attachment_texture, depth_attachment_sampler, in.tex_coord).r;
-    let z_near = compositor.camera_z_near_far_width_height.x;
-    let z_far = compositor.camera_z_near_far_width_height.y;
-    return (z_near + (pow(z_far + 1.0, depth) - 1.0)) / z_far;
+// The 8x8 Bayer matrix threshold for a pixel, in [0,1)
+fn bayer_threshold(pixel: vec2<u32>) -> f32 {
+    let x = pixel.x & 7u;
+    let xy = x ^ (pixel.y & 7u);
+    // interleaves the bits of x ^ y and x, reversed
+    let index = ((xy & 1u) << 5u) | ((x & 1u) << 4u) | ((xy & 2u) << 2u) | ((x & 2u) << 1u) | ((xy & 4u) >> 1u) | ((x & 4u) >> 2u);
+    return (f32(index) + 0.5) / 64.0;
 }
 
-// linear depth of scene in world [z_near, z_far]
-fn world_linear_depth(in: VertexOutput) -> f32 {
-    let depth = textureSample(depth_attachment_texture, depth_attachment_sampler, in.tex_coord).r;
-    let z_near = compositor.camera_z_near_far_width_height.x;
-    let z_far = compositor.camera_z_near_far_width_height.y;
-    return z_near + (pow(z_far + 1.0, depth) - 1.0);
+// Offsets color by up to half a quantization step of the surface, in the surface's encoding,
+// so smooth gradients dither between steps rather than banding
+fn dither(color: vec4<f32>, pixel: vec2<u32>) -> vec4<f32> {
+    if (compositor.dither.x == 0.0) {
+        return color;
+    }
+    // premultiplied, so transparent pixels stay black
+    let offset = (bayer_threshold(pixel) - 0.5) / compositor.dither.z * color.a;
+    let is_srgb = compositor.dither.y > 0.5;
+    var encoded = max(color.rgb, vec3<f32>(0.0));
+    if (is_srgb) {
+        encoded = linear_to_srgb(encoded);
+    }
+    encoded = max(encoded + offset, vec3<f32>(0.0));
+    if (is_srgb) {
+        encoded = srgb_to_linear(encoded);
+    }
+    return vec4<f32>(encoded, color.a);
 }
 
 @fragment
 fn compositor_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return scene(in);
+    let debug_view = compositor.debug_view.x;
+    if (debug_view == DEBUG_VIEW_WORLD_NORMALS) {
+        return encode_output(debug_world_normals(in));
+    } else if (debug_view == DEBUG_VIEW_VELOCITY) {
+        return encode_output(debug_velocity(in));
+    } else if (debug_view == DEBUG_VIEW_DEPTH) {
+        return encode_output(vec4<f32>(vec3<f32>(normalized_linear_depth(sample_raw_depth(in), depth_params())), 1.0));
+    } else if (debug_view == DEBUG_VIEW_LIGHT_COMPLEXITY) {
+        return encode_output(debug_light_complexity(in));
+    }
+    let color = encode_output(adjust_color(tonemap(apply_fog(in, scene(in)))));
+    return dither(color, vec2<u32>(in.clip_position.xy));
 }
\ No newline at end of file
@@ -0,0 +1,141 @@
+//
+//  Uniforms
+//
+
+struct FilterUniform {
+    // x: roughness, y: level of the source sampled, z: the destination face, 0 to 5,
+    // w: samples per texel
+    params: vec4<f32>,
+    // x: source face size in texels at level 0
+    source: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // (0,0) at the top left of the face
+    @location(0) tex_coord: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> cubemap_filter: FilterUniform;
+
+@group(0) @binding(1)
+var source_texture: texture_cube<f32>;
+
+@group(0) @binding(2)
+var source_sampler: sampler;
+
+let PI: f32 = 3.14159265359;
+
+//
+//  Util
+//
+
+// World direction through a point of a face, following the cube face layout of wgpu:
+// +X, -X, +Y, -Y, +Z, -Z
+fn face_direction(tex_coord: vec2<f32>, face: u32) -> vec3<f32> {
+    let uv = tex_coord * 2.0 - 1.0;
+    var direction = vec3<f32>(0.0);
+    switch (face) {
+        case 0u: { direction = vec3<f32>(1.0, -uv.y, -uv.x); }
+        case 1u: { direction = vec3<f32>(-1.0, -uv.y, uv.x); }
+        case 2u: { direction = vec3<f32>(uv.x, 1.0, uv.y); }
+        case 3u: { direction = vec3<f32>(uv.x, -1.0, -uv.y); }
+        case 4u: { direction = vec3<f32>(uv.x, -uv.y, 1.0); }
+        default: { direction = vec3<f32>(-uv.x, -uv.y, -1.0); }
+    }
+    return normalize(direction);
+}
+
+// The i'th of n points of the Hammersley sequence
+fn hammersley(i: u32, n: u32) -> vec2<f32> {
+    var bits = i;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return vec2<f32>(f32(i) / f32(n), f32(bits) * 2.3283064365386963e-10);
+}
+
+// A half vector about `normal`, importance sampled from the GGX distribution
+fn importance_sample_ggx(xi: vec2<f32>, normal: vec3<f32>, alpha: f32) -> vec3<f32> {
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let h = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+    var up = vec3<f32>(1.0, 0.0, 0.0);
+    if (abs(normal.z) < 0.999) {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return normalize(tangent * h.x + bitangent * h.y + normal * h.z);
+}
+
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+//
+//  Entry points
+//
+
+// A fullscreen triangle covering the face being rendered
+@vertex
+fn vs_main_filter(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let x = f32((in_vertex_index << 1u) & 2u);
+    let y = f32(in_vertex_index & 2u);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x * 2.0 - 1.0, 1.0 - y * 2.0, 0.0, 1.0);
+    out.tex_coord = vec2<f32>(x, y);
+    return out;
+}
+
+// Resamples the source at level cubemap_filter.params.y; downsampling a mip level into the next
+// filters 2x2 texels into each
+@fragment
+fn fs_main_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = face_direction(in.tex_coord, u32(cubemap_filter.params.z));
+    return textureSampleLevel(source_texture, source_sampler, direction, cubemap_filter.params.y);
+}
+
+// Convolves the source with the GGX distribution at roughness cubemap_filter.params.x, assuming
+// the view and reflection directions equal the normal as is usual for prefiltered radiance.
+// Samples are read from the source's mip chain at a level matching their solid angle, which
+// keeps few samples free of fireflies.
+@fragment
+fn fs_main_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = face_direction(in.tex_coord, u32(cubemap_filter.params.z));
+    let roughness = cubemap_filter.params.x;
+    if (roughness <= 0.0) {
+        return textureSampleLevel(source_texture, source_sampler, normal, 0.0);
+    }
+
+    let alpha = roughness * roughness;
+    let sample_count = u32(cubemap_filter.params.w);
+    let source_size = cubemap_filter.source.x;
+    let texel_solid_angle = 4.0 * PI / (6.0 * source_size * source_size);
+
+    var color = vec3<f32>(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < sample_count; i = i + 1u) {
+        let h = importance_sample_ggx(hammersley(i, sample_count), normal, alpha);
+        let l = normalize(2.0 * dot(normal, h) * h - normal);
+        let n_dot_l = dot(normal, l);
+        if (n_dot_l > 0.0) {
+            let n_dot_h = max(dot(normal, h), 0.0);
+            // with view = normal, pdf = D * n_dot_h / (4 * v_dot_h) reduces to D / 4
+            let pdf = distribution_ggx(n_dot_h, alpha) / 4.0;
+            let sample_solid_angle = 1.0 / (f32(sample_count) * pdf + 1e-4);
+            let level = max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+            color = color + textureSampleLevel(source_texture, source_sampler, l, level).rgb * n_dot_l;
+            total_weight = total_weight + n_dot_l;
+        }
+    }
+
+    return vec4<f32>(color / max(total_weight, 1e-4), 1.0);
+}
@@ -0,0 +1,38 @@
+//
+//  Uniforms
+//
+
+// The camera uniform leads with its view projection matrix, see depth.wgsl
+struct View {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> view: View;
+
+//
+//  Lines
+//
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main_debug_lines(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = view.view_proj * vec4<f32>(in.position, 1.0);
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main_debug_lines(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
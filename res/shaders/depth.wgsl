@@ -0,0 +1,55 @@
+#include "shaders/instance.wgsl"
+
+//
+//  Uniforms
+//
+
+// Both the camera and light uniforms lead with their view projection matrix, so either
+// can be bound here; the camera for the depth prepass and a light for its shadow map.
+struct View {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> view: View;
+
+//
+//  Model
+//
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+//
+// Vertex
+//
+
+@vertex
+fn vs_main_depth(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let model_matrix = instance_model_matrix(instance);
+
+    // must match the world position computation in model.wgsl for prepass depths to be equal
+    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+    return view.view_proj * world_position;
+}
+
+//
+// Fragment
+//
+
+// Each fragment adds a little heat, so pixels shaded many times over glow, see depth_pass
+@fragment
+fn fs_main_overdraw() -> @location(0) vec4<f32> {
+    return vec4<f32>(0.1, 0.04, 0.01, 1.0);
+}
+
+// must match LIGHT_COMPLEXITY_MAX in compositor.wgsl
+let COUNT_MAX: f32 = 8.0;
+
+// Each fragment adds 1 / COUNT_MAX to red, so counts up to COUNT_MAX fit the 8 bit color
+// attachment and can be read back out, see compositor.wgsl
+@fragment
+fn fs_main_count() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0 / COUNT_MAX, 0.0, 0.0, 1.0);
+}
@@ -0,0 +1,53 @@
+// Conversions between depth attachment values, linear view depth and world position, shared
+// by the passes which read a depth attachment: #include "shaders/depth_linearization.wgsl".
+// `params` is camera::Camera::depth_linearization_params, whose math these match:
+// x: z_near, y: z_far (0 for an infinite far plane), z: 1.0 if the camera uses reversed depth,
+// otherwise 0.0. Cameras with an oblique clip plane, e.g. a portal's, don't linearize exactly.
+
+// Remaps a depth attachment value so that 0 is near and 1 is far regardless of depth mode
+fn standard_depth(depth: f32, params: vec3<f32>) -> f32 {
+    return mix(depth, 1.0 - depth, params.z);
+}
+
+// Linear view depth, in world units in [z_near, z_far], of a depth attachment value
+fn linear_depth(depth: f32, params: vec3<f32>) -> f32 {
+    let d = standard_depth(depth, params);
+    let z_near = params.x;
+    let z_far = params.y;
+    if (z_far <= 0.0) {
+        // infinite far plane, depth = 1 - z_near / z
+        return z_near / max(1.0 - d, 1e-7);
+    }
+    return z_near * z_far / (z_far - d * (z_far - z_near));
+}
+
+// The depth attachment value of a linear view depth, the inverse of linear_depth
+fn depth_from_linear(view_depth: f32, params: vec3<f32>) -> f32 {
+    let z_near = params.x;
+    let z_far = params.y;
+    let z = max(view_depth, z_near);
+    var d = 1.0 - z_near / z;
+    if (z_far > 0.0) {
+        d = z_far * (z - z_near) / (z * (z_far - z_near));
+    }
+    return standard_depth(d, params);
+}
+
+// Linear view depth normalized to [0,1] from the near to the far plane. There's no far plane
+// to normalize against for an infinite camera, which falls back to the (non-linear) standard depth.
+fn normalized_linear_depth(depth: f32, params: vec3<f32>) -> f32 {
+    let z_near = params.x;
+    let z_far = params.y;
+    if (z_far <= 0.0) {
+        return standard_depth(depth, params);
+    }
+    return (linear_depth(depth, params) - z_near) / (z_far - z_near);
+}
+
+// Homogeneous world position of a depth attachment value at a texture coordinate, w is 0 at an
+// infinite far plane. `inverse_view_proj` inverts the view projection the attachment was
+// rendered with, e.g. camera.view_inverse * camera.proj_inverse.
+fn world_position_from_depth(tex_coord: vec2<f32>, depth: f32, inverse_view_proj: mat4x4<f32>) -> vec4<f32> {
+    let ndc = vec4<f32>(tex_coord.x * 2.0 - 1.0, 1.0 - tex_coord.y * 2.0, depth, 1.0);
+    return inverse_view_proj * ndc;
+}
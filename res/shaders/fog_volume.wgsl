@@ -0,0 +1,34 @@
+// The layout of volumetric_fog::VolumetricFog's froxel volume, shared by the passes which
+// build and sample it: #include "shaders/fog_volume.wgsl". Froxels divide the camera's view
+// into a grid across the screen, and into slices distributed exponentially in view depth
+// from the camera's near plane to the fog's distance, so near slices are thin.
+
+struct FogUniform {
+    inverse_view_proj: mat4x4<f32>,
+    // xyz: the camera's position
+    camera_position: vec4<f32>,
+    // xyz: the camera's depth linearization params, see depth_linearization.wgsl,
+    // w: the view depth the froxels reach
+    depth: vec4<f32>,
+    // xyz: froxels across, up and into the view, w: 1 if the fog is drawn, otherwise 0
+    grid: vec4<f32>,
+    // x: extinction per world unit, y: height where the fog starts thinning, z: the rate it
+    // thins with height, w: phase function anisotropy
+    medium: vec4<f32>,
+    // rgb: fraction of extinction which is scattered
+    albedo: vec4<f32>,
+    // rgb: the scene's ambient light, scattered evenly
+    ambient: vec4<f32>,
+};
+
+// View depth at a slice coordinate, 0 at the near plane and 1 at the fog's distance
+fn fog_slice_depth(slice: f32, fog: FogUniform) -> f32 {
+    let z_near = fog.depth.x;
+    return z_near * pow(fog.depth.w / z_near, slice);
+}
+
+// The inverse of fog_slice_depth, clamped to the volume
+fn fog_slice_coordinate(view_depth: f32, fog: FogUniform) -> f32 {
+    let z_near = fog.depth.x;
+    return clamp(log(max(view_depth, z_near) / z_near) / log(fog.depth.w / z_near), 0.0, 1.0);
+}
@@ -0,0 +1,186 @@
+//
+//  Uniforms
+//
+
+struct GrassUniform {
+    view_proj: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    // x, y: world cell of the first candidate blade, z: spacing, w: candidates along each side
+    grid: vec4<f32>,
+    // x: height, y: width, z: lod distance, w: max distance
+    blade: vec4<f32>,
+    // x: world size, y: height scale, z: wind response
+    terrain: vec4<f32>,
+    base_color: vec4<f32>,
+    tip_color: vec4<f32>,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view_pos: vec4<f32>,
+    proj_inverse: mat4x4<f32>,
+    view_inverse: mat4x4<f32>,
+};
+
+struct Light {
+    shadow_view_proj: mat4x4<f32>,
+
+    position: vec3<f32>,
+    direction: vec3<f32>,
+    ambient: vec3<f32>,
+    color: vec3<f32>,
+
+    // x: constant, y: linear, z: exponential, w: dot spot breadth
+    attenuation: vec4<f32>,
+
+    // 0: Ambient
+    // 1: Point
+    // 2: Spot
+    // 3: Directional
+    light_type: i32,
+    radius: f32,
+
+    shadow: vec4<f32>,
+    shadow_rect: vec4<f32>,
+};
+
+struct EnvironmentUniform {
+    // rotates world directions into the environment map's frame
+    rotation: mat4x4<f32>,
+    // x: intensity
+    intensity: vec4<f32>,
+};
+
+struct Wind {
+    // xyz: direction the wind blows toward, w: strength
+    direction: vec4<f32>,
+    // x: gust strength, y: gust frequency, z: time in seconds
+    gust: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> grass: GrassUniform;
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(2) @binding(0)
+var<uniform> light: Light;
+
+@group(3) @binding(0)
+var environment_map_texture: texture_cube<f32>;
+
+@group(3) @binding(1)
+var environment_map_sampler: sampler;
+
+@group(3) @binding(2)
+var<uniform> environment: EnvironmentUniform;
+
+@group(3) @binding(3)
+var<uniform> wind: Wind;
+
+//
+//  Blades
+//
+
+struct InstanceInput {
+    // xyz: root position, w: yaw
+    @location(0) position: vec4<f32>,
+    // x: height, y: width, z: lean, w: segments
+    @location(1) params: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    // 0 at the root, 1 at the tip
+    @location(2) along: f32,
+};
+
+// The wind's velocity at a world position; must match wind::Wind::velocity_at
+fn wind_velocity(world_position: vec3<f32>) -> vec3<f32> {
+    let phase = dot(world_position, wind.direction.xyz) * 0.1 - wind.gust.z * wind.gust.y * 6.2831853;
+    let gust = max(sin(phase) * 0.6 + sin(phase * 2.3 + 1.7) * 0.4, 0.0);
+    return wind.direction.xyz * (wind.direction.w + wind.gust.x * gust);
+}
+
+fn sample_environment(direction: vec3<f32>) -> vec3<f32> {
+    let rotated = (environment.rotation * vec4<f32>(direction, 0.0)).xyz;
+    return textureSample(environment_map_texture, environment_map_sampler, rotated).rgb * environment.intensity.x;
+}
+
+// A tapering triangle strip alternating between the blade's edges, ending at its tip, which
+// leans and bends downwind quadratically along its length
+@vertex
+fn vs_main_grass(@builtin(vertex_index) vertex_index: u32, instance: InstanceInput) -> VertexOutput {
+    let root = instance.position.xyz;
+    let yaw = instance.position.w;
+    let height = instance.params.x;
+    let segments = instance.params.w;
+
+    let along = min(f32(vertex_index / 2u) / segments, 1.0);
+    let side = select(-0.5, 0.5, (vertex_index & 1u) == 1u);
+    let across = vec3<f32>(cos(yaw), 0.0, sin(yaw));
+    let facing = vec3<f32>(-across.z, 0.0, across.x);
+
+    let wind_bend = wind_velocity(root) * grass.terrain.z;
+    let bend = (facing * instance.params.z + wind_bend) * along * along;
+    let world_position = root
+        + across * side * instance.params.y * (1.0 - along)
+        + (vec3<f32>(0.0, along, 0.0) + bend) * height;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.world_position = world_position;
+    // tilted up, so blades catch light from above rather than only edge-on
+    out.world_normal = normalize(facing + vec3<f32>(0.0, 0.5, 0.0) + bend);
+    out.along = along;
+    return out;
+}
+
+fn blade_color(in: VertexOutput) -> vec3<f32> {
+    return mix(grass.base_color.rgb, grass.tip_color.rgb, in.along);
+}
+
+// blades are lit from both sides
+fn blade_normal(in: VertexOutput, front_facing: bool) -> vec3<f32> {
+    return select(-in.world_normal, in.world_normal, front_facing);
+}
+
+@fragment
+fn fs_main_grass_ambient(in: VertexOutput) -> @location(0) vec4<f32> {
+    let sky = sample_environment(vec3<f32>(0.0, 1.0, 0.0));
+    // roots are occluded by the surrounding blades
+    let occlusion = mix(0.4, 1.0, in.along);
+    // the ambient light's hemisphere, see hemisphere_ambient in model.wgsl
+    let up = dot(normalize(in.world_normal), light.direction) * 0.5 + 0.5;
+    let ambient = mix(light.color, light.ambient, up);
+    return vec4<f32>(blade_color(in) * (sky + ambient) * occlusion, 1.0);
+}
+
+@fragment
+fn fs_main_grass_lit(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    var light_dir = normalize(light.direction);
+    if (light.light_type == 1 || light.light_type == 2) {
+        light_dir = normalize(light.position - in.world_position);
+    }
+
+    let light_distance = length(light.position - in.world_position);
+    var attenuation = 1.0 / (light.attenuation.x + (light.attenuation.y * light_distance) + (light.attenuation.z * light_distance * light_distance));
+    if (light.light_type == 2) {
+        let d = clamp(dot(normalize(in.world_position - light.position), light.direction), 0.0, 1.0);
+        attenuation = attenuation * (d - light.attenuation.w) / (1.0 - light.attenuation.w);
+    }
+    // fade to nothing at the light's radius, matching light_radius_window in model.wgsl
+    if (light.radius > 0.0) {
+        let ratio = light_distance / light.radius;
+        let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+        attenuation = attenuation * window * window;
+    }
+
+    // wrapped diffuse stands in for light passing through the thin blades
+    let n_dot_l = dot(blade_normal(in, front_facing), light_dir);
+    let diffuse = max((n_dot_l + 0.5) / 1.5, 0.0);
+    return vec4<f32>(blade_color(in) * light.color * diffuse * max(attenuation, 0.0), 1.0);
+}
@@ -0,0 +1,140 @@
+//
+//  Uniforms
+//
+
+struct GrassUniform {
+    view_proj: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    // x, y: world cell of the first candidate blade, z: spacing, w: candidates along each side
+    grid: vec4<f32>,
+    // x: height, y: width, z: lod distance, w: max distance
+    blade: vec4<f32>,
+    // x: world size, y: height scale, z: wind response
+    terrain: vec4<f32>,
+    base_color: vec4<f32>,
+    tip_color: vec4<f32>,
+};
+
+struct Blade {
+    // xyz: root position, w: yaw
+    position: vec4<f32>,
+    // x: height, y: width, z: lean, w: segments
+    params: vec4<f32>,
+};
+
+// Matches wgpu::util::DrawIndirect
+struct DrawArgs {
+    vertex_count: u32,
+    instance_count: atomic<u32>,
+    base_vertex: u32,
+    base_instance: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> grass: GrassUniform;
+
+@group(1) @binding(0)
+var density_texture: texture_2d<f32>;
+
+@group(1) @binding(1)
+var density_sampler: sampler;
+
+@group(1) @binding(2)
+var height_texture: texture_2d<f32>;
+
+@group(1) @binding(3)
+var height_sampler: sampler;
+
+// the near list followed by the far list, each of grid.w * grid.w blades
+@group(1) @binding(4)
+var<storage, read_write> blades: array<Blade>;
+
+@group(1) @binding(5)
+var<storage, read_write> draw_args: array<DrawArgs, 2>;
+
+// must match NEAR_SEGMENTS and FAR_SEGMENTS in grass.rs
+let NEAR_SEGMENTS: f32 = 4.0;
+let FAR_SEGMENTS: f32 = 1.0;
+
+//
+//  Util
+//
+
+// pcg hash, see "Hash Functions for GPU Rendering" (Jarzynski & Olano)
+fn hash(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// [0,1) from a hash
+fn unit_float(h: u32) -> f32 {
+    return f32(h >> 8u) / 16777216.0;
+}
+
+fn world_uv(xz: vec2<f32>) -> vec2<f32> {
+    return xz / grass.terrain.x + 0.5;
+}
+
+//
+//  Cull
+//
+
+// Plants the candidate blade in a world cell if the density map calls for it and it's in view
+@compute @workgroup_size(8, 8, 1)
+fn cs_main_grass(@builtin(global_invocation_id) id: vec3<u32>) {
+    let side = u32(grass.grid.w);
+    if (id.x >= side || id.y >= side) {
+        return;
+    }
+
+    // randomness is seeded by world cell so each blade is the same from frame to frame
+    let cell = vec2<i32>(grass.grid.xy) + vec2<i32>(id.xy);
+    let seed = hash(bitcast<u32>(cell.x) ^ hash(bitcast<u32>(cell.y)));
+    let r0 = hash(seed);
+    let r1 = hash(r0);
+    let r2 = hash(r1);
+    let r3 = hash(r2);
+    let r4 = hash(r3);
+
+    let spacing = grass.grid.z;
+    let xz = (vec2<f32>(cell) + vec2<f32>(unit_float(r0), unit_float(r1))) * spacing;
+    let distance = length(xz - grass.camera_position.xz);
+    let max_distance = grass.blade.w;
+    if (distance > max_distance) {
+        return;
+    }
+
+    // thin far blades, widening the survivors to keep the coverage
+    let lod_distance = grass.blade.z;
+    let keep = 1.0 - 0.75 * smoothstep(lod_distance, max_distance, distance);
+    let density = textureSampleLevel(density_texture, density_sampler, world_uv(xz), 0.0).r;
+    if (unit_float(r2) >= density * keep) {
+        return;
+    }
+
+    let height = grass.blade.x * (0.7 + 0.6 * unit_float(r3));
+    let ground = textureSampleLevel(height_texture, height_sampler, world_uv(xz), 0.0).r * grass.terrain.y;
+    let root = vec3<f32>(xz.x, ground, xz.y);
+
+    // conservatively cull against the frustum's sides with the blade's midpoint
+    let clip = grass.view_proj * vec4<f32>(root + vec3<f32>(0.0, 0.5 * height, 0.0), 1.0);
+    let slack = clip.w * 0.1 + height;
+    if (clip.w <= -height || abs(clip.x) > clip.w + slack || abs(clip.y) > clip.w + slack) {
+        return;
+    }
+
+    let near = distance < lod_distance;
+    let list = select(1u, 0u, near);
+    var blade: Blade;
+    blade.position = vec4<f32>(root, unit_float(r4) * 6.2831853);
+    blade.params = vec4<f32>(
+        height,
+        grass.blade.y / keep,
+        unit_float(seed) * 0.3,
+        select(FAR_SEGMENTS, NEAR_SEGMENTS, near)
+    );
+
+    let index = atomicAdd(&draw_args[list].instance_count, 1u);
+    blades[list * side * side + index] = blade;
+}
@@ -0,0 +1,53 @@
+// The per-instance attributes of a model's instance buffer, laid out as model::InstanceData,
+// shared by the shaders which draw models: #include "shaders/instance.wgsl". Each instance's
+// transform is stored as position, rotation quaternion and uniform scale, and rebuilt here;
+// passes which must agree on positions, e.g. the depth prepass and the ambient pass, all
+// build it with instance_model_matrix.
+
+struct InstanceInput {
+    // xyz: position, w: uniform scale
+    @location(5) position_scale: vec4<f32>,
+    // rotation as a quaternion (x, y, z, w)
+    @location(6) rotation: vec4<f32>,
+
+    // seconds added to the scene's time when playing a vertex animation
+    @location(13) time_offset: f32,
+    // in [0, 1), desynchronizes instanced effects, see model::Instance::with_phase
+    @location(14) phase: f32,
+};
+
+// Matches cgmath's conversion of a quaternion to a matrix
+fn instance_quat_to_mat3(q: vec4<f32>) -> mat3x3<f32> {
+    let x2 = q.x + q.x;
+    let y2 = q.y + q.y;
+    let z2 = q.z + q.z;
+    let xx = q.x * x2;
+    let yy = q.y * y2;
+    let zz = q.z * z2;
+    let xy = q.x * y2;
+    let xz = q.x * z2;
+    let yz = q.y * z2;
+    let wx = q.w * x2;
+    let wy = q.w * y2;
+    let wz = q.w * z2;
+    return mat3x3<f32>(
+        vec3<f32>(1.0 - (yy + zz), xy + wz, xz - wy),
+        vec3<f32>(xy - wz, 1.0 - (xx + zz), yz + wx),
+        vec3<f32>(xz + wy, yz - wx, 1.0 - (xx + yy)),
+    );
+}
+
+// Scale is uniform, so the rotation alone transforms normals
+fn instance_normal_matrix(instance: InstanceInput) -> mat3x3<f32> {
+    return instance_quat_to_mat3(instance.rotation);
+}
+
+fn instance_model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    let basis = instance_quat_to_mat3(instance.rotation) * instance.position_scale.w;
+    return mat4x4<f32>(
+        vec4<f32>(basis[0], 0.0),
+        vec4<f32>(basis[1], 0.0),
+        vec4<f32>(basis[2], 0.0),
+        vec4<f32>(instance.position_scale.xyz, 1.0),
+    );
+}
@@ -0,0 +1,185 @@
+//
+//  Uniforms
+//
+
+struct AnimationUniform {
+    // x: time in seconds, y: frame delta in seconds, z: instance count
+    time: vec4<f32>,
+    // xyz: flow bounds min, w: flow field frequency
+    flow_min: vec4<f32>,
+    // xyz: flow bounds max, w: flow field time scale
+    flow_max: vec4<f32>,
+};
+
+struct Animation {
+    // xyz: the instance's position, w: its scale
+    position: vec4<f32>,
+    // the instance's rotation, as a quaternion (x, y, z, w)
+    rotation: vec4<f32>,
+    // orbit: xyz center, w angular velocity
+    // oscillate: xyz direction scaled by amplitude, w frequency
+    // flow: x speed
+    params: vec4<f32>,
+    // orbit: xyz axis; oscillate: x phase; w: the motion, one of MOTION_*
+    extra: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> animation: AnimationUniform;
+
+@group(0) @binding(1)
+var<storage, read> animations: array<Animation>;
+
+@group(0) @binding(2)
+var<storage, read_write> flow_positions: array<vec4<f32>>;
+
+// the model's instance buffer, laid out as model::InstanceData: position and scale, the
+// rotation quaternion, then the vertex animation time offset and phase, which are left alone;
+// 10 floats in all, see shaders/instance.wgsl
+@group(0) @binding(3)
+var<storage, read_write> instances: array<f32>;
+
+// must match the MOTION_* constants in instance_animation.rs
+let MOTION_ORBIT: f32 = 1.0;
+let MOTION_OSCILLATE: f32 = 2.0;
+let MOTION_FLOW: f32 = 3.0;
+
+let INSTANCE_STRIDE: u32 = 10u;
+let TAU: f32 = 6.28318530718;
+
+//
+//  Util
+//
+
+fn quat_to_mat3(q: vec4<f32>) -> mat3x3<f32> {
+    let x2 = q.x + q.x;
+    let y2 = q.y + q.y;
+    let z2 = q.z + q.z;
+    let xx = q.x * x2;
+    let yy = q.y * y2;
+    let zz = q.z * z2;
+    let xy = q.x * y2;
+    let xz = q.x * z2;
+    let yz = q.y * z2;
+    let wx = q.w * x2;
+    let wy = q.w * y2;
+    let wz = q.w * z2;
+    return mat3x3<f32>(
+        vec3<f32>(1.0 - (yy + zz), xy + wz, xz - wy),
+        vec3<f32>(xy - wz, 1.0 - (xx + zz), yz + wx),
+        vec3<f32>(xz + wy, yz - wx, 1.0 - (xx + yy)),
+    );
+}
+
+// rotation by angle about a unit axis
+fn axis_angle_to_mat3(axis: vec3<f32>, angle: f32) -> mat3x3<f32> {
+    let s = sin(angle);
+    let c = cos(angle);
+    let t = 1.0 - c;
+    let a = axis;
+    return mat3x3<f32>(
+        vec3<f32>(t * a.x * a.x + c, t * a.x * a.y + s * a.z, t * a.x * a.z - s * a.y),
+        vec3<f32>(t * a.x * a.y - s * a.z, t * a.y * a.y + c, t * a.y * a.z + s * a.x),
+        vec3<f32>(t * a.x * a.z + s * a.y, t * a.y * a.z - s * a.x, t * a.z * a.z + c),
+    );
+}
+
+// a swirling, slowly changing velocity field with unit-ish magnitude
+fn flow_velocity(p: vec3<f32>) -> vec3<f32> {
+    let k = animation.flow_min.w;
+    let t = animation.time.x * animation.flow_max.w;
+    let v = vec3<f32>(
+        sin(p.y * k + t) + cos(p.z * k * 1.3 + t * 0.7),
+        0.25 * sin(p.x * k * 0.8 + t * 1.1),
+        sin(p.x * k + t * 0.9) + cos(p.y * k * 1.7 - t),
+    );
+    let length_squared = dot(v, v);
+    if (length_squared < 1e-8) {
+        return vec3<f32>(0.0, 0.0, 1.0);
+    }
+    return v / sqrt(length_squared);
+}
+
+// a rotation turning +z to face along a unit direction, keeping +y as close to up as it allows
+fn face_along(forward: vec3<f32>) -> mat3x3<f32> {
+    var right = cross(vec3<f32>(0.0, 1.0, 0.0), forward);
+    if (dot(right, right) < 1e-8) {
+        right = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    right = normalize(right);
+    return mat3x3<f32>(right, cross(forward, right), forward);
+}
+
+// wraps value into [lo, hi)
+fn wrap(value: vec3<f32>, lo: vec3<f32>, hi: vec3<f32>) -> vec3<f32> {
+    let size = max(hi - lo, vec3<f32>(1e-4));
+    let offset = value - lo;
+    return lo + offset - floor(offset / size) * size;
+}
+
+// the quaternion (x, y, z, w) of a rotation matrix
+fn mat3_to_quat(m: mat3x3<f32>) -> vec4<f32> {
+    let trace = m[0].x + m[1].y + m[2].z;
+    if (trace > 0.0) {
+        let s = 0.5 / sqrt(trace + 1.0);
+        return vec4<f32>((m[1].z - m[2].y) * s, (m[2].x - m[0].z) * s, (m[0].y - m[1].x) * s, 0.25 / s);
+    } else if (m[0].x > m[1].y && m[0].x > m[2].z) {
+        let s = 2.0 * sqrt(1.0 + m[0].x - m[1].y - m[2].z);
+        return vec4<f32>(0.25 * s, (m[1].x + m[0].y) / s, (m[2].x + m[0].z) / s, (m[1].z - m[2].y) / s);
+    } else if (m[1].y > m[2].z) {
+        let s = 2.0 * sqrt(1.0 + m[1].y - m[0].x - m[2].z);
+        return vec4<f32>((m[1].x + m[0].y) / s, 0.25 * s, (m[2].y + m[1].z) / s, (m[2].x - m[0].z) / s);
+    }
+    let s = 2.0 * sqrt(1.0 + m[2].z - m[0].x - m[1].y);
+    return vec4<f32>((m[2].x + m[0].z) / s, (m[2].y + m[1].z) / s, 0.25 * s, (m[0].y - m[1].x) / s);
+}
+
+fn write_instance(index: u32, position: vec3<f32>, rotation: mat3x3<f32>, scale: f32) {
+    let base = index * INSTANCE_STRIDE;
+    instances[base] = position.x;
+    instances[base + 1u] = position.y;
+    instances[base + 2u] = position.z;
+    instances[base + 3u] = scale;
+
+    let q = mat3_to_quat(rotation);
+    instances[base + 4u] = q.x;
+    instances[base + 5u] = q.y;
+    instances[base + 6u] = q.z;
+    instances[base + 7u] = q.w;
+}
+
+//
+//  Entry point
+//
+
+@compute @workgroup_size(64)
+fn cs_main_instance_animation(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= u32(animation.time.z)) {
+        return;
+    }
+
+    let instance = animations[index];
+    let motion = instance.extra.w;
+    var position = instance.position.xyz;
+    var rotation = quat_to_mat3(instance.rotation);
+    let time = animation.time.x;
+
+    if (motion == MOTION_ORBIT) {
+        let center = instance.params.xyz;
+        let turn = axis_angle_to_mat3(instance.extra.xyz, instance.params.w * time);
+        position = center + turn * (position - center);
+        rotation = turn * rotation;
+    } else if (motion == MOTION_OSCILLATE) {
+        let angle = TAU * instance.params.w * time + instance.extra.x;
+        position = position + instance.params.xyz * sin(angle);
+    } else if (motion == MOTION_FLOW) {
+        let velocity = flow_velocity(flow_positions[index].xyz);
+        position = flow_positions[index].xyz + velocity * instance.params.x * animation.time.y;
+        position = wrap(position, animation.flow_min.xyz, animation.flow_max.xyz);
+        flow_positions[index] = vec4<f32>(position, 1.0);
+        rotation = face_along(velocity) * rotation;
+    }
+
+    write_instance(index, position, rotation, instance.position.w);
+}
@@ -0,0 +1,29 @@
+// The material uniform at @group(0) @binding(0), laid out as model::MaterialUniform and
+// shared by the shaders which bind a model's material bind group:
+// #include "shaders/material.wgsl".
+
+struct Material {
+    ambient: vec4<f32>,
+    diffuse: vec4<f32>,
+    specular: vec4<f32>,
+    shininess: f32,
+    toon_bands: f32,
+    toon_rim_strength: f32,
+    toon_rim_power: f32,
+    outline_color: vec4<f32>,
+    outline_width: f32,
+    alpha_cutoff: f32,
+    wind_sway: f32,
+    // radians, applied after uv_transform's scale
+    uv_rotation: f32,
+    // x: layer count, y: layer tiling
+    terrain: vec4<f32>,
+    // x: world size, y: height scale
+    heightmap: vec4<f32>,
+    // x: frame count, y: frame rate, z: vertex count, w: texture width
+    vertex_animation: vec4<f32>,
+    // xy: offset, zw: scale
+    uv_transform: vec4<f32>,
+    // x: columns, y: rows, z: frame count, w: frame rate; no flipbook when x is 0
+    flipbook: vec4<f32>,
+};
@@ -0,0 +1,90 @@
+#include "shaders/instance.wgsl"
+
+//
+//  Uniforms
+//
+
+// Only the camera's leading view projection matrix is read, as in depth.wgsl
+struct View {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> view: View;
+
+//
+//  Model
+//
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+};
+
+//
+// Vertex
+//
+
+// Vertex animation and wind aren't applied, so the override shows the mesh as imported (or skinned)
+@vertex
+fn vs_main_override(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = instance_model_matrix(instance);
+
+    var out: VertexOutput;
+    // must match the world position computation in depth.wgsl, so a depth prepass agrees
+    out.clip_position = view.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_normal = instance_normal_matrix(instance) * model.normal;
+    out.tex_coords = model.tex_coords;
+    out.normal = model.normal;
+    out.tangent = model.tangent;
+    out.bitangent = model.bitangent;
+    return out;
+}
+
+//
+// Fragment
+//
+
+// The world space normal mapped from [-1,1] to [0,1]; black where the mesh has no normals
+@fragment
+fn fs_main_override_normals(in: VertexOutput) -> @location(0) vec4<f32> {
+    let length_squared = dot(in.world_normal, in.world_normal);
+    if (length_squared < 0.000001) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    return vec4<f32>(in.world_normal / sqrt(length_squared) * 0.5 + 0.5, 1.0);
+}
+
+// An 8x8 checker per unit of texture space, tinted red along u and green along v so flipped or
+// rotated layouts stand out
+@fragment
+fn fs_main_override_uv_checker(in: VertexOutput) -> @location(0) vec4<f32> {
+    let cell = floor(in.tex_coords * 8.0);
+    let checker = (i32(cell.x) + i32(cell.y)) & 1;
+    let tint = vec3<f32>(fract(in.tex_coords), 0.5);
+    let shade = select(0.35, 1.0, checker == 0);
+    return vec4<f32>(tint * shade, 1.0);
+}
+
+// Green where the tangent frame is right handed, red where it's mirrored, and yellow where the
+// tangents are missing or degenerate, e.g. averaged away across a seam
+@fragment
+fn fs_main_override_tangent_handedness(in: VertexOutput) -> @location(0) vec4<f32> {
+    let handedness = dot(cross(in.normal, in.tangent), in.bitangent);
+    if (abs(handedness) < 0.001) {
+        return vec4<f32>(1.0, 1.0, 0.0, 1.0);
+    }
+    return select(vec4<f32>(1.0, 0.0, 0.0, 1.0), vec4<f32>(0.0, 1.0, 0.0, 1.0), handedness > 0.0);
+}
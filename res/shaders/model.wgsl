@@ -1,22 +1,36 @@
+#include "shaders/instance.wgsl"
+#include "shaders/material.wgsl"
+
 //
 //  Uniforms
 //
 
-struct Material {
-    ambient: vec4<f32>,
-    diffuse: vec4<f32>,
-    specular: vec4<f32>,
-    shininess: f32,
-};
-
 struct CameraUniform {
-    view_pos: vec4<f32>,
+    // view_proj leads so depth.wgsl can bind the camera uniform
     view_proj: mat4x4<f32>,
+    view_pos: vec4<f32>,
     proj_inverse: mat4x4<f32>,
     view_inverse: mat4x4<f32>,
 };
 
+struct EnvironmentUniform {
+    // rotates world directions into the environment map's frame
+    rotation: mat4x4<f32>,
+    // x: intensity
+    intensity: vec4<f32>,
+};
+
+struct Wind {
+    // xyz: direction the wind blows toward, w: strength
+    direction: vec4<f32>,
+    // x: gust strength, y: gust frequency, z: time in seconds
+    gust: vec4<f32>,
+};
+
 struct Light {
+    // shadow_view_proj leads so depth.wgsl can bind the light uniform for shadow passes
+    shadow_view_proj: mat4x4<f32>,
+
     position: vec3<f32>,
     direction: vec3<f32>,
     ambient: vec3<f32>,
@@ -30,18 +44,26 @@ struct Light {
     // 2: Spot
     // 3: Directional
     light_type: i32,
-
+    // distance beyond which point and spot lights have no effect, 0 for unbounded
+    radius: f32,
+    // the flat attenuation of lights demoted to simplified shading, 0 for full attenuation;
+    // see light::LightDetail
+    simple_attenuation: f32,
+
+    // x: normal offset bias, y: shadow map texel size, z: light bleeding reduction,
+    // w: shadow mode (0: none, 1: pcf, 2: variance, 3: exponential variance)
+    shadow: vec4<f32>,
+    // the shadow map's region of shadow_map_texture, which may be a shadow atlas, as
+    // xy: offset, zw: scale in texture coordinates
+    shadow_rect: vec4<f32>,
+    // x: gobo mode (0: none, 1: projected from a spot light, 2: cubemap around a point light),
+    // y: gobo rotation in radians
+    gobo: vec4<f32>,
 };
 
 @group(0) @binding(0)
 var<uniform> material: Material;
 
-@group(0) @binding(1)
-var environment_map_texture: texture_cube<f32>;
-
-@group(0) @binding(2)
-var environment_map_sampler: sampler;
-
 @group(0) @binding(3)
 var diffuse_texture: texture_2d<f32>;
 
@@ -60,12 +82,131 @@ var shininess_texture: texture_2d<f32>;
 @group(0) @binding(8)
 var shininess_sampler: sampler;
 
+@group(0) @binding(9)
+var ambient_occlusion_texture: texture_2d<f32>;
+
+@group(0) @binding(10)
+var ambient_occlusion_sampler: sampler;
+
+@group(0) @binding(11)
+var splat_map_texture: texture_2d_array<f32>;
+
+@group(0) @binding(12)
+var splat_map_sampler: sampler;
+
+@group(0) @binding(13)
+var terrain_albedo_texture: texture_2d_array<f32>;
+
+@group(0) @binding(14)
+var terrain_albedo_sampler: sampler;
+
+@group(0) @binding(15)
+var terrain_normal_texture: texture_2d_array<f32>;
+
+@group(0) @binding(16)
+var terrain_normal_sampler: sampler;
+
+@group(0) @binding(17)
+var heightmap_texture: texture_2d<f32>;
+
+@group(0) @binding(18)
+var heightmap_sampler: sampler;
+
+@group(0) @binding(19)
+var vertex_animation_positions: texture_2d<f32>;
+
+@group(0) @binding(20)
+var vertex_animation_normals: texture_2d<f32>;
+
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
 @group(2) @binding(0)
 var<uniform> light: Light;
 
+@group(2) @binding(1)
+var shadow_map_texture: texture_depth_2d;
+
+@group(2) @binding(2)
+var shadow_map_sampler: sampler_comparison;
+
+@group(2) @binding(3)
+var shadow_moments_texture: texture_2d<f32>;
+
+@group(2) @binding(4)
+var gobo_texture: texture_2d<f32>;
+
+@group(2) @binding(5)
+var gobo_cube_texture: texture_cube<f32>;
+
+@group(2) @binding(6)
+var gobo_sampler: sampler;
+
+@group(3) @binding(0)
+var environment_map_texture: texture_cube<f32>;
+
+@group(3) @binding(1)
+var environment_map_sampler: sampler;
+
+@group(3) @binding(2)
+var<uniform> environment: EnvironmentUniform;
+
+@group(3) @binding(3)
+var<uniform> wind: Wind;
+
+#ifdef REFLECTION_PROBES
+// Must match MAX_REFLECTION_PROBES in reflection_probes.rs
+let MAX_REFLECTION_PROBES: i32 = 32;
+
+struct ReflectionProbes {
+    // x: the number of probe slots to consider
+    count: vec4<f32>,
+    // xyz: center, w: radius of influence, 0 for empty slots
+    probes: array<vec4<f32>, MAX_REFLECTION_PROBES>,
+}
+
+@group(3) @binding(4)
+var reflection_probe_textures: texture_cube_array<f32>;
+
+@group(3) @binding(5)
+var reflection_probe_sampler: sampler;
+
+@group(3) @binding(6)
+var<uniform> reflection_probes: ReflectionProbes;
+#endif
+
+// Samples the environment map in a world space direction, applying the environment's rotation
+// and intensity, or where `position` lies within a reflection probe, the nearest probe's
+// capture, blended to the environment map over the outer fifth of the probe's radius. Without
+// REFLECTION_PROBES, where the adapter can't bind cube array textures, only the environment map.
+fn sample_environment(position: vec3<f32>, direction: vec3<f32>) -> vec4<f32> {
+    let rotated = (environment.rotation * vec4<f32>(direction, 0.0)).xyz;
+    let color = textureSample(environment_map_texture, environment_map_sampler, rotated);
+    let environment_color = vec4<f32>(color.rgb * environment.intensity.x, color.a);
+
+#ifdef REFLECTION_PROBES
+    var probe_index = 0;
+    var probe_weight = 0.0;
+    var nearest = 1e30;
+    let count = min(i32(reflection_probes.count.x), MAX_REFLECTION_PROBES);
+    for (var i = 0; i < count; i = i + 1) {
+        let probe = reflection_probes.probes[i];
+        let distance = length(position - probe.xyz);
+        if (probe.w > 0.0 && distance < probe.w && distance < nearest) {
+            nearest = distance;
+            probe_index = i;
+            probe_weight = 1.0 - smoothstep(0.8 * probe.w, probe.w, distance);
+        }
+    }
+
+    // sampled whether or not a probe applies, keeping the sample in uniform control flow
+    let probe_color = textureSample(reflection_probe_textures, reflection_probe_sampler, direction, probe_index);
+    return mix(environment_color, probe_color, probe_weight);
+#else
+    return environment_color;
+#endif
+}
+
 //
 //  Model
 //
@@ -76,17 +217,12 @@ struct VertexInput {
     @location(2) normal: vec3<f32>,
     @location(3) tangent: vec3<f32>,
     @location(4) bitangent: vec3<f32>,
-};
-
-struct InstanceInput {
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
-
-    @location(9) normal_matrix_1: vec3<f32>,
-    @location(10) normal_matrix_2: vec3<f32>,
-    @location(11) normal_matrix_3: vec3<f32>,
+#ifdef VERTEX_SPLAT_WEIGHTS
+    @location(12) splat_weights: vec4<f32>,
+#endif
+#ifdef VERTEX_ANIMATION
+    @builtin(vertex_index) vertex_index: u32,
+#endif
 };
 
 struct VertexOutput {
@@ -100,12 +236,31 @@ struct VertexOutput {
     @location(6) tangent_view_position: vec3<f32>,
     @location(7) tangent_light_position: vec3<f32>,
     @location(8) tangent_light_dir: vec3<f32>,
+    // weights of terrain layers 0..3
+    @location(9) splat_weights: vec4<f32>,
 };
 
 //
 //  Util
 //
 
+// The wind's velocity at a world position; must match wind::Wind::velocity_at
+fn wind_velocity(world_position: vec3<f32>) -> vec3<f32> {
+    let phase = dot(world_position, wind.direction.xyz) * 0.1 - wind.gust.z * wind.gust.y * 6.2831853;
+    let gust = max(sin(phase) * 0.6 + sin(phase * 2.3 + 1.7) * 0.4, 0.0);
+    return wind.direction.xyz * (wind.direction.w + wind.gust.x * gust);
+}
+
+// Bends the vertex downwind in proportion to its height above the model's origin, see
+// MaterialProperties::wind_sway. The instance's phase shifts it along the gusts by that
+// fraction of their wavelength.
+fn apply_wind(world_position: vec4<f32>, model_height: f32, phase: f32) -> vec4<f32> {
+    let bend = material.wind_sway * max(model_height, 0.0);
+    // 2PI / 0.1 is the primary gust's wavelength, see wind_velocity
+    let gust_position = world_position.xyz + wind.direction.xyz * (phase * 62.831853);
+    return vec4<f32>(world_position.xyz + wind_velocity(gust_position) * bend, 1.0);
+}
+
 // returns [0,1] for where v lands in range [a,b]. Result is unclamped.
 fn inverse_lerp(a: f32, b: f32, v: f32) -> f32 {
     return (v - a) / (b - a);
@@ -122,8 +277,103 @@ fn fs_get_light_dir(in: VertexOutput) -> vec3<f32> {
     }
 }
 
+// Must match EVSM_EXPONENT in shadow.rs and shadow.wgsl
+let EVSM_EXPONENT: f32 = 40.0;
+
+// Bilinearly filtered read of the shadow moments; Rg32Float isn't filterable so we do it by hand
+fn fs_load_shadow_moments(shadow_coord: vec2<f32>) -> vec2<f32> {
+    let size = textureDimensions(shadow_moments_texture);
+    let texel = shadow_coord * vec2<f32>(size) - 0.5;
+    let base = floor(texel);
+    let t = texel - base;
+    let min_coord = vec2<i32>(0, 0);
+    let max_coord = size - vec2<i32>(1, 1);
+    let c0 = clamp(vec2<i32>(base), min_coord, max_coord);
+    let c1 = clamp(vec2<i32>(base) + vec2<i32>(1, 1), min_coord, max_coord);
+    let m00 = textureLoad(shadow_moments_texture, c0, 0).rg;
+    let m10 = textureLoad(shadow_moments_texture, vec2<i32>(c1.x, c0.y), 0).rg;
+    let m01 = textureLoad(shadow_moments_texture, vec2<i32>(c0.x, c1.y), 0).rg;
+    let m11 = textureLoad(shadow_moments_texture, c1, 0).rg;
+    return mix(mix(m00, m10, t.x), mix(m01, m11, t.x), t.y);
+}
+
+// Chebyshev's upper bound on the fraction of the light reaching `depth`, given the moments of the occluders
+fn chebyshev_upper_bound(moments: vec2<f32>, depth: f32, min_variance: f32) -> f32 {
+    let variance = max(moments.y - moments.x * moments.x, min_variance);
+    let d = depth - moments.x;
+    let p_max = variance / (variance + d * d);
+
+    // cut off the tail of the penumbra to hide light bleeding between overlapping occluders
+    let reduced_p_max = clamp((p_max - light.shadow.z) / (1.0 - light.shadow.z), 0.0, 1.0);
+    return select(reduced_p_max, 1.0, depth <= moments.x);
+}
+
+// Returns [0,1] for how much of the light reaches the fragment; 0 is fully shadowed.
+fn fs_compute_shadow_visibility(in: VertexOutput) -> f32 {
+    let shadow_mode = i32(light.shadow.w);
+    let world_position = in.world_position.xyz + normalize(in.world_normal) * light.shadow.x;
+    let light_clip_position = light.shadow_view_proj * vec4<f32>(world_position, 1.0);
+    let light_ndc = light_clip_position.xyz / light_clip_position.w;
+    let shadow_coord = light_ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+
+    var visibility = 1.0;
+    if (shadow_mode == 1) {
+        // keep the bilinear footprint within the map's region, off its neighbors in an atlas
+        let inset = 0.5 * light.shadow.y * light.shadow_rect.zw;
+        let atlas_coord = clamp(
+            shadow_coord * light.shadow_rect.zw + light.shadow_rect.xy,
+            light.shadow_rect.xy + inset,
+            light.shadow_rect.xy + light.shadow_rect.zw - inset
+        );
+        visibility = textureSampleCompareLevel(shadow_map_texture, shadow_map_sampler, atlas_coord, light_ndc.z);
+    } else if (shadow_mode == 2) {
+        let moments = fs_load_shadow_moments(shadow_coord);
+        visibility = chebyshev_upper_bound(moments, light_ndc.z, 0.00002);
+    } else if (shadow_mode == 3) {
+        let moments = fs_load_shadow_moments(shadow_coord);
+        let warped_depth = exp(EVSM_EXPONENT * light_ndc.z);
+        let min_variance = 0.00002 * EVSM_EXPONENT * EVSM_EXPONENT * warped_depth * warped_depth;
+        visibility = chebyshev_upper_bound(moments, warped_depth, min_variance);
+    }
+
+    // fragments outside the shadow volume are treated as lit
+    let in_shadow_volume = all(shadow_coord >= vec2<f32>(0.0)) && all(shadow_coord <= vec2<f32>(1.0)) && light_ndc.z <= 1.0;
+    return select(1.0, visibility, in_shadow_volume);
+}
+
+// ALPHA_MASK is defined by the material's variant key. Fragment entry points discard when
+// this returns true; the discard can't live in a helper because the GL backend emits every
+// function into the vertex stage too.
+fn alpha_masked(alpha: f32) -> bool {
+#ifdef ALPHA_MASK
+    return alpha < material.alpha_cutoff;
+#else
+    return false;
+#endif
+}
+
+// Fades a light to nothing at its radius, so geometry culled beyond it doesn't pop; see
+// light::Light::radius
+fn light_radius_window(light_distance: f32) -> f32 {
+    if (light.radius <= 0.0) {
+        return 1.0;
+    }
+    let ratio = light_distance / light.radius;
+    let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window;
+}
+
 fn fs_compute_light_attenuation(in: VertexOutput) -> f32 {
     let light_distance = length(light.position - in.world_position.xyz);
+    if (light.simple_attenuation > 0.0) {
+        // simplified lights cast no shadows, and their spot cone is hard edged
+        var simple = light.simple_attenuation * light_radius_window(light_distance);
+        if (light.light_type == 2) {
+            let to_light = normalize(in.world_position.xyz - light.position);
+            simple = simple * step(light.attenuation.w, dot(to_light, light.direction));
+        }
+        return simple;
+    }
     var light_attenuation = 1.0 / (light.attenuation.x + (light.attenuation.y * light_distance) + (light.attenuation.z * light_distance * light_distance));
 
     if (light.light_type == 2) {
@@ -134,7 +384,139 @@ fn fs_compute_light_attenuation(in: VertexOutput) -> f32 {
         light_attenuation = light_attenuation * spot;
     }
 
-    return light_attenuation;
+    return light_attenuation * light_radius_window(light_distance) * fs_compute_shadow_visibility(in);
+}
+
+// The tint of the light's gobo reaching the fragment, see light::Light::set_gobo. Sampled
+// from the top mip level, as the projection's derivatives jump across the spot's cone.
+fn fs_compute_gobo(in: VertexOutput) -> vec3<f32> {
+    let mode = i32(light.gobo.x);
+    let from_light = in.world_position.xyz - light.position;
+    let c = cos(light.gobo.y);
+    let s = sin(light.gobo.y);
+
+    if (mode == 1) {
+        // project across the cone, with the texture's top toward world up
+        let forward = light.direction;
+        let reference = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), abs(forward.y) > 0.99);
+        let base_right = normalize(cross(forward, reference));
+        let base_up = cross(base_right, forward);
+        let right = c * base_right + s * base_up;
+        let up = c * base_up - s * base_right;
+        let depth = dot(from_light, forward);
+        if (depth <= 0.0) {
+            return vec3<f32>(0.0);
+        }
+        let cos_breadth = max(light.attenuation.w, 0.0001);
+        let tan_breadth = sqrt(max(1.0 - cos_breadth * cos_breadth, 0.0)) / cos_breadth;
+        let uv = vec2<f32>(dot(from_light, right), -dot(from_light, up)) / (depth * tan_breadth) * 0.5 + 0.5;
+        return textureSampleLevel(gobo_texture, gobo_sampler, uv, 0.0).rgb;
+    } else if (mode == 2) {
+        // turned about world up
+        let direction = vec3<f32>(c * from_light.x + s * from_light.z, from_light.y, c * from_light.z - s * from_light.x);
+        return textureSampleLevel(gobo_cube_texture, gobo_sampler, direction, 0.0).rgb;
+    }
+    return vec3<f32>(1.0);
+}
+
+// Terrain layer weights from the mesh's vertices, or even weights if it has none
+fn vertex_splat_weights(model: VertexInput) -> vec4<f32> {
+#ifdef VERTEX_SPLAT_WEIGHTS
+    return model.splat_weights;
+#else
+    return vec4<f32>(1.0);
+#endif
+}
+
+#ifdef CLIPMAP
+fn clipmap_height(xz: vec2<f32>) -> f32 {
+    let uv = xz / material.heightmap.x + 0.5;
+    return textureSampleLevel(heightmap_texture, heightmap_sampler, uv, 0.0).r * material.heightmap.y;
+}
+
+// Centers a clipmap ring vertex on the camera and displaces it by the heightmap; see clipmap.rs
+// for the vertex encoding. Texture coordinates span the heightmap.
+fn resolve_vertex(vertex: VertexInput, instance: InstanceInput) -> VertexInput {
+    let spacing = vertex.tex_coords.x;
+    let snap = vertex.normal.x;
+    let xz = floor(camera.view_pos.xz / snap) * snap + vertex.position.xz;
+
+    var height = clipmap_height(xz);
+    if (vertex.tex_coords.y > 0.5) {
+        // match the coarser ring's edge, which has no vertex here
+        let along = vertex.tangent.xz * spacing;
+        height = 0.5 * (clipmap_height(xz - along) + clipmap_height(xz + along));
+    }
+
+    // central differences across a grid cell
+    let dx = clipmap_height(xz + vec2<f32>(spacing, 0.0)) - clipmap_height(xz - vec2<f32>(spacing, 0.0));
+    let dz = clipmap_height(xz + vec2<f32>(0.0, spacing)) - clipmap_height(xz - vec2<f32>(0.0, spacing));
+
+    var out = vertex;
+    out.position = vec3<f32>(xz.x, height, xz.y);
+    out.tex_coords = xz / material.heightmap.x + 0.5;
+    out.tangent = normalize(vec3<f32>(2.0 * spacing, dx, 0.0));
+    out.bitangent = normalize(vec3<f32>(0.0, dz, 2.0 * spacing));
+    out.normal = normalize(cross(out.bitangent, out.tangent));
+    return out;
+}
+#else
+#ifdef VERTEX_ANIMATION
+fn vertex_animation_texel(frame: u32, vertex_index: u32) -> vec2<i32> {
+    let index = frame * u32(material.vertex_animation.z) + vertex_index;
+    let width = u32(material.vertex_animation.w);
+    return vec2<i32>(i32(index % width), i32(index / width));
+}
+
+// Replaces the vertex's position and normal with those of the baked animation at the scene's
+// time (carried by the wind uniform) plus the instance's offset, blending adjacent frames.
+// The tangent frame is turned to follow the normal.
+fn resolve_vertex(vertex: VertexInput, instance: InstanceInput) -> VertexInput {
+    let frame_count = material.vertex_animation.x;
+    let time = (wind.gust.z + instance.time_offset) * material.vertex_animation.y;
+    let frame = time - floor(time / frame_count) * frame_count;
+    let frame_a = u32(frame) % u32(frame_count);
+    let frame_b = (frame_a + 1u) % u32(frame_count);
+    let blend = fract(frame);
+
+    let texel_a = vertex_animation_texel(frame_a, vertex.vertex_index);
+    let texel_b = vertex_animation_texel(frame_b, vertex.vertex_index);
+    let position = mix(
+        textureLoad(vertex_animation_positions, texel_a, 0).xyz,
+        textureLoad(vertex_animation_positions, texel_b, 0).xyz,
+        blend
+    );
+    let normal = normalize(mix(
+        textureLoad(vertex_animation_normals, texel_a, 0).xyz,
+        textureLoad(vertex_animation_normals, texel_b, 0).xyz,
+        blend
+    ));
+
+    var out = vertex;
+    out.position = position;
+    out.normal = normal;
+    let tangent = vertex.tangent - normal * dot(normal, vertex.tangent);
+    if (dot(tangent, tangent) > 1e-8) {
+        // keep the bitangent on the same side of the tangent as in the rest pose
+        let handedness = sign(dot(cross(vertex.normal, vertex.tangent), vertex.bitangent));
+        out.tangent = normalize(tangent);
+        out.bitangent = cross(normal, out.tangent) * handedness;
+    }
+    return out;
+}
+#else
+fn resolve_vertex(vertex: VertexInput, instance: InstanceInput) -> VertexInput {
+    return vertex;
+}
+#endif
+#endif
+
+// Scales, rotates then offsets the mesh's texture coordinates by the material's transform
+fn transform_tex_coords(tex_coords: vec2<f32>) -> vec2<f32> {
+    let scaled = tex_coords * material.uv_transform.zw;
+    let c = cos(material.uv_rotation);
+    let s = sin(material.uv_rotation);
+    return vec2<f32>(c * scaled.x - s * scaled.y, s * scaled.x + c * scaled.y) + material.uv_transform.xy;
 }
 
 //
@@ -142,26 +524,18 @@ fn fs_compute_light_attenuation(in: VertexOutput) -> f32 {
 //
 
 @vertex
-fn vs_main_ambient(model: VertexInput, instance: InstanceInput) -> VertexOutput {
-    let model_matrix = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
-
-    let normal_matrix = mat3x3<f32>(
-        instance.normal_matrix_1,
-        instance.normal_matrix_2,
-        instance.normal_matrix_3,
-    );
+fn vs_main_ambient(vertex: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model = resolve_vertex(vertex, instance);
+    let model_matrix = instance_model_matrix(instance);
+    let normal_matrix = instance_normal_matrix(instance);
 
-    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+    var world_position: vec4<f32> = apply_wind(model_matrix * vec4<f32>(model.position, 1.0), model.position.y, instance.phase);
 
     var out: VertexOutput;
     out.clip_position = camera.view_proj * world_position;
     out.world_position = world_position;
-    out.tex_coords = model.tex_coords;
+    out.tex_coords = transform_tex_coords(model.tex_coords);
+    out.splat_weights = vertex_splat_weights(model);
     out.world_normal = normal_matrix * model.normal;
     out.world_tangent = normal_matrix * model.tangent;
     out.world_bitangent = normal_matrix * model.bitangent;
@@ -169,19 +543,10 @@ fn vs_main_ambient(model: VertexInput, instance: InstanceInput) -> VertexOutput
 }
 
 @vertex
-fn vs_main_lit(model: VertexInput, instance: InstanceInput) -> VertexOutput {
-    let model_matrix = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
-
-    let normal_matrix = mat3x3<f32>(
-        instance.normal_matrix_1,
-        instance.normal_matrix_2,
-        instance.normal_matrix_3,
-    );
+fn vs_main_lit(vertex: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model = resolve_vertex(vertex, instance);
+    let model_matrix = instance_model_matrix(instance);
+    let normal_matrix = instance_normal_matrix(instance);
 
     let world_normal = normalize(normal_matrix * model.normal);
     let world_tangent = normalize(normal_matrix * model.tangent);
@@ -192,12 +557,13 @@ fn vs_main_lit(model: VertexInput, instance: InstanceInput) -> VertexOutput {
         world_normal
     ));
 
-    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+    var world_position: vec4<f32> = apply_wind(model_matrix * vec4<f32>(model.position, 1.0), model.position.y, instance.phase);
 
     var out: VertexOutput;
     out.clip_position = camera.view_proj * world_position;
     out.world_position = world_position;
-    out.tex_coords = model.tex_coords;
+    out.tex_coords = transform_tex_coords(model.tex_coords);
+    out.splat_weights = vertex_splat_weights(model);
     out.world_normal = world_normal;
     out.tangent_position = tangent_matrix * world_position.xyz;
     out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
@@ -207,69 +573,185 @@ fn vs_main_lit(model: VertexInput, instance: InstanceInput) -> VertexOutput {
     return out;
 }
 
+// Renders the back faces of the model pushed out along their normals, leaving an ink
+// outline around the silhouette once the front faces are drawn over them.
+@vertex
+fn vs_main_outline(vertex: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model = resolve_vertex(vertex, instance);
+    let model_matrix = instance_model_matrix(instance);
+    let normal_matrix = instance_normal_matrix(instance);
+
+    let world_normal = normalize(normal_matrix * model.normal);
+    var world_position: vec4<f32> = apply_wind(model_matrix * vec4<f32>(model.position, 1.0), model.position.y, instance.phase);
+    world_position = vec4<f32>(world_position.xyz + world_normal * material.outline_width, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.world_position = world_position;
+    out.tex_coords = transform_tex_coords(model.tex_coords);
+    out.world_normal = world_normal;
+    return out;
+}
+
 //
 // Fragment Ambient
 //
 
-@fragment
-fn fs_main_ambient_untextured(in: VertexOutput) -> @location(0) vec4<f32> {
+// Samples the diffuse texture, or the current frame of its flipbook grid at the scene's time
+// (carried by the wind uniform). Gradients are taken from the unwrapped coordinates so that
+// cell edges don't drop to the smallest mip level.
+fn sample_diffuse(tex_coords: vec2<f32>) -> vec4<f32> {
+    let grid = material.flipbook.xy;
+    if (grid.x < 1.0) {
+        return textureSample(diffuse_texture, diffuse_sampler, tex_coords);
+    }
+
+    let frame = floor(wind.gust.z * material.flipbook.w) % material.flipbook.z;
+    let cell = vec2<f32>(frame % grid.x, floor(frame / grid.x));
+    let uv = (fract(tex_coords) + cell) / grid;
+    return textureSampleGrad(diffuse_texture, diffuse_sampler, uv, dpdx(tex_coords) / grid, dpdy(tex_coords) / grid);
+}
+
+// The ambient light's hemisphere blends from light.ambient (the sky) on surfaces facing
+// light.direction (up) to light.color (the ground) on those facing away
+fn hemisphere_ambient(normal: vec3<f32>) -> vec3<f32> {
+    let up = dot(normalize(normal), light.direction) * 0.5 + 0.5;
+    return mix(light.color, light.ambient, up);
+}
+
+// `occlusion` attenuates the ambient (but not reflected) light reaching the fragment
+
+fn ambient_untextured(in: VertexOutput, occlusion: f32) -> vec4<f32> {
     let object_color = material.diffuse;
     let object_normal = in.world_normal;
     let reflection_dir = reflect(normalize(in.world_position.xyz - camera.view_pos.xyz), object_normal);
-    let environment_color = textureSample(environment_map_texture, environment_map_sampler, in.world_normal).rgb;
-    let environment_reflection = material.specular.rgb * textureSample(environment_map_texture, environment_map_sampler, reflection_dir).rgb;
-    let ambient_color = (environment_color.rgb * material.ambient.rgb * object_color.rgb) + (light.ambient * object_color.rgb);
+    let environment_color = sample_environment(in.world_position.xyz, in.world_normal).rgb;
+    let environment_reflection = material.specular.rgb * sample_environment(in.world_position.xyz, reflection_dir).rgb;
+    let ambient_color = occlusion * ((environment_color.rgb * material.ambient.rgb * object_color.rgb) + (hemisphere_ambient(object_normal) * object_color.rgb));
 
     return vec4<f32>(environment_reflection + ambient_color, object_color.a);
 }
 
-@fragment
-fn fs_main_ambient_diffuse(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color = material.diffuse * textureSample(diffuse_texture, diffuse_sampler, in.tex_coords);
+fn ambient_diffuse(in: VertexOutput, occlusion: f32) -> vec4<f32> {
+    let object_color = material.diffuse * sample_diffuse(in.tex_coords);
     let object_normal = in.world_normal;
     let reflection_dir = reflect(normalize(in.world_position.xyz - camera.view_pos.xyz), object_normal);
-    let environment_color = textureSample(environment_map_texture, environment_map_sampler, in.world_normal).rgb;
-    let environment_reflection = material.specular.rgb * textureSample(environment_map_texture, environment_map_sampler, reflection_dir).rgb;
-    let ambient_color = (environment_color.rgb * material.ambient.rgb * object_color.rgb) + (light.ambient * object_color.rgb);
+    let environment_color = sample_environment(in.world_position.xyz, in.world_normal).rgb;
+    let environment_reflection = material.specular.rgb * sample_environment(in.world_position.xyz, reflection_dir).rgb;
+    let ambient_color = occlusion * ((environment_color.rgb * material.ambient.rgb * object_color.rgb) + (hemisphere_ambient(object_normal) * object_color.rgb));
 
     return vec4<f32>(environment_reflection + ambient_color, object_color.a);
 }
 
-@fragment
-fn fs_main_ambient_diffuse_normal(in: VertexOutput) -> @location(0) vec4<f32> {
+fn ambient_diffuse_normal(in: VertexOutput, occlusion: f32) -> vec4<f32> {
     let tangent_to_world = mat3x3<f32>(
         in.world_tangent,
         in.world_bitangent,
         in.world_normal
     );
 
-    let object_color = material.diffuse * textureSample(diffuse_texture, diffuse_sampler, in.tex_coords);
+    let object_color = material.diffuse * sample_diffuse(in.tex_coords);
     let object_normal = tangent_to_world * (textureSample(normal_texture, normal_sampler, in.tex_coords).xyz * 2.0 - 1.0);
     let reflection_dir = reflect(normalize(in.world_position.xyz - camera.view_pos.xyz), object_normal);
-    let environment_color = textureSample(environment_map_texture, environment_map_sampler, object_normal);
-    let environment_reflection = material.specular.rgb * textureSample(environment_map_texture, environment_map_sampler, reflection_dir).rgb;
-    let ambient_color = (environment_color.rgb * material.ambient.rgb * object_color.rgb) + (light.ambient * object_color.rgb);
+    let environment_color = sample_environment(in.world_position.xyz, object_normal);
+    let environment_reflection = material.specular.rgb * sample_environment(in.world_position.xyz, reflection_dir).rgb;
+    let ambient_color = occlusion * ((environment_color.rgb * material.ambient.rgb * object_color.rgb) + (hemisphere_ambient(object_normal) * object_color.rgb));
     return vec4<f32>(ambient_color, object_color.a);
 }
 
-@fragment
-fn fs_main_ambient_diffuse_normal_shininess(in: VertexOutput) -> @location(0) vec4<f32> {
+fn ambient_diffuse_normal_shininess(in: VertexOutput, occlusion: f32) -> vec4<f32> {
     let tangent_to_world = mat3x3<f32>(
         in.world_tangent,
         in.world_bitangent,
         in.world_normal
     );
 
-    let object_color = material.diffuse * textureSample(diffuse_texture, diffuse_sampler, in.tex_coords);
+    let object_color = material.diffuse * sample_diffuse(in.tex_coords);
     let object_normal = tangent_to_world * (textureSample(normal_texture, normal_sampler, in.tex_coords).xyz * 2.0 - 1.0);
     let object_shininess = material.specular.rgb * textureSample(shininess_texture, shininess_sampler, in.tex_coords).r;
     let reflection_dir = reflect(normalize(in.world_position.xyz - camera.view_pos.xyz), object_normal);
-    let environment_color = textureSample(environment_map_texture, environment_map_sampler, object_normal);
-    let environment_reflection = object_shininess * textureSample(environment_map_texture, environment_map_sampler, reflection_dir).rgb;
-    let ambient_color = (environment_color.rgb * material.ambient.rgb * object_color.rgb) + (light.ambient * object_color.rgb);
+    let environment_color = sample_environment(in.world_position.xyz, object_normal);
+    let environment_reflection = object_shininess * sample_environment(in.world_position.xyz, reflection_dir).rgb;
+    let ambient_color = occlusion * ((environment_color.rgb * material.ambient.rgb * object_color.rgb) + (hemisphere_ambient(object_normal) * object_color.rgb));
     return vec4<f32>(ambient_color, object_color.a);
 }
 
+fn sample_ambient_occlusion(in: VertexOutput) -> f32 {
+    return textureSample(ambient_occlusion_texture, ambient_occlusion_sampler, in.tex_coords).r;
+}
+
+@fragment
+fn fs_main_ambient_untextured(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = ambient_untextured(in, 1.0);
+    if (alpha_masked(color.a)) {
+        discard;
+    }
+    return color;
+}
+
+@fragment
+fn fs_main_ambient_diffuse(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = ambient_diffuse(in, 1.0);
+    if (alpha_masked(color.a)) {
+        discard;
+    }
+    return color;
+}
+
+@fragment
+fn fs_main_ambient_diffuse_normal(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = ambient_diffuse_normal(in, 1.0);
+    if (alpha_masked(color.a)) {
+        discard;
+    }
+    return color;
+}
+
+@fragment
+fn fs_main_ambient_diffuse_normal_shininess(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = ambient_diffuse_normal_shininess(in, 1.0);
+    if (alpha_masked(color.a)) {
+        discard;
+    }
+    return color;
+}
+
+@fragment
+fn fs_main_ambient_untextured_ao(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = ambient_untextured(in, sample_ambient_occlusion(in));
+    if (alpha_masked(color.a)) {
+        discard;
+    }
+    return color;
+}
+
+@fragment
+fn fs_main_ambient_diffuse_ao(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = ambient_diffuse(in, sample_ambient_occlusion(in));
+    if (alpha_masked(color.a)) {
+        discard;
+    }
+    return color;
+}
+
+@fragment
+fn fs_main_ambient_diffuse_normal_ao(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = ambient_diffuse_normal(in, sample_ambient_occlusion(in));
+    if (alpha_masked(color.a)) {
+        discard;
+    }
+    return color;
+}
+
+@fragment
+fn fs_main_ambient_diffuse_normal_shininess_ao(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = ambient_diffuse_normal_shininess(in, sample_ambient_occlusion(in));
+    if (alpha_masked(color.a)) {
+        discard;
+    }
+    return color;
+}
+
 
 //
 //  Fragment Lit
@@ -277,7 +759,7 @@ fn fs_main_ambient_diffuse_normal_shininess(in: VertexOutput) -> @location(0) ve
 
 @fragment
 fn fs_main_lit_diffuse_normal_shininess(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color:vec4<f32> = material.diffuse * textureSample(diffuse_texture, diffuse_sampler, in.tex_coords);
+    let object_color:vec4<f32> = material.diffuse * sample_diffuse(in.tex_coords);
     let object_normal:vec4<f32> = textureSample(normal_texture, normal_sampler, in.tex_coords);
     let object_shininess:vec4<f32> = textureSample(shininess_texture, shininess_sampler, in.tex_coords);
 
@@ -286,12 +768,17 @@ fn fs_main_lit_diffuse_normal_shininess(in: VertexOutput) -> @location(0) vec4<f
     let view_dir = normalize(in.tangent_view_position - in.tangent_position);
     let half_dir = normalize(view_dir + light_dir);
     let light_attenuation = fs_compute_light_attenuation(in);
+    let light_color = light.color * fs_compute_gobo(in);
 
     let diffuse_strength = light_attenuation * max(dot(tangent_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    let diffuse_color = light_color * diffuse_strength;
 
     let specular_strength = light_attenuation * pow(max(dot(tangent_normal, half_dir), 0.0), object_shininess.g * material.shininess);
-    let specular_color = object_shininess.r * specular_strength * light.color * material.specular.rgb;
+    let specular_color = object_shininess.r * specular_strength * light_color * material.specular.rgb;
+
+    if (alpha_masked(object_color.a)) {
+        discard;
+    }
 
     let result = (diffuse_color * object_color.rgb) + specular_color;
     return vec4<f32>(result, object_color.a);
@@ -299,7 +786,7 @@ fn fs_main_lit_diffuse_normal_shininess(in: VertexOutput) -> @location(0) vec4<f
 
 @fragment
 fn fs_main_lit_diffuse_normal(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color:vec4<f32> = material.diffuse * textureSample(diffuse_texture, diffuse_sampler, in.tex_coords);
+    let object_color:vec4<f32> = material.diffuse * sample_diffuse(in.tex_coords);
     let object_normal:vec4<f32> = textureSample(normal_texture, normal_sampler, in.tex_coords);
 
     let tangent_normal = object_normal.xyz * 2.0 - 1.0;
@@ -307,12 +794,17 @@ fn fs_main_lit_diffuse_normal(in: VertexOutput) -> @location(0) vec4<f32> {
     let view_dir = normalize(in.tangent_view_position - in.tangent_position);
     let half_dir = normalize(view_dir + light_dir);
     let light_attenuation = fs_compute_light_attenuation(in);
+    let light_color = light.color * fs_compute_gobo(in);
 
     let diffuse_strength = light_attenuation * max(dot(tangent_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    let diffuse_color = light_color * diffuse_strength;
 
     let specular_strength = light_attenuation * pow(max(dot(tangent_normal, half_dir), 0.0), material.shininess);
-    let specular_color = material.specular.rgb * specular_strength * light.color;
+    let specular_color = material.specular.rgb * specular_strength * light_color;
+
+    if (alpha_masked(object_color.a)) {
+        discard;
+    }
 
     let result = (diffuse_color * object_color.rgb) + specular_color;
     return vec4<f32>(result, object_color.a);
@@ -320,19 +812,24 @@ fn fs_main_lit_diffuse_normal(in: VertexOutput) -> @location(0) vec4<f32> {
 
 @fragment
 fn fs_main_lit_diffuse(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color:vec4<f32> = material.diffuse * textureSample(diffuse_texture, diffuse_sampler, in.tex_coords);
+    let object_color:vec4<f32> = material.diffuse * sample_diffuse(in.tex_coords);
 
     let tangent_normal = vec3<f32>(0.0, 0.0, 1.0);
     let light_dir = fs_get_light_dir(in);
     let view_dir = normalize(in.tangent_view_position - in.tangent_position);
     let half_dir = normalize(view_dir + light_dir);
     let light_attenuation = fs_compute_light_attenuation(in);
+    let light_color = light.color * fs_compute_gobo(in);
 
     let diffuse_strength = light_attenuation * max(dot(tangent_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    let diffuse_color = light_color * diffuse_strength;
 
     let specular_strength = light_attenuation * pow(max(dot(tangent_normal, half_dir), 0.0), material.shininess);
-    let specular_color = material.specular.rgb * specular_strength * light.color;
+    let specular_color = material.specular.rgb * specular_strength * light_color;
+
+    if (alpha_masked(object_color.a)) {
+        discard;
+    }
 
     let result = (diffuse_color * object_color.rgb) + specular_color;
     return vec4<f32>(result, object_color.a);
@@ -347,13 +844,193 @@ fn fs_main_lit_untextured(in: VertexOutput) -> @location(0) vec4<f32> {
     let view_dir = normalize(in.tangent_view_position - in.tangent_position);
     let half_dir = normalize(view_dir + light_dir);
     let light_attenuation = fs_compute_light_attenuation(in);
+    let light_color = light.color * fs_compute_gobo(in);
+
+    let diffuse_strength = light_attenuation * max(dot(tangent_normal, light_dir), 0.0);
+    let diffuse_color = light_color * diffuse_strength;
+
+    let specular_strength = light_attenuation * pow(max(dot(tangent_normal, half_dir), 0.0), material.shininess);
+    let specular_color = material.specular.rgb * specular_strength * light_color;
+
+    if (alpha_masked(object_color.a)) {
+        discard;
+    }
+
+    let result = (diffuse_color * object_color.rgb) + specular_color;
+    return vec4<f32>(result, object_color.a);
+}
+
+//
+//  Fragment Lit Toon
+//
+
+fn lit_toon(in: VertexOutput, object_color: vec4<f32>, tangent_normal: vec3<f32>) -> vec4<f32> {
+    let light_dir = fs_get_light_dir(in);
+    let view_dir = normalize(in.tangent_view_position - in.tangent_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let light_attenuation = fs_compute_light_attenuation(in);
+    let light_color = light.color * fs_compute_gobo(in);
+
+    // quantize diffuse into flat bands; any lit fragment gets at least the first band
+    let bands = max(material.toon_bands, 1.0);
+    let n_dot_l = max(dot(tangent_normal, light_dir), 0.0);
+    let diffuse_strength = light_attenuation * ceil(n_dot_l * bands) / bands;
+    let diffuse_color = light_color * diffuse_strength;
+
+    // hard edged specular highlight
+    let specular_term = pow(max(dot(tangent_normal, half_dir), 0.0), material.shininess);
+    let specular_strength = light_attenuation * step(0.5, specular_term);
+    let specular_color = material.specular.rgb * specular_strength * light_color;
+
+    // rim light along the silhouette, on the side facing the light
+    let rim_term = pow(1.0 - max(dot(tangent_normal, view_dir), 0.0), material.toon_rim_power) * n_dot_l;
+    let rim_strength = light_attenuation * material.toon_rim_strength * smoothstep(0.45, 0.5, rim_term);
+    let rim_color = light_color * rim_strength;
+
+    let result = (diffuse_color * object_color.rgb) + specular_color + rim_color;
+    return vec4<f32>(result, object_color.a);
+}
+
+@fragment
+fn fs_main_lit_toon_diffuse_normal(in: VertexOutput) -> @location(0) vec4<f32> {
+    let object_color:vec4<f32> = material.diffuse * sample_diffuse(in.tex_coords);
+    let object_normal:vec4<f32> = textureSample(normal_texture, normal_sampler, in.tex_coords);
+    let color = lit_toon(in, object_color, object_normal.xyz * 2.0 - 1.0);
+    if (alpha_masked(color.a)) {
+        discard;
+    }
+    return color;
+}
+
+@fragment
+fn fs_main_lit_toon_diffuse(in: VertexOutput) -> @location(0) vec4<f32> {
+    let object_color:vec4<f32> = material.diffuse * sample_diffuse(in.tex_coords);
+    let color = lit_toon(in, object_color, vec3<f32>(0.0, 0.0, 1.0));
+    if (alpha_masked(color.a)) {
+        discard;
+    }
+    return color;
+}
+
+@fragment
+fn fs_main_lit_toon_untextured(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = lit_toon(in, material.diffuse, vec3<f32>(0.0, 0.0, 1.0));
+    if (alpha_masked(color.a)) {
+        discard;
+    }
+    return color;
+}
+
+//
+//  Fragment Terrain
+//
+
+struct TerrainSample {
+    albedo: vec4<f32>,
+    // tangent space
+    normal: vec3<f32>,
+};
+
+// The weight of a terrain layer, from the splat map and vertex weights
+fn terrain_layer_weight(in: VertexOutput, layer: i32) -> f32 {
+    var weight = 1.0;
+#ifdef HAS_SPLAT_MAP
+    let splat = textureSample(splat_map_texture, splat_map_sampler, in.tex_coords, layer / 4);
+    weight = splat[layer % 4];
+#endif
+    if (layer < 4) {
+        weight = weight * in.splat_weights[layer];
+    }
+    return weight;
+}
+
+// Blends the material's tiling terrain layers by their weights
+fn sample_terrain(in: VertexOutput) -> TerrainSample {
+    let layer_count = i32(material.terrain.x);
+    let uv = in.tex_coords * material.terrain.y;
+
+    var albedo = vec4<f32>(0.0);
+    var normal = vec3<f32>(0.0);
+    var total_weight = 0.0;
+    for (var layer: i32 = 0; layer < layer_count; layer = layer + 1) {
+        let weight = terrain_layer_weight(in, layer);
+        albedo = albedo + weight * textureSample(terrain_albedo_texture, terrain_albedo_sampler, uv, layer);
+#ifdef HAS_TERRAIN_NORMAL_TEXTURE
+        normal = normal + weight * (textureSample(terrain_normal_texture, terrain_normal_sampler, uv, layer).xyz * 2.0 - 1.0);
+#else
+        normal = normal + weight * vec3<f32>(0.0, 0.0, 1.0);
+#endif
+        total_weight = total_weight + weight;
+    }
+
+    var out: TerrainSample;
+    if (total_weight <= 0.0) {
+        out.albedo = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        out.normal = vec3<f32>(0.0, 0.0, 1.0);
+    } else {
+        out.albedo = material.diffuse * albedo / total_weight;
+        out.normal = normalize(normal);
+    }
+    return out;
+}
+
+@fragment
+fn fs_main_ambient_terrain(in: VertexOutput) -> @location(0) vec4<f32> {
+    let terrain = sample_terrain(in);
+    let tangent_to_world = mat3x3<f32>(
+        in.world_tangent,
+        in.world_bitangent,
+        in.world_normal
+    );
+
+#ifdef HAS_AMBIENT_OCCLUSION_TEXTURE
+    let occlusion = sample_ambient_occlusion(in);
+#else
+    let occlusion = 1.0;
+#endif
+
+    let object_color = terrain.albedo;
+    let object_normal = tangent_to_world * terrain.normal;
+    let environment_color = sample_environment(in.world_position.xyz, object_normal);
+    let ambient_color = occlusion * ((environment_color.rgb * material.ambient.rgb * object_color.rgb) + (hemisphere_ambient(object_normal) * object_color.rgb));
+
+    if (alpha_masked(object_color.a)) {
+        discard;
+    }
+    return vec4<f32>(ambient_color, object_color.a);
+}
+
+@fragment
+fn fs_main_lit_terrain(in: VertexOutput) -> @location(0) vec4<f32> {
+    let terrain = sample_terrain(in);
+    let object_color = terrain.albedo;
+
+    let tangent_normal = terrain.normal;
+    let light_dir = fs_get_light_dir(in);
+    let view_dir = normalize(in.tangent_view_position - in.tangent_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let light_attenuation = fs_compute_light_attenuation(in);
+    let light_color = light.color * fs_compute_gobo(in);
 
     let diffuse_strength = light_attenuation * max(dot(tangent_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    let diffuse_color = light_color * diffuse_strength;
 
     let specular_strength = light_attenuation * pow(max(dot(tangent_normal, half_dir), 0.0), material.shininess);
-    let specular_color = material.specular.rgb * specular_strength * light.color;
+    let specular_color = material.specular.rgb * specular_strength * light_color;
+
+    if (alpha_masked(object_color.a)) {
+        discard;
+    }
 
     let result = (diffuse_color * object_color.rgb) + specular_color;
     return vec4<f32>(result, object_color.a);
-}
\ No newline at end of file
+}
+
+//
+//  Fragment Outline
+//
+
+@fragment
+fn fs_main_outline(in: VertexOutput) -> @location(0) vec4<f32> {
+    return material.outline_color;
+}
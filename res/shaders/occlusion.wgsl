@@ -0,0 +1,78 @@
+//
+//  Uniforms
+//
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view_pos: vec4<f32>,
+    proj_inverse: mat4x4<f32>,
+    view_inverse: mat4x4<f32>,
+};
+
+struct OcclusionUniform {
+    // x: z_near, y: z_far (0 for an infinite far plane), z: 1 if reversed depth
+    camera_depth: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(1) @binding(0)
+var<uniform> occlusion: OcclusionUniform;
+
+@group(2) @binding(0)
+var depth_texture: texture_2d<f32>;
+
+// per occludee, set to 1 by any of its proxy's fragments which pass the depth test
+@group(2) @binding(1)
+var<storage, read_write> visibility: array<u32>;
+
+//
+//  Proxies
+//
+
+struct OccludeeInput {
+    @location(0) bounds_min: vec3<f32>,
+    @location(1) bounds_max: vec3<f32>,
+    @location(2) index: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) index: u32,
+};
+
+// Draws each occludee's bounds as a box of 14 vertices in a triangle strip
+@vertex
+fn vs_main_occlusion(
+    @builtin(vertex_index) vertex_index: u32,
+    occludee: OccludeeInput,
+) -> VertexOutput {
+    // each mask's bits select the max side of one axis for the strip's vertices in turn
+    let bit = 1u << vertex_index;
+    let corner = vec3<f32>(
+        select(0.0, 1.0, (0x287au & bit) != 0u),
+        select(0.0, 1.0, (0x02afu & bit) != 0u),
+        select(0.0, 1.0, (0x31e3u & bit) != 0u),
+    );
+    let position = mix(occludee.bounds_min, occludee.bounds_max, corner);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.index = occludee.index;
+    return out;
+}
+
+// The depth attachment is read rather than attached, so fragments behind the scene don't mark
+// their occludee visible whether or not the depth test runs before the fragment shader
+@fragment
+fn fs_main_occlusion(in: VertexOutput) -> @location(0) vec4<f32> {
+    let scene_depth = textureLoad(depth_texture, vec2<i32>(in.clip_position.xy), 0).r;
+    let depth = in.clip_position.z;
+    let in_front = select(depth <= scene_depth, depth >= scene_depth, occlusion.camera_depth.z > 0.5);
+    if (in_front) {
+        visibility[in.index] = 1u;
+    }
+    // blended away, see occlusion::OcclusionCuller::prepare_pipeline
+    return vec4<f32>(0.0);
+}
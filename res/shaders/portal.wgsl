@@ -0,0 +1,65 @@
+//
+//  Uniforms
+//
+
+struct PortalUniform {
+    // places the unit quad, centered in its xy plane and facing +z, in the world
+    model: mat4x4<f32>,
+    // x: 1 if the view is mirrored, y: 1 if the portal is open
+    params: vec4<f32>,
+    // rgb: color drawn when the portal is closed
+    closed_color: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> portal: PortalUniform;
+
+// the view through the portal, rendered at the same size as the target the portal is drawn into
+@group(0) @binding(1)
+var view_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var view_sampler: sampler;
+
+// The camera uniform leads with its view projection matrix, see depth.wgsl
+struct View {
+    view_proj: mat4x4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> view: View;
+
+//
+//  Surface
+//
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main_portal(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    // two counter-clockwise triangles
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, 0.5),
+    );
+    let corner = corners[vertex_index];
+
+    var out: VertexOutput;
+    out.clip_position = view.view_proj * portal.model * vec4<f32>(corner, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main_portal(in: VertexOutput) -> @location(0) vec4<f32> {
+    // the view was rendered by a camera continuing this one through the portal, so lines
+    // up with the target pixel for pixel
+    var uv = in.clip_position.xy / vec2<f32>(textureDimensions(view_texture));
+    uv.x = mix(uv.x, 1.0 - uv.x, portal.params.x);
+    let color = textureSample(view_texture, view_sampler, uv);
+    return select(vec4<f32>(portal.closed_color.rgb, 1.0), vec4<f32>(color.rgb, 1.0), portal.params.y > 0.5);
+}
@@ -0,0 +1,68 @@
+#include "shaders/instance.wgsl"
+
+//
+//  Uniforms
+//
+
+struct Light {
+    shadow_view_proj: mat4x4<f32>,
+    position: vec3<f32>,
+    direction: vec3<f32>,
+    ambient: vec3<f32>,
+    color: vec3<f32>,
+    attenuation: vec4<f32>,
+    light_type: i32,
+    radius: f32,
+    shadow: vec4<f32>,
+    shadow_rect: vec4<f32>,
+};
+
+// Must match EVSM_EXPONENT in shadow.rs and model.wgsl
+let EVSM_EXPONENT: f32 = 40.0;
+
+@group(0) @binding(0)
+var<uniform> light: Light;
+
+//
+//  Model
+//
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+//
+// Vertex
+//
+
+@vertex
+fn vs_main_shadow(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let model_matrix = instance_model_matrix(instance);
+
+    return light.shadow_view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+}
+
+// A triangle on the far plane covering the viewport, which clears a shadow atlas region's
+// depth, see shadow_atlas::ShadowAtlasTexture
+@vertex
+fn vs_main_atlas_clear(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let x = f32((in_vertex_index << 1u) & 2u);
+    let y = f32(in_vertex_index & 2u);
+    return vec4<f32>(x * 2.0 - 1.0, 1.0 - y * 2.0, 1.0, 1.0);
+}
+
+//
+// Fragment
+//
+
+@fragment
+fn fs_main_shadow_variance(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let depth = position.z;
+    return vec4<f32>(depth, depth * depth, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main_shadow_exponential_variance(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let warped_depth = exp(EVSM_EXPONENT * position.z);
+    return vec4<f32>(warped_depth, warped_depth * warped_depth, 0.0, 1.0);
+}
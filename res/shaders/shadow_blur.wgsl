@@ -0,0 +1,46 @@
+// Separable gaussian blur for variance shadow map moments
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var source_texture: texture_2d<f32>;
+
+@vertex
+fn vs_main_blur(
+    @builtin(vertex_index) in_vertex_index: u32,
+) -> VertexOutput {
+    // wgsl doesn't let us index `let` arrays with a variable. So it has to be a `var` local to this function.
+    var fsq_clip_positions:array<vec4<f32>,3> = array<vec4<f32>, 3>(vec4<f32>(-1.0, 1.0, 0.0, 1.0), vec4<f32>(3.0, 1.0, 0.0, 1.0), vec4<f32>(-1.0, -3.0, 0.0, 1.0));
+
+    var out: VertexOutput;
+    out.clip_position = fsq_clip_positions[in_vertex_index];
+    return out;
+}
+
+// 9-tap gaussian along `direction`, clamping taps to the texture's edges
+fn blur(in: VertexOutput, direction: vec2<i32>) -> vec4<f32> {
+    var weights:array<f32,5> = array<f32, 5>(0.2270270270, 0.1945945946, 0.1216216216, 0.0540540541, 0.0162162162);
+    let max_coord = textureDimensions(source_texture) - vec2<i32>(1, 1);
+    let coord = vec2<i32>(in.clip_position.xy);
+
+    var result = textureLoad(source_texture, coord, 0).rg * weights[0];
+    for (var i: i32 = 1; i < 5; i = i + 1) {
+        let offset = direction * i;
+        result = result + textureLoad(source_texture, clamp(coord + offset, vec2<i32>(0, 0), max_coord), 0).rg * weights[i];
+        result = result + textureLoad(source_texture, clamp(coord - offset, vec2<i32>(0, 0), max_coord), 0).rg * weights[i];
+    }
+
+    return vec4<f32>(result, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main_blur_horizontal(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in, vec2<i32>(1, 0));
+}
+
+@fragment
+fn fs_main_blur_vertical(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in, vec2<i32>(0, 1));
+}
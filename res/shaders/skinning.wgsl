@@ -0,0 +1,98 @@
+//
+//  Uniforms
+//
+
+struct SkinningUniform {
+    // x: vertex count
+    counts: vec4<u32>,
+};
+
+struct SkinWeights {
+    joints: vec4<u32>,
+    weights: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> skinning: SkinningUniform;
+
+// rest pose and skinned vertices are laid out as model::ModelVertex: position, tex coords,
+// normal, tangent and bitangent, 14 floats in all
+@group(0) @binding(1)
+var<storage, read> rest_vertices: array<f32>;
+
+@group(0) @binding(2)
+var<storage, read> skin_weights: array<SkinWeights>;
+
+@group(0) @binding(3)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+@group(0) @binding(4)
+var<storage, read_write> skinned_vertices: array<f32>;
+
+let VERTEX_STRIDE: u32 = 14u;
+let POSITION_OFFSET: u32 = 0u;
+let NORMAL_OFFSET: u32 = 5u;
+let TANGENT_OFFSET: u32 = 8u;
+let BITANGENT_OFFSET: u32 = 11u;
+
+//
+//  Util
+//
+
+fn read_vec3(index: u32) -> vec3<f32> {
+    return vec3<f32>(rest_vertices[index], rest_vertices[index + 1u], rest_vertices[index + 2u]);
+}
+
+fn write_vec3(index: u32, value: vec3<f32>) {
+    skinned_vertices[index] = value.x;
+    skinned_vertices[index + 1u] = value.y;
+    skinned_vertices[index + 2u] = value.z;
+}
+
+// normalizes, leaving zero length vectors (e.g. unset tangents) alone
+fn safe_normalize(v: vec3<f32>) -> vec3<f32> {
+    let length_squared = dot(v, v);
+    if (length_squared > 0.0) {
+        return v / sqrt(length_squared);
+    }
+    return v;
+}
+
+//
+//  Entry point
+//
+
+@compute @workgroup_size(64)
+fn cs_main_skinning(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= skinning.counts.x) {
+        return;
+    }
+
+    let skin = skin_weights[index];
+    let total = dot(skin.weights, vec4<f32>(1.0));
+    var skin_matrix = mat4x4<f32>(
+        vec4<f32>(1.0, 0.0, 0.0, 0.0),
+        vec4<f32>(0.0, 1.0, 0.0, 0.0),
+        vec4<f32>(0.0, 0.0, 1.0, 0.0),
+        vec4<f32>(0.0, 0.0, 0.0, 1.0),
+    );
+    if (total > 0.0) {
+        let weights = skin.weights / total;
+        skin_matrix = joint_matrices[skin.joints.x] * weights.x
+            + joint_matrices[skin.joints.y] * weights.y
+            + joint_matrices[skin.joints.z] * weights.z
+            + joint_matrices[skin.joints.w] * weights.w;
+    }
+    // joints are assumed free of non-uniform scale, so directions transform by the upper 3x3
+    let rotation = mat3x3<f32>(skin_matrix[0].xyz, skin_matrix[1].xyz, skin_matrix[2].xyz);
+
+    let base = index * VERTEX_STRIDE;
+    let position = skin_matrix * vec4<f32>(read_vec3(base + POSITION_OFFSET), 1.0);
+    write_vec3(base + POSITION_OFFSET, position.xyz);
+    skinned_vertices[base + 3u] = rest_vertices[base + 3u];
+    skinned_vertices[base + 4u] = rest_vertices[base + 4u];
+    write_vec3(base + NORMAL_OFFSET, safe_normalize(rotation * read_vec3(base + NORMAL_OFFSET)));
+    write_vec3(base + TANGENT_OFFSET, safe_normalize(rotation * read_vec3(base + TANGENT_OFFSET)));
+    write_vec3(base + BITANGENT_OFFSET, safe_normalize(rotation * read_vec3(base + BITANGENT_OFFSET)));
+}
@@ -0,0 +1,250 @@
+#include "shaders/depth_linearization.wgsl"
+#include "shaders/fog_volume.wgsl"
+
+//
+//  Uniforms
+//
+
+struct Light {
+    // shadow_view_proj leads so depth.wgsl can bind the light uniform for shadow passes
+    shadow_view_proj: mat4x4<f32>,
+
+    position: vec3<f32>,
+    direction: vec3<f32>,
+    ambient: vec3<f32>,
+    color: vec3<f32>,
+
+    // x: constant, y: linear, z: exponential, w: dot spot breadth
+    attenuation: vec4<f32>,
+
+    // 0: Ambient
+    // 1: Point
+    // 2: Spot
+    // 3: Directional
+    light_type: i32,
+    // distance beyond which point and spot lights have no effect, 0 for unbounded
+    radius: f32,
+
+    // x: normal offset bias, y: shadow map texel size, z: light bleeding reduction,
+    // w: shadow mode (0: none, 1: pcf, 2: variance, 3: exponential variance)
+    shadow: vec4<f32>,
+    // the shadow map's region of shadow_map_texture, which may be a shadow atlas, as
+    // xy: offset, zw: scale in texture coordinates
+    shadow_rect: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> fog: FogUniform;
+
+// per froxel, rgb: light scattered toward the camera per world unit, a: extinction per world unit
+@group(0) @binding(1)
+var<storage, read_write> scattering: array<vec4<f32>>;
+
+// per froxel, rgb: light scattered toward the camera between it and the camera, a: transmittance,
+// as packed rgba16float texels in rows padded for copying to the fog volume texture
+@group(0) @binding(2)
+var<storage, read_write> fog_volume: array<vec2<u32>>;
+
+@group(1) @binding(0)
+var<uniform> light: Light;
+
+@group(1) @binding(1)
+var shadow_map_texture: texture_depth_2d;
+
+@group(1) @binding(2)
+var shadow_map_sampler: sampler_comparison;
+
+@group(1) @binding(3)
+var shadow_moments_texture: texture_2d<f32>;
+
+let PI: f32 = 3.14159265359;
+
+// Must match EVSM_EXPONENT in shadow.rs and shadow.wgsl
+let EVSM_EXPONENT: f32 = 40.0;
+
+//
+//  Util
+//
+
+fn froxel_index(id: vec3<u32>) -> u32 {
+    let grid = vec3<u32>(fog.grid.xyz);
+    return (id.z * grid.y + id.y) * grid.x + id.x;
+}
+
+// Must match VolumetricFog::volume_row_texels; buffer to texture copies need rows of a multiple of
+// 256 bytes, which is 32 rgba16float texels
+fn volume_index(id: vec3<u32>) -> u32 {
+    let grid = vec3<u32>(fog.grid.xyz);
+    let row_texels = (grid.x + 31u) / 32u * 32u;
+    return (id.z * grid.y + id.y) * row_texels + id.x;
+}
+
+fn in_grid(id: vec3<u32>) -> bool {
+    return all(id < vec3<u32>(fog.grid.xyz));
+}
+
+// World position of the center of a froxel
+fn froxel_position(id: vec3<u32>) -> vec3<f32> {
+    let grid = fog.grid.xyz;
+    let tex_coord = (vec2<f32>(id.xy) + 0.5) / grid.xy;
+    let view_depth = fog_slice_depth((f32(id.z) + 0.5) / grid.z, fog);
+    let depth = depth_from_linear(view_depth, fog.depth.xyz);
+    let position = world_position_from_depth(tex_coord, depth, fog.inverse_view_proj);
+    return position.xyz / position.w;
+}
+
+// The Henyey-Greenstein phase function, `cos_theta` between the light's direction of travel
+// and the direction toward the camera
+fn phase(cos_theta: f32, g: f32) -> f32 {
+    let g2 = g * g;
+    return (1.0 - g2) / (4.0 * PI * pow(max(1.0 + g2 - 2.0 * g * cos_theta, 1e-4), 1.5));
+}
+
+fn inverse_lerp(a: f32, b: f32, v: f32) -> f32 {
+    return clamp((v - a) / (b - a), 0.0, 1.0);
+}
+
+// Matches light_radius_window in model.wgsl
+fn light_radius_window(light_distance: f32) -> f32 {
+    if (light.radius <= 0.0) {
+        return 1.0;
+    }
+    let ratio = light_distance / light.radius;
+    let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window;
+}
+
+// Bilinearly filtered read of the shadow moments; Rg32Float isn't filterable so we do it by hand
+fn load_shadow_moments(shadow_coord: vec2<f32>) -> vec2<f32> {
+    let size = textureDimensions(shadow_moments_texture);
+    let texel = shadow_coord * vec2<f32>(size) - 0.5;
+    let base = floor(texel);
+    let t = texel - base;
+    let min_coord = vec2<i32>(0, 0);
+    let max_coord = size - vec2<i32>(1, 1);
+    let c0 = clamp(vec2<i32>(base), min_coord, max_coord);
+    let c1 = clamp(vec2<i32>(base) + vec2<i32>(1, 1), min_coord, max_coord);
+    let m00 = textureLoad(shadow_moments_texture, c0, 0).rg;
+    let m10 = textureLoad(shadow_moments_texture, vec2<i32>(c1.x, c0.y), 0).rg;
+    let m01 = textureLoad(shadow_moments_texture, vec2<i32>(c0.x, c1.y), 0).rg;
+    let m11 = textureLoad(shadow_moments_texture, c1, 0).rg;
+    return mix(mix(m00, m10, t.x), mix(m01, m11, t.x), t.y);
+}
+
+fn chebyshev_upper_bound(moments: vec2<f32>, depth: f32, min_variance: f32) -> f32 {
+    let variance = max(moments.y - moments.x * moments.x, min_variance);
+    let d = depth - moments.x;
+    let p_max = variance / (variance + d * d);
+    let reduced_p_max = clamp((p_max - light.shadow.z) / (1.0 - light.shadow.z), 0.0, 1.0);
+    return select(reduced_p_max, 1.0, depth <= moments.x);
+}
+
+// [0,1] for how much of the light reaches `position`, as fs_compute_shadow_visibility in
+// model.wgsl without the normal offset, as the medium has no surface
+fn shadow_visibility(position: vec3<f32>) -> f32 {
+    let shadow_mode = i32(light.shadow.w);
+    let light_clip_position = light.shadow_view_proj * vec4<f32>(position, 1.0);
+    let light_ndc = light_clip_position.xyz / light_clip_position.w;
+    let shadow_coord = light_ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+
+    var visibility = 1.0;
+    if (shadow_mode == 1) {
+        let inset = 0.5 * light.shadow.y * light.shadow_rect.zw;
+        let atlas_coord = clamp(
+            shadow_coord * light.shadow_rect.zw + light.shadow_rect.xy,
+            light.shadow_rect.xy + inset,
+            light.shadow_rect.xy + light.shadow_rect.zw - inset
+        );
+        visibility = textureSampleCompareLevel(shadow_map_texture, shadow_map_sampler, atlas_coord, light_ndc.z);
+    } else if (shadow_mode == 2) {
+        let moments = load_shadow_moments(shadow_coord);
+        visibility = chebyshev_upper_bound(moments, light_ndc.z, 0.00002);
+    } else if (shadow_mode == 3) {
+        let moments = load_shadow_moments(shadow_coord);
+        let warped_depth = exp(EVSM_EXPONENT * light_ndc.z);
+        let min_variance = 0.00002 * EVSM_EXPONENT * EVSM_EXPONENT * warped_depth * warped_depth;
+        visibility = chebyshev_upper_bound(moments, warped_depth, min_variance);
+    }
+
+    let in_shadow_volume = all(shadow_coord >= vec2<f32>(0.0)) && all(shadow_coord <= vec2<f32>(1.0)) && light_ndc.z <= 1.0;
+    return select(1.0, visibility, in_shadow_volume);
+}
+
+//
+//  Entry points
+//
+
+// Fills each froxel with the medium's extinction, and the ambient light it scatters
+@compute @workgroup_size(4, 4, 4)
+fn cs_main_density(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (!in_grid(id)) {
+        return;
+    }
+    let position = froxel_position(id);
+    let height = max(position.y - fog.medium.y, 0.0);
+    let extinction = fog.medium.x * exp(-height * fog.medium.z);
+    let ambient = fog.ambient.rgb * fog.albedo.rgb * extinction;
+    scattering[froxel_index(id)] = vec4<f32>(ambient, extinction);
+}
+
+// Adds the light scattered toward the camera by each froxel from the bound light
+@compute @workgroup_size(4, 4, 4)
+fn cs_main_light(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (!in_grid(id)) {
+        return;
+    }
+    let index = froxel_index(id);
+    let froxel = scattering[index];
+    if (froxel.a <= 0.0) {
+        return;
+    }
+
+    let position = froxel_position(id);
+    var to_light = light.direction;
+    var attenuation = 1.0;
+    if (light.light_type == 1 || light.light_type == 2) {
+        let offset = light.position - position;
+        let light_distance = length(offset);
+        to_light = offset / max(light_distance, 1e-4);
+        attenuation = light_radius_window(light_distance) / (light.attenuation.x + (light.attenuation.y * light_distance) + (light.attenuation.z * light_distance * light_distance));
+        if (light.light_type == 2) {
+            let d = clamp(dot(-to_light, light.direction), 0.0, 1.0);
+            attenuation = attenuation * inverse_lerp(light.attenuation.w, 1.0, d);
+        }
+    }
+    if (attenuation <= 0.0) {
+        return;
+    }
+
+    let view_dir = normalize(position - fog.camera_position.xyz);
+    let in_scattering = light.color * attenuation * shadow_visibility(position) * phase(dot(to_light, view_dir), fog.medium.w);
+    scattering[index] = vec4<f32>(froxel.rgb + in_scattering * fog.albedo.rgb * froxel.a, froxel.a);
+}
+
+// Accumulates each column of froxels front to back into the fog volume, each froxel holding
+// the light scattered toward the camera and the transmittance up to its far side
+@compute @workgroup_size(8, 8, 1)
+fn cs_main_integrate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let grid = vec3<u32>(fog.grid.xyz);
+    if (id.x >= grid.x || id.y >= grid.y) {
+        return;
+    }
+
+    var in_scattering = vec3<f32>(0.0);
+    var transmittance = 1.0;
+    var near = fog_slice_depth(0.0, fog);
+    for (var z = 0u; z < grid.z; z = z + 1u) {
+        let far = fog_slice_depth(f32(z + 1u) / f32(grid.z), fog);
+        let froxel = scattering[froxel_index(vec3<u32>(id.xy, z))];
+        let extinction = max(froxel.a, 1e-6);
+        let slice_transmittance = exp(-extinction * (far - near));
+        // scattering integrated through the slice, as it's attenuated along the way
+        in_scattering = in_scattering + transmittance * froxel.rgb * (1.0 - slice_transmittance) / extinction;
+        transmittance = transmittance * slice_transmittance;
+        fog_volume[volume_index(vec3<u32>(id.xy, z))] = vec2<u32>(
+            pack2x16float(in_scattering.rg),
+            pack2x16float(vec2<f32>(in_scattering.b, transmittance))
+        );
+        near = far;
+    }
+}
@@ -0,0 +1,106 @@
+#include "shaders/depth_linearization.wgsl"
+
+//
+//  Uniforms
+//
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view_pos: vec4<f32>,
+    proj_inverse: mat4x4<f32>,
+    view_inverse: mat4x4<f32>,
+};
+
+struct WeatherUniform {
+    color: vec4<f32>,
+    // x: z_near, y: z_far (0 for an infinite far plane), z: 1 if reversed depth, w: collision fade distance
+    camera_depth: vec4<f32>,
+    // x: volume extent, y: 0 for streaks, 1 for flakes
+    volume: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(1) @binding(0)
+var<uniform> weather: WeatherUniform;
+
+@group(2) @binding(0)
+var depth_attachment_texture: texture_2d<f32>;
+
+//
+//  Particles
+//
+
+struct InstanceInput {
+    @location(0) position: vec3<f32>,
+    @location(1) width: f32,
+    @location(2) axis: vec3<f32>,
+    @location(3) alpha: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) view_depth: f32,
+    @location(2) alpha: f32,
+    // the particle's radius: the streak cylinder's, or the flake sphere's
+    @location(3) radius: f32,
+};
+
+// linear depth of the scene at a pixel, in world units
+fn scene_linear_depth(pixel: vec2<f32>) -> f32 {
+    let depth = textureLoad(depth_attachment_texture, vec2<i32>(pixel), 0).r;
+    return linear_depth(depth, weather.camera_depth.xyz);
+}
+
+@vertex
+fn vs_main_weather(
+    @builtin(vertex_index) in_vertex_index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // wgsl doesn't let us index `let` arrays with a variable. So it has to be a `var` local to this function.
+    var corners: array<vec2<f32>, 6> = array<vec2<f32>, 6>(vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0));
+    let corner = corners[in_vertex_index];
+
+    let to_camera = normalize(camera.view_pos.xyz - instance.position);
+    let side = normalize(cross(instance.axis, to_camera)) * instance.width;
+    let world_position = instance.position + instance.axis * corner.y + side * corner.x;
+
+    // fade particles approaching the volume's edge, where they wrap around
+    let extent = weather.volume.x;
+    let distance = length(instance.position - camera.view_pos.xyz);
+    let edge_fade = 1.0 - smoothstep(0.75 * extent, extent, distance);
+
+    let forward = -camera.view_inverse[2].xyz;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.uv = corner;
+    out.view_depth = dot(world_position - camera.view_pos.xyz, forward);
+    out.alpha = instance.alpha * edge_fade;
+    out.radius = mix(instance.width, min(instance.width, length(instance.axis)), weather.volume.y);
+    return out;
+}
+
+@fragment
+fn fs_main_weather(in: VertexOutput) -> @location(0) vec4<f32> {
+    // fade out as the particle's front surface reaches scene geometry, hiding it entirely
+    // behind it. Flakes are treated as spheres and streaks as cylinders around their axis, so
+    // the surface bulges toward the camera by the particle's radius at its center and its
+    // seam with geometry is curved rather than a straight cut across the quad.
+    let radius2 = mix(in.uv.x * in.uv.x, dot(in.uv, in.uv), weather.volume.y);
+    let front = in.view_depth - in.radius * sqrt(max(1.0 - radius2, 0.0));
+    let scene_depth = scene_linear_depth(in.clip_position.xy);
+    let collision_fade = clamp((scene_depth - front) / weather.camera_depth.w, 0.0, 1.0);
+
+    let streak = (1.0 - abs(in.uv.x)) * (1.0 - in.uv.y * in.uv.y);
+    let flake = clamp(1.0 - length(in.uv), 0.0, 1.0);
+    let shape = mix(streak, flake, weather.volume.y);
+
+    let alpha = weather.color.a * in.alpha * collision_fade * shape;
+    if (alpha <= 0.0) {
+        discard;
+    }
+    return vec4<f32>(weather.color.rgb, alpha);
+}
@@ -0,0 +1,236 @@
+//! Packs an OBJ or glTF model and its textures offline for resources::load_packed_model, so
+//! large scenes load without parsing, tangent generation or image decoding.
+//!
+//!     cargo run --release --bin asset_import -- <model.obj|.gltf|.glb> <output.wgpm> [--lods <count>] [--mtl <materials.mtl>]
+//!
+//! The model's textures are written beside the output as mipmapped KTX2 files, named as its
+//! MTL or glTF file names them with the extension replaced, and the pack's materials name
+//! those; images embedded in a glTF file are named as gltf::GltfModel names them. --mtl reads
+//! an OBJ's materials from a library in place of the one it names, as load_model's
+//! material_name does. Put the output directory's contents in res/ to load them.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use wgpu_demo::{gltf, mesh_builder, packed_asset, texture};
+
+// Levels of detail generated per mesh, the original included, unless --lods says otherwise
+const DEFAULT_LOD_COUNT: usize = 4;
+
+struct Options {
+    input: PathBuf,
+    output: PathBuf,
+    lod_count: usize,
+    material_library: Option<PathBuf>,
+}
+
+fn main() {
+    let options = match parse_options(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Usage: asset_import <model.obj|.gltf|.glb> <output.wgpm> [--lods <count>] [--mtl <materials.mtl>]");
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = import(&options) {
+        eprintln!("Unable to import \"{}\": {:?}", options.input.display(), e);
+        std::process::exit(1);
+    }
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options> {
+    let mut paths = Vec::new();
+    let mut lod_count = DEFAULT_LOD_COUNT;
+    let mut material_library = None;
+    while let Some(arg) = args.next() {
+        if arg == "--lods" {
+            let count = args.next().ok_or_else(|| anyhow!("--lods needs a count"))?;
+            lod_count = count
+                .parse::<usize>()
+                .with_context(|| format!("\"{}\" isn't a level of detail count", count))?
+                .max(1);
+        } else if arg == "--mtl" {
+            let path = args.next().ok_or_else(|| anyhow!("--mtl needs a path"))?;
+            material_library = Some(PathBuf::from(path));
+        } else {
+            paths.push(PathBuf::from(arg));
+        }
+    }
+    match <[PathBuf; 2]>::try_from(paths) {
+        Ok([input, output]) => Ok(Options {
+            input,
+            output,
+            lod_count,
+            material_library,
+        }),
+        Err(_) => Err(anyhow!("Expected an input model and an output path")),
+    }
+}
+
+// A model read for packing: its materials, the encoded images embedded in it by name, and a
+// mesh builder per mesh
+struct SourceModel {
+    materials: Vec<packed_asset::PackedMaterial>,
+    images: HashMap<String, Vec<u8>>,
+    meshes: Vec<mesh_builder::MeshBuilder>,
+}
+
+fn import(options: &Options) -> Result<()> {
+    let extension = options
+        .input
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let source_dir = options.input.parent().unwrap_or_else(|| Path::new(""));
+    let source = match extension.as_deref() {
+        Some("obj") => read_obj(options, source_dir)?,
+        Some("gltf" | "glb") => read_gltf(options, source_dir)?,
+        _ => return Err(anyhow!("Only OBJ and glTF models are supported")),
+    };
+    let output_dir = options.output.parent().unwrap_or_else(|| Path::new(""));
+
+    // textures shared between materials are converted once
+    let mut converted: HashMap<String, String> = HashMap::new();
+    let mut materials = Vec::new();
+    for mut material in source.materials {
+        for (texture, is_linear) in material.textures_mut() {
+            if texture.is_empty() {
+                continue;
+            }
+            if let Some(name) = converted.get(texture.as_str()) {
+                *texture = name.clone();
+                continue;
+            }
+            match convert_texture(source_dir, &source.images, output_dir, texture, is_linear) {
+                Ok(name) => {
+                    converted.insert(texture.clone(), name.clone());
+                    *texture = name;
+                }
+                // left naming the source image, which the loader replaces with a placeholder
+                Err(e) => eprintln!("Unable to convert texture \"{}\": {:?}", texture, e),
+            }
+        }
+        materials.push(material);
+    }
+
+    let mut triangles = 0;
+    let meshes = source
+        .meshes
+        .into_iter()
+        .map(|builder| {
+            builder.validate()?;
+            let mut mesh = packed_asset::PackedMesh {
+                name: builder.name().to_owned(),
+                material: builder.material(),
+                vertices: builder.vertices().to_vec(),
+                lods: vec![builder.indices().to_vec()],
+            };
+            mesh.generate_lods(options.lod_count);
+            let lod_triangles = mesh
+                .lods
+                .iter()
+                .map(|lod| (lod.len() / 3).to_string())
+                .collect::<Vec<_>>();
+            println!(
+                "Mesh \"{}\": {} vertices, triangles per level of detail {}",
+                mesh.name,
+                mesh.vertices.len(),
+                lod_triangles.join(", ")
+            );
+            triangles += mesh.lods[0].len() / 3;
+            Ok(mesh)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let packed = packed_asset::PackedModel { materials, meshes };
+    std::fs::create_dir_all(output_dir)?;
+    std::fs::write(&options.output, packed.to_bytes())?;
+    println!(
+        "Packed \"{}\": {} meshes, {} triangles, {} materials, {} textures",
+        options.output.display(),
+        packed.meshes.len(),
+        triangles,
+        packed.materials.len(),
+        converted.len()
+    );
+    Ok(())
+}
+
+fn read_obj(options: &Options, source_dir: &Path) -> Result<SourceModel> {
+    let mut obj_reader = std::io::BufReader::new(std::fs::File::open(&options.input)?);
+    let (models, obj_materials) = tobj::load_obj_buf(
+        &mut obj_reader,
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+        |library| {
+            let path = match &options.material_library {
+                Some(path) => path.clone(),
+                None => source_dir.join(library),
+            };
+            tobj::load_mtl(path)
+        },
+    )?;
+    Ok(SourceModel {
+        materials: obj_materials?
+            .iter()
+            .map(packed_asset::PackedMaterial::from)
+            .collect(),
+        images: HashMap::new(),
+        meshes: models
+            .iter()
+            .map(|m| mesh_builder::MeshBuilder::from_obj(&m.name, &m.mesh))
+            .collect(),
+    })
+}
+
+fn read_gltf(options: &Options, source_dir: &Path) -> Result<SourceModel> {
+    if options.material_library.is_some() {
+        return Err(anyhow!("--mtl only applies to OBJ models"));
+    }
+    let file_name = options.input.to_string_lossy();
+    let bytes = std::fs::read(&options.input)?;
+    let model = gltf::GltfModel::read(&file_name, &bytes, |uri| {
+        Ok(std::fs::read(source_dir.join(uri))?)
+    })?;
+    Ok(SourceModel {
+        materials: model.materials,
+        images: model.images,
+        meshes: model.meshes,
+    })
+}
+
+// Writes the texture `name` of the model in `source_dir` as a mipmapped KTX2 file beneath
+// `output_dir`, returning the name the pack's material refers to it by. Textures named in
+// `images` are decoded from the encoded image there, rather than read from a file.
+fn convert_texture(
+    source_dir: &Path,
+    images: &HashMap<String, Vec<u8>>,
+    output_dir: &Path,
+    name: &str,
+    is_linear: bool,
+) -> Result<String> {
+    let decoded = match images.get(name) {
+        Some(bytes) => texture::DecodedTexture::decode(bytes, name, is_linear, true)?,
+        None => {
+            let bytes = std::fs::read(source_dir.join(name))?;
+            texture::DecodedTexture::decode(&bytes, name, is_linear, true)?
+        }
+    };
+    let packed_name = Path::new(name).with_extension("ktx2");
+    let path = output_dir.join(&packed_name);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, packed_asset::encode_ktx2(&decoded))?;
+    packed_name
+        .to_str()
+        .map(str::to_owned)
+        .ok_or_else(|| anyhow!("\"{}\" isn't a valid texture name", packed_name.display()))
+}
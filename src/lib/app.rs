@@ -1,83 +1,509 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Fullscreen, WindowBuilder},
 };
 
-use crate::lib::gpu_state;
+use super::gpu_state;
 
 use super::scene::Scene;
-use super::{compositor, gpu_state::GpuState};
+use super::{
+    camera,
+    compositor::{self, Compositor},
+    debug_groups::DebugGroups,
+    frame_context::FrameContext,
+    frame_pacer::FramePacer,
+    gpu_state::{FramePhase, GpuState, GpuStateDescriptor},
+    graphics_settings::GraphicsSettings,
+    input_recording::{InputPlayer, InputRecorder, InputRecording, RecordedEvent},
+    render_hooks::RenderHookPoint,
+    screenshot::CaptureMode,
+};
+
+pub struct AppConfig {
+    // caps the frame rate independent of vsync, None renders as fast as presentation allows
+    pub max_fps: Option<f32>,
+    // in [0,1), 0 passes raw frame times to update, higher values smooth dt over more frames
+    pub dt_smoothing: f32,
+    // when set, every frame is updated with this time step in seconds regardless of frame timing
+    pub fixed_timestep: Option<f32>,
+    // records input to, or replays input from, a file. Pair recording with fixed_timestep
+    // to make a replay reproduce the recorded session exactly
+    pub input_recording: Option<InputRecording>,
+    // the window's initial inner size, None leaves it to the platform
+    pub window_size: Option<winit::dpi::PhysicalSize<u32>>,
+    // borderless fullscreen on the window's monitor
+    pub fullscreen: bool,
+    // requests a transparent window, and has the compositor leave the background transparent
+    // rather than drawing the sky, e.g. to draw over the desktop. Note that wgpu 0.13 always
+    // configures the surface as opaque, so whether the desktop shows through depends on the
+    // platform
+    pub transparent: bool,
+    // the backends the adapter may be chosen from, e.g. to force Vulkan or GL; overridden by
+    // the WGPU_BACKEND environment variable, see GpuStateDescriptor
+    pub backends: wgpu::Backends,
+    // when false, frames are presented as soon as they're ready, which may tear
+    pub vsync: bool,
+    // the scene camera's attachment size relative to the window, see GraphicsSettings::render_scale
+    pub render_scale: f32,
+    // a scene description for the app's factory to load in place of its built-in scene
+    pub scene_manifest: Option<PathBuf>,
+    // when set, renders this many frames at a fixed 60hz time step as fast as possible,
+    // prints frame time statistics and exits
+    pub benchmark_frames: Option<u32>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            max_fps: None,
+            dt_smoothing: 0.0,
+            fixed_timestep: None,
+            input_recording: None,
+            window_size: None,
+            fullscreen: false,
+            transparent: false,
+            backends: wgpu::Backends::all(),
+            vsync: true,
+            render_scale: 1.0,
+            scene_manifest: None,
+            benchmark_frames: None,
+        }
+    }
+}
+
+impl AppConfig {
+    pub const USAGE: &'static str = "Options:
+    --size <WIDTHxHEIGHT>       initial window size, e.g. 1280x720
+    --fullscreen                borderless fullscreen
+    --transparent               transparent window background
+    --backend <BACKENDS>        comma separated, from vulkan, metal, dx12, dx11, gl, primary
+                                and all; the WGPU_BACKEND environment variable overrides this
+    --no-vsync                  present frames as soon as they're ready
+    --render-scale <SCALE>      render at a fraction or multiple of the window size,
+                                from 0.5 to 2.0
+    --max-fps <FPS>             cap the frame rate
+    --fixed-timestep <SECONDS>  update every frame with the same time step
+    --record <PATH>             record input to a file
+    --replay <PATH>             replay input recorded to a file
+    --scene <PATH>              load a scene manifest
+    --benchmark <FRAMES>        render a number of frames, print timings and exit
+    --help                      print this message";
+
+    /// Parses command line arguments (excluding the program name) over the defaults. Prints
+    /// usage and exits on --help.
+    pub fn from_args<I>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| anyhow!("Missing value for {}", name))
+            };
+            match arg.as_str() {
+                "--size" => {
+                    let size = value(&arg)?;
+                    let (width, height) = size
+                        .split_once('x')
+                        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                        .filter(|(w, h)| *w > 0 && *h > 0)
+                        .ok_or_else(|| {
+                            anyhow!("Invalid size \"{}\", expected e.g. 1280x720", size)
+                        })?;
+                    config.window_size = Some(winit::dpi::PhysicalSize::new(width, height));
+                }
+                "--fullscreen" => config.fullscreen = true,
+                "--transparent" => config.transparent = true,
+                "--backend" => config.backends = parse_backends(&value(&arg)?)?,
+                "--no-vsync" => config.vsync = false,
+                "--render-scale" => {
+                    config.render_scale = parse_number(&arg, &value(&arg)?)?;
+                    if !camera::RENDER_SCALE_RANGE.contains(&config.render_scale) {
+                        return Err(anyhow!(
+                            "Render scale {} is outside {:?}",
+                            config.render_scale,
+                            camera::RENDER_SCALE_RANGE
+                        ));
+                    }
+                }
+                "--max-fps" => config.max_fps = Some(parse_positive(&arg, &value(&arg)?)?),
+                "--fixed-timestep" => {
+                    config.fixed_timestep = Some(parse_positive(&arg, &value(&arg)?)?)
+                }
+                "--record" => {
+                    config.input_recording = Some(InputRecording::Record(value(&arg)?.into()))
+                }
+                "--replay" => {
+                    config.input_recording = Some(InputRecording::Replay(value(&arg)?.into()))
+                }
+                "--scene" => config.scene_manifest = Some(value(&arg)?.into()),
+                "--benchmark" => config.benchmark_frames = Some(parse_number(&arg, &value(&arg)?)?),
+                "--help" | "-h" => {
+                    println!("{}", Self::USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(anyhow!("Unrecognized argument \"{}\"", arg)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("Invalid value \"{}\" for {}", value, name))
+}
+
+// A number for a rate or duration, which must be finite and above zero
+fn parse_positive(name: &str, value: &str) -> Result<f32> {
+    let number: f32 = parse_number(name, value)?;
+    if !(number.is_finite() && number > 0.0) {
+        return Err(anyhow!(
+            "Invalid value \"{}\" for {}, expected a positive number",
+            value,
+            name
+        ));
+    }
+    Ok(number)
+}
+
+fn parse_backends(names: &str) -> Result<wgpu::Backends> {
+    names
+        .split(',')
+        .try_fold(wgpu::Backends::empty(), |backends, name| {
+            Ok(backends | parse_backend(name.trim())?)
+        })
+}
+
+fn parse_backend(name: &str) -> Result<wgpu::Backends> {
+    match name.to_lowercase().as_str() {
+        "vulkan" => Ok(wgpu::Backends::VULKAN),
+        "metal" => Ok(wgpu::Backends::METAL),
+        "dx12" => Ok(wgpu::Backends::DX12),
+        "dx11" => Ok(wgpu::Backends::DX11),
+        "gl" => Ok(wgpu::Backends::GL),
+        "primary" => Ok(wgpu::Backends::PRIMARY),
+        "all" => Ok(wgpu::Backends::all()),
+        _ => Err(anyhow!("Unrecognized backend \"{}\"", name)),
+    }
+}
+
+// Resizes are applied once the window has kept its size this long...
+const RESIZE_SETTLE_TIME: instant::Duration = instant::Duration::from_millis(100);
+// ...or at least this often while it's being resized, so the view keeps up with a slow drag
+const RESIZE_MAX_INTERVAL: instant::Duration = instant::Duration::from_millis(250);
+
+// While minimized, frames are updated but not drawn, at about this rate
+const MINIMIZED_FRAME_INTERVAL: instant::Duration = instant::Duration::from_millis(16);
+
+// Collects window resizes so the surface and every size dependent attachment are rebuilt
+// once a resize settles, rather than for each of the many events a live drag sends
+#[derive(Default)]
+struct ResizeDebouncer {
+    // the latest size, when it first changed, and when it last changed
+    pending: Option<(
+        winit::dpi::PhysicalSize<u32>,
+        instant::Instant,
+        instant::Instant,
+    )>,
+    // the window has no area, e.g. it's minimized, so there's no surface to draw to; the
+    // surface and attachments keep their last size until it's restored
+    minimized: bool,
+}
+
+impl ResizeDebouncer {
+    fn resized(&mut self, size: winit::dpi::PhysicalSize<u32>) {
+        self.minimized = size.width == 0 || size.height == 0;
+        if self.minimized {
+            self.pending = None;
+            return;
+        }
+        let now = instant::Instant::now();
+        let first = self.pending.map_or(now, |(_, first, _)| first);
+        self.pending = Some((size, first, now));
+    }
+
+    // The size to apply now, if a resize has settled or been pending too long
+    fn take_due(&mut self) -> Option<winit::dpi::PhysicalSize<u32>> {
+        let (_, first, last) = self.pending?;
+        let now = instant::Instant::now();
+        if now - last >= RESIZE_SETTLE_TIME || now - first >= RESIZE_MAX_INTERVAL {
+            self.take()
+        } else {
+            None
+        }
+    }
+
+    fn take(&mut self) -> Option<winit::dpi::PhysicalSize<u32>> {
+        self.pending.take().map(|(size, _, _)| size)
+    }
+}
+
+// Frame times gathered in benchmark mode
+struct Benchmark {
+    remaining_frames: u32,
+    last_frame: Option<instant::Instant>,
+    frame_times: Vec<f32>,
+}
+
+impl Benchmark {
+    fn new(frames: u32) -> Self {
+        Self {
+            remaining_frames: frames,
+            last_frame: None,
+            frame_times: Vec::with_capacity(frames as usize),
+        }
+    }
+
+    // Returns true when the benchmark is done
+    fn end_frame(&mut self) -> bool {
+        let now = instant::Instant::now();
+        if let Some(last_frame) = self.last_frame {
+            self.frame_times
+                .push((now - last_frame).as_secs_f32() * 1000.0);
+        }
+        self.last_frame = Some(now);
+        self.remaining_frames = self.remaining_frames.saturating_sub(1);
+        self.remaining_frames == 0
+    }
+
+    fn report(&self) {
+        if self.frame_times.is_empty() {
+            println!("Benchmark: too few frames to time");
+            return;
+        }
+        let mut sorted = self.frame_times.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let mean = sorted.iter().sum::<f32>() / sorted.len() as f32;
+        let percentile = |p: f32| sorted[((sorted.len() - 1) as f32 * p).round() as usize];
+        println!(
+            "Benchmark: {} frames, mean {:.2}ms ({:.1} fps), min {:.2}ms, median {:.2}ms, 99th percentile {:.2}ms, max {:.2}ms",
+            sorted.len(),
+            mean,
+            1000.0 / mean,
+            sorted[0],
+            percentile(0.5),
+            percentile(0.99),
+            sorted[sorted.len() - 1]
+        );
+    }
+}
 
-pub async fn run<F, U>(factory: F, update: U)
+pub async fn run<F, U>(config: AppConfig, factory: F, update: U)
 where
     F: Fn(&winit::window::Window, &mut GpuState) -> Scene,
     U: 'static + Fn(&mut Scene),
 {
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
+    let mut window_builder = WindowBuilder::new()
         .with_decorations(true)
-        .with_title("WGPU Demo")
-        .build(&event_loop)
-        .unwrap();
+        .with_transparent(config.transparent)
+        .with_title("WGPU Demo");
+    if let Some(window_size) = config.window_size {
+        window_builder = window_builder.with_inner_size(window_size);
+    }
+    if config.fullscreen {
+        window_builder = window_builder.with_fullscreen(Some(Fullscreen::Borderless(None)));
+    }
+    let window = window_builder.build(&event_loop).unwrap();
 
-    let mut gpu_state = gpu_state::GpuState::new(&window).await;
+    let mut gpu_state = gpu_state::GpuState::new(
+        &window,
+        &GpuStateDescriptor {
+            backends: config.backends,
+            present_mode: if config.vsync {
+                wgpu::PresentMode::Fifo
+            } else {
+                wgpu::PresentMode::AutoNoVsync
+            },
+        },
+    )
+    .await;
     let mut scene = factory(&window, &mut gpu_state);
-    let mut compositor = compositor::Compositor::new(
-        &mut gpu_state,
-        &scene.camera.render_buffers,
-        scene.environment_map.clone(),
+    scene.set_graphics_settings(
+        &gpu_state,
+        GraphicsSettings {
+            render_scale: config.render_scale,
+            ..scene.graphics_settings()
+        },
     );
+    let mut compositor = compositor::Compositor::new(&mut gpu_state, &scene.camera.render_buffers);
+    compositor.set_transparent_background(config.transparent);
+    // the scene's graphics settings last applied to the compositor
+    let mut compositor_settings = scene.graphics_settings();
+    compositor.apply_graphics_settings(&compositor_settings);
 
     // start even loop
-    let mut last_render_time = instant::Instant::now();
+    let mut benchmark = config.benchmark_frames.map(Benchmark::new);
+    // benchmarks run flat out, with a fixed time step so runs are comparable
+    let (max_fps, fixed_timestep) = match benchmark {
+        Some(_) => (None, Some(config.fixed_timestep.unwrap_or(1.0 / 60.0))),
+        None => (config.max_fps, config.fixed_timestep),
+    };
+    let mut frame_pacer = FramePacer::new(max_fps, config.dt_smoothing);
+    let fixed_timestep = fixed_timestep.map(instant::Duration::from_secs_f32);
+
+    let (mut recorder, mut player) = match &config.input_recording {
+        Some(InputRecording::Record(path)) => (
+            InputRecorder::new(path)
+                .map_err(|e| eprintln!("{:?}", e))
+                .ok(),
+            None,
+        ),
+        Some(InputRecording::Replay(path)) => (
+            None,
+            InputPlayer::load(path)
+                .map_err(|e| eprintln!("{:?}", e))
+                .ok(),
+        ),
+        None => (None, None),
+    };
+
+    let mut modifiers = ModifiersState::empty();
+    let mut resizes = ResizeDebouncer::default();
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::DeviceEvent {
-                event: DeviceEvent::MouseMotion{ delta, },
+                ref event,
                 .. // We're not using device_id currently
             } => {
-                if !scene.input(None, Some(delta)) {
-                    compositor.input(None, Some(delta));
+                // live input is ignored while replaying
+                if let (Some(recorded), None) = (RecordedEvent::from_device_event(event), &player) {
+                    if let Some(recorder) = &mut recorder {
+                        recorder.record(recorded.clone());
+                    }
+                    dispatch_recorded_event(&window, &mut scene, &mut compositor, &recorded);
                 }
             }
         Event::RedrawRequested(window_id) if window_id == window.id() => {
-            let now = instant::Instant::now();
-            let dt = now - last_render_time;
-            last_render_time = now;
+            let mut dt = frame_pacer.begin_frame();
+            if let Some(fixed_timestep) = fixed_timestep {
+                dt = fixed_timestep;
+            }
+
+            if let Some(frame) = player.as_mut().and_then(|player| player.next_frame()) {
+                for recorded in &frame.events {
+                    dispatch_recorded_event(&window, &mut scene, &mut compositor, &recorded.event);
+                }
+                dt = instant::Duration::from_secs_f64(frame.dt);
+            }
+            if player.as_ref().is_some_and(|player| player.is_finished()) {
+                println!("Input replay finished, resuming live input");
+                player = None;
+            }
+
+            if let Some(Err(e)) = recorder.as_mut().map(|recorder| recorder.end_frame(dt)) {
+                eprintln!("Input recording failed, stopping: {:?}", e);
+                recorder = None;
+            }
+
+            if let Some(size) = resizes.take_due() {
+                resize(&mut gpu_state, &mut scene, &mut compositor, size);
+            }
+
+            gpu_state.begin_frame();
+
             update(&mut scene);
-            scene.update( &mut gpu_state, dt);
+            {
+                let mut frame = FrameContext::update(&mut gpu_state, dt);
+                scene.update(&mut frame);
+
+                if scene.graphics_settings() != compositor_settings {
+                    compositor_settings = scene.graphics_settings();
+                    compositor.apply_graphics_settings(&compositor_settings);
+                }
+                compositor.update(&mut frame, &scene.presentation());
+            }
 
-            compositor.update(&mut gpu_state, &scene.camera, dt);
+            if resizes.minimized {
+                // nothing is drawn, but the frame's writes are submitted so they don't pile up
+                gpu_state.end_frame(None);
+                // without presentation to pace frames, they'd run flat out
+                if *control_flow != ControlFlow::Exit {
+                    *control_flow = ControlFlow::WaitUntil(instant::Instant::now() + MINIMIZED_FRAME_INTERVAL);
+                }
+                return;
+            }
+            if let ControlFlow::WaitUntil(_) = control_flow {
+                // restored from minimized
+                *control_flow = ControlFlow::Poll;
+            }
 
             match gpu_state.surface.get_current_texture() {
                 Ok(output) => {
+                    let mut encoder = gpu_state.begin_encoding();
+                    let mut frame = FrameContext::encode(&mut gpu_state, &mut encoder, dt);
+                    scene.render(&mut frame);
+                    let output_view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+                    scene.encode_render_hooks(RenderHookPoint::BeforeCompositor, &mut frame, &output_view);
+                    frame.encoder().push_group("Compositor");
+                    compositor.render(&mut frame, &scene.presentation(), &output);
+                    frame.encoder().pop_group();
+                    scene.encode_render_hooks(RenderHookPoint::AfterCompositor, &mut frame, &output_view);
+                    if frame.gpu_state().screenshots.is_requested(CaptureMode::Final) {
+                        // surfaces can't be copied from on every backend, so the presented
+                        // image is drawn again into a texture which can
+                        let gpu_state = frame.gpu_state_mut();
+                        let size = gpu_state.size();
+                        let target = gpu_state.screenshots.final_target(&gpu_state.device, size, compositor.target_info().target_format);
+                        frame.encoder().push_group("Screenshot");
+                        frame.encoder().begin_render_pass(&wgpu::RenderPassDescriptor {
+                            label: Some("Screenshot Clear Pass"),
+                            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                                view: &target.view,
+                                resolve_target: None,
+                                ops: wgpu::Operations {
+                                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                                    store: true,
+                                },
+                            })],
+                            depth_stencil_attachment: None,
+                        });
+                        scene.encode_render_hooks(RenderHookPoint::BeforeCompositor, &mut frame, &target.view);
+                        compositor.render_to_view(&mut frame, &scene.presentation(), &target.view);
+                        scene.encode_render_hooks(RenderHookPoint::AfterCompositor, &mut frame, &target.view);
+                        frame.encoder().pop_group();
+                    }
+                    {
+                        let (gpu_state, encoder) = frame.gpu_state_and_encoder();
+                        gpu_state.screenshots.encode(&gpu_state.device, encoder, &mut gpu_state.readbacks, &scene.camera);
+                    }
 
-                    let mut encoder =
-                            gpu_state
-                                .device
-                                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                                    label: Some("Render Encoder"),
-                                });
-
-                    scene.render(&mut gpu_state, &mut encoder);
-                    compositor.render(&mut gpu_state, &scene.camera, &mut encoder, &output);
-
-                    gpu_state.queue.submit(std::iter::once(encoder.finish()));
+                    gpu_state.end_frame(Some(encoder));
                     output.present();
-
                 },
                 Err(wgpu::SurfaceError::Lost) => {
-                    let size = gpu_state.size();
-                    gpu_state.resize(size);
-                    scene.resize(&mut gpu_state, size);
-                    compositor.resize(&mut gpu_state, &scene.camera.render_buffers, size);
+                    let size = resizes.take().unwrap_or_else(|| gpu_state.size());
+                    resize(&mut gpu_state, &mut scene, &mut compositor, size);
+                }
+                // the surface no longer matches the window, so a pending resize can't wait
+                Err(wgpu::SurfaceError::Outdated) if resizes.pending.is_some() => {
+                    let size = resizes.take().unwrap();
+                    resize(&mut gpu_state, &mut scene, &mut compositor, size);
                 }
                 // The system is out of memory, we should probably quit
                 Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
                 // All other errors (Outdated, Timeout) should be resolved by the next frame
                 Err(e) => eprintln!("{:?}", e),
             }
+            if gpu_state.frame_phase() != FramePhase::Idle {
+                // the frame wasn't drawn, but its writes are submitted and allocations released
+                gpu_state.end_frame(None);
+            }
+
+            if let Some(benchmark) = &mut benchmark {
+                if benchmark.end_frame() {
+                    benchmark.report();
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
         }
         Event::MainEventsCleared => {
             // RedrawRequested will only trigger once, unless we manually
@@ -87,7 +513,7 @@ where
         Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == window.id() && !scene.input(Some(event), None) => {
+            } if window_id == window.id() && !handle_window_input(&mut scene, &mut recorder, player.is_some(), event) => {
                 match event {
                     WindowEvent::CloseRequested
                     | WindowEvent::KeyboardInput {
@@ -99,15 +525,23 @@ where
                             },
                         ..
                     } => *control_flow = ControlFlow::Exit,
-                    WindowEvent::Resized(physical_size) => {
-                        gpu_state.resize(*physical_size);
-                        scene.resize(&mut gpu_state, *physical_size);
-                        compositor.resize(&mut gpu_state, &scene.camera.render_buffers, *physical_size);
+                    WindowEvent::ModifiersChanged(state) => modifiers = *state,
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F12),
+                                ..
+                            },
+                        ..
+                    } => {
+                        // F12 captures what's presented, shift-F12 the scene before the compositor
+                        let mode = if modifiers.shift() { CaptureMode::Scene } else { CaptureMode::Final };
+                        gpu_state.screenshots.capture(mode, screenshot_path(mode));
                     }
+                    WindowEvent::Resized(physical_size) => resizes.resized(*physical_size),
                     WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        gpu_state.resize(**new_inner_size);
-                        scene.resize(&mut gpu_state, **new_inner_size);
-                        compositor.resize(&mut gpu_state, &scene.camera.render_buffers, **new_inner_size);
+                        resizes.resized(**new_inner_size)
                     }
                     _ => {}
                 }
@@ -115,3 +549,80 @@ where
         _ => {}
     });
 }
+
+// Rebuilds the surface and every attachment which follows the window's size
+fn resize(
+    gpu_state: &mut GpuState,
+    scene: &mut Scene,
+    compositor: &mut Compositor,
+    size: winit::dpi::PhysicalSize<u32>,
+) {
+    gpu_state.resize(size);
+    scene.resize(gpu_state, size);
+    compositor.resize(gpu_state, &scene.camera.render_buffers, size);
+}
+
+// A file in the working directory named for the time, e.g. screenshot-1700000000123.png
+fn screenshot_path(mode: CaptureMode) -> PathBuf {
+    let millis = instant::SystemTime::now()
+        .duration_since(instant::SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis());
+    let extension = match mode {
+        CaptureMode::Scene => "exr",
+        CaptureMode::Final => "png",
+    };
+    PathBuf::from(format!("screenshot-{}.{}", millis, extension))
+}
+
+// Forwards a window event to the scene, recording it if recording. While replaying, live
+// input is swallowed. Returns true if the event was consumed.
+fn handle_window_input(
+    scene: &mut Scene,
+    recorder: &mut Option<InputRecorder>,
+    replaying: bool,
+    event: &WindowEvent,
+) -> bool {
+    match RecordedEvent::from_window_event(event) {
+        Some(recorded) => {
+            if replaying {
+                // let escape through so a replay can be aborted, and the window's resizes so
+                // the surface follows it, recorded resizes having resized the window
+                return !matches!(
+                    event,
+                    WindowEvent::KeyboardInput {
+                        input: KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::Escape),
+                            ..
+                        },
+                        ..
+                    } | WindowEvent::Resized(_)
+                        | WindowEvent::ScaleFactorChanged { .. }
+                );
+            }
+            if let Some(recorder) = recorder {
+                recorder.record(recorded);
+            }
+            scene.input(Some(event), None)
+        }
+        None => scene.input(Some(event), None),
+    }
+}
+
+fn dispatch_recorded_event(
+    window: &winit::window::Window,
+    scene: &mut Scene,
+    compositor: &mut Compositor,
+    event: &RecordedEvent,
+) {
+    if let Some(size) = event.resized() {
+        window.set_inner_size(size);
+    }
+    if let Some(window_event) = event.to_window_event() {
+        scene.input(Some(&window_event), None);
+    }
+    if let Some(delta) = event.mouse_motion() {
+        if !scene.input(None, Some(delta)) {
+            compositor.input(None, Some(delta));
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use cgmath::prelude::*;
+
+use super::{
+    gpu_state::GpuState,
+    model,
+    render_pipeline::{self, DepthMode},
+    resources, texture,
+    util::*,
+};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// What bake renders into its texture
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BakeOutput {
+    // the material's diffuse color and texture
+    Albedo,
+    // the model space normal, perturbed by the material's normal texture, mapped to [0,1]
+    Normal,
+    // the material's ambient occlusion texture, white where it has none
+    AmbientOcclusion,
+    // 0.5 where flat, brighter where convex and darker where concave
+    Curvature,
+}
+
+impl BakeOutput {
+    /// Albedo is a color, stored as sRGB as diffuse textures are; the other outputs are data.
+    pub fn format(&self) -> wgpu::TextureFormat {
+        match self {
+            BakeOutput::Albedo => wgpu::TextureFormat::Rgba8UnormSrgb,
+            BakeOutput::Normal | BakeOutput::AmbientOcclusion | BakeOutput::Curvature => {
+                wgpu::TextureFormat::Rgba8Unorm
+            }
+        }
+    }
+
+    fn fragment_main(&self) -> &'static str {
+        match self {
+            BakeOutput::Albedo => "fs_main_bake_albedo",
+            BakeOutput::Normal => "fs_main_bake_normal",
+            BakeOutput::AmbientOcclusion => "fs_main_bake_ambient_occlusion",
+            BakeOutput::Curvature => "fs_main_bake_curvature",
+        }
+    }
+}
+
+pub struct BakeDescriptor {
+    pub output: BakeOutput,
+    pub width: u32,
+    pub height: u32,
+    // when set, every mesh is baked with this of the model's materials, otherwise each with
+    // its own
+    pub material: Option<usize>,
+    // fills the texels no mesh covers
+    pub clear_color: wgpu::Color,
+    // multiplies BakeOutput::Curvature, the turn of the normal per model space unit, before
+    // it's mapped to [0,1]
+    pub curvature_scale: f32,
+}
+
+impl Default for BakeDescriptor {
+    fn default() -> Self {
+        Self {
+            output: BakeOutput::Albedo,
+            width: 1024,
+            height: 1024,
+            material: None,
+            clear_color: wgpu::Color::TRANSPARENT,
+            curvature_scale: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct BakeUniformData {
+    // x: curvature scale
+    params: Vec4,
+}
+
+unsafe impl bytemuck::Pod for BakeUniformData {}
+unsafe impl bytemuck::Zeroable for BakeUniformData {}
+
+impl Default for BakeUniformData {
+    fn default() -> Self {
+        Self {
+            params: Vec4::zero(),
+        }
+    }
+}
+
+type BakeUniform = UniformWrapper<BakeUniformData>;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Encodes a render of the model's meshes into a new texture laid out by their texture
+/// coordinates, e.g. to preview curvature or flatten a material into a single texture. Each
+/// mesh is drawn at its texture coordinates rather than its positions, so only the parts laid
+/// out within [0,1] are baked, overlapping UVs overwrite one another, and texels outside every
+/// UV island keep the clear color. The texture can be bound by materials and copied from.
+pub fn bake(
+    gpu_state: &mut GpuState,
+    encoder: &mut wgpu::CommandEncoder,
+    model: &model::Model,
+    descriptor: &BakeDescriptor,
+) -> texture::Texture {
+    let target = create_target(&gpu_state.device, descriptor);
+
+    let mut uniform = BakeUniform::new(&gpu_state.device);
+    uniform.get_mut().params.x = descriptor.curvature_scale;
+    uniform.write(gpu_state);
+
+    let material_of = |mesh: &model::Mesh| {
+        model
+            .materials()
+            .get(descriptor.material.unwrap_or(mesh.material))
+    };
+    for material in model.meshes().iter().filter_map(material_of) {
+        prepare_pipeline(gpu_state, material, descriptor.output);
+    }
+
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Bake Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: &target.view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(descriptor.clear_color),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    });
+
+    for mesh in model.meshes() {
+        let material = match material_of(mesh) {
+            Some(material) => material,
+            None => continue,
+        };
+        let pipeline_id = pipeline_id(material, descriptor.output);
+        if let Some(pipeline) = gpu_state.pipeline_vendor.get_pipeline(&pipeline_id) {
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &material.bind_group, &[]);
+            render_pass.set_bind_group(1, uniform.bind_group(), &[]);
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+        } else {
+            eprintln!("Missing bake pipeline \"{}\"", pipeline_id);
+        }
+    }
+    drop(render_pass);
+
+    target
+}
+
+fn create_target(device: &wgpu::Device, descriptor: &BakeDescriptor) -> texture::Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Bake Texture"),
+        size: wgpu::Extent3d {
+            width: descriptor.width.max(1),
+            height: descriptor.height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: descriptor.output.format(),
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+            | wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_SRC,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    // repeating, as diffuse textures do
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::Repeat,
+        address_mode_v: wgpu::AddressMode::Repeat,
+        address_mode_w: wgpu::AddressMode::Repeat,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    });
+
+    texture::Texture {
+        texture,
+        view,
+        sampler,
+        view_dimension: wgpu::TextureViewDimension::D2,
+    }
+}
+
+// The material's bind group layout varies with the textures it binds, as its variant key does,
+// so there's a pipeline per output and key
+fn pipeline_id(material: &model::Material, output: BakeOutput) -> String {
+    format!(
+        "bake {:?} {}",
+        output,
+        material.variant_key(&render_pipeline::Pass::Ambient, DepthMode::default())
+    )
+}
+
+fn prepare_pipeline(gpu_state: &mut GpuState, material: &model::Material, output: BakeOutput) {
+    let pipeline_id = pipeline_id(material, output);
+    if gpu_state.pipeline_vendor.has_pipeline(&pipeline_id) {
+        return;
+    }
+
+    let layout = gpu_state
+        .device
+        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&pipeline_id),
+            bind_group_layouts: &[
+                &material.bind_group_layout,
+                &BakeUniform::bind_group_layout(&gpu_state.device),
+            ],
+            push_constant_ranges: &[],
+        });
+
+    let defines = material
+        .variant_key(&render_pipeline::Pass::Ambient, DepthMode::default())
+        .defines();
+    let shader = wgpu::ShaderModuleDescriptor {
+        label: Some("shaders/bake.wgsl"),
+        source: wgpu::ShaderSource::Wgsl(
+            resources::load_shader_sync("shaders/bake.wgsl", &defines)
+                .unwrap()
+                .into(),
+        ),
+    };
+
+    gpu_state.pipeline_vendor.create_render_pipeline(
+        &pipeline_id,
+        &gpu_state.device,
+        render_pipeline::Properties {
+            vs_main: "vs_main_bake",
+            fs_main: Some(output.fragment_main()),
+            layout: &layout,
+            color_format: output.format(),
+            depth_format: None,
+            depth_bias: wgpu::DepthBiasState::default(),
+            depth_mode: DepthMode::default(),
+            // mirrored UV islands wind the other way
+            cull_mode: None,
+            blend: Some(wgpu::BlendState::REPLACE),
+            vertex_layouts: &[model::ModelVertex::vertex_buffer_layout()],
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            shader,
+            pass: render_pipeline::Pass::Ambient,
+        },
+    );
+}
@@ -1,5 +1,6 @@
-use super::{gpu_state, util::*};
+use super::{frustum::Frustum, gpu_state, render_pipeline::DepthMode, util::*};
 use cgmath::prelude::*;
+use instant::Duration;
 use std::ops::Mul;
 
 #[rustfmt::skip]
@@ -10,13 +11,27 @@ pub const OPENGL_TO_WGPU_MATRIX: Mat4 = Mat4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
+// Applied after OPENGL_TO_WGPU_MATRIX, maps depth d to 1 - d
+#[rustfmt::skip]
+pub const REVERSE_Z_MATRIX: Mat4 = Mat4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 1.0, 1.0,
+);
+
+// The supported scales of a camera's attachments relative to its target, see
+// Camera::set_render_scale
+pub const RENDER_SCALE_RANGE: std::ops::RangeInclusive<f32> = 0.5..=2.0;
+
 ///////////////////////////////////////////////
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CameraUniformData {
-    view_position: Vec4,
+    // must lead, see depth_pass
     view_proj: Mat4,
+    view_position: Vec4,
     proj_inverse: Mat4,
     view_inverse: Mat4,
 }
@@ -53,6 +68,114 @@ pub type CameraUniform = UniformWrapper<CameraUniformData>;
 
 ///////////////////////////////////////////////
 
+/// Physical camera settings for manual exposure. Note that the lighting must be in physical
+/// units (e.g. a sunlit scene around 100000 lux) for photographic settings to look right.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ManualExposure {
+    // f-number, e.g. 16.0 for f/16
+    pub aperture: f32,
+    // in seconds
+    pub shutter_speed: f32,
+    pub iso: f32,
+}
+
+impl Default for ManualExposure {
+    // "sunny 16"
+    fn default() -> Self {
+        Self {
+            aperture: 16.0,
+            shutter_speed: 1.0 / 100.0,
+            iso: 100.0,
+        }
+    }
+}
+
+impl ManualExposure {
+    pub fn ev100(&self) -> f32 {
+        (self.aperture * self.aperture / self.shutter_speed * 100.0 / self.iso).log2()
+    }
+
+    // Saturation based sensitivity; the luminance which maps to white is 1.2 * 2^EV100
+    pub fn exposure(&self) -> f32 {
+        1.0 / (1.2 * 2_f32.powf(self.ev100()))
+    }
+}
+
+///////////////////////////////////////////////
+
+/// How a camera's depth attachment starts the frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DepthClear {
+    // the far plane of the camera's depth mode, see DepthMode::clear_depth
+    FarPlane,
+    Value(f32),
+    // keeps what's there, e.g. to layer this camera's rendering over another's
+    Load,
+}
+
+/// How a camera's attachments start the frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClearSettings {
+    // None keeps what's there
+    pub color: Option<wgpu::Color>,
+    pub depth: DepthClear,
+}
+
+impl Default for ClearSettings {
+    fn default() -> Self {
+        Self {
+            color: Some(wgpu::Color {
+                r: 0.1,
+                g: 0.1,
+                b: 0.1,
+                a: 1.0,
+            }),
+            depth: DepthClear::FarPlane,
+        }
+    }
+}
+
+/// A rectangle as fractions of a camera's attachments, from the top left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewportRect {
+    pub const FULL: Self = Self {
+        x: 0.0,
+        y: 0.0,
+        width: 1.0,
+        height: 1.0,
+    };
+
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    // In pixels of a target of `size`, as (x, y, width, height), clamped to the target
+    fn to_pixels(self, size: winit::dpi::PhysicalSize<u32>) -> (u32, u32, u32, u32) {
+        let to_pixels = |fraction: f32, extent: u32| {
+            ((fraction.clamp(0.0, 1.0) * extent as f32).round() as u32).min(extent)
+        };
+        let x = to_pixels(self.x, size.width);
+        let y = to_pixels(self.y, size.height);
+        let right = to_pixels(self.x + self.width, size.width);
+        let bottom = to_pixels(self.y + self.height, size.height);
+        (x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+}
+
+///////////////////////////////////////////////
+
 pub struct RenderBuffers {
     pub color: Option<super::texture::Texture>,
     pub depth: Option<super::texture::Texture>,
@@ -60,6 +183,51 @@ pub struct RenderBuffers {
 
 ///////////////////////////////////////////////
 
+/// Where a camera is and which way it looks, see Camera::pose and Camera::blend_to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraPose {
+    pub position: Point3,
+    // columns are right, up and backward, orthonormal and right handed
+    pub look: Mat3,
+}
+
+impl CameraPose {
+    pub fn new(position: Point3, look: Mat3) -> Self {
+        Self { position, look }
+    }
+
+    /// The pose at `position` looking toward `at`, as Camera::look_at places the camera
+    pub fn look_at<P, V>(position: P, at: P, up: V) -> Self
+    where
+        P: Into<Point3>,
+        V: Into<Vec3>,
+    {
+        let position: Point3 = position.into();
+        let at: Point3 = at.into();
+        let up: Vec3 = up.into().normalize();
+
+        let forward = -(at - position).normalize();
+        let right = up.cross(forward).normalize();
+        let up = forward.cross(right).normalize();
+
+        Self {
+            position,
+            look: Mat3::from_cols(right, up, forward),
+        }
+    }
+}
+
+// An in-progress Camera::blend_to; the look is slerped between the two orientations
+struct PoseBlend {
+    from_position: Point3,
+    from_rotation: Quat,
+    to: CameraPose,
+    to_rotation: Quat,
+    duration: Duration,
+    elapsed: Duration,
+    easing: Easing,
+}
+
 pub struct Camera {
     // world view
     position: Point3,
@@ -70,6 +238,27 @@ pub struct Camera {
     fov_y: Rad,
     z_near: f32,
     z_far: f32,
+    depth_mode: DepthMode,
+    // world space plane (normal, distance) replacing the near plane, see set_clip_plane
+    clip_plane: Option<Vec4>,
+
+    // how the attachments start the frame
+    clear: ClearSettings,
+
+    // the region of the attachments rendered to, the size of the target the attachments are
+    // presented to, and the attachments' size relative to it
+    viewport: ViewportRect,
+    scissor: Option<ViewportRect>,
+    target_size: winit::dpi::PhysicalSize<u32>,
+    render_scale: f32,
+    render_size: winit::dpi::PhysicalSize<u32>,
+
+    // exposure, see Camera::exposure
+    exposure_compensation: f32,
+    manual_exposure: Option<ManualExposure>,
+
+    // the pose being moved to, see blend_to
+    blend: Option<PoseBlend>,
 
     // uniform storage
     is_dirty: bool,
@@ -87,19 +276,8 @@ impl Camera {
         z_far: f32,
     ) -> Self {
         let uniform = CameraUniform::new(&gpu_state.device);
-
-        // create depth texture
-        let depth_attachment = super::texture::Texture::create_depth_texture(
-            &gpu_state.device,
-            &gpu_state.config,
-            "Depth Attachment",
-        );
-
-        let color_attachment = super::texture::Texture::create_color_texture(
-            &gpu_state.device,
-            &gpu_state.config,
-            "Color Attachment",
-        );
+        let target_size =
+            winit::dpi::PhysicalSize::new(gpu_state.config.width, gpu_state.config.height);
 
         Self {
             position: Point3::new(0.0, 0.0, 0.0),
@@ -108,16 +286,24 @@ impl Camera {
             fov_y: fov_y.into(),
             z_near,
             z_far,
+            depth_mode: DepthMode::Standard,
+            clip_plane: None,
+            clear: ClearSettings::default(),
+            viewport: ViewportRect::FULL,
+            scissor: None,
+            target_size,
+            render_scale: 1.0,
+            render_size: target_size,
+            exposure_compensation: 0.0,
+            manual_exposure: None,
+            blend: None,
             is_dirty: true,
             uniform,
-            render_buffers: RenderBuffers {
-                color: Some(color_attachment),
-                depth: Some(depth_attachment),
-            },
+            render_buffers: Self::create_render_buffers(gpu_state, target_size),
         }
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue) {
+    pub fn update(&mut self, gpu_state: &gpu_state::GpuState) {
         if self.is_dirty {
             let position = self.position;
             let projection = self.projection_matrix();
@@ -125,40 +311,161 @@ impl Camera {
             self.uniform
                 .get_mut()
                 .update_view_proj(position, projection, view);
-            self.uniform.write(queue);
+            self.uniform.write(gpu_state);
             self.is_dirty = false;
         }
     }
 
     pub fn resize(&mut self, gpu_state: &gpu_state::GpuState, size: winit::dpi::PhysicalSize<u32>) {
-        self.aspect = size.width as f32 / size.height as f32;
+        self.target_size = size;
+        self.update_aspect();
+        self.recreate_render_buffers(gpu_state);
+        self.is_dirty = true;
+    }
 
-        if self.render_buffers.depth.is_some() {
-            self.render_buffers
-                .depth
-                .replace(super::texture::Texture::create_depth_texture(
-                    &gpu_state.device,
-                    &gpu_state.config,
-                    "Depth Attachment",
-                ));
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Scales the attachments relative to the target they're presented to, between
+    /// RENDER_SCALE_RANGE; below 1 renders faster at lower resolution, above 1 supersamples.
+    /// The attachments are replaced by the next call to prepare_render_buffers.
+    pub fn set_render_scale(&mut self, render_scale: f32) {
+        self.render_scale =
+            render_scale.clamp(*RENDER_SCALE_RANGE.start(), *RENDER_SCALE_RANGE.end());
+    }
+
+    /// The size of the attachments, i.e. the target size scaled by the render scale.
+    pub fn render_size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.render_size
+    }
+
+    /// Replaces the attachments if the render scale changed since they were created, returning
+    /// true if so; anything bound to the previous attachments must be rebound.
+    pub fn prepare_render_buffers(&mut self, gpu_state: &gpu_state::GpuState) -> bool {
+        if self.scaled_target_size() == self.render_size {
+            return false;
         }
+        self.recreate_render_buffers(gpu_state);
+        true
+    }
+
+    fn scaled_target_size(&self) -> winit::dpi::PhysicalSize<u32> {
+        winit::dpi::PhysicalSize::new(
+            ((self.target_size.width as f32 * self.render_scale).round() as u32).max(1),
+            ((self.target_size.height as f32 * self.render_scale).round() as u32).max(1),
+        )
+    }
+
+    fn recreate_render_buffers(&mut self, gpu_state: &gpu_state::GpuState) {
+        self.render_size = self.scaled_target_size();
+        let render_buffers = Self::create_render_buffers(gpu_state, self.render_size);
 
+        // keep the attachments this camera was configured without
+        if self.render_buffers.depth.is_some() {
+            self.render_buffers.depth = render_buffers.depth;
+        }
         if self.render_buffers.color.is_some() {
-            self.render_buffers
-                .color
-                .replace(super::texture::Texture::create_color_texture(
-                    &gpu_state.device,
-                    &gpu_state.config,
-                    "Color Attachment",
-                ));
+            self.render_buffers.color = render_buffers.color;
+        }
+    }
+
+    fn create_render_buffers(
+        gpu_state: &gpu_state::GpuState,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) -> RenderBuffers {
+        let config = wgpu::SurfaceConfiguration {
+            width: size.width,
+            height: size.height,
+            ..gpu_state.config.clone()
+        };
+
+        RenderBuffers {
+            color: Some(super::texture::Texture::create_color_texture(
+                &gpu_state.device,
+                &config,
+                "Color Attachment",
+            )),
+            depth: Some(super::texture::Texture::create_depth_texture(
+                &gpu_state.device,
+                &config,
+                "Depth Attachment",
+            )),
+        }
+    }
+
+    // the projection's aspect matches the viewport's, so letterboxed or split views aren't
+    // stretched
+    fn update_aspect(&mut self) {
+        let width = self.target_size.width as f32 * self.viewport.width;
+        let height = self.target_size.height as f32 * self.viewport.height;
+        if width > 0.0 && height > 0.0 {
+            self.aspect = width / height;
+            self.is_dirty = true;
         }
-        self.is_dirty = true;
+    }
+
+    pub fn viewport(&self) -> ViewportRect {
+        self.viewport
+    }
+
+    /// Restricts rendering to a region of the attachments, e.g. for letterboxing or split
+    /// screen. Clears still apply to the whole attachments.
+    pub fn set_viewport(&mut self, viewport: ViewportRect) {
+        if viewport != self.viewport {
+            self.viewport = viewport;
+            self.update_aspect();
+        }
+    }
+
+    pub fn scissor(&self) -> Option<ViewportRect> {
+        self.scissor
+    }
+
+    /// Further clips rendering to a region of the attachments, without changing the projection.
+    pub fn set_scissor(&mut self, scissor: Option<ViewportRect>) {
+        self.scissor = scissor;
+    }
+
+    /// Applies the viewport and scissor to a render pass drawing to this camera's attachments.
+    pub fn apply_viewport(&self, render_pass: &mut wgpu::RenderPass) {
+        if self.viewport != ViewportRect::FULL {
+            self.apply_viewport_depth_range(render_pass, 0.0, 1.0);
+        }
+        if let Some(scissor) = self.scissor {
+            let (x, y, width, height) = scissor.to_pixels(self.render_size);
+            render_pass.set_scissor_rect(x, y, width, height);
+        }
+    }
+
+    /// Sets the camera's viewport, mapping depth into min_depth..max_depth, e.g. for a
+    /// model::RenderLayer. The scissor rect, if any, is left as set by apply_viewport.
+    pub fn apply_viewport_depth_range(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        min_depth: f32,
+        max_depth: f32,
+    ) {
+        let (x, y, width, height) = self.viewport.to_pixels(self.render_size);
+        render_pass.set_viewport(
+            x as f32,
+            y as f32,
+            width.max(1) as f32,
+            height.max(1) as f32,
+            min_depth,
+            max_depth,
+        );
     }
 
     pub fn fov_y(&self) -> Rad {
         self.fov_y
     }
 
+    /// The projection's width over height, which follows the viewport
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
     pub fn set_fov_y<R: Into<Rad>>(&mut self, new_fov_y: R) {
         let new_fov_y: Rad = new_fov_y.into();
         if new_fov_y != self.fov_y {
@@ -167,33 +474,245 @@ impl Camera {
         }
     }
 
+    /// Returns (z_near, z_far); z_far is f32::INFINITY if the camera has an infinite far plane.
     pub fn depth_range(&self) -> (f32, f32) {
         (self.z_near, self.z_far)
     }
 
+    pub fn has_infinite_far(&self) -> bool {
+        self.z_far.is_infinite()
+    }
+
+    /// Switches to a projection with no far plane, e.g. for space or terrain scenes where
+    /// distant geometry should never be clipped. Use set_depth_range to restore a finite far plane.
+    pub fn set_infinite_far(&mut self, z_near: f32) {
+        self.set_depth_range(z_near, f32::INFINITY);
+    }
+
     pub fn set_depth_range(&mut self, z_near: f32, z_far: f32) {
-        if (z_near - self.z_near).abs() > 1e-4 || (z_far - self.z_far).abs() > 1e-4 {
+        // compare far planes exactly, as infinity - infinity is NaN
+        if (z_near - self.z_near).abs() > 1e-4
+            || z_far.is_infinite() != self.z_far.is_infinite()
+            || (z_far - self.z_far).abs() > 1e-4
+        {
             self.z_near = z_near;
             self.z_far = z_far;
             self.is_dirty = true;
         }
     }
 
+    /// The depth range and mode as res/shaders/depth_linearization.wgsl takes them: x: z_near,
+    /// y: z_far (0 for an infinite far plane), z: 1 if the camera uses reversed depth, otherwise 0
+    pub fn depth_linearization_params(&self) -> Vec3 {
+        Vec3::new(
+            self.z_near,
+            if self.z_far.is_finite() {
+                self.z_far
+            } else {
+                0.0
+            },
+            match self.depth_mode {
+                DepthMode::Standard => 0.0,
+                DepthMode::Reversed => 1.0,
+            },
+        )
+    }
+
+    /// The linear view depth, in world units in [z_near, z_far], of a depth attachment value.
+    /// Matches linear_depth in depth_linearization.wgsl; like it, doesn't account for the
+    /// oblique near plane of a clip_plane.
+    pub fn linear_depth(&self, depth: f32) -> f32 {
+        let depth = match self.depth_mode {
+            DepthMode::Standard => depth,
+            DepthMode::Reversed => 1.0 - depth,
+        };
+        if self.has_infinite_far() {
+            // depth = 1 - z_near / z
+            return self.z_near / (1.0 - depth).max(1e-7);
+        }
+        self.z_near * self.z_far / (self.z_far - depth * (self.z_far - self.z_near))
+    }
+
+    /// The depth attachment value of a linear view depth, the inverse of linear_depth
+    pub fn depth_from_linear(&self, linear_depth: f32) -> f32 {
+        let z = linear_depth.max(self.z_near);
+        let depth = if self.has_infinite_far() {
+            1.0 - self.z_near / z
+        } else {
+            self.z_far * (z - self.z_near) / (z * (self.z_far - self.z_near))
+        };
+        match self.depth_mode {
+            DepthMode::Standard => depth,
+            DepthMode::Reversed => 1.0 - depth,
+        }
+    }
+
+    /// The world position of a depth attachment value at a texture coordinate of the camera's
+    /// attachments, (0,0) at the top left; None at an infinite far plane. Matches
+    /// world_position_from_depth in depth_linearization.wgsl.
+    pub fn world_position_from_depth(&self, tex_coord: Vec2, depth: f32) -> Option<Point3> {
+        let inverse_view_proj = (self.projection_matrix() * self.view_matrix()).invert()?;
+        let ndc = Vec4::new(tex_coord.x * 2.0 - 1.0, 1.0 - tex_coord.y * 2.0, depth, 1.0);
+        let position = inverse_view_proj * ndc;
+        if position.w.abs() < 1e-7 {
+            return None;
+        }
+        Some(Point3::from_homogeneous(position))
+    }
+
+    pub fn depth_mode(&self) -> DepthMode {
+        self.depth_mode
+    }
+
+    /// Note: the depth mode is baked into the pipelines which render with this camera,
+    /// Scene creates any which are missing on update.
+    pub fn set_depth_mode(&mut self, depth_mode: DepthMode) {
+        if depth_mode != self.depth_mode {
+            self.depth_mode = depth_mode;
+            self.is_dirty = true;
+        }
+    }
+
+    pub fn clip_plane(&self) -> Option<Vec4> {
+        self.clip_plane
+    }
+
+    /// Replaces the near plane with a world space plane (xyz normal, w distance, as
+    /// frustum::Plane), clipping away everything behind it, e.g. geometry between a mirror's
+    /// reflected camera and the mirror. The camera must be behind the plane.
+    pub fn set_clip_plane(&mut self, clip_plane: Option<Vec4>) {
+        if clip_plane != self.clip_plane {
+            self.clip_plane = clip_plane;
+            self.is_dirty = true;
+        }
+    }
+
+    pub fn clear(&self) -> ClearSettings {
+        self.clear
+    }
+
+    pub fn set_clear(&mut self, clear: ClearSettings) {
+        self.clear = clear;
+    }
+
+    pub fn color_load_op(&self) -> wgpu::LoadOp<wgpu::Color> {
+        match self.clear.color {
+            Some(color) => wgpu::LoadOp::Clear(color),
+            None => wgpu::LoadOp::Load,
+        }
+    }
+
+    pub fn depth_load_op(&self) -> wgpu::LoadOp<f32> {
+        match self.clear.depth {
+            DepthClear::FarPlane => wgpu::LoadOp::Clear(self.depth_mode.clear_depth()),
+            DepthClear::Value(depth) => wgpu::LoadOp::Clear(depth),
+            DepthClear::Load => wgpu::LoadOp::Load,
+        }
+    }
+
+    /// The linear scale applied to scene color by the compositor: the manual exposure if
+    /// set (otherwise 1), adjusted by the exposure compensation.
+    pub fn exposure(&self) -> f32 {
+        let exposure = self
+            .manual_exposure
+            .map_or(1.0, |manual_exposure| manual_exposure.exposure());
+        exposure * 2_f32.powf(self.exposure_compensation)
+    }
+
+    pub fn exposure_compensation(&self) -> f32 {
+        self.exposure_compensation
+    }
+
+    /// Brightens (positive) or darkens (negative) the image by `ev` stops.
+    pub fn set_exposure_compensation(&mut self, ev: f32) {
+        self.exposure_compensation = ev;
+    }
+
+    pub fn manual_exposure(&self) -> Option<ManualExposure> {
+        self.manual_exposure
+    }
+
+    pub fn set_manual_exposure(&mut self, manual_exposure: Option<ManualExposure>) {
+        self.manual_exposure = manual_exposure;
+    }
+
+    /// Places the camera at `position` looking toward `at`, ending any blend in progress
     pub fn look_at<P, V>(&mut self, position: P, at: P, up: V)
     where
         P: Into<Point3>,
         V: Into<Vec3>,
     {
-        let position: Point3 = position.into();
-        let at: Point3 = at.into();
-        let up: Vec3 = up.into().normalize();
+        let pose = CameraPose::look_at(position, at, up);
+        self.set_pose(pose.position, pose.look);
+    }
 
-        let forward = -(at - position).normalize();
-        let right = up.cross(forward).normalize();
-        let up = forward.cross(right).normalize();
+    /// Places the camera directly, ending any blend in progress; `look`'s columns are right, up
+    /// and backward, and must be orthonormal and right handed.
+    pub fn set_pose(&mut self, position: Point3, look: Mat3) {
+        self.blend = None;
+        self.apply_pose(position, look);
+    }
+
+    pub fn pose(&self) -> CameraPose {
+        CameraPose::new(self.position, self.look)
+    }
+
+    /// Moves the camera from where it is now to `pose` over `duration`, shaped by `easing`,
+    /// as advanced by update_blend. The position is interpolated linearly and the look
+    /// orientation along the shortest arc, so scripted viewpoint changes don't snap. A blend
+    /// started mid-blend sets out from wherever the camera has got to.
+    pub fn blend_to(&mut self, pose: CameraPose, duration: Duration, easing: Easing) {
+        if duration.is_zero() {
+            self.set_pose(pose.position, pose.look);
+            return;
+        }
+        self.blend = Some(PoseBlend {
+            from_position: self.position,
+            from_rotation: Quat::from(self.look),
+            to: pose,
+            to_rotation: Quat::from(pose.look),
+            duration,
+            elapsed: Duration::ZERO,
+            easing,
+        });
+    }
 
-        self.look = Mat3::from_cols(right, up, forward);
+    pub fn is_blending(&self) -> bool {
+        self.blend.is_some()
+    }
+
+    /// Stops a blend in progress where it is
+    pub fn cancel_blend(&mut self) {
+        self.blend = None;
+    }
+
+    /// Advances the blend in progress, if any, by `dt`; called by the scene each update. The
+    /// blend overrides other movement, e.g. by the camera controller, until it completes.
+    pub fn update_blend(&mut self, dt: Duration) {
+        let blend = match &mut self.blend {
+            Some(blend) => blend,
+            None => return,
+        };
+        blend.elapsed += dt;
+        if blend.elapsed >= blend.duration {
+            // land exactly on the target, rather than on the slerp's approximation of it
+            let to = blend.to;
+            self.blend = None;
+            self.apply_pose(to.position, to.look);
+            return;
+        }
+
+        let t = blend
+            .easing
+            .apply(blend.elapsed.as_secs_f32() / blend.duration.as_secs_f32());
+        let position = blend.from_position + (blend.to.position - blend.from_position) * t;
+        let look = Mat3::from(blend.from_rotation.slerp(blend.to_rotation, t));
+        self.apply_pose(position, look);
+    }
+
+    fn apply_pose(&mut self, position: Point3, look: Mat3) {
         self.position = position;
+        self.look = look;
         self.is_dirty = true;
     }
 
@@ -211,6 +730,10 @@ impl Camera {
         self.is_dirty = true;
     }
 
+    pub fn position(&self) -> Point3 {
+        self.position
+    }
+
     pub fn world_rotation(&self) -> Mat3 {
         self.look
     }
@@ -227,22 +750,85 @@ impl Camera {
         world_translation.mul(world_rotation)
     }
 
+    /// The camera's view volume in world space, e.g. for culling, or drawing with debug_draw.
+    pub fn frustum(&self) -> Frustum {
+        Frustum::new(
+            self.position,
+            self.look,
+            self.fov_y,
+            self.aspect,
+            self.z_near,
+            self.z_far,
+        )
+    }
+
     pub fn view_matrix(&self) -> Mat4 {
         self.world_transform().invert().unwrap()
     }
 
     pub fn projection_matrix(&self) -> Mat4 {
-        OPENGL_TO_WGPU_MATRIX
-            * cgmath::perspective(self.fov_y, self.aspect, self.z_near, self.z_far)
+        let perspective = if self.has_infinite_far() {
+            self.infinite_perspective()
+        } else {
+            cgmath::perspective(self.fov_y, self.aspect, self.z_near, self.z_far)
+        };
+        let mut projection = OPENGL_TO_WGPU_MATRIX * perspective;
+        if let Some(clip_plane) = self.clip_plane {
+            projection = self.oblique_projection(projection, clip_plane);
+        }
+        match self.depth_mode {
+            DepthMode::Standard => projection,
+            DepthMode::Reversed => REVERSE_Z_MATRIX * projection,
+        }
+    }
+
+    // Lengyel's oblique near plane, for [0,1] depth: the projection's z row is replaced by the
+    // view space clip plane, scaled so the far corner of the frustum opposite the plane
+    // still maps to depth 1. Depth precision suffers the more oblique the plane is.
+    fn oblique_projection(&self, projection: Mat4, clip_plane: Vec4) -> Mat4 {
+        // planes transform by the inverse transpose, and the view's inverse is the world transform
+        let plane = self.world_transform().transpose() * clip_plane;
+        let corner = match projection.invert() {
+            Some(inverse) => inverse * Vec4::new(plane.x.signum(), plane.y.signum(), 1.0, 1.0),
+            None => return projection,
+        };
+        let scale = plane.dot(corner);
+        if scale.abs() < 1e-6 {
+            return projection;
+        }
+        let row = plane / scale;
+        let mut projection = projection;
+        projection.x.z = row.x;
+        projection.y.z = row.y;
+        projection.z.z = row.z;
+        projection.w.z = row.w;
+        projection
+    }
+
+    // The limit of cgmath::perspective as z_far approaches infinity
+    #[rustfmt::skip]
+    fn infinite_perspective(&self) -> Mat4 {
+        let f = 1.0 / (self.fov_y / 2.0).tan();
+        Mat4::new(
+            f / self.aspect, 0.0, 0.0, 0.0,
+            0.0, f, 0.0, 0.0,
+            0.0, 0.0, -1.0, -1.0,
+            0.0, 0.0, -2.0 * self.z_near, 0.0,
+        )
     }
 
     pub fn bind_group(&self) -> &wgpu::BindGroup {
-        &self.uniform.bind_group
+        self.uniform.bind_group()
     }
 
     pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
         CameraUniform::bind_group_layout(device)
     }
+
+    /// The entries of bind_group_layout, see shader_reflection::validate_bindings
+    pub fn bind_group_layout_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+        CameraUniform::bind_group_layout_entries()
+    }
 }
 
 ///////////////////////////////////////////////
@@ -102,7 +102,7 @@ impl CameraController {
             MouseScrollDelta::LineDelta(_, scroll) => *scroll * 20_f32,
             MouseScrollDelta::PixelDelta(PhysicalPosition { y: scroll, .. }) => *scroll as f32,
         };
-        self.zoom = self.zoom.min(100f32).max(-100f32);
+        self.zoom = self.zoom.clamp(-100f32, 100f32);
     }
 
     pub fn update(&mut self, camera: &mut Camera, dt: Duration) {
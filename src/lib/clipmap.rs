@@ -0,0 +1,136 @@
+use cgmath::prelude::*;
+
+use super::{gpu_state::GpuState, mesh_builder, model, util::*};
+
+pub struct ClipmapDescriptor {
+    // number of nested rings; each is twice the size and half the density of the one inside it
+    pub levels: u32,
+    // quads along each side of a ring, rounded up to a multiple of 4
+    pub resolution: u32,
+    // world units between vertices of the innermost ring
+    pub spacing: f32,
+}
+
+impl Default for ClipmapDescriptor {
+    fn default() -> Self {
+        Self {
+            levels: 6,
+            resolution: 64,
+            spacing: 0.5,
+        }
+    }
+}
+
+fn level_mesh_name(level: u32) -> String {
+    format!("clipmap_level_{}", level)
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Geometry clipmap terrain: nested square rings of flat grid geometry which the vertex stage
+// centers on the camera and displaces by the material's heightmap (see model::Heightmap), so
+// nothing is rebuilt or uploaded as the camera moves. Unlike terrain::TerrainManager the
+// terrain's extent is bounded only by the heightmap, which repeats.
+//
+// Every ring shares a center snapped to the outermost ring's vertex spacing, so each ring
+// exactly fills the hole in the next and vertices stay put as the camera moves between snaps.
+// Vertices along a ring's outer edge which don't coincide with the coarser ring's vertices
+// take the average height of their neighbors, closing the T-junction cracks between rings.
+//
+// Ring vertices are encoded for the CLIPMAP vertex stage in model.wgsl:
+//  position.xz: offset from the shared center
+//  tex_coords.x: the ring's vertex spacing, tex_coords.y: 1 for edge vertices to average
+//  normal.x: the snap spacing
+//  tangent: the direction along the edge to the averaged neighbors
+//
+// Heightmapped materials don't write the depth prepass or cast shadows, since those passes
+// don't displace vertices.
+pub fn create_model(
+    gpu_state: &GpuState,
+    descriptor: &ClipmapDescriptor,
+    material: model::Material,
+) -> model::Model {
+    if material.heightmap.is_none() {
+        eprintln!(
+            "Clipmap material \"{}\" has no heightmap, its rings will be flat",
+            material.name
+        );
+    }
+
+    let levels = descriptor.levels.max(1);
+    let resolution = descriptor.resolution.max(4).div_ceil(4) * 4;
+    let snap = descriptor.spacing * (1 << (levels - 1)) as f32;
+
+    let meshes = (0..levels)
+        .map(|level| {
+            let spacing = descriptor.spacing * (1 << level) as f32;
+            build_level(gpu_state, level, resolution, spacing, snap)
+        })
+        .collect();
+
+    model::Model::new(
+        &gpu_state.device,
+        meshes,
+        vec![material],
+        &[model::Instance::new(Point3::origin(), Quat::one())],
+    )
+}
+
+fn build_level(
+    gpu_state: &GpuState,
+    level: u32,
+    resolution: u32,
+    spacing: f32,
+    snap: f32,
+) -> model::Mesh {
+    let row = resolution + 1;
+    let half = resolution as f32 / 2.0;
+
+    let mut builder = mesh_builder::MeshBuilder::with_capacity(
+        &level_mesh_name(level),
+        (row * row) as usize,
+        (resolution * resolution * 6) as usize,
+    );
+    for j in 0..row {
+        for i in 0..row {
+            // odd vertices along the outer edge fall between the coarser ring's vertices
+            let along_x = (j == 0 || j == resolution) && i % 2 == 1;
+            let along_z = (i == 0 || i == resolution) && j % 2 == 1;
+            let tangent = if along_z {
+                Vec3::unit_z()
+            } else {
+                Vec3::unit_x()
+            };
+
+            builder.push_vertex(model::ModelVertex {
+                position: Point3::new(
+                    (i as f32 - half) * spacing,
+                    0.0,
+                    (j as f32 - half) * spacing,
+                ),
+                tex_coords: Vec2::new(spacing, if along_x || along_z { 1.0 } else { 0.0 }),
+                normal: Vec3::new(snap, 0.0, 0.0),
+                tangent,
+                bitangent: Vec3::zero(),
+            });
+        }
+    }
+
+    // rings outside the innermost leave a hole for the ring inside them
+    let hole = (resolution / 4)..(resolution * 3 / 4);
+    for j in 0..resolution {
+        for i in 0..resolution {
+            if level > 0 && hole.contains(&i) && hole.contains(&j) {
+                continue;
+            }
+            let a = j * row + i;
+            let b = a + row;
+            builder.push_triangle(a, b, a + 1);
+            builder.push_triangle(a + 1, b, b + 1);
+        }
+    }
+
+    builder
+        .build(&gpu_state.device, false)
+        .expect("clipmap ring indices are in range")
+}
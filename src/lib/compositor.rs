@@ -1,12 +1,38 @@
-use std::rc::Rc;
-
-use super::{camera, gpu_state, texture, util::*};
+use super::{
+    camera, environment,
+    frame_context::{FrameContext, ScenePresentation},
+    gpu_state,
+    graphics_settings::{GraphicsSettings, PostEffects},
+    resources, shader_reflection,
+    texture::Texture,
+    util::*,
+    volumetric_fog,
+};
+use anyhow::{anyhow, Result};
 use cgmath::prelude::*;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct CompositorUniformData {
     camera_z_near_far_width_height: Vec4,
+    // x: 1 if the camera uses reversed depth, otherwise 0
+    camera_depth_mode: Vec4,
+    // x: linear exposure scale, see camera::Camera::exposure
+    camera_exposure: Vec4,
+    // x: the DebugView, see DebugView::index
+    debug_view: Vec4,
+    // the camera's view projection as of the previous frame, for reprojection
+    previous_view_proj: Mat4,
+    // x: 1 if the background is transparent, otherwise 0
+    background: Vec4,
+    // x: 1 to dither, otherwise 0, y: 1 if the target's format encodes to sRGB, otherwise 0,
+    // z: the target's quantization steps per channel
+    dither: Vec4,
+    // x: brightness, y: contrast, z: saturation, w: gamma, see ColorAdjustment
+    color_adjustment: Vec4,
+    // x: the TransferFunction the shader encodes output with, see TransferFunction::index,
+    // y: for Pq, HDR_REFERENCE_WHITE_NITS over PQ's 10000 nit peak
+    output_transfer: Vec4,
 }
 
 unsafe impl bytemuck::Pod for CompositorUniformData {}
@@ -16,6 +42,132 @@ impl Default for CompositorUniformData {
     fn default() -> Self {
         Self {
             camera_z_near_far_width_height: Vec4::zero(),
+            camera_depth_mode: Vec4::zero(),
+            camera_exposure: Vec4::zero(),
+            debug_view: Vec4::zero(),
+            previous_view_proj: Mat4::identity(),
+            background: Vec4::zero(),
+            dither: Vec4::zero(),
+            color_adjustment: Vec4::new(0.0, 1.0, 1.0, 1.0),
+            output_transfer: Vec4::zero(),
+        }
+    }
+}
+
+// The brightness scene white is shown at on HDR10 targets, the reference white of ITU-R BT.2408
+const HDR_REFERENCE_WHITE_NITS: f32 = 203.0;
+
+/// How the compositor encodes its output for its target, see TargetInfo
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferFunction {
+    // linear values, for targets which encode them as they're written (sRGB formats) or which
+    // are linear (float formats, e.g. an extended range scRGB surface)
+    Linear,
+    // sRGB encoded by the shader, for UNORM targets which don't encode themselves
+    Srgb,
+    // SMPTE ST 2084 (PQ) with BT.2020 primaries for HDR10 targets, scene white shown at
+    // HDR_REFERENCE_WHITE_NITS
+    Pq,
+}
+
+impl TransferFunction {
+    // must match the TRANSFER_* constants in compositor.wgsl
+    fn index(&self) -> f32 {
+        match self {
+            TransferFunction::Linear => 0.0,
+            TransferFunction::Srgb => 1.0,
+            TransferFunction::Pq => 2.0,
+        }
+    }
+}
+
+/// The formats the compositor samples the scene from and draws into, and the transfer function
+/// its output needs to display correctly there, rather than assuming both match
+/// texture::Texture::COLOR_FORMAT. The compositor negotiates it from the surface's format, see
+/// TargetInfo::new; wgpu can't report a surface's color space, so HDR10 surfaces must ask for
+/// TransferFunction::Pq (and usually a 10 bit format) with Compositor::set_target_info.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TargetInfo {
+    // the format of the texture the compositor draws into, e.g. the surface's
+    pub target_format: wgpu::TextureFormat,
+    // the format of the camera's color attachment, which sets how the compositor binds and
+    // filters it; sampling decodes its sRGB encoding, if any
+    pub color_format: wgpu::TextureFormat,
+    pub transfer: TransferFunction,
+}
+
+impl TargetInfo {
+    /// Negotiates the transfer function for drawing into `target_format`: sRGB and float
+    /// formats take linear output, and other formats are sRGB encoded by the shader.
+    pub fn new(target_format: wgpu::TextureFormat, color_format: wgpu::TextureFormat) -> Self {
+        let is_float = quantization_steps(target_format).is_none();
+        let transfer = if target_format.describe().srgb || is_float {
+            TransferFunction::Linear
+        } else {
+            TransferFunction::Srgb
+        };
+        Self {
+            target_format,
+            color_format,
+            transfer,
+        }
+    }
+}
+
+/// Full-screen views of what can be derived from the scene's attachments, in place of the
+/// tonemapped scene, for debugging. Material attributes (e.g. albedo) aren't available, as the
+/// scene is forward rendered to color and depth alone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebugView {
+    #[default]
+    None,
+    // world space normals reconstructed from depth, mapped from [-1,1] to [0,1]; flat per
+    // triangle, as normal maps aren't seen
+    WorldNormals,
+    // screen space motion due to the camera's movement since the previous frame, red right
+    // and green down, 0.5 is still
+    Velocity,
+    // linear depth from near (black) to far (white)
+    Depth,
+    // the number of lights evaluated per pixel, from blue (one) to red (eight or more), as
+    // drawn by the scene with Scene::debug_light_complexity
+    LightComplexity,
+}
+
+impl DebugView {
+    // must match the DEBUG_VIEW_* constants in compositor.wgsl
+    fn index(&self) -> f32 {
+        match self {
+            DebugView::None => 0.0,
+            DebugView::WorldNormals => 1.0,
+            DebugView::Velocity => 2.0,
+            DebugView::Depth => 3.0,
+            DebugView::LightComplexity => 4.0,
+        }
+    }
+}
+
+/// Adjustments to the compositor's final output for calibrating a display, applied after
+/// tonemapping in display (sRGB encoded) terms. The default leaves the output unchanged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorAdjustment {
+    // added to each channel, e.g. 0.1 lifts black to 10% grey
+    pub brightness: f32,
+    // scales each channel's distance from mid grey; 0 is flat grey
+    pub contrast: f32,
+    // scales each color's distance from its luminance; 0 is greyscale
+    pub saturation: f32,
+    // above 1 brightens mid tones, below 1 darkens them, leaving black and white in place
+    pub gamma: f32,
+}
+
+impl Default for ColorAdjustment {
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 1.0,
+            saturation: 1.0,
+            gamma: 1.0,
         }
     }
 }
@@ -24,86 +176,112 @@ type CompositorUniform = UniformWrapper<CompositorUniformData>;
 
 pub struct Compositor {
     size: winit::dpi::PhysicalSize<u32>,
+    // the size of the camera attachments last bound, which differs from size by the camera's
+    // render scale
+    render_size: winit::dpi::PhysicalSize<u32>,
     time: instant::Duration,
     uniform: CompositorUniform,
-    environment_map: Rc<texture::Texture>,
     textures_bind_group_layout: wgpu::BindGroupLayout,
     textures_bind_group: wgpu::BindGroup,
+    // filters the color attachment if its format can be, see color_attachment_binding_types
+    color_attachment_sampler: wgpu::Sampler,
     depth_attachment_sampler: wgpu::Sampler,
+    render_pipeline_layout: wgpu::PipelineLayout,
     render_pipeline: wgpu::RenderPipeline,
+    // when set, the render pipeline is rebuilt when compositor.wgsl is edited, see
+    // set_shader_hot_reload
+    shader_watch: Option<resources::ShaderWatch>,
+    // bound in place of the frame's volumetric fog when it has none
+    fog_placeholder: volumetric_fog::VolumetricFogPlaceholder,
+    // id of the volumetric fog textures_bind_group samples, if any
+    fog_id: Option<usize>,
+    debug_view: DebugView,
+    previous_view_proj: Option<Mat4>,
+    transparent_background: bool,
+    dither: bool,
+    color_adjustment: ColorAdjustment,
+    target_info: TargetInfo,
+    // the target info was set with set_target_info rather than negotiated
+    target_info_overridden: bool,
 }
 
 impl Compositor {
     pub fn new(
         gpu_state: &mut gpu_state::GpuState,
-        render_buffers: &crate::camera::RenderBuffers,
-        environment_map: Rc<texture::Texture>,
+        render_buffers: &camera::RenderBuffers,
     ) -> Self {
         let uniform = CompositorUniform::new(&gpu_state.device);
+        let target_info = TargetInfo::new(gpu_state.config.format, Texture::COLOR_FORMAT);
+        let (color_sample_type, color_sampler_type) =
+            Self::color_attachment_binding_types(target_info.color_format);
 
+        // the volumetric fog's bindings follow the attachments', as the pipeline has no bind
+        // groups to spare
+        let mut textures_bind_group_layout_entries = vec![
+            // Color atachment
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: color_sample_type,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            // Color Attachment Sampler
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(color_sampler_type),
+                count: None,
+            },
+            // Depth atachment
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            // Depth Attachment Sampler
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+        textures_bind_group_layout_entries.extend(
+            volumetric_fog::VolumetricFog::sample_bind_group_layout_entries(
+                Self::FOG_FIRST_BINDING,
+            ),
+        );
         let textures_bind_group_layout =
             gpu_state
                 .device
                 .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                     label: Some("Compositor Bind Group Layout"),
-                    entries: &[
-                        // Color atachment
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Texture {
-                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                                view_dimension: wgpu::TextureViewDimension::D2,
-                                multisampled: false,
-                            },
-                            count: None,
-                        },
-                        // Color Attachment Sampler
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                            count: None,
-                        },
-                        // Depth atachment
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 2,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Texture {
-                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                                view_dimension: wgpu::TextureViewDimension::D2,
-                                multisampled: false,
-                            },
-                            count: None,
-                        },
-                        // Depth Attachment Sampler
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 3,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                            count: None,
-                        },
-                        // Environment Map
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 4,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Texture {
-                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                                view_dimension: wgpu::TextureViewDimension::Cube,
-                                multisampled: false,
-                            },
-                            count: None,
-                        },
-                        // Environment Map Sampler
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 5,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                            count: None,
-                        },
-                    ],
+                    entries: &textures_bind_group_layout_entries,
                 });
 
+        let color_filter = if color_sampler_type == wgpu::SamplerBindingType::Filtering {
+            wgpu::FilterMode::Linear
+        } else {
+            wgpu::FilterMode::Nearest
+        };
+        let color_attachment_sampler = gpu_state.device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: color_filter,
+            min_filter: color_filter,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
         let depth_attachment_sampler = gpu_state.device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
@@ -114,12 +292,14 @@ impl Compositor {
             ..Default::default()
         });
 
+        let fog_placeholder = volumetric_fog::VolumetricFogPlaceholder::new(&gpu_state.device);
         let textures_bind_group = Self::create_textures_bind_group(
             gpu_state,
             render_buffers,
             &textures_bind_group_layout,
+            &color_attachment_sampler,
             &depth_attachment_sampler,
-        &environment_map,
+            fog_placeholder.sample_bind_group_entries(Self::FOG_FIRST_BINDING),
         );
 
         let render_pipeline_layout =
@@ -131,84 +311,293 @@ impl Compositor {
                         &textures_bind_group_layout,
                         &uniform.bind_group_layout,
                         &camera::Camera::bind_group_layout(&gpu_state.device),
+                        &environment::Environment::bind_group_layout(
+                            &gpu_state.device,
+                            environment::Environment::supports_reflection_probes(gpu_state),
+                        ),
                     ],
                     push_constant_ranges: &[],
                 });
 
-        let shader = gpu_state
-            .device
-            .create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: Some("Shader"),
-                source: wgpu::ShaderSource::Wgsl(
-                    super::resources::load_string_sync("shaders/compositor.wgsl")
-                        .unwrap()
-                        .into(),
-                ),
-            });
-
-        let render_pipeline =
-            gpu_state
-                .device
-                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("Render Pipeline"),
-                    layout: Some(&render_pipeline_layout),
-                    vertex: wgpu::VertexState {
-                        module: &shader,
-                        entry_point: "compositor_vs_main",
-                        buffers: &[],
-                    },
-                    fragment: Some(wgpu::FragmentState {
-                        module: &shader,
-                        entry_point: "compositor_fs_main",
-                        targets: &[Some(wgpu::ColorTargetState {
-                            format: gpu_state.config.format,
-                            blend: Some(wgpu::BlendState {
-                                color: wgpu::BlendComponent::REPLACE,
-                                alpha: wgpu::BlendComponent::REPLACE,
-                            }),
-                            write_mask: wgpu::ColorWrites::ALL,
-                        })],
-                    }),
-                    primitive: wgpu::PrimitiveState {
-                        topology: wgpu::PrimitiveTopology::TriangleList,
-                        strip_index_format: None,
-                        front_face: wgpu::FrontFace::Ccw,
-                        cull_mode: None,
-                        polygon_mode: wgpu::PolygonMode::Fill,
-                        unclipped_depth: false,
-                        conservative: false,
-                    },
-                    depth_stencil: None,
-                    multisample: wgpu::MultisampleState {
-                        count: 1,
-                        mask: !0,
-                        alpha_to_coverage_enabled: false,
-                    },
-                    multiview: None,
-                });
+        let shader_source = resources::load_shader_sync(Self::SHADER, &[]).unwrap();
+        let render_pipeline = Self::create_render_pipeline(
+            gpu_state,
+            &render_pipeline_layout,
+            &shader_source,
+            target_info.target_format,
+        );
 
         Self {
             size: gpu_state.size(),
+            render_size: gpu_state.size(),
             time: instant::Duration::default(),
             uniform,
-            environment_map,
             textures_bind_group_layout,
             textures_bind_group,
+            color_attachment_sampler,
             depth_attachment_sampler,
+            render_pipeline_layout,
             render_pipeline,
+            // edits are only looked for during development
+            shader_watch: cfg!(debug_assertions).then(|| resources::ShaderWatch::new(Self::SHADER)),
+            fog_placeholder,
+            fog_id: None,
+            debug_view: DebugView::None,
+            previous_view_proj: None,
+            transparent_background: false,
+            dither: true,
+            color_adjustment: ColorAdjustment::default(),
+            target_info,
+            target_info_overridden: false,
+        }
+    }
+
+    // Builds the pipeline from preprocessed compositor.wgsl `source`, drawing into
+    // `target_format`
+    fn create_render_pipeline(
+        gpu_state: &gpu_state::GpuState,
+        layout: &wgpu::PipelineLayout,
+        source: &str,
+        target_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader = gpu_state
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(Self::SHADER),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+
+        gpu_state
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Render Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Self::VS_MAIN,
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Self::FS_MAIN,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: target_format,
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent::REPLACE,
+                            alpha: wgpu::BlendComponent::REPLACE,
+                        }),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+            })
+    }
+
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view
+    }
+
+    pub fn set_debug_view(&mut self, debug_view: DebugView) {
+        self.debug_view = debug_view;
+    }
+
+    pub fn transparent_background(&self) -> bool {
+        self.transparent_background
+    }
+
+    /// When set, the sky isn't drawn; pixels the scene's geometry doesn't cover are written
+    /// with zero alpha, and color is premultiplied by alpha, for compositing the window over
+    /// the desktop (see app::AppConfig::transparent).
+    pub fn set_transparent_background(&mut self, transparent_background: bool) {
+        self.transparent_background = transparent_background;
+    }
+
+    pub fn dither(&self) -> bool {
+        self.dither
+    }
+
+    /// When set (the default), an ordered dither is added as the scene is quantized to the
+    /// surface format, hiding banding in smooth gradients. Debug views aren't dithered.
+    pub fn set_dither(&mut self, dither: bool) {
+        self.dither = dither;
+    }
+
+    /// Applies the graphics settings which concern the compositor, currently only
+    /// graphics_settings::PostEffects::DITHER, see set_dither. app::run calls this whenever the
+    /// scene's settings change.
+    pub fn apply_graphics_settings(&mut self, settings: &GraphicsSettings) {
+        self.set_dither(settings.post_effects.contains(PostEffects::DITHER));
+    }
+
+    pub fn color_adjustment(&self) -> ColorAdjustment {
+        self.color_adjustment
+    }
+
+    /// Adjusts the brightness, contrast, saturation and gamma of the output, independent of
+    /// the camera's exposure. Contrast and saturation are clamped to be non-negative, and gamma
+    /// to be positive. Debug views aren't adjusted.
+    pub fn set_color_adjustment(&mut self, color_adjustment: ColorAdjustment) {
+        self.color_adjustment = ColorAdjustment {
+            brightness: color_adjustment.brightness,
+            contrast: color_adjustment.contrast.max(0.0),
+            saturation: color_adjustment.saturation.max(0.0),
+            gamma: color_adjustment.gamma.max(0.01),
+        };
+    }
+
+    pub fn target_info(&self) -> TargetInfo {
+        self.target_info
+    }
+
+    /// Overrides the negotiated target, e.g. to encode for an HDR10 surface with
+    /// TransferFunction::Pq. A target format other than the surface's reconfigures the surface
+    /// with it, failing if the surface doesn't support it (see GpuState::surface_formats), and
+    /// the color format must be the camera's, as the compositor's bindings are built for it.
+    /// None returns to negotiating from the surface's format, keeping the surface as it is.
+    pub fn set_target_info(
+        &mut self,
+        gpu_state: &mut gpu_state::GpuState,
+        target_info: Option<TargetInfo>,
+    ) -> Result<()> {
+        if let Some(target_info) = target_info {
+            if target_info.color_format != self.target_info.color_format {
+                return Err(anyhow!(
+                    "The scene is sampled from {:?}, not {:?}",
+                    self.target_info.color_format,
+                    target_info.color_format
+                ));
+            }
+            gpu_state.set_surface_format(target_info.target_format)?;
         }
+        self.target_info_overridden = target_info.is_some();
+        let target_info = target_info.unwrap_or_else(|| {
+            TargetInfo::new(gpu_state.config.format, self.target_info.color_format)
+        });
+        self.retarget(gpu_state, target_info);
+        Ok(())
+    }
+
+    // Adopts `target_info`, rebuilding the pipeline if its target format changed
+    fn retarget(&mut self, gpu_state: &gpu_state::GpuState, target_info: TargetInfo) {
+        if target_info.target_format != self.target_info.target_format {
+            let source = resources::load_shader_sync(Self::SHADER, &[]).unwrap();
+            self.render_pipeline = Self::create_render_pipeline(
+                gpu_state,
+                &self.render_pipeline_layout,
+                &source,
+                target_info.target_format,
+            );
+        }
+        self.target_info = target_info;
     }
 
     pub fn time(&self) -> instant::Duration {
         self.time
     }
 
+    pub fn shader_hot_reload(&self) -> bool {
+        self.shader_watch.is_some()
+    }
+
+    /// When enabled, edits to compositor.wgsl (and the files it includes) in the crate's res/
+    /// directory rebuild the render pipeline on the next update, keeping the compositor's
+    /// uniform values and bindings. Enabled by default in debug builds.
+    pub fn set_shader_hot_reload(&mut self, enabled: bool) {
+        if enabled != self.shader_hot_reload() {
+            self.shader_watch = enabled.then(|| resources::ShaderWatch::new(Self::SHADER));
+        }
+    }
+
+    // Rebuilds the render pipeline from the watched shader's source. An edit which doesn't
+    // compile, or no longer matches the pipeline's layout, is reported and the current
+    // pipeline kept.
+    fn reload_shader(&mut self, gpu_state: &gpu_state::GpuState) {
+        let shader_watch = match &mut self.shader_watch {
+            Some(shader_watch) => shader_watch,
+            None => return,
+        };
+        let label = format!("compositor shader \"{}\"", shader_watch.file_name());
+        let source = shader_watch.load(&[]).and_then(|source| {
+            shader_reflection::reflect_bindings(
+                &label,
+                &source,
+                &[
+                    (Self::VS_MAIN, naga::ShaderStage::Vertex),
+                    (Self::FS_MAIN, naga::ShaderStage::Fragment),
+                ],
+            )?;
+            Ok(source)
+        });
+        let source = match source {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("Unable to reload {}: {}", label, e);
+                return;
+            }
+        };
+
+        gpu_state
+            .device
+            .push_error_scope(wgpu::ErrorFilter::Validation);
+        let render_pipeline = Self::create_render_pipeline(
+            gpu_state,
+            &self.render_pipeline_layout,
+            &source,
+            self.target_info.target_format,
+        );
+        match pollster::block_on(gpu_state.device.pop_error_scope()) {
+            Some(e) => eprintln!("Unable to reload {}: {}", label, e),
+            None => {
+                self.render_pipeline = render_pipeline;
+                println!("Reloaded {}", label);
+            }
+        }
+    }
+
+    // binding of the volumetric fog uniform in the textures bind group, see compositor.wgsl
+    const FOG_FIRST_BINDING: u32 = 4;
+
+    const SHADER: &'static str = "shaders/compositor.wgsl";
+    const VS_MAIN: &'static str = "compositor_vs_main";
+    const FS_MAIN: &'static str = "compositor_fs_main";
+
+    // The color attachment's sample type for `color_format`, and the sampler it takes; linear
+    // filtering where the format allows it, e.g. not for 32 bit float formats
+    fn color_attachment_binding_types(
+        color_format: wgpu::TextureFormat,
+    ) -> (wgpu::TextureSampleType, wgpu::SamplerBindingType) {
+        let sample_type = color_format.describe().sample_type;
+        let sampler_type = match sample_type {
+            wgpu::TextureSampleType::Float { filterable: true } => {
+                wgpu::SamplerBindingType::Filtering
+            }
+            _ => wgpu::SamplerBindingType::NonFiltering,
+        };
+        (sample_type, sampler_type)
+    }
+
     fn create_textures_bind_group(
         gpu_state: &gpu_state::GpuState,
-        render_buffers: &crate::camera::RenderBuffers,
+        render_buffers: &camera::RenderBuffers,
         texture_layout: &wgpu::BindGroupLayout,
+        color_attachment_sampler: &wgpu::Sampler,
         depth_attachment_sampler: &wgpu::Sampler,
-        environment_map: &texture::Texture,
+        fog_entries: Vec<wgpu::BindGroupEntry>,
     ) -> wgpu::BindGroup {
         let mut bind_group_entries = vec![];
 
@@ -219,7 +608,7 @@ impl Compositor {
             });
             bind_group_entries.push(wgpu::BindGroupEntry {
                 binding: bind_group_entries.len() as u32,
-                resource: wgpu::BindingResource::Sampler(&color_attachment.sampler),
+                resource: wgpu::BindingResource::Sampler(color_attachment_sampler),
             });
         }
 
@@ -234,16 +623,7 @@ impl Compositor {
                 resource: wgpu::BindingResource::Sampler(depth_attachment_sampler),
             })
         }
-
-        bind_group_entries.push(wgpu::BindGroupEntry {
-            binding: bind_group_entries.len() as u32,
-            resource: wgpu::BindingResource::TextureView(&environment_map.view),
-        });
-
-        bind_group_entries.push(wgpu::BindGroupEntry {
-            binding: bind_group_entries.len() as u32,
-            resource: wgpu::BindingResource::Sampler(&environment_map.sampler),
-        });
+        bind_group_entries.extend(fog_entries);
 
         gpu_state
             .device
@@ -257,16 +637,20 @@ impl Compositor {
     pub fn resize(
         &mut self,
         gpu_state: &mut super::gpu_state::GpuState,
-        render_buffers: &crate::camera::RenderBuffers,
+        render_buffers: &camera::RenderBuffers,
         new_size: winit::dpi::PhysicalSize<u32>,
     ) {
         self.size = new_size;
+        // the next update rebinds the frame's fog, if it has one
+        self.fog_id = None;
         self.textures_bind_group = Self::create_textures_bind_group(
             gpu_state,
             render_buffers,
             &self.textures_bind_group_layout,
+            &self.color_attachment_sampler,
             &self.depth_attachment_sampler,
-            &self.environment_map,
+            self.fog_placeholder
+                .sample_bind_group_entries(Self::FOG_FIRST_BINDING),
         );
     }
 
@@ -282,53 +666,160 @@ impl Compositor {
         false
     }
 
-    pub fn update(
-        &mut self,
-        gpu_state: &mut super::gpu_state::GpuState,
-        camera: &camera::Camera,
-        dt: instant::Duration,
-    ) {
-        self.time += dt;
+    pub fn update(&mut self, frame: &mut FrameContext, scene: &ScenePresentation) {
+        debug_assert_eq!(
+            frame.phase(),
+            gpu_state::FramePhase::Update,
+            "Compositor::update called outside FramePhase::Update"
+        );
+        let camera = scene.camera;
+        self.time += frame.dt;
+        let gpu_state = frame.gpu_state();
 
-        let (z_near, z_far) = camera.depth_range();
+        if self
+            .shader_watch
+            .as_ref()
+            .is_some_and(|shader_watch| shader_watch.changed())
+        {
+            self.reload_shader(gpu_state);
+        }
+        if !self.target_info_overridden && gpu_state.config.format != self.target_info.target_format
+        {
+            // the surface was reconfigured with another format
+            let target_info =
+                TargetInfo::new(gpu_state.config.format, self.target_info.color_format);
+            self.retarget(gpu_state, target_info);
+        }
+
+        // the camera replaces its attachments when its render scale changes; the color and depth
+        // are sampled with linear filtering, scaling them to the surface
+        let fog_id = scene.volumetric_fog.map(|fog| fog.id());
+        if camera.render_size() != self.render_size || fog_id != self.fog_id {
+            self.render_size = camera.render_size();
+            self.fog_id = fog_id;
+            let fog_entries = match scene.volumetric_fog {
+                Some(fog) => fog.sample_bind_group_entries(Self::FOG_FIRST_BINDING),
+                None => self
+                    .fog_placeholder
+                    .sample_bind_group_entries(Self::FOG_FIRST_BINDING),
+            };
+            self.textures_bind_group = Self::create_textures_bind_group(
+                gpu_state,
+                &camera.render_buffers,
+                &self.textures_bind_group_layout,
+                &self.color_attachment_sampler,
+                &self.depth_attachment_sampler,
+                fog_entries,
+            );
+        }
+
+        let depth_params = camera.depth_linearization_params();
         self.uniform.get_mut().camera_z_near_far_width_height = Vec4::new(
-            z_near,
-            z_far,
-            self.size.width as f32,
-            self.size.height as f32,
+            depth_params.x,
+            depth_params.y,
+            scene.size.width as f32,
+            scene.size.height as f32,
+        );
+        self.uniform.get_mut().camera_depth_mode = Vec4::new(depth_params.z, 0.0, 0.0, 0.0);
+        self.uniform.get_mut().camera_exposure = Vec4::new(camera.exposure(), 0.0, 0.0, 0.0);
+        self.uniform.get_mut().debug_view = Vec4::new(self.debug_view.index(), 0.0, 0.0, 0.0);
+        self.uniform.get_mut().background = Vec4::new(
+            if self.transparent_background {
+                1.0
+            } else {
+                0.0
+            },
+            0.0,
+            0.0,
+            0.0,
+        );
+        self.uniform.get_mut().color_adjustment = Vec4::new(
+            self.color_adjustment.brightness,
+            self.color_adjustment.contrast,
+            self.color_adjustment.saturation,
+            self.color_adjustment.gamma,
+        );
+        let format = self.target_info.target_format;
+        self.uniform.get_mut().dither = Vec4::new(
+            if self.dither && quantization_steps(format).is_some() {
+                1.0
+            } else {
+                0.0
+            },
+            if format.describe().srgb { 1.0 } else { 0.0 },
+            quantization_steps(format).unwrap_or(1) as f32,
+            0.0,
+        );
+        self.uniform.get_mut().output_transfer = Vec4::new(
+            self.target_info.transfer.index(),
+            HDR_REFERENCE_WHITE_NITS / 10000.0,
+            0.0,
+            0.0,
         );
 
-        self.uniform.write(&gpu_state.queue);
+        // the scene was rendered with the camera as it is now, so on the first frame there's
+        // no motion
+        let view_proj = camera.projection_matrix() * camera.view_matrix();
+        self.uniform.get_mut().previous_view_proj = self.previous_view_proj.unwrap_or(view_proj);
+        self.previous_view_proj = Some(view_proj);
+
+        self.uniform.write(gpu_state);
     }
 
     pub fn render(
         &self,
-        _gpu_state: &mut gpu_state::GpuState,
-        camera: &camera::Camera,
-        encoder: &mut wgpu::CommandEncoder,
+        frame: &mut FrameContext,
+        scene: &ScenePresentation,
         output: &wgpu::SurfaceTexture,
     ) {
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
+        self.render_to_view(frame, scene, &view);
+    }
 
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Compositor FSQ Render Pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Load, // FSQ doens't need to clear
-                    store: true,
-                },
-            })],
-            depth_stencil_attachment: None,
-        });
+    /// As render, into a view of a texture of the target format (see target_info), e.g. the
+    /// target of a screenshot::CaptureMode::Final capture
+    pub fn render_to_view(
+        &self,
+        frame: &mut FrameContext,
+        scene: &ScenePresentation,
+        view: &wgpu::TextureView,
+    ) {
+        debug_assert_eq!(
+            frame.phase(),
+            gpu_state::FramePhase::Encode,
+            "Compositor::render_to_view called outside FramePhase::Encode"
+        );
+        let mut render_pass = frame
+            .encoder()
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Compositor FSQ Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load, // FSQ doens't need to clear
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
 
         render_pass.set_pipeline(&self.render_pipeline);
         render_pass.set_bind_group(0, &self.textures_bind_group, &[]);
-        render_pass.set_bind_group(1, &self.uniform.bind_group, &[]);
-        render_pass.set_bind_group(2, &camera.bind_group(), &[]);
+        render_pass.set_bind_group(1, self.uniform.bind_group(), &[]);
+        render_pass.set_bind_group(2, scene.camera.bind_group(), &[]);
+        render_pass.set_bind_group(3, scene.environment.bind_group(), &[]);
         render_pass.draw(0..3, 0..1);
     }
 }
+
+// The steps per color channel of a surface format, None for float formats which don't band
+fn quantization_steps(format: wgpu::TextureFormat) -> Option<u32> {
+    match format {
+        wgpu::TextureFormat::Rgb10a2Unorm => Some(1023),
+        wgpu::TextureFormat::Rgba16Float | wgpu::TextureFormat::Rgba32Float => None,
+        _ => Some(255),
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    collections::BTreeSet,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{anyhow, Result};
+
+use super::{resources, shader_preprocessor, shader_reflection};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BindingKind {
+    Uniform,
+    Texture,
+    Sampler,
+}
+
+// The bind group interface model.wgsl is rendered with: the material at group 0 (uniform,
+// then texture/sampler pairs), the camera at group 1, the light at group 2 and the scene's
+// environment map, wind and reflection probes at group 3. Custom shaders may use any subset of
+// it, but nothing outside it. The reflection probes are only bound where the adapter supports
+// them, when pipelines define REFLECTION_PROBES, so should be read within #ifdef REFLECTION_PROBES.
+const INTERFACE: [(u32, u32, BindingKind); 34] = [
+    (0, 0, BindingKind::Uniform),
+    (0, 3, BindingKind::Texture),
+    (0, 4, BindingKind::Sampler),
+    (0, 5, BindingKind::Texture),
+    (0, 6, BindingKind::Sampler),
+    (0, 7, BindingKind::Texture),
+    (0, 8, BindingKind::Sampler),
+    (0, 9, BindingKind::Texture),
+    (0, 10, BindingKind::Sampler),
+    (0, 11, BindingKind::Texture),
+    (0, 12, BindingKind::Sampler),
+    (0, 13, BindingKind::Texture),
+    (0, 14, BindingKind::Sampler),
+    (0, 15, BindingKind::Texture),
+    (0, 16, BindingKind::Sampler),
+    (0, 17, BindingKind::Texture),
+    (0, 18, BindingKind::Sampler),
+    (0, 19, BindingKind::Texture),
+    (0, 20, BindingKind::Texture),
+    (1, 0, BindingKind::Uniform),
+    (2, 0, BindingKind::Uniform),
+    (2, 1, BindingKind::Texture),
+    (2, 2, BindingKind::Sampler),
+    (2, 3, BindingKind::Texture),
+    (2, 4, BindingKind::Texture),
+    (2, 5, BindingKind::Texture),
+    (2, 6, BindingKind::Sampler),
+    (3, 0, BindingKind::Texture),
+    (3, 1, BindingKind::Sampler),
+    (3, 2, BindingKind::Uniform),
+    (3, 3, BindingKind::Uniform),
+    (3, 4, BindingKind::Texture),
+    (3, 5, BindingKind::Sampler),
+    (3, 6, BindingKind::Uniform),
+];
+
+pub struct CustomShaderDescriptor<'a> {
+    // path relative to res/, e.g. "shaders/hologram.wgsl"
+    pub path: &'a str,
+    pub vs_main_ambient: &'a str,
+    pub fs_main_ambient: &'a str,
+    pub vs_main_lit: &'a str,
+    pub fs_main_lit: &'a str,
+}
+
+impl<'a> Default for CustomShaderDescriptor<'a> {
+    fn default() -> Self {
+        Self {
+            path: Default::default(),
+            vs_main_ambient: "vs_main_ambient",
+            fs_main_ambient: "fs_main_ambient",
+            vs_main_lit: "vs_main_lit",
+            fs_main_lit: "fs_main_lit",
+        }
+    }
+}
+
+// The next loaded shader's id, see CustomShader::id
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A user supplied WGSL shader which replaces model.wgsl for a material's ambient and lit
+/// passes. The shader is parsed and validated up front (preprocessed with no defines), and
+/// its entry points may only use bindings from the interface model.wgsl uses.
+#[derive(Debug)]
+pub struct CustomShader {
+    id: usize,
+    pub path: String,
+    pub source: String,
+    pub vs_main_ambient: String,
+    pub fs_main_ambient: String,
+    pub vs_main_lit: String,
+    pub fs_main_lit: String,
+    material_bindings: BTreeSet<u32>,
+}
+
+impl CustomShader {
+    pub fn load(descriptor: &CustomShaderDescriptor) -> Result<Self> {
+        let source = shader_preprocessor::resolve_includes(
+            &resources::load_string_sync(descriptor.path)?,
+            resources::load_string_sync,
+        )?;
+        Self::from_source(source, descriptor)
+    }
+
+    pub fn from_source(source: String, descriptor: &CustomShaderDescriptor) -> Result<Self> {
+        let base_source = shader_preprocessor::preprocess(&source, &[])?;
+        let bindings = shader_reflection::reflect_bindings(
+            &format!("custom shader \"{}\"", descriptor.path),
+            &base_source,
+            &[
+                (descriptor.vs_main_ambient, naga::ShaderStage::Vertex),
+                (descriptor.fs_main_ambient, naga::ShaderStage::Fragment),
+                (descriptor.vs_main_lit, naga::ShaderStage::Vertex),
+                (descriptor.fs_main_lit, naga::ShaderStage::Fragment),
+            ],
+        )?;
+
+        let mut material_bindings = BTreeSet::new();
+        for binding in bindings {
+            let kind = match binding.kind {
+                shader_reflection::BindingKind::UniformBuffer => Some(BindingKind::Uniform),
+                shader_reflection::BindingKind::Texture { .. } => Some(BindingKind::Texture),
+                shader_reflection::BindingKind::Sampler { .. } => Some(BindingKind::Sampler),
+                _ => None,
+            };
+
+            let expected = INTERFACE
+                .iter()
+                .find(|(group, index, _)| *group == binding.group && *index == binding.binding)
+                .map(|(_, _, kind)| *kind);
+
+            if expected.is_none() || expected != kind {
+                return Err(anyhow!(
+                    "Custom shader \"{}\" uses @group({}) @binding({}) as {:?}, which doesn't match the material interface (expected {:?})",
+                    descriptor.path,
+                    binding.group,
+                    binding.binding,
+                    kind,
+                    expected
+                ));
+            }
+
+            if binding.group == 0 {
+                material_bindings.insert(binding.binding);
+            }
+        }
+
+        Ok(Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            path: descriptor.path.to_owned(),
+            source,
+            vs_main_ambient: descriptor.vs_main_ambient.to_owned(),
+            fs_main_ambient: descriptor.fs_main_ambient.to_owned(),
+            vs_main_lit: descriptor.vs_main_lit.to_owned(),
+            fs_main_lit: descriptor.fs_main_lit.to_owned(),
+            material_bindings,
+        })
+    }
+
+    // unique per loaded shader, identifies it in material pipeline variant keys
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    // group 0 bindings the shader reads, which the material must provide
+    pub fn material_bindings(&self) -> impl Iterator<Item = &u32> {
+        self.material_bindings.iter()
+    }
+}
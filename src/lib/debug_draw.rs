@@ -0,0 +1,173 @@
+use wgpu::vertex_attr_array;
+
+use super::{
+    camera,
+    frustum::Frustum,
+    gpu_state::GpuState,
+    render_pipeline::{self, DepthMode, RenderPipelineVendor},
+    resources, texture,
+    transient_buffers::{TransientAllocation, TransientBufferPool},
+    util::*,
+};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct DebugLineVertex {
+    position: Point3,
+    color: Vec4,
+}
+
+unsafe impl bytemuck::Pod for DebugLineVertex {}
+unsafe impl bytemuck::Zeroable for DebugLineVertex {}
+
+const DEBUG_LINE_VERTEX_ATTRIBS: [wgpu::VertexAttribute; 2] =
+    vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+impl DebugLineVertex {
+    fn vertex_buffer_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &DEBUG_LINE_VERTEX_ATTRIBS,
+        }
+    }
+}
+
+fn pipeline_id(depth_mode: DepthMode) -> String {
+    format!("debug_lines_{:?}", depth_mode)
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Immediate mode line drawing for debugging, e.g. visualizing another camera's frustum. Lines
+// added during a frame's update are drawn (depth tested against the scene) by that frame's
+// render, then discarded.
+pub struct DebugLines {
+    vertices: Vec<DebugLineVertex>,
+    uploaded: Option<(TransientAllocation, u32)>,
+}
+
+impl Default for DebugLines {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DebugLines {
+    pub fn new() -> Self {
+        Self {
+            vertices: Vec::new(),
+            uploaded: None,
+        }
+    }
+
+    pub fn add_line<P, C>(&mut self, a: P, b: P, color: C)
+    where
+        P: Into<Point3>,
+        C: Into<Vec4>,
+    {
+        let color = color.into();
+        self.vertices.push(DebugLineVertex {
+            position: a.into(),
+            color,
+        });
+        self.vertices.push(DebugLineVertex {
+            position: b.into(),
+            color,
+        });
+    }
+
+    pub fn add_frustum<C: Into<Vec4>>(&mut self, frustum: &Frustum, color: C) {
+        let color = color.into();
+        for (a, b) in frustum.edges() {
+            self.add_line(a, b, color);
+        }
+    }
+
+    pub fn prepare_pipeline(gpu_state: &mut GpuState, depth_mode: DepthMode) {
+        let pipeline_id = pipeline_id(depth_mode);
+        if gpu_state.pipeline_vendor.has_pipeline(&pipeline_id) {
+            return;
+        }
+
+        let layout = gpu_state
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(&pipeline_id),
+                bind_group_layouts: &[&camera::Camera::bind_group_layout(&gpu_state.device)],
+                push_constant_ranges: &[],
+            });
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("shaders/debug_lines.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                resources::load_string_sync("shaders/debug_lines.wgsl")
+                    .unwrap()
+                    .into(),
+            ),
+        };
+
+        gpu_state.pipeline_vendor.create_render_pipeline(
+            &pipeline_id,
+            &gpu_state.device,
+            render_pipeline::Properties {
+                vs_main: "vs_main_debug_lines",
+                fs_main: Some("fs_main_debug_lines"),
+                layout: &layout,
+                color_format: texture::Texture::COLOR_FORMAT,
+                depth_format: Some(texture::Texture::DEPTH_FORMAT),
+                depth_bias: wgpu::DepthBiasState::default(),
+                depth_mode,
+                vertex_layouts: &[DebugLineVertex::vertex_buffer_layout()],
+                topology: wgpu::PrimitiveTopology::LineList,
+                shader,
+                cull_mode: None,
+                blend: None,
+                pass: render_pipeline::Pass::Debug,
+            },
+        );
+    }
+
+    /// Moves the lines added since the previous upload into this frame's transient buffers.
+    pub fn upload(&mut self, gpu_state: &mut GpuState) {
+        self.uploaded = if self.vertices.is_empty() {
+            None
+        } else {
+            let allocation = gpu_state.transient_buffers.allocate(
+                &gpu_state.device,
+                &gpu_state.queue,
+                bytemuck::cast_slice(&self.vertices),
+            );
+            Some((allocation, self.vertices.len() as u32))
+        };
+        self.vertices.clear();
+    }
+
+    pub fn draw<'a, 'b>(
+        &'a self,
+        render_pass: &'b mut wgpu::RenderPass<'a>,
+        pipeline_vendor: &'a RenderPipelineVendor,
+        transient_buffers: &'a TransientBufferPool,
+        camera: &'a camera::Camera,
+    ) where
+        'a: 'b, // 'a lifetime at least as long as 'b
+    {
+        let (allocation, vertex_count) = match &self.uploaded {
+            Some(uploaded) => uploaded,
+            None => return,
+        };
+
+        let pipeline_id = pipeline_id(camera.depth_mode());
+        if let Some(pipeline) = pipeline_vendor.get_pipeline(&pipeline_id) {
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, camera.bind_group(), &[]);
+            render_pass.set_vertex_buffer(0, transient_buffers.slice(allocation));
+            render_pass.draw(0..*vertex_count, 0..1);
+        } else {
+            eprintln!(
+                "No pipeline available to render debug lines id: {}",
+                pipeline_id
+            );
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt::Display;
+
+/// Named debug groups and markers, which GPU capture tools (RenderDoc, Xcode and so on) show
+/// as a tree of the frame's passes and draws. They're only recorded when built with the
+/// `debug_groups` feature; otherwise they cost nothing, labels aren't even formatted, so
+/// per-draw labels may be passed as format_args!.
+pub trait DebugGroups {
+    /// Opens a group, which must be closed with pop_group within the same encoder or pass
+    fn push_group(&mut self, label: impl Display);
+    fn pop_group(&mut self);
+    fn marker(&mut self, label: impl Display);
+}
+
+impl DebugGroups for wgpu::CommandEncoder {
+    #[allow(unused_variables)]
+    fn push_group(&mut self, label: impl Display) {
+        #[cfg(feature = "debug_groups")]
+        self.push_debug_group(&label.to_string());
+    }
+
+    fn pop_group(&mut self) {
+        #[cfg(feature = "debug_groups")]
+        self.pop_debug_group();
+    }
+
+    #[allow(unused_variables)]
+    fn marker(&mut self, label: impl Display) {
+        #[cfg(feature = "debug_groups")]
+        self.insert_debug_marker(&label.to_string());
+    }
+}
+
+impl DebugGroups for wgpu::RenderPass<'_> {
+    #[allow(unused_variables)]
+    fn push_group(&mut self, label: impl Display) {
+        #[cfg(feature = "debug_groups")]
+        self.push_debug_group(&label.to_string());
+    }
+
+    fn pop_group(&mut self) {
+        #[cfg(feature = "debug_groups")]
+        self.pop_debug_group();
+    }
+
+    #[allow(unused_variables)]
+    fn marker(&mut self, label: impl Display) {
+        #[cfg(feature = "debug_groups")]
+        self.insert_debug_marker(&label.to_string());
+    }
+}
+
+impl DebugGroups for wgpu::ComputePass<'_> {
+    #[allow(unused_variables)]
+    fn push_group(&mut self, label: impl Display) {
+        #[cfg(feature = "debug_groups")]
+        self.push_debug_group(&label.to_string());
+    }
+
+    fn pop_group(&mut self) {
+        #[cfg(feature = "debug_groups")]
+        self.pop_debug_group();
+    }
+
+    #[allow(unused_variables)]
+    fn marker(&mut self, label: impl Display) {
+        #[cfg(feature = "debug_groups")]
+        self.insert_debug_marker(&label.to_string());
+    }
+}
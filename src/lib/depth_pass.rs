@@ -0,0 +1,219 @@
+use std::ops::Range;
+
+use super::{
+    camera,
+    gpu_state::GpuState,
+    model,
+    render_pipeline::{self, DepthMode, RenderPipelineVendor},
+    resources, texture,
+};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Position-only depth pipelines read nothing but vertex positions and instance transforms, so a
+// single pipeline per vertex layout serves every opaque material. Depth bias is pipeline state,
+// so distinct biases (e.g. per shadow map) still get their own pipeline. These are used by the
+// camera depth prepass and by pcf shadow maps.
+
+// The only vertex layout in use, see model::Model::vertex_layout
+const MODEL_VERTEX_LAYOUT: &str = "model";
+
+pub fn pipeline_id(bias: &wgpu::DepthBiasState, depth_mode: DepthMode) -> String {
+    format!(
+        "depth_{}_[{},{}]_{:?}",
+        MODEL_VERTEX_LAYOUT, bias.constant, bias.slope_scale, depth_mode
+    )
+}
+
+pub fn prepass_pipeline_id(depth_mode: DepthMode) -> String {
+    pipeline_id(&wgpu::DepthBiasState::default(), depth_mode)
+}
+
+pub fn overdraw_pipeline_id(depth_mode: DepthMode) -> String {
+    format!("overdraw_{}_{:?}", MODEL_VERTEX_LAYOUT, depth_mode)
+}
+
+pub fn light_count_pipeline_id(depth_mode: DepthMode) -> String {
+    format!("light_count_{}_{:?}", MODEL_VERTEX_LAYOUT, depth_mode)
+}
+
+pub fn prepare_pipeline(
+    gpu_state: &mut GpuState,
+    bias: wgpu::DepthBiasState,
+    depth_mode: DepthMode,
+) {
+    let pipeline_id = pipeline_id(&bias, depth_mode);
+    if gpu_state.pipeline_vendor.has_pipeline(&pipeline_id) {
+        return;
+    }
+
+    // any uniform leading with a view projection matrix may be bound, the camera
+    // and light uniforms share the same layout
+    let layout = gpu_state
+        .device
+        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&pipeline_id),
+            bind_group_layouts: &[&camera::Camera::bind_group_layout(&gpu_state.device)],
+            push_constant_ranges: &[],
+        });
+
+    let shader = wgpu::ShaderModuleDescriptor {
+        label: Some("shaders/depth.wgsl"),
+        source: wgpu::ShaderSource::Wgsl(
+            resources::load_shader_sync("shaders/depth.wgsl", &[])
+                .unwrap()
+                .into(),
+        ),
+    };
+
+    gpu_state.pipeline_vendor.create_render_pipeline(
+        &pipeline_id,
+        &gpu_state.device,
+        render_pipeline::Properties {
+            vs_main: "vs_main_depth",
+            fs_main: None,
+            layout: &layout,
+            color_format: texture::Texture::COLOR_FORMAT,
+            depth_format: Some(texture::Texture::DEPTH_FORMAT),
+            depth_bias: bias,
+            depth_mode,
+            vertex_layouts: &model::Model::vertex_layout(),
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            shader,
+            cull_mode: Some(wgpu::Face::Back),
+            blend: None,
+            pass: render_pipeline::Pass::Depth,
+        },
+    );
+}
+
+pub fn prepare_prepass_pipeline(gpu_state: &mut GpuState, depth_mode: DepthMode) {
+    prepare_pipeline(gpu_state, wgpu::DepthBiasState::default(), depth_mode);
+}
+
+// Debug variants of the position-only pipeline: geometry adds a constant color per fragment
+// without writing depth. Against a cleared depth buffer every fragment counts; against the
+// depth prepass only those which would be shaded do.
+
+// Overdraw hotspots glow
+pub fn prepare_overdraw_pipeline(gpu_state: &mut GpuState, depth_mode: DepthMode) {
+    prepare_counting_pipeline(
+        gpu_state,
+        overdraw_pipeline_id(depth_mode),
+        "fs_main_overdraw",
+        depth_mode,
+    );
+}
+
+// Red counts fragments, e.g. the lights evaluated per pixel, see
+// compositor::DebugView::LightComplexity
+pub fn prepare_light_count_pipeline(gpu_state: &mut GpuState, depth_mode: DepthMode) {
+    prepare_counting_pipeline(
+        gpu_state,
+        light_count_pipeline_id(depth_mode),
+        "fs_main_count",
+        depth_mode,
+    );
+}
+
+fn prepare_counting_pipeline(
+    gpu_state: &mut GpuState,
+    pipeline_id: String,
+    fs_main: &str,
+    depth_mode: DepthMode,
+) {
+    if gpu_state.pipeline_vendor.has_pipeline(&pipeline_id) {
+        return;
+    }
+
+    let layout = gpu_state
+        .device
+        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&pipeline_id),
+            bind_group_layouts: &[&camera::Camera::bind_group_layout(&gpu_state.device)],
+            push_constant_ranges: &[],
+        });
+
+    let shader = wgpu::ShaderModuleDescriptor {
+        label: Some("shaders/depth.wgsl"),
+        source: wgpu::ShaderSource::Wgsl(
+            resources::load_shader_sync("shaders/depth.wgsl", &[])
+                .unwrap()
+                .into(),
+        ),
+    };
+
+    gpu_state.pipeline_vendor.create_render_pipeline(
+        &pipeline_id,
+        &gpu_state.device,
+        render_pipeline::Properties {
+            vs_main: "vs_main_depth",
+            fs_main: Some(fs_main),
+            layout: &layout,
+            color_format: texture::Texture::COLOR_FORMAT,
+            depth_format: Some(texture::Texture::DEPTH_FORMAT),
+            depth_bias: wgpu::DepthBiasState::default(),
+            depth_mode,
+            vertex_layouts: &model::Model::vertex_layout(),
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            shader,
+            cull_mode: Some(wgpu::Face::Back),
+            blend: Some(render_pipeline::ADDITIVE_BLEND),
+            pass: render_pipeline::Pass::Debug,
+        },
+    );
+}
+
+// Draws every mesh of `models` whose shape the position-only pipeline can reproduce with the
+// counting pipeline `pipeline_id`, e.g. overdraw_pipeline_id
+pub fn draw_counting<'a, 'b, I>(
+    render_pass: &'b mut wgpu::RenderPass<'a>,
+    pipeline_vendor: &'a RenderPipelineVendor,
+    pipeline_id: &str,
+    models: I,
+    camera: &'a camera::Camera,
+) where
+    'a: 'b, // 'a lifetime at least as long as 'b
+    I: Iterator<Item = &'a model::Model>,
+{
+    if let Some(pipeline) = pipeline_vendor.get_pipeline(pipeline_id) {
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, camera.bind_group(), &[]);
+        for model in models {
+            model::draw_model_positions(render_pass, model, |material| material.casts_shadows());
+        }
+    } else {
+        eprintln!(
+            "No pipeline available to render debug counts id: {}",
+            pipeline_id
+        );
+    }
+}
+
+// Draws the meshes of `models` whose materials can be rendered position-only, each for its
+// range of instances
+pub fn draw_prepass<'a, 'b, I>(
+    render_pass: &'b mut wgpu::RenderPass<'a>,
+    pipeline_vendor: &'a RenderPipelineVendor,
+    models: I,
+    camera: &'a camera::Camera,
+) where
+    'a: 'b, // 'a lifetime at least as long as 'b
+    I: Iterator<Item = (&'a model::Model, Range<u32>)>,
+{
+    let pipeline_id = prepass_pipeline_id(camera.depth_mode());
+    if let Some(pipeline) = pipeline_vendor.get_pipeline(&pipeline_id) {
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, camera.bind_group(), &[]);
+        for (model, instances) in models {
+            model::draw_model_positions_range(render_pass, model, instances, |material| {
+                material.writes_prepass_depth()
+            });
+        }
+    } else {
+        eprintln!(
+            "No pipeline available to render depth prepass id: {}",
+            pipeline_id
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use super::{gpu_state::GpuState, reflection_probes, texture, util::*, wind};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct EnvironmentUniformData {
+    // rotates world directions into the environment map's frame
+    rotation: Mat4,
+    // x: intensity
+    intensity: Vec4,
+}
+
+unsafe impl bytemuck::Pod for EnvironmentUniformData {}
+unsafe impl bytemuck::Zeroable for EnvironmentUniformData {}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// The scene's environment cubemap, used by materials for reflections and ambient light and by
+// the compositor for the sky. It's bound at group 3 of material pipelines, independent of the
+// materials themselves, so that it can be swapped at runtime. Its yaw and intensity apply to
+// every use, so an environment can be aligned with the scene's lighting without re-baking.
+// The scene's wind and reflection probes are bound alongside, since they're consumed by the
+// same shaders.
+pub struct Environment {
+    map: Rc<texture::Texture>,
+    // an empty set of a single 1 texel probe until set_reflection_probes, or None where the
+    // adapter can't bind the cube array textures probes are captured to
+    reflection_probes: Option<reflection_probes::ReflectionProbes>,
+    yaw: Rad,
+    intensity: f32,
+    is_dirty: bool,
+    wind: wind::Wind,
+    uniform_buffer: wgpu::Buffer,
+    // one per wind uniform buffer, selected by the wind's slot
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl Environment {
+    pub fn new(gpu_state: &GpuState, map: Rc<texture::Texture>) -> Self {
+        let device = &gpu_state.device;
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment::uniform_buffer"),
+            contents: bytemuck::cast_slice(&[EnvironmentUniformData {
+                rotation: Mat4::identity(),
+                intensity: Vec4::new(1.0, 0.0, 0.0, 0.0),
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let wind = wind::Wind::new(device, &Default::default());
+        let reflection_probes = Self::supports_reflection_probes(gpu_state)
+            .then(|| reflection_probes::ReflectionProbes::new(device, 1, 1, 1));
+        let bind_groups = Self::create_bind_groups(
+            device,
+            &map,
+            &uniform_buffer,
+            &wind,
+            reflection_probes.as_ref(),
+        );
+        Self {
+            map,
+            reflection_probes,
+            yaw: rad(0.0),
+            intensity: 1.0,
+            is_dirty: false,
+            wind,
+            uniform_buffer,
+            bind_groups,
+        }
+    }
+
+    pub fn map(&self) -> &Rc<texture::Texture> {
+        &self.map
+    }
+
+    pub fn set_map(&mut self, device: &wgpu::Device, map: Rc<texture::Texture>) {
+        if !Rc::ptr_eq(&map, &self.map) {
+            self.bind_groups = Self::create_bind_groups(
+                device,
+                &map,
+                &self.uniform_buffer,
+                &self.wind,
+                self.reflection_probes.as_ref(),
+            );
+            self.map = map;
+        }
+    }
+
+    /// Reflection probes are captured to, and bound as, a cube array texture, which GLES 3.0
+    /// adapters can't provide. Where this is false the environment binds no probes and
+    /// materials reflect only the environment map.
+    pub fn supports_reflection_probes(gpu_state: &GpuState) -> bool {
+        gpu_state
+            .downlevel_flags
+            .contains(wgpu::DownlevelFlags::CUBE_ARRAY_TEXTURES)
+    }
+
+    /// None where the adapter doesn't support reflection probes, see supports_reflection_probes
+    pub fn reflection_probes(&self) -> Option<&reflection_probes::ReflectionProbes> {
+        self.reflection_probes.as_ref()
+    }
+
+    /// The scene's reflection probes, to place and capture probes in
+    pub fn reflection_probes_mut(&mut self) -> Option<&mut reflection_probes::ReflectionProbes> {
+        self.reflection_probes.as_mut()
+    }
+
+    /// Replaces the scene's reflection probes, e.g. with a set of more capacity or larger
+    /// captures. Fails where the adapter doesn't support reflection probes.
+    pub fn set_reflection_probes(
+        &mut self,
+        device: &wgpu::Device,
+        reflection_probes: reflection_probes::ReflectionProbes,
+    ) -> Result<()> {
+        if self.reflection_probes.is_none() {
+            return Err(anyhow!(
+                "Reflection probes need cube array textures, which the adapter doesn't support"
+            ));
+        }
+        self.bind_groups = Self::create_bind_groups(
+            device,
+            &self.map,
+            &self.uniform_buffer,
+            &self.wind,
+            Some(&reflection_probes),
+        );
+        self.reflection_probes = Some(reflection_probes);
+        Ok(())
+    }
+
+    pub fn yaw(&self) -> Rad {
+        self.yaw
+    }
+
+    /// Rotates the environment about the world up axis.
+    pub fn set_yaw<R: Into<Rad>>(&mut self, yaw: R) {
+        let yaw: Rad = yaw.into();
+        if yaw != self.yaw {
+            self.yaw = yaw;
+            self.is_dirty = true;
+        }
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    /// Scales the environment's contribution to the sky, ambient light and reflections.
+    pub fn set_intensity(&mut self, intensity: f32) {
+        if intensity != self.intensity {
+            self.intensity = intensity;
+            self.is_dirty = true;
+        }
+    }
+
+    pub fn wind(&self) -> &wind::Wind {
+        &self.wind
+    }
+
+    pub fn wind_mut(&mut self) -> &mut wind::Wind {
+        &mut self.wind
+    }
+
+    pub fn update(&mut self, gpu_state: &GpuState, dt: instant::Duration) {
+        let queue = &gpu_state.queue;
+        self.wind.update(gpu_state, dt);
+        if let Some(reflection_probes) = &mut self.reflection_probes {
+            reflection_probes.update(queue);
+        }
+
+        if self.is_dirty {
+            // rotating the environment by yaw is sampling it with directions rotated by -yaw
+            let data = EnvironmentUniformData {
+                rotation: Mat4::from_angle_y(-self.yaw),
+                intensity: Vec4::new(self.intensity, 0.0, 0.0, 0.0),
+            };
+            queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[data]));
+            self.is_dirty = false;
+        }
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_groups[self.wind.slot()]
+    }
+
+    /// The layout of bind_group; `reflection_probes` adds the probe bindings 4 to 6, and should
+    /// be supports_reflection_probes.
+    pub fn bind_group_layout(
+        device: &wgpu::Device,
+        reflection_probes: bool,
+    ) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &Self::bind_group_layout_entries(reflection_probes),
+            label: Some("Environment Bind Group Layout"),
+        })
+    }
+
+    /// The entries of bind_group_layout, see shader_reflection::validate_bindings
+    pub fn bind_group_layout_entries(reflection_probes: bool) -> Vec<wgpu::BindGroupLayoutEntry> {
+        let mut entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::Cube,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // wind, read by vertex stages which animate foliage
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
+        if reflection_probes {
+            entries.extend(Self::reflection_probe_layout_entries());
+        }
+        entries
+    }
+
+    // reflection probe captures, see reflection_probes::ReflectionProbes
+    fn reflection_probe_layout_entries() -> [wgpu::BindGroupLayoutEntry; 3] {
+        [
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::CubeArray,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 6,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ]
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        map: &texture::Texture,
+        uniform_buffer: &wgpu::Buffer,
+        wind: &wind::Wind,
+        reflection_probes: Option<&reflection_probes::ReflectionProbes>,
+    ) -> Vec<wgpu::BindGroup> {
+        let layout = Self::bind_group_layout(device, reflection_probes.is_some());
+        wind.buffers()
+            .iter()
+            .map(|wind_buffer| {
+                let mut entries = vec![
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&map.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&map.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wind_buffer.as_entire_binding(),
+                    },
+                ];
+                if let Some(reflection_probes) = reflection_probes {
+                    entries.extend([
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: wgpu::BindingResource::TextureView(
+                                &reflection_probes.texture().view,
+                            ),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 5,
+                            resource: wgpu::BindingResource::Sampler(
+                                &reflection_probes.texture().sampler,
+                            ),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 6,
+                            resource: reflection_probes.uniform_buffer().as_entire_binding(),
+                        },
+                    ]);
+                }
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &layout,
+                    entries: &entries,
+                    label: Some("Environment Bind Group"),
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,113 @@
+use super::{
+    camera, environment,
+    gpu_state::{FramePhase, GpuState},
+    readback, transient_buffers, transient_textures, volumetric_fog,
+};
+
+/// What the phase of a frame being drawn may touch, handed to Scene and Compositor update and
+/// render. Made with FrameContext::update during FramePhase::Update, it reaches the GPU state
+/// and the per-frame pools; made with FrameContext::encode during FramePhase::Encode, it also
+/// holds the frame's encoder. As it borrows the GpuState, the phase can't change while one is
+/// held.
+pub struct FrameContext<'a> {
+    gpu_state: &'a mut GpuState,
+    // the encoder from GpuState::begin_encoding, while encoding
+    encoder: Option<&'a mut wgpu::CommandEncoder>,
+    // time since the previous frame
+    pub dt: instant::Duration,
+}
+
+impl<'a> FrameContext<'a> {
+    /// The context of a frame's update, between GpuState::begin_frame and begin_encoding
+    pub fn update(gpu_state: &'a mut GpuState, dt: instant::Duration) -> Self {
+        debug_assert_eq!(
+            gpu_state.frame_phase(),
+            FramePhase::Update,
+            "FrameContext::update made outside FramePhase::Update"
+        );
+        Self {
+            gpu_state,
+            encoder: None,
+            dt,
+        }
+    }
+
+    /// The context of a frame's encoding into `encoder`, from GpuState::begin_encoding
+    pub fn encode(
+        gpu_state: &'a mut GpuState,
+        encoder: &'a mut wgpu::CommandEncoder,
+        dt: instant::Duration,
+    ) -> Self {
+        debug_assert_eq!(
+            gpu_state.frame_phase(),
+            FramePhase::Encode,
+            "FrameContext::encode made outside FramePhase::Encode"
+        );
+        Self {
+            gpu_state,
+            encoder: Some(encoder),
+            dt,
+        }
+    }
+
+    /// The frame's GpuState::frame_index
+    pub fn frame_index(&self) -> u64 {
+        self.gpu_state.frame_index()
+    }
+
+    pub fn phase(&self) -> FramePhase {
+        self.gpu_state.frame_phase()
+    }
+
+    pub fn gpu_state(&self) -> &GpuState {
+        self.gpu_state
+    }
+
+    pub fn gpu_state_mut(&mut self) -> &mut GpuState {
+        self.gpu_state
+    }
+
+    pub fn transient_buffers(&mut self) -> &mut transient_buffers::TransientBufferPool {
+        &mut self.gpu_state.transient_buffers
+    }
+
+    pub fn transient_textures(&mut self) -> &mut transient_textures::TransientTexturePool {
+        &mut self.gpu_state.transient_textures
+    }
+
+    pub fn readbacks(&mut self) -> &mut readback::ReadbackPool {
+        &mut self.gpu_state.readbacks
+    }
+
+    /// The frame's encoder; panics outside FramePhase::Encode
+    pub fn encoder(&mut self) -> &mut wgpu::CommandEncoder {
+        self.encoder
+            .as_deref_mut()
+            .expect("FrameContext::encoder called outside FramePhase::Encode")
+    }
+
+    /// The GpuState and encoder together, for encoding work which also allocates from the
+    /// per-frame pools; panics outside FramePhase::Encode
+    pub fn gpu_state_and_encoder(&mut self) -> (&mut GpuState, &mut wgpu::CommandEncoder) {
+        let encoder = self
+            .encoder
+            .as_deref_mut()
+            .expect("FrameContext::gpu_state_and_encoder called outside FramePhase::Encode");
+        (self.gpu_state, encoder)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// What a scene shares each frame with those presenting it, e.g. the compositor, so they follow
+// the scene's camera wherever it's owned rather than being handed its properties piecemeal.
+// See Scene::presentation.
+pub struct ScenePresentation<'a> {
+    // the camera whose attachments are presented
+    pub camera: &'a camera::Camera,
+    pub environment: &'a environment::Environment,
+    // the fog volume built for the camera this frame, if the scene has fog and it's enabled
+    pub volumetric_fog: Option<&'a volumetric_fog::VolumetricFog>,
+    // the size of the surface presented to
+    pub size: winit::dpi::PhysicalSize<u32>,
+}
@@ -0,0 +1,58 @@
+use instant::Duration;
+
+// OS sleeps routinely overshoot by a millisecond or more, so the last stretch before
+// a frame deadline is spun rather than slept
+const SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+
+// Frame times above this (e.g. after a stall or window drag) are clamped so simulation
+// doesn't take one enormous step
+const MAX_DT: Duration = Duration::from_millis(250);
+
+pub struct FramePacer {
+    target_frame_time: Option<Duration>,
+    dt_smoothing: f64,
+    last_frame: instant::Instant,
+    smoothed_dt: Option<f64>,
+}
+
+impl FramePacer {
+    /// `max_fps` caps the frame rate independent of vsync. `dt_smoothing` in [0,1) is the
+    /// weight of the previous smoothed dt in an exponential moving average; 0 yields raw dt.
+    pub fn new(max_fps: Option<f32>, dt_smoothing: f32) -> Self {
+        Self {
+            target_frame_time: max_fps
+                .filter(|fps| *fps > 0.0)
+                .map(|fps| Duration::from_secs_f64(1.0 / fps as f64)),
+            dt_smoothing: dt_smoothing.clamp(0.0, 0.99) as f64,
+            last_frame: instant::Instant::now(),
+            smoothed_dt: None,
+        }
+    }
+
+    /// Waits until the target frame time has elapsed since the previous frame began,
+    /// then returns the (clamped, smoothed) time step for the new frame.
+    pub fn begin_frame(&mut self) -> Duration {
+        if let Some(target_frame_time) = self.target_frame_time {
+            let deadline = self.last_frame + target_frame_time;
+            let now = instant::Instant::now();
+            if deadline > now + SPIN_THRESHOLD {
+                std::thread::sleep(deadline - now - SPIN_THRESHOLD);
+            }
+            while instant::Instant::now() < deadline {
+                std::hint::spin_loop();
+            }
+        }
+
+        let now = instant::Instant::now();
+        let dt = (now - self.last_frame).min(MAX_DT).as_secs_f64();
+        self.last_frame = now;
+
+        let smoothed_dt = match self.smoothed_dt {
+            Some(previous) => previous * self.dt_smoothing + dt * (1.0 - self.dt_smoothing),
+            None => dt,
+        };
+        self.smoothed_dt = Some(smoothed_dt);
+
+        Duration::from_secs_f64(smoothed_dt)
+    }
+}
@@ -0,0 +1,150 @@
+use cgmath::prelude::*;
+
+use super::util::*;
+
+// The far corners of a frustum with an infinite far plane are placed at this distance from
+// the eye, so that it can still be visualized
+const INFINITE_FAR_CORNER_DISTANCE: f32 = 1000.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl Plane {
+    pub fn from_point_normal(point: Point3, normal: Vec3) -> Self {
+        let normal = normal.normalize();
+        Self {
+            normal,
+            distance: -normal.dot(point.to_vec()),
+        }
+    }
+
+    // A plane which every point lies in front of
+    fn everywhere() -> Self {
+        Self {
+            normal: Vec3::zero(),
+            distance: 0.0,
+        }
+    }
+
+    /// Positive in front of the plane (i.e. the side the normal faces).
+    pub fn signed_distance(&self, point: Point3) -> f32 {
+        self.normal.dot(point.to_vec()) + self.distance
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    // left, right, bottom, top, near, far; normals face into the frustum
+    pub planes: [Plane; 6],
+    // near bottom-left, bottom-right, top-right, top-left, then the same for the far plane
+    pub corners: [Point3; 8],
+}
+
+impl Frustum {
+    /// `look` is the camera's world rotation, whose columns are right, up and backward.
+    /// An infinite `z_far` yields a far plane which culls nothing.
+    pub fn new(
+        position: Point3,
+        look: Mat3,
+        fov_y: Rad,
+        aspect: f32,
+        z_near: f32,
+        z_far: f32,
+    ) -> Self {
+        let right = look.x;
+        let up = look.y;
+        let forward = -look.z;
+
+        let tan_half_height = (fov_y / 2.0).tan();
+        let tan_half_width = tan_half_height * aspect;
+
+        // directions from the eye along the frustum's side planes
+        let left_edge = forward - right * tan_half_width;
+        let right_edge = forward + right * tan_half_width;
+        let bottom_edge = forward - up * tan_half_height;
+        let top_edge = forward + up * tan_half_height;
+
+        let far_plane = if z_far.is_finite() {
+            Plane::from_point_normal(position + forward * z_far, -forward)
+        } else {
+            Plane::everywhere()
+        };
+
+        let planes = [
+            Plane::from_point_normal(position, left_edge.cross(up)),
+            Plane::from_point_normal(position, up.cross(right_edge)),
+            Plane::from_point_normal(position, right.cross(bottom_edge)),
+            Plane::from_point_normal(position, top_edge.cross(right)),
+            Plane::from_point_normal(position + forward * z_near, forward),
+            far_plane,
+        ];
+
+        let corner = |distance: f32, x: f32, y: f32| {
+            position
+                + (forward + right * (x * tan_half_width) + up * (y * tan_half_height)) * distance
+        };
+        let far_distance = z_far.min(INFINITE_FAR_CORNER_DISTANCE.max(z_near));
+        let corners = [
+            corner(z_near, -1.0, -1.0),
+            corner(z_near, 1.0, -1.0),
+            corner(z_near, 1.0, 1.0),
+            corner(z_near, -1.0, 1.0),
+            corner(far_distance, -1.0, -1.0),
+            corner(far_distance, 1.0, -1.0),
+            corner(far_distance, 1.0, 1.0),
+            corner(far_distance, -1.0, 1.0),
+        ];
+
+        Self { planes, corners }
+    }
+
+    pub fn contains_point(&self, point: Point3) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(point) >= 0.0)
+    }
+
+    /// Conservative; may report spheres near the frustum's corners as intersecting.
+    pub fn intersects_sphere(&self, center: Point3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(center) >= -radius)
+    }
+
+    /// Conservative; may report boxes near the frustum's corners as intersecting.
+    pub fn intersects_aabb(&self, min: Point3, max: Point3) -> bool {
+        self.planes.iter().all(|plane| {
+            // the box corner furthest along the plane normal
+            let positive = Point3::new(
+                if plane.normal.x >= 0.0 { max.x } else { min.x },
+                if plane.normal.y >= 0.0 { max.y } else { min.y },
+                if plane.normal.z >= 0.0 { max.z } else { min.z },
+            );
+            plane.signed_distance(positive) >= 0.0
+        })
+    }
+
+    /// The twelve edges of the frustum, e.g. for drawing as lines.
+    pub fn edges(&self) -> [(Point3, Point3); 12] {
+        let c = &self.corners;
+        [
+            (c[0], c[1]),
+            (c[1], c[2]),
+            (c[2], c[3]),
+            (c[3], c[0]),
+            (c[4], c[5]),
+            (c[5], c[6]),
+            (c[6], c[7]),
+            (c[7], c[4]),
+            (c[0], c[4]),
+            (c[1], c[5]),
+            (c[2], c[6]),
+            (c[3], c[7]),
+        ]
+    }
+}
@@ -0,0 +1,1019 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+use anyhow::{anyhow, Context, Result};
+use cgmath::prelude::*;
+use serde::Deserialize;
+
+use super::{
+    mesh_builder::MeshBuilder, meshopt, model::ModelVertex, packed_asset::PackedMaterial, util::*,
+};
+
+// "glTF", leading a binary glTF file
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_HEADER_SIZE: usize = 12;
+const GLB_CHUNK_JSON: u32 = 0x4E4F_534A;
+const GLB_CHUNK_BIN: u32 = 0x004E_4942;
+
+// The extensions a file may require which the reader handles; KHR_mesh_quantization only asks
+// for integer attributes, which every attribute is read as
+const SUPPORTED_EXTENSIONS: [&str; 3] = [
+    "EXT_meshopt_compression",
+    "KHR_mesh_quantization",
+    "KHR_texture_transform",
+];
+const DRACO_EXTENSION: &str = "KHR_draco_mesh_compression";
+
+// Primitive::mode of triangle lists, the only mode read
+const MODE_TRIANGLES: u32 = 4;
+
+const COMPONENT_BYTE: u32 = 5120;
+const COMPONENT_UNSIGNED_BYTE: u32 = 5121;
+const COMPONENT_SHORT: u32 = 5122;
+const COMPONENT_UNSIGNED_SHORT: u32 = 5123;
+const COMPONENT_UNSIGNED_INT: u32 = 5125;
+const COMPONENT_FLOAT: u32 = 5126;
+
+// Material shininess at zero roughness; rougher materials fall off with the square of their
+// smoothness
+const MAX_SHININESS: f32 = 1000.0;
+// The specular reflectance of non-metals
+const DIELECTRIC_SPECULAR: f32 = 0.04;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// A glTF 2.0 model, read from a .gltf or .glb file by GltfModel::read into the same shape
+/// load_model builds from an OBJ: a mesh builder per primitive, tangents computed, and
+/// materials naming their textures.
+pub struct GltfModel {
+    pub materials: Vec<PackedMaterial>,
+    // the encoded images embedded in the file, keyed by the texture names the materials use
+    // for them; other texture names are files relative to the glTF file
+    pub images: HashMap<String, Vec<u8>>,
+    pub meshes: Vec<MeshBuilder>,
+}
+
+impl GltfModel {
+    /// Reads the glTF file `file_name` from its `bytes`, calling `load` for the external
+    /// buffers it names, relative to the file. Meshes are flattened out of the default
+    /// scene's node hierarchy, each primitive transformed to the scene's space. Buffer views
+    /// compressed with EXT_meshopt_compression are decoded, and quantized attributes
+    /// (KHR_mesh_quantization) dequantized. Draco compressed geometry isn't supported.
+    ///
+    /// Materials are approximated by the renderer's: the base color is the diffuse color and
+    /// texture, roughness sets the shininess, and normal and occlusion textures are kept. A
+    /// material's KHR_texture_transform is applied to its meshes' texture coordinates.
+    pub fn read<F>(file_name: &str, bytes: &[u8], load: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Result<Vec<u8>>,
+    {
+        let (json, binary_chunk) = if bytes.len() >= 4 && read_u32(bytes, 0) == GLB_MAGIC {
+            split_glb(bytes)?
+        } else {
+            (bytes, None)
+        };
+        let root: Root = serde_json::from_slice(json).context("Invalid glTF JSON")?;
+
+        if root
+            .extensions_required
+            .iter()
+            .any(|e| e == DRACO_EXTENSION)
+        {
+            return Err(draco_unsupported());
+        }
+        let unsupported = root
+            .extensions_required
+            .iter()
+            .filter(|e| !SUPPORTED_EXTENSIONS.contains(&e.as_str()))
+            .collect::<Vec<_>>();
+        if !unsupported.is_empty() {
+            return Err(anyhow!("Requires unsupported extensions {:?}", unsupported));
+        }
+
+        let buffers = root
+            .buffers
+            .iter()
+            .enumerate()
+            .map(|(index, buffer)| match &buffer.uri {
+                Some(uri) => match decode_data_uri(uri)? {
+                    Some((_, data)) => Ok(Some(Cow::Owned(data))),
+                    None => load(&decode_uri(uri))
+                        .map(|data| Some(Cow::Owned(data)))
+                        .with_context(|| format!("Unable to load buffer \"{}\"", uri)),
+                },
+                // the binary chunk of a .glb, or a fallback for meshopt compressed views
+                None if index == 0 => Ok(binary_chunk.map(Cow::Borrowed)),
+                None => Ok(None),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let views = root
+            .buffer_views
+            .iter()
+            .enumerate()
+            .map(|(index, view)| {
+                read_buffer_view(view, &buffers)
+                    .with_context(|| format!("Unable to read buffer view {}", index))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let stem = std::path::Path::new(file_name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("gltf");
+        let mut reader = Reader {
+            root: &root,
+            views,
+            stem,
+            images: HashMap::new(),
+        };
+
+        let mut materials = root
+            .materials
+            .iter()
+            .enumerate()
+            .map(|(index, material)| reader.material(index, material))
+            .collect::<Result<Vec<_>>>()?;
+        // primitives without a material share a default one, added if any need it
+        let default_material = materials.len();
+
+        let mut meshes = Vec::new();
+        for (node_mesh, transform) in reader.scene_meshes() {
+            let mesh = root
+                .meshes
+                .get(node_mesh)
+                .ok_or_else(|| anyhow!("No mesh {}", node_mesh))?;
+            let mesh_name = mesh
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("Mesh {}", node_mesh));
+            for (index, primitive) in mesh.primitives.iter().enumerate() {
+                let name = if mesh.primitives.len() > 1 {
+                    format!("{} {}", mesh_name, index)
+                } else {
+                    mesh_name.clone()
+                };
+                let material = primitive.material.unwrap_or(default_material);
+                if let Some(builder) = reader
+                    .primitive(&name, primitive, &transform, material)
+                    .with_context(|| format!("Unable to read mesh \"{}\"", name))?
+                {
+                    meshes.push(builder);
+                }
+            }
+        }
+        if meshes.iter().any(|m| m.material() == default_material) {
+            materials.push(PackedMaterial {
+                name: "Default".to_owned(),
+                ..PackedMaterial::from_base_color(Vec3::new(1.0, 1.0, 1.0), 0.0, 1.0)
+            });
+        }
+
+        Ok(Self {
+            materials,
+            images: reader.images,
+            meshes,
+        })
+    }
+}
+
+impl PackedMaterial {
+    // A material of a metal/roughness glTF material's base color, metallic and roughness
+    // factors, untextured
+    fn from_base_color(base_color: Vec3, metallic: f32, roughness: f32) -> Self {
+        let metallic = metallic.clamp(0.0, 1.0);
+        let smoothness = 1.0 - roughness.clamp(0.0, 1.0);
+        let dielectric = Vec3::new(1.0, 1.0, 1.0) * DIELECTRIC_SPECULAR;
+        Self {
+            name: String::new(),
+            ambient: Vec3::new(1.0, 1.0, 1.0),
+            diffuse: base_color,
+            specular: dielectric.lerp(base_color, metallic),
+            // a shininess below 1 would spread highlights past the hemisphere
+            shininess: (MAX_SHININESS * smoothness * smoothness).max(1.0),
+            diffuse_texture: String::new(),
+            normal_texture: String::new(),
+            shininess_texture: String::new(),
+            ambient_occlusion_texture: String::new(),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Reads meshes and materials out of a parsed file, with its buffer views' data
+struct Reader<'a> {
+    root: &'a Root,
+    // each view's data, decoded if compressed; None for views of a buffer with no data
+    views: Vec<Option<Cow<'a, [u8]>>>,
+    // the glTF file's name without its extension, naming embedded images
+    stem: &'a str,
+    images: HashMap<String, Vec<u8>>,
+}
+
+impl<'a> Reader<'a> {
+    // The meshes of the default scene's nodes, or every root node's if it has no scenes,
+    // with the transforms of the nodes which place them
+    fn scene_meshes(&self) -> Vec<(usize, Mat4)> {
+        let nodes = &self.root.nodes;
+        let roots = match self.root.scenes.get(self.root.scene.unwrap_or(0)) {
+            Some(scene) => scene.nodes.clone(),
+            None => {
+                let children = nodes
+                    .iter()
+                    .flat_map(|node| node.children.iter().copied())
+                    .collect::<HashSet<_>>();
+                (0..nodes.len()).filter(|n| !children.contains(n)).collect()
+            }
+        };
+
+        let mut meshes = Vec::new();
+        // nodes already visited are skipped, so a malformed hierarchy with cycles terminates
+        let mut visited = HashSet::new();
+        let mut stack = roots
+            .into_iter()
+            .map(|node| (node, Mat4::identity()))
+            .collect::<Vec<_>>();
+        while let Some((index, parent)) = stack.pop() {
+            let node = match nodes.get(index) {
+                Some(node) if visited.insert(index) => node,
+                _ => continue,
+            };
+            let transform = parent * node.transform();
+            if let Some(mesh) = node.mesh {
+                meshes.push((mesh, transform));
+            }
+            stack.extend(node.children.iter().map(|child| (*child, transform)));
+        }
+        meshes
+    }
+
+    // A mesh builder of a triangle list primitive, its geometry transformed by `transform`;
+    // None for other primitives, which are skipped
+    fn primitive(
+        &self,
+        name: &str,
+        primitive: &Primitive,
+        transform: &Mat4,
+        material: usize,
+    ) -> Result<Option<MeshBuilder>> {
+        let mode = primitive.mode.unwrap_or(MODE_TRIANGLES);
+        if mode != MODE_TRIANGLES {
+            eprintln!(
+                "Skipping mesh \"{}\", its primitive mode {} isn't a triangle list",
+                name, mode
+            );
+            return Ok(None);
+        }
+
+        let attribute = |name: &str| primitive.attributes.get(name).copied();
+        let position_accessor =
+            attribute("POSITION").ok_or_else(|| anyhow!("No POSITION attribute"))?;
+        // Draco compressed primitives may carry uncompressed fallbacks, which are read if so
+        if primitive.extensions.contains_key(DRACO_EXTENSION)
+            && self
+                .root
+                .accessors
+                .get(position_accessor)
+                .is_some_and(|accessor| accessor.buffer_view.is_none())
+        {
+            return Err(draco_unsupported());
+        }
+
+        let positions = self.floats(position_accessor, 3)?;
+        let normals = attribute("NORMAL")
+            .map(|accessor| self.floats(accessor, 3))
+            .transpose()?;
+        let tex_coords = attribute("TEXCOORD_0")
+            .map(|accessor| self.floats(accessor, 2))
+            .transpose()?;
+        let vertex_count = positions.len() / 3;
+        let indices = match primitive.indices {
+            Some(accessor) => self.indices(accessor)?,
+            None => (0..vertex_count as u32).collect(),
+        };
+
+        let linear = Mat3::from_cols(
+            transform.x.truncate(),
+            transform.y.truncate(),
+            transform.z.truncate(),
+        );
+        let normal_matrix = linear
+            .invert()
+            .map(|inverse| inverse.transpose())
+            .unwrap_or_else(Mat3::identity);
+        let tex_coord_transform = self
+            .root
+            .materials
+            .get(material)
+            .and_then(|material| material.texture_transform())
+            .unwrap_or_default();
+
+        let mut builder = MeshBuilder::with_capacity(name, vertex_count, indices.len());
+        builder.set_material(material);
+        for i in 0..vertex_count {
+            let position =
+                Point3::new(positions[i * 3], positions[i * 3 + 1], positions[i * 3 + 2]);
+            let normal = match &normals {
+                Some(normals) if normals.len() >= (i + 1) * 3 => {
+                    let normal = Vec3::new(normals[i * 3], normals[i * 3 + 1], normals[i * 3 + 2]);
+                    (normal_matrix * normal).normalize()
+                }
+                _ => Vec3::zero(),
+            };
+            let tex_coord = match &tex_coords {
+                Some(tex_coords) if tex_coords.len() >= (i + 1) * 2 => {
+                    tex_coord_transform.apply(Vec2::new(tex_coords[i * 2], tex_coords[i * 2 + 1]))
+                }
+                _ => Vec2::zero(),
+            };
+            builder.push_vertex(ModelVertex {
+                position: transform.transform_point(position),
+                tex_coords: tex_coord,
+                normal,
+                tangent: Vec3::zero(),
+                bitangent: Vec3::zero(),
+            });
+        }
+        // mirroring transforms turn triangles inside out
+        let mirrored = linear.determinant() < 0.0;
+        for triangle in indices.chunks_exact(3) {
+            if mirrored {
+                builder.push_triangle(triangle[0], triangle[2], triangle[1]);
+            } else {
+                builder.push_triangle(triangle[0], triangle[1], triangle[2]);
+            }
+        }
+        builder.validate()?;
+        if normals.is_none() {
+            builder.compute_normals();
+        }
+        builder.compute_tangents();
+        Ok(Some(builder))
+    }
+
+    fn material(&mut self, index: usize, material: &Material) -> Result<PackedMaterial> {
+        let pbr = &material.pbr_metallic_roughness;
+        let [r, g, b, _] = pbr.base_color_factor;
+        let texture = |reader: &mut Self, info: &Option<TextureInfo>| match info {
+            Some(info) => reader.texture_name(info.index),
+            None => Ok(String::new()),
+        };
+        Ok(PackedMaterial {
+            name: material
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("Material {}", index)),
+            diffuse_texture: texture(self, &pbr.base_color_texture)?,
+            normal_texture: texture(self, &material.normal_texture)?,
+            // the metallic roughness texture holds roughness in green, not the glossiness
+            // shininess textures hold, so isn't used
+            ambient_occlusion_texture: texture(self, &material.occlusion_texture)?,
+            ..PackedMaterial::from_base_color(
+                Vec3::new(r, g, b),
+                pbr.metallic_factor,
+                pbr.roughness_factor,
+            )
+        })
+    }
+
+    // The name a material refers to texture `index` by: the image's file, or for an embedded
+    // image a name made up for it, with its data added to `images`
+    fn texture_name(&mut self, index: usize) -> Result<String> {
+        let image_index = match self.root.textures.get(index).and_then(|t| t.source) {
+            Some(image_index) => image_index,
+            None => return Ok(String::new()),
+        };
+        let image = self
+            .root
+            .images
+            .get(image_index)
+            .ok_or_else(|| anyhow!("No image {}", image_index))?;
+        let (mime_type, data) = match (&image.uri, image.buffer_view) {
+            (Some(uri), _) => match decode_data_uri(uri)? {
+                Some((mime_type, data)) => (mime_type, data),
+                None => return Ok(decode_uri(uri)),
+            },
+            (None, Some(view)) => (
+                image.mime_type.clone().unwrap_or_default(),
+                self.view(view)?.to_vec(),
+            ),
+            (None, None) => return Err(anyhow!("Image {} has no data", image_index)),
+        };
+        let extension = match mime_type.as_str() {
+            "image/jpeg" => "jpg",
+            "image/png" => "png",
+            _ => "bin",
+        };
+        let name = format!("{}_image{}.{}", self.stem, image_index, extension);
+        self.images.insert(name.clone(), data);
+        Ok(name)
+    }
+
+    fn view(&self, index: usize) -> Result<&[u8]> {
+        self.views
+            .get(index)
+            .ok_or_else(|| anyhow!("No buffer view {}", index))?
+            .as_deref()
+            .ok_or_else(|| anyhow!("Buffer view {} has no data", index))
+    }
+
+    // The components of accessor `index`, which must have `components` per element, as floats;
+    // normalized integers are mapped to [0, 1] or [-1, 1]
+    fn floats(&self, index: usize, components: usize) -> Result<Vec<f32>> {
+        let accessor = self.accessor(index)?;
+        if accessor.components()? != components {
+            return Err(anyhow!(
+                "Accessor {} is a {}, expected {} components",
+                index,
+                accessor.kind,
+                components
+            ));
+        }
+        let normalized = accessor.normalized;
+        self.read(accessor, |bytes, component_type| {
+            read_float(bytes, component_type, normalized)
+        })
+    }
+
+    // The indices of accessor `index`
+    fn indices(&self, index: usize) -> Result<Vec<u32>> {
+        let accessor = self.accessor(index)?;
+        match accessor.component_type {
+            COMPONENT_UNSIGNED_BYTE | COMPONENT_UNSIGNED_SHORT | COMPONENT_UNSIGNED_INT => {}
+            component_type => {
+                return Err(anyhow!(
+                    "Accessor {} has component type {}, which can't be indices",
+                    index,
+                    component_type
+                ))
+            }
+        }
+        self.read(accessor, read_index)
+    }
+
+    fn accessor(&self, index: usize) -> Result<&'a Accessor> {
+        self.root
+            .accessors
+            .get(index)
+            .ok_or_else(|| anyhow!("No accessor {}", index))
+    }
+
+    // Every component of `accessor`, converted by `convert` from its bytes and component type,
+    // with sparse substitutions applied
+    fn read<T, F>(&self, accessor: &Accessor, convert: F) -> Result<Vec<T>>
+    where
+        T: Copy + Default,
+        F: Fn(&[u8], u32) -> T,
+    {
+        let components = accessor.components()?;
+        let value_size = component_size(accessor.component_type)?;
+        let element_size = components * value_size;
+        let mut values = vec![T::default(); accessor.count * components];
+
+        // an accessor without a view is all zeros, unless made sparse
+        if let Some(view_index) = accessor.buffer_view {
+            let view = self.view(view_index)?;
+            let stride = self.root.buffer_views[view_index]
+                .byte_stride
+                .unwrap_or(element_size);
+            read_elements(
+                view,
+                accessor.byte_offset,
+                stride,
+                accessor.count,
+                element_size,
+                |element, bytes| {
+                    for c in 0..components {
+                        values[element * components + c] =
+                            convert(&bytes[c * value_size..], accessor.component_type);
+                    }
+                },
+            )?;
+        }
+
+        if let Some(sparse) = &accessor.sparse {
+            let index_size = component_size(sparse.indices.component_type)?;
+            let mut targets = Vec::with_capacity(sparse.count);
+            read_elements(
+                self.view(sparse.indices.buffer_view)?,
+                sparse.indices.byte_offset,
+                index_size,
+                sparse.count,
+                index_size,
+                |_, bytes| targets.push(read_index(bytes, sparse.indices.component_type) as usize),
+            )?;
+            let mut out_of_range = false;
+            read_elements(
+                self.view(sparse.values.buffer_view)?,
+                sparse.values.byte_offset,
+                element_size,
+                sparse.count,
+                element_size,
+                |element, bytes| {
+                    let target = targets[element];
+                    if target >= accessor.count {
+                        out_of_range = true;
+                        return;
+                    }
+                    for c in 0..components {
+                        values[target * components + c] =
+                            convert(&bytes[c * value_size..], accessor.component_type);
+                    }
+                },
+            )?;
+            if out_of_range {
+                return Err(anyhow!("Sparse accessor substitutes elements past its end"));
+            }
+        }
+        Ok(values)
+    }
+}
+
+// Calls `visit` with the index and bytes of each of `count` elements of `element_size` bytes,
+// `stride` bytes apart from `offset` in `view`
+fn read_elements<F>(
+    view: &[u8],
+    offset: usize,
+    stride: usize,
+    count: usize,
+    element_size: usize,
+    mut visit: F,
+) -> Result<()>
+where
+    F: FnMut(usize, &[u8]),
+{
+    if count == 0 {
+        return Ok(());
+    }
+    let end = offset + (count - 1) * stride + element_size;
+    if end > view.len() {
+        return Err(anyhow!(
+            "Accessor reads to byte {} of a {} byte buffer view",
+            end,
+            view.len()
+        ));
+    }
+    for element in 0..count {
+        let start = offset + element * stride;
+        visit(element, &view[start..start + element_size]);
+    }
+    Ok(())
+}
+
+// The data of `view`, decoded if compressed with EXT_meshopt_compression; None if its buffer
+// has none, as the fallback buffers of compressed views may not
+fn read_buffer_view<'a>(
+    view: &BufferView,
+    buffers: &'a [Option<Cow<'a, [u8]>>],
+) -> Result<Option<Cow<'a, [u8]>>> {
+    let slice = |buffer: usize, offset: usize, length: usize| -> Result<Option<&'a [u8]>> {
+        match buffers.get(buffer) {
+            Some(Some(data)) => {
+                let data: &'a [u8] = data;
+                data.get(offset..offset + length)
+                    .map(Some)
+                    .ok_or_else(|| anyhow!("Extends past the end of buffer {}", buffer))
+            }
+            Some(None) => Ok(None),
+            None => Err(anyhow!("No buffer {}", buffer)),
+        }
+    };
+    match &view.extensions.meshopt {
+        Some(compression) => {
+            let data = slice(
+                compression.buffer,
+                compression.byte_offset,
+                compression.byte_length,
+            )?
+            .ok_or_else(|| anyhow!("Compressed data's buffer {} is empty", compression.buffer))?;
+            let decoded = meshopt::decode(
+                compression.mode,
+                compression.filter,
+                compression.count,
+                compression.byte_stride,
+                data,
+            )?;
+            Ok(Some(Cow::Owned(decoded)))
+        }
+        None => Ok(slice(view.buffer, view.byte_offset, view.byte_length)?.map(Cow::Borrowed)),
+    }
+}
+
+// The JSON and binary chunks of a .glb file
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>)> {
+    if bytes.len() < GLB_HEADER_SIZE {
+        return Err(anyhow!("Binary glTF ends early"));
+    }
+    let version = read_u32(bytes, 4);
+    if version != 2 {
+        return Err(anyhow!("Binary glTF version {} isn't supported", version));
+    }
+    let length = (read_u32(bytes, 8) as usize).min(bytes.len());
+    let mut offset = GLB_HEADER_SIZE;
+    let mut json = None;
+    let mut binary = None;
+    while offset + 8 <= length {
+        let chunk_length = read_u32(bytes, offset) as usize;
+        let chunk_type = read_u32(bytes, offset + 4);
+        let chunk = bytes
+            .get(offset + 8..offset + 8 + chunk_length)
+            .ok_or_else(|| anyhow!("Binary glTF chunk ends early"))?;
+        match chunk_type {
+            GLB_CHUNK_JSON if json.is_none() => json = Some(chunk),
+            GLB_CHUNK_BIN if binary.is_none() => binary = Some(chunk),
+            _ => {}
+        }
+        offset += 8 + chunk_length;
+    }
+    let json = json.ok_or_else(|| anyhow!("Binary glTF has no JSON chunk"))?;
+    Ok((json, binary))
+}
+
+fn draco_unsupported() -> anyhow::Error {
+    anyhow!(
+        "Draco compressed geometry ({}) isn't supported; export with EXT_meshopt_compression instead, e.g. with gltfpack -cc",
+        DRACO_EXTENSION
+    )
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn component_size(component_type: u32) -> Result<usize> {
+    match component_type {
+        COMPONENT_BYTE | COMPONENT_UNSIGNED_BYTE => Ok(1),
+        COMPONENT_SHORT | COMPONENT_UNSIGNED_SHORT => Ok(2),
+        COMPONENT_UNSIGNED_INT | COMPONENT_FLOAT => Ok(4),
+        _ => Err(anyhow!("Unknown component type {}", component_type)),
+    }
+}
+
+fn read_float(bytes: &[u8], component_type: u32, normalized: bool) -> f32 {
+    let (value, max) = match component_type {
+        COMPONENT_BYTE => (bytes[0] as i8 as f32, i8::MAX as f32),
+        COMPONENT_UNSIGNED_BYTE => (bytes[0] as f32, u8::MAX as f32),
+        COMPONENT_SHORT => (
+            i16::from_le_bytes([bytes[0], bytes[1]]) as f32,
+            i16::MAX as f32,
+        ),
+        COMPONENT_UNSIGNED_SHORT => (
+            u16::from_le_bytes([bytes[0], bytes[1]]) as f32,
+            u16::MAX as f32,
+        ),
+        COMPONENT_UNSIGNED_INT => (read_u32(bytes, 0) as f32, u32::MAX as f32),
+        _ => return f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    };
+    if normalized {
+        (value / max).max(-1.0)
+    } else {
+        value
+    }
+}
+
+fn read_index(bytes: &[u8], component_type: u32) -> u32 {
+    match component_type {
+        COMPONENT_UNSIGNED_BYTE => bytes[0] as u32,
+        COMPONENT_UNSIGNED_SHORT => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+        _ => read_u32(bytes, 0),
+    }
+}
+
+// The mime type and data of a data URI, None if `uri` isn't one
+fn decode_data_uri(uri: &str) -> Result<Option<(String, Vec<u8>)>> {
+    let rest = match uri.strip_prefix("data:") {
+        Some(rest) => rest,
+        None => return Ok(None),
+    };
+    let (header, data) = rest
+        .split_once(',')
+        .ok_or_else(|| anyhow!("Data URI has no data"))?;
+    let mime_type = header.split(';').next().unwrap_or_default().to_owned();
+    let data = if header.ends_with(";base64") {
+        decode_base64(data)?
+    } else {
+        decode_uri(data).into_bytes()
+    };
+    Ok(Some((mime_type, data)))
+}
+
+// Replaces a URI's percent encoded bytes
+fn decode_uri(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| uri.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for c in text.bytes().take_while(|c| *c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err(anyhow!("Invalid base64 character {:?}", c as char)),
+        };
+        bits = bits << 6 | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            decoded.push((bits >> bit_count) as u8);
+        }
+    }
+    Ok(decoded)
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// The parts of the glTF 2.0 schema the reader uses; see
+// https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Root {
+    extensions_required: Vec<String>,
+    scene: Option<usize>,
+    scenes: Vec<Scene>,
+    nodes: Vec<Node>,
+    meshes: Vec<Mesh>,
+    accessors: Vec<Accessor>,
+    buffer_views: Vec<BufferView>,
+    buffers: Vec<Buffer>,
+    materials: Vec<Material>,
+    textures: Vec<Texture>,
+    images: Vec<Image>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Scene {
+    nodes: Vec<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Node {
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    // column major
+    matrix: Option<[f32; 16]>,
+    translation: Option<[f32; 3]>,
+    // x, y, z, w
+    rotation: Option<[f32; 4]>,
+    scale: Option<[f32; 3]>,
+}
+
+impl Node {
+    // The node's transform relative to its parent
+    fn transform(&self) -> Mat4 {
+        if let Some(m) = self.matrix {
+            return Mat4::new(
+                m[0], m[1], m[2], m[3], m[4], m[5], m[6], m[7], m[8], m[9], m[10], m[11], m[12],
+                m[13], m[14], m[15],
+            );
+        }
+        let [tx, ty, tz] = self.translation.unwrap_or([0.0; 3]);
+        let [x, y, z, w] = self.rotation.unwrap_or([0.0, 0.0, 0.0, 1.0]);
+        let [sx, sy, sz] = self.scale.unwrap_or([1.0; 3]);
+        Mat4::from_translation(Vec3::new(tx, ty, tz))
+            * Mat4::from(Quat::new(w, x, y, z))
+            * Mat4::from_nonuniform_scale(sx, sy, sz)
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Mesh {
+    name: Option<String>,
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    mode: Option<u32>,
+    extensions: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Accessor {
+    buffer_view: Option<usize>,
+    byte_offset: usize,
+    component_type: u32,
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+    sparse: Option<Sparse>,
+}
+
+impl Accessor {
+    fn components(&self) -> Result<usize> {
+        match self.kind.as_str() {
+            "SCALAR" => Ok(1),
+            "VEC2" => Ok(2),
+            "VEC3" => Ok(3),
+            "VEC4" => Ok(4),
+            kind => Err(anyhow!("Accessors of type {} aren't supported", kind)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Sparse {
+    count: usize,
+    indices: SparseIndices,
+    values: SparseValues,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SparseIndices {
+    buffer_view: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SparseValues {
+    buffer_view: usize,
+    #[serde(default)]
+    byte_offset: usize,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct BufferView {
+    buffer: usize,
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>,
+    extensions: BufferViewExtensions,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct BufferViewExtensions {
+    #[serde(rename = "EXT_meshopt_compression")]
+    meshopt: Option<MeshoptCompression>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MeshoptCompression {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: usize,
+    count: usize,
+    mode: meshopt::Mode,
+    #[serde(default)]
+    filter: meshopt::Filter,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Buffer {
+    uri: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Material {
+    name: Option<String>,
+    pbr_metallic_roughness: PbrMetallicRoughness,
+    normal_texture: Option<TextureInfo>,
+    occlusion_texture: Option<TextureInfo>,
+}
+
+impl Material {
+    // The transform of the material's texture coordinates, taken from its base color texture,
+    // or failing that its normal or occlusion texture; the renderer's materials sample every
+    // texture with the same coordinates
+    fn texture_transform(&self) -> Option<TextureTransform> {
+        [
+            &self.pbr_metallic_roughness.base_color_texture,
+            &self.normal_texture,
+            &self.occlusion_texture,
+        ]
+        .into_iter()
+        .flatten()
+        .next()
+        .and_then(|info| info.extensions.transform)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct PbrMetallicRoughness {
+    base_color_factor: [f32; 4],
+    base_color_texture: Option<TextureInfo>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+}
+
+impl Default for PbrMetallicRoughness {
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.0; 4],
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TextureInfo {
+    index: usize,
+    #[serde(default)]
+    extensions: TextureInfoExtensions,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct TextureInfoExtensions {
+    #[serde(rename = "KHR_texture_transform")]
+    transform: Option<TextureTransform>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default)]
+struct TextureTransform {
+    offset: [f32; 2],
+    rotation: f32,
+    scale: [f32; 2],
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        Self {
+            offset: [0.0; 2],
+            rotation: 0.0,
+            scale: [1.0; 2],
+        }
+    }
+}
+
+impl TextureTransform {
+    // Scales, then rotates, then offsets `tex_coord`
+    fn apply(&self, tex_coord: Vec2) -> Vec2 {
+        let (sin, cos) = self.rotation.sin_cos();
+        let u = tex_coord.x * self.scale[0];
+        let v = tex_coord.y * self.scale[1];
+        Vec2::new(
+            self.offset[0] + cos * u + sin * v,
+            self.offset[1] - sin * u + cos * v,
+        )
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Texture {
+    source: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Image {
+    uri: Option<String>,
+    buffer_view: Option<usize>,
+    mime_type: Option<String>,
+}
@@ -1,17 +1,70 @@
+use anyhow::{anyhow, Result};
+
+pub struct GpuStateDescriptor {
+    // the backends the adapter may be chosen from; the WGPU_BACKEND environment variable,
+    // a comma separated list e.g. "vulkan,gl", overrides this
+    pub backends: wgpu::Backends,
+    pub present_mode: wgpu::PresentMode,
+}
+
+impl Default for GpuStateDescriptor {
+    fn default() -> Self {
+        Self {
+            backends: wgpu::Backends::all(),
+            present_mode: wgpu::PresentMode::Fifo,
+        }
+    }
+}
+
+/// Where the app is in a frame, see GpuState::begin_frame. Per-frame allocations, from the
+/// transient buffer and texture pools and the readback pool, may only be made from Update
+/// onward, and debug builds assert so; commands are recorded in Encode. Uniform writes go
+/// through the queue and aren't bound to a phase, landing with the next submission.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FramePhase {
+    // between frames; transient allocations and readbacks of the last frame are released
+    Idle,
+    // subsystems update, writing uniforms and allocating transient buffers and textures
+    Update,
+    // the frame's passes are recorded into the encoder from begin_encoding
+    Encode,
+}
+
 pub struct GpuState {
     pub surface: wgpu::Surface,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
+    // the formats the surface can be configured with, preferred first, see set_surface_format
+    pub surface_formats: Vec<wgpu::TextureFormat>,
     pub size: winit::dpi::PhysicalSize<u32>,
     pub pipeline_vendor: super::render_pipeline::RenderPipelineVendor,
+    pub transient_buffers: super::transient_buffers::TransientBufferPool,
+    pub transient_textures: super::transient_textures::TransientTexturePool,
+    pub readbacks: super::readback::ReadbackPool,
+    pub texture_streams: super::texture_streaming::TextureStreamer,
+    pub screenshots: super::screenshot::Screenshots,
+    // the WebGPU features the adapter supports which downlevel adapters, e.g. GL, may lack
+    pub downlevel_flags: wgpu::DownlevelFlags,
+    // frames begun, see begin_frame
+    frame_index: u64,
+    frame_phase: FramePhase,
 }
 
 impl GpuState {
-    pub async fn new(window: &winit::window::Window) -> Self {
+    pub async fn new(window: &winit::window::Window, descriptor: &GpuStateDescriptor) -> Self {
         let size = window.inner_size();
 
-        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let backends = match wgpu::util::backend_bits_from_env() {
+            Some(backends) if backends.is_empty() => {
+                eprintln!("WGPU_BACKEND names no known backends, ignoring it");
+                descriptor.backends
+            }
+            Some(backends) => backends,
+            None => descriptor.backends,
+        };
+
+        let instance = wgpu::Instance::new(backends);
         let surface = unsafe { instance.create_surface(window) };
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
@@ -20,7 +73,12 @@ impl GpuState {
                 force_fallback_adapter: false,
             })
             .await
-            .unwrap();
+            .unwrap_or_else(|| panic!("Unable to find an adapter for backends {:?}", backends));
+        let adapter_info = adapter.get_info();
+        println!(
+            "Using adapter \"{}\" ({:?})",
+            adapter_info.name, adapter_info.backend
+        );
 
         let (device, queue) = adapter
             .request_device(
@@ -34,28 +92,95 @@ impl GpuState {
             .await
             .unwrap();
 
+        let surface_formats = surface.get_supported_formats(&adapter);
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: *surface
-                .get_supported_formats(&adapter)
+            format: *surface_formats
                 .first()
                 .expect("Unable to find a surface compatible with the adapter"),
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: descriptor.present_mode,
         };
         surface.configure(&device, &config);
 
+        let transient_buffers = super::transient_buffers::TransientBufferPool::new(&device);
+
         Self {
             surface,
             device,
             queue,
             config,
+            surface_formats,
             size,
             pipeline_vendor: super::render_pipeline::RenderPipelineVendor::default(),
+            transient_buffers,
+            transient_textures: Default::default(),
+            readbacks: Default::default(),
+            texture_streams: Default::default(),
+            screenshots: Default::default(),
+            downlevel_flags: adapter.get_downlevel_capabilities().flags,
+            frame_index: 0,
+            frame_phase: FramePhase::Idle,
         }
     }
 
+    /// The number of frames begun, counting the current one
+    pub fn frame_index(&self) -> u64 {
+        self.frame_index
+    }
+
+    pub fn frame_phase(&self) -> FramePhase {
+        self.frame_phase
+    }
+
+    /// Starts a frame, readying the per-frame pools for allocations, and enters
+    /// FramePhase::Update. Every frame begun must be ended with end_frame, drawn or not.
+    pub fn begin_frame(&mut self) {
+        debug_assert_eq!(
+            self.frame_phase,
+            FramePhase::Idle,
+            "GpuState::begin_frame called before the previous frame ended"
+        );
+        self.frame_index += 1;
+        self.frame_phase = FramePhase::Update;
+        self.transient_buffers.begin_frame(&self.device);
+        self.transient_textures.begin_frame();
+        self.readbacks.begin_frame(&self.device);
+        self.texture_streams.begin_frame(&self.device, &self.queue);
+    }
+
+    /// Enters FramePhase::Encode, returning the encoder the frame's passes are recorded into
+    pub fn begin_encoding(&mut self) -> wgpu::CommandEncoder {
+        debug_assert_eq!(
+            self.frame_phase,
+            FramePhase::Update,
+            "GpuState::begin_encoding called outside a frame's update"
+        );
+        self.frame_phase = FramePhase::Encode;
+        self.device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            })
+    }
+
+    /// Submits the frame's commands, if it recorded any, along with the buffer writes made
+    /// since begin_frame, then releases the frame's transient allocations to be recycled once
+    /// the GPU is done with them, and returns to FramePhase::Idle.
+    pub fn end_frame(&mut self, encoder: Option<wgpu::CommandEncoder>) {
+        debug_assert_ne!(
+            self.frame_phase,
+            FramePhase::Idle,
+            "GpuState::end_frame called without a frame begun"
+        );
+        self.queue.submit(encoder.map(|encoder| encoder.finish()));
+        self.transient_buffers.end_frame(&self.queue);
+        self.transient_textures.end_frame();
+        self.readbacks.end_frame();
+        self.texture_streams.end_frame(&self.queue);
+        self.frame_phase = FramePhase::Idle;
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
@@ -65,6 +190,29 @@ impl GpuState {
         }
     }
 
+    /// Reconfigures the surface with `format`, e.g. a 10 bit format for an HDR10 display, if
+    /// it's one of surface_formats. Pipelines drawing into the surface must be rebuilt for it.
+    /// Must be called outside FramePhase::Encode, while no surface texture is held.
+    pub fn set_surface_format(&mut self, format: wgpu::TextureFormat) -> Result<()> {
+        debug_assert_ne!(
+            self.frame_phase,
+            FramePhase::Encode,
+            "GpuState::set_surface_format called while encoding a frame"
+        );
+        if !self.surface_formats.contains(&format) {
+            return Err(anyhow!(
+                "The surface can't be configured with {:?}, only {:?}",
+                format,
+                self.surface_formats
+            ));
+        }
+        if format != self.config.format {
+            self.config.format = format;
+            self.surface.configure(&self.device, &self.config);
+        }
+        Ok(())
+    }
+
     pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
         self.size
     }
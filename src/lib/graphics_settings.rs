@@ -0,0 +1,119 @@
+use bitflags::bitflags;
+
+use super::camera;
+
+/// Shadow map resolution for every shadow casting light in the scene
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShadowQuality {
+    Low,
+    Medium,
+    // the resolution of shadow::ShadowDescriptor's default
+    #[default]
+    High,
+    Ultra,
+}
+
+impl ShadowQuality {
+    /// Width and height of the square shadow maps, in texels
+    pub fn resolution(&self) -> u32 {
+        match self {
+            ShadowQuality::Low => 512,
+            ShadowQuality::Medium => 1024,
+            ShadowQuality::High => 2048,
+            ShadowQuality::Ultra => 4096,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AntiAliasing {
+    #[default]
+    None,
+    // renders the camera's attachments at twice the render scale, which the compositor's
+    // linear filtering resolves 2x2 texels per pixel; limited by camera::RENDER_SCALE_RANGE
+    Supersample,
+}
+
+impl AntiAliasing {
+    fn render_scale_factor(&self) -> f32 {
+        match self {
+            AntiAliasing::None => 1.0,
+            AntiAliasing::Supersample => 2.0,
+        }
+    }
+}
+
+bitflags! {
+    pub struct PostEffects: u32 {
+        // applied with compositor::Compositor::set_dither, see
+        // compositor::Compositor::apply_graphics_settings
+        const DITHER = 1 << 0;
+        // see scene::Scene::volumetric_fog
+        const VOLUMETRIC_FOG = 1 << 1;
+    }
+}
+
+impl Default for PostEffects {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// How much detail material textures are sampled with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextureQuality {
+    // quarter resolution
+    Low,
+    // half resolution
+    Medium,
+    #[default]
+    High,
+}
+
+impl TextureQuality {
+    /// The number of most detailed mip levels skipped, see model::Material::set_min_texture_lod
+    pub fn min_lod(&self) -> f32 {
+        match self {
+            TextureQuality::Low => 2.0,
+            TextureQuality::Medium => 1.0,
+            TextureQuality::High => 0.0,
+        }
+    }
+}
+
+/// The options a settings menu typically exposes, applied across the scene's subsystems by
+/// scene::Scene::set_graphics_settings. The defaults leave a scene as it was created.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GraphicsSettings {
+    pub shadow_quality: ShadowQuality,
+    pub anti_aliasing: AntiAliasing,
+    pub post_effects: PostEffects,
+    // draws the scene's rain or snow, see scene::Scene::weather
+    pub weather: bool,
+    pub texture_quality: TextureQuality,
+    // the camera's attachment size relative to the window before anti-aliasing, see
+    // camera::Camera::set_render_scale
+    pub render_scale: f32,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            shadow_quality: ShadowQuality::default(),
+            anti_aliasing: AntiAliasing::default(),
+            post_effects: PostEffects::default(),
+            weather: true,
+            texture_quality: TextureQuality::default(),
+            render_scale: 1.0,
+        }
+    }
+}
+
+impl GraphicsSettings {
+    /// The render scale the camera is given, combining render_scale and anti_aliasing
+    pub fn camera_render_scale(&self) -> f32 {
+        let range = camera::RENDER_SCALE_RANGE;
+        (self.render_scale * self.anti_aliasing.render_scale_factor())
+            .clamp(*range.start(), *range.end())
+    }
+}
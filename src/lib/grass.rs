@@ -0,0 +1,503 @@
+use cgmath::prelude::*;
+use wgpu::{util::DeviceExt, vertex_attr_array};
+
+use super::{
+    camera, environment,
+    gpu_state::GpuState,
+    light, model,
+    render_pipeline::{self, DepthMode, RenderPipelineVendor},
+    resources, texture,
+    util::*,
+};
+
+// blades are built from triangle strips; near blades bend smoothly, far blades are a single triangle
+const NEAR_SEGMENTS: u32 = 4;
+const FAR_SEGMENTS: u32 = 1;
+
+// must match the workgroup size of cs_main_grass in grass_cull.wgsl
+const WORKGROUP_SIZE: u32 = 8;
+
+fn pipeline_id(pass: render_pipeline::Pass, depth_mode: DepthMode) -> String {
+    format!("grass_{:?}_{:?}", pass, depth_mode)
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct GrassBlade {
+    // xyz: root position, w: yaw
+    position: Vec4,
+    // x: height, y: width, z: lean, w: segments
+    params: Vec4,
+}
+
+unsafe impl bytemuck::Pod for GrassBlade {}
+unsafe impl bytemuck::Zeroable for GrassBlade {}
+
+const GRASS_BLADE_ATTRIBS: [wgpu::VertexAttribute; 2] =
+    vertex_attr_array![0 => Float32x4, 1 => Float32x4];
+
+impl GrassBlade {
+    fn vertex_buffer_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &GRASS_BLADE_ATTRIBS,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct GrassUniformData {
+    view_proj: Mat4,
+    camera_position: Vec4,
+    // x, y: world cell of the first candidate blade, z: spacing, w: candidates along each side
+    grid: Vec4,
+    // x: height, y: width, z: lod distance, w: max distance
+    blade: Vec4,
+    // x: world size, y: height scale, z: wind response
+    terrain: Vec4,
+    base_color: Vec4,
+    tip_color: Vec4,
+}
+
+unsafe impl bytemuck::Pod for GrassUniformData {}
+unsafe impl bytemuck::Zeroable for GrassUniformData {}
+
+pub struct GrassDescriptor {
+    // world units spanned by the density map (and heightmap), centered on the world origin
+    pub world_size: f32,
+    // world units between candidate blades; the density map decides which are planted
+    pub spacing: f32,
+    // blades are planted within this distance of the camera
+    pub max_distance: f32,
+    // beyond this distance blades are single triangles, thinning out toward max_distance
+    pub lod_distance: f32,
+    pub blade_height: f32,
+    pub blade_width: f32,
+    pub base_color: Vec3,
+    pub tip_color: Vec3,
+    // how far blade tips bend per unit of wind velocity, relative to blade height
+    pub wind_response: f32,
+}
+
+impl Default for GrassDescriptor {
+    fn default() -> Self {
+        Self {
+            world_size: 64.0,
+            spacing: 0.15,
+            max_distance: 24.0,
+            lod_distance: 8.0,
+            blade_height: 0.4,
+            blade_width: 0.04,
+            base_color: Vec3::new(0.05, 0.15, 0.02),
+            tip_color: Vec3::new(0.35, 0.55, 0.15),
+            wind_response: 0.15,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Grass blades planted on the GPU. Each frame a compute pass visits a grid of candidate blades
+// around the camera, plants those the density map calls for (on the heightmap's surface, if
+// any), culls them against the camera's frustum, and appends survivors to a near or far
+// instance list by distance. The pass also fills in the instance counts of each list's
+// indirect draw arguments, so the CPU never learns how many blades were drawn.
+//
+// Blades are drawn in the scene's ambient and lit passes, bent by the scene's wind. They
+// don't cast or receive shadows.
+pub struct Grass {
+    descriptor: GrassDescriptor,
+    height_scale: f32,
+    // candidates along each side of the grid; also the capacity of each instance list
+    grid_side: u32,
+    uniform_data: GrassUniformData,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    blades_buffer: wgpu::Buffer,
+    draw_args_buffer: wgpu::Buffer,
+    cull_bind_group: wgpu::BindGroup,
+    cull_pipeline: wgpu::ComputePipeline,
+    // textures are held for the lifetime of the bind group reading them
+    _density_map: texture::Texture,
+    _heightmap: Option<model::Heightmap>,
+}
+
+impl Grass {
+    /// `density_map`'s red channel is the chance a candidate blade is planted. If provided,
+    /// `heightmap` should match the terrain's, e.g. a clipmap's material heightmap.
+    pub fn new(
+        gpu_state: &GpuState,
+        descriptor: GrassDescriptor,
+        density_map: texture::Texture,
+        heightmap: Option<model::Heightmap>,
+    ) -> Self {
+        let device = &gpu_state.device;
+        let grid_side = (2.0 * descriptor.max_distance / descriptor.spacing).ceil() as u32;
+        let capacity = (grid_side * grid_side) as wgpu::BufferAddress;
+
+        let uniform_data = GrassUniformData {
+            view_proj: Mat4::identity(),
+            camera_position: Vec4::zero(),
+            grid: Vec4::zero(),
+            blade: Vec4::zero(),
+            terrain: Vec4::zero(),
+            base_color: Vec4::zero(),
+            tip_color: Vec4::zero(),
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grass::uniform_buffer"),
+            contents: bytemuck::cast_slice(&[uniform_data]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &Self::uniform_bind_group_layout(device),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("Grass Uniform Bind Group"),
+        });
+
+        // the near list is followed by the far list, each with room for every candidate
+        let blades_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Grass::blades_buffer"),
+            size: 2 * capacity * std::mem::size_of::<GrassBlade>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let draw_args_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grass::draw_args_buffer"),
+            contents: bytemuck::cast_slice(&Self::cleared_draw_args()),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // without a heightmap the density map stands in, its heights scaled to zero
+        let height_texture = heightmap
+            .as_ref()
+            .map(|heightmap| &heightmap.texture)
+            .unwrap_or(&density_map);
+        let cull_bind_group_layout = Self::cull_bind_group_layout(device);
+        let cull_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &cull_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&density_map.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&density_map.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&height_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&height_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: blades_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: draw_args_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Grass Cull Bind Group"),
+        });
+
+        let cull_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("grass_cull"),
+            bind_group_layouts: &[
+                &Self::uniform_bind_group_layout(device),
+                &cull_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let cull_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shaders/grass_cull.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                resources::load_string_sync("shaders/grass_cull.wgsl")
+                    .unwrap()
+                    .into(),
+            ),
+        });
+        let cull_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("ComputePipeline: grass_cull"),
+            layout: Some(&cull_pipeline_layout),
+            module: &cull_shader,
+            entry_point: "cs_main_grass",
+        });
+
+        Self {
+            height_scale: heightmap
+                .as_ref()
+                .map_or(0.0, |heightmap| heightmap.height_scale),
+            descriptor,
+            grid_side,
+            uniform_data,
+            uniform_buffer,
+            uniform_bind_group,
+            blades_buffer,
+            draw_args_buffer,
+            cull_bind_group,
+            cull_pipeline,
+            _density_map: density_map,
+            _heightmap: heightmap,
+        }
+    }
+
+    pub fn descriptor(&self) -> &GrassDescriptor {
+        &self.descriptor
+    }
+
+    // DrawIndirect arguments of the near and far lists with no instances; vertex_count,
+    // instance_count, base_vertex, base_instance
+    fn cleared_draw_args() -> [u32; 8] {
+        [
+            2 * NEAR_SEGMENTS + 1,
+            0,
+            0,
+            0,
+            2 * FAR_SEGMENTS + 1,
+            0,
+            0,
+            0,
+        ]
+    }
+
+    fn list_capacity(&self) -> wgpu::BufferAddress {
+        (self.grid_side * self.grid_side) as wgpu::BufferAddress
+    }
+
+    pub fn prepare_pipelines(gpu_state: &mut GpuState, depth_mode: DepthMode) {
+        for pass in [render_pipeline::Pass::Ambient, render_pipeline::Pass::Lit] {
+            let pipeline_id = pipeline_id(pass, depth_mode);
+            if gpu_state.pipeline_vendor.has_pipeline(&pipeline_id) {
+                continue;
+            }
+
+            let layout = gpu_state
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some(&pipeline_id),
+                    bind_group_layouts: &[
+                        &Self::uniform_bind_group_layout(&gpu_state.device),
+                        &camera::Camera::bind_group_layout(&gpu_state.device),
+                        &light::Light::bind_group_layout(&gpu_state.device),
+                        &environment::Environment::bind_group_layout(
+                            &gpu_state.device,
+                            environment::Environment::supports_reflection_probes(gpu_state),
+                        ),
+                    ],
+                    push_constant_ranges: &[],
+                });
+
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("shaders/grass.wgsl"),
+                source: wgpu::ShaderSource::Wgsl(
+                    resources::load_string_sync("shaders/grass.wgsl")
+                        .unwrap()
+                        .into(),
+                ),
+            };
+
+            gpu_state.pipeline_vendor.create_render_pipeline(
+                &pipeline_id,
+                &gpu_state.device,
+                render_pipeline::Properties {
+                    vs_main: "vs_main_grass",
+                    fs_main: Some(match pass {
+                        render_pipeline::Pass::Ambient => "fs_main_grass_ambient",
+                        _ => "fs_main_grass_lit",
+                    }),
+                    layout: &layout,
+                    color_format: texture::Texture::COLOR_FORMAT,
+                    depth_format: Some(texture::Texture::DEPTH_FORMAT),
+                    depth_bias: wgpu::DepthBiasState::default(),
+                    depth_mode,
+                    vertex_layouts: &[GrassBlade::vertex_buffer_layout()],
+                    topology: wgpu::PrimitiveTopology::TriangleStrip,
+                    shader,
+                    // blades are seen from both sides
+                    cull_mode: None,
+                    blend: None,
+                    pass,
+                },
+            );
+        }
+    }
+
+    /// Centers the candidate grid on the camera. `lod_bias` scales the descriptor's
+    /// lod_distance, and `max_distance` further limits its max_distance, see
+    /// scene::Scene::lod_bias and max_instance_distance.
+    pub fn update(
+        &mut self,
+        gpu_state: &GpuState,
+        camera: &camera::Camera,
+        lod_bias: f32,
+        max_distance: Option<f32>,
+    ) {
+        let descriptor = &self.descriptor;
+        let position = camera.position();
+        let spacing = descriptor.spacing;
+        let half_side = (self.grid_side / 2) as f32;
+
+        // candidates are placed by world cell, so blades stay put as the camera moves
+        let data = &mut self.uniform_data;
+        data.view_proj = camera.projection_matrix() * camera.view_matrix();
+        data.camera_position = position.to_homogeneous();
+        data.grid = Vec4::new(
+            (position.x / spacing).floor() - half_side,
+            (position.z / spacing).floor() - half_side,
+            spacing,
+            self.grid_side as f32,
+        );
+        let max_distance = max_distance.map_or(descriptor.max_distance, |max_distance| {
+            descriptor.max_distance.min(max_distance)
+        });
+        data.blade = Vec4::new(
+            descriptor.blade_height,
+            descriptor.blade_width,
+            // blades thin out between the two distances, which mustn't meet
+            (descriptor.lod_distance * lod_bias).min(max_distance * 0.99),
+            max_distance,
+        );
+        data.terrain = Vec4::new(
+            descriptor.world_size,
+            self.height_scale,
+            descriptor.wind_response,
+            0.0,
+        );
+        data.base_color = descriptor.base_color.extend(1.0);
+        data.tip_color = descriptor.tip_color.extend(1.0);
+
+        gpu_state
+            .queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[*data]));
+        gpu_state.queue.write_buffer(
+            &self.draw_args_buffer,
+            0,
+            bytemuck::cast_slice(&Self::cleared_draw_args()),
+        );
+    }
+
+    /// Plants and culls this frame's blades; must be encoded before the passes drawing them.
+    pub fn cull(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Grass Cull Pass"),
+        });
+        compute_pass.set_pipeline(&self.cull_pipeline);
+        compute_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.cull_bind_group, &[]);
+        let workgroups = self.grid_side.div_ceil(WORKGROUP_SIZE);
+        compute_pass.dispatch_workgroups(workgroups, workgroups, 1);
+    }
+
+    /// Draws the near and far blade lists for an ambient or lit pass; `light` is the scene's
+    /// ambient light for the ambient pass.
+    pub fn draw<'a, 'b>(
+        &'a self,
+        render_pass: &'b mut wgpu::RenderPass<'a>,
+        pipeline_vendor: &'a RenderPipelineVendor,
+        camera: &'a camera::Camera,
+        light: &'a light::Light,
+        environment: &'a environment::Environment,
+        pass: render_pipeline::Pass,
+    ) where
+        'a: 'b, // 'a lifetime at least as long as 'b
+    {
+        let pipeline_id = pipeline_id(pass, camera.depth_mode());
+        if let Some(pipeline) = pipeline_vendor.get_pipeline(&pipeline_id) {
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+            render_pass.set_bind_group(1, camera.bind_group(), &[]);
+            render_pass.set_bind_group(2, light.bind_group(), &[]);
+            render_pass.set_bind_group(3, environment.bind_group(), &[]);
+
+            let list_size =
+                self.list_capacity() * std::mem::size_of::<GrassBlade>() as wgpu::BufferAddress;
+            let draw_args_size = std::mem::size_of::<wgpu::util::DrawIndirect>();
+            for list in 0..2 {
+                let offset = list * list_size;
+                render_pass
+                    .set_vertex_buffer(0, self.blades_buffer.slice(offset..offset + list_size));
+                render_pass.draw_indirect(
+                    &self.draw_args_buffer,
+                    list * draw_args_size as wgpu::BufferAddress,
+                );
+            }
+        } else {
+            eprintln!("No pipeline available to render grass id: {}", pipeline_id);
+        }
+    }
+
+    fn uniform_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE
+                    | wgpu::ShaderStages::VERTEX
+                    | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Grass Uniform Bind Group Layout"),
+        })
+    }
+
+    fn cull_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let storage = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                // density map
+                texture(0),
+                sampler(1),
+                // heightmap
+                texture(2),
+                sampler(3),
+                // near then far blade lists
+                storage(4),
+                // near then far draw arguments
+                storage(5),
+            ],
+            label: Some("Grass Cull Bind Group Layout"),
+        })
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{
+        DeviceEvent, DeviceId, ElementState, KeyboardInput, ModifiersState, MouseButton,
+        MouseScrollDelta, TouchPhase, WindowEvent,
+    },
+};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+pub enum InputRecording {
+    // writes every input event and frame time step to the file
+    Record(PathBuf),
+    // reads back a file written by Record, substituting its events and time steps for live ones
+    Replay(PathBuf),
+}
+
+/// The winit events which affect what a session draws: window input, the window's size, focus
+/// and cursor, and mouse motion, the only device event the app forwards to the scene and
+/// compositor. Other device events (raw keys, buttons and axes), touch, IME, file drops and the
+/// like are neither used by the app nor recorded. A scale factor change is recorded as the
+/// resize it causes, as that's all the app takes from it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RecordedEvent {
+    Keyboard(KeyboardInput),
+    ModifiersChanged(ModifiersState),
+    MouseWheel {
+        delta: MouseScrollDelta,
+        phase: TouchPhase,
+    },
+    MouseButton {
+        button: MouseButton,
+        state: ElementState,
+    },
+    MouseMotion {
+        delta: (f64, f64),
+    },
+    CursorMoved {
+        position: PhysicalPosition<f64>,
+    },
+    CursorEntered,
+    CursorLeft,
+    Focused(bool),
+    // the window's new inner size; replayed by resizing the window rather than as an event
+    Resized(PhysicalSize<u32>),
+}
+
+impl RecordedEvent {
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        match event {
+            WindowEvent::KeyboardInput { input, .. } => Some(Self::Keyboard(*input)),
+            WindowEvent::ModifiersChanged(state) => Some(Self::ModifiersChanged(*state)),
+            WindowEvent::MouseWheel { delta, phase, .. } => Some(Self::MouseWheel {
+                delta: *delta,
+                phase: *phase,
+            }),
+            WindowEvent::MouseInput { button, state, .. } => Some(Self::MouseButton {
+                button: *button,
+                state: *state,
+            }),
+            WindowEvent::CursorMoved { position, .. } => Some(Self::CursorMoved {
+                position: *position,
+            }),
+            WindowEvent::CursorEntered { .. } => Some(Self::CursorEntered),
+            WindowEvent::CursorLeft { .. } => Some(Self::CursorLeft),
+            WindowEvent::Focused(focused) => Some(Self::Focused(*focused)),
+            WindowEvent::Resized(size) => Some(Self::Resized(*size)),
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                Some(Self::Resized(**new_inner_size))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn from_device_event(event: &DeviceEvent) -> Option<Self> {
+        match event {
+            DeviceEvent::MouseMotion { delta } => Some(Self::MouseMotion { delta: *delta }),
+            _ => None,
+        }
+    }
+
+    /// Reconstructs the window event this was recorded from, or None for device events and
+    /// resizes, see resized.
+    #[allow(deprecated)] // the modifiers fields must still be populated
+    pub fn to_window_event(&self) -> Option<WindowEvent<'static>> {
+        // SAFETY: the dummy id is only compared against other ids, nothing in the app does so
+        let device_id = unsafe { DeviceId::dummy() };
+        match self {
+            Self::Keyboard(input) => Some(WindowEvent::KeyboardInput {
+                device_id,
+                input: *input,
+                is_synthetic: false,
+            }),
+            Self::ModifiersChanged(state) => Some(WindowEvent::ModifiersChanged(*state)),
+            Self::MouseWheel { delta, phase } => Some(WindowEvent::MouseWheel {
+                device_id,
+                delta: *delta,
+                phase: *phase,
+                modifiers: Default::default(),
+            }),
+            Self::MouseButton { button, state } => Some(WindowEvent::MouseInput {
+                device_id,
+                state: *state,
+                button: *button,
+                modifiers: Default::default(),
+            }),
+            Self::CursorMoved { position } => Some(WindowEvent::CursorMoved {
+                device_id,
+                position: *position,
+                modifiers: Default::default(),
+            }),
+            Self::CursorEntered => Some(WindowEvent::CursorEntered { device_id }),
+            Self::CursorLeft => Some(WindowEvent::CursorLeft { device_id }),
+            Self::Focused(focused) => Some(WindowEvent::Focused(*focused)),
+            Self::MouseMotion { .. } | Self::Resized(_) => None,
+        }
+    }
+
+    /// The size a replay should resize the window to. The window's own resize event then
+    /// resizes the surface, so it always matches the window.
+    pub fn resized(&self) -> Option<PhysicalSize<u32>> {
+        match self {
+            Self::Resized(size) => Some(*size),
+            _ => None,
+        }
+    }
+
+    pub fn mouse_motion(&self) -> Option<(f64, f64)> {
+        match self {
+            Self::MouseMotion { delta } => Some(*delta),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimestampedEvent {
+    // seconds since recording began; informational, replay is driven by frame boundaries
+    pub time: f64,
+    pub event: RecordedEvent,
+}
+
+/// The events received before a frame was updated, and the time step it was updated with.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub dt: f64,
+    pub events: Vec<TimestampedEvent>,
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Recordings are stored as one json RecordedFrame per line, so that a truncated recording (e.g.
+// from a crash, the most interesting kind for a bug report) still replays up to the crash.
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+    start: instant::Instant,
+    pending: RecordedFrame,
+}
+
+impl InputRecorder {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("Unable to create input recording {:?}", path))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            start: instant::Instant::now(),
+            pending: RecordedFrame::default(),
+        })
+    }
+
+    pub fn record(&mut self, event: RecordedEvent) {
+        self.pending.events.push(TimestampedEvent {
+            time: self.start.elapsed().as_secs_f64(),
+            event,
+        });
+    }
+
+    // Writes the events recorded since the previous frame along with this frame's time step
+    pub fn end_frame(&mut self, dt: instant::Duration) -> Result<()> {
+        let mut frame = std::mem::take(&mut self.pending);
+        frame.dt = dt.as_secs_f64();
+        serde_json::to_writer(&mut self.writer, &frame)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub struct InputPlayer {
+    frames: VecDeque<RecordedFrame>,
+}
+
+impl InputPlayer {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Unable to open input recording {:?}", path))?;
+
+        let mut frames = VecDeque::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            frames.push_back(
+                serde_json::from_str(&line)
+                    .with_context(|| format!("{:?} line {}", path, index + 1))?,
+            );
+        }
+
+        Ok(Self { frames })
+    }
+
+    pub fn next_frame(&mut self) -> Option<RecordedFrame> {
+        self.frames.pop_front()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
@@ -0,0 +1,337 @@
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use super::{gpu_state::GpuState, model, resources, util::*};
+
+// must match the workgroup size of cs_main_instance_animation in instance_animation.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+
+// must match the MOTION_* constants in instance_animation.wgsl
+const MOTION_NONE: f32 = 0.0;
+const MOTION_ORBIT: f32 = 1.0;
+const MOTION_OSCILLATE: f32 = 2.0;
+const MOTION_FLOW: f32 = 3.0;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct AnimationData {
+    // xyz: the instance's position, w: its scale
+    position: Vec4,
+    // the instance's rotation, as a quaternion (x, y, z, w)
+    rotation: [f32; 4],
+    // orbit: xyz center, w angular velocity
+    // oscillate: xyz direction scaled by amplitude, w frequency
+    // flow: x speed
+    params: Vec4,
+    // orbit: xyz axis; oscillate: x phase; w: the motion, one of MOTION_*
+    extra: Vec4,
+}
+
+unsafe impl bytemuck::Pod for AnimationData {}
+unsafe impl bytemuck::Zeroable for AnimationData {}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct AnimationUniformData {
+    // x: time in seconds, y: frame delta in seconds, z: instance count
+    time: Vec4,
+    // xyz: flow bounds min, w: flow field frequency
+    flow_min: Vec4,
+    // xyz: flow bounds max, w: flow field time scale
+    flow_max: Vec4,
+}
+
+unsafe impl bytemuck::Pod for AnimationUniformData {}
+unsafe impl bytemuck::Zeroable for AnimationUniformData {}
+
+/// How an instance moves, relative to the position, rotation and scale it was created with.
+#[derive(Copy, Clone, Debug)]
+pub enum Motion {
+    None,
+    // circles `center` about `axis`, turning to keep the same side toward it
+    Orbit {
+        center: Point3,
+        axis: Vec3,
+        // radians per second
+        angular_velocity: f32,
+    },
+    // bobs back and forth along `direction`, e.g. floating debris
+    Oscillate {
+        direction: Vec3,
+        amplitude: f32,
+        // cycles per second
+        frequency: f32,
+        // radians, so neighbors needn't move in lockstep
+        phase: f32,
+    },
+    // drifts through the animator's flow field, facing along its +z as it goes, e.g. fish
+    // or birds; instances leaving the field's bounds wrap around to the opposite side
+    Flow {
+        speed: f32,
+    },
+}
+
+pub struct FlowField {
+    pub min: Point3,
+    pub max: Point3,
+    // spatial frequency of the field's swirls; higher is tighter
+    pub frequency: f32,
+    // how quickly the field's swirls change
+    pub time_scale: f32,
+}
+
+impl Default for FlowField {
+    fn default() -> Self {
+        Self {
+            min: Point3::new(-50.0, 0.0, -50.0),
+            max: Point3::new(50.0, 20.0, 50.0),
+            frequency: 0.1,
+            time_scale: 0.2,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Animates a model's instances on the GPU: each frame a compute pass writes every instance's
+// transform straight into the model's instance buffer, computed from the instance as the
+// model was created and its Motion. This moves tens of thousands of instances without
+// Model::update_instances, whose writes (and Model::update's initial upload) are overwritten
+// by the next pass. Orbits and oscillations are functions of time; flowing instances carry
+// their positions from frame to frame in a buffer of their own.
+pub struct InstanceAnimator {
+    model_id: usize,
+    instance_count: u32,
+    time: instant::Duration,
+    uniform_data: AnimationUniformData,
+    uniform_buffer: wgpu::Buffer,
+    bind_groups: Vec<wgpu::BindGroup>,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl InstanceAnimator {
+    /// `motions` are applied to `model`'s instances in order. `model_id` is the model's key in
+    /// Scene::models.
+    pub fn new(
+        gpu_state: &GpuState,
+        model_id: usize,
+        model: &model::Model,
+        motions: &[Motion],
+        flow_field: FlowField,
+    ) -> Self {
+        let device = &gpu_state.device;
+        let instances = model.instances();
+        let instance_count = instances.len().min(motions.len()) as u32;
+        if instances.len() != motions.len() {
+            eprintln!(
+                "InstanceAnimator given {} motions for a model with {} instances",
+                motions.len(),
+                instances.len()
+            );
+        }
+
+        let animations = instances
+            .iter()
+            .zip(motions.iter())
+            .take(instance_count as usize)
+            .map(|(instance, motion)| Self::animation_data(instance, motion))
+            .collect::<Vec<_>>();
+        // flowing instances start where they were placed; storage buffers can't be empty
+        let flow_positions = if animations.is_empty() {
+            vec![[0.0; 4]]
+        } else {
+            animations
+                .iter()
+                .map(|animation| animation.position.into())
+                .collect::<Vec<[f32; 4]>>()
+        };
+        let animations = if animations.is_empty() {
+            vec![Self::animation_data(
+                &model::Instance::new(Point3::origin(), Quat::one()),
+                &Motion::None,
+            )]
+        } else {
+            animations
+        };
+
+        let uniform_data = AnimationUniformData {
+            time: Vec4::new(0.0, 0.0, instance_count as f32, 0.0),
+            flow_min: flow_field.min.to_vec().extend(flow_field.frequency),
+            flow_max: flow_field.max.to_vec().extend(flow_field.time_scale),
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("InstanceAnimator::uniform_buffer"),
+            contents: bytemuck::cast_slice(&[uniform_data]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let animations_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("InstanceAnimator::animations_buffer"),
+            contents: bytemuck::cast_slice(&animations),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let flow_positions_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("InstanceAnimator::flow_positions_buffer"),
+            contents: bytemuck::cast_slice(&flow_positions),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let bind_group_layout = Self::bind_group_layout(device);
+        // one per instance buffer of the model's ring, see model::Model::instance_slot
+        let bind_groups = model
+            .instance_buffers()
+            .iter()
+            .map(|instance_buffer| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: uniform_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: animations_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: flow_positions_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: instance_buffer.as_entire_binding(),
+                        },
+                    ],
+                    label: Some("Instance Animation Bind Group"),
+                })
+            })
+            .collect();
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("instance_animation"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shaders/instance_animation.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                resources::load_string_sync("shaders/instance_animation.wgsl")
+                    .unwrap()
+                    .into(),
+            ),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("ComputePipeline: instance_animation"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main_instance_animation",
+        });
+
+        Self {
+            model_id,
+            instance_count,
+            time: instant::Duration::ZERO,
+            uniform_data,
+            uniform_buffer,
+            bind_groups,
+            pipeline,
+        }
+    }
+
+    fn animation_data(instance: &model::Instance, motion: &Motion) -> AnimationData {
+        let rotation = instance.rotation();
+        let (params, extra) = match *motion {
+            Motion::None => (Vec4::zero(), Vec4::new(0.0, 0.0, 0.0, MOTION_NONE)),
+            Motion::Orbit {
+                center,
+                axis,
+                angular_velocity,
+            } => (
+                center.to_vec().extend(angular_velocity),
+                axis.normalize().extend(MOTION_ORBIT),
+            ),
+            Motion::Oscillate {
+                direction,
+                amplitude,
+                frequency,
+                phase,
+            } => (
+                (direction.normalize() * amplitude).extend(frequency),
+                Vec4::new(phase, 0.0, 0.0, MOTION_OSCILLATE),
+            ),
+            Motion::Flow { speed } => (
+                Vec4::new(speed, 0.0, 0.0, 0.0),
+                Vec4::new(0.0, 0.0, 0.0, MOTION_FLOW),
+            ),
+        };
+        AnimationData {
+            position: instance.position().to_vec().extend(instance.scale()),
+            rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+            params,
+            extra,
+        }
+    }
+
+    pub fn model_id(&self) -> usize {
+        self.model_id
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: instant::Duration) {
+        self.time += dt;
+        self.uniform_data.time.x = self.time.as_secs_f32();
+        self.uniform_data.time.y = dt.as_secs_f32();
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform_data]),
+        );
+    }
+
+    /// Writes this frame's instance transforms to the model's current instance buffer; must be
+    /// encoded before the passes drawing the model, including shadow passes.
+    pub fn animate(&self, encoder: &mut wgpu::CommandEncoder, model: &model::Model) {
+        if self.instance_count == 0 {
+            return;
+        }
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Instance Animation Pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_groups[model.instance_slot()], &[]);
+        compute_pass.dispatch_workgroups(self.instance_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // instances as created, and their motions
+                storage(1, true),
+                // flowing instances' current positions
+                storage(2, false),
+                // the model's instance buffer
+                storage(3, false),
+            ],
+            label: Some("Instance Animation Bind Group Layout"),
+        })
+    }
+}
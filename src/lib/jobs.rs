@@ -0,0 +1,137 @@
+use rayon::prelude::*;
+
+// Items handed to a worker at a time; smaller batches cost more in scheduling than they save
+const MIN_BATCH_SIZE: usize = 64;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Runs a frame's CPU-heavy work (culling, particle simulation, animation) across a pool of
+// worker threads, splitting slices into batches the workers steal from one another. Each call
+// returns once all of its work is done, so work borrows from the caller like a plain loop
+// would. Small slices run on the calling thread, as do all jobs of a serial system.
+pub struct JobSystem {
+    // None runs jobs on the calling thread
+    pool: Option<rayon::ThreadPool>,
+}
+
+impl Default for JobSystem {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl JobSystem {
+    /// A job system with `worker_count` workers, or one per core if 0. A single worker runs
+    /// jobs serially on the calling thread, e.g. to profile or debug them.
+    pub fn new(worker_count: usize) -> Self {
+        let worker_count = if worker_count == 0 {
+            std::thread::available_parallelism().map_or(1, |count| count.get())
+        } else {
+            worker_count
+        };
+        if worker_count == 1 {
+            return Self::serial();
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(worker_count)
+            .thread_name(|index| format!("Job Worker {}", index))
+            .build();
+        match pool {
+            Ok(pool) => Self { pool: Some(pool) },
+            Err(e) => {
+                eprintln!(
+                    "Unable to start {} job workers, running jobs serially: {}",
+                    worker_count, e
+                );
+                Self::serial()
+            }
+        }
+    }
+
+    /// A job system running every job on the calling thread
+    pub fn serial() -> Self {
+        Self { pool: None }
+    }
+
+    /// The number of threads jobs run on
+    pub fn worker_count(&self) -> usize {
+        self.pool
+            .as_ref()
+            .map_or(1, |pool| pool.current_num_threads())
+    }
+
+    /// Calls `job` with each of `items`, across the workers
+    pub fn for_each_mut<T, F>(&self, items: &mut [T], job: F)
+    where
+        T: Send,
+        F: Fn(&mut T) + Send + Sync,
+    {
+        match self.pool_for(items.len()) {
+            Some(pool) => pool.install(|| {
+                items
+                    .par_iter_mut()
+                    .with_min_len(MIN_BATCH_SIZE)
+                    .for_each(job)
+            }),
+            None => items.iter_mut().for_each(job),
+        }
+    }
+
+    /// The results of calling `job` with each of `items`, across the workers, in the items'
+    /// order
+    pub fn map<T, R, F>(&self, items: &[T], job: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Send + Sync,
+    {
+        match self.pool_for(items.len()) {
+            Some(pool) => pool.install(|| {
+                items
+                    .par_iter()
+                    .with_min_len(MIN_BATCH_SIZE)
+                    .map(job)
+                    .collect()
+            }),
+            None => items.iter().map(job).collect(),
+        }
+    }
+
+    /// As map, with mutable access to each of `items`
+    pub fn map_mut<T, R, F>(&self, items: &mut [T], job: F) -> Vec<R>
+    where
+        T: Send,
+        R: Send,
+        F: Fn(&mut T) -> R + Send + Sync,
+    {
+        match self.pool_for(items.len()) {
+            Some(pool) => pool.install(|| {
+                items
+                    .par_iter_mut()
+                    .with_min_len(MIN_BATCH_SIZE)
+                    .map(job)
+                    .collect()
+            }),
+            None => items.iter_mut().map(job).collect(),
+        }
+    }
+
+    /// Runs `a` and `b`, potentially in parallel, returning both results
+    pub fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        match &self.pool {
+            Some(pool) => pool.join(a, b),
+            None => (a(), b()),
+        }
+    }
+
+    // The pool to run a job over `len` items on, None if they'd be done sooner serially
+    fn pool_for(&self, len: usize) -> Option<&rayon::ThreadPool> {
+        self.pool.as_ref().filter(|_| len > MIN_BATCH_SIZE)
+    }
+}
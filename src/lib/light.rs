@@ -1,11 +1,34 @@
-use super::util::*;
+use std::rc::Rc;
+
+use super::{
+    camera,
+    gpu_state::GpuState,
+    shadow::{self, ShadowBias, ShadowDescriptor},
+    shadow_atlas, texture,
+    util::*,
+};
+use anyhow::{anyhow, Result};
 use cgmath::prelude::*;
 
 const EPSILON: f32 = 1e-4;
 
+// The breadth in degrees of lights converted to spot lights without one, see
+// Light::set_light_type
+const DEFAULT_SPOT_BREADTH: f32 = 45.0;
+
+// A light's derived radius is where its contribution falls below this, roughly one step of an
+// 8 bit channel
+const RADIUS_CUTOFF: f32 = 1.0 / 256.0;
+
+// A demoted light is only promoted again once its projected size clears the threshold it fell
+// below by this factor, so lights near a threshold don't flicker between levels
+const LOD_HYSTERESIS: f32 = 1.2;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 struct LightUniformData {
+    // must lead, see depth_pass
+    shadow_view_proj: Mat4,
     position: Point3,
     _padding1: u32, // uniforms require 16-byte (4 float field spacing)
     direction: Vec3,
@@ -17,7 +40,19 @@ struct LightUniformData {
     // x: constant, y: linear, z: exponential, w: dot spot breadth
     attenuation: Vec4,
     light_type: i32,
-    _padding5: [u32; 3],
+    // distance beyond which the light has no effect, 0 for unbounded
+    radius: f32,
+    // the flat attenuation of lights demoted to LightDetail::Simple, 0 for full attenuation
+    simple_attenuation: f32,
+    _padding5: u32,
+    // x: normal offset bias, y: shadow map texel size, z: light bleeding reduction,
+    // w: shadow mode (0: none, 1: pcf, 2: variance, 3: exponential variance)
+    shadow: Vec4,
+    // the shadow map's region of the texture it's sampled from, see shadow::ShadowMap::uv_rect
+    shadow_rect: Vec4,
+    // x: gobo mode (0: none, 1: projected from a spot light, 2: cubemap around a point light),
+    // y: gobo rotation in radians
+    gobo: Vec4,
 }
 
 unsafe impl bytemuck::Pod for LightUniformData {}
@@ -32,11 +67,17 @@ impl Default for LightUniformData {
             color: Vec3::zero(),
             attenuation: Vec4::zero(),
             light_type: 0,
+            radius: 0.0,
+            simple_attenuation: 0.0,
+            shadow_view_proj: Mat4::identity(),
+            shadow: Vec4::zero(),
+            shadow_rect: Vec4::new(0.0, 0.0, 1.0, 1.0),
+            gobo: Vec4::zero(),
             _padding1: 0,
             _padding2: 0,
             _padding3: 0,
             _padding4: 0,
-            _padding5: [0; 3],
+            _padding5: 0,
         }
     }
 }
@@ -75,6 +116,22 @@ impl LightUniformData {
         self.light_type = light_type.into();
         self
     }
+
+    fn set_shadow(&mut self, shadow_map: Option<&shadow::ShadowMap>) -> &mut Self {
+        self.shadow = match shadow_map {
+            Some(shadow_map) => Vec4::new(
+                shadow_map.bias().normal_offset,
+                1.0 / shadow_map.resolution() as f32,
+                shadow_map.light_bleeding_reduction(),
+                shadow_map.mode().value(),
+            ),
+            None => Vec4::zero(),
+        };
+        self.shadow_rect = shadow_map
+            .map(|shadow_map| shadow_map.uv_rect())
+            .unwrap_or_else(|| Vec4::new(0.0, 0.0, 1.0, 1.0));
+        self
+    }
 }
 
 type LightUniform = UniformWrapper<LightUniformData>;
@@ -100,14 +157,68 @@ impl LightType {
 
 impl From<LightType> for i32 {
     fn from(light_type: LightType) -> Self {
-        light_type.value() as i32
+        light_type.value()
     }
 }
 
+/// How a point or spot light is demoted as it shrinks on screen, see Light::set_lod. Each
+/// threshold is a projected size, the light's radius over the half height of the view at its
+/// distance; the light drops to the level when its size falls below.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightLod {
+    // below this the light casts no shadows
+    pub unshadowed: f32,
+    // below this the light is also shaded with a flat attenuation, its spot cone hard edged
+    // and without its gobo
+    pub simple: f32,
+    // below this the light isn't drawn at all
+    pub culled: f32,
+}
+
+impl Default for LightLod {
+    fn default() -> Self {
+        Self {
+            unshadowed: 0.25,
+            simple: 0.08,
+            culled: 0.02,
+        }
+    }
+}
+
+/// The level a light is evaluated at, chosen each update by its LightLod; each level
+/// includes the savings of those before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LightDetail {
+    Full,
+    Unshadowed,
+    Simple,
+    Culled,
+}
+
 pub struct AmbientLightDescriptor {
     pub ambient: Vec3,
 }
 
+/// Ambient light which varies with the surface normal, blending from `sky` on surfaces facing
+/// `up` to `ground` on those facing away, as light bounced from the sky and the ground would.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hemisphere {
+    pub sky: Vec3,
+    pub ground: Vec3,
+    pub up: Vec3,
+}
+
+impl Hemisphere {
+    /// A hemisphere which lights surfaces with `color` whichever way they face
+    pub fn uniform(color: Vec3) -> Self {
+        Self {
+            sky: color,
+            ground: color,
+            up: Vec3::unit_y(),
+        }
+    }
+}
+
 pub struct PointLightDescriptor {
     pub position: Point3,
     pub ambient: Vec3,
@@ -126,6 +237,7 @@ pub struct SpotLightDescriptor {
     pub linear_attenuation: f32,
     pub exponential_attenuation: f32,
     pub spot_breadth: Deg,
+    pub shadows: Option<ShadowDescriptor>,
 }
 
 pub struct DirectionalLightDescriptor {
@@ -133,11 +245,42 @@ pub struct DirectionalLightDescriptor {
     pub ambient: Vec3,
     pub color: Vec3,
     pub constant_attenuation: f32,
+    // directional shadow volumes are centered on the light's position (see Light::set_position)
+    // unless fit to the camera, see shadow::ShadowFit
+    pub shadows: Option<ShadowDescriptor>,
 }
 
 pub struct Light {
     light_type: LightType,
     uniform: LightUniform,
+    shadow_map: Option<shadow::ShadowMap>,
+    // bound in place of shadow_map's textures for lights which don't cast shadows,
+    // or whose shadow mode doesn't use moments
+    shadow_map_placeholder: Option<texture::Texture>,
+    shadow_moments_placeholder: Option<texture::Texture>,
+    // tints the light, see Light::set_gobo
+    gobo: Option<Rc<texture::Texture>>,
+    // bound in the gobo slots the light's gobo doesn't fill
+    gobo_placeholder: texture::Texture,
+    gobo_cube_placeholder: texture::Texture,
+    // one per uniform buffer, selected by the uniform's slot
+    bind_groups: Vec<wgpu::BindGroup>,
+    // the gobo changed while the shadow map was packed into an atlas, so the bind groups are
+    // rebuilt when the atlas is next at hand, see Light::pack_shadow_map
+    bind_groups_stale: bool,
+    // overrides the radius derived from attenuation, see Light::radius
+    radius: Option<f32>,
+    // None for lights which are never demoted, see Light::set_lod
+    lod: Option<LightLod>,
+    detail: LightDetail,
+}
+
+// The textures bound for a light's gobo: the gobo in the slot matching its dimension, and
+// placeholders in the other
+struct GoboBinding<'a> {
+    texture: &'a texture::Texture,
+    cube_texture: &'a texture::Texture,
+    sampler: &'a wgpu::Sampler,
 }
 
 impl Light {
@@ -146,12 +289,12 @@ impl Light {
         uniform
             .get_mut()
             .set_light_type(LightType::Ambient)
+            // an ambient light's color and direction are its hemisphere's ground and up
             .set_ambient(desc.ambient)
+            .set_color(desc.ambient)
+            .set_direction(Vec3::unit_y())
             .set_attenuation(Vec4::new(1.0, 0.0, 0.0, 0.0));
-        Self {
-            light_type: LightType::Ambient,
-            uniform,
-        }
+        Self::new(device, LightType::Ambient, uniform, None)
     }
 
     pub fn new_point(device: &wgpu::Device, desc: &PointLightDescriptor) -> Self {
@@ -168,10 +311,7 @@ impl Light {
                 desc.exponential_attenuation,
                 0.0,
             ));
-        Self {
-            light_type: LightType::Point,
-            uniform,
-        }
+        Self::new(device, LightType::Point, uniform, None)
     }
 
     pub fn new_spot(device: &wgpu::Device, desc: &SpotLightDescriptor) -> Self {
@@ -189,10 +329,7 @@ impl Light {
                 desc.exponential_attenuation,
                 desc.spot_breadth.cos(),
             ));
-        Self {
-            light_type: LightType::Spot,
-            uniform,
-        }
+        Self::new(device, LightType::Spot, uniform, desc.shadows.as_ref())
     }
 
     pub fn new_directional(device: &wgpu::Device, desc: &DirectionalLightDescriptor) -> Self {
@@ -204,16 +341,244 @@ impl Light {
             .set_ambient(desc.ambient)
             .set_color(desc.color)
             .set_attenuation(Vec4::new(desc.constant_attenuation, 0.0, 0.0, 0.0));
+        Self::new(
+            device,
+            LightType::Directional,
+            uniform,
+            desc.shadows.as_ref(),
+        )
+    }
+
+    fn new(
+        device: &wgpu::Device,
+        light_type: LightType,
+        mut uniform: LightUniform,
+        shadows: Option<&ShadowDescriptor>,
+    ) -> Self {
+        let shadow_map = shadows.map(|desc| shadow::ShadowMap::new(device, desc));
+        let shadow_map_placeholder = if shadow_map.is_none() {
+            Some(shadow::ShadowMap::create_placeholder_texture(device))
+        } else {
+            None
+        };
+        let shadow_moments_placeholder = if shadow_map
+            .as_ref()
+            .and_then(|shadow_map| shadow_map.moments_texture())
+            .is_none()
+        {
+            Some(shadow::ShadowMap::create_moments_placeholder_texture(
+                device,
+            ))
+        } else {
+            None
+        };
+
+        let gobo_placeholder = texture::Texture::create_render_target(
+            device,
+            1,
+            1,
+            wgpu::TextureFormat::Rgba8Unorm,
+            "Gobo Placeholder",
+        );
+        let gobo_cube_placeholder = Self::create_gobo_cube_placeholder(device);
+
+        uniform.get_mut().set_shadow(shadow_map.as_ref());
+
+        let bind_groups = Self::create_bind_groups(
+            device,
+            &uniform,
+            shadow_map.as_ref(),
+            None,
+            shadow_map_placeholder.as_ref(),
+            shadow_moments_placeholder.as_ref(),
+            &Self::gobo_binding(None, &gobo_placeholder, &gobo_cube_placeholder),
+        );
+
         Self {
-            light_type: LightType::Directional,
+            light_type,
             uniform,
+            shadow_map,
+            shadow_map_placeholder,
+            shadow_moments_placeholder,
+            gobo: None,
+            gobo_placeholder,
+            gobo_cube_placeholder,
+            bind_groups,
+            bind_groups_stale: false,
+            radius: None,
+            lod: None,
+            detail: LightDetail::Full,
+        }
+    }
+
+    fn create_gobo_cube_placeholder(device: &wgpu::Device) -> texture::Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Gobo Cube Placeholder"),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Gobo Cube Placeholder"),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        texture::Texture {
+            texture,
+            view,
+            sampler,
+            view_dimension: wgpu::TextureViewDimension::Cube,
+        }
+    }
+
+    fn gobo_binding<'a>(
+        gobo: Option<&'a texture::Texture>,
+        placeholder: &'a texture::Texture,
+        cube_placeholder: &'a texture::Texture,
+    ) -> GoboBinding<'a> {
+        match gobo {
+            Some(gobo) if gobo.view_dimension == wgpu::TextureViewDimension::Cube => GoboBinding {
+                texture: placeholder,
+                cube_texture: gobo,
+                sampler: &gobo.sampler,
+            },
+            Some(gobo) => GoboBinding {
+                texture: gobo,
+                cube_texture: cube_placeholder,
+                sampler: &gobo.sampler,
+            },
+            None => GoboBinding {
+                texture: placeholder,
+                cube_texture: cube_placeholder,
+                sampler: &placeholder.sampler,
+            },
         }
     }
 
+    // one per uniform buffer, see UniformWrapper. `shadow_atlas` is bound for shadow maps
+    // packed into it.
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        uniform: &LightUniform,
+        shadow_map: Option<&shadow::ShadowMap>,
+        shadow_atlas: Option<&texture::Texture>,
+        shadow_map_placeholder: Option<&texture::Texture>,
+        shadow_moments_placeholder: Option<&texture::Texture>,
+        gobo: &GoboBinding,
+    ) -> Vec<wgpu::BindGroup> {
+        uniform
+            .buffers()
+            .iter()
+            .map(|uniform_buffer| {
+                Self::create_bind_group(
+                    device,
+                    uniform_buffer,
+                    shadow_map
+                        .and_then(|shadow_map| shadow_map.texture())
+                        .or(shadow_atlas)
+                        .or(shadow_map_placeholder)
+                        .unwrap(),
+                    shadow_map
+                        .and_then(|shadow_map| shadow_map.moments_texture())
+                        .or(shadow_moments_placeholder)
+                        .unwrap(),
+                    gobo,
+                )
+            })
+            .collect()
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        uniform_buffer: &wgpu::Buffer,
+        shadow_texture: &texture::Texture,
+        shadow_moments_texture: &texture::Texture,
+        gobo: &GoboBinding,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &Self::bind_group_layout(device),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&shadow_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&shadow_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&shadow_moments_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&gobo.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&gobo.cube_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(gobo.sampler),
+                },
+            ],
+            label: Some("Light Bind Group"),
+        })
+    }
+
     pub fn light_type(&self) -> LightType {
         self.light_type
     }
 
+    /// Converts the light to `light_type` in place, keeping its uniform buffers and bind groups,
+    /// so whatever holds the light or its bind group stays valid. Its other fields carry over;
+    /// a light becoming a spot or directional light without a direction points down, and one
+    /// becoming a spot light without a breadth gets DEFAULT_SPOT_BREADTH. A shadow map or gobo
+    /// the new type can't use is kept but idle, and resumes if the light converts back.
+    pub fn set_light_type(&mut self, light_type: LightType) {
+        if light_type == self.light_type {
+            return;
+        }
+        self.light_type = light_type;
+        self.uniform.get_mut().set_light_type(light_type);
+
+        match light_type {
+            LightType::Spot | LightType::Directional => {
+                if self.direction().magnitude2() < EPSILON {
+                    self.uniform.get_mut().set_direction(-Vec3::unit_y());
+                }
+            }
+            LightType::Ambient => {
+                // an ambient light's direction is its hemisphere's up
+                if self.direction().magnitude2() < EPSILON {
+                    self.uniform.get_mut().set_direction(Vec3::unit_y());
+                }
+            }
+            LightType::Point => {}
+        }
+        // other types leave the spot breadth's cosine at 0, a hemisphere
+        if light_type == LightType::Spot && self.uniform.get().attenuation.w <= 0.0 {
+            self.set_spot_breadth(deg(DEFAULT_SPOT_BREADTH));
+        }
+
+        self.write_gobo_mode();
+        self.write_shadow();
+        self.request_shadow_update();
+    }
+
     pub fn ambient(&self) -> Vec3 {
         self.uniform.get().ambient
     }
@@ -225,6 +590,35 @@ impl Light {
         }
     }
 
+    /// The sky, ground and up of an ambient light, or None for other light types
+    pub fn hemisphere(&self) -> Option<Hemisphere> {
+        match self.light_type {
+            LightType::Ambient => Some(Hemisphere {
+                sky: self.ambient(),
+                ground: self.color(),
+                up: self.direction(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Sets an ambient light's hemisphere. Other light types have no hemisphere, their ambient
+    /// term is set with set_ambient.
+    pub fn set_hemisphere(&mut self, hemisphere: Hemisphere) {
+        if self.light_type != LightType::Ambient {
+            eprintln!(
+                "Light::set_hemisphere only applies to ambient lights, not {:?}",
+                self.light_type
+            );
+            return;
+        }
+        self.set_ambient(hemisphere.sky);
+        self.set_color(hemisphere.ground);
+        if hemisphere.up.magnitude2() > EPSILON {
+            self.set_direction(hemisphere.up);
+        }
+    }
+
     pub fn position(&self) -> Point3 {
         self.uniform.get().position
     }
@@ -233,6 +627,7 @@ impl Light {
         let new_position: Point3 = position.into();
         if new_position.distance2(self.position()) > EPSILON {
             self.uniform.get_mut().set_position(new_position);
+            self.request_shadow_update();
         }
     }
 
@@ -244,6 +639,7 @@ impl Light {
         let new_dir: Vec3 = dir.into();
         if new_dir.distance2(self.direction()) > EPSILON {
             self.uniform.get_mut().set_direction(new_dir);
+            self.request_shadow_update();
         }
     }
 
@@ -295,7 +691,7 @@ impl Light {
     }
 
     pub fn spot_breadth(&self) -> Deg {
-        deg(self.uniform.get().attenuation.w.acos())
+        rad(self.uniform.get().attenuation.w.acos()).into()
     }
 
     pub fn set_spot_breadth(&mut self, spot_breadth: Deg) {
@@ -306,15 +702,477 @@ impl Light {
         }
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue) {
-        self.uniform.write(queue);
+    /// The distance beyond which a point or spot light has no effect, None for lights which
+    /// reach everywhere. Unless set with set_radius, it's where the light's attenuated color
+    /// falls below RADIUS_CUTOFF. Shading fades to nothing at the radius, and Scene doesn't
+    /// draw the light's pass for models whose bounds lie beyond it.
+    pub fn radius(&self) -> Option<f32> {
+        match self.light_type {
+            LightType::Point | LightType::Spot => self.radius.or_else(|| self.attenuation_radius()),
+            LightType::Ambient | LightType::Directional => None,
+        }
+    }
+
+    /// Sets the light's radius, or None to derive it from attenuation; see radius.
+    pub fn set_radius(&mut self, radius: Option<f32>) {
+        self.radius = radius.map(|radius| radius.max(0.0));
+    }
+
+    // solves constant + linear * d + exponential * d^2 = brightest channel / RADIUS_CUTOFF
+    fn attenuation_radius(&self) -> Option<f32> {
+        let attenuation = self.uniform.get().attenuation;
+        let color = self.uniform.get().color;
+        let threshold = color.x.max(color.y).max(color.z) / RADIUS_CUTOFF;
+        let (c, l, e) = (attenuation.x - threshold, attenuation.y, attenuation.z);
+        if c >= 0.0 {
+            Some(0.0)
+        } else if e > EPSILON {
+            Some((-l + (l * l - 4.0 * e * c).sqrt()) / (2.0 * e))
+        } else if l > EPSILON {
+            Some(-c / l)
+        } else {
+            None
+        }
+    }
+
+    /// True if the light has a shadow map and is of a type which casts shadows, spot or
+    /// directional; see set_light_type
+    pub fn casts_shadows(&self) -> bool {
+        self.shadow_map.is_some()
+            && matches!(self.light_type, LightType::Spot | LightType::Directional)
+    }
+
+    /// True if the light casts shadows and isn't demoted past LightDetail::Full, so its shadow
+    /// map is rendered and sampled
+    pub fn renders_shadows(&self) -> bool {
+        self.casts_shadows() && self.detail == LightDetail::Full
+    }
+
+    pub fn lod(&self) -> Option<LightLod> {
+        self.lod
+    }
+
+    /// Has a point or spot light demoted to cheaper evaluation, or culled, as it shrinks on
+    /// screen, by `lod`'s thresholds scaled by Scene::lod_bias; None keeps it at full detail.
+    /// Lights without a radius, and those whose radius holds the camera, are never demoted.
+    pub fn set_lod(&mut self, lod: Option<LightLod>) {
+        self.lod = lod;
+    }
+
+    /// The level the light was evaluated at as of the last update, see set_lod
+    pub fn detail(&self) -> LightDetail {
+        self.detail
+    }
+
+    // The level of detail `camera` sees the light at, see set_lod
+    fn choose_detail(&self, camera: &camera::Camera, lod_bias: f32) -> LightDetail {
+        let (lod, radius) = match (self.lod, self.radius()) {
+            (Some(lod), Some(radius)) => (lod, radius),
+            _ => return LightDetail::Full,
+        };
+        let distance = self.position().distance(camera.position());
+        if distance <= radius {
+            return LightDetail::Full;
+        }
+        let half_height = distance * (camera.fov_y().0 * 0.5).tan();
+        let size = radius / half_height.max(EPSILON) * lod_bias.max(EPSILON);
+
+        [
+            (LightDetail::Culled, lod.culled),
+            (LightDetail::Simple, lod.simple),
+            (LightDetail::Unshadowed, lod.unshadowed),
+        ]
+        .into_iter()
+        .find(|(detail, threshold)| {
+            let hysteresis = if self.detail >= *detail {
+                LOD_HYSTERESIS
+            } else {
+                1.0
+            };
+            size < threshold * hysteresis
+        })
+        .map_or(LightDetail::Full, |(detail, _)| detail)
+    }
+
+    pub fn shadow_map(&self) -> Option<&shadow::ShadowMap> {
+        self.shadow_map.as_ref()
+    }
+
+    /// Returns the shadow bias for this light, or None if the light doesn't cast shadows
+    pub fn shadow_bias(&self) -> Option<ShadowBias> {
+        self.shadow_map.as_ref().map(|shadow_map| shadow_map.bias())
+    }
+
+    /// Sets the shadow bias for this light. Has no effect if the light doesn't cast shadows.
+    /// Changing the constant or slope-scaled terms may require a new shadow pipeline,
+    /// which will be created by the next call to prepare_pipelines.
+    pub fn set_shadow_bias(&mut self, bias: ShadowBias) {
+        if let Some(shadow_map) = self
+            .shadow_map
+            .as_mut()
+            .filter(|shadow_map| shadow_map.bias() != bias)
+        {
+            shadow_map.set_bias(bias);
+            self.write_shadow();
+        }
+    }
+
+    /// Recreates this light's shadow map at `resolution` texels square, keeping its range, bias
+    /// and mode, e.g. to follow graphics_settings::ShadowQuality. Has no effect if the light
+    /// doesn't cast shadows. A scene's shadow_atlas::ShadowAtlas may grant less.
+    pub fn set_shadow_resolution(&mut self, device: &wgpu::Device, resolution: u32) {
+        let resolution = resolution.max(1);
+        if let Some(shadow_map) = &mut self.shadow_map {
+            shadow_map.set_requested_resolution(resolution);
+        }
+        self.allocate_shadow_resolution(device, resolution);
+    }
+
+    /// Recreates this light's shadow map in a texture of its own at `resolution` texels square
+    /// while keeping the resolution it requested, see shadow_atlas::ShadowAtlas. Has no effect
+    /// if the light doesn't cast shadows.
+    pub fn allocate_shadow_resolution(&mut self, device: &wgpu::Device, resolution: u32) {
+        let resolution = resolution.max(1);
+        let (desc, requested_resolution) = match &self.shadow_map {
+            Some(shadow_map)
+                if shadow_map.resolution() != resolution || shadow_map.texture().is_none() =>
+            {
+                (
+                    ShadowDescriptor {
+                        resolution,
+                        ..shadow_map.descriptor()
+                    },
+                    shadow_map.requested_resolution(),
+                )
+            }
+            _ => return,
+        };
+
+        let mut shadow_map = shadow::ShadowMap::new(device, &desc);
+        shadow_map.set_requested_resolution(requested_resolution);
+        self.replace_shadow_map(device, shadow_map, None);
+    }
+
+    /// True if the light's shadow map may be packed into a shadow atlas; only Pcf maps of spot
+    /// lights may, see shadow_atlas::ShadowAtlasTexture. Point lights don't render shadow maps,
+    /// and directional lights keep textures of their own.
+    pub fn packs_shadow_map(&self) -> bool {
+        self.light_type == LightType::Spot
+            && self
+                .shadow_map
+                .as_ref()
+                .is_some_and(|shadow_map| shadow_map.mode() == shadow::ShadowMode::Pcf)
+    }
+
+    /// Recreates this light's shadow map in `region` of `atlas`, keeping the resolution it
+    /// requested. Has no effect unless packs_shadow_map.
+    pub fn pack_shadow_map(
+        &mut self,
+        device: &wgpu::Device,
+        region: shadow_atlas::ShadowAtlasRegion,
+        atlas: &shadow_atlas::ShadowAtlasTexture,
+    ) {
+        if !self.packs_shadow_map() {
+            return;
+        }
+        let shadow_map = match &self.shadow_map {
+            Some(shadow_map)
+                if shadow_map.atlas_region() != Some(region)
+                    || shadow_map.uv_rect() != region.uv_rect(atlas.size()) =>
+            {
+                shadow_map
+            }
+            Some(_) if self.bind_groups_stale => {
+                self.rebuild_bind_groups(device, Some(&atlas.texture));
+                return;
+            }
+            _ => return,
+        };
+
+        let packed =
+            shadow::ShadowMap::new_in_atlas(device, &shadow_map.descriptor(), region, atlas.size());
+        self.replace_shadow_map(device, packed, Some(&atlas.texture));
+    }
+
+    fn replace_shadow_map(
+        &mut self,
+        device: &wgpu::Device,
+        shadow_map: shadow::ShadowMap,
+        shadow_atlas: Option<&texture::Texture>,
+    ) {
+        self.shadow_map = Some(shadow_map);
+        self.write_shadow();
+        self.rebuild_bind_groups(device, shadow_atlas);
+    }
+
+    // Writes the shadow map's terms to the uniform, or none for lights which don't render
+    // shadows, see renders_shadows
+    fn write_shadow(&mut self) {
+        let shadow_map = self.shadow_map.as_ref().filter(|_| self.renders_shadows());
+        self.uniform.get_mut().set_shadow(shadow_map);
+    }
+
+    // `shadow_atlas` must be the atlas the shadow map is packed into, if it is
+    fn rebuild_bind_groups(
+        &mut self,
+        device: &wgpu::Device,
+        shadow_atlas: Option<&texture::Texture>,
+    ) {
+        self.bind_groups = Self::create_bind_groups(
+            device,
+            &self.uniform,
+            self.shadow_map.as_ref(),
+            shadow_atlas,
+            self.shadow_map_placeholder.as_ref(),
+            self.shadow_moments_placeholder.as_ref(),
+            &Self::gobo_binding(
+                self.gobo.as_deref(),
+                &self.gobo_placeholder,
+                &self.gobo_cube_placeholder,
+            ),
+        );
+        self.bind_groups_stale = false;
+    }
+
+    pub fn gobo(&self) -> Option<&Rc<texture::Texture>> {
+        self.gobo.as_ref()
+    }
+
+    /// Tints (or with black regions, masks) the light by a texture, e.g. stained glass or a
+    /// window frame. Spot lights project a 2D texture across their cone, its top toward world
+    /// up; point lights cast a cubemap all around, sampled in the direction from the light.
+    /// Fails for other lights, or a texture of the wrong dimension.
+    pub fn set_gobo(
+        &mut self,
+        device: &wgpu::Device,
+        gobo: Option<Rc<texture::Texture>>,
+    ) -> Result<()> {
+        let dimension = match self.light_type {
+            LightType::Spot => wgpu::TextureViewDimension::D2,
+            LightType::Point => wgpu::TextureViewDimension::Cube,
+            LightType::Ambient | LightType::Directional => {
+                return Err(anyhow!(
+                    "Only spot and point lights have gobos, not {:?} lights",
+                    self.light_type
+                ))
+            }
+        };
+        if let Some(gobo) = &gobo {
+            if gobo.view_dimension != dimension {
+                return Err(anyhow!(
+                    "A {:?} light's gobo must be a {:?} texture, not {:?}",
+                    self.light_type,
+                    dimension,
+                    gobo.view_dimension
+                ));
+            }
+        }
+
+        self.gobo = gobo;
+        self.write_gobo_mode();
+        if self
+            .shadow_map
+            .as_ref()
+            .is_some_and(|shadow_map| shadow_map.atlas_region().is_some())
+        {
+            self.bind_groups_stale = true;
+        } else {
+            self.rebuild_bind_groups(device, None);
+        }
+        Ok(())
+    }
+
+    // Writes the uniform's gobo mode, 0 if the light can't project its gobo or is demoted
+    // past projecting one
+    fn write_gobo_mode(&mut self) {
+        let mode = match (
+            self.light_type,
+            self.gobo.as_ref().map(|gobo| gobo.view_dimension),
+        ) {
+            _ if self.detail >= LightDetail::Simple => 0.0,
+            (LightType::Spot, Some(wgpu::TextureViewDimension::D2)) => 1.0,
+            (LightType::Point, Some(wgpu::TextureViewDimension::Cube)) => 2.0,
+            _ => 0.0,
+        };
+        self.uniform.get_mut().gobo.x = mode;
+    }
+
+    pub fn gobo_rotation(&self) -> Rad {
+        rad(self.uniform.get().gobo.y)
+    }
+
+    /// Turns a spot light's gobo about the light's direction, or a point light's about world up
+    pub fn set_gobo_rotation<R: Into<Rad>>(&mut self, rotation: R) {
+        let rotation: Rad = rotation.into();
+        if rotation.0 != self.uniform.get().gobo.y {
+            self.uniform.get_mut().gobo.y = rotation.0;
+        }
+    }
+
+    /// Has the light's shadow map rendered on the next frame, see shadow::ShadowUpdate. Moving
+    /// or turning the light requests one.
+    pub fn request_shadow_update(&mut self) {
+        if let Some(shadow_map) = &mut self.shadow_map {
+            shadow_map.request_update();
+        }
+    }
+
+    pub fn prepare_pipelines(&self, gpu_state: &mut GpuState) {
+        if let Some(shadow_map) = &self.shadow_map {
+            shadow_map.prepare_pipeline(gpu_state);
+        }
+    }
+
+    /// Writes the light's uniform. Directional shadows fitted to the view follow `camera`, see
+    /// shadow::ShadowFit, and the light's level of detail is chosen as `camera` sees it, see
+    /// set_lod. The shadow transform only changes on frames the shadow map is rendered, as
+    /// its last render is sampled until the next.
+    pub fn update(&mut self, gpu_state: &GpuState, camera: &camera::Camera, lod_bias: f32) {
+        let detail = self.choose_detail(camera, lod_bias);
+        if detail != self.detail {
+            self.detail = detail;
+            self.write_shadow();
+            self.write_gobo_mode();
+            if detail == LightDetail::Full {
+                // the shadow map wasn't rendered while demoted
+                self.request_shadow_update();
+            }
+        }
+
+        let renders_shadows = self.renders_shadows();
+        let shadow_due = self
+            .shadow_map
+            .as_mut()
+            .filter(|_| renders_shadows)
+            .is_some_and(|shadow_map| shadow_map.schedule());
+        if let Some(view_proj) = self
+            .shadow_map
+            .as_ref()
+            .filter(|_| shadow_due)
+            .and_then(|shadow_map| shadow_map.view_proj(self, camera))
+        {
+            if view_proj != self.uniform.get().shadow_view_proj {
+                self.uniform.get_mut().shadow_view_proj = view_proj;
+            }
+        }
+        // a radius of 0 is unbounded to the shaders, so bound an unlit light tightly instead
+        let radius = self
+            .radius()
+            .map(|radius| radius.max(EPSILON))
+            .unwrap_or(0.0);
+        if radius != self.uniform.get().radius {
+            self.uniform.get_mut().radius = radius;
+        }
+        // simplified lights are attenuated as at half their radius throughout, faded by the
+        // radius window as usual
+        let simple_attenuation = if self.detail >= LightDetail::Simple {
+            let attenuation = self.uniform.get().attenuation;
+            let d = radius * 0.5;
+            1.0 / (attenuation.x + attenuation.y * d + attenuation.z * d * d).max(EPSILON)
+        } else {
+            0.0
+        };
+        if simple_attenuation != self.uniform.get().simple_attenuation {
+            self.uniform.get_mut().simple_attenuation = simple_attenuation;
+        }
+        self.uniform.write(gpu_state);
     }
 
     pub fn bind_group(&self) -> &wgpu::BindGroup {
-        &self.uniform.bind_group
+        &self.bind_groups[self.uniform.slot()]
     }
 
     pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &Self::bind_group_layout_entries(),
+            label: Some("Light Bind Group Layout"),
+        })
+    }
+
+    /// The entries of bind_group_layout, see shader_reflection::validate_bindings. Compute
+    /// shaders may bind lights too, see volumetric_fog::VolumetricFog.
+    pub fn bind_group_layout_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX
+                    | wgpu::ShaderStages::FRAGMENT
+                    | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Shadow map
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            // Shadow map comparison sampler
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                count: None,
+            },
+            // Shadow map moments, for variance shadow modes
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            // Gobo, projected by spot lights
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            // Gobo cubemap, cast by point lights
+            wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::Cube,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            // Gobo sampler
+            wgpu::BindGroupLayoutEntry {
+                binding: 6,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+    }
+
+    /// The bind group used when rendering this light's shadow map; it holds only the light
+    /// uniform, since the shadow map can't be sampled while it's being rendered to.
+    pub fn shadow_pass_bind_group(&self) -> &wgpu::BindGroup {
+        self.uniform.bind_group()
+    }
+
+    pub fn shadow_pass_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
         LightUniform::bind_group_layout(device)
     }
 }
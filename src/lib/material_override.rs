@@ -0,0 +1,110 @@
+use std::ops::Range;
+
+use super::{
+    camera,
+    gpu_state::GpuState,
+    model,
+    render_pipeline::{self, DepthMode, RenderPipelineVendor},
+    resources, texture,
+};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Built-in views drawn in place of every material, scene-wide, for debugging imported assets,
+/// e.g. tangents averaged across a seam or texture coordinates which are missing or flipped.
+/// Set with Scene::debug_material_override.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MaterialOverride {
+    // world space vertex normals mapped from [-1,1] to [0,1], before normal mapping
+    Normals,
+    // a checker over texture coordinates, tinted by u (red) and v (green)
+    UvChecker,
+    // green where the tangent frame is right handed, red where it's mirrored and yellow where
+    // it's degenerate
+    TangentHandedness,
+}
+
+impl MaterialOverride {
+    fn fragment_main(&self) -> &'static str {
+        match self {
+            MaterialOverride::Normals => "fs_main_override_normals",
+            MaterialOverride::UvChecker => "fs_main_override_uv_checker",
+            MaterialOverride::TangentHandedness => "fs_main_override_tangent_handedness",
+        }
+    }
+}
+
+pub fn pipeline_id(mode: MaterialOverride, depth_mode: DepthMode) -> String {
+    format!("material_override_{:?}_{:?}", mode, depth_mode)
+}
+
+pub fn prepare_pipeline(gpu_state: &mut GpuState, mode: MaterialOverride, depth_mode: DepthMode) {
+    let pipeline_id = pipeline_id(mode, depth_mode);
+    if gpu_state.pipeline_vendor.has_pipeline(&pipeline_id) {
+        return;
+    }
+
+    let layout = gpu_state
+        .device
+        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&pipeline_id),
+            bind_group_layouts: &[&camera::Camera::bind_group_layout(&gpu_state.device)],
+            push_constant_ranges: &[],
+        });
+
+    let shader = wgpu::ShaderModuleDescriptor {
+        label: Some("shaders/material_override.wgsl"),
+        source: wgpu::ShaderSource::Wgsl(
+            resources::load_shader_sync("shaders/material_override.wgsl", &[])
+                .unwrap()
+                .into(),
+        ),
+    };
+
+    gpu_state.pipeline_vendor.create_render_pipeline(
+        &pipeline_id,
+        &gpu_state.device,
+        render_pipeline::Properties {
+            vs_main: "vs_main_override",
+            fs_main: Some(mode.fragment_main()),
+            layout: &layout,
+            color_format: texture::Texture::COLOR_FORMAT,
+            depth_format: Some(texture::Texture::DEPTH_FORMAT),
+            depth_bias: wgpu::DepthBiasState::default(),
+            depth_mode,
+            vertex_layouts: &model::Model::vertex_layout(),
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            shader,
+            cull_mode: Some(wgpu::Face::Back),
+            blend: None,
+            pass: render_pipeline::Pass::Ambient,
+        },
+    );
+}
+
+// Draws every mesh of `models` with the override, each for its range of instances. Alpha
+// masking isn't applied, so cutout materials draw solid.
+pub fn draw<'a, 'b, I>(
+    render_pass: &'b mut wgpu::RenderPass<'a>,
+    pipeline_vendor: &'a RenderPipelineVendor,
+    mode: MaterialOverride,
+    models: I,
+    camera: &'a camera::Camera,
+) where
+    'a: 'b, // 'a lifetime at least as long as 'b
+    I: Iterator<Item = (&'a model::Model, Range<u32>)>,
+{
+    let pipeline_id = pipeline_id(mode, camera.depth_mode());
+    if let Some(pipeline) = pipeline_vendor.get_pipeline(&pipeline_id) {
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, camera.bind_group(), &[]);
+        for (model, instances) in models {
+            model::draw_model_positions_range(render_pass, model, instances, |_| true);
+        }
+    } else {
+        eprintln!(
+            "No pipeline available to render material override id: {}",
+            pipeline_id
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use std::{collections::HashMap, rc::Rc, sync::mpsc};
+
+use anyhow::{anyhow, Result};
+use cgmath::prelude::*;
+
+use super::{
+    camera,
+    frame_context::FrameContext,
+    gpu_state::{FramePhase, GpuState},
+    light, mesh_builder,
+    model::{self, ModelVertex},
+    scene, screenshot, texture,
+    util::*,
+};
+
+// Vertices around the preview sphere's equator, and rings from pole to pole
+const SPHERE_SEGMENTS: u32 = 48;
+const SPHERE_RINGS: u32 = 24;
+
+// The camera's vertical field of view and distance from the unit sphere's center, which
+// leave a small margin around it
+const CAMERA_FOV_Y_DEGREES: f32 = 30.0;
+const CAMERA_DISTANCE: f32 = 4.4;
+
+// The id of the preview sphere's model in the preview scene
+const SPHERE_MODEL_ID: usize = 0;
+
+pub struct MaterialPreviewDescriptor {
+    // the width and height of previews in pixels
+    pub size: u32,
+    // fills the pixels the sphere doesn't cover, as linear color
+    pub background: wgpu::Color,
+}
+
+impl Default for MaterialPreviewDescriptor {
+    fn default() -> Self {
+        Self {
+            size: 128,
+            background: wgpu::Color::TRANSPARENT,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Renders materials onto a sphere under a studio lighting rig into small images, e.g. for the
+// thumbnails of an asset browser or an editor's material list. The preview keeps a scene of
+// its own, the sphere lit by a warm key light, a cool fill and a rim light over a soft
+// hemisphere ambient, and reflecting the environment map it was made with. Each render runs
+// a frame of that scene offscreen and reads its color attachment back, as a
+// screenshot::CaptureMode::Scene capture would, so previews are shaded exactly as the
+// material is in a scene, without the compositor's exposure and post effects.
+pub struct MaterialPreview {
+    scene: scene::Scene,
+    size: u32,
+}
+
+impl MaterialPreview {
+    pub fn new(
+        gpu_state: &mut GpuState,
+        environment_map: Rc<texture::Texture>,
+        descriptor: &MaterialPreviewDescriptor,
+    ) -> Result<Self> {
+        let size = descriptor.size.max(1);
+        let mut camera = camera::Camera::new(
+            gpu_state,
+            deg(CAMERA_FOV_Y_DEGREES),
+            0.1,
+            CAMERA_DISTANCE * 2.0,
+        );
+        camera.resize(gpu_state, winit::dpi::PhysicalSize::new(size, size));
+        camera.look_at(
+            Point3::new(0.0, 0.0, CAMERA_DISTANCE),
+            Point3::origin(),
+            Vec3::unit_y(),
+        );
+        camera.set_clear(camera::ClearSettings {
+            color: Some(descriptor.background),
+            ..Default::default()
+        });
+
+        // the material being previewed is swapped in for the placeholder while rendering
+        let placeholder = model::Material::new(
+            &gpu_state.device,
+            model::MaterialProperties {
+                name: "Material Preview Placeholder",
+                ..Default::default()
+            },
+        );
+        let sphere = model::Model::new(
+            &gpu_state.device,
+            vec![create_sphere(&gpu_state.device)?],
+            vec![placeholder],
+            &[model::Instance::new(Point3::origin(), Quat::one())],
+        );
+
+        let mut scene = scene::Scene::new(
+            gpu_state,
+            camera,
+            environment_map,
+            studio_lights(&gpu_state.device),
+            HashMap::from([(SPHERE_MODEL_ID, sphere)]),
+        );
+        scene.hemisphere_ambient = Some(light::Hemisphere {
+            sky: Vec3::new(0.16, 0.17, 0.19),
+            ground: Vec3::new(0.05, 0.045, 0.04),
+            up: Vec3::unit_y(),
+        });
+        // a single sphere is no work to spread across threads
+        scene.jobs = super::jobs::JobSystem::serial();
+
+        Ok(Self { scene, size })
+    }
+
+    /// The width and height of the previews in pixels
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Renders `material` on the preview sphere, returning the image. `material` is only
+    /// borrowed mutably to be moved into the preview's scene for the render, and is returned
+    /// to its place unchanged; its pipelines are prepared as any scene's would be. Surface
+    /// materials preview as they look in a scene; terrain, heightmap and vertex animation
+    /// materials need geometry of their own, so preview as the sphere will allow.
+    ///
+    /// The preview renders a frame of its own, submits it and waits for the GPU to finish,
+    /// so must be called between frames, while gpu_state's frame phase is Idle; it's meant
+    /// for tooling and loading screens rather than every frame.
+    pub fn render(
+        &mut self,
+        gpu_state: &mut GpuState,
+        material: &mut model::Material,
+    ) -> Result<image::RgbaImage> {
+        if gpu_state.frame_phase() != FramePhase::Idle {
+            return Err(anyhow!(
+                "Material previews must be rendered between frames, not during {:?}",
+                gpu_state.frame_phase()
+            ));
+        }
+        let slot = &mut self
+            .scene
+            .models
+            .get_mut(&SPHERE_MODEL_ID)
+            .expect("The preview scene has no sphere")
+            .materials_mut()[0];
+        std::mem::swap(slot, material);
+        let result = self.render_scene(gpu_state);
+        let slot = &mut self
+            .scene
+            .models
+            .get_mut(&SPHERE_MODEL_ID)
+            .expect("The preview scene has no sphere")
+            .materials_mut()[0];
+        std::mem::swap(slot, material);
+        result
+    }
+
+    // Renders a frame of the preview scene and reads back its color attachment
+    fn render_scene(&mut self, gpu_state: &mut GpuState) -> Result<image::RgbaImage> {
+        let format = texture::Texture::COLOR_FORMAT;
+        let bytes_per_row = self.size * format.describe().block_size as u32;
+        let padded_bytes_per_row = bytes_per_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = gpu_state.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Material Preview Readback"),
+            size: padded_bytes_per_row as wgpu::BufferAddress * self.size as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        gpu_state.begin_frame();
+        self.scene.update(&mut FrameContext::update(
+            gpu_state,
+            instant::Duration::default(),
+        ));
+        let mut encoder = gpu_state.begin_encoding();
+        self.scene.render(&mut FrameContext::encode(
+            gpu_state,
+            &mut encoder,
+            instant::Duration::default(),
+        ));
+        let color = match &self.scene.camera.render_buffers.color {
+            Some(color) => color,
+            None => {
+                gpu_state.end_frame(Some(encoder));
+                return Err(anyhow!("The preview camera has no color attachment"));
+            }
+        };
+        encoder.copy_texture_to_buffer(
+            color.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: std::num::NonZeroU32::new(self.size),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.size,
+                height: self.size,
+                depth_or_array_layers: 1,
+            },
+        );
+        gpu_state.end_frame(Some(encoder));
+
+        let (sender, receiver) = mpsc::channel();
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        gpu_state.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let texels = buffer
+            .slice(..)
+            .get_mapped_range()
+            .chunks_exact(padded_bytes_per_row as usize)
+            .flat_map(|row| row[..bytes_per_row as usize].iter().copied())
+            .collect::<Vec<u8>>();
+        buffer.unmap();
+        let rgba = screenshot::to_rgba8(format, texels)?;
+        image::RgbaImage::from_raw(self.size, self.size, rgba)
+            .ok_or_else(|| anyhow!("Texels don't fill a {0}x{0} image", self.size))
+    }
+}
+
+// A unit UV sphere, its texture coordinates wrapping twice around the equator so square
+// textures keep their aspect
+fn create_sphere(device: &wgpu::Device) -> Result<model::Mesh> {
+    let mut builder = mesh_builder::MeshBuilder::with_capacity(
+        "Material Preview Sphere",
+        ((SPHERE_RINGS + 1) * (SPHERE_SEGMENTS + 1)) as usize,
+        (SPHERE_RINGS * SPHERE_SEGMENTS * 6) as usize,
+    );
+    for ring in 0..=SPHERE_RINGS {
+        let v = ring as f32 / SPHERE_RINGS as f32;
+        let theta = std::f32::consts::PI * v;
+        for segment in 0..=SPHERE_SEGMENTS {
+            let u = segment as f32 / SPHERE_SEGMENTS as f32;
+            let phi = std::f32::consts::TAU * u;
+            let normal = Vec3::new(
+                theta.sin() * phi.sin(),
+                theta.cos(),
+                theta.sin() * phi.cos(),
+            );
+            builder.push_vertex(ModelVertex {
+                position: Point3::from_vec(normal),
+                tex_coords: Vec2::new(2.0 * u, v),
+                normal,
+                tangent: Vec3::zero(),
+                bitangent: Vec3::zero(),
+            });
+        }
+    }
+    let index = |ring: u32, segment: u32| ring * (SPHERE_SEGMENTS + 1) + segment;
+    for ring in 0..SPHERE_RINGS {
+        for segment in 0..SPHERE_SEGMENTS {
+            let a = index(ring, segment);
+            let b = index(ring + 1, segment);
+            let c = index(ring + 1, segment + 1);
+            let d = index(ring, segment + 1);
+            // the triangles which meet at the poles collapse
+            if ring != SPHERE_RINGS - 1 {
+                builder.push_triangle(a, b, c);
+            }
+            if ring != 0 {
+                builder.push_triangle(a, c, d);
+            }
+        }
+    }
+    builder.compute_tangents();
+    builder.build(device, false)
+}
+
+// A warm key light high to the front left, a cool fill low to the right, and a rim light
+// behind to pick out the sphere's silhouette; none cast shadows, there's nothing to shadow
+fn studio_lights(device: &wgpu::Device) -> HashMap<usize, light::Light> {
+    let light = |direction: Vec3, color: Vec3| {
+        light::Light::new_directional(
+            device,
+            &light::DirectionalLightDescriptor {
+                direction: direction.normalize(),
+                ambient: Vec3::zero(),
+                color,
+                constant_attenuation: 1.0,
+                shadows: None,
+            },
+        )
+    };
+    HashMap::from([
+        (
+            0,
+            light(Vec3::new(-1.0, 1.2, 1.4), Vec3::new(0.8, 0.76, 0.7)),
+        ),
+        (
+            1,
+            light(Vec3::new(1.4, -0.2, 0.8), Vec3::new(0.2, 0.23, 0.28)),
+        ),
+        (
+            2,
+            light(Vec3::new(0.6, 0.8, -1.4), Vec3::new(0.45, 0.45, 0.5)),
+        ),
+    ])
+}
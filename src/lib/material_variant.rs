@@ -0,0 +1,120 @@
+use std::fmt;
+
+use bitflags::bitflags;
+
+use super::render_pipeline::{DepthMode, Pass};
+
+bitflags! {
+    pub struct MaterialTextures: u32 {
+        const DIFFUSE = 1 << 0;
+        const NORMAL = 1 << 1;
+        const SHININESS = 1 << 2;
+        const AMBIENT_OCCLUSION = 1 << 3;
+        // terrain layers, see model::TerrainLayers
+        const TERRAIN_ALBEDO = 1 << 4;
+        const TERRAIN_NORMAL = 1 << 5;
+        const SPLAT_MAP = 1 << 6;
+        // clipmap displacement, see model::Heightmap
+        const HEIGHTMAP = 1 << 7;
+        // baked vertex animation, see model::VertexAnimation
+        const VERTEX_ANIMATION = 1 << 8;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AlphaMode {
+    #[default]
+    Opaque,
+    // fragments with alpha below the material's alpha cutoff are discarded
+    Mask,
+}
+
+/// Identifies a single pipeline variant of a material. Used as the key into
+/// `RenderPipelineVendor`, and to derive the defines the shader is preprocessed with. It holds
+/// nothing specific to one material: texture bindings are fixed whichever textures are present
+/// (see model::Material::DIFFUSE_TEXTURE_BINDING), so every material with the same key shares
+/// one pipeline per pass, compiled once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialVariantKey {
+    pub pass: Pass,
+    pub textures: MaterialTextures,
+    pub alpha_mode: AlphaMode,
+    pub cull_mode: Option<wgpu::Face>,
+    pub depth_mode: DepthMode,
+    pub toon_shading: bool,
+    // terrain layer weights are read from a per-vertex buffer, see model::Mesh::set_splat_weights
+    pub vertex_splat_weights: bool,
+    // see custom_shader::CustomShader::id
+    pub custom_shader: Option<usize>,
+}
+
+impl MaterialVariantKey {
+    pub fn defines(&self) -> Vec<&'static str> {
+        let mut defines = vec![match self.pass {
+            Pass::Ambient => "PASS_AMBIENT",
+            Pass::Lit => "PASS_LIT",
+            Pass::Outline => "PASS_OUTLINE",
+            Pass::Shadow => "PASS_SHADOW",
+            Pass::Depth => "PASS_DEPTH",
+            Pass::Debug => "PASS_DEBUG",
+            Pass::Particles => "PASS_PARTICLES",
+        }];
+
+        for (texture, define) in [
+            (MaterialTextures::DIFFUSE, "HAS_DIFFUSE_TEXTURE"),
+            (MaterialTextures::NORMAL, "HAS_NORMAL_TEXTURE"),
+            (MaterialTextures::SHININESS, "HAS_SHININESS_TEXTURE"),
+            (
+                MaterialTextures::AMBIENT_OCCLUSION,
+                "HAS_AMBIENT_OCCLUSION_TEXTURE",
+            ),
+            (MaterialTextures::TERRAIN_ALBEDO, "TERRAIN"),
+            (
+                MaterialTextures::TERRAIN_NORMAL,
+                "HAS_TERRAIN_NORMAL_TEXTURE",
+            ),
+            (MaterialTextures::SPLAT_MAP, "HAS_SPLAT_MAP"),
+            (MaterialTextures::HEIGHTMAP, "CLIPMAP"),
+            (MaterialTextures::VERTEX_ANIMATION, "VERTEX_ANIMATION"),
+        ] {
+            if self.textures.contains(texture) {
+                defines.push(define);
+            }
+        }
+
+        if self.alpha_mode == AlphaMode::Mask {
+            defines.push("ALPHA_MASK");
+        }
+        if self.toon_shading {
+            defines.push("TOON_SHADING");
+        }
+        if self.vertex_splat_weights {
+            defines.push("VERTEX_SPLAT_WEIGHTS");
+        }
+
+        defines
+    }
+}
+
+impl fmt::Display for MaterialVariantKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "material[{:?} {:?} {:?} cull:{:?}",
+            self.pass, self.textures, self.alpha_mode, self.cull_mode
+        )?;
+        if self.depth_mode == DepthMode::Reversed {
+            write!(f, " reversed-z")?;
+        }
+        if self.toon_shading {
+            write!(f, " toon")?;
+        }
+        if self.vertex_splat_weights {
+            write!(f, " vertex-splat")?;
+        }
+        if let Some(custom_shader) = self.custom_shader {
+            write!(f, " custom:{}", custom_shader)?;
+        }
+        write!(f, "]")
+    }
+}
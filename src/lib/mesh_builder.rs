@@ -0,0 +1,272 @@
+use anyhow::{anyhow, Result};
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use super::{
+    model::{self, MeshData, ModelVertex},
+    util::*,
+};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Assembles a mesh's geometry on the CPU, fills in normals and tangents if asked, and uploads
+// it as a model::Mesh. Meshes built in code (terrain, clipmaps, splines) and loaded from files
+// all go through here, so index validation and the tangent convention live in one place.
+pub struct MeshBuilder {
+    name: String,
+    material: usize,
+    data: MeshData,
+}
+
+impl MeshBuilder {
+    pub fn new(name: &str) -> Self {
+        Self::with_capacity(name, 0, 0)
+    }
+
+    pub fn with_capacity(name: &str, vertices: usize, indices: usize) -> Self {
+        Self {
+            name: name.to_owned(),
+            material: 0,
+            data: MeshData {
+                vertices: Vec::with_capacity(vertices),
+                indices: Vec::with_capacity(indices),
+            },
+        }
+    }
+
+    /// Takes a mesh parsed from an OBJ file, triangulated with a single index per vertex, and
+    /// computes its tangents; and its normals, if the file has none.
+    pub fn from_obj(name: &str, mesh: &tobj::Mesh) -> Self {
+        let vertex_count = mesh.positions.len() / 3;
+        let mut builder = Self::with_capacity(name, vertex_count, mesh.indices.len());
+        builder.set_material(mesh.material_id.unwrap_or(0));
+        let has_normals = mesh.normals.len() >= vertex_count * 3;
+        let has_tex_coords = mesh.texcoords.len() >= vertex_count * 2;
+        for i in 0..vertex_count {
+            builder.push_vertex(ModelVertex {
+                position: Point3::new(
+                    mesh.positions[i * 3],
+                    mesh.positions[i * 3 + 1],
+                    mesh.positions[i * 3 + 2],
+                ),
+                tex_coords: if has_tex_coords {
+                    Vec2::new(mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1])
+                } else {
+                    Vec2::zero()
+                },
+                normal: if has_normals {
+                    Vec3::new(
+                        mesh.normals[i * 3],
+                        mesh.normals[i * 3 + 1],
+                        mesh.normals[i * 3 + 2],
+                    )
+                } else {
+                    Vec3::zero()
+                },
+                tangent: Vec3::zero(),
+                bitangent: Vec3::zero(),
+            });
+        }
+        for triangle in mesh.indices.chunks_exact(3) {
+            builder.push_triangle(triangle[0], triangle[1], triangle[2]);
+        }
+        if !has_normals {
+            builder.compute_normals();
+        }
+        builder.compute_tangents();
+        builder
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The index of the mesh's material in its model.
+    pub fn set_material(&mut self, material: usize) -> &mut Self {
+        self.material = material;
+        self
+    }
+
+    pub fn material(&self) -> usize {
+        self.material
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.data.vertices.len() as u32
+    }
+
+    pub fn vertices(&self) -> &[ModelVertex] {
+        &self.data.vertices
+    }
+
+    pub fn vertices_mut(&mut self) -> &mut [ModelVertex] {
+        &mut self.data.vertices
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.data.indices
+    }
+
+    /// Returns the vertex's index.
+    pub fn push_vertex(&mut self, vertex: ModelVertex) -> u32 {
+        self.data.vertices.push(vertex);
+        self.data.vertices.len() as u32 - 1
+    }
+
+    /// Pushes a vertex whose normal and tangents are left for compute_normals and
+    /// compute_tangents, returning its index.
+    pub fn push_position(&mut self, position: Point3, tex_coords: Vec2) -> u32 {
+        self.push_vertex(ModelVertex {
+            position,
+            tex_coords,
+            normal: Vec3::zero(),
+            tangent: Vec3::zero(),
+            bitangent: Vec3::zero(),
+        })
+    }
+
+    /// Front faces wind counter-clockwise.
+    pub fn push_triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.data.indices.extend_from_slice(&[a, b, c]);
+    }
+
+    /// Pushes the triangles a, b, c and a, c, d.
+    pub fn push_quad(&mut self, a: u32, b: u32, c: u32, d: u32) {
+        self.push_triangle(a, b, c);
+        self.push_triangle(a, c, d);
+    }
+
+    /// Replaces vertex normals with the area weighted average of their triangles' normals.
+    pub fn compute_normals(&mut self) {
+        let vertices = &mut self.data.vertices;
+        for vertex in vertices.iter_mut() {
+            vertex.normal = Vec3::zero();
+        }
+        for triangle in self.data.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+            if a.max(b).max(c) >= vertices.len() {
+                continue;
+            }
+            // unnormalized, so larger triangles contribute more
+            let normal = (vertices[b].position - vertices[a].position)
+                .cross(vertices[c].position - vertices[a].position);
+            for i in [a, b, c] {
+                vertices[i].normal += normal;
+            }
+        }
+        for vertex in vertices.iter_mut() {
+            vertex.normal = if vertex.normal.magnitude2() > 0.0 {
+                vertex.normal.normalize()
+            } else {
+                Vec3::unit_y()
+            };
+        }
+    }
+
+    /// Replaces vertex tangents and bitangents with the average of their triangles', derived
+    /// from texture coordinates. The bitangent points toward decreasing v, matching the normal
+    /// mapping in model.wgsl.
+    pub fn compute_tangents(&mut self) {
+        let vertices = &mut self.data.vertices;
+        for vertex in vertices.iter_mut() {
+            vertex.tangent = Vec3::zero();
+            vertex.bitangent = Vec3::zero();
+        }
+        for triangle in self.data.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+            if a.max(b).max(c) >= vertices.len() {
+                continue;
+            }
+
+            let delta_pos1 = vertices[b].position - vertices[a].position;
+            let delta_pos2 = vertices[c].position - vertices[a].position;
+            let delta_uv1 = vertices[b].tex_coords - vertices[a].tex_coords;
+            let delta_uv2 = vertices[c].tex_coords - vertices[a].tex_coords;
+
+            let determinant = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+            if determinant.abs() <= f32::EPSILON {
+                // degenerate texture coordinates say nothing about direction
+                continue;
+            }
+            let r = 1.0 / determinant;
+            let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+            let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+            for i in [a, b, c] {
+                vertices[i].tangent += tangent;
+                vertices[i].bitangent += bitangent;
+            }
+        }
+        for vertex in vertices.iter_mut() {
+            if vertex.tangent.magnitude2() > 0.0 && vertex.bitangent.magnitude2() > 0.0 {
+                vertex.tangent = vertex.tangent.normalize();
+                vertex.bitangent = vertex.bitangent.normalize();
+            } else {
+                // any basis around the normal beats NaNs in the shader
+                let normal = vertex.normal;
+                let reference = if normal.x.abs() < 0.9 {
+                    Vec3::unit_x()
+                } else {
+                    Vec3::unit_z()
+                };
+                vertex.bitangent = normal.cross(reference).normalize();
+                vertex.tangent = vertex.bitangent.cross(normal).normalize();
+            }
+        }
+    }
+
+    /// Checks that the indices form whole triangles of existing vertices.
+    pub fn validate(&self) -> Result<()> {
+        if !self.data.indices.len().is_multiple_of(3) {
+            return Err(anyhow!(
+                "Mesh \"{}\" has {} indices, which isn't a whole number of triangles",
+                self.name,
+                self.data.indices.len()
+            ));
+        }
+        let vertex_count = self.vertex_count();
+        if let Some(index) = self
+            .data
+            .indices
+            .iter()
+            .find(|index| **index >= vertex_count)
+        {
+            return Err(anyhow!(
+                "Mesh \"{}\" has index {} but only {} vertices",
+                self.name,
+                index,
+                vertex_count
+            ));
+        }
+        Ok(())
+    }
+
+    /// Uploads the geometry, keeping it on the mesh as `data` if `retain_data` is set.
+    pub fn build(self, device: &wgpu::Device, retain_data: bool) -> Result<model::Mesh> {
+        self.validate()?;
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", self.name)),
+            contents: bytemuck::cast_slice(&self.data.vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", self.name)),
+            contents: bytemuck::cast_slice(&self.data.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Ok(model::Mesh {
+            num_elements: self.data.indices.len() as u32,
+            name: self.name,
+            vertex_buffer,
+            index_buffer,
+            material: self.material,
+            splat_weights_buffer: None,
+            bounds: model::Bounds::from_points(
+                self.data.vertices.iter().map(|vertex| vertex.position),
+            ),
+            data: if retain_data { Some(self.data) } else { None },
+        })
+    }
+}
@@ -0,0 +1,645 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+// Leads a vertex buffer encoding in its high nibble; the low nibble is the codec version
+const VERTEX_HEADER: u8 = 0xa0;
+// Leads an index buffer encoding, as above
+const INDEX_HEADER: u8 = 0xe0;
+// Leads an index sequence encoding, as above
+const SEQUENCE_HEADER: u8 = 0xd0;
+
+// Vertices are encoded in blocks which fit this many bytes, and at most VERTEX_BLOCK_MAX_SIZE
+const VERTEX_BLOCK_SIZE_BYTES: usize = 8192;
+const VERTEX_BLOCK_MAX_SIZE: usize = 256;
+// Each byte of a block's vertices is encoded in groups of this many
+const BYTE_GROUP_SIZE: usize = 16;
+// A vertex encoding ends with the first vertex, padded to at least this many bytes
+const TAIL_MIN_SIZE: usize = 32;
+// An index buffer encoding ends with this table of auxiliary triangle codes
+const CODE_AUX_TABLE_SIZE: usize = 16;
+
+/// How a buffer view's data was encoded, named as EXT_meshopt_compression names them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Mode {
+    // vertex attributes, see decode_vertex_buffer
+    Attributes,
+    // a triangle list, see decode_index_buffer
+    Triangles,
+    // any other indices, see decode_index_sequence
+    Indices,
+}
+
+/// The filter applied to attributes after decoding, named as EXT_meshopt_compression names
+/// them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Filter {
+    #[default]
+    None,
+    // unit vectors as 8 or 16 bit octahedral coordinates
+    Octahedral,
+    // unit quaternions as three 16 bit components and the index of the fourth
+    Quaternion,
+    // floats as a 24 bit mantissa and 8 bit exponent
+    Exponential,
+}
+
+/// Decodes `count` elements of `stride` bytes encoded by meshoptimizer, as a glTF buffer view
+/// compressed with EXT_meshopt_compression is, returning their bytes, little endian.
+pub fn decode(
+    mode: Mode,
+    filter: Filter,
+    count: usize,
+    stride: usize,
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    match mode {
+        Mode::Attributes => {
+            let mut decoded = decode_vertex_buffer(count, stride, data)?;
+            apply_filter(filter, stride, &mut decoded)?;
+            Ok(decoded)
+        }
+        Mode::Triangles | Mode::Indices if filter != Filter::None => {
+            Err(anyhow!("{:?} can't be filtered, only attributes can", mode))
+        }
+        Mode::Triangles => decode_index_buffer(count, stride, data),
+        Mode::Indices => decode_index_sequence(count, stride, data),
+    }
+}
+
+/// Decodes `count` vertices of `stride` bytes each, a multiple of 4 up to 256, encoded with
+/// meshoptimizer's vertex codec. Each byte of a vertex is stored as its difference from the
+/// same byte of the previous vertex, in groups packed to the fewest bits that hold them.
+pub fn decode_vertex_buffer(count: usize, stride: usize, data: &[u8]) -> Result<Vec<u8>> {
+    if stride == 0 || stride > 256 || !stride.is_multiple_of(4) {
+        return Err(anyhow!(
+            "Vertex stride {} isn't a multiple of 4 up to 256",
+            stride
+        ));
+    }
+    if data.len() < 1 + stride {
+        return Err(anyhow!("Vertex data ends early"));
+    }
+    if data[0] & 0xf0 != VERTEX_HEADER {
+        return Err(anyhow!("Not a meshopt vertex buffer"));
+    }
+    let version = data[0] & 0x0f;
+    if version > 0 {
+        return Err(anyhow!(
+            "Unsupported meshopt vertex codec version {}",
+            version
+        ));
+    }
+
+    // the first vertex is predicted from the one stored in the tail
+    let mut last_vertex = data[data.len() - stride..].to_vec();
+    let block_size = vertex_block_size(stride);
+    let mut vertices = vec![0; count * stride];
+    let mut offset = 1;
+    let mut vertex = 0;
+    while vertex < count {
+        let block_count = block_size.min(count - vertex);
+        let block = &mut vertices[vertex * stride..(vertex + block_count) * stride];
+        offset = decode_vertex_block(data, offset, block, block_count, stride, &mut last_vertex)?;
+        vertex += block_count;
+    }
+
+    if data.len() - offset != stride.max(TAIL_MIN_SIZE) {
+        return Err(anyhow!("Vertex data doesn't end where its vertices do"));
+    }
+    Ok(vertices)
+}
+
+/// Decodes a triangle list of `count` indices of `index_size` bytes, 2 or 4, encoded with
+/// meshoptimizer's index codec, which refers back to recently seen edges and vertices.
+pub fn decode_index_buffer(count: usize, index_size: usize, data: &[u8]) -> Result<Vec<u8>> {
+    if !count.is_multiple_of(3) {
+        return Err(anyhow!(
+            "{} indices isn't a whole number of triangles",
+            count
+        ));
+    }
+    check_index_size(index_size)?;
+    // the header, a code per triangle and the table of auxiliary codes
+    if data.len() < 1 + count / 3 + CODE_AUX_TABLE_SIZE {
+        return Err(anyhow!("Index data ends early"));
+    }
+    if data[0] & 0xf0 != INDEX_HEADER {
+        return Err(anyhow!("Not a meshopt index buffer"));
+    }
+    let version = data[0] & 0x0f;
+    if version > 1 {
+        return Err(anyhow!(
+            "Unsupported meshopt index codec version {}",
+            version
+        ));
+    }
+
+    let mut edge_fifo = [[u32::MAX; 2]; 16];
+    let mut vertex_fifo = [u32::MAX; 16];
+    let mut edge_fifo_offset = 0;
+    let mut vertex_fifo_offset = 0;
+    let mut next = 0u32;
+    let mut last = 0u32;
+    // the codes naming a vertex fifo entry; version 1 spends 13 and 14 on small index deltas
+    let fifo_code_max = if version >= 1 { 13 } else { 15 };
+
+    let codes = &data[1..1 + count / 3];
+    let data_end = data.len() - CODE_AUX_TABLE_SIZE;
+    let code_aux_table = &data[data_end..];
+    let mut reader = ByteReader {
+        data: &data[..data_end],
+        offset: 1 + count / 3,
+    };
+    let mut indices = Vec::with_capacity(count * index_size);
+
+    for &code in codes {
+        if code < 0xf0 {
+            // the triangle shares an edge from the fifo, and names its third vertex
+            let edge = edge_fifo[(edge_fifo_offset + 15 - (code >> 4) as usize) & 15];
+            let (a, b) = (edge[0], edge[1]);
+            let vertex_code = (code & 15) as usize;
+            let c = if vertex_code < fifo_code_max {
+                let c = if vertex_code == 0 {
+                    next += 1;
+                    next - 1
+                } else {
+                    vertex_fifo[(vertex_fifo_offset + 15 - vertex_code) & 15]
+                };
+                if vertex_code == 0 {
+                    push_vertex_fifo(&mut vertex_fifo, &mut vertex_fifo_offset, c);
+                }
+                c
+            } else {
+                last = match vertex_code {
+                    13 => last.wrapping_sub(1),
+                    14 => last.wrapping_add(1),
+                    _ => decode_index(&mut reader, last)?,
+                };
+                push_vertex_fifo(&mut vertex_fifo, &mut vertex_fifo_offset, last);
+                last
+            };
+            write_triangle(&mut indices, index_size, a, b, c);
+            push_edge_fifo(&mut edge_fifo, &mut edge_fifo_offset, c, b);
+            push_edge_fifo(&mut edge_fifo, &mut edge_fifo_offset, a, c);
+        } else {
+            // a triangle sharing no edge; its vertices are new, from the vertex fifo, or when
+            // the code and auxiliary byte follow in the data, free indices coded as 15
+            let (code_aux, free) = if code < 0xfe {
+                (code_aux_table[(code & 15) as usize], false)
+            } else {
+                (reader.byte()?, true)
+            };
+            if free && code_aux == 0 {
+                next = 0;
+            }
+            let a_is_free = code == 0xff;
+            let b_code = (code_aux >> 4) as usize;
+            let c_code = (code_aux & 15) as usize;
+            let b_is_free = free && b_code == 15;
+            let c_is_free = free && c_code == 15;
+            let vertex = |vertex_code: usize, next: &mut u32| {
+                if vertex_code == 0 {
+                    *next += 1;
+                    *next - 1
+                } else {
+                    vertex_fifo[(vertex_fifo_offset + 16 - vertex_code) & 15]
+                }
+            };
+            let mut a = if a_is_free { 0 } else { vertex(0, &mut next) };
+            let mut b = vertex(b_code, &mut next);
+            let mut c = vertex(c_code, &mut next);
+            if a_is_free {
+                last = decode_index(&mut reader, last)?;
+                a = last;
+            }
+            if b_is_free {
+                last = decode_index(&mut reader, last)?;
+                b = last;
+            }
+            if c_is_free {
+                last = decode_index(&mut reader, last)?;
+                c = last;
+            }
+            write_triangle(&mut indices, index_size, a, b, c);
+            push_vertex_fifo(&mut vertex_fifo, &mut vertex_fifo_offset, a);
+            if b_code == 0 || b_is_free {
+                push_vertex_fifo(&mut vertex_fifo, &mut vertex_fifo_offset, b);
+            }
+            if c_code == 0 || c_is_free {
+                push_vertex_fifo(&mut vertex_fifo, &mut vertex_fifo_offset, c);
+            }
+            push_edge_fifo(&mut edge_fifo, &mut edge_fifo_offset, b, a);
+            push_edge_fifo(&mut edge_fifo, &mut edge_fifo_offset, c, b);
+            push_edge_fifo(&mut edge_fifo, &mut edge_fifo_offset, a, c);
+        }
+    }
+
+    if reader.offset != data_end {
+        return Err(anyhow!("Index data doesn't end where its triangles do"));
+    }
+    Ok(indices)
+}
+
+/// Decodes `count` indices of `index_size` bytes, 2 or 4, in no particular order, encoded
+/// with meshoptimizer's index sequence codec as deltas from one of two previous indices.
+pub fn decode_index_sequence(count: usize, index_size: usize, data: &[u8]) -> Result<Vec<u8>> {
+    check_index_size(index_size)?;
+    // the header, at least a byte per index and a 4 byte tail
+    if data.len() < 1 + count + 4 {
+        return Err(anyhow!("Index sequence data ends early"));
+    }
+    if data[0] & 0xf0 != SEQUENCE_HEADER {
+        return Err(anyhow!("Not a meshopt index sequence"));
+    }
+    let version = data[0] & 0x0f;
+    if version > 1 {
+        return Err(anyhow!(
+            "Unsupported meshopt index sequence version {}",
+            version
+        ));
+    }
+
+    let data_end = data.len() - 4;
+    let mut reader = ByteReader {
+        data: &data[..data_end],
+        offset: 1,
+    };
+    let mut last = [0u32; 2];
+    let mut indices = Vec::with_capacity(count * index_size);
+    for _ in 0..count {
+        let v = reader.vbyte()?;
+        // the low bit picks the previous index the delta is from
+        let baseline = (v & 1) as usize;
+        let delta = unzigzag(v >> 1);
+        last[baseline] = last[baseline].wrapping_add(delta);
+        write_index(&mut indices, index_size, last[baseline]);
+    }
+
+    if reader.offset != data_end {
+        return Err(anyhow!(
+            "Index sequence data doesn't end where its indices do"
+        ));
+    }
+    Ok(indices)
+}
+
+/// Reverses `filter` on decoded attributes of `stride` bytes each, in place
+pub fn apply_filter(filter: Filter, stride: usize, data: &mut [u8]) -> Result<()> {
+    match filter {
+        Filter::None => {}
+        Filter::Octahedral if stride == 4 => {
+            for vertex in data.chunks_exact_mut(4) {
+                let [x, y, z] = decode_octahedral([0, 1, 2].map(|i| vertex[i] as i8 as f32), 127.0);
+                vertex[0] = x as i8 as u8;
+                vertex[1] = y as i8 as u8;
+                vertex[2] = z as i8 as u8;
+            }
+        }
+        Filter::Octahedral if stride == 8 => {
+            for vertex in data.chunks_exact_mut(8) {
+                let components = [0, 1, 2].map(|i| read_i16(vertex, i) as f32);
+                let decoded = decode_octahedral(components, 32767.0);
+                for (i, value) in decoded.into_iter().enumerate() {
+                    write_i16(vertex, i, value as i16);
+                }
+            }
+        }
+        Filter::Quaternion if stride == 8 => {
+            let scale = std::f32::consts::FRAC_1_SQRT_2;
+            for vertex in data.chunks_exact_mut(8) {
+                let packed = read_i16(vertex, 3);
+                // the fourth component holds the quaternion's scale, and which component was
+                // dropped, as the largest, in its low two bits
+                let component_scale = scale / (packed | 3) as f32;
+                let [x, y, z] = [0, 1, 2].map(|i| read_i16(vertex, i) as f32 * component_scale);
+                let w = (1.0 - x * x - y * y - z * z).max(0.0).sqrt();
+                let dropped = (packed & 3) as usize;
+                write_i16(vertex, (dropped + 1) & 3, round_signed(x * 32767.0) as i16);
+                write_i16(vertex, (dropped + 2) & 3, round_signed(y * 32767.0) as i16);
+                write_i16(vertex, (dropped + 3) & 3, round_signed(z * 32767.0) as i16);
+                write_i16(vertex, dropped, (w * 32767.0 + 0.5) as i16);
+            }
+        }
+        Filter::Exponential if stride.is_multiple_of(4) => {
+            for component in data.chunks_exact_mut(4) {
+                let v =
+                    u32::from_le_bytes([component[0], component[1], component[2], component[3]]);
+                let mantissa = ((v << 8) as i32) >> 8;
+                let exponent = (v as i32) >> 24;
+                let value = f32::from_bits(((exponent + 127) as u32) << 23) * mantissa as f32;
+                component.copy_from_slice(&value.to_bits().to_le_bytes());
+            }
+        }
+        _ => {
+            return Err(anyhow!(
+                "The {:?} filter can't decode a stride of {} bytes",
+                filter,
+                stride
+            ))
+        }
+    }
+    Ok(())
+}
+
+// The vertices per block, as many as fit VERTEX_BLOCK_SIZE_BYTES in whole byte groups
+fn vertex_block_size(stride: usize) -> usize {
+    let size = (VERTEX_BLOCK_SIZE_BYTES / stride) & !(BYTE_GROUP_SIZE - 1);
+    size.min(VERTEX_BLOCK_MAX_SIZE)
+}
+
+// Decodes a block of `count` vertices from data at `offset` into `vertices`, a byte of every
+// vertex at a time, returning the offset past it. `last_vertex` predicts the block's first
+// vertex, and is left holding its last.
+fn decode_vertex_block(
+    data: &[u8],
+    mut offset: usize,
+    vertices: &mut [u8],
+    count: usize,
+    stride: usize,
+    last_vertex: &mut [u8],
+) -> Result<usize> {
+    let aligned_count = (count + BYTE_GROUP_SIZE - 1) & !(BYTE_GROUP_SIZE - 1);
+    let mut deltas = [0u8; VERTEX_BLOCK_MAX_SIZE];
+    for k in 0..stride {
+        offset = decode_bytes(data, offset, &mut deltas[..aligned_count])?;
+        let mut previous = last_vertex[k];
+        for (i, delta) in deltas[..count].iter().enumerate() {
+            let value = (*delta >> 1 ^ (*delta & 1).wrapping_neg()).wrapping_add(previous);
+            vertices[i * stride + k] = value;
+            previous = value;
+        }
+    }
+    last_vertex.copy_from_slice(&vertices[(count - 1) * stride..count * stride]);
+    Ok(offset)
+}
+
+// Decodes the byte groups filling `bytes` from data at `offset`, returning the offset past
+// them. A header of 2 bits per group gives each group's width: all zero, 2 or 4 bits per
+// byte with the all ones value escaping to a full byte which follows, or 8 bits.
+fn decode_bytes(data: &[u8], offset: usize, bytes: &mut [u8]) -> Result<usize> {
+    let group_count = bytes.len() / BYTE_GROUP_SIZE;
+    let header_size = group_count.div_ceil(4);
+    let header = data
+        .get(offset..offset + header_size)
+        .ok_or_else(|| anyhow!("Vertex data ends early"))?;
+    let mut offset = offset + header_size;
+    for (group, bytes) in bytes.chunks_exact_mut(BYTE_GROUP_SIZE).enumerate() {
+        let bits_log2 = (header[group / 4] >> ((group % 4) * 2)) & 3;
+        offset = match bits_log2 {
+            0 => {
+                bytes.fill(0);
+                offset
+            }
+            1 => decode_bytes_group(data, offset, bytes, 2)?,
+            2 => decode_bytes_group(data, offset, bytes, 4)?,
+            _ => {
+                let group = data
+                    .get(offset..offset + BYTE_GROUP_SIZE)
+                    .ok_or_else(|| anyhow!("Vertex data ends early"))?;
+                bytes.copy_from_slice(group);
+                offset + BYTE_GROUP_SIZE
+            }
+        };
+    }
+    Ok(offset)
+}
+
+// Decodes a group of bytes packed `bits` to a byte, most significant first, followed by the
+// escaped bytes
+fn decode_bytes_group(data: &[u8], offset: usize, bytes: &mut [u8], bits: u32) -> Result<usize> {
+    let packed_size = BYTE_GROUP_SIZE * bits as usize / 8;
+    let packed = data
+        .get(offset..offset + packed_size)
+        .ok_or_else(|| anyhow!("Vertex data ends early"))?;
+    let escape = (1u8 << bits) - 1;
+    let per_byte = (8 / bits) as usize;
+    let mut escaped = offset + packed_size;
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let shift = 8 - bits * (i % per_byte + 1) as u32;
+        let value = (packed[i / per_byte] >> shift) & escape;
+        *byte = if value == escape {
+            let value = *data
+                .get(escaped)
+                .ok_or_else(|| anyhow!("Vertex data ends early"))?;
+            escaped += 1;
+            value
+        } else {
+            value
+        };
+    }
+    Ok(escaped)
+}
+
+// The unit vector of octahedral coordinates (x, y) and z, the scale they're encoded at,
+// scaled back up to `max`
+fn decode_octahedral(components: [f32; 3], max: f32) -> [f32; 3] {
+    let [mut x, mut y, z] = components;
+    let z = z - x.abs() - y.abs();
+    // the lower hemisphere is folded over the upper's diagonals
+    let t = z.min(0.0);
+    x += if x >= 0.0 { t } else { -t };
+    y += if y >= 0.0 { t } else { -t };
+    let scale = max / (x * x + y * y + z * z).sqrt();
+    [x, y, z].map(|c| round_signed(c * scale) as f32)
+}
+
+// Rounds half away from zero, truncating to an integer
+fn round_signed(value: f32) -> i32 {
+    (value + if value >= 0.0 { 0.5 } else { -0.5 }) as i32
+}
+
+fn read_i16(bytes: &[u8], index: usize) -> i16 {
+    i16::from_le_bytes([bytes[index * 2], bytes[index * 2 + 1]])
+}
+
+fn write_i16(bytes: &mut [u8], index: usize, value: i16) {
+    bytes[index * 2..index * 2 + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn check_index_size(index_size: usize) -> Result<()> {
+    match index_size {
+        2 | 4 => Ok(()),
+        _ => Err(anyhow!("Indices must be 2 or 4 bytes, not {}", index_size)),
+    }
+}
+
+fn write_index(indices: &mut Vec<u8>, index_size: usize, index: u32) {
+    if index_size == 2 {
+        indices.extend_from_slice(&(index as u16).to_le_bytes());
+    } else {
+        indices.extend_from_slice(&index.to_le_bytes());
+    }
+}
+
+fn write_triangle(indices: &mut Vec<u8>, index_size: usize, a: u32, b: u32, c: u32) {
+    for index in [a, b, c] {
+        write_index(indices, index_size, index);
+    }
+}
+
+fn push_edge_fifo(fifo: &mut [[u32; 2]; 16], offset: &mut usize, a: u32, b: u32) {
+    fifo[*offset] = [a, b];
+    *offset = (*offset + 1) & 15;
+}
+
+fn push_vertex_fifo(fifo: &mut [u32; 16], offset: &mut usize, v: u32) {
+    fifo[*offset] = v;
+    *offset = (*offset + 1) & 15;
+}
+
+// Decodes a free index, zigzag encoded as its difference from `last`
+fn decode_index(reader: &mut ByteReader, last: u32) -> Result<u32> {
+    Ok(last.wrapping_add(unzigzag(reader.vbyte()?)))
+}
+
+fn unzigzag(v: u32) -> u32 {
+    (v >> 1) ^ (v & 1).wrapping_neg()
+}
+
+// Reads the bytes of an index encoding, failing rather than panicking on data which ends
+// early
+struct ByteReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn byte(&mut self) -> Result<u8> {
+        let byte = *self
+            .data
+            .get(self.offset)
+            .ok_or_else(|| anyhow!("Index data ends early"))?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    // A variable length integer of up to 5 bytes, 7 bits a byte, low bits first; the high bit
+    // of each byte but the fifth marks another following
+    fn vbyte(&mut self) -> Result<u32> {
+        let lead = self.byte()?;
+        if lead < 128 {
+            return Ok(lead as u32);
+        }
+        let mut result = (lead & 127) as u32;
+        let mut shift = 7;
+        for _ in 0..4 {
+            let group = self.byte()?;
+            result |= ((group & 127) as u32) << shift;
+            shift += 7;
+            if group < 128 {
+                break;
+            }
+        }
+        Ok(result)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Flattens indices to the little endian bytes the decoders write
+    fn index_bytes(indices: &[u32], index_size: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for &index in indices {
+            write_index(&mut bytes, index_size, index);
+        }
+        bytes
+    }
+
+    // Five vertices of three u16 positions and two u8 texture coordinates, encoded by
+    // meshoptimizer's meshopt_encodeVertexBuffer
+    const VERTICES: [([u16; 3], [u8; 2]); 5] = [
+        ([0, 0, 0], [0, 0]),
+        ([1000, 0, 0], [255, 0]),
+        ([1000, 1000, 0], [255, 255]),
+        ([0, 1000, 0], [0, 255]),
+        ([500, 500, 65535], [128, 128]),
+    ];
+    const ENCODED_VERTICES: [u8; 84] = [
+        160, 1, 51, 192, 0, 0, 47, 48, 23, 1, 51, 128, 0, 0, 6, 5, 1, 12, 192, 0, 0, 47, 24, 1, 12,
+        192, 0, 0, 6, 3, 1, 0, 64, 0, 0, 1, 0, 64, 0, 0, 1, 18, 192, 0, 0, 255, 1, 4, 192, 0, 0,
+        253, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0,
+    ];
+
+    // Four triangles encoded by meshopt_encodeIndexBuffer, which rotated the last of them
+    const TRIANGLES: [u32; 12] = [0, 1, 2, 0, 2, 3, 2, 1, 4, 2, 4, 3];
+    const ENCODED_TRIANGLES: [u8; 21] = [
+        224, 240, 0, 48, 1, 0, 118, 135, 86, 103, 120, 169, 134, 101, 137, 104, 152, 1, 105, 0, 0,
+    ];
+
+    // Indices encoded as meshopt_encodeIndexSequence would, each a zigzagged delta from the
+    // previous index of the baseline its low bit picks; 1 is from the untouched second baseline
+    // and 70000 takes three bytes
+    const SEQUENCE: [u32; 7] = [0, 1, 2, 300, 299, 1, 70000];
+    const ENCODED_SEQUENCE: [u8; 15] = [209, 0, 4, 4, 168, 9, 2, 5, 148, 130, 17, 0, 0, 0, 0];
+
+    #[test]
+    fn decodes_vertex_buffer() {
+        let expected = VERTICES
+            .iter()
+            .flat_map(|(position, tex_coord)| {
+                position
+                    .iter()
+                    .flat_map(|c| c.to_le_bytes())
+                    .chain(tex_coord.iter().copied())
+            })
+            .collect::<Vec<_>>();
+        let decoded = decode_vertex_buffer(VERTICES.len(), 8, &ENCODED_VERTICES).unwrap();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn rejects_bad_vertex_buffers() {
+        let mut wrong_header = ENCODED_VERTICES;
+        wrong_header[0] = INDEX_HEADER;
+        assert!(decode_vertex_buffer(VERTICES.len(), 8, &wrong_header).is_err());
+        assert!(decode_vertex_buffer(VERTICES.len(), 8, &ENCODED_VERTICES[..40]).is_err());
+        assert!(decode_vertex_buffer(VERTICES.len(), 7, &ENCODED_VERTICES).is_err());
+    }
+
+    #[test]
+    fn decodes_index_buffer() {
+        for index_size in [2, 4] {
+            let decoded =
+                decode_index_buffer(TRIANGLES.len(), index_size, &ENCODED_TRIANGLES).unwrap();
+            assert_eq!(decoded, index_bytes(&TRIANGLES, index_size));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_index_buffers() {
+        let mut wrong_header = ENCODED_TRIANGLES;
+        wrong_header[0] = VERTEX_HEADER;
+        assert!(decode_index_buffer(TRIANGLES.len(), 4, &wrong_header).is_err());
+        assert!(decode_index_buffer(TRIANGLES.len(), 4, &ENCODED_TRIANGLES[..10]).is_err());
+        assert!(decode_index_buffer(TRIANGLES.len(), 3, &ENCODED_TRIANGLES).is_err());
+        assert!(decode_index_buffer(TRIANGLES.len() - 1, 4, &ENCODED_TRIANGLES).is_err());
+    }
+
+    #[test]
+    fn decodes_index_sequence() {
+        let decoded = decode_index_sequence(SEQUENCE.len(), 4, &ENCODED_SEQUENCE).unwrap();
+        assert_eq!(decoded, index_bytes(&SEQUENCE, 4));
+        // wider indices are truncated to the requested size, as meshoptimizer does
+        let decoded = decode_index_sequence(SEQUENCE.len(), 2, &ENCODED_SEQUENCE).unwrap();
+        assert_eq!(decoded, index_bytes(&SEQUENCE, 2));
+    }
+
+    #[test]
+    fn rejects_bad_index_sequences() {
+        let mut wrong_header = ENCODED_SEQUENCE;
+        wrong_header[0] = INDEX_HEADER;
+        assert!(decode_index_sequence(SEQUENCE.len(), 4, &wrong_header).is_err());
+        let mut future_version = ENCODED_SEQUENCE;
+        future_version[0] = SEQUENCE_HEADER | 2;
+        assert!(decode_index_sequence(SEQUENCE.len(), 4, &future_version).is_err());
+        assert!(decode_index_sequence(SEQUENCE.len(), 4, &ENCODED_SEQUENCE[..10]).is_err());
+        assert!(decode_index_sequence(SEQUENCE.len() - 1, 4, &ENCODED_SEQUENCE).is_err());
+    }
+}
@@ -1,12 +1,62 @@
+//! A forward renderer built on wgpu: scenes of lit, shadowed models, post effects and the
+//! window and event loop to show them, see app::run. examples/demo.rs builds a small scene.
+
 pub mod app;
+pub mod bake;
 pub mod camera;
 pub mod camera_controller;
+pub mod clipmap;
 pub mod compositor;
+pub mod custom_shader;
+pub mod debug_draw;
+pub mod debug_groups;
+pub mod depth_pass;
+pub mod environment;
+pub mod frame_context;
+pub mod frame_pacer;
+pub mod frustum;
+pub mod gltf;
 pub mod gpu_state;
+pub mod graphics_settings;
+pub mod grass;
+pub mod input_recording;
+pub mod instance_animation;
+pub mod jobs;
 pub mod light;
+pub mod material_override;
+pub mod material_preview;
+pub mod material_variant;
+pub mod mesh_builder;
+pub mod meshopt;
 pub mod model;
+pub mod occlusion;
+pub mod packed_asset;
+pub mod portal;
+pub mod prelude;
+pub mod readback;
+pub mod reflection_probes;
+pub mod render_hooks;
 pub mod render_pipeline;
 pub mod resources;
+pub mod scatter;
 pub mod scene;
+pub mod screenshot;
+pub mod shader_preprocessor;
+pub mod shader_reflection;
+pub mod shadow;
+pub mod shadow_atlas;
+pub mod skinning;
+pub mod socket;
+pub mod spline_mesh;
+pub mod static_batch;
+pub mod terrain;
 pub mod texture;
+pub mod texture_residency;
+pub mod texture_streaming;
+pub mod transient_buffers;
+pub mod transient_textures;
 pub mod util;
+pub mod vertex_animation;
+pub mod volumetric_fog;
+pub mod weather;
+pub mod wind;
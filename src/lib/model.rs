@@ -1,21 +1,26 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{collections::HashMap, ops::Range, rc::Rc};
 
 use cgmath::prelude::*;
 use wgpu::{util::DeviceExt, vertex_attr_array};
 
 use super::{
     camera,
+    custom_shader::CustomShader,
+    environment,
     gpu_state::GpuState,
     light,
-    render_pipeline::{self, RenderPipelineVendor},
-    resources, texture,
+    material_variant::{AlphaMode, MaterialTextures, MaterialVariantKey},
+    render_pipeline::{self, DepthMode, RenderPipelineVendor},
+    resources, shader_preprocessor, shader_reflection, socket, texture,
     util::*,
 };
 
 ///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
 static MODEL_VERTEX_ATTRIBS: [wgpu::VertexAttribute; 5] = vertex_attr_array![0 => Float32x3, 1 => Float32x2, 2 => Float32x3, 3 => Float32x3, 4 => Float32x3];
-static MODEL_INSTANCE_ATTRIBS: [wgpu::VertexAttribute; 7] = wgpu::vertex_attr_array![5 => Float32x4, 6 => Float32x4, 7 => Float32x4, 8 => Float32x4, 9 => Float32x3, 10 => Float32x3, 11 => Float32x3, ];
+static MODEL_INSTANCE_ATTRIBS: [wgpu::VertexAttribute; 4] =
+    wgpu::vertex_attr_array![5 => Float32x4, 6 => Float32x4, 13 => Float32, 14 => Float32, ];
+static SPLAT_WEIGHTS_ATTRIBS: [wgpu::VertexAttribute; 1] = vertex_attr_array![12 => Float32x4];
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
@@ -31,7 +36,7 @@ unsafe impl bytemuck::Pod for ModelVertex {}
 unsafe impl bytemuck::Zeroable for ModelVertex {}
 
 impl ModelVertex {
-    fn vertex_buffer_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
+    pub fn vertex_buffer_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Vertex,
@@ -46,6 +51,10 @@ impl ModelVertex {
 pub struct Instance {
     position: Point3,
     rotation: Quat,
+    scale: f32,
+    time_offset: f32,
+    phase: f32,
+    user_data: Option<u32>,
 }
 
 impl Instance {
@@ -57,13 +66,94 @@ impl Instance {
         Self {
             position: position.into(),
             rotation: rotation.into(),
+            scale: 1.0,
+            time_offset: 0.0,
+            phase: 0.0,
+            user_data: None,
         }
     }
 
+    /// Uniformly scales the instance, so its normals are unaffected.
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Offsets the playback of the instance's vertex animation, in seconds, so instances of a
+    /// crowd needn't move in lockstep. See VertexAnimation.
+    pub fn with_time_offset(mut self, time_offset: f32) -> Self {
+        self.time_offset = time_offset;
+        self
+    }
+
+    /// A per-instance value in [0, 1) passed to vertex shaders as `phase`, e.g. a random seed,
+    /// so instanced effects needn't move in lockstep. Wind sway shifts its gusts by this
+    /// fraction of their wavelength.
+    pub fn with_phase(mut self, phase: f32) -> Self {
+        self.phase = phase.rem_euclid(1.0);
+        self
+    }
+
+    /// Tags the instance with a value of the caller's, e.g. an entity id or an enum's
+    /// discriminant, mapping it back to the caller's own objects. Never sent to the GPU.
+    pub fn with_user_data(mut self, user_data: u32) -> Self {
+        self.user_data = Some(user_data);
+        self
+    }
+
+    pub fn position(&self) -> Point3 {
+        self.position
+    }
+
+    pub fn rotation(&self) -> Quat {
+        self.rotation
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn time_offset(&self) -> f32 {
+        self.time_offset
+    }
+
+    pub fn phase(&self) -> f32 {
+        self.phase
+    }
+
+    pub fn set_position<P: Into<Point3>>(&mut self, position: P) {
+        self.position = position.into();
+    }
+
+    pub fn set_rotation<R: Into<Quat>>(&mut self, rotation: R) {
+        self.rotation = rotation.into();
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale;
+    }
+
+    pub fn user_data(&self) -> Option<u32> {
+        self.user_data
+    }
+
+    pub fn set_user_data(&mut self, user_data: Option<u32>) {
+        self.user_data = user_data;
+    }
+
+    /// The instance's model matrix
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_translation(self.position.to_vec())
+            * Mat4::from(self.rotation)
+            * Mat4::from_scale(self.scale)
+    }
+
     fn as_data(&self) -> InstanceData {
         InstanceData {
-            model: Mat4::from_translation(self.position.to_vec()) * Mat4::from(self.rotation),
-            normal_matrix: Mat3::from(self.rotation),
+            position_scale: self.position.to_vec().extend(self.scale),
+            rotation: self.rotation.v.extend(self.rotation.s),
+            time_offset: self.time_offset,
+            phase: self.phase,
         }
     }
 
@@ -76,11 +166,17 @@ impl Instance {
     }
 }
 
+// The instance's transform is rebuilt in the vertex shader from its parts, which take well
+// under half the space of a model and normal matrix; see shaders/instance.wgsl
 #[repr(C)]
 #[derive(Copy, Clone)]
 struct InstanceData {
-    model: Mat4,
-    normal_matrix: Mat3,
+    // xyz: position, w: uniform scale
+    position_scale: Vec4,
+    // the rotation quaternion as (x, y, z, w)
+    rotation: Vec4,
+    time_offset: f32,
+    phase: f32,
 }
 
 unsafe impl bytemuck::Pod for InstanceData {}
@@ -89,20 +185,136 @@ unsafe impl bytemuck::Zeroable for InstanceData {}
 impl Default for InstanceData {
     fn default() -> Self {
         Self {
-            model: Mat4::identity(),
-            normal_matrix: Mat3::identity(),
+            position_scale: Vec4::new(0.0, 0.0, 0.0, 1.0),
+            rotation: Vec4::new(0.0, 0.0, 0.0, 1.0),
+            time_offset: 0.0,
+            phase: 0.0,
         }
     }
 }
 
 ///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
+/// A CPU copy of a mesh's geometry, e.g. for picking or physics.
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+/// An axis aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Bounds {
+    /// The bounds of the points, None if there are none.
+    pub fn from_points<I: IntoIterator<Item = Point3>>(points: I) -> Option<Self> {
+        points
+            .into_iter()
+            .fold(None, |bounds: Option<Self>, point| {
+                Some(match bounds {
+                    Some(bounds) => Self {
+                        min: Point3::new(
+                            bounds.min.x.min(point.x),
+                            bounds.min.y.min(point.y),
+                            bounds.min.z.min(point.z),
+                        ),
+                        max: Point3::new(
+                            bounds.max.x.max(point.x),
+                            bounds.max.y.max(point.y),
+                            bounds.max.z.max(point.z),
+                        ),
+                    },
+                    None => Self {
+                        min: point,
+                        max: point,
+                    },
+                })
+            })
+    }
+
+    pub fn corners(&self) -> [Point3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Point3::new(min.x, min.y, min.z),
+            Point3::new(max.x, min.y, min.z),
+            Point3::new(min.x, max.y, min.z),
+            Point3::new(max.x, max.y, min.z),
+            Point3::new(min.x, min.y, max.z),
+            Point3::new(max.x, min.y, max.z),
+            Point3::new(min.x, max.y, max.z),
+            Point3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    /// The bounds enclosing these bounds once transformed.
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        Self::from_points(
+            self.corners()
+                .iter()
+                .map(|corner| transform.transform_point(*corner)),
+        )
+        .unwrap()
+    }
+
+    pub fn intersects_sphere(&self, center: Point3, radius: f32) -> bool {
+        self.distance2_to(center) <= radius * radius
+    }
+
+    /// The distance from `point` to the nearest point of the bounds, 0 if it's inside.
+    pub fn distance_to(&self, point: Point3) -> f32 {
+        self.distance2_to(point).sqrt()
+    }
+
+    fn distance2_to(&self, point: Point3) -> f32 {
+        let closest = Point3::new(
+            point.x.clamp(self.min.x, self.max.x),
+            point.y.clamp(self.min.y, self.max.y),
+            point.z.clamp(self.min.z, self.max.z),
+        );
+        closest.distance2(point)
+    }
+}
+
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    // per-vertex terrain layer weights, see Mesh::set_splat_weights
+    pub splat_weights_buffer: Option<wgpu::Buffer>,
+    // retained if requested when built or loaded, see mesh_builder::MeshBuilder::build and
+    // resources::load_model
+    pub data: Option<MeshData>,
+    // of the vertex positions before any vertex stage displacement, None if empty
+    pub bounds: Option<Bounds>,
+}
+
+impl Mesh {
+    /// Assigns the weights of terrain layers 0 through 3 at each vertex, for use with
+    /// materials whose TerrainLayers have vertex_weights set.
+    pub fn set_splat_weights(&mut self, device: &wgpu::Device, weights: &[Vec4]) {
+        let weights: Vec<[f32; 4]> = weights.iter().map(|weights| (*weights).into()).collect();
+        self.splat_weights_buffer = Some(device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Mesh::splat_weights_buffer"),
+                contents: bytemuck::cast_slice(&weights),
+                usage: wgpu::BufferUsages::VERTEX,
+            },
+        ));
+    }
+
+    fn splat_weights_buffer_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vec4>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &SPLAT_WEIGHTS_ATTRIBS,
+        }
+    }
 }
 
 #[repr(C)]
@@ -112,12 +324,54 @@ pub struct MaterialUniform {
     diffuse: Vec4,
     specular: Vec4,
     shininess: f32,
-    _padding: [f32; 3],
+    toon_bands: f32,
+    toon_rim_strength: f32,
+    toon_rim_power: f32,
+    outline_color: Vec4,
+    outline_width: f32,
+    alpha_cutoff: f32,
+    wind_sway: f32,
+    // radians, see UvTransform
+    uv_rotation: f32,
+    // x: layer count, y: layer tiling
+    terrain: Vec4,
+    // x: world size, y: height scale
+    heightmap: Vec4,
+    // x: frame count, y: frame rate, z: vertex count, w: texture width
+    vertex_animation: Vec4,
+    // xy: offset, zw: scale
+    uv_transform: Vec4,
+    // x: columns, y: rows, z: frame count, w: frame rate; no flipbook when x is 0
+    flipbook: Vec4,
 }
 
 unsafe impl bytemuck::Pod for MaterialUniform {}
 unsafe impl bytemuck::Zeroable for MaterialUniform {}
 
+impl MaterialUniform {
+    fn set_uv_transform(&mut self, uv_transform: &UvTransform) {
+        self.uv_transform = Vec4::new(
+            uv_transform.offset.x,
+            uv_transform.offset.y,
+            uv_transform.scale.x,
+            uv_transform.scale.y,
+        );
+        self.uv_rotation = uv_transform.rotation.0;
+    }
+
+    fn set_flipbook(&mut self, flipbook: Option<&Flipbook>) {
+        self.flipbook = match flipbook {
+            Some(flipbook) if flipbook.columns > 0 && flipbook.rows > 0 => Vec4::new(
+                flipbook.columns as f32,
+                flipbook.rows as f32,
+                flipbook.frame_count() as f32,
+                flipbook.frame_rate,
+            ),
+            _ => Vec4::zero(),
+        };
+    }
+}
+
 impl Default for MaterialUniform {
     fn default() -> Self {
         let one = Vec4::new(1.0, 1.0, 1.0, 1.0);
@@ -126,7 +380,128 @@ impl Default for MaterialUniform {
             diffuse: one,
             specular: one,
             shininess: 1.0,
-            _padding: Default::default(),
+            toon_bands: 1.0,
+            toon_rim_strength: 0.0,
+            toon_rim_power: 1.0,
+            outline_color: Vec4::new(0.0, 0.0, 0.0, 1.0),
+            outline_width: 0.0,
+            alpha_cutoff: 0.5,
+            wind_sway: 0.0,
+            uv_rotation: 0.0,
+            terrain: Vec4::zero(),
+            heightmap: Vec4::zero(),
+            vertex_animation: Vec4::zero(),
+            uv_transform: Vec4::new(0.0, 0.0, 1.0, 1.0),
+            flipbook: Vec4::zero(),
+        }
+    }
+}
+
+/// Transforms a material's texture coordinates before its textures are sampled, e.g. to adjust
+/// tiling or scroll a texture without editing the mesh. Coordinates are scaled, then rotated
+/// counterclockwise about the origin, then offset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UvTransform {
+    pub offset: Vec2,
+    pub scale: Vec2,
+    pub rotation: Rad,
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        Self {
+            offset: Vec2::zero(),
+            scale: Vec2::new(1.0, 1.0),
+            rotation: rad(0.0),
+        }
+    }
+}
+
+/// A diffuse texture laid out as a grid of animation frames, e.g. for fire or screens, played
+/// left to right and top to bottom at the scene's time. Each frame spans the whole of the
+/// mesh's texture coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Flipbook {
+    pub columns: u32,
+    pub rows: u32,
+    // frames in the grid when the last row isn't full; 0 plays every cell
+    pub frame_count: u32,
+    // frames per second; the animation loops. 0 holds the first frame.
+    pub frame_rate: f32,
+}
+
+impl Default for Flipbook {
+    fn default() -> Self {
+        Self {
+            columns: 1,
+            rows: 1,
+            frame_count: 0,
+            frame_rate: 24.0,
+        }
+    }
+}
+
+impl Flipbook {
+    /// The number of frames played
+    pub fn frame_count(&self) -> u32 {
+        let cells = self.columns * self.rows;
+        if self.frame_count == 0 {
+            cells
+        } else {
+            self.frame_count.min(cells)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Outline {
+    // distance in world units the outline hull is pushed out along vertex normals
+    pub width: f32,
+    pub color: Vec4,
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Self {
+            width: 0.02,
+            color: Vec4::new(0.0, 0.0, 0.0, 1.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToonShading {
+    // number of flat diffuse bands
+    pub bands: u32,
+    pub rim_strength: f32,
+    // higher values narrow the rim light towards the silhouette
+    pub rim_power: f32,
+    pub outline: Option<Outline>,
+}
+
+impl Default for ToonShading {
+    fn default() -> Self {
+        Self {
+            bands: 3,
+            rim_strength: 0.5,
+            rim_power: 4.0,
+            outline: Some(Outline::default()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Shading {
+    #[default]
+    Phong,
+    Toon(ToonShading),
+}
+
+impl Shading {
+    fn outline(&self) -> Option<&Outline> {
+        match self {
+            Shading::Phong => None,
+            Shading::Toon(toon) => toon.outline.as_ref(),
         }
     }
 }
@@ -137,10 +512,32 @@ pub struct MaterialProperties<'a> {
     pub diffuse: Vec4,
     pub specular: Vec4,
     pub shininess: f32,
-    pub environment_map: Option<Rc<texture::Texture>>,
     pub diffuse_texture: Option<texture::Texture>,
     pub normal_texture: Option<texture::Texture>,
     pub shininess_texture: Option<texture::Texture>,
+    // multiplied into the ambient term, sampled from the red channel
+    pub ambient_occlusion_texture: Option<texture::Texture>,
+    pub shading: Shading,
+    pub alpha_mode: AlphaMode,
+    // only used when alpha_mode is AlphaMode::Mask
+    pub alpha_cutoff: f32,
+    pub cull_mode: Option<wgpu::Face>,
+    // replaces model.wgsl for the ambient and lit passes
+    pub custom_shader: Option<Rc<CustomShader>>,
+    // how far vertices bend with the scene's wind per unit of height above the model's origin,
+    // e.g. for foliage; 0 is rigid. Swaying geometry doesn't sway in the depth prepass or
+    // shadow maps.
+    pub wind_sway: f32,
+    // blends tiling layers in place of the diffuse, normal and shininess textures
+    pub terrain: Option<TerrainLayers>,
+    // displaces clipmap ring geometry in the vertex stage, see clipmap
+    pub heightmap: Option<Heightmap>,
+    // plays a baked animation in the vertex stage, see vertex_animation
+    pub vertex_animation: Option<VertexAnimation>,
+    // applied to the mesh's texture coordinates, see Material::set_uv_transform
+    pub uv_transform: UvTransform,
+    // animates the diffuse texture, see Material::set_flipbook
+    pub flipbook: Option<Flipbook>,
 }
 
 impl<'a> Default for MaterialProperties<'a> {
@@ -151,45 +548,201 @@ impl<'a> Default for MaterialProperties<'a> {
             diffuse: Vec4::new(1.0, 1.0, 1.0, 1.0),
             specular: Vec4::new(1.0, 1.0, 1.0, 1.0),
             shininess: 1.0,
-            environment_map: None,
             diffuse_texture: None,
             normal_texture: None,
             shininess_texture: None,
+            ambient_occlusion_texture: None,
+            shading: Shading::default(),
+            alpha_mode: AlphaMode::default(),
+            alpha_cutoff: 0.5,
+            cull_mode: Some(wgpu::Face::Back),
+            custom_shader: None,
+            wind_sway: 0.0,
+            terrain: None,
+            heightmap: None,
+            vertex_animation: None,
+            uv_transform: UvTransform::default(),
+            flipbook: None,
         }
     }
 }
 
+/// Tiling albedo (and optionally normal) layers which a terrain material blends across its
+/// surface by per-layer weights. Weights come from the splat map, the mesh's vertex weights,
+/// or the product of both; layers without any weight source are blended evenly.
+pub struct TerrainLayers {
+    // a texture array with a layer per terrain layer, see resources::load_texture_array
+    pub albedo: texture::Texture,
+    // a texture array matching albedo, loaded as normal maps
+    pub normal: Option<texture::Texture>,
+    // a texture array holding the weights of layers 4n..4n+3 in the RGBA of its layer n,
+    // spanning the mesh's texture coordinates. Load as a normal map so it isn't treated as sRGB.
+    pub splat_map: Option<texture::Texture>,
+    // weight layers 0..3 by the mesh's splat weights, see Mesh::set_splat_weights
+    pub vertex_weights: bool,
+    pub layer_count: u32,
+    // repeats of each layer across the mesh's texture coordinates
+    pub tiling: f32,
+}
+
+/// A heightfield sampled by the vertex stage to displace clipmap rings, see clipmap. The
+/// texture's red channel spans [0, height_scale] over a square of world_size centered on
+/// the world origin, and repeats beyond it.
+pub struct Heightmap {
+    // load as a normal map so it isn't treated as sRGB
+    pub texture: texture::Texture,
+    pub world_size: f32,
+    pub height_scale: f32,
+}
+
+/// An animation baked into textures holding every vertex's position and normal in each frame,
+/// played by the vertex stage at the scene's time plus each instance's time offset; see
+/// vertex_animation::bake. Its mesh's vertices must be in the order they were baked.
+pub struct VertexAnimation {
+    pub positions: texture::Texture,
+    pub normals: texture::Texture,
+    pub vertex_count: u32,
+    pub frame_count: u32,
+    // frames per second; the animation loops
+    pub frame_rate: f32,
+    // texels in each row of the textures, which hold each frame's vertices in turn
+    pub texture_width: u32,
+}
+
+// The textures a material binds, borrowed from its properties or the material itself
+struct MaterialBindings<'a> {
+    diffuse_texture: Option<&'a texture::Texture>,
+    normal_texture: Option<&'a texture::Texture>,
+    shininess_texture: Option<&'a texture::Texture>,
+    ambient_occlusion_texture: Option<&'a texture::Texture>,
+    terrain: Option<&'a TerrainLayers>,
+    heightmap: Option<&'a Heightmap>,
+    vertex_animation: Option<&'a VertexAnimation>,
+    // when set, replaces the samplers of the surface and terrain layer textures, see
+    // Material::set_min_texture_lod
+    texture_sampler: Option<&'a wgpu::Sampler>,
+}
+
+struct MaterialBindGroup {
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    textures: MaterialTextures,
+    // the entries of layout, see CustomShader::material_bindings and shader_reflection
+    layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
+}
+
 pub struct Material {
     pub name: String,
     pub ambient: Vec4,
     pub diffuse: Vec4,
     pub specular: Vec4,
     pub shininess: f32,
-    pub environment_map: Option<Rc<texture::Texture>>,
     pub diffuse_texture: Option<texture::Texture>,
     pub normal_texture: Option<texture::Texture>,
     pub shininess_texture: Option<texture::Texture>,
+    pub ambient_occlusion_texture: Option<texture::Texture>,
+    pub shading: Shading,
+    pub alpha_mode: AlphaMode,
+    pub cull_mode: Option<wgpu::Face>,
+    pub custom_shader: Option<Rc<CustomShader>>,
+    pub wind_sway: f32,
+    pub terrain: Option<TerrainLayers>,
+    pub heightmap: Option<Heightmap>,
+    pub vertex_animation: Option<VertexAnimation>,
     pub material_uniform: MaterialUniform, // represents non-texture uniforms
     pub material_uniform_buffer: wgpu::Buffer, // represents non-texture uniforms
     pub bind_group_layout: wgpu::BindGroupLayout,
     pub bind_group: wgpu::BindGroup,
-    pub ambient_pipeline_id: String,
-    pub lit_pipeline_id: String,
+    bind_group_layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
+    textures: MaterialTextures,
+    min_texture_lod: f32,
+    texture_sampler: Option<wgpu::Sampler>,
+    uv_transform: UvTransform,
+    flipbook: Option<Flipbook>,
+    // the uniform changed since it was last written
+    is_dirty: bool,
 }
 
 impl Material {
-    pub fn new(device: &wgpu::Device, properties: MaterialProperties) -> Self {
-        let mut bind_group_layout_entries = Vec::new();
-        let mut bind_group_entries = Vec::new();
-        let mut base_id = String::new();
+    // Texture bindings are fixed to match model.wgsl, so that any combination
+    // of optional textures can be bound. Each texture is followed by its sampler, except the
+    // vertex animation textures, which are read texel by texel.
+    // Bindings 1 and 2 are unused, the environment map is bound by the scene, see environment.
+    const DIFFUSE_TEXTURE_BINDING: u32 = 3;
+    const NORMAL_TEXTURE_BINDING: u32 = 5;
+    const SHININESS_TEXTURE_BINDING: u32 = 7;
+    const AMBIENT_OCCLUSION_TEXTURE_BINDING: u32 = 9;
+    const SPLAT_MAP_BINDING: u32 = 11;
+    const TERRAIN_ALBEDO_BINDING: u32 = 13;
+    const TERRAIN_NORMAL_BINDING: u32 = 15;
+    const HEIGHTMAP_BINDING: u32 = 17;
+    const VERTEX_ANIMATION_POSITIONS_BINDING: u32 = 19;
+    const VERTEX_ANIMATION_NORMALS_BINDING: u32 = 20;
+    // the most layers a splat map array can weight; vertex weights cover 4
+    pub const MAX_TERRAIN_LAYERS: u32 = 16;
 
-        let material_uniform = MaterialUniform {
+    pub fn new(device: &wgpu::Device, properties: MaterialProperties) -> Self {
+        let mut material_uniform = MaterialUniform {
             ambient: color4(properties.ambient),
             diffuse: color4(properties.diffuse),
             specular: color4(properties.specular),
             shininess: properties.shininess,
+            alpha_cutoff: properties.alpha_cutoff,
+            wind_sway: properties.wind_sway,
             ..Default::default()
         };
+        material_uniform.set_uv_transform(&properties.uv_transform);
+        material_uniform.set_flipbook(properties.flipbook.as_ref());
+        if properties.flipbook.is_some() && properties.diffuse_texture.is_none() {
+            eprintln!(
+                "Material \"{}\" has a flipbook but no diffuse texture to animate",
+                properties.name
+            );
+        }
+
+        if let Some(terrain) = &properties.terrain {
+            let max_layers = if terrain.splat_map.is_some() {
+                Self::MAX_TERRAIN_LAYERS
+            } else {
+                4
+            };
+            if terrain.layer_count > max_layers {
+                eprintln!(
+                    "Material \"{}\" has {} terrain layers, only {} can be weighted",
+                    properties.name, terrain.layer_count, max_layers
+                );
+            }
+            material_uniform.terrain = Vec4::new(
+                terrain.layer_count.min(max_layers) as f32,
+                terrain.tiling,
+                0.0,
+                0.0,
+            );
+        }
+
+        if let Some(heightmap) = &properties.heightmap {
+            material_uniform.heightmap =
+                Vec4::new(heightmap.world_size, heightmap.height_scale, 0.0, 0.0);
+        }
+
+        if let Some(animation) = &properties.vertex_animation {
+            material_uniform.vertex_animation = Vec4::new(
+                animation.frame_count as f32,
+                animation.frame_rate,
+                animation.vertex_count as f32,
+                animation.texture_width as f32,
+            );
+        }
+
+        if let Shading::Toon(toon) = properties.shading {
+            material_uniform.toon_bands = toon.bands.max(1) as f32;
+            material_uniform.toon_rim_strength = toon.rim_strength;
+            material_uniform.toon_rim_power = toon.rim_power;
+        }
+        if let Some(outline) = properties.shading.outline() {
+            material_uniform.outline_color = color4(outline.color);
+            material_uniform.outline_width = outline.width;
+        }
 
         let material_uniform_buffer =
             device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -198,9 +751,313 @@ impl Material {
                 usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             });
 
+        let bindings = MaterialBindings {
+            diffuse_texture: properties.diffuse_texture.as_ref(),
+            normal_texture: properties.normal_texture.as_ref(),
+            shininess_texture: properties.shininess_texture.as_ref(),
+            ambient_occlusion_texture: properties.ambient_occlusion_texture.as_ref(),
+            terrain: properties.terrain.as_ref(),
+            heightmap: properties.heightmap.as_ref(),
+            vertex_animation: properties.vertex_animation.as_ref(),
+            texture_sampler: None,
+        };
+        let bind_group =
+            Self::create_bind_group(device, properties.name, &material_uniform_buffer, &bindings);
+
+        // a custom shader can only be used if the material provides every binding it reads
+        let custom_shader = properties.custom_shader.filter(|custom_shader| {
+            let missing = Self::missing_bindings(&bind_group.layout_entries, custom_shader);
+            if !missing.is_empty() {
+                eprintln!(
+                    "Material \"{}\" doesn't provide bindings {:?} required by custom shader \"{}\", falling back to the built-in shader",
+                    properties.name, missing, custom_shader.path
+                );
+            }
+            missing.is_empty()
+        });
+
+        Self {
+            name: properties.name.to_owned(),
+            ambient: properties.ambient,
+            diffuse: properties.diffuse,
+            specular: properties.specular,
+            shininess: properties.shininess,
+            diffuse_texture: properties.diffuse_texture,
+            normal_texture: properties.normal_texture,
+            shininess_texture: properties.shininess_texture,
+            ambient_occlusion_texture: properties.ambient_occlusion_texture,
+            shading: properties.shading,
+            alpha_mode: properties.alpha_mode,
+            cull_mode: properties.cull_mode,
+            custom_shader,
+            wind_sway: properties.wind_sway,
+            terrain: properties.terrain,
+            heightmap: properties.heightmap,
+            vertex_animation: properties.vertex_animation,
+            material_uniform,
+            material_uniform_buffer,
+            bind_group: bind_group.bind_group,
+            bind_group_layout: bind_group.layout,
+            bind_group_layout_entries: bind_group.layout_entries,
+            textures: bind_group.textures,
+            min_texture_lod: 0.0,
+            texture_sampler: None,
+            uv_transform: properties.uv_transform,
+            flipbook: properties.flipbook,
+            is_dirty: false,
+        }
+    }
+
+    pub fn uv_transform(&self) -> UvTransform {
+        self.uv_transform
+    }
+
+    /// Replaces the transform applied to the mesh's texture coordinates. Changes are written
+    /// by update, so a scrolling texture may set its offset every frame.
+    pub fn set_uv_transform(&mut self, uv_transform: UvTransform) {
+        if uv_transform != self.uv_transform {
+            self.uv_transform = uv_transform;
+            self.material_uniform.set_uv_transform(&uv_transform);
+            self.is_dirty = true;
+        }
+    }
+
+    pub fn set_uv_offset(&mut self, offset: Vec2) {
+        self.set_uv_transform(UvTransform {
+            offset,
+            ..self.uv_transform
+        });
+    }
+
+    pub fn set_uv_scale(&mut self, scale: Vec2) {
+        self.set_uv_transform(UvTransform {
+            scale,
+            ..self.uv_transform
+        });
+    }
+
+    pub fn set_uv_rotation<R: Into<Rad>>(&mut self, rotation: R) {
+        self.set_uv_transform(UvTransform {
+            rotation: rotation.into(),
+            ..self.uv_transform
+        });
+    }
+
+    pub fn flipbook(&self) -> Option<Flipbook> {
+        self.flipbook
+    }
+
+    /// Animates the diffuse texture as a grid of frames, or stops animating it. Frames are
+    /// chosen by the scene's time, see wind::Wind, so every material with the same frame rate
+    /// plays in step.
+    pub fn set_flipbook(&mut self, flipbook: Option<Flipbook>) {
+        if flipbook != self.flipbook {
+            self.flipbook = flipbook;
+            self.material_uniform.set_flipbook(flipbook.as_ref());
+            self.is_dirty = true;
+        }
+    }
+
+    /// Writes the material's uniform if it changed, see Model::update.
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        if self.is_dirty {
+            queue.write_buffer(
+                &self.material_uniform_buffer,
+                0,
+                bytemuck::cast_slice(&[self.material_uniform]),
+            );
+            self.is_dirty = false;
+        }
+    }
+
+    /// Replaces the diffuse texture, returning the texture no longer bound; see set_texture.
+    pub fn set_diffuse_texture(
+        &mut self,
+        device: &wgpu::Device,
+        texture: Option<texture::Texture>,
+    ) -> Option<texture::Texture> {
+        self.set_texture(device, MaterialTextures::DIFFUSE, texture)
+    }
+
+    /// Replaces the normal map, returning the texture no longer bound; see set_texture.
+    pub fn set_normal_texture(
+        &mut self,
+        device: &wgpu::Device,
+        texture: Option<texture::Texture>,
+    ) -> Option<texture::Texture> {
+        self.set_texture(device, MaterialTextures::NORMAL, texture)
+    }
+
+    /// Replaces the shininess texture, returning the texture no longer bound; see set_texture.
+    pub fn set_shininess_texture(
+        &mut self,
+        device: &wgpu::Device,
+        texture: Option<texture::Texture>,
+    ) -> Option<texture::Texture> {
+        self.set_texture(device, MaterialTextures::SHININESS, texture)
+    }
+
+    /// Replaces the ambient occlusion texture, returning the texture no longer bound; see
+    /// set_texture.
+    pub fn set_ambient_occlusion_texture(
+        &mut self,
+        device: &wgpu::Device,
+        texture: Option<texture::Texture>,
+    ) -> Option<texture::Texture> {
+        self.set_texture(device, MaterialTextures::AMBIENT_OCCLUSION, texture)
+    }
+
+    /// Replaces one of the diffuse, normal, shininess or ambient occlusion textures and
+    /// rebuilds the bind group, returning the previous texture. Adding or removing a texture
+    /// changes the material's pipeline variant, which prepare_pipelines creates if needed.
+    /// A texture whose combination with the others no shader supports (a normal map requires
+    /// a diffuse texture, a shininess texture requires a normal map), or which would leave a
+    /// custom shader without a binding it reads, isn't bound and is returned instead.
+    pub fn set_texture(
+        &mut self,
+        device: &wgpu::Device,
+        slot: MaterialTextures,
+        texture: Option<texture::Texture>,
+    ) -> Option<texture::Texture> {
+        let mut textures = self.textures;
+        textures.set(slot, texture.is_some());
+        if textures.contains(MaterialTextures::NORMAL)
+            && !textures.contains(MaterialTextures::DIFFUSE)
+            || textures.contains(MaterialTextures::SHININESS)
+                && !textures.contains(MaterialTextures::NORMAL)
+        {
+            eprintln!(
+                "Material \"{}\" doesn't support textures {:?}, ignoring the {:?} texture change",
+                self.name, textures, slot
+            );
+            return texture;
+        }
+
+        let slot_texture = match self.texture_slot_mut(slot) {
+            Some(slot_texture) => slot_texture,
+            None => {
+                eprintln!(
+                    "Material::set_texture only replaces a single diffuse, normal, shininess or ambient occlusion texture, not {:?}",
+                    slot
+                );
+                return texture;
+            }
+        };
+        let previous = std::mem::replace(slot_texture, texture);
+
+        let bind_group = Self::create_bind_group(
+            device,
+            &self.name,
+            &self.material_uniform_buffer,
+            &self.bindings(),
+        );
+        if let Some(custom_shader) = &self.custom_shader {
+            let missing = Self::missing_bindings(&bind_group.layout_entries, custom_shader);
+            if !missing.is_empty() {
+                eprintln!(
+                    "Material \"{}\" would no longer provide bindings {:?} required by custom shader \"{}\", ignoring the {:?} texture change",
+                    self.name, missing, custom_shader.path, slot
+                );
+                let slot_texture = self.texture_slot_mut(slot).unwrap();
+                return std::mem::replace(slot_texture, previous);
+            }
+        }
+
+        self.bind_group = bind_group.bind_group;
+        self.bind_group_layout = bind_group.layout;
+        self.bind_group_layout_entries = bind_group.layout_entries;
+        self.textures = bind_group.textures;
+        previous
+    }
+
+    pub fn min_texture_lod(&self) -> f32 {
+        self.min_texture_lod
+    }
+
+    /// Has the surface and terrain layer textures skip their `min_lod` most detailed mip
+    /// levels, e.g. 1 samples at most half resolution, trading detail for memory bandwidth;
+    /// see graphics_settings::TextureQuality. At 0 (the default) each texture's own sampler is
+    /// used. Rebuilds the bind group when changed.
+    pub fn set_min_texture_lod(&mut self, device: &wgpu::Device, min_lod: f32) {
+        let min_lod = min_lod.max(0.0);
+        if min_lod == self.min_texture_lod {
+            return;
+        }
+
+        self.min_texture_lod = min_lod;
+        self.texture_sampler = if min_lod > 0.0 {
+            // matches the samplers of mipmapped textures, see texture::Texture::from_bytes
+            Some(device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some(&self.name),
+                address_mode_u: wgpu::AddressMode::Repeat,
+                address_mode_v: wgpu::AddressMode::Repeat,
+                address_mode_w: wgpu::AddressMode::Repeat,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Linear,
+                lod_min_clamp: min_lod,
+                ..Default::default()
+            }))
+        } else {
+            None
+        };
+
+        // the textures bound are unchanged, so the layout and pipeline variant are too
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.name,
+            &self.material_uniform_buffer,
+            &self.bindings(),
+        )
+        .bind_group;
+    }
+
+    fn bindings(&self) -> MaterialBindings<'_> {
+        MaterialBindings {
+            diffuse_texture: self.diffuse_texture.as_ref(),
+            normal_texture: self.normal_texture.as_ref(),
+            shininess_texture: self.shininess_texture.as_ref(),
+            ambient_occlusion_texture: self.ambient_occlusion_texture.as_ref(),
+            terrain: self.terrain.as_ref(),
+            heightmap: self.heightmap.as_ref(),
+            vertex_animation: self.vertex_animation.as_ref(),
+            texture_sampler: self.texture_sampler.as_ref(),
+        }
+    }
+
+    fn texture_slot_mut(
+        &mut self,
+        slot: MaterialTextures,
+    ) -> Option<&mut Option<texture::Texture>> {
+        if slot == MaterialTextures::DIFFUSE {
+            Some(&mut self.diffuse_texture)
+        } else if slot == MaterialTextures::NORMAL {
+            Some(&mut self.normal_texture)
+        } else if slot == MaterialTextures::SHININESS {
+            Some(&mut self.shininess_texture)
+        } else if slot == MaterialTextures::AMBIENT_OCCLUSION {
+            Some(&mut self.ambient_occlusion_texture)
+        } else {
+            None
+        }
+    }
+
+    // Creates the layout and bind group for the material's uniform and textures. Texture
+    // bindings are fixed, see DIFFUSE_TEXTURE_BINDING.
+    fn create_bind_group(
+        device: &wgpu::Device,
+        name: &str,
+        material_uniform_buffer: &wgpu::Buffer,
+        bindings: &MaterialBindings,
+    ) -> MaterialBindGroup {
+        let mut bind_group_layout_entries = Vec::new();
+        let mut bind_group_entries = Vec::new();
+        let mut textures = MaterialTextures::empty();
+
+        // the outline vertex stage reads outline_width
         bind_group_layout_entries.push(wgpu::BindGroupLayoutEntry {
             binding: 0,
-            visibility: wgpu::ShaderStages::FRAGMENT,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
             ty: wgpu::BindingType::Buffer {
                 ty: wgpu::BufferBindingType::Uniform,
                 has_dynamic_offset: false,
@@ -213,120 +1070,240 @@ impl Material {
             resource: material_uniform_buffer.as_entire_binding(),
         });
 
-        let mut offset = 1u32;
-
-        if let Some(texture) = &properties.environment_map {
-            base_id = format!("(environment-map-{})", offset);
-            offset += Self::create_bind_groups_for(
-                texture,
-                offset,
-                &mut bind_group_layout_entries,
-                &mut bind_group_entries,
-            );
-        }
-
-        if let Some(texture) = &properties.diffuse_texture {
-            base_id = format!("{}(diffuse-{})", base_id, offset);
-            offset += Self::create_bind_groups_for(
-                texture,
-                offset,
-                &mut bind_group_layout_entries,
-                &mut bind_group_entries,
-            );
-        }
-
-        if let Some(texture) = &properties.normal_texture {
-            base_id = format!("{}(normal-{})", base_id, offset);
-            offset += Self::create_bind_groups_for(
-                texture,
-                offset,
-                &mut bind_group_layout_entries,
-                &mut bind_group_entries,
-            );
+        for (flag, binding, texture) in [
+            (
+                MaterialTextures::DIFFUSE,
+                Self::DIFFUSE_TEXTURE_BINDING,
+                bindings.diffuse_texture,
+            ),
+            (
+                MaterialTextures::NORMAL,
+                Self::NORMAL_TEXTURE_BINDING,
+                bindings.normal_texture,
+            ),
+            (
+                MaterialTextures::SHININESS,
+                Self::SHININESS_TEXTURE_BINDING,
+                bindings.shininess_texture,
+            ),
+            (
+                MaterialTextures::AMBIENT_OCCLUSION,
+                Self::AMBIENT_OCCLUSION_TEXTURE_BINDING,
+                bindings.ambient_occlusion_texture,
+            ),
+            (
+                MaterialTextures::SPLAT_MAP,
+                Self::SPLAT_MAP_BINDING,
+                bindings
+                    .terrain
+                    .and_then(|terrain| terrain.splat_map.as_ref()),
+            ),
+            (
+                MaterialTextures::TERRAIN_ALBEDO,
+                Self::TERRAIN_ALBEDO_BINDING,
+                bindings.terrain.map(|terrain| &terrain.albedo),
+            ),
+            (
+                MaterialTextures::TERRAIN_NORMAL,
+                Self::TERRAIN_NORMAL_BINDING,
+                bindings.terrain.and_then(|terrain| terrain.normal.as_ref()),
+            ),
+        ] {
+            if let Some(texture) = texture {
+                textures |= flag;
+                // the splat map holds layer weights rather than detail, so keeps its sampler
+                let sampler = match bindings.texture_sampler {
+                    Some(sampler) if flag != MaterialTextures::SPLAT_MAP => sampler,
+                    _ => &texture.sampler,
+                };
+                Self::create_bind_groups_for(
+                    texture,
+                    sampler,
+                    binding,
+                    wgpu::ShaderStages::FRAGMENT,
+                    &mut bind_group_layout_entries,
+                    &mut bind_group_entries,
+                );
+            }
         }
 
-        if let Some(texture) = &properties.shininess_texture {
-            base_id = format!("{}(shininess-{})", base_id, offset);
+        // the heightmap is read by the vertex stage
+        if let Some(heightmap) = bindings.heightmap {
+            textures |= MaterialTextures::HEIGHTMAP;
             Self::create_bind_groups_for(
-                texture,
-                offset,
+                &heightmap.texture,
+                &heightmap.texture.sampler,
+                Self::HEIGHTMAP_BINDING,
+                wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                 &mut bind_group_layout_entries,
                 &mut bind_group_entries,
             );
         }
 
-        if base_id.is_empty() {
-            base_id = "untextured".to_string();
+        // vertex animation textures hold 32 bit floats, which can't be filtered
+        if let Some(animation) = bindings.vertex_animation {
+            textures |= MaterialTextures::VERTEX_ANIMATION;
+            for (binding, texture) in [
+                (
+                    Self::VERTEX_ANIMATION_POSITIONS_BINDING,
+                    &animation.positions,
+                ),
+                (Self::VERTEX_ANIMATION_NORMALS_BINDING, &animation.normals),
+            ] {
+                bind_group_layout_entries.push(wgpu::BindGroupLayoutEntry {
+                    binding,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                });
+                bind_group_entries.push(wgpu::BindGroupEntry {
+                    binding,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                });
+            }
         }
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &bind_group_layout_entries,
-            label: Some(properties.name),
+            label: Some(name),
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &bind_group_layout,
             entries: &bind_group_entries,
-            label: Some(properties.name),
+            label: Some(name),
         });
 
-        Self {
-            name: properties.name.to_owned(),
-            ambient: properties.ambient,
-            diffuse: properties.diffuse,
-            specular: properties.specular,
-            shininess: properties.shininess,
-            environment_map: properties.environment_map,
-            diffuse_texture: properties.diffuse_texture,
-            normal_texture: properties.normal_texture,
-            shininess_texture: properties.shininess_texture,
-            material_uniform,
-            material_uniform_buffer,
+        MaterialBindGroup {
+            layout_entries: bind_group_layout_entries,
+            layout: bind_group_layout,
             bind_group,
-            bind_group_layout,
-            ambient_pipeline_id: format!("model_ambient_[{base_id}]"),
-            lit_pipeline_id: format!("model_lit_[{base_id}]"),
+            textures,
         }
     }
 
-    pub fn prepare_pipelines(&self, gpu_state: &mut GpuState) {
-        for pass in vec![render_pipeline::Pass::Ambient, render_pipeline::Pass::Lit].iter() {
-            if !gpu_state
-                .pipeline_vendor
-                .has_pipeline(self.pipeline_id(pass))
-            {
+    // the bindings a custom shader reads which a material doesn't provide
+    fn missing_bindings(
+        layout_entries: &[wgpu::BindGroupLayoutEntry],
+        custom_shader: &CustomShader,
+    ) -> Vec<u32> {
+        custom_shader
+            .material_bindings()
+            .filter(|binding| {
+                !layout_entries
+                    .iter()
+                    .any(|entry| entry.binding == **binding)
+            })
+            .copied()
+            .collect()
+    }
+
+    pub fn prepare_pipelines(&self, gpu_state: &mut GpuState, depth_mode: DepthMode) {
+        let reflection_probes = environment::Environment::supports_reflection_probes(gpu_state);
+        for pass in [
+            render_pipeline::Pass::Ambient,
+            render_pipeline::Pass::Lit,
+            render_pipeline::Pass::Outline,
+        ]
+        .iter()
+        .filter(|pass| self.renders_pass(pass))
+        {
+            let key = self.variant_key(pass, depth_mode);
+            if !gpu_state.pipeline_vendor.has_material_pipeline(&key) {
+                let label = key.to_string();
                 let layout =
                     gpu_state
                         .device
                         .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                            label: Some(self.pipeline_id(pass)),
+                            label: Some(&label),
                             bind_group_layouts: &[
                                 &self.bind_group_layout,
                                 &camera::Camera::bind_group_layout(&gpu_state.device),
                                 &light::Light::bind_group_layout(&gpu_state.device),
+                                &environment::Environment::bind_group_layout(
+                                    &gpu_state.device,
+                                    reflection_probes,
+                                ),
                             ],
                             push_constant_ranges: &[],
                         });
 
+                // where probes can't be bound the shaders are built without them, see
+                // environment::Environment::supports_reflection_probes
+                let mut defines = key.defines();
+                if reflection_probes {
+                    defines.push("REFLECTION_PROBES");
+                }
+                let source = match (pass, &self.custom_shader) {
+                    (
+                        render_pipeline::Pass::Ambient | render_pipeline::Pass::Lit,
+                        Some(custom_shader),
+                    ) => shader_preprocessor::preprocess(&custom_shader.source, &defines).unwrap(),
+                    _ => resources::load_shader_sync(self.shader(pass), &defines).unwrap(),
+                };
+
+                // catch a shader reading bindings the layouts don't provide here, with the
+                // binding named, rather than as a device error creating the pipeline
+                let shader_label = format!(
+                    "material \"{}\" shader \"{}\" ({})",
+                    self.name,
+                    self.shader(pass),
+                    label
+                );
+                let bindings = shader_reflection::reflect_bindings(
+                    &shader_label,
+                    &source,
+                    &[
+                        (self.vertex_main(pass), naga::ShaderStage::Vertex),
+                        (self.fragment_main(pass), naga::ShaderStage::Fragment),
+                    ],
+                )
+                .and_then(|bindings| {
+                    shader_reflection::validate_bindings(
+                        &shader_label,
+                        &bindings,
+                        &[
+                            &self.bind_group_layout_entries,
+                            &camera::Camera::bind_group_layout_entries(),
+                            &light::Light::bind_group_layout_entries(),
+                            &environment::Environment::bind_group_layout_entries(reflection_probes),
+                        ],
+                    )
+                });
+                if let Err(e) = bindings {
+                    panic!("{}", e);
+                }
+
                 let shader = wgpu::ShaderModuleDescriptor {
                     label: Some(self.shader(pass)),
-                    source: wgpu::ShaderSource::Wgsl(
-                        resources::load_string_sync(self.shader(pass))
-                            .unwrap()
-                            .into(),
-                    ),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
                 };
 
-                gpu_state.pipeline_vendor.create_render_pipeline(
-                    self.pipeline_id(pass),
+                // outlines draw the back faces of an inflated hull so the model's front faces cover them
+                let cull_mode = match pass {
+                    render_pipeline::Pass::Outline => Some(wgpu::Face::Front),
+                    _ => self.cull_mode,
+                };
+
+                gpu_state.pipeline_vendor.create_material_pipeline(
+                    key,
                     &gpu_state.device,
                     render_pipeline::Properties {
                         vs_main: self.vertex_main(pass),
-                        fs_main: self.fragment_main(pass),
+                        fs_main: Some(self.fragment_main(pass)),
                         layout: &layout,
                         color_format: texture::Texture::COLOR_FORMAT,
                         depth_format: Some(texture::Texture::DEPTH_FORMAT),
-                        vertex_layouts: &Model::vertex_layout(),
+                        depth_bias: wgpu::DepthBiasState::default(),
+                        depth_mode,
+                        cull_mode,
+                        blend: None,
+                        vertex_layouts: &self.vertex_layout(),
+                        topology: wgpu::PrimitiveTopology::TriangleList,
                         shader,
                         pass: *pass,
                     },
@@ -335,55 +1312,176 @@ impl Material {
         }
     }
 
-    pub fn pipeline_id(&self, pass: &render_pipeline::Pass) -> &str {
+    pub fn renders_pass(&self, pass: &render_pipeline::Pass) -> bool {
         match pass {
-            render_pipeline::Pass::Ambient => &self.ambient_pipeline_id,
-            render_pipeline::Pass::Lit => &self.lit_pipeline_id,
+            render_pipeline::Pass::Ambient | render_pipeline::Pass::Lit => true,
+            render_pipeline::Pass::Outline => self.shading.outline().is_some(),
+            render_pipeline::Pass::Shadow
+            | render_pipeline::Pass::Depth
+            | render_pipeline::Pass::Debug
+            | render_pipeline::Pass::Particles => false,
         }
     }
 
-    fn vertex_main(&self, pass: &render_pipeline::Pass) -> &'static str {
-        match pass {
-            render_pipeline::Pass::Ambient => "vs_main_ambient",
-            render_pipeline::Pass::Lit => "vs_main_lit",
+    fn uses_vertex_splat_weights(&self) -> bool {
+        self.terrain
+            .as_ref()
+            .is_some_and(|terrain| terrain.vertex_weights)
+    }
+
+    fn vertex_layout<'a>(&self) -> Vec<wgpu::VertexBufferLayout<'a>> {
+        let mut layout = Model::vertex_layout();
+        if self.uses_vertex_splat_weights() {
+            layout.push(Mesh::splat_weights_buffer_layout());
+        }
+        layout
+    }
+
+    // Opaque, rigid materials rendered with the built-in vertex stage can be drawn by the shared
+    // position-only depth pipeline, see depth_pass
+    pub fn writes_prepass_depth(&self) -> bool {
+        self.alpha_mode == AlphaMode::Opaque
+            && self.custom_shader.is_none()
+            && self.wind_sway == 0.0
+            && self.casts_shadows()
+    }
+
+    // Whether the vertex stage moves geometry beyond its mesh's bounds
+    pub fn displaces_vertices(&self) -> bool {
+        self.wind_sway != 0.0 || !self.casts_shadows() || self.custom_shader.is_some()
+    }
+
+    // Heightmapped and vertex animated geometry only takes shape in the material's vertex
+    // stage, which the position-only shadow and depth pipelines don't run
+    pub fn casts_shadows(&self) -> bool {
+        self.heightmap.is_none() && self.vertex_animation.is_none()
+    }
+
+    pub fn variant_key(
+        &self,
+        pass: &render_pipeline::Pass,
+        depth_mode: DepthMode,
+    ) -> MaterialVariantKey {
+        if let render_pipeline::Pass::Shadow
+        | render_pipeline::Pass::Depth
+        | render_pipeline::Pass::Debug
+        | render_pipeline::Pass::Particles = pass
+        {
+            unimplemented!("Material only vends Ambient, Lit and Outline pipelines")
+        }
+
+        // the outline pass always uses the built-in shader
+        let custom_shader = match pass {
+            render_pipeline::Pass::Outline => None,
+            _ => self
+                .custom_shader
+                .as_ref()
+                .map(|custom_shader| custom_shader.id()),
+        };
+
+        MaterialVariantKey {
+            pass: *pass,
+            textures: self.textures,
+            alpha_mode: self.alpha_mode,
+            cull_mode: self.cull_mode,
+            depth_mode,
+            toon_shading: matches!(self.shading, Shading::Toon(_)),
+            vertex_splat_weights: self.uses_vertex_splat_weights(),
+            custom_shader,
+        }
+    }
+
+    fn vertex_main(&self, pass: &render_pipeline::Pass) -> &str {
+        match (pass, &self.custom_shader) {
+            (render_pipeline::Pass::Ambient, Some(custom_shader)) => &custom_shader.vs_main_ambient,
+            (render_pipeline::Pass::Lit, Some(custom_shader)) => &custom_shader.vs_main_lit,
+            (render_pipeline::Pass::Ambient, None) => "vs_main_ambient",
+            (render_pipeline::Pass::Lit, None) => "vs_main_lit",
+            (render_pipeline::Pass::Outline, _) => "vs_main_outline",
+            (
+                render_pipeline::Pass::Shadow
+                | render_pipeline::Pass::Depth
+                | render_pipeline::Pass::Debug
+                | render_pipeline::Pass::Particles,
+                _,
+            ) => unimplemented!("Material only vends Ambient, Lit and Outline pipelines"),
         }
     }
 
-    fn fragment_main(&self, pass: &render_pipeline::Pass) -> &'static str {
+    fn fragment_main(&self, pass: &render_pipeline::Pass) -> &str {
         match pass {
             render_pipeline::Pass::Ambient => self.ambient_fragment_main(),
             render_pipeline::Pass::Lit => self.lit_fragment_main(),
+            render_pipeline::Pass::Outline => "fs_main_outline",
+            render_pipeline::Pass::Shadow
+            | render_pipeline::Pass::Depth
+            | render_pipeline::Pass::Debug
+            | render_pipeline::Pass::Particles => {
+                unimplemented!("Material only vends Ambient, Lit and Outline pipelines")
+            }
         }
     }
 
-    fn shader(&self, pass: &render_pipeline::Pass) -> &'static str {
+    fn shader(&self, pass: &render_pipeline::Pass) -> &str {
         match pass {
             render_pipeline::Pass::Ambient => self.ambient_shader(),
             render_pipeline::Pass::Lit => self.lit_shader(),
+            render_pipeline::Pass::Outline => "shaders/model.wgsl",
+            render_pipeline::Pass::Shadow
+            | render_pipeline::Pass::Depth
+            | render_pipeline::Pass::Debug
+            | render_pipeline::Pass::Particles => {
+                unimplemented!("Material only vends Ambient, Lit and Outline pipelines")
+            }
         }
     }
 
-    fn ambient_fragment_main(&self) -> &'static str {
+    fn ambient_fragment_main(&self) -> &str {
+        if let Some(custom_shader) = &self.custom_shader {
+            return &custom_shader.fs_main_ambient;
+        }
+        if self.terrain.is_some() {
+            return "fs_main_ambient_terrain";
+        }
+
         match (
             &self.diffuse_texture,
             &self.normal_texture,
             &self.shininess_texture,
+            &self.ambient_occlusion_texture,
         ) {
-            (None, None, None) => "fs_main_ambient_untextured",
-            (Some(_), None, None) => "fs_main_ambient_diffuse",
-            (Some(_), Some(_), None) => "fs_main_ambient_diffuse_normal",
-            (Some(_), Some(_), Some(_)) => "fs_main_ambient_diffuse_normal_shininess",
+            (None, None, None, None) => "fs_main_ambient_untextured",
+            (Some(_), None, None, None) => "fs_main_ambient_diffuse",
+            (Some(_), Some(_), None, None) => "fs_main_ambient_diffuse_normal",
+            (Some(_), Some(_), Some(_), None) => "fs_main_ambient_diffuse_normal_shininess",
+            (None, None, None, Some(_)) => "fs_main_ambient_untextured_ao",
+            (Some(_), None, None, Some(_)) => "fs_main_ambient_diffuse_ao",
+            (Some(_), Some(_), None, Some(_)) => "fs_main_ambient_diffuse_normal_ao",
+            (Some(_), Some(_), Some(_), Some(_)) => "fs_main_ambient_diffuse_normal_shininess_ao",
             _ => unimplemented!(
                 "Material::ambient_fragment_main doesn't support texture conbination specified"
             ),
         }
     }
 
-    fn ambient_shader(&self) -> &'static str {
-        "shaders/model.wgsl"
+    fn ambient_shader(&self) -> &str {
+        match &self.custom_shader {
+            Some(custom_shader) => &custom_shader.path,
+            None => "shaders/model.wgsl",
+        }
     }
 
-    fn lit_fragment_main(&self) -> &'static str {
+    fn lit_fragment_main(&self) -> &str {
+        if let Some(custom_shader) = &self.custom_shader {
+            return &custom_shader.fs_main_lit;
+        }
+        if self.terrain.is_some() {
+            return "fs_main_lit_terrain";
+        }
+        if let Shading::Toon(_) = self.shading {
+            return self.lit_toon_fragment_main();
+        }
+
         match (
             &self.diffuse_texture,
             &self.normal_texture,
@@ -401,19 +1499,38 @@ impl Material {
         }
     }
 
-    fn lit_shader(&self) -> &'static str {
-        "shaders/model.wgsl"
+    // toon shading ignores the shininess texture; its highlight is a hard edged step
+    fn lit_toon_fragment_main(&self) -> &'static str {
+        match (&self.diffuse_texture, &self.normal_texture) {
+            (None, None) => "fs_main_lit_toon_untextured",
+            (Some(_), None) => "fs_main_lit_toon_diffuse",
+            (Some(_), Some(_)) => "fs_main_lit_toon_diffuse_normal",
+            _ => {
+                unimplemented!(
+                    "Material::lit_toon_fragment_main doesn't support texture combination specified"
+                )
+            }
+        }
+    }
+
+    fn lit_shader(&self) -> &str {
+        match &self.custom_shader {
+            Some(custom_shader) => &custom_shader.path,
+            None => "shaders/model.wgsl",
+        }
     }
 
     fn create_bind_groups_for<'a: 'b, 'b>(
         texture: &'a texture::Texture,
+        sampler: &'a wgpu::Sampler,
         offset: u32,
+        visibility: wgpu::ShaderStages,
         bind_group_layout_entries: &'b mut Vec<wgpu::BindGroupLayoutEntry>,
         bind_group_entries: &'b mut Vec<wgpu::BindGroupEntry<'a>>,
-    ) -> u32 {
+    ) {
         bind_group_layout_entries.push(wgpu::BindGroupLayoutEntry {
             binding: offset,
-            visibility: wgpu::ShaderStages::FRAGMENT,
+            visibility,
             ty: wgpu::BindingType::Texture {
                 multisampled: false,
                 view_dimension: texture.view_dimension,
@@ -429,27 +1546,78 @@ impl Material {
 
         bind_group_layout_entries.push(wgpu::BindGroupLayoutEntry {
             binding: offset + 1,
-            visibility: wgpu::ShaderStages::FRAGMENT,
+            visibility,
             ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
             count: None,
         });
 
         bind_group_entries.push(wgpu::BindGroupEntry {
             binding: offset + 1,
-            resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            resource: wgpu::BindingResource::Sampler(sampler),
         });
+    }
+}
 
-        2
+// The share of the depth range the foreground layer is squeezed into, nearest the camera. The
+// world only reaches it within a hair of the near plane.
+const FOREGROUND_DEPTH_RANGE: f32 = 0.01;
+
+/// The groups a scene draws its models in, in this order, each with its own depth handling,
+/// see Model::set_render_layer. Layers share the depth buffer, and depth tests within a
+/// layer as usual.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RenderLayer {
+    // pinned to the far plane, behind everything drawn after it, e.g. a skybox
+    Background,
+    #[default]
+    World,
+    // squeezed in front of the world, so it never sinks into walls, e.g. first person props
+    Foreground,
+    // pinned to the near plane, over everything drawn before it, e.g. a 3D cursor
+    Overlay,
+}
+
+impl RenderLayer {
+    /// The viewport depth range (min, max) the layer's geometry is mapped into
+    pub fn depth_range(&self, depth_mode: DepthMode) -> (f32, f32) {
+        let near = 1.0 - depth_mode.clear_depth();
+        let far = depth_mode.clear_depth();
+        match self {
+            RenderLayer::Background => (far, far),
+            RenderLayer::World => (0.0, 1.0),
+            RenderLayer::Foreground => match depth_mode {
+                DepthMode::Standard => (0.0, FOREGROUND_DEPTH_RANGE),
+                DepthMode::Reversed => (1.0 - FOREGROUND_DEPTH_RANGE, 1.0),
+            },
+            RenderLayer::Overlay => (near, near),
+        }
     }
 }
 
+/// A contiguous range of a model's instances, e.g. those in one cell of a grid, which can be
+/// drawn on their own with draw_model_range; see Model::set_instance_groups
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstanceGroup {
+    pub instances: Range<u32>,
+    // world space bounds of the group's instances, as Model::bounds
+    pub bounds: Option<Bounds>,
+}
+
 pub struct Model {
     meshes: Vec<Mesh>,
     materials: Vec<Material>,
     instances: Vec<Instance>,
     instance_data: Vec<InstanceData>,
     is_dirty: bool,
-    instance_buffer: wgpu::Buffer,
+    // a ring of FRAMES_IN_FLIGHT buffers, written in turn; the current one is drawn
+    instance_buffers: Vec<wgpu::Buffer>,
+    instance_slot: usize,
+    // world space bounds of every instance, see Model::bounds
+    bounds: Option<Bounds>,
+    instance_groups: Vec<InstanceGroup>,
+    sockets: HashMap<String, socket::Socket>,
+    render_layer: RenderLayer,
+    render_order: i32,
 }
 
 impl Model {
@@ -460,11 +1628,18 @@ impl Model {
         instances: &[Instance],
     ) -> Self {
         let instance_data: Vec<InstanceData> = instances.iter().map(Instance::as_data).collect();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Model::instance_buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
+        let instance_buffers = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Model::instance_buffer"),
+                    contents: bytemuck::cast_slice(&instance_data),
+                    // storage, so instance_animation::InstanceAnimator can write it
+                    usage: wgpu::BufferUsages::VERTEX
+                        | wgpu::BufferUsages::STORAGE
+                        | wgpu::BufferUsages::COPY_DST,
+                })
+            })
+            .collect();
 
         Model {
             meshes,
@@ -472,16 +1647,194 @@ impl Model {
             instances: instances.to_vec(),
             instance_data,
             is_dirty: true,
-            instance_buffer,
+            instance_buffers,
+            instance_slot: 0,
+            bounds: None,
+            instance_groups: Vec::new(),
+            sockets: HashMap::new(),
+            render_layer: RenderLayer::World,
+            render_order: 0,
         }
     }
 
-    pub fn prepare_pipelines(&self, gpu_state: &mut GpuState) {
+    pub fn prepare_pipelines(&self, gpu_state: &mut GpuState, depth_mode: DepthMode) {
         for material in self.materials.iter() {
-            material.prepare_pipelines(gpu_state);
+            material.prepare_pipelines(gpu_state, depth_mode);
+        }
+    }
+
+    pub fn meshes(&self) -> &[Mesh] {
+        &self.meshes
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    /// Mutable access to the materials, e.g. to replace their textures; see
+    /// Material::set_texture.
+    pub fn materials_mut(&mut self) -> &mut [Material] {
+        &mut self.materials
+    }
+
+    pub fn add_mesh(&mut self, mesh: Mesh) {
+        self.meshes.push(mesh);
+        self.update_bounds();
+    }
+
+    /// Removes the first mesh with the given name, returning it.
+    pub fn remove_mesh(&mut self, name: &str) -> Option<Mesh> {
+        let index = self.meshes.iter().position(|mesh| mesh.name == name)?;
+        let mesh = self.meshes.remove(index);
+        self.update_bounds();
+        Some(mesh)
+    }
+
+    /// The world space bounds of the model's instances as of the last update, None if it has
+    /// no geometry, or if a material's vertex stage displaces vertices beyond their meshes'
+    /// bounds. Instances moved on the GPU, e.g. by an instance_animation::InstanceAnimator,
+    /// aren't accounted for; skinned meshes are as far as their bounds are kept up to date,
+    /// see set_mesh_bounds.
+    pub fn bounds(&self) -> Option<Bounds> {
+        self.bounds
+    }
+
+    /// Replaces the model space bounds of the first mesh with the given name, e.g. to enclose
+    /// a skinned mesh's current pose (see skinning::Skin::bounds), and updates the model's.
+    pub fn set_mesh_bounds(&mut self, name: &str, bounds: Option<Bounds>) {
+        let mesh = match self.meshes.iter_mut().find(|mesh| mesh.name == name) {
+            Some(mesh) => mesh,
+            None => return,
+        };
+        if mesh.bounds != bounds {
+            mesh.bounds = bounds;
+            self.update_bounds();
+        }
+    }
+
+    /// Groups the model's instances into contiguous ranges, e.g. by the grid cell they're
+    /// placed in, so each group can be culled and drawn on its own. Ranges are clamped to the
+    /// instances, and those left empty dropped. scene::Scene draws a model with groups group
+    /// by group, culling those outside the camera's view, so instances in no group aren't drawn.
+    pub fn set_instance_groups(&mut self, groups: &[Range<u32>]) {
+        let count = self.instances.len() as u32;
+        self.instance_groups = groups
+            .iter()
+            .map(|range| range.start.min(count)..range.end.min(count))
+            .filter(|range| !range.is_empty())
+            .map(|instances| InstanceGroup {
+                instances,
+                bounds: None,
+            })
+            .collect();
+        self.update_bounds();
+    }
+
+    pub fn render_layer(&self) -> RenderLayer {
+        self.render_layer
+    }
+
+    /// Sets the group the model is drawn in, see RenderLayer. Only world models are
+    /// occlusion culled, or culled by distance.
+    pub fn set_render_layer(&mut self, render_layer: RenderLayer) {
+        self.render_layer = render_layer;
+    }
+
+    pub fn render_order(&self) -> i32 {
+        self.render_order
+    }
+
+    /// Sets the order the model is drawn in within its layer, lowest first, e.g. so later
+    /// models in the background or overlay layers are drawn over earlier ones.
+    pub fn set_render_order(&mut self, render_order: i32) {
+        self.render_order = render_order;
+    }
+
+    /// The model's instance groups, empty unless set with set_instance_groups
+    pub fn instance_groups(&self) -> &[InstanceGroup] {
+        &self.instance_groups
+    }
+
+    /// Adds a named attachment point, replacing any of the same name, see socket::Attachment.
+    pub fn add_socket(&mut self, name: &str, socket: socket::Socket) {
+        self.sockets.insert(name.to_owned(), socket);
+    }
+
+    pub fn remove_socket(&mut self, name: &str) -> Option<socket::Socket> {
+        self.sockets.remove(name)
+    }
+
+    pub fn socket(&self, name: &str) -> Option<&socket::Socket> {
+        self.sockets.get(name)
+    }
+
+    fn update_bounds(&mut self) {
+        let mesh_bounds = if self
+            .meshes
+            .iter()
+            .filter_map(|mesh| self.materials.get(mesh.material))
+            .any(|material| material.displaces_vertices())
+        {
+            None
+        } else {
+            Bounds::from_points(
+                self.meshes
+                    .iter()
+                    .filter_map(|mesh| mesh.bounds)
+                    .flat_map(|bounds| bounds.corners()),
+            )
+        };
+
+        let instance_bounds = |instances: &[Instance]| {
+            mesh_bounds.and_then(|mesh_bounds| {
+                Bounds::from_points(
+                    instances
+                        .iter()
+                        .flat_map(|instance| mesh_bounds.transformed(&instance.matrix()).corners()),
+                )
+            })
+        };
+
+        self.bounds = instance_bounds(&self.instances);
+        for group in self.instance_groups.iter_mut() {
+            let range = group.instances.start as usize..group.instances.end as usize;
+            group.bounds = instance_bounds(&self.instances[range]);
         }
     }
 
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    /// The instances, for editing in place; the instance buffer and bounds are updated by the
+    /// next update, as with update_instance.
+    pub fn instances_mut(&mut self) -> &mut [Instance] {
+        self.is_dirty = true;
+        &mut self.instances
+    }
+
+    /// The index of the first instance tagged with `user_data`, see Instance::with_user_data
+    pub fn find_instance(&self, user_data: u32) -> Option<usize> {
+        self.instances
+            .iter()
+            .position(|instance| instance.user_data == Some(user_data))
+    }
+
+    /// The instance buffer holding the most recently written instances, which draws use
+    pub fn instance_buffer(&self) -> &wgpu::Buffer {
+        &self.instance_buffers[self.instance_slot]
+    }
+
+    /// Every buffer of the instance ring, see util::UniformWrapper
+    pub fn instance_buffers(&self) -> &[wgpu::Buffer] {
+        &self.instance_buffers
+    }
+
+    /// The index of the current buffer in instance_buffers()
+    pub fn instance_slot(&self) -> usize {
+        self.instance_slot
+    }
+
     pub fn update_instance(&mut self, at: usize, to: Instance) {
         if at < self.instances.len() {
             self.instances[at] = to;
@@ -503,6 +1856,9 @@ impl Model {
     }
 
     pub fn update(&mut self, queue: &wgpu::Queue) {
+        for material in self.materials.iter_mut() {
+            material.update(queue);
+        }
         if !self.is_dirty {
             return;
         }
@@ -511,9 +1867,11 @@ impl Model {
         for (instance, data) in self.instances.iter().zip(self.instance_data.iter_mut()) {
             *data = instance.as_data();
         }
+        self.update_bounds();
 
+        self.instance_slot = (self.instance_slot + 1) % self.instance_buffers.len();
         queue.write_buffer(
-            &self.instance_buffer,
+            &self.instance_buffers[self.instance_slot],
             0,
             bytemuck::cast_slice(&self.instance_data),
         );
@@ -536,28 +1894,145 @@ pub fn draw_model<'a, 'b>(
     model: &'a Model,
     camera: &'a camera::Camera,
     light: &'a light::Light,
+    environment: &'a environment::Environment,
     pass: &render_pipeline::Pass,
 ) where
     'a: 'b, // 'a lifetime at least as long as 'b
 {
-    let instances = 0..model.instances.len() as u32;
+    draw_model_range(
+        render_pass,
+        pipeline_vendor,
+        model,
+        0..model.instances.len() as u32,
+        camera,
+        light,
+        environment,
+        pass,
+    );
+}
+
+/// Draws the contiguous range of the model's instances, e.g. one of its instance groups; the
+/// range is clamped to the model's instances.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_model_range<'a, 'b>(
+    render_pass: &'b mut wgpu::RenderPass<'a>,
+    pipeline_vendor: &'a RenderPipelineVendor,
+    model: &'a Model,
+    instances: Range<u32>,
+    camera: &'a camera::Camera,
+    light: &'a light::Light,
+    environment: &'a environment::Environment,
+    pass: &render_pipeline::Pass,
+) where
+    'a: 'b, // 'a lifetime at least as long as 'b
+{
+    let count = model.instances.len() as u32;
+    let instances = instances.start.min(count)..instances.end.min(count);
+    if instances.is_empty() {
+        return;
+    }
     for mesh in &model.meshes {
         let material = &model.materials[mesh.material];
+        if !material.renders_pass(pass) {
+            continue;
+        }
 
-        if let Some(pipeline) = pipeline_vendor.get_pipeline(material.pipeline_id(pass)) {
+        let key = material.variant_key(pass, camera.depth_mode());
+        if let Some(pipeline) = pipeline_vendor.get_material_pipeline(&key) {
+            if key.vertex_splat_weights {
+                match &mesh.splat_weights_buffer {
+                    Some(splat_weights_buffer) => {
+                        render_pass.set_vertex_buffer(2, splat_weights_buffer.slice(..))
+                    }
+                    None => {
+                        eprintln!(
+                            "Mesh \"{}\" has no splat weights for terrain material \"{}\"",
+                            mesh.name, material.name
+                        );
+                        continue;
+                    }
+                }
+            }
             render_pass.set_pipeline(pipeline);
             render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, model.instance_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, model.instance_buffer().slice(..));
             render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
             render_pass.set_bind_group(0, &material.bind_group, &[]);
             render_pass.set_bind_group(1, camera.bind_group(), &[]);
             render_pass.set_bind_group(2, light.bind_group(), &[]);
+            render_pass.set_bind_group(3, environment.bind_group(), &[]);
             render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
         } else {
-            eprintln!(
-                "No pipeline available to render material id: {}",
-                material.pipeline_id(pass)
-            );
+            eprintln!("No pipeline available to render material variant: {}", key);
+        }
+    }
+}
+
+pub fn draw_model_shadow<'a, 'b>(
+    render_pass: &'b mut wgpu::RenderPass<'a>,
+    pipeline_vendor: &'a RenderPipelineVendor,
+    model: &'a Model,
+    light: &'a light::Light,
+) where
+    'a: 'b, // 'a lifetime at least as long as 'b
+{
+    let shadow_map = match light.shadow_map() {
+        Some(shadow_map) => shadow_map,
+        None => return,
+    };
+
+    if let Some(pipeline) = pipeline_vendor.get_pipeline(shadow_map.pipeline_id()) {
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, light.shadow_pass_bind_group(), &[]);
+        draw_model_positions(render_pass, model, Material::casts_shadows);
+    } else {
+        eprintln!(
+            "No pipeline available to render shadow map id: {}",
+            shadow_map.pipeline_id()
+        );
+    }
+}
+
+// Draws meshes for pipelines which only read vertex positions and instance transforms, the
+// caller is responsible for setting the pipeline and its bind groups.
+pub fn draw_model_positions<'a, 'b, F>(
+    render_pass: &'b mut wgpu::RenderPass<'a>,
+    model: &'a Model,
+    include_material: F,
+) where
+    'a: 'b, // 'a lifetime at least as long as 'b
+    F: Fn(&Material) -> bool,
+{
+    draw_model_positions_range(
+        render_pass,
+        model,
+        0..model.instances.len() as u32,
+        include_material,
+    );
+}
+
+// As draw_model_positions, for a contiguous range of the model's instances, clamped to them
+pub fn draw_model_positions_range<'a, 'b, F>(
+    render_pass: &'b mut wgpu::RenderPass<'a>,
+    model: &'a Model,
+    instances: Range<u32>,
+    include_material: F,
+) where
+    'a: 'b, // 'a lifetime at least as long as 'b
+    F: Fn(&Material) -> bool,
+{
+    let count = model.instances.len() as u32;
+    let instances = instances.start.min(count)..instances.end.min(count);
+    if instances.is_empty() {
+        return;
+    }
+    render_pass.set_vertex_buffer(1, model.instance_buffer().slice(..));
+    for mesh in &model.meshes {
+        if !include_material(&model.materials[mesh.material]) {
+            continue;
         }
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
     }
 }
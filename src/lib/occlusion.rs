@@ -0,0 +1,477 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+use anyhow::{anyhow, Result};
+use cgmath::prelude::*;
+use wgpu::vertex_attr_array;
+
+use super::{
+    camera,
+    gpu_state::GpuState,
+    model::Bounds,
+    render_pipeline::{self, DepthMode},
+    resources, texture,
+    transient_buffers::TransientAllocation,
+    util::*,
+};
+
+const PIPELINE_ID: &str = "occlusion_proxies";
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct OccludeeInstance {
+    bounds_min: Point3,
+    bounds_max: Point3,
+    // the occludee's slot in the visibility buffer
+    index: u32,
+}
+
+unsafe impl bytemuck::Pod for OccludeeInstance {}
+unsafe impl bytemuck::Zeroable for OccludeeInstance {}
+
+const OCCLUDEE_INSTANCE_ATTRIBS: [wgpu::VertexAttribute; 3] =
+    vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Uint32];
+
+impl OccludeeInstance {
+    fn vertex_buffer_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &OCCLUDEE_INSTANCE_ATTRIBS,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct OcclusionUniformData {
+    // x: z_near, y: z_far (0 for an infinite far plane), z: 1 if reversed depth
+    camera_depth: Vec4,
+}
+
+unsafe impl bytemuck::Pod for OcclusionUniformData {}
+unsafe impl bytemuck::Zeroable for OcclusionUniformData {}
+
+impl Default for OcclusionUniformData {
+    fn default() -> Self {
+        Self {
+            camera_depth: Vec4::zero(),
+        }
+    }
+}
+
+type OcclusionUniform = UniformWrapper<OcclusionUniformData>;
+
+/// What an occlusion test is for: a model's instance group, or the whole model when it has no
+/// groups.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Occludee {
+    pub model_id: usize,
+    pub group: Option<usize>,
+}
+
+pub struct OcclusionCullerDescriptor {
+    // occludees tested each frame; those beyond are never culled
+    pub max_occludees: u32,
+    // occludees whose bounds have a shorter diagonal aren't tested, as their proxies would
+    // cost about as much as drawing them
+    pub min_occludee_size: f32,
+}
+
+impl Default for OcclusionCullerDescriptor {
+    fn default() -> Self {
+        Self {
+            max_occludees: 4096,
+            min_occludee_size: 1.0,
+        }
+    }
+}
+
+// The latest test of an occludee read back from the GPU
+#[derive(Clone, Copy, Debug)]
+struct OcclusionResult {
+    frame: u64,
+    visible: bool,
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Culls large occludees hidden behind the scene's geometry, in the manner of hardware
+// occlusion queries, which wgpu doesn't expose: after the scene is drawn, each occludee in
+// the camera's view draws its bounds as a proxy box, whose fragments test themselves against
+// the camera's depth attachment and mark the occludee visible in a storage buffer if any are in
+// front. The buffer is read back asynchronously, so results arrive a few frames late; an
+// occludee is only culled by a result tested since it last came into view, so occludees
+// entering the view are drawn until they're known to be hidden, though ones coming out from
+// behind an occluder may appear a few frames late. Proxies are drawn for culled occludees too,
+// testing against their occluders alone. Needs fragment shaders which write storage buffers,
+// which some downlevel adapters lack.
+pub struct OcclusionCuller {
+    descriptor: OcclusionCullerDescriptor,
+    uniform: OcclusionUniform,
+    visibility_buffer: wgpu::Buffer,
+    test_bind_group_layout: wgpu::BindGroupLayout,
+    test_bind_group: Option<wgpu::BindGroup>,
+    frame: u64,
+    // the occludees tested this frame by visibility buffer slot, each with the frame since
+    // which it has been tested continuously
+    tested: Vec<Occludee>,
+    tested_since: HashMap<Occludee, u64>,
+    uploaded: Option<(TransientAllocation, u32)>,
+    // set by update, cleared by the test it enables, so a frame is tested once
+    due: Cell<bool>,
+    results: Rc<RefCell<HashMap<Occludee, OcclusionResult>>>,
+}
+
+impl OcclusionCuller {
+    pub fn new(
+        gpu_state: &GpuState,
+        render_buffers: &camera::RenderBuffers,
+        descriptor: OcclusionCullerDescriptor,
+    ) -> Result<Self> {
+        if !gpu_state
+            .downlevel_flags
+            .contains(wgpu::DownlevelFlags::FRAGMENT_WRITABLE_STORAGE)
+        {
+            return Err(anyhow!(
+                "Occlusion culling needs fragment shaders which write storage buffers, which the adapter doesn't support"
+            ));
+        }
+
+        let visibility_buffer = gpu_state.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("OcclusionCuller::visibility_buffer"),
+            size: (descriptor.max_occludees.max(1) as usize * std::mem::size_of::<u32>())
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let test_bind_group_layout = Self::test_bind_group_layout(&gpu_state.device);
+        let test_bind_group = Self::create_test_bind_group(
+            gpu_state,
+            render_buffers,
+            &test_bind_group_layout,
+            &visibility_buffer,
+        );
+
+        Ok(Self {
+            descriptor,
+            uniform: OcclusionUniform::new(&gpu_state.device),
+            visibility_buffer,
+            test_bind_group_layout,
+            test_bind_group,
+            frame: 0,
+            tested: Vec::new(),
+            tested_since: HashMap::new(),
+            uploaded: None,
+            due: Cell::new(false),
+            results: Rc::new(RefCell::new(HashMap::new())),
+        })
+    }
+
+    pub fn resize(&mut self, gpu_state: &GpuState, render_buffers: &camera::RenderBuffers) {
+        self.test_bind_group = Self::create_test_bind_group(
+            gpu_state,
+            render_buffers,
+            &self.test_bind_group_layout,
+            &self.visibility_buffer,
+        );
+    }
+
+    pub fn prepare_pipeline(gpu_state: &mut GpuState) {
+        if gpu_state.pipeline_vendor.has_pipeline(PIPELINE_ID) {
+            return;
+        }
+
+        let layout = gpu_state
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(PIPELINE_ID),
+                bind_group_layouts: &[
+                    &camera::Camera::bind_group_layout(&gpu_state.device),
+                    &OcclusionUniform::bind_group_layout(&gpu_state.device),
+                    &Self::test_bind_group_layout(&gpu_state.device),
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("shaders/occlusion.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                resources::load_shader_sync("shaders/occlusion.wgsl", &[])
+                    .unwrap()
+                    .into(),
+            ),
+        };
+
+        // the proxies only write the visibility buffer; the color attachment is there for the
+        // pass to have an attachment, and blended so it's left as it is
+        let keep_destination = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+
+        gpu_state.pipeline_vendor.create_render_pipeline(
+            PIPELINE_ID,
+            &gpu_state.device,
+            render_pipeline::Properties {
+                vs_main: "vs_main_occlusion",
+                fs_main: Some("fs_main_occlusion"),
+                layout: &layout,
+                color_format: texture::Texture::COLOR_FORMAT,
+                // depth is tested in the fragment shader, so the pipeline is depth mode agnostic
+                depth_format: None,
+                depth_bias: wgpu::DepthBiasState::default(),
+                depth_mode: DepthMode::default(),
+                vertex_layouts: &[OccludeeInstance::vertex_buffer_layout()],
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                shader,
+                // back faces stand in for front faces clipped by the near plane
+                cull_mode: None,
+                blend: Some(wgpu::BlendState {
+                    color: keep_destination,
+                    alpha: keep_destination,
+                }),
+                pass: render_pipeline::Pass::Particles,
+            },
+        );
+    }
+
+    /// Chooses this frame's occludees to test from `occludees` and their world space bounds:
+    /// those in `camera`'s view, at least min_occludee_size across, and which the camera's
+    /// near plane can't cut into.
+    pub fn update<I>(&mut self, gpu_state: &mut GpuState, camera: &camera::Camera, occludees: I)
+    where
+        I: IntoIterator<Item = (Occludee, Bounds)>,
+    {
+        self.frame += 1;
+
+        let frustum = camera.frustum();
+        let (z_near, _) = camera.depth_range();
+        // the distance from the camera to the corners of its near plane, with a margin; a box
+        // this close may have its front faces clipped, leaving only back faces to test
+        let tan_half_fov = (camera.fov_y() * 0.5).tan();
+        let aspect = camera.aspect();
+        let near_reach =
+            2.0 * z_near * (1.0 + tan_half_fov * tan_half_fov * (1.0 + aspect * aspect)).sqrt();
+        let min_size2 = self.descriptor.min_occludee_size * self.descriptor.min_occludee_size;
+
+        let mut instances = Vec::new();
+        self.tested.clear();
+        for (occludee, bounds) in occludees {
+            if instances.len() >= self.descriptor.max_occludees as usize {
+                break;
+            }
+            if bounds.min.distance2(bounds.max) < min_size2
+                || !frustum.intersects_aabb(bounds.min, bounds.max)
+                || bounds.intersects_sphere(camera.position(), near_reach)
+            {
+                continue;
+            }
+            instances.push(OccludeeInstance {
+                bounds_min: bounds.min,
+                bounds_max: bounds.max,
+                index: self.tested.len() as u32,
+            });
+            self.tested.push(occludee);
+        }
+
+        // occludees which weren't tested this frame start over when they next are
+        let frame = self.frame;
+        let tested_since = std::mem::take(&mut self.tested_since);
+        self.tested_since = self
+            .tested
+            .iter()
+            .map(|occludee| {
+                (
+                    *occludee,
+                    tested_since.get(occludee).copied().unwrap_or(frame),
+                )
+            })
+            .collect();
+        self.results
+            .borrow_mut()
+            .retain(|occludee, _| self.tested_since.contains_key(occludee));
+
+        self.uploaded = if instances.is_empty() {
+            None
+        } else {
+            gpu_state.queue.write_buffer(
+                &self.visibility_buffer,
+                0,
+                bytemuck::cast_slice(&vec![0u32; instances.len()]),
+            );
+            let allocation = gpu_state.transient_buffers.allocate(
+                &gpu_state.device,
+                &gpu_state.queue,
+                bytemuck::cast_slice(&instances),
+            );
+            Some((allocation, instances.len() as u32))
+        };
+        self.due.set(true);
+
+        self.uniform.get_mut().camera_depth = camera.depth_linearization_params().extend(0.0);
+        self.uniform.write(gpu_state);
+    }
+
+    /// Draws this frame's proxies against `camera`'s depth attachment, which must hold the
+    /// scene's geometry, and reads back the results. Only the first call after update tests.
+    pub fn test(
+        &self,
+        gpu_state: &mut GpuState,
+        encoder: &mut wgpu::CommandEncoder,
+        camera: &camera::Camera,
+    ) {
+        if !self.due.replace(false) {
+            return;
+        }
+        let ((allocation, occludee_count), test_bind_group, color_attachment) = match (
+            &self.uploaded,
+            &self.test_bind_group,
+            camera.render_buffers.color.as_ref(),
+        ) {
+            (Some(uploaded), Some(test_bind_group), Some(color_attachment)) => {
+                (uploaded, test_bind_group, color_attachment)
+            }
+            _ => return,
+        };
+        let pipeline = match gpu_state.pipeline_vendor.get_pipeline(PIPELINE_ID) {
+            Some(pipeline) => pipeline,
+            None => {
+                eprintln!(
+                    "No pipeline available to test occlusion id: {}",
+                    PIPELINE_ID
+                );
+                return;
+            }
+        };
+
+        // the depth attachment is read by the proxies, so can't be attached
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Occlusion Test Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &color_attachment.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        camera.apply_viewport(&mut render_pass);
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, camera.bind_group(), &[]);
+        render_pass.set_bind_group(1, self.uniform.bind_group(), &[]);
+        render_pass.set_bind_group(2, test_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, gpu_state.transient_buffers.slice(allocation));
+        render_pass.draw(0..14, 0..*occludee_count);
+        drop(render_pass);
+
+        let frame = self.frame;
+        let tested = self.tested.clone();
+        let results = self.results.clone();
+        gpu_state.readbacks.read_buffer(
+            &gpu_state.device,
+            encoder,
+            &self.visibility_buffer,
+            0,
+            (*occludee_count as usize * std::mem::size_of::<u32>()) as wgpu::BufferAddress,
+            move |data| {
+                let visibility: &[u32] = bytemuck::cast_slice(data);
+                let mut results = results.borrow_mut();
+                for (occludee, visible) in tested.iter().zip(visibility.iter()) {
+                    let newer = results
+                        .get(occludee)
+                        .is_none_or(|result| result.frame < frame);
+                    if newer {
+                        results.insert(
+                            *occludee,
+                            OcclusionResult {
+                                frame,
+                                visible: *visible != 0,
+                            },
+                        );
+                    }
+                }
+            },
+        );
+    }
+
+    /// True if the occludee was hidden when last tested, and has been tested continuously
+    /// since. Untested occludees, e.g. those too small or too near the camera, aren't occluded.
+    pub fn is_occluded(&self, occludee: &Occludee) -> bool {
+        let tested_since = match self.tested_since.get(occludee) {
+            Some(frame) => *frame,
+            None => return false,
+        };
+        self.results
+            .borrow()
+            .get(occludee)
+            .is_some_and(|result| result.frame >= tested_since && !result.visible)
+    }
+
+    /// The number of occludees tested this frame
+    pub fn tested_count(&self) -> usize {
+        self.tested.len()
+    }
+
+    fn test_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Occlusion Test Bind Group Layout"),
+        })
+    }
+
+    fn create_test_bind_group(
+        gpu_state: &GpuState,
+        render_buffers: &camera::RenderBuffers,
+        layout: &wgpu::BindGroupLayout,
+        visibility_buffer: &wgpu::Buffer,
+    ) -> Option<wgpu::BindGroup> {
+        render_buffers.depth.as_ref().map(|depth_attachment| {
+            gpu_state
+                .device
+                .create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&depth_attachment.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: visibility_buffer.as_entire_binding(),
+                        },
+                    ],
+                    label: Some("Occlusion Test Bind Group"),
+                })
+        })
+    }
+}
@@ -0,0 +1,480 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, Result};
+
+use super::{model, texture, util::*};
+
+// Leads every packed model, followed by FORMAT_VERSION
+const MAGIC: &[u8; 4] = b"WGPM";
+// Bumped whenever the layout changes, so stale packs are rejected rather than misread
+const FORMAT_VERSION: u32 = 1;
+
+// The cells across a mesh's largest extent its first simplified level of detail is welded to;
+// each further level halves it, see PackedMesh::generate_lods
+const LOD_BASE_RESOLUTION: u32 = 64;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+// the identifier, nine u32 fields, four u32 offsets and lengths, and two u64
+const KTX2_HEADER_SIZE: usize = 80;
+// byte offset, byte length and uncompressed byte length, each a u64
+const KTX2_LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+// the Vulkan formats of texture::Texture's 8 bit RGBA textures
+const VK_FORMAT_R8G8B8A8_UNORM: u32 = 37;
+const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+
+/// A material of a packed model. Textures are file names relative to the resource directory,
+/// KTX2 files once packed, and empty for none.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PackedMaterial {
+    pub name: String,
+    pub ambient: Vec3,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    pub shininess: f32,
+    pub diffuse_texture: String,
+    pub normal_texture: String,
+    pub shininess_texture: String,
+    pub ambient_occlusion_texture: String,
+}
+
+impl From<&tobj::Material> for PackedMaterial {
+    fn from(m: &tobj::Material) -> Self {
+        Self {
+            name: m.name.clone(),
+            ambient: Vec3::from(m.ambient),
+            diffuse: Vec3::from(m.diffuse),
+            specular: Vec3::from(m.specular),
+            shininess: m.shininess,
+            diffuse_texture: m.diffuse_texture.clone(),
+            normal_texture: m.normal_texture.clone(),
+            shininess_texture: m.shininess_texture.clone(),
+            ambient_occlusion_texture: m.ambient_texture.clone(),
+        }
+    }
+}
+
+impl PackedMaterial {
+    /// The material's texture names with whether each holds linear data, e.g. normals or
+    /// occlusion, rather than sRGB color, for tools which convert or rename them
+    pub fn textures_mut(&mut self) -> [(&mut String, bool); 4] {
+        [
+            (&mut self.diffuse_texture, false),
+            (&mut self.normal_texture, true),
+            (&mut self.shininess_texture, true),
+            (&mut self.ambient_occlusion_texture, true),
+        ]
+    }
+}
+
+/// A mesh of a packed model: its vertices, tangents already computed, and the triangle list
+/// of each of its levels of detail, most detailed first, all indexing those vertices
+pub struct PackedMesh {
+    pub name: String,
+    pub material: usize,
+    pub vertices: Vec<model::ModelVertex>,
+    pub lods: Vec<Vec<u32>>,
+}
+
+impl PackedMesh {
+    /// Appends simplified triangle lists until the mesh has `count` levels of detail, or welding
+    /// any coarser stops paying off. Each level welds the vertices of the most detailed within
+    /// a grid cell to one of them, the grid halving in resolution from LOD_BASE_RESOLUTION; a
+    /// level is kept only if it drops at least a quarter of the previous level's triangles.
+    pub fn generate_lods(&mut self, count: usize) {
+        let finest = match self.lods.first() {
+            Some(finest) => finest.clone(),
+            None => return,
+        };
+        let mut resolution = LOD_BASE_RESOLUTION;
+        while self.lods.len() < count && resolution >= 2 {
+            let simplified = weld(&self.vertices, &finest, resolution);
+            resolution /= 2;
+            let previous = self.lods.last().map_or(0, |lod| lod.len());
+            if !simplified.is_empty() && simplified.len() * 4 <= previous * 3 {
+                self.lods.push(simplified);
+            }
+        }
+    }
+
+    /// The triangle list of level of detail `lod`, clamped to the coarsest
+    pub fn lod(&self, lod: usize) -> &[u32] {
+        self.lods
+            .get(lod.min(self.lods.len().saturating_sub(1)))
+            .map_or(&[], |indices| indices.as_slice())
+    }
+}
+
+// Remaps `indices` so the vertices within each of `resolution` cells across the vertices'
+// largest extent share the first of them, dropping the triangles which collapse, and those
+// left duplicating another
+fn weld(vertices: &[model::ModelVertex], indices: &[u32], resolution: u32) -> Vec<u32> {
+    let bounds = match model::Bounds::from_points(vertices.iter().map(|v| v.position)) {
+        Some(bounds) => bounds,
+        None => return indices.to_vec(),
+    };
+    let extent = bounds.max - bounds.min;
+    let cell_size = (extent.x.max(extent.y).max(extent.z) / resolution as f32).max(f32::EPSILON);
+
+    let mut representatives = HashMap::new();
+    let remap = vertices
+        .iter()
+        .enumerate()
+        .map(|(index, vertex)| {
+            let cell = (vertex.position - bounds.min) / cell_size;
+            let key = (cell.x as i32, cell.y as i32, cell.z as i32);
+            *representatives.entry(key).or_insert(index as u32)
+        })
+        .collect::<Vec<_>>();
+
+    let mut seen = HashSet::new();
+    let mut welded = Vec::new();
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| remap[triangle[i] as usize]);
+        if a == b || b == c || a == c {
+            continue;
+        }
+        let mut key = [a, b, c];
+        key.sort_unstable();
+        if seen.insert(key) {
+            welded.extend_from_slice(&[a, b, c]);
+        }
+    }
+    welded
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// A model preprocessed offline by the asset_import tool: geometry ready to upload, with
+/// tangents and levels of detail computed, and materials naming KTX2 textures with their mips
+/// already generated; see resources::load_packed_model. Stored little endian, as MAGIC,
+/// FORMAT_VERSION, then the materials and meshes, each list prefixed by its length.
+pub struct PackedModel {
+    pub materials: Vec<PackedMaterial>,
+    pub meshes: Vec<PackedMesh>,
+}
+
+impl PackedModel {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        put_u32(&mut bytes, FORMAT_VERSION);
+
+        put_u32(&mut bytes, self.materials.len() as u32);
+        for material in &self.materials {
+            put_str(&mut bytes, &material.name);
+            for color in [material.ambient, material.diffuse, material.specular] {
+                for channel in [color.x, color.y, color.z] {
+                    put_f32(&mut bytes, channel);
+                }
+            }
+            put_f32(&mut bytes, material.shininess);
+            for texture in [
+                &material.diffuse_texture,
+                &material.normal_texture,
+                &material.shininess_texture,
+                &material.ambient_occlusion_texture,
+            ] {
+                put_str(&mut bytes, texture);
+            }
+        }
+
+        put_u32(&mut bytes, self.meshes.len() as u32);
+        for mesh in &self.meshes {
+            put_str(&mut bytes, &mesh.name);
+            put_u32(&mut bytes, mesh.material as u32);
+            put_u32(&mut bytes, mesh.vertices.len() as u32);
+            // each vertex as its fields' floats, in declaration order
+            for value in bytemuck::cast_slice::<_, f32>(&mesh.vertices) {
+                put_f32(&mut bytes, *value);
+            }
+            put_u32(&mut bytes, mesh.lods.len() as u32);
+            for lod in &mesh.lods {
+                put_u32(&mut bytes, lod.len() as u32);
+                for index in lod {
+                    put_u32(&mut bytes, *index);
+                }
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(anyhow!("Not a packed model"));
+        }
+        let version = reader.u32()?;
+        if version != FORMAT_VERSION {
+            return Err(anyhow!(
+                "Packed model is version {}, expected {}; repack it",
+                version,
+                FORMAT_VERSION
+            ));
+        }
+
+        let material_count = reader.u32()?;
+        let mut materials = Vec::new();
+        for _ in 0..material_count {
+            materials.push(PackedMaterial {
+                name: reader.string()?,
+                ambient: reader.vec3()?,
+                diffuse: reader.vec3()?,
+                specular: reader.vec3()?,
+                shininess: reader.f32()?,
+                diffuse_texture: reader.string()?,
+                normal_texture: reader.string()?,
+                shininess_texture: reader.string()?,
+                ambient_occlusion_texture: reader.string()?,
+            });
+        }
+
+        let mesh_count = reader.u32()?;
+        let mut meshes = Vec::new();
+        for _ in 0..mesh_count {
+            let name = reader.string()?;
+            let material = reader.u32()? as usize;
+            let vertex_count = reader.u32()? as usize;
+            let vertex_floats =
+                std::mem::size_of::<model::ModelVertex>() / std::mem::size_of::<f32>();
+            let floats = reader
+                .take(vertex_count * vertex_floats * 4)?
+                .chunks_exact(4)
+                .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+                .collect::<Vec<f32>>();
+            let vertices = bytemuck::cast_slice::<_, model::ModelVertex>(&floats).to_vec();
+            let lod_count = reader.u32()?;
+            let mut lods = Vec::new();
+            for _ in 0..lod_count {
+                let index_count = reader.u32()? as usize;
+                let indices = reader
+                    .take(index_count * 4)?
+                    .chunks_exact(4)
+                    .map(|index| u32::from_le_bytes(index.try_into().unwrap()))
+                    .collect::<Vec<u32>>();
+                if let Some(index) = indices.iter().find(|i| **i as usize >= vertex_count) {
+                    return Err(anyhow!(
+                        "Mesh \"{}\" has index {} but only {} vertices",
+                        name,
+                        index,
+                        vertex_count
+                    ));
+                }
+                lods.push(indices);
+            }
+            meshes.push(PackedMesh {
+                name,
+                material,
+                vertices,
+                lods,
+            });
+        }
+
+        Ok(Self { materials, meshes })
+    }
+}
+
+fn put_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_f32(bytes: &mut Vec<u8>, value: f32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_str(bytes: &mut Vec<u8>, value: &str) {
+    put_u32(bytes, value.len() as u32);
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+// Reads the fields written by the put_* functions, failing rather than panicking on files
+// which end early
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| anyhow!("Unexpected end of file at byte {}", self.offset))?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3> {
+        Ok(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Encodes a decoded texture's mip chain as an uncompressed KTX2 file, sRGB unless it's a
+/// normal map, so loading it skips decoding and mip generation
+pub fn encode_ktx2(decoded: &texture::DecodedTexture) -> Vec<u8> {
+    let levels = decoded.levels();
+    let srgb = !decoded.is_normal_map();
+    let dfd = data_format_descriptor(srgb);
+    let (width, height) = levels[0].dimensions();
+
+    let dfd_offset = KTX2_HEADER_SIZE + levels.len() * KTX2_LEVEL_INDEX_ENTRY_SIZE;
+    // levels are stored smallest first, each at a 4 byte aligned offset
+    let mut offsets = vec![0; levels.len()];
+    let mut offset = dfd_offset + dfd.len();
+    for (index, level) in levels.iter().enumerate().rev() {
+        offsets[index] = offset;
+        offset += level.as_raw().len();
+    }
+
+    let mut bytes = Vec::with_capacity(offset);
+    bytes.extend_from_slice(&KTX2_IDENTIFIER);
+    for value in [
+        if srgb {
+            VK_FORMAT_R8G8B8A8_SRGB
+        } else {
+            VK_FORMAT_R8G8B8A8_UNORM
+        },
+        1, // type size
+        width,
+        height,
+        0, // depth
+        0, // layer count, not an array
+        1, // face count
+        levels.len() as u32,
+        0, // no supercompression
+        dfd_offset as u32,
+        dfd.len() as u32,
+        0, // no key/value data
+        0,
+    ] {
+        put_u32(&mut bytes, value);
+    }
+    // no supercompression global data
+    put_u64(&mut bytes, 0);
+    put_u64(&mut bytes, 0);
+    for (level, offset) in levels.iter().zip(offsets) {
+        let len = level.as_raw().len() as u64;
+        put_u64(&mut bytes, offset as u64);
+        put_u64(&mut bytes, len);
+        put_u64(&mut bytes, len);
+    }
+    bytes.extend_from_slice(&dfd);
+    for level in levels.iter().rev() {
+        bytes.extend_from_slice(level.as_raw());
+    }
+    bytes
+}
+
+/// Decodes a KTX2 file of uncompressed 8 bit RGBA levels, as encode_ktx2 writes. Linear
+/// (UNORM) files decode as normal maps, sRGB files as color.
+pub fn decode_ktx2(bytes: &[u8], label: &str) -> Result<texture::DecodedTexture> {
+    let mut reader = Reader { bytes, offset: 0 };
+    if reader.take(KTX2_IDENTIFIER.len())? != KTX2_IDENTIFIER {
+        return Err(anyhow!("\"{}\" isn't a KTX2 file", label));
+    }
+    let format = reader.u32()?;
+    let _type_size = reader.u32()?;
+    let width = reader.u32()?;
+    let height = reader.u32()?;
+    let depth = reader.u32()?;
+    let layer_count = reader.u32()?;
+    let face_count = reader.u32()?;
+    let level_count = reader.u32()?.max(1);
+    let supercompression = reader.u32()?;
+
+    let is_normal_map = match format {
+        VK_FORMAT_R8G8B8A8_UNORM => true,
+        VK_FORMAT_R8G8B8A8_SRGB => false,
+        _ => {
+            return Err(anyhow!(
+                "KTX2 \"{}\" is Vulkan format {}, only 8 bit RGBA is supported",
+                label,
+                format
+            ))
+        }
+    };
+    if depth > 1 || layer_count > 1 || face_count != 1 || supercompression != 0 {
+        return Err(anyhow!(
+            "KTX2 \"{}\" must be a single 2D image without supercompression",
+            label
+        ));
+    }
+
+    reader.offset = KTX2_HEADER_SIZE;
+    let mut levels = Vec::new();
+    for level in 0..level_count {
+        let offset = reader.u64()? as usize;
+        let len = reader.u64()? as usize;
+        let _uncompressed_len = reader.u64()?;
+        let level_width = (width >> level).max(1);
+        let level_height = (height >> level).max(1);
+        let texels = bytes
+            .get(offset..offset.saturating_add(len))
+            .ok_or_else(|| anyhow!("KTX2 \"{}\" level {} is truncated", label, level))?;
+        let image = image::RgbaImage::from_raw(level_width, level_height, texels.to_vec())
+            .ok_or_else(|| {
+                anyhow!(
+                    "KTX2 \"{}\" level {} doesn't fill {}x{} texels",
+                    label,
+                    level,
+                    level_width,
+                    level_height
+                )
+            })?;
+        levels.push(image);
+    }
+    texture::DecodedTexture::from_levels(label, levels, is_normal_map)
+}
+
+// A KTX2 data format descriptor of one basic block, describing 8 bit RGBA texels; see the
+// Khronos Data Format Specification
+fn data_format_descriptor(srgb: bool) -> Vec<u8> {
+    const SAMPLE_COUNT: u32 = 4;
+    const BLOCK_SIZE: u32 = 24 + 16 * SAMPLE_COUNT;
+    // color model RGBSDA, BT.709 primaries, and the transfer function
+    let transfer = if srgb { 2 } else { 1 };
+    let mut words = vec![
+        4 + BLOCK_SIZE,
+        0, // Khronos vendor, basic descriptor type
+        2 | (BLOCK_SIZE << 16),
+        1 | (1 << 8) | (transfer << 16),
+        0, // 1x1x1x1 texel blocks
+        4, // bytes in plane 0
+        0,
+    ];
+    for (index, channel) in [0u32, 1, 2, 15].into_iter().enumerate() {
+        // alpha is never sRGB encoded, which the linear qualifier flags
+        let qualifiers = if channel == 15 && srgb { 0x10 } else { 0 };
+        words.push((index as u32 * 8) | (7 << 16) | ((channel | qualifiers) << 24));
+        words.push(0); // sample position
+        words.push(0); // lower
+        words.push(255); // upper
+    }
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
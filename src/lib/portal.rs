@@ -0,0 +1,483 @@
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use super::{
+    camera,
+    gpu_state::GpuState,
+    render_pipeline::{self, DepthMode, RenderPipelineVendor},
+    resources, texture,
+    util::*,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct PortalUniformData {
+    // places the unit quad, centered in its xy plane and facing +z, in the world
+    model: [[f32; 4]; 4],
+    // x: 1 if the view is mirrored, y: 1 if the portal is open
+    params: [f32; 4],
+    // rgb: color drawn when the portal is closed
+    closed_color: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for PortalUniformData {}
+unsafe impl bytemuck::Zeroable for PortalUniformData {}
+
+fn pipeline_id(depth_mode: DepthMode) -> String {
+    format!("portal_{:?}", depth_mode)
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum PortalKind {
+    // reflects the scene in the portal's plane
+    Mirror,
+    // looks out of `destination`, a frame placed like the portal's transform; looking into
+    // the portal's front, you see out of the destination's front. Placing the destination
+    // facing the portal, or rotated relative to it, makes for non-euclidean spaces.
+    Window { destination: Mat4 },
+}
+
+pub struct PortalDescriptor {
+    pub kind: PortalKind,
+    // places the portal's surface, a rectangle centered in the transform's xy plane and
+    // facing its +z; portals are one sided, and must be rigid (no scale)
+    pub transform: Mat4,
+    // width and height of the surface
+    pub size: Vec2,
+    // how many times the portal can be seen through itself, e.g. a window whose destination
+    // faces it, each costing a render of the scene; mirrors can't see themselves, so only
+    // ever need 1
+    pub recursion_depth: usize,
+    // drawn in place of the view beyond the recursion depth
+    pub closed_color: Vec3,
+}
+
+impl Default for PortalDescriptor {
+    fn default() -> Self {
+        Self {
+            kind: PortalKind::Mirror,
+            transform: Mat4::identity(),
+            size: Vec2::new(1.0, 1.0),
+            recursion_depth: 1,
+            closed_color: Vec3::new(0.1, 0.1, 0.1),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// A mirror or window into another part of the scene. Each frame the scene is rendered once per
+// recursion level from a camera continuing the viewer's through the portal, deepest first,
+// and the portal's surface draws the result over whatever it covers, pixel for pixel. The
+// surface is depth tested like any geometry, so only the pixels it covers show the view.
+// Geometry behind the portal's exit is clipped by the views' oblique near planes.
+//
+// Views through a portal draw other portals closed, and only draw the portal itself open
+// while within its recursion depth.
+pub struct Portal {
+    kind: PortalKind,
+    transform: Mat4,
+    size: Vec2,
+    closed_color: Vec3,
+    is_dirty: bool,
+    // the camera rendering each recursion level's view
+    cameras: Vec<camera::Camera>,
+    // [0]: closed, [1]: open
+    uniform_buffers: [wgpu::Buffer; 2],
+    bind_group_layout: wgpu::BindGroupLayout,
+    closed_bind_group: wgpu::BindGroup,
+    open_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl Portal {
+    /// `camera` is the scene's camera, whose projection the portal's views share.
+    pub fn new(
+        gpu_state: &GpuState,
+        camera: &camera::Camera,
+        descriptor: &PortalDescriptor,
+    ) -> Self {
+        let device = &gpu_state.device;
+        let recursion_depth = match descriptor.kind {
+            PortalKind::Mirror => descriptor.recursion_depth.min(1),
+            PortalKind::Window { .. } => descriptor.recursion_depth,
+        };
+        if recursion_depth != descriptor.recursion_depth {
+            eprintln!(
+                "Portal recursion depth {} clamped to {}, mirrors can't see themselves",
+                descriptor.recursion_depth, recursion_depth
+            );
+        }
+
+        let cameras = (0..recursion_depth)
+            .map(|_| {
+                let (z_near, z_far) = camera.depth_range();
+                let mut view_camera = camera::Camera::new(gpu_state, camera.fov_y(), z_near, z_far);
+                view_camera.set_depth_mode(camera.depth_mode());
+                view_camera
+            })
+            .collect::<Vec<_>>();
+
+        let uniform_buffers = ["Closed", "Open"].map(|label| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("Portal {} Uniform Buffer", label)),
+                contents: bytemuck::cast_slice(&[PortalUniformData {
+                    model: Mat4::identity().into(),
+                    params: [0.0; 4],
+                    closed_color: [0.0; 4],
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            })
+        });
+        let bind_group_layout = Self::bind_group_layout(device);
+        let (closed_bind_group, open_bind_groups) =
+            Self::create_bind_groups(device, &bind_group_layout, &uniform_buffers, &cameras);
+
+        Self {
+            kind: descriptor.kind,
+            transform: descriptor.transform,
+            size: descriptor.size,
+            closed_color: descriptor.closed_color,
+            is_dirty: true,
+            cameras,
+            uniform_buffers,
+            bind_group_layout,
+            closed_bind_group,
+            open_bind_groups,
+        }
+    }
+
+    pub fn kind(&self) -> PortalKind {
+        self.kind
+    }
+
+    pub fn set_kind(&mut self, kind: PortalKind) {
+        self.kind = kind;
+        self.is_dirty = true;
+    }
+
+    pub fn transform(&self) -> Mat4 {
+        self.transform
+    }
+
+    pub fn set_transform(&mut self, transform: Mat4) {
+        self.transform = transform;
+        self.is_dirty = true;
+    }
+
+    pub fn recursion_depth(&self) -> usize {
+        self.cameras.len()
+    }
+
+    /// The camera rendering the view seen through `level` portals.
+    pub fn camera(&self, level: usize) -> Option<&camera::Camera> {
+        self.cameras.get(level)
+    }
+
+    pub fn resize(&mut self, gpu_state: &GpuState, new_size: winit::dpi::PhysicalSize<u32>) {
+        for camera in self.cameras.iter_mut() {
+            camera.resize(gpu_state, new_size);
+        }
+        self.recreate_bind_groups(gpu_state);
+    }
+
+    // the bind groups refer to the cameras' color attachments, so must follow their replacement
+    fn recreate_bind_groups(&mut self, gpu_state: &GpuState) {
+        let (closed_bind_group, open_bind_groups) = Self::create_bind_groups(
+            &gpu_state.device,
+            &self.bind_group_layout,
+            &self.uniform_buffers,
+            &self.cameras,
+        );
+        self.closed_bind_group = closed_bind_group;
+        self.open_bind_groups = open_bind_groups;
+    }
+
+    /// Places the views' cameras to continue `camera` through the portal.
+    pub fn update(&mut self, gpu_state: &GpuState, camera: &camera::Camera) {
+        // the views are sampled pixel for pixel, so follow the camera's render scale
+        let mut replaced_render_buffers = false;
+        for view_camera in self.cameras.iter_mut() {
+            view_camera.set_render_scale(camera.render_scale());
+            replaced_render_buffers |= view_camera.prepare_render_buffers(gpu_state);
+        }
+        if replaced_render_buffers {
+            self.recreate_bind_groups(gpu_state);
+        }
+
+        let (z_near, z_far) = camera.depth_range();
+        let clip_plane = self.exit_frame();
+        let clip_normal = clip_plane.z.truncate().normalize();
+        let clip_point = Point3::from_vec(clip_plane.w.truncate());
+        let clip_plane = clip_normal.extend(-clip_normal.dot(clip_point.to_vec()));
+        let view_transform = self.view_transform();
+        let is_mirrored = self.is_mirrored();
+
+        let queue = &gpu_state.queue;
+        let mut world_transform = camera.world_transform();
+        for view_camera in self.cameras.iter_mut() {
+            world_transform = view_transform * world_transform;
+            let mut look = Mat3::from_cols(
+                world_transform.x.truncate(),
+                world_transform.y.truncate(),
+                world_transform.z.truncate(),
+            );
+            if is_mirrored {
+                // a reflection is left handed, make it a rotation by flipping the view's right
+                // axis; the surface flips the image back
+                look.x = -look.x;
+            }
+            view_camera.set_pose(Point3::from_vec(world_transform.w.truncate()), look);
+            view_camera.set_fov_y(camera.fov_y());
+            view_camera.set_depth_range(z_near, z_far);
+            view_camera.set_depth_mode(camera.depth_mode());
+            view_camera.set_clip_plane(Some(clip_plane));
+            // the surface samples the view pixel for pixel, so it must cover the same region
+            view_camera.set_viewport(camera.viewport());
+            view_camera.set_scissor(camera.scissor());
+            // views are rendered from scratch each frame, whatever the camera keeps
+            view_camera.set_clear(camera::ClearSettings {
+                color: camera
+                    .clear()
+                    .color
+                    .or(camera::ClearSettings::default().color),
+                depth: camera::DepthClear::FarPlane,
+            });
+            view_camera.update(gpu_state);
+        }
+
+        if self.is_dirty {
+            let model = self.transform * Mat4::from_nonuniform_scale(self.size.x, self.size.y, 1.0);
+            let mirrored = if self.is_mirrored() { 1.0 } else { 0.0 };
+            for (open, buffer) in self.uniform_buffers.iter().enumerate() {
+                queue.write_buffer(
+                    buffer,
+                    0,
+                    bytemuck::cast_slice(&[PortalUniformData {
+                        model: model.into(),
+                        params: [mirrored, open as f32, 0.0, 0.0],
+                        closed_color: self.closed_color.extend(1.0).into(),
+                    }]),
+                );
+            }
+            self.is_dirty = false;
+        }
+    }
+
+    /// Whether `camera` can see the portal's front, i.e. whether its views need rendering.
+    pub fn is_visible(&self, camera: &camera::Camera) -> bool {
+        let normal = self.transform.z.truncate();
+        let center = Point3::from_vec(self.transform.w.truncate());
+        if normal.dot(camera.position() - center) <= 0.0 {
+            return false;
+        }
+
+        let half_width = self.transform.x.truncate() * self.size.x * 0.5;
+        let half_height = self.transform.y.truncate() * self.size.y * 0.5;
+        let corners = [
+            center - half_width - half_height,
+            center + half_width - half_height,
+            center + half_width + half_height,
+            center - half_width + half_height,
+        ];
+        let min = corners.iter().fold(corners[0], |min, c| {
+            Point3::new(min.x.min(c.x), min.y.min(c.y), min.z.min(c.z))
+        });
+        let max = corners.iter().fold(corners[0], |max, c| {
+            Point3::new(max.x.max(c.x), max.y.max(c.y), max.z.max(c.z))
+        });
+        camera.frustum().intersects_aabb(min, max)
+    }
+
+    fn is_mirrored(&self) -> bool {
+        matches!(self.kind, PortalKind::Mirror)
+    }
+
+    // The frame whose +z faces the space the views look into, clipping away what's behind it.
+    fn exit_frame(&self) -> Mat4 {
+        match self.kind {
+            PortalKind::Mirror => self.transform,
+            PortalKind::Window { destination } => destination,
+        }
+    }
+
+    // Carries the viewer (or a view) through the portal.
+    fn view_transform(&self) -> Mat4 {
+        match self.kind {
+            PortalKind::Mirror => {
+                let normal = self.transform.z.truncate().normalize();
+                let distance = -normal.dot(self.transform.w.truncate());
+                let reflect = |axis: Vec3| axis - normal * 2.0 * normal.dot(axis);
+                Mat4::from_cols(
+                    reflect(Vec3::unit_x()).extend(0.0),
+                    reflect(Vec3::unit_y()).extend(0.0),
+                    reflect(Vec3::unit_z()).extend(0.0),
+                    (normal * -2.0 * distance).extend(1.0),
+                )
+            }
+            PortalKind::Window { destination } => {
+                // entering the portal's front leaves through the destination's front
+                destination
+                    * Mat4::from_angle_y(Rad::turn_div_2())
+                    * self.transform.invert().unwrap_or_else(Mat4::identity)
+            }
+        }
+    }
+
+    pub fn prepare_pipeline(gpu_state: &mut GpuState, depth_mode: DepthMode) {
+        let pipeline_id = pipeline_id(depth_mode);
+        if gpu_state.pipeline_vendor.has_pipeline(&pipeline_id) {
+            return;
+        }
+
+        let layout = gpu_state
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(&pipeline_id),
+                bind_group_layouts: &[
+                    &Self::bind_group_layout(&gpu_state.device),
+                    &camera::Camera::bind_group_layout(&gpu_state.device),
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("shaders/portal.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                resources::load_string_sync("shaders/portal.wgsl")
+                    .unwrap()
+                    .into(),
+            ),
+        };
+
+        gpu_state.pipeline_vendor.create_render_pipeline(
+            &pipeline_id,
+            &gpu_state.device,
+            render_pipeline::Properties {
+                vs_main: "vs_main_portal",
+                fs_main: Some("fs_main_portal"),
+                layout: &layout,
+                color_format: texture::Texture::COLOR_FORMAT,
+                depth_format: Some(texture::Texture::DEPTH_FORMAT),
+                depth_bias: wgpu::DepthBiasState::default(),
+                depth_mode,
+                vertex_layouts: &[],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                shader,
+                cull_mode: Some(wgpu::Face::Back),
+                blend: None,
+                pass: render_pipeline::Pass::Ambient,
+            },
+        );
+    }
+
+    /// Draws the portal's surface, showing the view through `level` portals, or closed if None.
+    /// Must be drawn after the geometry it covers is shaded, and before lit passes, which
+    /// would otherwise add light over the view.
+    pub fn draw<'a, 'b>(
+        &'a self,
+        render_pass: &'b mut wgpu::RenderPass<'a>,
+        pipeline_vendor: &'a RenderPipelineVendor,
+        camera: &'a camera::Camera,
+        level: Option<usize>,
+    ) where
+        'a: 'b, // 'a lifetime at least as long as 'b
+    {
+        let bind_group = match level.and_then(|level| self.open_bind_groups.get(level)) {
+            Some(bind_group) => bind_group,
+            None => &self.closed_bind_group,
+        };
+
+        let pipeline_id = pipeline_id(camera.depth_mode());
+        if let Some(pipeline) = pipeline_vendor.get_pipeline(&pipeline_id) {
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.set_bind_group(1, camera.bind_group(), &[]);
+            render_pass.draw(0..6, 0..1);
+        } else {
+            eprintln!("No pipeline available to render portal id: {}", pipeline_id);
+        }
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffers: &[wgpu::Buffer; 2],
+        cameras: &[camera::Camera],
+    ) -> (wgpu::BindGroup, Vec<wgpu::BindGroup>) {
+        let create_bind_group = |uniform_buffer: &wgpu::Buffer, view: &texture::Texture| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&view.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&view.sampler),
+                    },
+                ],
+                label: Some("Portal Bind Group"),
+            })
+        };
+
+        let open_bind_groups = cameras
+            .iter()
+            .filter_map(|camera| camera.render_buffers.color.as_ref())
+            .map(|view| create_bind_group(&uniform_buffers[1], view))
+            .collect::<Vec<_>>();
+        // the closed surface samples nothing, but needs something bound which isn't the
+        // target of any of the views it's drawn into
+        let placeholder = texture::Texture::create_color_texture(
+            device,
+            &wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                format: texture::Texture::COLOR_FORMAT,
+                width: 1,
+                height: 1,
+                present_mode: wgpu::PresentMode::Fifo,
+            },
+            "Portal Placeholder",
+        );
+        let closed_bind_group = create_bind_group(&uniform_buffers[0], &placeholder);
+        (closed_bind_group, open_bind_groups)
+    }
+
+    fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("Portal Bind Group Layout"),
+        })
+    }
+}
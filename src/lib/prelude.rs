@@ -0,0 +1,17 @@
+//! The types most programs built on the renderer use, e.g. `use wgpu_demo::prelude::*;`.
+//! Everything else is reached through its module.
+
+pub use super::{
+    app::{self, AppConfig},
+    camera::{self, Camera},
+    gpu_state::{self, GpuState},
+    light::{
+        self, AmbientLightDescriptor, DirectionalLightDescriptor, Light, LightLod, LightType,
+        PointLightDescriptor, SpotLightDescriptor,
+    },
+    model::{self, Instance, Model},
+    resources,
+    scene::{self, Scene},
+    shadow::{self, ShadowDescriptor},
+    util::*,
+};
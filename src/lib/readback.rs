@@ -0,0 +1,243 @@
+use std::sync::{Arc, Mutex};
+
+// Buffers which go unused for this many frames are freed
+const EVICT_AFTER_FRAMES: u64 = 60;
+
+type ReadbackCallback = Box<dyn FnOnce(&[u8])>;
+
+// Rows of a texture readback, which are copied at COPY_BYTES_PER_ROW_ALIGNMENT and handed to
+// the callback tightly packed
+#[derive(Clone, Copy)]
+struct RowLayout {
+    bytes_per_row: usize,
+    padded_bytes_per_row: usize,
+    rows: usize,
+}
+
+struct ReadbackBuffer {
+    buffer: wgpu::Buffer,
+    size: wgpu::BufferAddress,
+    last_used_frame: u64,
+}
+
+struct PendingReadback {
+    buffer: ReadbackBuffer,
+    // bytes of buffer holding the result
+    size: wgpu::BufferAddress,
+    rows: Option<RowLayout>,
+    callback: ReadbackCallback,
+    // set by map_async once the buffer may be read, or couldn't be mapped
+    mapped: Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>,
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Reads GPU buffers and textures back to the CPU without stalling the frame (picking results,
+// statistics and so on). A read copies its source into a mappable buffer from a ring of
+// reusable ones as part of the frame's commands; once the frame is submitted the buffer is
+// mapped asynchronously, and its contents are handed to the read's callback at the start of a
+// later frame, usually one or two after, when the GPU has caught up. Nothing waits on the
+// device. Usage each frame is: begin_frame, record reads, submit, then end_frame; reads
+// recorded outside a frame are caught by a debug assertion.
+#[derive(Default)]
+pub struct ReadbackPool {
+    free: Vec<ReadbackBuffer>,
+    // reads recorded this frame, mapped by end_frame
+    recorded: Vec<PendingReadback>,
+    in_flight: Vec<PendingReadback>,
+    frame: u64,
+    // between begin_frame and end_frame, when reads may be recorded
+    in_frame: bool,
+}
+
+impl ReadbackPool {
+    /// Delivers the results of reads whose buffers have been mapped, and frees buffers which
+    /// have gone unused for a while.
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        self.in_frame = true;
+        device.poll(wgpu::Maintain::Poll);
+
+        let (ready, in_flight): (Vec<_>, Vec<_>) = self
+            .in_flight
+            .drain(..)
+            .partition(|pending| pending.mapped.lock().unwrap().is_some());
+        self.in_flight = in_flight;
+
+        for pending in ready {
+            let result = pending.mapped.lock().unwrap().take().unwrap();
+            match result {
+                Ok(()) => {
+                    {
+                        let slice = pending.buffer.buffer.slice(..pending.size);
+                        let data = slice.get_mapped_range();
+                        match pending.rows {
+                            Some(rows) => {
+                                let mut packed = Vec::with_capacity(rows.bytes_per_row * rows.rows);
+                                for row in data.chunks(rows.padded_bytes_per_row).take(rows.rows) {
+                                    packed.extend_from_slice(&row[..rows.bytes_per_row]);
+                                }
+                                (pending.callback)(&packed);
+                            }
+                            None => (pending.callback)(&data),
+                        }
+                    }
+                    pending.buffer.buffer.unmap();
+                }
+                Err(e) => eprintln!("Readback buffer couldn't be mapped: {:?}", e),
+            }
+            self.free.push(pending.buffer);
+        }
+
+        let frame = self.frame;
+        self.free
+            .retain(|buffer| frame - buffer.last_used_frame < EVICT_AFTER_FRAMES);
+    }
+
+    /// Records a copy of `size` bytes of `source` from `offset`, which must be multiples of
+    /// wgpu::COPY_BUFFER_ALIGNMENT; `source` needs COPY_SRC usage. `callback` receives the
+    /// bytes a frame or more later.
+    pub fn read_buffer<F>(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        size: wgpu::BufferAddress,
+        callback: F,
+    ) where
+        F: FnOnce(&[u8]) + 'static,
+    {
+        let buffer = self.acquire(device, size);
+        encoder.copy_buffer_to_buffer(source, offset, &buffer.buffer, 0, size);
+        self.record(buffer, size, None, Box::new(callback));
+    }
+
+    /// Records a copy of a `width` by `height` texel region of `texture`'s first mip level from
+    /// `origin`; `texture` needs COPY_SRC usage. `callback` receives the texels a frame or more
+    /// later, rows tightly packed from the top.
+    #[allow(clippy::too_many_arguments)]
+    pub fn read_texture<F>(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        format: wgpu::TextureFormat,
+        origin: (u32, u32),
+        width: u32,
+        height: u32,
+        callback: F,
+    ) where
+        F: FnOnce(&[u8]) + 'static,
+    {
+        let bytes_per_row = width * format.describe().block_size as u32;
+        let padded_bytes_per_row = bytes_per_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let size = padded_bytes_per_row as wgpu::BufferAddress * height as wgpu::BufferAddress;
+
+        let buffer = self.acquire(device, size);
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: origin.0,
+                    y: origin.1,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: std::num::NonZeroU32::new(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.record(
+            buffer,
+            size,
+            Some(RowLayout {
+                bytes_per_row: bytes_per_row as usize,
+                padded_bytes_per_row: padded_bytes_per_row as usize,
+                rows: height as usize,
+            }),
+            Box::new(callback),
+        );
+    }
+
+    /// The number of reads whose results haven't been delivered yet
+    pub fn pending_count(&self) -> usize {
+        self.recorded.len() + self.in_flight.len()
+    }
+
+    /// Must be called after the submission holding this frame's reads; maps their buffers.
+    pub fn end_frame(&mut self) {
+        self.in_frame = false;
+        for pending in self.recorded.drain(..) {
+            let mapped = pending.mapped.clone();
+            pending.buffer.buffer.slice(..pending.size).map_async(
+                wgpu::MapMode::Read,
+                move |result| {
+                    *mapped.lock().unwrap() = Some(result);
+                },
+            );
+            self.in_flight.push(pending);
+        }
+        self.frame += 1;
+    }
+
+    // Takes the smallest free buffer which holds `size` bytes, or creates one
+    fn acquire(&mut self, device: &wgpu::Device, size: wgpu::BufferAddress) -> ReadbackBuffer {
+        let size = size.max(wgpu::COPY_BUFFER_ALIGNMENT);
+        let index = self
+            .free
+            .iter()
+            .enumerate()
+            .filter(|(_, buffer)| buffer.size >= size)
+            .min_by_key(|(_, buffer)| buffer.size)
+            .map(|(index, _)| index);
+
+        let mut buffer = match index {
+            Some(index) => self.free.swap_remove(index),
+            None => ReadbackBuffer {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Readback Buffer"),
+                    size,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                size,
+                last_used_frame: self.frame,
+            },
+        };
+        buffer.last_used_frame = self.frame;
+        buffer
+    }
+
+    fn record(
+        &mut self,
+        buffer: ReadbackBuffer,
+        size: wgpu::BufferAddress,
+        rows: Option<RowLayout>,
+        callback: ReadbackCallback,
+    ) {
+        debug_assert!(
+            self.in_frame,
+            "ReadbackPool read recorded outside a frame, see gpu_state::GpuState::begin_frame"
+        );
+        self.recorded.push(PendingReadback {
+            buffer,
+            size,
+            rows,
+            callback,
+            mapped: Arc::new(Mutex::new(None)),
+        });
+    }
+}
@@ -0,0 +1,161 @@
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+use wgpu::util::DeviceExt;
+
+use super::{texture, util::*};
+
+// Must match MAX_REFLECTION_PROBES in model.wgsl
+pub const MAX_REFLECTION_PROBES: usize = 32;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ReflectionProbesUniformData {
+    // x: the number of probe slots to consider, the highest placed probe's index + 1
+    count: Vec4,
+    // xyz: the probe's center, w: its radius of influence, 0 for empty slots
+    probes: [Vec4; MAX_REFLECTION_PROBES],
+}
+
+unsafe impl bytemuck::Pod for ReflectionProbesUniformData {}
+unsafe impl bytemuck::Zeroable for ReflectionProbesUniformData {}
+
+/// Where a reflection probe was captured, and the sphere within which it's reflected in place
+/// of the environment map
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReflectionProbe {
+    pub center: Point3,
+    pub radius: f32,
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Cubemaps captured around the scene, held in one texture::CubemapArray so any number of
+// probes up to MAX_REFLECTION_PROBES share a single binding. They're bound with the scene's
+// environment::Environment, and materials reflect the probe nearest each fragment whose radius
+// holds it, blending to the environment map toward its edge.
+pub struct ReflectionProbes {
+    cubemaps: texture::CubemapArray,
+    // one slot per cubemap of the array
+    probes: Vec<Option<ReflectionProbe>>,
+    uniform_buffer: wgpu::Buffer,
+    is_dirty: bool,
+}
+
+impl ReflectionProbes {
+    /// Creates room for `capacity` probes (at most MAX_REFLECTION_PROBES), whose captures are
+    /// cubemaps of faces `size` texels square with `mip_level_count` mip levels.
+    pub fn new(device: &wgpu::Device, capacity: usize, size: u32, mip_level_count: u32) -> Self {
+        let capacity = capacity.clamp(1, MAX_REFLECTION_PROBES);
+        let cubemaps = texture::CubemapArray::new(
+            device,
+            size,
+            mip_level_count,
+            capacity as u32,
+            "Reflection Probes",
+        );
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("ReflectionProbes::uniform_buffer"),
+            contents: bytemuck::cast_slice(&[ReflectionProbesUniformData {
+                count: Vec4::new(0.0, 0.0, 0.0, 0.0),
+                probes: [Vec4::new(0.0, 0.0, 0.0, 0.0); MAX_REFLECTION_PROBES],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            cubemaps,
+            probes: vec![None; capacity],
+            uniform_buffer,
+            is_dirty: false,
+        }
+    }
+
+    /// The number of probes which can be placed
+    pub fn capacity(&self) -> usize {
+        self.probes.len()
+    }
+
+    /// Face size of the probes' captures, in texels
+    pub fn size(&self) -> u32 {
+        self.cubemaps.size()
+    }
+
+    pub fn probe(&self, index: usize) -> Option<&ReflectionProbe> {
+        self.probes.get(index)?.as_ref()
+    }
+
+    /// Places probe `index` at `probe`, reflecting `capture`, e.g. a texture::Cubemap rendered
+    /// from the probe's center and filtered. The capture must be size() texels square; it's
+    /// copied by `encoder`, so may be rendered into again after that's submitted.
+    pub fn set(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        index: usize,
+        probe: ReflectionProbe,
+        capture: &texture::Cubemap,
+    ) -> Result<()> {
+        if index >= self.capacity() {
+            return Err(anyhow!(
+                "Reflection probe {} is beyond the capacity of {}",
+                index,
+                self.capacity()
+            ));
+        }
+        self.cubemaps
+            .copy_from(device, encoder, index as u32, capture)?;
+        self.probes[index] = Some(probe);
+        self.is_dirty = true;
+        Ok(())
+    }
+
+    /// Moves or resizes probe `index` without recapturing it. Has no effect on empty slots.
+    pub fn set_placement(&mut self, index: usize, probe: ReflectionProbe) {
+        if let Some(Some(placed)) = self.probes.get_mut(index) {
+            if *placed != probe {
+                *placed = probe;
+                self.is_dirty = true;
+            }
+        }
+    }
+
+    /// Removes probe `index`, so the fragments it covered reflect the environment map again
+    pub fn remove(&mut self, index: usize) {
+        if let Some(slot) = self.probes.get_mut(index) {
+            if slot.take().is_some() {
+                self.is_dirty = true;
+            }
+        }
+    }
+
+    pub fn texture(&self) -> &Rc<texture::Texture> {
+        self.cubemaps.texture()
+    }
+
+    pub fn uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.uniform_buffer
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        if !self.is_dirty {
+            return;
+        }
+        let mut data = ReflectionProbesUniformData {
+            count: Vec4::new(0.0, 0.0, 0.0, 0.0),
+            probes: [Vec4::new(0.0, 0.0, 0.0, 0.0); MAX_REFLECTION_PROBES],
+        };
+        for (index, probe) in self.probes.iter().enumerate() {
+            if let Some(probe) = probe {
+                data.probes[index] = Vec4::new(
+                    probe.center.x,
+                    probe.center.y,
+                    probe.center.z,
+                    probe.radius.max(0.0),
+                );
+                data.count.x = (index + 1) as f32;
+            }
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[data]));
+        self.is_dirty = false;
+    }
+}
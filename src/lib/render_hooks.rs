@@ -0,0 +1,61 @@
+use super::{camera, environment, gpu_state::GpuState};
+
+/// Where in the frame a RenderHook is called. The ambient and lit points fall within the
+/// scene's render pass, drawn by the scene's camera; the compositor points fall between passes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderHookPoint {
+    BeforeAmbient,
+    AfterAmbient,
+    // after portal surfaces and toon outlines, which follow the ambient pass
+    BeforeLit,
+    AfterLit,
+    BeforeCompositor,
+    AfterCompositor,
+}
+
+impl RenderHookPoint {
+    /// True for the points within the scene's render pass, where hooks are drawn rather than encoded
+    pub fn is_in_scene_pass(&self) -> bool {
+        match self {
+            RenderHookPoint::BeforeAmbient
+            | RenderHookPoint::AfterAmbient
+            | RenderHookPoint::BeforeLit
+            | RenderHookPoint::AfterLit => true,
+            RenderHookPoint::BeforeCompositor | RenderHookPoint::AfterCompositor => false,
+        }
+    }
+}
+
+pub struct RenderHookContext<'a> {
+    pub gpu_state: &'a GpuState,
+    // the scene's camera, whose render_buffers are the frame's color and depth attachments
+    pub camera: &'a camera::Camera,
+    pub environment: &'a environment::Environment,
+}
+
+/// Injects custom drawing into the frame, see scene::Scene::render_hooks. Both methods default
+/// to doing nothing, so a hook implements those it needs.
+pub trait RenderHook {
+    /// Called at the points within the scene's render pass. `render_pass` has the camera's
+    /// attachments and viewport; pipelines must match its color and depth formats and
+    /// camera::Camera::depth_mode.
+    fn draw<'a>(
+        &'a self,
+        _point: RenderHookPoint,
+        _render_pass: &mut wgpu::RenderPass<'a>,
+        _context: &RenderHookContext<'a>,
+    ) {
+    }
+
+    /// Called before and after the compositor, to record passes of its own. Before the
+    /// compositor the camera's attachments hold the rendered scene; `output` is the surface
+    /// texture the compositor draws to.
+    fn encode(
+        &self,
+        _point: RenderHookPoint,
+        _encoder: &mut wgpu::CommandEncoder,
+        _output: &wgpu::TextureView,
+        _context: &RenderHookContext,
+    ) {
+    }
+}
@@ -1,18 +1,92 @@
 use std::collections::HashMap;
 
-#[derive(Clone, Copy, Debug)]
+use super::material_variant::MaterialVariantKey;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Pass {
     Ambient,
     Lit,
+    Shadow,
+    Outline,
+    // position-only depth, see depth_pass
+    Depth,
+    // depth tested overlay lines, see debug_draw
+    Debug,
+    // alpha blended camera facing quads, see weather
+    Particles,
+}
+
+// Which end of the [0,1] depth range is near. Reversed depth maps the near plane to 1 and
+// the far plane to 0, which pairs with a floating point depth buffer to spread precision far
+// more evenly across the view distance, reducing z-fighting in distant geometry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DepthMode {
+    #[default]
+    Standard,
+    Reversed,
+}
+
+impl DepthMode {
+    pub fn compare_function(&self) -> wgpu::CompareFunction {
+        match self {
+            DepthMode::Standard => wgpu::CompareFunction::LessEqual,
+            DepthMode::Reversed => wgpu::CompareFunction::GreaterEqual,
+        }
+    }
+
+    // the value depth attachments are cleared to, i.e. the far plane
+    pub fn clear_depth(&self) -> f32 {
+        match self {
+            DepthMode::Standard => 1.0,
+            DepthMode::Reversed => 0.0,
+        }
+    }
 }
 
+// Blend states for use with `Properties::blend`
+
+// e.g. particles and light accumulation, dst + src
+pub const ADDITIVE_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent::OVER,
+};
+
+// e.g. UI, where color has already been multiplied by alpha
+pub const PREMULTIPLIED_ALPHA_BLEND: wgpu::BlendState =
+    wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING;
+
+// e.g. decals darkening what's beneath them, dst * src
+pub const MULTIPLY_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Dst,
+        dst_factor: wgpu::BlendFactor::Zero,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
 pub struct Properties<'a> {
     pub vs_main: &'a str,
-    pub fs_main: &'a str,
+    pub fs_main: Option<&'a str>,
     pub layout: &'a wgpu::PipelineLayout,
     pub color_format: wgpu::TextureFormat,
     pub depth_format: Option<wgpu::TextureFormat>,
+    pub depth_bias: wgpu::DepthBiasState,
+    // must match the projection of the camera (or light) the pipeline renders with
+    pub depth_mode: DepthMode,
+    pub cull_mode: Option<wgpu::Face>,
+    // overrides the blend state which is otherwise derived from `pass`
+    pub blend: Option<wgpu::BlendState>,
     pub vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>],
+    pub topology: wgpu::PrimitiveTopology,
     pub shader: wgpu::ShaderModuleDescriptor<'a>,
     pub pass: Pass,
 }
@@ -20,6 +94,7 @@ pub struct Properties<'a> {
 #[derive(Default)]
 pub struct RenderPipelineVendor {
     pipelines: HashMap<String, wgpu::RenderPipeline>,
+    material_pipelines: HashMap<MaterialVariantKey, wgpu::RenderPipeline>,
 }
 
 impl RenderPipelineVendor {
@@ -31,31 +106,74 @@ impl RenderPipelineVendor {
         self.pipelines.get(named)
     }
 
+    pub fn has_material_pipeline(&self, key: &MaterialVariantKey) -> bool {
+        self.material_pipelines.contains_key(key)
+    }
+
+    pub fn get_material_pipeline(&self, key: &MaterialVariantKey) -> Option<&wgpu::RenderPipeline> {
+        self.material_pipelines.get(key)
+    }
+
+    /// The number of material pipelines created, one per distinct MaterialVariantKey however
+    /// many materials share it; e.g. to check a scene's materials aren't fragmenting into
+    /// variants which each cost a compile at startup.
+    pub fn material_pipeline_count(&self) -> usize {
+        self.material_pipelines.len()
+    }
+
     pub fn create_render_pipeline(
         &mut self,
         named: &str,
         device: &wgpu::Device,
         properties: Properties,
     ) -> &wgpu::RenderPipeline {
+        let pipeline = Self::build_render_pipeline(named, device, properties);
+        self.pipelines.insert(named.to_owned(), pipeline);
+        self.pipelines.get(named).unwrap()
+    }
+
+    pub fn create_material_pipeline(
+        &mut self,
+        key: MaterialVariantKey,
+        device: &wgpu::Device,
+        properties: Properties,
+    ) -> &wgpu::RenderPipeline {
+        let pipeline = Self::build_render_pipeline(&key.to_string(), device, properties);
+        self.material_pipelines.insert(key, pipeline);
+        self.material_pipelines.get(&key).unwrap()
+    }
+
+    fn build_render_pipeline(
+        named: &str,
+        device: &wgpu::Device,
+        properties: Properties,
+    ) -> wgpu::RenderPipeline {
         let shader = device.create_shader_module(properties.shader);
         let depth_write_enabled = match properties.pass {
             Pass::Ambient => true,
             Pass::Lit => false,
+            Pass::Shadow => true,
+            Pass::Outline => true,
+            Pass::Depth => true,
+            Pass::Debug => false,
+            Pass::Particles => false,
         };
 
-        let blend_state = match properties.pass {
-            Pass::Ambient => wgpu::BlendState::REPLACE,
-            Pass::Lit => wgpu::BlendState {
-                color: wgpu::BlendComponent {
-                    src_factor: wgpu::BlendFactor::One,
-                    dst_factor: wgpu::BlendFactor::One,
-                    operation: wgpu::BlendOperation::Add,
-                },
-                alpha: wgpu::BlendComponent::OVER,
-            },
-        };
+        // shadow passes write to formats (e.g. Rg32Float moments) which may not be blendable
+        let blend_state = properties.blend.or(match properties.pass {
+            Pass::Ambient | Pass::Outline => Some(wgpu::BlendState::REPLACE),
+            Pass::Shadow | Pass::Depth => None,
+            Pass::Lit => Some(ADDITIVE_BLEND),
+            Pass::Debug | Pass::Particles => Some(wgpu::BlendState::ALPHA_BLENDING),
+        });
+
+        let color_targets = [Some(wgpu::ColorTargetState {
+            format: properties.color_format,
+            blend: blend_state,
+            write_mask: wgpu::ColorWrites::ALL,
+        })];
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some(&format!("RenderPipeline: {}", named)),
             layout: Some(properties.layout),
             vertex: wgpu::VertexState {
@@ -63,20 +181,17 @@ impl RenderPipelineVendor {
                 entry_point: properties.vs_main,
                 buffers: properties.vertex_layouts,
             },
-            fragment: Some(wgpu::FragmentState {
+            // depth-only passes have no fragment stage
+            fragment: properties.fs_main.map(|fs_main| wgpu::FragmentState {
                 module: &shader,
-                entry_point: properties.fs_main,
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: properties.color_format,
-                    blend: Some(blend_state),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
+                entry_point: fs_main,
+                targets: &color_targets,
             }),
             primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
+                topology: properties.topology,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
+                cull_mode: properties.cull_mode,
                 polygon_mode: wgpu::PolygonMode::Fill,
                 unclipped_depth: false,
                 conservative: false,
@@ -86,9 +201,9 @@ impl RenderPipelineVendor {
                 .map(|format| wgpu::DepthStencilState {
                     format,
                     depth_write_enabled,
-                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    depth_compare: properties.depth_mode.compare_function(),
                     stencil: wgpu::StencilState::default(),
-                    bias: wgpu::DepthBiasState::default(),
+                    bias: properties.depth_bias,
                 }),
             multisample: wgpu::MultisampleState {
                 count: 1,
@@ -96,9 +211,6 @@ impl RenderPipelineVendor {
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
-        });
-
-        self.pipelines.insert(named.to_owned(), pipeline);
-        self.pipelines.get(named).unwrap()
+        })
     }
 }
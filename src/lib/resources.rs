@@ -1,11 +1,9 @@
-use cgmath::prelude::*;
 use std::{
+    collections::HashMap,
     io::{BufReader, Cursor},
-    rc::Rc,
 };
-use wgpu::util::DeviceExt;
 
-use super::{model, texture, util::*};
+use super::{gltf, mesh_builder, model, packed_asset, shader_preprocessor, texture, util::*};
 
 /////////////////////////////////////////
 
@@ -13,6 +11,83 @@ pub fn load_string_sync(file_name: &str) -> anyhow::Result<String> {
     pollster::block_on(load_string(file_name))
 }
 
+/// Loads a WGSL shader, resolving its `#include`s and preprocessing it with `defines`; see
+/// shader_preprocessor
+pub fn load_shader_sync(file_name: &str, defines: &[&str]) -> anyhow::Result<String> {
+    let source =
+        shader_preprocessor::resolve_includes(&load_string_sync(file_name)?, load_string_sync)?;
+    shader_preprocessor::preprocess(&source, defines)
+}
+
+/// Watches a shader's source in the crate's res/ directory, and the files it includes, for
+/// edits made while running; the copies in OUT_DIR which load_shader_sync reads only change
+/// when the crate is rebuilt. Builds run away from their source tree never see a change.
+pub struct ShaderWatch {
+    file_name: String,
+    // the shader and every file it included when last loaded, with their modification times
+    files: Vec<(std::path::PathBuf, Option<std::time::SystemTime>)>,
+}
+
+impl ShaderWatch {
+    pub fn new(file_name: &str) -> Self {
+        let mut watch = Self {
+            file_name: file_name.to_owned(),
+            files: Vec::new(),
+        };
+        // loading finds the included files to watch
+        let _ = watch.load(&[]);
+        watch
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    /// True when the shader or a file it includes has been modified since it was last loaded
+    /// (or since the watch was created)
+    pub fn changed(&self) -> bool {
+        self.files
+            .iter()
+            .any(|(path, modified)| Self::modified(path) != *modified)
+    }
+
+    /// Loads the shader from its source as load_shader_sync does, and watches the files it
+    /// includes from now on. Files are watched even when loading fails, so a fix is seen.
+    pub fn load(&mut self, defines: &[&str]) -> anyhow::Result<String> {
+        let included = std::cell::RefCell::new(Vec::new());
+        let load = |file_name: &str| {
+            included.borrow_mut().push(file_name.to_owned());
+            std::fs::read_to_string(Self::source_path(file_name)).map_err(anyhow::Error::from)
+        };
+        let source = load(&self.file_name)
+            .and_then(|source| shader_preprocessor::resolve_includes(&source, load))
+            .and_then(|source| shader_preprocessor::preprocess(&source, defines));
+        self.watch_files(&included.into_inner());
+        source
+    }
+
+    fn watch_files(&mut self, file_names: &[String]) {
+        self.files = file_names
+            .iter()
+            .map(|file_name| {
+                let path = Self::source_path(file_name);
+                let modified = Self::modified(&path);
+                (path, modified)
+            })
+            .collect();
+    }
+
+    fn source_path(file_name: &str) -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("res")
+            .join(file_name)
+    }
+
+    fn modified(path: &std::path::Path) -> Option<std::time::SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+}
+
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
     let path = std::path::Path::new(env!("OUT_DIR"))
         .join("res")
@@ -45,6 +120,9 @@ pub fn load_texture_sync(
     ))
 }
 
+/// Loads an image file as a texture. KTX2 files, e.g. as written by the asset_import tool,
+/// are uploaded with the mips they hold in the color space they name, whatever
+/// `is_normal_map` and `generate_mipmaps` ask.
 pub async fn load_texture(
     file_name: &str,
     device: &wgpu::Device,
@@ -53,6 +131,10 @@ pub async fn load_texture(
     generate_mipmaps: bool,
 ) -> anyhow::Result<texture::Texture> {
     let data = load_binary(file_name).await?;
+    if is_ktx2(file_name) {
+        let decoded = packed_asset::decode_ktx2(&data, file_name)?;
+        return Ok(texture::Texture::from_decoded(device, queue, &decoded));
+    }
     texture::Texture::from_bytes(
         device,
         queue,
@@ -63,6 +145,29 @@ pub async fn load_texture(
     )
 }
 
+pub fn load_texture_array_sync(
+    file_names: &[&str],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    is_normal_map: bool,
+) -> anyhow::Result<texture::Texture> {
+    pollster::block_on(load_texture_array(file_names, device, queue, is_normal_map))
+}
+
+pub async fn load_texture_array(
+    file_names: &[&str],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    is_normal_map: bool,
+) -> anyhow::Result<texture::Texture> {
+    let mut layers = Vec::with_capacity(file_names.len());
+    for file_name in file_names {
+        layers.push(load_binary(file_name).await?);
+    }
+    let layers = layers.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    texture::Texture::array_from_bytes(device, queue, &layers, &file_names.join(","), is_normal_map)
+}
+
 pub fn load_cubemap_texture_sync(
     file_name: &str,
     device: &wgpu::Device,
@@ -86,8 +191,8 @@ pub fn load_model_sync(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     instances: &[model::Instance],
-    environment_map: Rc<texture::Texture>,
     generate_mipmaps: bool,
+    retain_mesh_data: bool,
 ) -> anyhow::Result<model::Model> {
     pollster::block_on(load_model(
         file_name,
@@ -95,20 +200,44 @@ pub fn load_model_sync(
         device,
         queue,
         instances,
-        environment_map,
         generate_mipmaps,
+        retain_mesh_data,
     ))
 }
 
+/// Loads an OBJ or glTF (.gltf or .glb) model and its materials. When retain_mesh_data is set
+/// each mesh keeps a copy of its geometry as Mesh::data after upload, e.g. for picking, physics
+/// or recomputing bounds. glTF models are read by gltf::GltfModel, decoding meshopt compressed
+/// geometry; Draco compressed geometry isn't supported, and is rejected with an error saying
+/// so. material_name only applies to OBJ models, whose MTL library it replaces.
 pub async fn load_model(
     file_name: &str,
     material_name: Option<&str>,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     instances: &[model::Instance],
-    environment_map: Rc<texture::Texture>,
     generate_mipmaps: bool,
+    retain_mesh_data: bool,
 ) -> anyhow::Result<model::Model> {
+    match model_extension(file_name).as_deref() {
+        Some("gltf" | "glb") => {
+            return load_gltf_model(
+                file_name,
+                device,
+                queue,
+                instances,
+                generate_mipmaps,
+                retain_mesh_data,
+            )
+            .await
+        }
+        Some("drc") => anyhow::bail!(
+            "load_model - \"{}\" is a Draco file, Draco compressed geometry isn't supported",
+            file_name
+        ),
+        _ => {}
+    }
+
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
@@ -130,126 +259,290 @@ pub async fn load_model(
 
     let mut materials = Vec::new();
     for m in obj_materials? {
-        let ambient = Vec4::new(m.ambient[0], m.ambient[1], m.ambient[2], 1.0);
-        let diffuse = Vec4::new(m.diffuse[0], m.diffuse[1], m.diffuse[2], 1.0);
-        let specular = Vec4::new(m.specular[0], m.specular[1], m.specular[2], 1.0);
-
-        let diffuse_texture =
-            load_texture(&m.diffuse_texture, device, queue, false, generate_mipmaps)
-                .await
-                .ok();
-        let normal_texture = load_texture(&m.normal_texture, device, queue, true, generate_mipmaps)
-            .await
-            .ok();
-        let shininess_texture =
-            load_texture(&m.shininess_texture, device, queue, false, generate_mipmaps)
-                .await
-                .ok();
-
-        materials.push(model::Material::new(
-            device,
-            model::MaterialProperties {
-                name: &m.name,
-                ambient,
-                diffuse,
-                specular,
-                shininess: m.shininess,
-                environment_map: Some(environment_map.clone()),
-                diffuse_texture,
-                normal_texture,
-                shininess_texture,
-            },
-        ));
+        let material = packed_asset::PackedMaterial::from(&m);
+        materials.push(
+            load_material(
+                file_name,
+                &material,
+                &HashMap::new(),
+                device,
+                queue,
+                generate_mipmaps,
+            )
+            .await,
+        );
     }
 
     let meshes = models
         .into_iter()
         .map(|m| {
-            let mut vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| model::ModelVertex {
-                    position: Point3::new(
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ),
-                    tex_coords: Vec2::new(m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]),
-                    normal: Vec3::new(
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ),
-                    tangent: Vec3::zero(),
-                    bitangent: Vec3::zero(),
-                })
-                .collect::<Vec<_>>();
-
-            let indices = &m.mesh.indices;
-            let mut triangles_included = (0..vertices.len()).collect::<Vec<_>>();
-
-            // compute tangent and bitangent
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-
-                let pos0: Vec3 = v0.position.to_vec();
-                let pos1: Vec3 = v1.position.to_vec();
-                let pos2: Vec3 = v2.position.to_vec();
-
-                let uv0: Vec2 = v0.tex_coords;
-                let uv1: Vec2 = v1.tex_coords;
-                let uv2: Vec2 = v2.tex_coords;
-
-                let delta_pos1 = pos1 - pos0;
-                let delta_pos2 = pos2 - pos0;
-                let delta_uv1 = uv1 - uv0;
-                let delta_uv2 = uv2 - uv0;
-
-                let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-                let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-                let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
-
-                vertices[c[0] as usize].tangent = tangent + vertices[c[0] as usize].tangent;
-                vertices[c[1] as usize].tangent = tangent + vertices[c[1] as usize].tangent;
-                vertices[c[2] as usize].tangent = tangent + vertices[c[2] as usize].tangent;
-                vertices[c[0] as usize].bitangent = bitangent + vertices[c[0] as usize].bitangent;
-                vertices[c[1] as usize].bitangent = bitangent + vertices[c[1] as usize].bitangent;
-                vertices[c[2] as usize].bitangent = bitangent + vertices[c[2] as usize].bitangent;
-
-                // Used to average the tangents/bitangents
-                triangles_included[c[0] as usize] += 1;
-                triangles_included[c[1] as usize] += 1;
-                triangles_included[c[2] as usize] += 1;
-            }
+            mesh_builder::MeshBuilder::from_obj(file_name, &m.mesh).build(device, retain_mesh_data)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
 
-            for (i, n) in triangles_included.into_iter().enumerate() {
-                let denom = 1.0 / n as f32;
-                let mut v = &mut vertices[i];
-                v.tangent = (v.tangent * denom).normalize();
-                v.bitangent = (v.bitangent * denom).normalize();
-            }
+    Ok(model::Model::new(device, meshes, materials, instances))
+}
+
+pub fn load_packed_model_sync(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    instances: &[model::Instance],
+    lod: usize,
+    retain_mesh_data: bool,
+) -> anyhow::Result<model::Model> {
+    pollster::block_on(load_packed_model(
+        file_name,
+        device,
+        queue,
+        instances,
+        lod,
+        retain_mesh_data,
+    ))
+}
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            model::Mesh {
-                name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+/// Loads a model packed offline by the asset_import tool, see packed_asset::PackedModel. Its
+/// geometry is uploaded as stored, with no parsing or tangent generation, and its textures
+/// are KTX2 files holding their mips, so none are decoded or generated either. Each mesh is
+/// built from its level of detail `lod`, clamped to its coarsest; retain_mesh_data is as for
+/// load_model.
+pub async fn load_packed_model(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    instances: &[model::Instance],
+    lod: usize,
+    retain_mesh_data: bool,
+) -> anyhow::Result<model::Model> {
+    let data = load_binary(file_name).await?;
+    let packed = packed_asset::PackedModel::from_bytes(&data)
+        .map_err(|e| anyhow::anyhow!("load_packed_model - \"{}\": {}", file_name, e))?;
+
+    let mut materials = Vec::new();
+    for material in &packed.materials {
+        materials
+            .push(load_material(file_name, material, &HashMap::new(), device, queue, true).await);
+    }
+
+    let meshes = packed
+        .meshes
+        .iter()
+        .map(|mesh| {
+            let indices = mesh.lod(lod);
+            let mut builder = mesh_builder::MeshBuilder::with_capacity(
+                &mesh.name,
+                mesh.vertices.len(),
+                indices.len(),
+            );
+            builder.set_material(mesh.material);
+            for vertex in &mesh.vertices {
+                builder.push_vertex(*vertex);
             }
+            for triangle in indices.chunks_exact(3) {
+                builder.push_triangle(triangle[0], triangle[1], triangle[2]);
+            }
+            builder.build(device, retain_mesh_data)
         })
-        .collect::<Vec<_>>();
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(model::Model::new(device, meshes, materials, instances))
+}
+
+// Loads a glTF model for load_model, see gltf::GltfModel::read. Buffers and images the file
+// refers to are loaded relative to it.
+async fn load_gltf_model(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    instances: &[model::Instance],
+    generate_mipmaps: bool,
+    retain_mesh_data: bool,
+) -> anyhow::Result<model::Model> {
+    let directory = std::path::Path::new(file_name)
+        .parent()
+        .unwrap_or_else(|| std::path::Path::new(""));
+    let relative = |uri: &str| directory.join(uri).to_string_lossy().into_owned();
+
+    let data = load_binary(file_name).await?;
+    let gltf = gltf::GltfModel::read(file_name, &data, |uri| {
+        pollster::block_on(load_binary(&relative(uri)))
+    })
+    .map_err(|e| anyhow::anyhow!("load_model - \"{}\": {:?}", file_name, e))?;
+
+    let mut materials = Vec::new();
+    for material in &gltf.materials {
+        let mut material = material.clone();
+        for (texture, _) in material.textures_mut() {
+            if !texture.is_empty() && !gltf.images.contains_key(texture.as_str()) {
+                *texture = relative(texture);
+            }
+        }
+        materials.push(
+            load_material(
+                file_name,
+                &material,
+                &gltf.images,
+                device,
+                queue,
+                generate_mipmaps,
+            )
+            .await,
+        );
+    }
+
+    let meshes = gltf
+        .meshes
+        .into_iter()
+        .map(|builder| builder.build(device, retain_mesh_data))
+        .collect::<anyhow::Result<Vec<_>>>()?;
 
     Ok(model::Model::new(device, meshes, materials, instances))
 }
+
+// Creates a material of the model `file_name`, loading its textures; textures named in
+// `images` are decoded from the encoded image there, e.g. those embedded in a glTF file
+async fn load_material(
+    file_name: &str,
+    m: &packed_asset::PackedMaterial,
+    images: &HashMap<String, Vec<u8>>,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    generate_mipmaps: bool,
+) -> model::Material {
+    let ambient = Vec4::new(m.ambient.x, m.ambient.y, m.ambient.z, 1.0);
+    let diffuse = Vec4::new(m.diffuse.x, m.diffuse.y, m.diffuse.z, 1.0);
+    let specular = Vec4::new(m.specular.x, m.specular.y, m.specular.z, 1.0);
+
+    // textures which the material names but which fail to load are replaced by placeholders
+    let mut missing = Vec::new();
+    let diffuse_texture = load_material_texture(
+        &m.diffuse_texture,
+        images,
+        device,
+        queue,
+        texture::Placeholder::Diffuse,
+        generate_mipmaps,
+        &mut missing,
+    )
+    .await;
+    let normal_texture = load_material_texture(
+        &m.normal_texture,
+        images,
+        device,
+        queue,
+        texture::Placeholder::Normal,
+        generate_mipmaps,
+        &mut missing,
+    )
+    .await;
+    let shininess_texture = load_material_texture(
+        &m.shininess_texture,
+        images,
+        device,
+        queue,
+        texture::Placeholder::Glossiness,
+        generate_mipmaps,
+        &mut missing,
+    )
+    .await;
+    let ambient_occlusion_texture = load_material_texture(
+        &m.ambient_occlusion_texture,
+        images,
+        device,
+        queue,
+        texture::Placeholder::AmbientOcclusion,
+        generate_mipmaps,
+        &mut missing,
+    )
+    .await;
+    if !missing.is_empty() {
+        eprintln!(
+            "Material \"{}\" of \"{}\" is missing textures, using placeholders:\n\t{}",
+            m.name,
+            file_name,
+            missing.join("\n\t")
+        );
+    }
+    // model.wgsl's textured variants each add a map to the previous one's, diffuse, normal then
+    // glossiness, so fill the gaps of materials with only data maps (as glTF materials without
+    // a base color texture are) with a white diffuse texture and a flat normal map
+    let diffuse_texture = match diffuse_texture {
+        None if normal_texture.is_some() || shininess_texture.is_some() => {
+            texture::Texture::placeholder(device, queue, texture::Placeholder::White).ok()
+        }
+        diffuse_texture => diffuse_texture,
+    };
+    let normal_texture = match normal_texture {
+        None if shininess_texture.is_some() => {
+            texture::Texture::placeholder(device, queue, texture::Placeholder::Normal).ok()
+        }
+        normal_texture => normal_texture,
+    };
+
+    model::Material::new(
+        device,
+        model::MaterialProperties {
+            name: &m.name,
+            ambient,
+            diffuse,
+            specular,
+            shininess: m.shininess,
+            diffuse_texture,
+            normal_texture,
+            shininess_texture,
+            ambient_occlusion_texture,
+            ..Default::default()
+        },
+    )
+}
+
+// Loads a texture named by a material; None when it names none, and `placeholder` when the
+// file fails to load, noting why in `missing`. Only color textures are loaded as sRGB; data
+// maps such as normals, glossiness and occlusion are loaded linear.
+async fn load_material_texture(
+    file_name: &str,
+    images: &HashMap<String, Vec<u8>>,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    placeholder: texture::Placeholder,
+    generate_mipmaps: bool,
+    missing: &mut Vec<String>,
+) -> Option<texture::Texture> {
+    if file_name.is_empty() {
+        return None;
+    }
+    let is_linear = !placeholder.is_color();
+    let texture = match images.get(file_name) {
+        Some(bytes) => texture::Texture::from_bytes(
+            device,
+            queue,
+            bytes,
+            file_name,
+            is_linear,
+            generate_mipmaps,
+        ),
+        None => load_texture(file_name, device, queue, is_linear, generate_mipmaps).await,
+    };
+    match texture {
+        Ok(texture) => Some(texture),
+        Err(e) => {
+            missing.push(format!("{:?} \"{}\": {}", placeholder, file_name, e));
+            texture::Texture::placeholder(device, queue, placeholder).ok()
+        }
+    }
+}
+
+fn is_ktx2(file_name: &str) -> bool {
+    std::path::Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("ktx2"))
+}
+
+// The lowercased extension of a model file, choosing how load_model reads it
+fn model_extension(file_name: &str) -> Option<String> {
+    std::path::Path::new(file_name)
+        .extension()?
+        .to_str()
+        .map(str::to_lowercase)
+}
@@ -0,0 +1,182 @@
+use cgmath::prelude::*;
+
+use super::{model, util::*};
+
+/// The ground instances are placed on.
+pub enum Surface<'a> {
+    // the ground's height at a world (x, z), e.g. terrain::TerrainManager::height_at
+    Height(&'a dyn Fn(f32, f32) -> f32),
+    // a world space mesh raycast straight down; samples which miss it are rejected. A mesh's
+    // data is retained by building it with mesh_builder::MeshBuilder::build's retain_data.
+    Mesh(&'a model::MeshData),
+}
+
+pub struct ScatterDescriptor {
+    pub count: usize,
+    // the world (x, z) rectangle instances are scattered over
+    pub min: Vec2,
+    pub max: Vec2,
+    // the same seed, descriptor and surface always produce the same instances; draw it from
+    // scene::Scene::random to keep a reproducible scene's scattering reproducible
+    pub seed: u32,
+    // each instance is turned about world up by up to this in either direction
+    pub yaw_jitter: Rad,
+    // each instance's uniform scale, picked evenly from this range
+    pub scale_range: (f32, f32),
+    // raises (or with a negative value, sinks) instances relative to the surface
+    pub offset: f32,
+}
+
+impl Default for ScatterDescriptor {
+    fn default() -> Self {
+        Self {
+            count: 100,
+            min: Vec2::new(-50.0, -50.0),
+            max: Vec2::new(50.0, 50.0),
+            seed: 1,
+            yaw_jitter: rad(std::f32::consts::PI),
+            scale_range: (1.0, 1.0),
+            offset: 0.0,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Each sample which misses a mesh surface is retried, up to this many times per instance, so
+// that a mesh covering part of the rectangle still gets close to count instances.
+const MAX_ATTEMPTS_PER_INSTANCE: usize = 8;
+
+/// Scatters instances randomly over the surface, for use with model::Model::new or
+/// resources::load_model.
+pub fn scatter(surface: &Surface, descriptor: &ScatterDescriptor) -> Vec<model::Instance> {
+    let ground = match surface {
+        Surface::Mesh(data) => Some(TriangleGrid::new(data)),
+        Surface::Height(_) => None,
+    };
+    let height_at = |x: f32, z: f32| match (surface, &ground) {
+        (Surface::Height(height), _) => Some(height(x, z)),
+        (Surface::Mesh(data), Some(ground)) => ground.raycast(data, x, z),
+        _ => None,
+    };
+
+    let mut random = Random::new(descriptor.seed);
+
+    let mut instances = Vec::with_capacity(descriptor.count);
+    let mut attempts = 0;
+    while instances.len() < descriptor.count
+        && attempts < descriptor.count * MAX_ATTEMPTS_PER_INSTANCE
+    {
+        attempts += 1;
+        let x = random.range(descriptor.min.x, descriptor.max.x);
+        let z = random.range(descriptor.min.y, descriptor.max.y);
+        // drawn for every sample, so that rejections don't change later instances' jitter
+        let yaw = descriptor.yaw_jitter * random.range(-1.0, 1.0);
+        let scale = random.range(descriptor.scale_range.0, descriptor.scale_range.1);
+
+        if let Some(y) = height_at(x, z) {
+            instances.push(
+                model::Instance::new(
+                    Point3::new(x, y + descriptor.offset, z),
+                    Quat::from_angle_y(yaw),
+                )
+                .with_scale(scale),
+            );
+        }
+    }
+
+    if instances.len() < descriptor.count {
+        eprintln!(
+            "Scattered only {} of {} instances, the surface covers little of the scatter area",
+            instances.len(),
+            descriptor.count
+        );
+    }
+    instances
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Buckets a mesh's triangles by the cells of an (x, z) grid their bounds overlap, so a
+// raycast only tests the triangles in one cell.
+struct TriangleGrid {
+    min: Vec2,
+    cell_size: Vec2,
+    cells_per_side: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl TriangleGrid {
+    fn new(data: &model::MeshData) -> Self {
+        let triangle_count = data.indices.len() / 3;
+        let (mut min, mut max) = (Vec2::new(f32::MAX, f32::MAX), Vec2::new(f32::MIN, f32::MIN));
+        for vertex in data.vertices.iter() {
+            min.x = min.x.min(vertex.position.x);
+            min.y = min.y.min(vertex.position.z);
+            max.x = max.x.max(vertex.position.x);
+            max.y = max.y.max(vertex.position.z);
+        }
+
+        // roughly one triangle per cell
+        let cells_per_side = ((triangle_count as f32).sqrt().ceil() as usize).clamp(1, 256);
+        let cell_size = Vec2::new(
+            ((max.x - min.x) / cells_per_side as f32).max(f32::EPSILON),
+            ((max.y - min.y) / cells_per_side as f32).max(f32::EPSILON),
+        );
+        let mut grid = Self {
+            min,
+            cell_size,
+            cells_per_side,
+            cells: vec![Vec::new(); cells_per_side * cells_per_side],
+        };
+
+        for (triangle, indices) in data.indices.chunks_exact(3).enumerate() {
+            let positions = [0, 1, 2].map(|i| data.vertices[indices[i] as usize].position);
+            let (x0, z0) = grid.cell(
+                positions.iter().map(|p| p.x).fold(f32::MAX, f32::min),
+                positions.iter().map(|p| p.z).fold(f32::MAX, f32::min),
+            );
+            let (x1, z1) = grid.cell(
+                positions.iter().map(|p| p.x).fold(f32::MIN, f32::max),
+                positions.iter().map(|p| p.z).fold(f32::MIN, f32::max),
+            );
+            for z in z0..=z1 {
+                for x in x0..=x1 {
+                    grid.cells[z * cells_per_side + x].push(triangle);
+                }
+            }
+        }
+        grid
+    }
+
+    fn cell(&self, x: f32, z: f32) -> (usize, usize) {
+        let last = self.cells_per_side - 1;
+        (
+            (((x - self.min.x) / self.cell_size.x).max(0.0) as usize).min(last),
+            (((z - self.min.y) / self.cell_size.y).max(0.0) as usize).min(last),
+        )
+    }
+
+    // the height of the highest triangle under (x, z), if any
+    fn raycast(&self, data: &model::MeshData, x: f32, z: f32) -> Option<f32> {
+        let (cell_x, cell_z) = self.cell(x, z);
+        self.cells[cell_z * self.cells_per_side + cell_x]
+            .iter()
+            .filter_map(|triangle| {
+                let indices = &data.indices[triangle * 3..triangle * 3 + 3];
+                let [a, b, c] = [0, 1, 2].map(|i| data.vertices[indices[i] as usize].position);
+
+                // barycentric coordinates of (x, z) in the triangle's projection onto the ground
+                let area = (b.x - a.x) * (c.z - a.z) - (c.x - a.x) * (b.z - a.z);
+                if area.abs() <= f32::EPSILON {
+                    // vertical, so a ray straight down can't hit it
+                    return None;
+                }
+                let u = ((x - a.x) * (c.z - a.z) - (c.x - a.x) * (z - a.z)) / area;
+                let v = ((b.x - a.x) * (z - a.z) - (x - a.x) * (b.z - a.z)) / area;
+                (u >= 0.0 && v >= 0.0 && u + v <= 1.0)
+                    .then_some(a.y + (b.y - a.y) * u + (c.y - a.y) * v)
+            })
+            .reduce(f32::max)
+    }
+}
@@ -1,14 +1,26 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{cell::Cell, collections::HashMap, ops::Range, rc::Rc};
 
 use cgmath::prelude::*;
 use winit::event::{ElementState, KeyboardInput, MouseButton, WindowEvent};
 
 use super::{
     camera::{self},
-    camera_controller, gpu_state, light, model, render_pipeline, texture,
+    camera_controller, debug_draw,
+    debug_groups::DebugGroups,
+    depth_pass, environment, frame_context,
+    frustum::Frustum,
+    gpu_state,
+    graphics_settings::{GraphicsSettings, PostEffects},
+    grass, instance_animation, jobs, light, material_override, model, occlusion, portal,
+    render_hooks::{RenderHook, RenderHookContext, RenderHookPoint},
+    render_pipeline, shadow_atlas, skinning, socket, terrain, texture, texture_residency,
     util::*,
+    volumetric_fog, weather,
 };
 
+/// The seed of a new scene's Scene::random; replace the generator to run with another.
+pub const DEFAULT_RANDOM_SEED: u32 = 1;
+
 //////////////////////////////////////////////
 
 pub struct Scene {
@@ -18,10 +30,78 @@ pub struct Scene {
 
     camera_controller: camera_controller::CameraController,
     ambient_light: light::Light,
-    pub environment_map: Rc<texture::Texture>,
+    graphics_settings: GraphicsSettings,
+    pub environment: environment::Environment,
     pub camera: camera::Camera,
     pub lights: HashMap<usize, light::Light>,
+    // when set, the shadow maps of `lights` are sized within this budget each update, and those
+    // which can be are packed into shadow_atlas_texture
+    pub shadow_atlas: Option<shadow_atlas::ShadowAtlas>,
+    shadow_atlas_texture: Option<shadow_atlas::ShadowAtlasTexture>,
+    // set once render has encoded the frame's view independent work, cleared by update
+    frame_shared_encoded: Cell<bool>,
+    // for each model with instance groups, whether each group is within the camera's frustum
+    // and max_instance_distance, culled by update; with the camera's world transform and
+    // projection when culled, since the camera may be moved or swapped before rendering
+    culled_groups: HashMap<usize, Vec<bool>>,
+    culled_for: Option<(Mat4, Mat4)>,
+    // when set, the ambient pass is lit by this hemisphere, otherwise by the sum of the lights'
+    // ambient terms from every direction
+    pub hemisphere_ambient: Option<light::Hemisphere>,
     pub models: HashMap<usize, model::Model>,
+    // lay down opaque depth with the shared position-only pipeline before shading,
+    // so the ambient pass only shades visible fragments
+    pub depth_prepass: bool,
+    // draw geometry as additive constant color instead of shading it, so overdraw
+    // hotspots glow, see depth_pass::prepare_overdraw_pipeline
+    pub debug_overdraw: bool,
+    // draw the number of lights evaluated per pixel instead of shading it, shown as a heatmap
+    // by compositor::DebugView::LightComplexity; implies the depth prepass
+    pub debug_light_complexity: bool,
+    // draw every material with this built-in view instead of shading it, e.g. to check an
+    // imported mesh's normals, texture coordinates or tangents
+    pub debug_material_override: Option<material_override::MaterialOverride>,
+    // lines added here during update are drawn over the scene for the current frame
+    pub debug_lines: debug_draw::DebugLines,
+    // scales the distances at which terrain, grass and lights switch to coarser levels of
+    // detail; below 1 trades detail for speed, above 1 keeps detail further away
+    pub lod_bias: f32,
+    // instance groups (and models without any) whose bounds lie entirely beyond this distance
+    // from the camera aren't drawn, and grass isn't planted beyond it
+    pub max_instance_distance: Option<f32>,
+    // runs update's CPU-heavy work (instance group culling, weather simulation and skinned
+    // bounds) across worker threads, one per core unless replaced
+    pub jobs: jobs::JobSystem,
+    // the source of the scene's randomness, e.g. for scatter and weather seeds; seeded with
+    // DEFAULT_RANDOM_SEED, so a scene built the same way is the same from run to run. Draw
+    // seeds or forks from it rather than seeding generators from elsewhere, e.g. the clock.
+    pub random: Random,
+    // rain or snow around the camera, drawn over the scene's geometry
+    pub weather: Option<weather::Weather>,
+    // fog lit by the scene's lights, built for the camera each frame and applied by the
+    // compositor, see ScenePresentation::volumetric_fog
+    pub volumetric_fog: Option<volumetric_fog::VolumetricFog>,
+    // culls models and instance groups hidden behind others, as tested from `camera` after
+    // each frame's first render; portal views aren't occlusion culled
+    pub occlusion_culler: Option<occlusion::OcclusionCuller>,
+    // streams chunks around the camera into its model in `models`
+    pub terrain: Option<terrain::TerrainManager>,
+    // streams levels of detail of large material textures of models in `models` in and out
+    // as the camera moves
+    pub texture_residency: texture_residency::TextureResidency,
+    // planted and culled on the GPU each frame, drawn with the scene's models
+    pub grass: Option<grass::Grass>,
+    // animate instances of models in `models` on the GPU each frame
+    pub instance_animators: Vec<instance_animation::InstanceAnimator>,
+    // skin meshes of models in `models` on the GPU each frame
+    pub skins: Vec<skinning::Skin>,
+    // instances of models in `models` following sockets on others, placed each update in
+    // order, so an attachment parented to another attached instance should follow it
+    pub attachments: Vec<socket::Attachment>,
+    // mirrors and windows onto other parts of the scene, each rendering views of the scene
+    pub portals: Vec<portal::Portal>,
+    // custom drawing injected around the ambient, lit and compositor passes, in order
+    pub render_hooks: Vec<Box<dyn RenderHook>>,
 }
 
 impl Scene {
@@ -34,8 +114,13 @@ impl Scene {
     ) -> Self {
         // create a pipeline (if needed) for each material
         for model in models.values() {
-            model.prepare_pipelines(gpu_state);
+            model.prepare_pipelines(gpu_state, camera.depth_mode());
+        }
+        for light in lights.values() {
+            light.prepare_pipelines(gpu_state);
         }
+        depth_pass::prepare_prepass_pipeline(gpu_state, camera.depth_mode());
+        debug_draw::DebugLines::prepare_pipeline(gpu_state, camera.depth_mode());
 
         // Create an ambient light which is the sum of all the ambient terms of the light sources provided
         let ambient_term = lights
@@ -55,10 +140,37 @@ impl Scene {
             mouse_pressed: false,
             camera_controller: camera_controller::CameraController::new(4.0, 0.4),
             ambient_light,
-            environment_map,
+            graphics_settings: GraphicsSettings::default(),
+            environment: environment::Environment::new(gpu_state, environment_map),
             camera,
             lights,
+            shadow_atlas: None,
+            shadow_atlas_texture: None,
+            frame_shared_encoded: Cell::new(false),
+            culled_groups: HashMap::new(),
+            culled_for: None,
+            hemisphere_ambient: None,
             models,
+            depth_prepass: false,
+            debug_overdraw: false,
+            debug_light_complexity: false,
+            debug_material_override: None,
+            debug_lines: debug_draw::DebugLines::new(),
+            lod_bias: 1.0,
+            max_instance_distance: None,
+            jobs: jobs::JobSystem::default(),
+            random: Random::new(DEFAULT_RANDOM_SEED),
+            weather: None,
+            volumetric_fog: None,
+            occlusion_culler: None,
+            terrain: None,
+            texture_residency: texture_residency::TextureResidency::default(),
+            grass: None,
+            instance_animators: Vec::new(),
+            skins: Vec::new(),
+            attachments: Vec::new(),
+            portals: Vec::new(),
+            render_hooks: Vec::new(),
         }
     }
 
@@ -73,12 +185,68 @@ impl Scene {
     ) {
         self.size = new_size;
         self.camera.resize(gpu_state, new_size);
+        for portal in self.portals.iter_mut() {
+            portal.resize(gpu_state, new_size);
+        }
+        if let Some(weather) = &mut self.weather {
+            weather.resize(gpu_state, &self.camera.render_buffers);
+        }
+        if let Some(occlusion_culler) = &mut self.occlusion_culler {
+            occlusion_culler.resize(gpu_state, &self.camera.render_buffers);
+        }
     }
 
     pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
         self.size
     }
 
+    pub fn graphics_settings(&self) -> GraphicsSettings {
+        self.graphics_settings
+    }
+
+    /// Applies the settings which differ from the current ones: shadow quality recreates the
+    /// shadow maps of the scene's lights, texture quality rebuilds its models' material bind
+    /// groups, and anti-aliasing and render scale resize the camera's attachments on the next
+    /// update. Lights and models added afterwards are left as created.
+    pub fn set_graphics_settings(
+        &mut self,
+        gpu_state: &gpu_state::GpuState,
+        settings: GraphicsSettings,
+    ) {
+        let previous = std::mem::replace(&mut self.graphics_settings, settings);
+
+        if settings.shadow_quality != previous.shadow_quality {
+            for light in self.lights.values_mut() {
+                light
+                    .set_shadow_resolution(&gpu_state.device, settings.shadow_quality.resolution());
+            }
+        }
+        if settings.texture_quality != previous.texture_quality {
+            for model in self.models.values_mut() {
+                for material in model.materials_mut() {
+                    material
+                        .set_min_texture_lod(&gpu_state.device, settings.texture_quality.min_lod());
+                }
+            }
+        }
+        if settings.camera_render_scale() != previous.camera_render_scale() {
+            self.camera.set_render_scale(settings.camera_render_scale());
+        }
+    }
+
+    /// The scene's state for presenting this frame, see compositor::Compositor::update.
+    pub fn presentation(&self) -> frame_context::ScenePresentation<'_> {
+        frame_context::ScenePresentation {
+            camera: &self.camera,
+            environment: &self.environment,
+            volumetric_fog: self
+                .volumetric_fog
+                .as_ref()
+                .filter(|_| self.draws_volumetric_fog()),
+            size: self.size,
+        }
+    }
+
     pub fn input(
         &mut self,
         event: Option<&winit::event::WindowEvent>,
@@ -124,28 +292,170 @@ impl Scene {
         false
     }
 
-    pub fn update(&mut self, gpu_state: &mut gpu_state::GpuState, dt: instant::Duration) {
+    pub fn update(&mut self, frame: &mut frame_context::FrameContext) {
+        debug_assert_eq!(
+            frame.phase(),
+            gpu_state::FramePhase::Update,
+            "Scene::update called outside FramePhase::Update"
+        );
+        let dt = frame.dt;
+        let gpu_state = frame.gpu_state_mut();
         self.camera_controller.update(&mut self.camera, dt);
-        self.camera.update(&gpu_state.queue);
+        self.camera.update_blend(dt);
+        self.camera.update(gpu_state);
+        if self.camera.prepare_render_buffers(gpu_state) {
+            if let Some(weather) = &mut self.weather {
+                weather.resize(gpu_state, &self.camera.render_buffers);
+            }
+            if let Some(occlusion_culler) = &mut self.occlusion_culler {
+                occlusion_culler.resize(gpu_state, &self.camera.render_buffers);
+            }
+        }
 
-        self.ambient_light.set_ambient(
-            self.lights
-                .values()
-                .fold(Vec3::zero(), |total, light| total + light.ambient()),
-        );
-        self.ambient_light.update(&gpu_state.queue);
+        let ambient_term = self
+            .lights
+            .values()
+            .fold(Vec3::zero(), |total, light| total + light.ambient());
+        let hemisphere = self
+            .hemisphere_ambient
+            .unwrap_or_else(|| light::Hemisphere::uniform(ambient_term));
+        self.ambient_light.set_hemisphere(hemisphere);
+        self.ambient_light
+            .update(gpu_state, &self.camera, self.lod_bias);
+        self.environment.update(gpu_state, dt);
 
+        self.allocate_shadow_maps(gpu_state);
+        if self.shadow_atlas_texture.is_some() {
+            shadow_atlas::ShadowAtlasTexture::prepare_pipeline(gpu_state);
+        }
         for light in self.lights.values_mut() {
-            light.update(&gpu_state.queue);
+            // shadow bias changes may require a new shadow pipeline
+            light.prepare_pipelines(gpu_state);
+            light.update(gpu_state, &self.camera, self.lod_bias);
+        }
+        if let Some(terrain) = &mut self.terrain {
+            if let Some(model) = self.models.get_mut(&terrain.model_id()) {
+                terrain.update(gpu_state, self.camera.position(), model, self.lod_bias);
+            }
         }
+        self.update_attachments();
+        self.update_skinned_bounds();
+        self.texture_residency.update(
+            gpu_state,
+            self.camera.position(),
+            &mut self.models,
+            self.lod_bias,
+        );
         for model in self.models.values_mut() {
+            // camera depth mode changes require new pipelines
+            model.prepare_pipelines(gpu_state, self.camera.depth_mode());
             model.update(&gpu_state.queue);
         }
+        self.cull_instance_groups();
+        for animator in self.instance_animators.iter_mut() {
+            animator.update(&gpu_state.queue, dt);
+        }
+        if self.occlusion_culler.is_some() {
+            let occludees = self.occludees();
+            if let Some(occlusion_culler) = &mut self.occlusion_culler {
+                occlusion::OcclusionCuller::prepare_pipeline(gpu_state);
+                occlusion_culler.update(gpu_state, &self.camera, occludees);
+            }
+        }
+        depth_pass::prepare_prepass_pipeline(gpu_state, self.camera.depth_mode());
+        if self.debug_overdraw {
+            depth_pass::prepare_overdraw_pipeline(gpu_state, self.camera.depth_mode());
+        }
+        if self.debug_light_complexity {
+            depth_pass::prepare_light_count_pipeline(gpu_state, self.camera.depth_mode());
+        }
+        if let Some(mode) = self.debug_material_override {
+            material_override::prepare_pipeline(gpu_state, mode, self.camera.depth_mode());
+        }
+        debug_draw::DebugLines::prepare_pipeline(gpu_state, self.camera.depth_mode());
+        self.debug_lines.upload(gpu_state);
+        if !self.portals.is_empty() {
+            portal::Portal::prepare_pipeline(gpu_state, self.camera.depth_mode());
+        }
+        for portal in self.portals.iter_mut() {
+            portal.update(gpu_state, &self.camera);
+        }
+        if let Some(grass) = &mut self.grass {
+            grass::Grass::prepare_pipelines(gpu_state, self.camera.depth_mode());
+            grass.update(
+                gpu_state,
+                &self.camera,
+                self.lod_bias,
+                self.max_instance_distance,
+            );
+        }
+        if let (Some(weather), true) = (&mut self.weather, self.graphics_settings.weather) {
+            weather::Weather::prepare_pipeline(gpu_state);
+            weather.update(
+                gpu_state,
+                &self.jobs,
+                &self.camera,
+                self.environment.wind(),
+                dt,
+            );
+        }
+        if self.draws_volumetric_fog() {
+            if let Some(fog) = &mut self.volumetric_fog {
+                fog.update(gpu_state, &self.camera, ambient_term);
+            }
+        }
 
+        self.frame_shared_encoded.set(false);
         self.time += dt;
     }
 
-    pub fn render(&self, gpu_state: &mut gpu_state::GpuState, encoder: &mut wgpu::CommandEncoder) {
+    /// Encodes the frame as seen by the scene's camera. Work which doesn't depend on the camera,
+    /// instance animation, skinning and shadow maps, is only encoded by the first call after
+    /// update, so the scene may be rendered once per camera each frame (split screen, probes,
+    /// swapping `camera` between calls) and each light's shadow map is still rendered once and
+    /// shared by every view, as it is by portal views.
+    pub fn render(&self, frame: &mut frame_context::FrameContext) {
+        debug_assert_eq!(
+            frame.phase(),
+            gpu_state::FramePhase::Encode,
+            "Scene::render called outside FramePhase::Encode"
+        );
+        let (gpu_state, encoder) = frame.gpu_state_and_encoder();
+        encoder.push_group("Scene");
+        if !self.frame_shared_encoded.replace(true) {
+            self.render_frame_shared(gpu_state, encoder);
+        }
+        if let Some(grass) = &self.grass {
+            encoder.push_group("Grass Cull");
+            grass.cull(encoder);
+            encoder.pop_group();
+        }
+        if let (Some(fog), true) = (&self.volumetric_fog, self.draws_volumetric_fog()) {
+            encoder.push_group("Volumetric Fog");
+            fog.compute(
+                encoder,
+                self.lights
+                    .values()
+                    .filter(|l| l.detail() != light::LightDetail::Culled),
+            );
+            encoder.pop_group();
+        }
+        // only fragments which are shaded evaluate lights
+        let depth_prepass = self.depth_prepass || self.debug_light_complexity;
+        let debug_counting = self.debug_overdraw || self.debug_light_complexity;
+        // debug views draw models alone, without portals or weather
+        let debug_view = debug_counting || self.debug_material_override.is_some();
+        if depth_prepass {
+            encoder.push_group("Depth Prepass");
+            self.render_depth_prepass(gpu_state, encoder);
+            encoder.pop_group();
+        }
+        if !debug_view && !self.portals.is_empty() {
+            encoder.push_group("Portal Views");
+            self.render_portal_views(gpu_state, encoder);
+            encoder.pop_group();
+        }
+
         let color_attachment = self
             .camera
             .render_buffers
@@ -155,12 +465,11 @@ impl Scene {
                 view: &color_attachment.view,
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                        g: 0.1,
-                        r: 0.1,
-                        b: 0.1,
-                        a: 1.0,
-                    }),
+                    load: if debug_counting {
+                        wgpu::LoadOp::Clear(wgpu::Color::BLACK)
+                    } else {
+                        self.camera.color_load_op()
+                    },
                     store: true,
                 },
             });
@@ -173,7 +482,12 @@ impl Scene {
                 .map(|depth_attachment| wgpu::RenderPassDepthStencilAttachment {
                     view: &depth_attachment.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        // the prepass has already started the frame's depth
+                        load: if depth_prepass {
+                            wgpu::LoadOp::Load
+                        } else {
+                            self.camera.depth_load_op()
+                        },
                         store: true,
                     }),
                     stencil_ops: None,
@@ -184,35 +498,859 @@ impl Scene {
             color_attachments: &[color_attachment],
             depth_stencil_attachment,
         });
+        self.camera.apply_viewport(&mut render_pass);
+
+        if self.debug_overdraw {
+            render_pass.marker("Overdraw");
+            for (layer, models) in self.model_layers() {
+                Self::apply_render_layer(&mut render_pass, &self.camera, layer);
+                depth_pass::draw_counting(
+                    &mut render_pass,
+                    &gpu_state.pipeline_vendor,
+                    &depth_pass::overdraw_pipeline_id(self.camera.depth_mode()),
+                    models.into_iter().map(|(_, model)| model),
+                    &self.camera,
+                );
+            }
+        } else if self.debug_light_complexity {
+            // every visible fragment is shaded once per light, see draw_view
+            let pipeline_id = depth_pass::light_count_pipeline_id(self.camera.depth_mode());
+            render_pass.marker("Light Complexity");
+            let layers = self.model_layers();
+            for light in self
+                .lights
+                .values()
+                .filter(|l| l.light_type() != light::LightType::Ambient)
+                .filter(|l| l.detail() != light::LightDetail::Culled)
+            {
+                for (layer, models) in layers.iter() {
+                    Self::apply_render_layer(&mut render_pass, &self.camera, *layer);
+                    depth_pass::draw_counting(
+                        &mut render_pass,
+                        &gpu_state.pipeline_vendor,
+                        &pipeline_id,
+                        models
+                            .iter()
+                            .filter(|(id, model)| self.lights_model(light, **id, model))
+                            .map(|(_, model)| *model),
+                        &self.camera,
+                    );
+                }
+            }
+        } else if let Some(mode) = self.debug_material_override {
+            render_pass.marker(format_args!("Material Override: {:?}", mode));
+            for (layer, models) in self.model_layers() {
+                Self::apply_render_layer(&mut render_pass, &self.camera, layer);
+                material_override::draw(
+                    &mut render_pass,
+                    &gpu_state.pipeline_vendor,
+                    mode,
+                    models.into_iter().flat_map(|(id, model)| {
+                        self.visible_instances(*id, model, &self.camera)
+                            .into_iter()
+                            .map(move |instances| (model, instances))
+                    }),
+                    &self.camera,
+                );
+            }
+        } else {
+            self.draw_view(&mut render_pass, gpu_state, &self.camera, None);
+        }
+        Self::apply_render_layer(&mut render_pass, &self.camera, model::RenderLayer::World);
+
+        render_pass.push_group("Debug Lines");
+        self.debug_lines.draw(
+            &mut render_pass,
+            &gpu_state.pipeline_vendor,
+            &gpu_state.transient_buffers,
+            &self.camera,
+        );
+        render_pass.pop_group();
+        drop(render_pass);
+
+        if let (Some(weather), true, false) =
+            (&self.weather, self.graphics_settings.weather, debug_view)
+        {
+            encoder.push_group("Weather");
+            self.render_weather(gpu_state, encoder, weather);
+            encoder.pop_group();
+        }
+        if let Some(occlusion_culler) = &self.occlusion_culler {
+            encoder.push_group("Occlusion Test");
+            occlusion_culler.test(gpu_state, encoder, &self.camera);
+            encoder.pop_group();
+        }
+        encoder.pop_group();
+    }
+
+    // Encodes the frame's work which is shared by every view: instance animation, skinning and
+    // the shadow maps which are due
+    fn render_frame_shared(
+        &self,
+        gpu_state: &mut gpu_state::GpuState,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        for animator in self.instance_animators.iter() {
+            if let Some(model) = self.models.get(&animator.model_id()) {
+                encoder.push_group(format_args!(
+                    "Instance Animation: Model {}",
+                    animator.model_id()
+                ));
+                animator.animate(encoder, model);
+                encoder.pop_group();
+            }
+        }
+        for skin in self.skins.iter() {
+            if self.models.contains_key(&skin.model_id()) {
+                encoder.push_group(format_args!("Skinning: Model {}", skin.model_id()));
+                skin.skin(encoder);
+                encoder.pop_group();
+            }
+        }
+        encoder.push_group("Shadow Maps");
+        self.render_shadow_maps(gpu_state, encoder);
+        encoder.pop_group();
+    }
+
+    /// Calls the render hooks at `point`, which must be between passes, e.g.
+    /// RenderHookPoint::BeforeCompositor; `output` is the surface texture's view.
+    pub fn encode_render_hooks(
+        &self,
+        point: RenderHookPoint,
+        frame: &mut frame_context::FrameContext,
+        output: &wgpu::TextureView,
+    ) {
+        if point.is_in_scene_pass() {
+            eprintln!(
+                "Scene::encode_render_hooks - {:?} is within the scene's render pass, and called by Scene::render",
+                point
+            );
+            return;
+        }
+        let (gpu_state, encoder) = frame.gpu_state_and_encoder();
+        let context = RenderHookContext {
+            gpu_state,
+            camera: &self.camera,
+            environment: &self.environment,
+        };
+        encoder.push_group(format_args!("Render Hooks: {:?}", point));
+        for hook in self.render_hooks.iter() {
+            hook.encode(point, encoder, output, &context);
+        }
+        encoder.pop_group();
+    }
+
+    // Calls the render hooks at `point` within the scene camera's render pass
+    fn draw_render_hooks<'a, 'b>(
+        &'a self,
+        point: RenderHookPoint,
+        render_pass: &'b mut wgpu::RenderPass<'a>,
+        gpu_state: &'a gpu_state::GpuState,
+    ) where
+        'a: 'b,
+    {
+        let context = RenderHookContext {
+            gpu_state,
+            camera: &self.camera,
+            environment: &self.environment,
+        };
+        render_pass.push_group(format_args!("Render Hooks: {:?}", point));
+        for hook in self.render_hooks.iter() {
+            hook.draw(point, render_pass, &context);
+        }
+        render_pass.pop_group();
+    }
+
+    // Draws the scene's geometry as seen by `camera`, which is the scene's camera, or if
+    // `portal_view` is Some((portal, level)), the camera of that portal's view through `level`
+    // portals. Grass is culled for the scene's camera, and render hooks draw into its pass, so
+    // only drawn by it.
+    fn draw_view<'a, 'b>(
+        &'a self,
+        render_pass: &'b mut wgpu::RenderPass<'a>,
+        gpu_state: &'a gpu_state::GpuState,
+        camera: &'a camera::Camera,
+        portal_view: Option<(usize, usize)>,
+    ) where
+        'a: 'b,
+    {
+        let grass = match portal_view {
+            None => self.grass.as_ref(),
+            Some(_) => None,
+        };
+        let draw_hooks = |point: RenderHookPoint, render_pass: &mut wgpu::RenderPass<'a>| {
+            if portal_view.is_none() {
+                self.draw_render_hooks(point, render_pass, gpu_state);
+            }
+        };
+
+        let layers = self.model_layers();
 
         // Render ambient pass
-        for model in self.models.values() {
-            model::draw_model(
-                &mut render_pass,
+        draw_hooks(RenderHookPoint::BeforeAmbient, render_pass);
+        render_pass.push_group("Ambient");
+        self.draw_layers(
+            render_pass,
+            gpu_state,
+            &layers,
+            camera,
+            &self.ambient_light,
+            render_pipeline::Pass::Ambient,
+        );
+        if let Some(grass) = grass {
+            grass.draw(
+                render_pass,
                 &gpu_state.pipeline_vendor,
-                model,
-                &self.camera,
+                camera,
                 &self.ambient_light,
-                &render_pipeline::Pass::Ambient,
+                &self.environment,
+                render_pipeline::Pass::Ambient,
             );
         }
+        render_pass.pop_group();
+        draw_hooks(RenderHookPoint::AfterAmbient, render_pass);
 
-        // Render lit passes (skipping ambient since they're rolled into self.ambient_light)
-        for light in self
-            .lights
-            .values()
-            .filter(|l| l.light_type() != light::LightType::Ambient)
+        // Render portal surfaces, open to the next level's view if there is one
+        for (index, portal) in self.portals.iter().enumerate() {
+            render_pass.marker(format_args!("Portal {}", index));
+            let level = match portal_view {
+                None => Some(0),
+                Some((portal_index, level)) if portal_index == index => Some(level + 1),
+                Some(_) => None,
+            };
+            portal.draw(render_pass, &gpu_state.pipeline_vendor, camera, level);
+        }
+
+        // Render ink outlines for toon shaded materials which request them
+        render_pass.push_group("Outlines");
+        self.draw_layers(
+            render_pass,
+            gpu_state,
+            &layers,
+            camera,
+            &self.ambient_light,
+            render_pipeline::Pass::Outline,
+        );
+        render_pass.pop_group();
+
+        // Render lit passes (skipping ambient since they're rolled into self.ambient_light,
+        // and lights culled by their level of detail)
+        draw_hooks(RenderHookPoint::BeforeLit, render_pass);
+        for (light_id, light) in self.lights.iter().filter(|(_, l)| {
+            l.light_type() != light::LightType::Ambient && l.detail() != light::LightDetail::Culled
+        }) {
+            render_pass.push_group(format_args!("Lit: Light {}", light_id));
+            self.draw_layers(
+                render_pass,
+                gpu_state,
+                &layers,
+                camera,
+                light,
+                render_pipeline::Pass::Lit,
+            );
+            if let Some(grass) = grass {
+                grass.draw(
+                    render_pass,
+                    &gpu_state.pipeline_vendor,
+                    camera,
+                    light,
+                    &self.environment,
+                    render_pipeline::Pass::Lit,
+                );
+            }
+            render_pass.pop_group();
+        }
+        draw_hooks(RenderHookPoint::AfterLit, render_pass);
+    }
+
+    // True if the model is within reach of `light`, by its radius and the model's bounds.
+    // Models moved on the GPU have no reliable bounds, so are always lit.
+    fn lights_model(&self, light: &light::Light, model_id: usize, model: &model::Model) -> bool {
+        let (radius, bounds) = match (light.radius(), model.bounds()) {
+            (Some(radius), Some(bounds)) => (radius, bounds),
+            _ => return true,
+        };
+        self.moves_on_gpu(model_id) || bounds.intersects_sphere(light.position(), radius)
+    }
+
+    // The models grouped by render layer, in the order the layers are drawn, each layer's
+    // models by render order then id
+    fn model_layers(&self) -> Vec<(model::RenderLayer, Vec<(&usize, &model::Model)>)> {
+        let mut models = self.models.iter().collect::<Vec<_>>();
+        models.sort_by_key(|(id, model)| (model.render_layer(), model.render_order(), **id));
+
+        let mut layers: Vec<(model::RenderLayer, Vec<_>)> = Vec::new();
+        for (id, model) in models {
+            match layers.last_mut() {
+                Some((layer, models)) if *layer == model.render_layer() => models.push((id, model)),
+                _ => layers.push((model.render_layer(), vec![(id, model)])),
+            }
+        }
+        layers
+    }
+
+    // Maps the depth of what's drawn next into the layer's range of `camera`'s viewport
+    fn apply_render_layer(
+        render_pass: &mut wgpu::RenderPass,
+        camera: &camera::Camera,
+        layer: model::RenderLayer,
+    ) {
+        let (min_depth, max_depth) = layer.depth_range(camera.depth_mode());
+        camera.apply_viewport_depth_range(render_pass, min_depth, max_depth);
+    }
+
+    // Draws the models of `layers` for the pass, layer by layer, leaving the depth range at
+    // the world layer's; lit passes only draw the models within reach of `light`
+    #[allow(clippy::too_many_arguments)]
+    fn draw_layers<'a, 'b>(
+        &'a self,
+        render_pass: &'b mut wgpu::RenderPass<'a>,
+        gpu_state: &'a gpu_state::GpuState,
+        layers: &[(model::RenderLayer, Vec<(&'a usize, &'a model::Model)>)],
+        camera: &'a camera::Camera,
+        light: &'a light::Light,
+        pass: render_pipeline::Pass,
+    ) where
+        'a: 'b,
+    {
+        let lit = matches!(pass, render_pipeline::Pass::Lit);
+        for (layer, models) in layers {
+            Self::apply_render_layer(render_pass, camera, *layer);
+            for (id, model) in models {
+                if lit && !self.lights_model(light, **id, model) {
+                    continue;
+                }
+                self.draw_model(render_pass, gpu_state, **id, model, camera, light, pass);
+            }
+        }
+        Self::apply_render_layer(render_pass, camera, model::RenderLayer::World);
+    }
+
+    /// The world transform of the socket named `socket` on instance `instance` of the model
+    /// `model_id`, following its skin's current pose if it has one; None if there's no
+    /// such model, instance or socket.
+    pub fn socket_transform(&self, model_id: usize, instance: usize, socket: &str) -> Option<Mat4> {
+        let model = self.models.get(&model_id)?;
+        let socket = model.socket(socket)?;
+        let instance = model.instances().get(instance)?;
+        let skin = self.skins.iter().find(|skin| skin.model_id() == model_id);
+        Some(instance.matrix() * socket.transform(skin))
+    }
+
+    // Sizes the lights' shadow maps within shadow_atlas, granting the largest requests first
+    // and breaking ties by light id so allocations are stable from frame to frame. Maps which
+    // can be are packed into their regions of shadow_atlas_texture; without an atlas they
+    // move back to textures of their own.
+    fn allocate_shadow_maps(&mut self, gpu_state: &gpu_state::GpuState) {
+        let atlas = match &self.shadow_atlas {
+            Some(atlas) => *atlas,
+            None => {
+                if self.shadow_atlas_texture.take().is_some() {
+                    for light in self.lights.values_mut() {
+                        if let Some(resolution) = light
+                            .shadow_map()
+                            .filter(|shadow_map| shadow_map.atlas_region().is_some())
+                            .map(|shadow_map| shadow_map.resolution())
+                        {
+                            light.allocate_shadow_resolution(&gpu_state.device, resolution);
+                        }
+                    }
+                }
+                return;
+            }
+        };
+        if self
+            .shadow_atlas_texture
+            .as_ref()
+            .map(|atlas_texture| atlas_texture.size())
+            != Some(atlas.size())
         {
-            for model in self.models.values() {
-                model::draw_model(
+            self.shadow_atlas_texture = Some(shadow_atlas::ShadowAtlasTexture::new(
+                &gpu_state.device,
+                atlas.size(),
+            ));
+        }
+        let atlas_texture = self.shadow_atlas_texture.as_ref().unwrap();
+
+        let mut requests = self
+            .lights
+            .iter()
+            .filter(|(_, light)| light.casts_shadows())
+            .filter_map(|(id, light)| {
+                light
+                    .shadow_map()
+                    .map(|shadow_map| (*id, shadow_map.requested_resolution()))
+            })
+            .collect::<Vec<_>>();
+        requests.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let regions = atlas.allocate(
+            &requests
+                .iter()
+                .map(|(_, resolution)| *resolution)
+                .collect::<Vec<_>>(),
+        );
+
+        for ((id, _), region) in requests.iter().zip(regions) {
+            let light = self.lights.get_mut(id).unwrap();
+            let resolution = match region {
+                Some(region) => region.size,
+                None => atlas.min_resolution(),
+            };
+            if region.is_none()
+                && light.shadow_map().map(|shadow_map| shadow_map.resolution()) != Some(resolution)
+            {
+                eprintln!(
+                    "Light {}'s shadow map doesn't fit in the {}x{} shadow atlas, exceeding it at {}x{}",
+                    id, atlas.size(), atlas.size(), resolution, resolution
+                );
+            }
+            match region {
+                Some(region) if light.packs_shadow_map() => {
+                    light.pack_shadow_map(&gpu_state.device, region, atlas_texture)
+                }
+                _ => light.allocate_shadow_resolution(&gpu_state.device, resolution),
+            }
+        }
+    }
+
+    // Fits the bounds of each skinned mesh to its current pose, so skinned models are culled
+    // like any other without clipping limbs posed beyond their rest pose
+    fn update_skinned_bounds(&mut self) {
+        let bounds = self.jobs.map(&self.skins, |skin| skin.bounds());
+        for (skin, bounds) in self.skins.iter().zip(bounds) {
+            if let Some(model) = self.models.get_mut(&skin.model_id()) {
+                model.set_mesh_bounds(skin.mesh_name(), bounds);
+            }
+        }
+    }
+
+    // Culls each model's instance groups to the camera's frustum and max_instance_distance
+    // across the job system's workers, for visible_instances. Models moved on the GPU are
+    // drawn in full, so aren't culled.
+    fn cull_instance_groups(&mut self) {
+        let frustum = self.camera.frustum();
+        let position = self.camera.position();
+        let mut culled_groups = HashMap::new();
+        for (model_id, model) in self.models.iter() {
+            if model.instance_groups().is_empty() || self.moves_on_gpu(*model_id) {
+                continue;
+            }
+            let max_distance = self
+                .max_instance_distance
+                .filter(|_| model.render_layer() == model::RenderLayer::World);
+            let visible = self.jobs.map(model.instance_groups(), |group| {
+                group
+                    .bounds
+                    .is_none_or(|bounds| in_view(&frustum, position, max_distance, &bounds))
+            });
+            culled_groups.insert(*model_id, visible);
+        }
+        self.culled_groups = culled_groups;
+        self.culled_for = Some(culling_key(&self.camera));
+    }
+
+    // Places each attached instance at its socket. Attachments whose parent, socket or child
+    // doesn't exist are skipped.
+    fn update_attachments(&mut self) {
+        for index in 0..self.attachments.len() {
+            let attachment = &self.attachments[index];
+            let transform = match self.socket_transform(
+                attachment.parent_model_id,
+                attachment.parent_instance,
+                &attachment.socket,
+            ) {
+                Some(transform) => transform,
+                None => continue,
+            };
+            let instance = attachment.instance;
+            if let Some(model) = self.models.get_mut(&attachment.model_id) {
+                if let Some(current) = model.instances().get(instance) {
+                    let placed = socket::place_instance(current, transform);
+                    model.update_instance(instance, placed);
+                }
+            }
+        }
+    }
+
+    fn draws_volumetric_fog(&self) -> bool {
+        self.graphics_settings
+            .post_effects
+            .contains(PostEffects::VOLUMETRIC_FOG)
+    }
+
+    // True if the model's instances are moved on the GPU, beyond its bounds. Skinned meshes
+    // aren't, their bounds follow their pose, see update_skinned_bounds.
+    fn moves_on_gpu(&self, model_id: usize) -> bool {
+        self.instance_animators
+            .iter()
+            .any(|animator| animator.model_id() == model_id)
+    }
+
+    // Draws the model, or if it has instance groups, those within `camera`'s view
+    #[allow(clippy::too_many_arguments)]
+    fn draw_model<'a, 'b>(
+        &'a self,
+        render_pass: &'b mut wgpu::RenderPass<'a>,
+        gpu_state: &'a gpu_state::GpuState,
+        model_id: usize,
+        model: &'a model::Model,
+        camera: &'a camera::Camera,
+        light: &'a light::Light,
+        pass: render_pipeline::Pass,
+    ) where
+        'a: 'b,
+    {
+        render_pass.push_group(format_args!("Model {}", model_id));
+        for instances in self.visible_instances(model_id, model, camera) {
+            model::draw_model_range(
+                render_pass,
+                &gpu_state.pipeline_vendor,
+                model,
+                instances,
+                camera,
+                light,
+                &self.environment,
+                &pass,
+            );
+        }
+        render_pass.pop_group();
+    }
+
+    // The ranges of the model's instances to draw as seen by `camera`: its instance groups
+    // within the camera's frustum and max_instance_distance, or all its instances if it has
+    // no groups and is within the distance, less those the occlusion culler found hidden when
+    // `camera` is the scene's. Models moved on the GPU are always drawn in full.
+    fn visible_instances(
+        &self,
+        model_id: usize,
+        model: &model::Model,
+        camera: &camera::Camera,
+    ) -> Vec<Range<u32>> {
+        let all = 0..model.instances().len() as u32;
+        if self.moves_on_gpu(model_id) {
+            return vec![all];
+        }
+        // other layers aren't drawn at their true depth, so neither distance nor occlusion
+        // says anything of whether they're seen
+        let world = model.render_layer() == model::RenderLayer::World;
+        let max_distance = self.max_instance_distance.filter(|_| world);
+        // occlusion was tested from the scene's camera, so says nothing of portal views
+        let occlusion_culler = self
+            .occlusion_culler
+            .as_ref()
+            .filter(|_| world && std::ptr::eq(camera, &self.camera));
+        let occluded = |group: Option<usize>| {
+            occlusion_culler.is_some_and(|occlusion_culler| {
+                occlusion_culler.is_occluded(&occlusion::Occludee { model_id, group })
+            })
+        };
+        if model.instance_groups().is_empty() {
+            let within_distance = |bounds: model::Bounds| {
+                max_distance
+                    .is_none_or(|distance| bounds.intersects_sphere(camera.position(), distance))
+            };
+            return if model.bounds().is_none_or(within_distance) && !occluded(None) {
+                vec![all]
+            } else {
+                Vec::new()
+            };
+        }
+
+        // update culled the groups for the scene's camera, unless it has moved since
+        let culled = self.culled_groups.get(&model_id).filter(|visible| {
+            std::ptr::eq(camera, &self.camera)
+                && visible.len() == model.instance_groups().len()
+                && self.culled_for == Some(culling_key(camera))
+        });
+        let frustum = camera.frustum();
+        model
+            .instance_groups()
+            .iter()
+            .enumerate()
+            .filter(|(index, group)| {
+                let visible = match culled {
+                    Some(visible) => visible[*index],
+                    None => group.bounds.is_none_or(|bounds| {
+                        in_view(&frustum, camera.position(), max_distance, &bounds)
+                    }),
+                };
+                visible && !occluded(Some(*index))
+            })
+            .map(|(_, group)| group.instances.clone())
+            .collect()
+    }
+
+    // The models and instance groups the occlusion culler may test, with their world space
+    // bounds; those moved on the GPU have no bounds to test, and those outside the world
+    // layer aren't drawn at their true depth
+    fn occludees(&self) -> Vec<(occlusion::Occludee, model::Bounds)> {
+        let mut occludees = Vec::new();
+        for (model_id, model) in self.models.iter() {
+            if self.moves_on_gpu(*model_id) || model.render_layer() != model::RenderLayer::World {
+                continue;
+            }
+            if model.instance_groups().is_empty() {
+                if let Some(bounds) = model.bounds() {
+                    occludees.push((
+                        occlusion::Occludee {
+                            model_id: *model_id,
+                            group: None,
+                        },
+                        bounds,
+                    ));
+                }
+                continue;
+            }
+            for (index, group) in model.instance_groups().iter().enumerate() {
+                if let Some(bounds) = group.bounds {
+                    occludees.push((
+                        occlusion::Occludee {
+                            model_id: *model_id,
+                            group: Some(index),
+                        },
+                        bounds,
+                    ));
+                }
+            }
+        }
+        occludees
+    }
+
+    // Renders each visible portal's views, deepest first, as each level draws the next
+    fn render_portal_views(
+        &self,
+        gpu_state: &mut gpu_state::GpuState,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        for (index, portal) in self.portals.iter().enumerate() {
+            if !portal.is_visible(&self.camera) {
+                continue;
+            }
+            for level in (0..portal.recursion_depth()).rev() {
+                let camera = match portal.camera(level) {
+                    Some(camera) => camera,
+                    None => continue,
+                };
+                let (color_attachment, depth_attachment) = match (
+                    camera.render_buffers.color.as_ref(),
+                    camera.render_buffers.depth.as_ref(),
+                ) {
+                    (Some(color), Some(depth)) => (color, depth),
+                    _ => continue,
+                };
+
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Portal Render Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &color_attachment.view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: camera.color_load_op(),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &depth_attachment.view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: camera.depth_load_op(),
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+
+                camera.apply_viewport(&mut render_pass);
+                self.draw_view(&mut render_pass, gpu_state, camera, Some((index, level)));
+            }
+        }
+    }
+
+    fn render_weather(
+        &self,
+        gpu_state: &mut gpu_state::GpuState,
+        encoder: &mut wgpu::CommandEncoder,
+        weather: &weather::Weather,
+    ) {
+        let color_attachment = match self.camera.render_buffers.color.as_ref() {
+            Some(color_attachment) => color_attachment,
+            None => return,
+        };
+
+        // the depth attachment is read by the weather shader, so can't be attached
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Weather Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &color_attachment.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        self.camera.apply_viewport(&mut render_pass);
+        weather.draw(
+            &mut render_pass,
+            &gpu_state.pipeline_vendor,
+            &gpu_state.transient_buffers,
+            &self.camera,
+        );
+    }
+
+    fn render_depth_prepass(
+        &self,
+        gpu_state: &mut gpu_state::GpuState,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let depth_attachment = match self.camera.render_buffers.depth.as_ref() {
+            Some(depth_attachment) => depth_attachment,
+            None => return,
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Prepass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth_attachment.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: self.camera.depth_load_op(),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        self.camera.apply_viewport(&mut render_pass);
+        // only instances the ambient pass draws may write depth, or culled ones would leave
+        // holes; each layer's depth is mapped as the ambient pass maps it
+        for (layer, models) in self.model_layers() {
+            Self::apply_render_layer(&mut render_pass, &self.camera, layer);
+            depth_pass::draw_prepass(
+                &mut render_pass,
+                &gpu_state.pipeline_vendor,
+                models.into_iter().flat_map(|(id, model)| {
+                    self.visible_instances(*id, model, &self.camera)
+                        .into_iter()
+                        .map(move |instances| (model, instances))
+                }),
+                &self.camera,
+            );
+        }
+    }
+
+    fn render_shadow_maps(
+        &self,
+        gpu_state: &mut gpu_state::GpuState,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let due_shadow_maps = self.lights.iter().filter_map(|(id, light)| {
+            // maps which aren't due keep their last render, see shadow::ShadowUpdate; those of
+            // lights converted to types which cast no shadows, or demoted past shadows, are idle
+            light
+                .shadow_map()
+                .filter(|shadow_map| light.renders_shadows() && shadow_map.is_due())
+                .map(|shadow_map| (id, light, shadow_map))
+        });
+
+        // maps packed into the shadow atlas share one pass, each in its own region
+        let mut packed = Vec::new();
+        for (id, light, shadow_map) in due_shadow_maps {
+            let texture = match shadow_map.texture() {
+                Some(texture) => texture,
+                None => {
+                    packed.push((id, light, shadow_map));
+                    continue;
+                }
+            };
+            encoder.push_group(format_args!("Shadow Map: Light {}", id));
+            {
+                // variance shadow modes render depth moments to a color attachment, pcf is depth-only
+                let color_attachments = shadow_map
+                    .moments_attachment()
+                    .into_iter()
+                    .map(Some)
+                    .collect::<Vec<_>>();
+
+                {
+                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("Shadow Map Render Pass"),
+                        color_attachments: &color_attachments,
+                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                            view: &texture.view,
+                            depth_ops: Some(wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(1.0),
+                                store: true,
+                            }),
+                            stencil_ops: None,
+                        }),
+                    });
+
+                    for model in self.models.values() {
+                        model::draw_model_shadow(
+                            &mut render_pass,
+                            &gpu_state.pipeline_vendor,
+                            model,
+                            light,
+                        );
+                    }
+                }
+
+                shadow_map.blur_moments(gpu_state, encoder);
+            }
+            encoder.pop_group();
+        }
+
+        if let (Some(atlas_texture), false) = (&self.shadow_atlas_texture, packed.is_empty()) {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Atlas Render Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &atlas_texture.texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        // regions which aren't due keep their last render
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            for (id, light, shadow_map) in packed {
+                render_pass.push_group(format_args!("Shadow Atlas: Light {}", id));
+                let region = shadow_map.atlas_region().unwrap();
+                shadow_atlas::ShadowAtlasTexture::begin_region(
                     &mut render_pass,
                     &gpu_state.pipeline_vendor,
-                    model,
-                    &self.camera,
-                    light,
-                    &render_pipeline::Pass::Lit,
+                    region,
                 );
+                for model in self.models.values() {
+                    model::draw_model_shadow(
+                        &mut render_pass,
+                        &gpu_state.pipeline_vendor,
+                        model,
+                        light,
+                    );
+                }
+                render_pass.pop_group();
             }
         }
     }
 }
+
+// True if `bounds` intersect `frustum` and, if given, lie within `max_distance` of `position`
+fn in_view(
+    frustum: &Frustum,
+    position: Point3,
+    max_distance: Option<f32>,
+    bounds: &model::Bounds,
+) -> bool {
+    frustum.intersects_aabb(bounds.min, bounds.max)
+        && max_distance.is_none_or(|distance| bounds.intersects_sphere(position, distance))
+}
+
+// What the frustum of `camera` is made from, to tell whether groups culled for it still apply
+fn culling_key(camera: &camera::Camera) -> (Mat4, Mat4) {
+    (camera.world_transform(), camera.projection_matrix())
+}
@@ -0,0 +1,229 @@
+use std::{path::PathBuf, rc::Rc};
+
+use anyhow::{anyhow, Result};
+
+use super::{camera, readback::ReadbackPool, texture};
+
+/// What a screenshot captures, chosen per capture, see Screenshots::capture
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CaptureMode {
+    // the scene camera's color attachment before the compositor, without post effects or
+    // anything render hooks draw around the compositor, e.g. UI; written as OpenEXR holding
+    // linear color, whatever the path's extension
+    Scene,
+    // the image presented to the window, with post effects and everything render hooks draw
+    // around the compositor; written in the format the path's extension names, e.g. PNG
+    Final,
+}
+
+struct Request {
+    mode: CaptureMode,
+    path: PathBuf,
+}
+
+// The offscreen copy of the presented image for Final captures
+struct FinalTarget {
+    texture: Rc<texture::Texture>,
+    size: winit::dpi::PhysicalSize<u32>,
+    format: wgpu::TextureFormat,
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Captures frames to image files. A capture is requested at any point, and taken from the
+// next frame rendered: its images are read back through the ReadbackPool, without stalling
+// the frame, then converted and written on a thread of their own. Surfaces can't be copied
+// from on every backend, so for Final captures the app draws the compositor and the render
+// hooks around it a second time, into final_target; see app::run.
+#[derive(Default)]
+pub struct Screenshots {
+    requests: Vec<Request>,
+    final_target: Option<FinalTarget>,
+}
+
+impl Screenshots {
+    /// Captures the next frame rendered as `mode`, writing it to `path` a few frames later
+    pub fn capture(&mut self, mode: CaptureMode, path: impl Into<PathBuf>) {
+        self.requests.push(Request {
+            mode,
+            path: path.into(),
+        });
+    }
+
+    /// True if the next frame rendered will be captured as `mode`
+    pub fn is_requested(&self, mode: CaptureMode) -> bool {
+        self.requests.iter().any(|request| request.mode == mode)
+    }
+
+    /// The texture the app draws the presented image into for Final captures, as it draws
+    /// the surface texture; `format` is the compositor's target format (see
+    /// compositor::Compositor::target_info), so the capture is encoded as presented. Kept from
+    /// capture to capture while the size and format match.
+    pub fn final_target(
+        &mut self,
+        device: &wgpu::Device,
+        size: winit::dpi::PhysicalSize<u32>,
+        format: wgpu::TextureFormat,
+    ) -> Rc<texture::Texture> {
+        match &self.final_target {
+            Some(target) if target.size == size && target.format == format => {}
+            _ => {
+                self.final_target = Some(FinalTarget {
+                    texture: Rc::new(texture::Texture::create_render_target(
+                        device,
+                        size.width,
+                        size.height,
+                        format,
+                        "Screenshot Final Target",
+                    )),
+                    size,
+                    format,
+                })
+            }
+        }
+        self.final_target.as_ref().unwrap().texture.clone()
+    }
+
+    /// Records reads of this frame's images for the requested captures, after the frame's
+    /// passes in `encoder`; `camera` is the scene's. Must be called before the frame's
+    /// submission, and Final captures need final_target to have been drawn into.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        readbacks: &mut ReadbackPool,
+        camera: &camera::Camera,
+    ) {
+        for request in self.requests.drain(..) {
+            let (texture, format, size) = match request.mode {
+                CaptureMode::Scene => match &camera.render_buffers.color {
+                    Some(color) => (
+                        &color.texture,
+                        texture::Texture::COLOR_FORMAT,
+                        camera.render_size(),
+                    ),
+                    None => {
+                        eprintln!(
+                            "Unable to capture \"{}\", the camera has no color attachment",
+                            request.path.display()
+                        );
+                        continue;
+                    }
+                },
+                CaptureMode::Final => match &self.final_target {
+                    Some(target) => (&target.texture.texture, target.format, target.size),
+                    None => {
+                        eprintln!(
+                            "Unable to capture \"{}\", nothing was drawn to the final target",
+                            request.path.display()
+                        );
+                        continue;
+                    }
+                },
+            };
+
+            let (width, height) = (size.width, size.height);
+            readbacks.read_texture(
+                device,
+                encoder,
+                texture,
+                format,
+                (0, 0),
+                width,
+                height,
+                move |texels| {
+                    let texels = texels.to_vec();
+                    // encoding large images takes a while, so is kept off the render thread
+                    std::thread::spawn(move || {
+                        let result =
+                            Self::write(request.mode, &request.path, format, width, height, texels);
+                        match result {
+                            Ok(()) => println!("Saved screenshot \"{}\"", request.path.display()),
+                            Err(e) => eprintln!(
+                                "Unable to save screenshot \"{}\": {:?}",
+                                request.path.display(),
+                                e
+                            ),
+                        }
+                    });
+                },
+            );
+        }
+    }
+
+    fn write(
+        mode: CaptureMode,
+        path: &std::path::Path,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        texels: Vec<u8>,
+    ) -> Result<()> {
+        let rgba = to_rgba8(format, texels)?;
+        match mode {
+            CaptureMode::Scene => {
+                let linear = rgba
+                    .chunks_exact(4)
+                    .flat_map(|texel| {
+                        let channel = |value: u8| value as f32 / 255.0;
+                        let color = |value: u8| {
+                            if is_srgb(format) {
+                                srgb_to_linear(channel(value))
+                            } else {
+                                channel(value)
+                            }
+                        };
+                        [
+                            color(texel[0]),
+                            color(texel[1]),
+                            color(texel[2]),
+                            channel(texel[3]),
+                        ]
+                    })
+                    .collect::<Vec<_>>();
+                let image = image::Rgba32FImage::from_raw(width, height, linear)
+                    .ok_or_else(|| anyhow!("Texels don't fill a {}x{} image", width, height))?;
+                image::DynamicImage::ImageRgba32F(image)
+                    .save_with_format(path, image::ImageFormat::OpenExr)?;
+            }
+            CaptureMode::Final => {
+                let image = image::RgbaImage::from_raw(width, height, rgba)
+                    .ok_or_else(|| anyhow!("Texels don't fill a {}x{} image", width, height))?;
+                image.save(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The texels of an 8 bit per channel format in RGBA order
+pub(super) fn to_rgba8(format: wgpu::TextureFormat, mut texels: Vec<u8>) -> Result<Vec<u8>> {
+    match format {
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => Ok(texels),
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => {
+            for texel in texels.chunks_exact_mut(4) {
+                texel.swap(0, 2);
+            }
+            Ok(texels)
+        }
+        _ => Err(anyhow!(
+            "Screenshots of {:?} textures aren't supported",
+            format
+        )),
+    }
+}
+
+fn is_srgb(format: wgpu::TextureFormat) -> bool {
+    matches!(
+        format,
+        wgpu::TextureFormat::Rgba8UnormSrgb | wgpu::TextureFormat::Bgra8UnormSrgb
+    )
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
@@ -0,0 +1,90 @@
+use anyhow::{anyhow, Result};
+
+// A minimal line based preprocessor supporting `#ifdef NAME`, `#ifndef NAME`, `#else` and
+// `#endif`, which may be nested. Directive lines and excluded lines are blanked rather than
+// removed so that line numbers in naga's error messages still match the source file.
+pub fn preprocess(source: &str, defines: &[&str]) -> Result<String> {
+    // (enclosing block is active, this block's condition)
+    let mut stack: Vec<(bool, bool)> = Vec::new();
+    let mut output = String::with_capacity(source.len());
+
+    for (index, line) in source.lines().enumerate() {
+        let is_active = stack
+            .last()
+            .is_none_or(|(parent_active, condition)| *parent_active && *condition);
+        let directive = line.trim();
+
+        if let Some(name) = directive.strip_prefix("#ifdef ") {
+            stack.push((is_active, defines.contains(&name.trim())));
+        } else if let Some(name) = directive.strip_prefix("#ifndef ") {
+            stack.push((is_active, !defines.contains(&name.trim())));
+        } else if directive == "#else" {
+            let (_, condition) = stack
+                .last_mut()
+                .ok_or_else(|| anyhow!("Line {}: #else without #ifdef", index + 1))?;
+            *condition = !*condition;
+        } else if directive == "#endif" {
+            stack
+                .pop()
+                .ok_or_else(|| anyhow!("Line {}: #endif without #ifdef", index + 1))?;
+        } else if is_active {
+            output.push_str(line);
+        }
+        output.push('\n');
+    }
+
+    if !stack.is_empty() {
+        return Err(anyhow!("Unterminated #ifdef"));
+    }
+
+    Ok(output)
+}
+
+// Resolves `#include "path"` lines, where path is relative to the res directory as for
+// resources::load_string, replacing each with the file's source as loaded by `load`. Included
+// files may include others. Each file is included once, however many times it's named, so
+// shared helpers may be included freely; as naga requires functions to be declared before
+// they're called, includes belong at the top of a file. Includes are resolved before
+// preprocess, so an #include within an #ifdef block is included regardless.
+pub fn resolve_includes<F>(source: &str, load: F) -> Result<String>
+where
+    F: Fn(&str) -> Result<String>,
+{
+    let mut included = Vec::new();
+    let mut output = String::with_capacity(source.len());
+    append_with_includes(source, &load, &mut included, &mut output)?;
+    Ok(output)
+}
+
+fn append_with_includes<F>(
+    source: &str,
+    load: &F,
+    included: &mut Vec<String>,
+    output: &mut String,
+) -> Result<()>
+where
+    F: Fn(&str) -> Result<String>,
+{
+    for (index, line) in source.lines().enumerate() {
+        let path = match line.trim().strip_prefix("#include ") {
+            Some(path) => path
+                .trim()
+                .strip_prefix('"')
+                .and_then(|path| path.strip_suffix('"'))
+                .ok_or_else(|| anyhow!("Line {}: expected #include \"path\"", index + 1))?,
+            None => {
+                output.push_str(line);
+                output.push('\n');
+                continue;
+            }
+        };
+
+        if included.iter().any(|included| included == path) {
+            continue;
+        }
+        included.push(path.to_string());
+        let source = load(path).map_err(|e| anyhow!("Unable to include \"{}\": {}", path, e))?;
+        append_with_includes(&source, load, included, output)?;
+    }
+    Ok(())
+}
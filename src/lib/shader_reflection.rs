@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+/// What a shader reads through a binding, as WGSL declares it; see binding_kind for the
+/// same of a bind group layout entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindingKind {
+    UniformBuffer,
+    StorageBuffer,
+    Texture {
+        dimension: wgpu::TextureViewDimension,
+        sample: SampleKind,
+    },
+    StorageTexture {
+        dimension: wgpu::TextureViewDimension,
+    },
+    Sampler {
+        comparison: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleKind {
+    Float,
+    Depth,
+    Sint,
+    Uint,
+}
+
+/// A binding read by one or more of a shader's entry points
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub group: u32,
+    pub binding: u32,
+    pub kind: BindingKind,
+    // the stages of the entry points which read it
+    pub stages: wgpu::ShaderStages,
+}
+
+/// Parses and validates WGSL `source` with naga, returning the bindings read by
+/// `entry_points`, ordered by group and binding. The shader may declare others, e.g.
+/// model.wgsl declares every optional texture. `label` names the shader in errors, e.g.
+/// `custom shader "shaders/hologram.wgsl"`.
+pub fn reflect_bindings(
+    label: &str,
+    source: &str,
+    entry_points: &[(&str, naga::ShaderStage)],
+) -> Result<Vec<ReflectedBinding>> {
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|e| anyhow!("Unable to parse {}:\n{}", label, e.emit_to_string(source)))?;
+
+    let info = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .map_err(|e| anyhow!("Invalid {}: {}", label, e))?;
+
+    let mut used_globals = HashMap::new();
+    for (entry_point, stage) in entry_points {
+        let index = module
+            .entry_points
+            .iter()
+            .position(|ep| ep.name == *entry_point && ep.stage == *stage)
+            .ok_or_else(|| {
+                anyhow!(
+                    "No {:?} entry point named \"{}\" in {}",
+                    stage,
+                    entry_point,
+                    label
+                )
+            })?;
+
+        let entry_point_info = info.get_entry_point(index);
+        for (handle, _) in module.global_variables.iter() {
+            if !entry_point_info[handle].is_empty() {
+                *used_globals
+                    .entry(handle)
+                    .or_insert_with(wgpu::ShaderStages::empty) |= shader_stages(*stage);
+            }
+        }
+    }
+
+    let mut bindings = Vec::new();
+    for (handle, variable) in module.global_variables.iter() {
+        let (binding, stages) = match (&variable.binding, used_globals.get(&handle)) {
+            (Some(binding), Some(stages)) => (binding, *stages),
+            _ => continue,
+        };
+
+        let kind = match (&variable.space, &module.types[variable.ty].inner) {
+            (naga::AddressSpace::Uniform, _) => BindingKind::UniformBuffer,
+            (naga::AddressSpace::Storage { .. }, _) => BindingKind::StorageBuffer,
+            (
+                naga::AddressSpace::Handle,
+                naga::TypeInner::Image {
+                    dim,
+                    arrayed,
+                    class,
+                },
+            ) => {
+                let dimension = view_dimension(*dim, *arrayed);
+                match class {
+                    naga::ImageClass::Sampled { kind, .. } => BindingKind::Texture {
+                        dimension,
+                        sample: match kind {
+                            naga::ScalarKind::Sint => SampleKind::Sint,
+                            naga::ScalarKind::Uint => SampleKind::Uint,
+                            _ => SampleKind::Float,
+                        },
+                    },
+                    naga::ImageClass::Depth { .. } => BindingKind::Texture {
+                        dimension,
+                        sample: SampleKind::Depth,
+                    },
+                    naga::ImageClass::Storage { .. } => BindingKind::StorageTexture { dimension },
+                }
+            }
+            (naga::AddressSpace::Handle, naga::TypeInner::Sampler { comparison }) => {
+                BindingKind::Sampler {
+                    comparison: *comparison,
+                }
+            }
+            (space, _) => {
+                return Err(anyhow!(
+                    "Unsupported address space {:?} for @group({}) @binding({}) in {}",
+                    space,
+                    binding.group,
+                    binding.binding,
+                    label
+                ))
+            }
+        };
+
+        bindings.push(ReflectedBinding {
+            group: binding.group,
+            binding: binding.binding,
+            kind,
+            stages,
+        });
+    }
+
+    bindings.sort_by_key(|binding| (binding.group, binding.binding));
+    Ok(bindings)
+}
+
+/// The kind of binding a bind group layout entry provides
+pub fn binding_kind(entry: &wgpu::BindGroupLayoutEntry) -> BindingKind {
+    match entry.ty {
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            ..
+        } => BindingKind::UniformBuffer,
+        wgpu::BindingType::Buffer { .. } => BindingKind::StorageBuffer,
+        wgpu::BindingType::Texture {
+            sample_type,
+            view_dimension,
+            ..
+        } => BindingKind::Texture {
+            dimension: view_dimension,
+            sample: match sample_type {
+                wgpu::TextureSampleType::Float { .. } => SampleKind::Float,
+                wgpu::TextureSampleType::Depth => SampleKind::Depth,
+                wgpu::TextureSampleType::Sint => SampleKind::Sint,
+                wgpu::TextureSampleType::Uint => SampleKind::Uint,
+            },
+        },
+        wgpu::BindingType::StorageTexture { view_dimension, .. } => BindingKind::StorageTexture {
+            dimension: view_dimension,
+        },
+        wgpu::BindingType::Sampler(sampler) => BindingKind::Sampler {
+            comparison: sampler == wgpu::SamplerBindingType::Comparison,
+        },
+    }
+}
+
+/// Checks that each of `bindings` is provided by the layouts a pipeline is created with, one
+/// per group, with a matching kind and visible to the stages reading it. Bindings the shader
+/// doesn't read may be provided freely.
+pub fn validate_bindings(
+    label: &str,
+    bindings: &[ReflectedBinding],
+    layouts: &[&[wgpu::BindGroupLayoutEntry]],
+) -> Result<()> {
+    for reflected in bindings {
+        let entry = layouts
+            .get(reflected.group as usize)
+            .and_then(|entries| {
+                entries
+                    .iter()
+                    .find(|entry| entry.binding == reflected.binding)
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "@group({}) @binding({}) is read as {:?} by {}, but isn't provided by its bind group layouts",
+                    reflected.group,
+                    reflected.binding,
+                    reflected.kind,
+                    label
+                )
+            })?;
+
+        let provided = binding_kind(entry);
+        if provided != reflected.kind {
+            return Err(anyhow!(
+                "@group({}) @binding({}) is read as {:?} by {}, but its bind group layout provides {:?}",
+                reflected.group,
+                reflected.binding,
+                reflected.kind,
+                label,
+                provided
+            ));
+        }
+
+        if !entry.visibility.contains(reflected.stages) {
+            return Err(anyhow!(
+                "@group({}) @binding({}) is read in {:?} by {}, but its bind group layout only makes it visible to {:?}",
+                reflected.group,
+                reflected.binding,
+                reflected.stages,
+                label,
+                entry.visibility
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn shader_stages(stage: naga::ShaderStage) -> wgpu::ShaderStages {
+    match stage {
+        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+    }
+}
+
+fn view_dimension(dim: naga::ImageDimension, arrayed: bool) -> wgpu::TextureViewDimension {
+    match (dim, arrayed) {
+        (naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+        (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+        (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+        (naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+        (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+        (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+    }
+}
@@ -0,0 +1,743 @@
+use cgmath::prelude::*;
+
+use super::{
+    camera, depth_pass, gpu_state::GpuState, light, model, render_pipeline, resources,
+    shadow_atlas::ShadowAtlasRegion, texture, transient_textures::TransientTextureDescriptor,
+    util::*,
+};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Bias terms applied when rendering and sampling a shadow map. Too little bias produces
+/// "shadow acne" (self-shadowing stripes), too much detaches shadows from their casters ("peter-panning").
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowBias {
+    // constant depth bias, in depth buffer units, applied by the rasterizer when rendering the shadow map
+    pub constant: i32,
+    // depth bias scaled by the caster's depth slope, applied by the rasterizer when rendering the shadow map
+    pub slope_scale: f32,
+    // distance in world units a receiver is pushed along its normal before the shadow map lookup
+    pub normal_offset: f32,
+}
+
+impl Default for ShadowBias {
+    fn default() -> Self {
+        Self {
+            constant: 2,
+            slope_scale: 2.0,
+            normal_offset: 0.05,
+        }
+    }
+}
+
+impl ShadowBias {
+    pub fn depth_bias_state(&self) -> wgpu::DepthBiasState {
+        wgpu::DepthBiasState {
+            constant: self.constant,
+            slope_scale: self.slope_scale,
+            clamp: 0.0,
+        }
+    }
+
+    // Depth bias is baked into the pipeline, so each distinct bias needs its own shadow pipeline.
+    // Pcf maps are depth-only and share depth_pass's pipelines.
+    fn pipeline_id(&self, mode: ShadowMode) -> String {
+        if !mode.uses_moments() {
+            return depth_pass::pipeline_id(
+                &self.depth_bias_state(),
+                render_pipeline::DepthMode::Standard,
+            );
+        }
+
+        format!(
+            "shadow_{}_[{},{}]",
+            mode.name(),
+            self.constant,
+            self.slope_scale
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadowMode {
+    // hardware depth comparison with bilinear percentage-closer filtering
+    Pcf,
+    // variance shadow maps store depth moments which are blurred, giving soft penumbras
+    Variance,
+    // exponentially warped variance shadow maps, which suffer less light bleeding than Variance
+    ExponentialVariance,
+}
+
+impl ShadowMode {
+    fn name(&self) -> &'static str {
+        match self {
+            ShadowMode::Pcf => "pcf",
+            ShadowMode::Variance => "variance",
+            ShadowMode::ExponentialVariance => "exponential_variance",
+        }
+    }
+
+    // Value written to the light uniform's shadow.w; 0 is reserved for "no shadow map"
+    pub(super) fn value(&self) -> f32 {
+        match self {
+            ShadowMode::Pcf => 1.0,
+            ShadowMode::Variance => 2.0,
+            ShadowMode::ExponentialVariance => 3.0,
+        }
+    }
+
+    fn uses_moments(&self) -> bool {
+        match self {
+            ShadowMode::Pcf => false,
+            ShadowMode::Variance | ShadowMode::ExponentialVariance => true,
+        }
+    }
+
+    fn fragment_main(&self) -> Option<&'static str> {
+        match self {
+            ShadowMode::Pcf => None,
+            ShadowMode::Variance => Some("fs_main_shadow_variance"),
+            ShadowMode::ExponentialVariance => Some("fs_main_shadow_exponential_variance"),
+        }
+    }
+
+    // Moments of the far plane, which the moments target is cleared to
+    fn clear_moments(&self) -> wgpu::Color {
+        let depth = match self {
+            ShadowMode::ExponentialVariance => EVSM_EXPONENT.exp(),
+            _ => 1.0,
+        };
+        wgpu::Color {
+            r: depth,
+            g: depth * depth,
+            b: 0.0,
+            a: 1.0,
+        }
+    }
+}
+
+// Must match EVSM_EXPONENT in shadow.wgsl and model.wgsl
+const EVSM_EXPONENT: f64 = 40.0;
+
+/// How a directional light's orthographic shadow volume is placed. Either way the volume moves
+/// in whole shadow map texels, so the shadows of static casters don't shimmer as it moves.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ShadowFit {
+    // centered on the light's position, extending `range` to either side
+    #[default]
+    Fixed,
+    // encloses the camera's view out to `distance`, and `range` beyond it toward the light to
+    // take in casters outside the view. The volume is sized by the view's bounding sphere, so
+    // it doesn't change size as the camera turns.
+    CameraFrustum {
+        distance: f32,
+    },
+}
+
+/// How often a shadow map is rendered. Between renders it keeps the shadows of its last render,
+/// sampled with the light's transform at the time, so a light's shadows lag its motion until
+/// the next.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShadowUpdate {
+    #[default]
+    EveryFrame,
+    // every n frames, e.g. for distant or slow moving lights; 0 and 1 render every frame
+    EveryNFrames(u32),
+    // only when requested with ShadowMap::request_update, e.g. for lights over static geometry
+    OnDemand,
+}
+
+pub struct ShadowDescriptor {
+    // width and height of the square shadow map in texels
+    pub resolution: u32,
+    // for spot lights, the far plane of the shadow projection. For directional lights, see fit.
+    pub range: f32,
+    // for directional lights, where the shadow volume is placed
+    pub fit: ShadowFit,
+    pub update: ShadowUpdate,
+    pub bias: ShadowBias,
+    pub mode: ShadowMode,
+    // for the variance modes, [0,1) amount of the penumbra to cut off to hide light bleeding
+    pub light_bleeding_reduction: f32,
+}
+
+impl Default for ShadowDescriptor {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            range: 50.0,
+            fit: ShadowFit::default(),
+            update: ShadowUpdate::default(),
+            bias: ShadowBias::default(),
+            mode: ShadowMode::Pcf,
+            light_bleeding_reduction: 0.2,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Render targets for the variance shadow modes. Moments are rendered into `moments`, then blurred
+// horizontally into a transient scratch target and vertically back into `moments`. Scratch
+// targets are shared by every shadow map of the same resolution, as blurs don't overlap.
+struct MomentsTargets {
+    moments: texture::Texture,
+    moments_bind_group: wgpu::BindGroup,
+}
+
+impl MomentsTargets {
+    fn new(device: &wgpu::Device, resolution: u32) -> Self {
+        let moments =
+            texture::Texture::create_moments_texture(device, resolution, "Shadow Map Moments");
+        let moments_bind_group = ShadowMap::create_blur_bind_group(device, &moments);
+
+        Self {
+            moments,
+            moments_bind_group,
+        }
+    }
+}
+
+pub struct ShadowMap {
+    // the map's own depth texture, or None when it's packed into a shadow atlas
+    texture: Option<texture::Texture>,
+    // the region of the shadow atlas the map is packed into, and the atlas' size in texels
+    atlas_region: Option<(ShadowAtlasRegion, u32)>,
+    moments: Option<MomentsTargets>,
+    resolution: u32,
+    // the resolution asked for, which a shadow_atlas::ShadowAtlas may have granted less of
+    requested_resolution: u32,
+    range: f32,
+    fit: ShadowFit,
+    update: ShadowUpdate,
+    // frames since the map was last rendered, and whether it's rendered this frame
+    frames_since_render: u32,
+    update_requested: bool,
+    is_due: bool,
+    bias: ShadowBias,
+    mode: ShadowMode,
+    light_bleeding_reduction: f32,
+    pipeline_id: String,
+}
+
+impl ShadowMap {
+    const BLUR_HORIZONTAL_PIPELINE_ID: &'static str = "shadow_blur_horizontal";
+    const BLUR_VERTICAL_PIPELINE_ID: &'static str = "shadow_blur_vertical";
+
+    pub fn new(device: &wgpu::Device, desc: &ShadowDescriptor) -> Self {
+        let moments = if desc.mode.uses_moments() {
+            Some(MomentsTargets::new(device, desc.resolution))
+        } else {
+            None
+        };
+        Self::create(
+            desc,
+            desc.resolution,
+            Some(texture::Texture::create_shadow_texture(
+                device,
+                desc.resolution,
+                "Shadow Map",
+            )),
+            None,
+            moments,
+        )
+    }
+
+    /// Creates a shadow map packed into `region` of a shadow_atlas::ShadowAtlasTexture
+    /// `atlas_size` texels square, at the region's resolution. Only Pcf maps may be packed, as
+    /// the variance modes blur their moments in textures of their own; other modes get their
+    /// own textures at that resolution.
+    pub fn new_in_atlas(
+        device: &wgpu::Device,
+        desc: &ShadowDescriptor,
+        region: ShadowAtlasRegion,
+        atlas_size: u32,
+    ) -> Self {
+        if desc.mode.uses_moments() {
+            eprintln!(
+                "{:?} shadow maps can't be packed into a shadow atlas",
+                desc.mode
+            );
+            return Self::create(
+                desc,
+                region.size,
+                Some(texture::Texture::create_shadow_texture(
+                    device,
+                    region.size,
+                    "Shadow Map",
+                )),
+                None,
+                Some(MomentsTargets::new(device, region.size)),
+            );
+        }
+        Self::create(desc, region.size, None, Some((region, atlas_size)), None)
+    }
+
+    fn create(
+        desc: &ShadowDescriptor,
+        resolution: u32,
+        texture: Option<texture::Texture>,
+        atlas_region: Option<(ShadowAtlasRegion, u32)>,
+        moments: Option<MomentsTargets>,
+    ) -> Self {
+        Self {
+            texture,
+            atlas_region,
+            moments,
+            resolution,
+            requested_resolution: desc.resolution,
+            range: desc.range,
+            fit: desc.fit,
+            update: desc.update,
+            frames_since_render: 0,
+            // a new map has nothing to keep
+            update_requested: true,
+            is_due: false,
+            bias: desc.bias,
+            mode: desc.mode,
+            light_bleeding_reduction: desc.light_bleeding_reduction.clamp(0.0, 0.99),
+            pipeline_id: desc.bias.pipeline_id(desc.mode),
+        }
+    }
+
+    /// Creates the 1x1 depth texture bound in place of a shadow map for lights which don't cast shadows
+    pub fn create_placeholder_texture(device: &wgpu::Device) -> texture::Texture {
+        texture::Texture::create_shadow_texture(device, 1, "Shadow Map Placeholder")
+    }
+
+    /// Creates the 1x1 moments texture bound in place of a shadow map's moments for lights
+    /// which don't use a variance shadow mode
+    pub fn create_moments_placeholder_texture(device: &wgpu::Device) -> texture::Texture {
+        texture::Texture::create_moments_texture(device, 1, "Shadow Map Moments Placeholder")
+    }
+
+    pub fn mode(&self) -> ShadowMode {
+        self.mode
+    }
+
+    /// The map's own depth texture, or None when it's packed into a shadow atlas
+    pub fn texture(&self) -> Option<&texture::Texture> {
+        self.texture.as_ref()
+    }
+
+    /// The region of the shadow atlas the map is packed into, if it is
+    pub fn atlas_region(&self) -> Option<ShadowAtlasRegion> {
+        self.atlas_region.map(|(region, _)| region)
+    }
+
+    /// The map's region of the texture it's sampled from as (x offset, y offset, x scale,
+    /// y scale) in texture coordinates; the whole texture unless it's packed into an atlas
+    pub fn uv_rect(&self) -> Vec4 {
+        match self.atlas_region {
+            Some((region, atlas_size)) => region.uv_rect(atlas_size),
+            None => Vec4::new(0.0, 0.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn light_bleeding_reduction(&self) -> f32 {
+        self.light_bleeding_reduction
+    }
+
+    /// The blurred moments texture sampled by the lit pass, if this shadow map uses a variance mode
+    pub fn moments_texture(&self) -> Option<&texture::Texture> {
+        self.moments.as_ref().map(|moments| &moments.moments)
+    }
+
+    /// The color attachment the shadow pass renders moments into, if this shadow map uses a variance mode
+    pub fn moments_attachment(&self) -> Option<wgpu::RenderPassColorAttachment<'_>> {
+        self.moments
+            .as_ref()
+            .map(|moments| wgpu::RenderPassColorAttachment {
+                view: &moments.moments.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.mode.clear_moments()),
+                    store: true,
+                },
+            })
+    }
+
+    /// Width and height of the shadow map in texels
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    /// The resolution the map was created with or last set to, which may exceed resolution()
+    /// when a shadow_atlas::ShadowAtlas grants it less
+    pub fn requested_resolution(&self) -> u32 {
+        self.requested_resolution
+    }
+
+    pub fn set_requested_resolution(&mut self, requested_resolution: u32) {
+        self.requested_resolution = requested_resolution;
+    }
+
+    /// A descriptor which creates a shadow map like this one, e.g. to recreate it at another resolution
+    pub fn descriptor(&self) -> ShadowDescriptor {
+        ShadowDescriptor {
+            resolution: self.requested_resolution,
+            range: self.range,
+            fit: self.fit,
+            update: self.update,
+            bias: self.bias,
+            mode: self.mode,
+            light_bleeding_reduction: self.light_bleeding_reduction,
+        }
+    }
+
+    pub fn range(&self) -> f32 {
+        self.range
+    }
+
+    pub fn set_range(&mut self, range: f32) {
+        self.range = range.max(0.0);
+        self.update_requested = true;
+    }
+
+    pub fn update(&self) -> ShadowUpdate {
+        self.update
+    }
+
+    pub fn set_update(&mut self, update: ShadowUpdate) {
+        self.update = update;
+    }
+
+    /// Has the map rendered on the next frame, whatever its update rate.
+    pub fn request_update(&mut self) {
+        self.update_requested = true;
+    }
+
+    /// True if the map is rendered this frame, as decided by schedule
+    pub fn is_due(&self) -> bool {
+        self.is_due
+    }
+
+    /// Decides whether the map is rendered this frame by its update rate and any request,
+    /// returning is_due; called once a frame by light::Light::update.
+    pub fn schedule(&mut self) -> bool {
+        let interval = match self.update {
+            ShadowUpdate::EveryFrame => Some(1),
+            ShadowUpdate::EveryNFrames(frames) => Some(frames.max(1)),
+            ShadowUpdate::OnDemand => None,
+        };
+        self.frames_since_render = self.frames_since_render.saturating_add(1);
+        self.is_due = self.update_requested
+            || interval.is_some_and(|interval| self.frames_since_render >= interval);
+        if self.is_due {
+            self.frames_since_render = 0;
+            self.update_requested = false;
+        }
+        self.is_due
+    }
+
+    pub fn fit(&self) -> ShadowFit {
+        self.fit
+    }
+
+    pub fn set_fit(&mut self, fit: ShadowFit) {
+        self.fit = fit;
+        self.update_requested = true;
+    }
+
+    pub fn bias(&self) -> ShadowBias {
+        self.bias
+    }
+
+    pub fn set_bias(&mut self, bias: ShadowBias) {
+        self.bias = bias;
+        self.pipeline_id = bias.pipeline_id(self.mode);
+        self.update_requested = true;
+    }
+
+    pub fn pipeline_id(&self) -> &str {
+        &self.pipeline_id
+    }
+
+    pub fn prepare_pipeline(&self, gpu_state: &mut GpuState) {
+        if !self.mode.uses_moments() {
+            // shadow maps use standard depth to suit the comparison sampler, see
+            // texture::Texture::create_shadow_texture
+            depth_pass::prepare_pipeline(
+                gpu_state,
+                self.bias.depth_bias_state(),
+                render_pipeline::DepthMode::Standard,
+            );
+            return;
+        }
+
+        Self::prepare_blur_pipelines(gpu_state);
+
+        if gpu_state.pipeline_vendor.has_pipeline(&self.pipeline_id) {
+            return;
+        }
+
+        let layout = gpu_state
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(&self.pipeline_id),
+                bind_group_layouts: &[&light::Light::shadow_pass_bind_group_layout(
+                    &gpu_state.device,
+                )],
+                push_constant_ranges: &[],
+            });
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("shaders/shadow.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                resources::load_shader_sync("shaders/shadow.wgsl", &[])
+                    .unwrap()
+                    .into(),
+            ),
+        };
+
+        gpu_state.pipeline_vendor.create_render_pipeline(
+            &self.pipeline_id,
+            &gpu_state.device,
+            render_pipeline::Properties {
+                vs_main: "vs_main_shadow",
+                fs_main: self.mode.fragment_main(),
+                layout: &layout,
+                color_format: texture::Texture::MOMENTS_FORMAT,
+                depth_format: Some(texture::Texture::DEPTH_FORMAT),
+                depth_bias: self.bias.depth_bias_state(),
+                depth_mode: render_pipeline::DepthMode::Standard,
+                vertex_layouts: &model::Model::vertex_layout(),
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                shader,
+                cull_mode: Some(wgpu::Face::Back),
+                blend: None,
+                pass: render_pipeline::Pass::Shadow,
+            },
+        );
+    }
+
+    fn prepare_blur_pipelines(gpu_state: &mut GpuState) {
+        for (pipeline_id, fs_main) in [
+            (Self::BLUR_HORIZONTAL_PIPELINE_ID, "fs_main_blur_horizontal"),
+            (Self::BLUR_VERTICAL_PIPELINE_ID, "fs_main_blur_vertical"),
+        ] {
+            if gpu_state.pipeline_vendor.has_pipeline(pipeline_id) {
+                continue;
+            }
+
+            let layout = gpu_state
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some(pipeline_id),
+                    bind_group_layouts: &[&Self::blur_bind_group_layout(&gpu_state.device)],
+                    push_constant_ranges: &[],
+                });
+
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("shaders/shadow_blur.wgsl"),
+                source: wgpu::ShaderSource::Wgsl(
+                    resources::load_string_sync("shaders/shadow_blur.wgsl")
+                        .unwrap()
+                        .into(),
+                ),
+            };
+
+            gpu_state.pipeline_vendor.create_render_pipeline(
+                pipeline_id,
+                &gpu_state.device,
+                render_pipeline::Properties {
+                    vs_main: "vs_main_blur",
+                    fs_main: Some(fs_main),
+                    layout: &layout,
+                    color_format: texture::Texture::MOMENTS_FORMAT,
+                    depth_format: None,
+                    depth_bias: wgpu::DepthBiasState::default(),
+                    depth_mode: render_pipeline::DepthMode::Standard,
+                    vertex_layouts: &[],
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    shader,
+                    cull_mode: None,
+                    blend: None,
+                    pass: render_pipeline::Pass::Shadow,
+                },
+            );
+        }
+    }
+
+    fn blur_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+            label: Some("Shadow Blur Bind Group Layout"),
+        })
+    }
+
+    fn create_blur_bind_group(device: &wgpu::Device, source: &texture::Texture) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &Self::blur_bind_group_layout(device),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&source.view),
+            }],
+            label: Some("Shadow Blur Bind Group"),
+        })
+    }
+
+    /// Applies a separable gaussian blur to the moments texture, through a scratch target
+    /// acquired from the GpuState's transient_textures. Has no effect if this shadow map
+    /// doesn't use a variance mode.
+    pub fn blur_moments(&self, gpu_state: &mut GpuState, encoder: &mut wgpu::CommandEncoder) {
+        let moments = match &self.moments {
+            Some(moments) => moments,
+            None => return,
+        };
+
+        let scratch = gpu_state.transient_textures.acquire(
+            &gpu_state.device,
+            &TransientTextureDescriptor {
+                width: self.resolution,
+                height: self.resolution,
+                format: texture::Texture::MOMENTS_FORMAT,
+            },
+        );
+        let blur_scratch = gpu_state.transient_textures.texture(scratch);
+        let blur_scratch_bind_group = Self::create_blur_bind_group(&gpu_state.device, blur_scratch);
+
+        for (pipeline_id, source, destination) in [
+            (
+                Self::BLUR_HORIZONTAL_PIPELINE_ID,
+                &moments.moments_bind_group,
+                blur_scratch,
+            ),
+            (
+                Self::BLUR_VERTICAL_PIPELINE_ID,
+                &blur_scratch_bind_group,
+                &moments.moments,
+            ),
+        ] {
+            let pipeline = match gpu_state.pipeline_vendor.get_pipeline(pipeline_id) {
+                Some(pipeline) => pipeline,
+                None => {
+                    eprintln!(
+                        "No pipeline available to blur shadow map id: {}",
+                        pipeline_id
+                    );
+                    break;
+                }
+            };
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Map Blur Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &destination.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load, // FSQ doesn't need to clear
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, source, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        gpu_state.transient_textures.release(scratch);
+    }
+
+    /// Computes the light-space view-projection matrix used to render and sample this shadow map.
+    /// `camera` is the view directional shadows are fit to, see ShadowFit. Returns None for
+    /// light types which don't support shadows.
+    pub fn view_proj(&self, light: &light::Light, camera: &camera::Camera) -> Option<Mat4> {
+        match light.light_type() {
+            light::LightType::Directional => {
+                // direction for directional lights points towards the light
+                let direction = light.direction().normalize();
+                let up = Self::up_vector(direction);
+                // half-size of the volume across the light, and the depth of the light's eye
+                // above its center
+                let (center, half_size, depth) = match self.fit {
+                    ShadowFit::Fixed => (light.position(), self.range, self.range),
+                    ShadowFit::CameraFrustum { distance } => {
+                        let (center, radius) = Self::view_bounding_sphere(camera, distance);
+                        (center, radius, radius + self.range)
+                    }
+                };
+
+                // snap the center to whole texels across the light, so that world positions
+                // keep falling on the same texels as the volume moves
+                let rotation = Mat4::look_at_rh(Point3::origin(), Point3::origin() - direction, up);
+                let texel_size = 2.0 * half_size / self.resolution as f32;
+                let light_space_center = rotation.transform_point(center);
+                let snapped = Point3::new(
+                    (light_space_center.x / texel_size).round() * texel_size,
+                    (light_space_center.y / texel_size).round() * texel_size,
+                    light_space_center.z,
+                );
+                let center = rotation.invert()?.transform_point(snapped);
+
+                let eye = center + direction * depth;
+                let view = Mat4::look_at_rh(eye, center, up);
+                let projection = cgmath::ortho(
+                    -half_size,
+                    half_size,
+                    -half_size,
+                    half_size,
+                    0.0,
+                    depth + half_size,
+                );
+                Some(camera::OPENGL_TO_WGPU_MATRIX * projection * view)
+            }
+            light::LightType::Spot => {
+                // direction for spot lights points away from the light
+                let direction = light.direction().normalize();
+                let eye = light.position();
+                let view = Mat4::look_at_rh(eye, eye + direction, Self::up_vector(direction));
+                let mut fov_y = light.spot_breadth() * 2.0;
+                if fov_y > deg(170.0) {
+                    fov_y = deg(170.0);
+                }
+                let projection = cgmath::perspective(fov_y, 1.0, 0.1, self.range.max(0.2));
+                Some(camera::OPENGL_TO_WGPU_MATRIX * projection * view)
+            }
+            light::LightType::Ambient | light::LightType::Point => None,
+        }
+    }
+
+    // The smallest sphere enclosing the camera's view out to `distance`, whose radius depends
+    // only on the camera's projection, not its pose
+    fn view_bounding_sphere(camera: &camera::Camera, distance: f32) -> (Point3, f32) {
+        let (z_near, z_far) = camera.depth_range();
+        let far = distance.min(z_far).max(z_near);
+        let forward = -camera.world_rotation().z;
+
+        // squared tangent of the angle between the view axis and the frustum's corner rays
+        let corner = camera.frustum().corners[0] - camera.position();
+        let corner_depth = corner.dot(forward);
+        let tan_squared = corner.magnitude2() / (corner_depth * corner_depth) - 1.0;
+
+        // the sphere through the near and far corners is centered on the view axis, unless it
+        // would lie beyond the far plane, where the far corners alone bound the view
+        let z = 0.5 * (far + z_near) * (1.0 + tan_squared);
+        let (z, radius) = if z >= far {
+            (far, far * tan_squared.sqrt())
+        } else {
+            (z, ((far - z) * (far - z) + far * far * tan_squared).sqrt())
+        };
+        (camera.position() + forward * z, radius)
+    }
+
+    // Picks an up vector which won't be degenerate when looking along `direction`
+    fn up_vector(direction: Vec3) -> Vec3 {
+        if direction.y.abs() > 0.99 {
+            Vec3::unit_z()
+        } else {
+            Vec3::unit_y()
+        }
+    }
+}
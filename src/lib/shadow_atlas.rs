@@ -0,0 +1,213 @@
+use super::{gpu_state::GpuState, render_pipeline, resources, texture, util::*};
+
+/// A square region of a ShadowAtlas, in texels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShadowAtlasRegion {
+    pub x: u32,
+    pub y: u32,
+    pub size: u32,
+}
+
+impl ShadowAtlasRegion {
+    /// The region in the texture coordinates of an atlas `atlas_size` texels square, as
+    /// (x offset, y offset, x scale, y scale)
+    pub fn uv_rect(&self, atlas_size: u32) -> Vec4 {
+        let atlas_size = atlas_size as f32;
+        Vec4::new(
+            self.x as f32 / atlas_size,
+            self.y as f32 / atlas_size,
+            self.size as f32 / atlas_size,
+            self.size as f32 / atlas_size,
+        )
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// A budget of shadow map texels, shared by the scene's shadow casting lights (see
+// scene::Scene::shadow_atlas). Each light's requested resolution is packed into a square atlas
+// of `size` texels as a quadtree of power of two regions, halving requests which don't fit
+// down to `min_resolution`, so adding lights lowers the resolution of the later ones rather
+// than growing shadow map memory. Pcf shadow maps of spot lights are rendered into their
+// regions of one shared ShadowAtlasTexture; other maps keep textures of their own, sized to
+// their regions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShadowAtlas {
+    size: u32,
+    min_resolution: u32,
+}
+
+impl ShadowAtlas {
+    /// `size` and `min_resolution` are rounded up to powers of two, and the minimum limited to
+    /// the atlas size.
+    pub fn new(size: u32, min_resolution: u32) -> Self {
+        let size = size.max(1).next_power_of_two();
+        Self {
+            size,
+            min_resolution: min_resolution.max(1).next_power_of_two().min(size),
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn min_resolution(&self) -> u32 {
+        self.min_resolution
+    }
+
+    /// Packs a square region for each of `requests` (resolutions in texels) in order, so
+    /// earlier requests take priority. Each region is the largest power of two no greater
+    /// than the request which still fits, and no smaller than min_resolution; requests which
+    /// don't fit at the minimum get None.
+    pub fn allocate(&self, requests: &[u32]) -> Vec<Option<ShadowAtlasRegion>> {
+        // free regions of each size, indexed by log2 of the atlas size over the region size
+        let levels = (self.size / self.min_resolution).trailing_zeros() as usize + 1;
+        let mut free: Vec<Vec<ShadowAtlasRegion>> = vec![Vec::new(); levels];
+        free[0].push(ShadowAtlasRegion {
+            x: 0,
+            y: 0,
+            size: self.size,
+        });
+
+        requests
+            .iter()
+            .map(|request| {
+                let size = if request.is_power_of_two() {
+                    *request
+                } else {
+                    request.next_power_of_two() / 2
+                };
+                let level = (self.size / size.clamp(self.min_resolution, self.size))
+                    .trailing_zeros() as usize;
+                (level..levels).find_map(|level| Self::take(&mut free, level))
+            })
+            .collect()
+    }
+
+    // Takes a free region at `level`, splitting a larger one if there's none
+    fn take(free: &mut [Vec<ShadowAtlasRegion>], level: usize) -> Option<ShadowAtlasRegion> {
+        if let Some(region) = free[level].pop() {
+            return Some(region);
+        }
+        if level == 0 {
+            return None;
+        }
+
+        let parent = Self::take(free, level - 1)?;
+        let size = parent.size / 2;
+        // the other three quarters stay free, the first is taken
+        for (dx, dy) in [(1, 1), (0, 1), (1, 0)] {
+            free[level].push(ShadowAtlasRegion {
+                x: parent.x + dx * size,
+                y: parent.y + dy * size,
+                size,
+            });
+        }
+        Some(ShadowAtlasRegion {
+            x: parent.x,
+            y: parent.y,
+            size,
+        })
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// The depth texture a ShadowAtlas packs shadow maps into, so the lit pass binds one shadow
+// texture for all of them. Each map renders into its region through the viewport, after
+// clearing just that region, so maps which aren't due keep their last render.
+pub struct ShadowAtlasTexture {
+    size: u32,
+    pub texture: texture::Texture,
+}
+
+impl ShadowAtlasTexture {
+    const CLEAR_PIPELINE_ID: &'static str = "shadow_atlas_clear";
+
+    pub fn new(device: &wgpu::Device, size: u32) -> Self {
+        Self {
+            size,
+            texture: texture::Texture::create_shadow_texture(device, size, "Shadow Atlas"),
+        }
+    }
+
+    /// Width and height of the atlas in texels
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn prepare_pipeline(gpu_state: &mut GpuState) {
+        if gpu_state
+            .pipeline_vendor
+            .has_pipeline(Self::CLEAR_PIPELINE_ID)
+        {
+            return;
+        }
+
+        let layout = gpu_state
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(Self::CLEAR_PIPELINE_ID),
+                bind_group_layouts: &[],
+                push_constant_ranges: &[],
+            });
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("shaders/shadow.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                resources::load_shader_sync("shaders/shadow.wgsl", &[])
+                    .unwrap()
+                    .into(),
+            ),
+        };
+
+        gpu_state.pipeline_vendor.create_render_pipeline(
+            Self::CLEAR_PIPELINE_ID,
+            &gpu_state.device,
+            render_pipeline::Properties {
+                vs_main: "vs_main_atlas_clear",
+                fs_main: None,
+                layout: &layout,
+                color_format: texture::Texture::DEPTH_FORMAT,
+                depth_format: Some(texture::Texture::DEPTH_FORMAT),
+                depth_bias: wgpu::DepthBiasState::default(),
+                // the clear triangle lies on the far plane at depth 1, and reversed depth's
+                // GreaterEqual test passes it over whatever the region holds
+                depth_mode: render_pipeline::DepthMode::Reversed,
+                vertex_layouts: &[],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                shader,
+                cull_mode: None,
+                blend: None,
+                pass: render_pipeline::Pass::Shadow,
+            },
+        );
+    }
+
+    /// Restricts `render_pass` to `region` and clears its depth, ready to render the shadow
+    /// map allocated to it.
+    pub fn begin_region<'a>(
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipeline_vendor: &'a render_pipeline::RenderPipelineVendor,
+        region: ShadowAtlasRegion,
+    ) {
+        render_pass.set_viewport(
+            region.x as f32,
+            region.y as f32,
+            region.size as f32,
+            region.size as f32,
+            0.0,
+            1.0,
+        );
+        render_pass.set_scissor_rect(region.x, region.y, region.size, region.size);
+
+        match pipeline_vendor.get_pipeline(Self::CLEAR_PIPELINE_ID) {
+            Some(pipeline) => {
+                render_pass.set_pipeline(pipeline);
+                render_pass.draw(0..3, 0..1);
+            }
+            None => eprintln!("No pipeline available to clear shadow atlas regions"),
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use anyhow::{anyhow, Result};
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use super::{gpu_state::GpuState, model, resources, util::*};
+
+// must match the workgroup size of cs_main_skinning in skinning.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The joints influencing a vertex and their weights. Weights needn't sum to one, they're
+/// normalized when skinning; a vertex with no weight stays in its rest pose.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SkinWeights {
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for SkinWeights {}
+unsafe impl bytemuck::Zeroable for SkinWeights {}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct SkinningUniformData {
+    // x: vertex count
+    counts: [u32; 4],
+}
+
+unsafe impl bytemuck::Pod for SkinningUniformData {}
+unsafe impl bytemuck::Zeroable for SkinningUniformData {}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Skins a mesh on the GPU. A compute pass blends the mesh's rest pose vertices by their joints'
+// matrices and writes the results into the mesh's own vertex buffer, so every pass drawing
+// the mesh (ambient, lit, shadow, depth prepass) sees the skinned geometry through the
+// ordinary pipelines, without skinning code in their vertex shaders.
+//
+// The mesh's retained MeshData (see mesh_builder::MeshBuilder::build) is its rest pose, and
+// stays so. Scene keeps the mesh's bounds enclosing the current pose, see bounds.
+pub struct Skin {
+    model_id: usize,
+    mesh_name: String,
+    joint_count: usize,
+    vertex_count: u32,
+    // the last matrices set, for placing sockets on joints and bounding the pose
+    joint_matrices: Vec<Mat4>,
+    // the rest pose bounds of the vertices each joint influences, None if it influences none
+    joint_bounds: Vec<Option<model::Bounds>>,
+    // the bounds of the vertices no joint influences, which stay in their rest pose
+    unweighted_bounds: Option<model::Bounds>,
+    joint_matrices_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl Skin {
+    /// Prepares `mesh`, which must have retained its data, for skinning by `joint_count`
+    /// joints. `model_id` is the key in Scene::models of the model the mesh is added to.
+    /// Joints start at identity, leaving the mesh in its rest pose.
+    pub fn new(
+        gpu_state: &GpuState,
+        model_id: usize,
+        mesh: &mut model::Mesh,
+        weights: &[SkinWeights],
+        joint_count: usize,
+    ) -> Result<Self> {
+        let device = &gpu_state.device;
+        let data = mesh.data.as_ref().ok_or_else(|| {
+            anyhow!(
+                "Mesh \"{}\" must retain its data to be skinned, see MeshBuilder::build",
+                mesh.name
+            )
+        })?;
+        if weights.len() != data.vertices.len() {
+            return Err(anyhow!(
+                "Mesh \"{}\" has {} vertices but {} skin weights",
+                mesh.name,
+                data.vertices.len(),
+                weights.len()
+            ));
+        }
+        if joint_count == 0 {
+            return Err(anyhow!("Mesh \"{}\" skinned by no joints", mesh.name));
+        }
+        if let Some(bad) = weights.iter().find(|w| {
+            w.joints
+                .iter()
+                .zip(w.weights.iter())
+                .any(|(joint, weight)| *weight != 0.0 && *joint as usize >= joint_count)
+        }) {
+            return Err(anyhow!(
+                "Mesh \"{}\" has skin weights {:?} referencing joints beyond its {}",
+                mesh.name,
+                bad,
+                joint_count
+            ));
+        }
+
+        let mut joint_bounds = vec![None; joint_count];
+        for (joint, bounds) in joint_bounds.iter_mut().enumerate() {
+            *bounds = model::Bounds::from_points(
+                data.vertices
+                    .iter()
+                    .zip(weights.iter())
+                    .filter(|(_, w)| {
+                        w.joints
+                            .iter()
+                            .zip(w.weights.iter())
+                            .any(|(j, weight)| *j as usize == joint && *weight != 0.0)
+                    })
+                    .map(|(vertex, _)| vertex.position),
+            );
+        }
+        let unweighted_bounds = model::Bounds::from_points(
+            data.vertices
+                .iter()
+                .zip(weights.iter())
+                .filter(|(_, w)| w.weights.iter().all(|weight| *weight == 0.0))
+                .map(|(vertex, _)| vertex.position),
+        );
+
+        // storage buffers can't be empty
+        let rest_vertices = if data.vertices.is_empty() {
+            vec![model::ModelVertex {
+                position: Point3::origin(),
+                tex_coords: Vec2::zero(),
+                normal: Vec3::zero(),
+                tangent: Vec3::zero(),
+                bitangent: Vec3::zero(),
+            }]
+        } else {
+            data.vertices.clone()
+        };
+        let weights = if weights.is_empty() {
+            vec![SkinWeights::default()]
+        } else {
+            weights.to_vec()
+        };
+        let vertex_count = data.vertices.len() as u32;
+
+        let rest_vertices_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Rest Vertex Buffer", mesh.name)),
+            contents: bytemuck::cast_slice(&rest_vertices),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let weights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Skin Weights Buffer", mesh.name)),
+            contents: bytemuck::cast_slice(&weights),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let identity: [[f32; 4]; 4] = Mat4::identity().into();
+        let joint_matrices_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Joint Matrices Buffer", mesh.name)),
+            contents: bytemuck::cast_slice(&vec![identity; joint_count]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Skinning Uniform Buffer", mesh.name)),
+            contents: bytemuck::cast_slice(&[SkinningUniformData {
+                counts: [vertex_count, 0, 0, 0],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        // the skinned vertices replace the mesh's vertex buffer, which must be writable
+        // from the compute pass
+        mesh.vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Skinned Vertex Buffer", mesh.name)),
+            contents: bytemuck::cast_slice(&rest_vertices),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+        });
+
+        let bind_group_layout = Self::bind_group_layout(device);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: rest_vertices_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: weights_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: joint_matrices_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: mesh.vertex_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Skinning Bind Group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("skinning"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shaders/skinning.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                resources::load_string_sync("shaders/skinning.wgsl")?.into(),
+            ),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("ComputePipeline: skinning"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main_skinning",
+        });
+
+        Ok(Self {
+            model_id,
+            mesh_name: mesh.name.clone(),
+            joint_count,
+            vertex_count,
+            joint_matrices: vec![Mat4::identity(); joint_count],
+            joint_bounds,
+            unweighted_bounds,
+            joint_matrices_buffer,
+            bind_group,
+            pipeline,
+        })
+    }
+
+    pub fn model_id(&self) -> usize {
+        self.model_id
+    }
+
+    /// The name of the skinned mesh, see model::Model::set_mesh_bounds
+    pub fn mesh_name(&self) -> &str {
+        &self.mesh_name
+    }
+
+    pub fn joint_count(&self) -> usize {
+        self.joint_count
+    }
+
+    /// Bounds in the mesh's model space enclosing its vertices as posed by the last joint
+    /// matrices set, None if it has none. Each joint's rest pose bounds are carried by its
+    /// matrix; as skinned vertices are blends of their positions carried by each of their
+    /// joints, these enclose them, if loosely when joints rotate far from their rest pose.
+    pub fn bounds(&self) -> Option<model::Bounds> {
+        model::Bounds::from_points(
+            self.joint_bounds
+                .iter()
+                .zip(self.joint_matrices.iter())
+                .filter_map(|(bounds, matrix)| Some(bounds.as_ref()?.transformed(matrix)))
+                .chain(self.unweighted_bounds)
+                .flat_map(|bounds| bounds.corners()),
+        )
+    }
+
+    /// Sets each joint's skinning matrix, i.e. its current transform times the inverse of its
+    /// rest transform, in the mesh's model space. Extra matrices are ignored.
+    pub fn set_joint_matrices(&mut self, queue: &wgpu::Queue, matrices: &[Mat4]) {
+        if matrices.len() < self.joint_count {
+            eprintln!(
+                "Skin given {} joint matrices for {} joints, the rest are unchanged",
+                matrices.len(),
+                self.joint_count
+            );
+        }
+        for (joint_matrix, matrix) in self.joint_matrices.iter_mut().zip(matrices.iter()) {
+            *joint_matrix = *matrix;
+        }
+        let matrices = matrices
+            .iter()
+            .take(self.joint_count)
+            .map(|matrix| (*matrix).into())
+            .collect::<Vec<[[f32; 4]; 4]>>();
+        queue.write_buffer(
+            &self.joint_matrices_buffer,
+            0,
+            bytemuck::cast_slice(&matrices),
+        );
+    }
+
+    /// The skinning matrix last set for `joint`, see set_joint_matrices
+    pub fn joint_matrix(&self, joint: usize) -> Option<Mat4> {
+        self.joint_matrices.get(joint).copied()
+    }
+
+    /// Writes the mesh's skinned vertices; must be encoded before the passes drawing it,
+    /// including shadow passes.
+    pub fn skin(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Skinning Pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.vertex_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // rest pose vertices
+                storage(1, true),
+                // skin weights
+                storage(2, true),
+                // joint matrices
+                storage(3, true),
+                // the mesh's vertex buffer
+                storage(4, false),
+            ],
+            label: Some("Skinning Bind Group Layout"),
+        })
+    }
+}
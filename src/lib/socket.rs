@@ -0,0 +1,89 @@
+use cgmath::prelude::*;
+
+use super::{model, skinning, util::*};
+
+/// What a socket follows on its model
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SocketAnchor {
+    // the model's instance, e.g. a lantern hook on a rigid cart
+    Node,
+    // a joint of the model's skin, e.g. a hand, see skinning::Skin
+    Joint(usize),
+}
+
+/// A named attachment point on a model, which other models' instances may be parented to;
+/// see model::Model::add_socket and Attachment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Socket {
+    pub anchor: SocketAnchor,
+    // the socket's offset in the model's space; for joint sockets, in the skin's rest pose
+    pub position: Point3,
+    pub rotation: Quat,
+}
+
+impl Socket {
+    pub fn new(anchor: SocketAnchor) -> Self {
+        Self {
+            anchor,
+            position: Point3::origin(),
+            rotation: Quat::one(),
+        }
+    }
+
+    pub fn with_offset<P, R>(mut self, position: P, rotation: R) -> Self
+    where
+        P: Into<Point3>,
+        R: Into<Quat>,
+    {
+        self.position = position.into();
+        self.rotation = rotation.into();
+        self
+    }
+
+    /// The socket's transform in its model's space. Joint sockets are moved by `skin`'s
+    /// current joint matrix, or stay in their rest pose without one.
+    pub fn transform(&self, skin: Option<&skinning::Skin>) -> Mat4 {
+        let offset = Mat4::from_translation(self.position.to_vec()) * Mat4::from(self.rotation);
+        match self.anchor {
+            SocketAnchor::Node => offset,
+            SocketAnchor::Joint(joint) => skin
+                .and_then(|skin| skin.joint_matrix(joint))
+                .map_or(offset, |joint_matrix| joint_matrix * offset),
+        }
+    }
+}
+
+/// Parents an instance of one model to a socket on an instance of another, so it follows the
+/// socket as the parent moves and animates, e.g. a weapon held in a skinned character's hand.
+/// The child instance's position, rotation and scale are replaced by the socket's each frame,
+/// see scene::Scene::attachments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attachment {
+    pub parent_model_id: usize,
+    pub parent_instance: usize,
+    pub socket: String,
+    pub model_id: usize,
+    pub instance: usize,
+}
+
+/// Places `instance` at `transform`, which must be a rotation, uniform scale and translation
+/// as sockets and instances compose to, keeping its time offset, phase and user data.
+pub fn place_instance(instance: &model::Instance, transform: Mat4) -> model::Instance {
+    let basis = Mat3::from_cols(
+        transform.x.truncate(),
+        transform.y.truncate(),
+        transform.z.truncate(),
+    );
+    let scale = basis.x.magnitude();
+    let rotation = if scale > 0.0 {
+        Quat::from(basis / scale).normalize()
+    } else {
+        Quat::one()
+    };
+
+    let mut placed = *instance;
+    placed.set_position(Point3::from_vec(transform.w.truncate()));
+    placed.set_rotation(rotation);
+    placed.set_scale(scale);
+    placed
+}
@@ -0,0 +1,279 @@
+use cgmath::prelude::*;
+
+use super::{mesh_builder, model, util::*};
+
+/// A curve through 3D space, parameterized from 0 at its start to segment_count() at its end.
+#[derive(Clone, Debug)]
+pub enum Spline {
+    // passes through every point; the first and last points are the curve's ends
+    CatmullRom(Vec<Point3>),
+    // cubic segments sharing end points: start, control, control, end/start, control, ...
+    // so 3n + 1 points make n segments
+    Bezier(Vec<Point3>),
+}
+
+impl Spline {
+    pub fn segment_count(&self) -> usize {
+        match self {
+            Spline::CatmullRom(points) => points.len().saturating_sub(1),
+            Spline::Bezier(points) => points.len().saturating_sub(1) / 3,
+        }
+    }
+
+    // the segment containing t, and t's offset within it
+    fn segment(&self, t: f32) -> (usize, f32) {
+        let last = self.segment_count().max(1) - 1;
+        let t = t.max(0.0);
+        let segment = (t.floor() as usize).min(last);
+        (segment, t - segment as f32)
+    }
+
+    // the four points weighting a segment; Catmull-Rom ends are extended by reflection
+    fn control_points(&self, segment: usize) -> [Point3; 4] {
+        match self {
+            Spline::CatmullRom(points) => {
+                let p1 = points[segment];
+                let p2 = points[segment + 1];
+                let p0 = if segment > 0 {
+                    points[segment - 1]
+                } else {
+                    p1 + (p1 - p2)
+                };
+                let p3 = if segment + 2 < points.len() {
+                    points[segment + 2]
+                } else {
+                    p2 + (p2 - p1)
+                };
+                [p0, p1, p2, p3]
+            }
+            Spline::Bezier(points) => {
+                let i = segment * 3;
+                [points[i], points[i + 1], points[i + 2], points[i + 3]]
+            }
+        }
+    }
+
+    pub fn point(&self, t: f32) -> Point3 {
+        let (segment, t) = self.segment(t);
+        let [p0, p1, p2, p3] = self.control_points(segment).map(|p| p.to_vec());
+        let (t2, t3) = (t * t, t * t * t);
+        let p = match self {
+            Spline::CatmullRom(_) => {
+                (p1 * 2.0
+                    + (p2 - p0) * t
+                    + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+                    + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+                    * 0.5
+            }
+            Spline::Bezier(_) => {
+                let s = 1.0 - t;
+                p0 * (s * s * s) + p1 * (3.0 * s * s * t) + p2 * (3.0 * s * t2) + p3 * t3
+            }
+        };
+        Point3::from_vec(p)
+    }
+
+    /// The curve's derivative with respect to t; not normalized.
+    pub fn derivative(&self, t: f32) -> Vec3 {
+        let (segment, t) = self.segment(t);
+        let [p0, p1, p2, p3] = self.control_points(segment).map(|p| p.to_vec());
+        match self {
+            Spline::CatmullRom(_) => {
+                ((p2 - p0)
+                    + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * (2.0 * t)
+                    + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * (3.0 * t * t))
+                    * 0.5
+            }
+            Spline::Bezier(_) => {
+                let s = 1.0 - t;
+                (p1 - p0) * (3.0 * s * s) + (p2 - p1) * (6.0 * s * t) + (p3 - p2) * (3.0 * t * t)
+            }
+        }
+    }
+}
+
+/// A flat strip of `width` centered on the spline, facing up, e.g. for roads and rivers.
+pub fn flat_profile(width: f32) -> Vec<Vec2> {
+    vec![Vec2::new(-width / 2.0, 0.0), Vec2::new(width / 2.0, 0.0)]
+}
+
+/// A closed circle around the spline facing outward, e.g. for pipes.
+pub fn circle_profile(radius: f32, segments: u32) -> Vec<Vec2> {
+    let segments = segments.max(3);
+    (0..=segments)
+        .map(|i| {
+            // clockwise, so the surface faces outward; the last point closes the circle
+            let angle = -std::f32::consts::TAU * (i % segments) as f32 / segments as f32;
+            Vec2::new(angle.cos(), angle.sin()) * radius
+        })
+        .collect()
+}
+
+pub struct SplineMeshDescriptor<'a> {
+    pub name: &'a str,
+    pub spline: &'a Spline,
+    // the cross section extruded along the spline, as (x, y) with x to the spline's right and
+    // y up. Each edge faces to its left as the profile is traversed, so a left to right flat
+    // profile faces up and a clockwise closed one faces outward. Repeat a point for a hard
+    // edge, and repeat the first point last to close the profile.
+    pub profile: &'a [Vec2],
+    pub samples_per_segment: u32,
+    // the profile's up is kept as close to this as the spline's direction allows
+    pub up: Vec3,
+    // world units along the spline spanned by the [0,1] range of the v texture coordinate;
+    // u spans the profile once
+    pub texture_span: f32,
+    pub material: usize,
+}
+
+impl<'a> SplineMeshDescriptor<'a> {
+    pub fn new(name: &'a str, spline: &'a Spline, profile: &'a [Vec2]) -> Self {
+        Self {
+            name,
+            spline,
+            profile,
+            samples_per_segment: 16,
+            up: Vec3::unit_y(),
+            texture_span: 1.0,
+            material: 0,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Extrudes the descriptor's profile along its spline, e.g. for roads, pipes and rivers.
+pub fn create_mesh(device: &wgpu::Device, descriptor: &SplineMeshDescriptor) -> model::Mesh {
+    let profile = descriptor.profile;
+    let mut builder = mesh_builder::MeshBuilder::new(descriptor.name);
+    builder.set_material(descriptor.material);
+    if descriptor.spline.segment_count() == 0 || profile.len() < 2 {
+        eprintln!(
+            "Spline mesh \"{}\" needs at least one spline segment and two profile points",
+            descriptor.name
+        );
+    } else {
+        extrude(descriptor, &mut builder);
+    }
+
+    builder
+        .build(device, false)
+        .expect("spline mesh indices are in range")
+}
+
+fn extrude(descriptor: &SplineMeshDescriptor, builder: &mut mesh_builder::MeshBuilder) {
+    let spline = descriptor.spline;
+    let profile = descriptor.profile;
+    let closed = profile.first() == profile.last();
+
+    // the profile's per point normals in its own plane, and u coordinates by length
+    let edge_normal = |a: Vec2, b: Vec2| {
+        let d = b - a;
+        Vec2::new(-d.y, d.x)
+    };
+    let last = profile.len() - 1;
+    let profile_normals = (0..profile.len())
+        .map(|j| {
+            let before = if j > 0 {
+                Some((profile[j - 1], profile[j]))
+            } else if closed {
+                Some((profile[last - 1], profile[last]))
+            } else {
+                None
+            };
+            let after = if j < last {
+                Some((profile[j], profile[j + 1]))
+            } else if closed {
+                Some((profile[0], profile[1]))
+            } else {
+                None
+            };
+            // zero length edges (repeated points) don't contribute, leaving a hard edge
+            let n = [before, after]
+                .iter()
+                .flatten()
+                .map(|(a, b)| edge_normal(*a, *b))
+                .filter(|n| n.magnitude2() > 0.0)
+                .fold(Vec2::zero(), |total, n| total + n.normalize());
+            if n.magnitude2() > 0.0 {
+                n.normalize()
+            } else {
+                Vec2::unit_y()
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut profile_u = vec![0.0; profile.len()];
+    for j in 1..profile.len() {
+        profile_u[j] = profile_u[j - 1] + (profile[j] - profile[j - 1]).magnitude();
+    }
+    let profile_length = profile_u[last].max(f32::EPSILON);
+
+    let samples = spline.segment_count() as u32 * descriptor.samples_per_segment.max(1);
+    let mut distance = 0.0;
+    let mut previous: Option<(Point3, Vec3)> = None;
+    for i in 0..=samples {
+        let t = i as f32 * spline.segment_count() as f32 / samples as f32;
+        let center = spline.point(t);
+        let forward = spline.derivative(t).normalize();
+
+        // a frame as upright as the spline allows, falling back to the previous sample's
+        // right where the spline runs parallel to up
+        let right = forward.cross(descriptor.up);
+        let right = if right.magnitude2() > 1e-8 {
+            right.normalize()
+        } else {
+            previous.map_or_else(
+                || forward.cross(Vec3::unit_x()).normalize(),
+                |(_, right)| right,
+            )
+        };
+        let up = right.cross(forward);
+
+        if let Some((previous_center, _)) = previous {
+            distance += (center - previous_center).magnitude();
+        }
+        previous = Some((center, right));
+
+        for j in 0..profile.len() {
+            let p = profile[j];
+            let n = profile_normals[j];
+            // the direction of increasing u, along the profile
+            let along = if j < last {
+                profile[j + 1] - p
+            } else {
+                p - profile[j - 1]
+            };
+            // orthogonalized against the (smoothed) normal
+            let normal = right * n.x + up * n.y;
+            let tangent = right * along.x + up * along.y;
+            let tangent = tangent - normal * normal.dot(tangent);
+            let tangent = if tangent.magnitude2() > 1e-12 {
+                tangent.normalize()
+            } else {
+                right
+            };
+
+            builder.push_vertex(model::ModelVertex {
+                position: center + right * p.x + up * p.y,
+                tex_coords: Vec2::new(
+                    profile_u[j] / profile_length,
+                    distance / descriptor.texture_span,
+                ),
+                normal,
+                tangent,
+                // matching resources::load_model, the bitangent points toward decreasing v
+                bitangent: -forward,
+            });
+        }
+    }
+
+    let row = profile.len() as u32;
+    for i in 0..samples {
+        for j in 0..row - 1 {
+            let a = i * row + j;
+            let c = a + row;
+            builder.push_triangle(a, a + 1, c);
+            builder.push_triangle(a + 1, c + 1, c);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use cgmath::prelude::*;
+
+use super::{
+    mesh_builder::MeshBuilder,
+    model::{self, MeshData, ModelVertex},
+    util::*,
+};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Merges static meshes into one mesh per material, each transformed into place on the CPU, so a
+// scene assembled from many unique props draws in a few calls rather than one per prop. Unlike
+// instancing the props needn't share geometry, but once batched they can't move, and are
+// culled as a whole by the model's bounds. Add meshes with their transforms, then build the
+// batches for a model using the materials the meshes referenced; add the model with a single
+// identity instance.
+pub struct StaticBatcher {
+    name: String,
+    // keyed by material, so batches are built in material order
+    batches: BTreeMap<usize, MeshBuilder>,
+}
+
+impl StaticBatcher {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            batches: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `mesh`, which must have retained its data (see mesh_builder::MeshBuilder::build
+    /// and resources::load_model), to its material's batch transformed by `transform`.
+    pub fn add_mesh(&mut self, mesh: &model::Mesh, transform: &Mat4) -> Result<()> {
+        let data = mesh.data.as_ref().ok_or_else(|| {
+            anyhow!(
+                "Mesh \"{}\" didn't retain its data, so can't be batched",
+                mesh.name
+            )
+        })?;
+        self.add(data, mesh.material, transform);
+        Ok(())
+    }
+
+    /// Adds the geometry to the batch of `material`, transformed by `transform`.
+    pub fn add(&mut self, data: &MeshData, material: usize, transform: &Mat4) {
+        let name = &self.name;
+        let batch = self.batches.entry(material).or_insert_with(|| {
+            let mut batch = MeshBuilder::new(&format!("{} Material {}", name, material));
+            batch.set_material(material);
+            batch
+        });
+
+        let basis = Mat3::from_cols(
+            transform.x.truncate(),
+            transform.y.truncate(),
+            transform.z.truncate(),
+        );
+        let normal_matrix = basis
+            .invert()
+            .map(|inverse| inverse.transpose())
+            .unwrap_or(basis);
+        let transform_direction = |matrix: &Mat3, direction: Vec3| {
+            let direction = matrix * direction;
+            if direction.magnitude2() > 0.0 {
+                direction.normalize()
+            } else {
+                direction
+            }
+        };
+
+        let first_vertex = batch.vertex_count();
+        for vertex in data.vertices.iter() {
+            batch.push_vertex(ModelVertex {
+                position: transform.transform_point(vertex.position),
+                tex_coords: vertex.tex_coords,
+                normal: transform_direction(&normal_matrix, vertex.normal),
+                // tangents follow the surface, as positions do
+                tangent: transform_direction(&basis, vertex.tangent),
+                bitangent: transform_direction(&basis, vertex.bitangent),
+            });
+        }
+
+        // a mirroring transform turns front faces around
+        let mirrored = basis.determinant() < 0.0;
+        for triangle in data.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| first_vertex + triangle[i]);
+            if mirrored {
+                batch.push_triangle(a, c, b);
+            } else {
+                batch.push_triangle(a, b, c);
+            }
+        }
+    }
+
+    /// The number of meshes build will make, one per material added
+    pub fn batch_count(&self) -> usize {
+        self.batches.len()
+    }
+
+    /// Uploads a mesh per material, keeping the merged geometry on them as `data` if
+    /// `retain_data` is set. Fails if any batch's indices are invalid, see
+    /// mesh_builder::MeshBuilder::validate.
+    pub fn build(self, device: &wgpu::Device, retain_data: bool) -> Result<Vec<model::Mesh>> {
+        self.batches
+            .into_values()
+            .map(|batch| batch.build(device, retain_data))
+            .collect()
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use cgmath::prelude::*;
+
+use super::{gpu_state::GpuState, mesh_builder, model, util::*};
+
+pub struct TerrainDescriptor {
+    // world units along each side of a chunk
+    pub chunk_size: f32,
+    // quads along each side of a chunk at the highest level of detail
+    pub chunk_resolution: u32,
+    // each level of detail halves the chunk resolution of the previous one
+    pub lod_count: u32,
+    // distance from the camera at which each successive level of detail begins
+    pub lod_distance: f32,
+    // chunks whose centers are within this distance of the camera are loaded
+    pub load_radius: f32,
+    // limits the chunks built per update, so moving quickly doesn't stall a frame
+    pub max_builds_per_update: usize,
+    // how far below the surface chunk edge skirts extend, hiding cracks between levels of detail
+    pub skirt_depth: f32,
+    // world units spanned by the [0,1] texture coordinate range
+    pub texture_span: f32,
+}
+
+impl Default for TerrainDescriptor {
+    fn default() -> Self {
+        Self {
+            chunk_size: 32.0,
+            chunk_resolution: 32,
+            lod_count: 4,
+            lod_distance: 64.0,
+            load_radius: 256.0,
+            max_builds_per_update: 4,
+            skirt_depth: 2.0,
+            texture_span: 32.0,
+        }
+    }
+}
+
+type ChunkCoord = (i32, i32);
+
+fn chunk_mesh_name(coord: ChunkCoord) -> String {
+    format!("terrain_chunk_{}_{}", coord.0, coord.1)
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Streams a heightfield terrain into a Model as chunk meshes, loading chunks near the camera
+// at a level of detail chosen by their distance, and unloading them once out of range. All
+// chunks share the model's single material (e.g. a terrain material, see model::TerrainLayers)
+// so layer textures are loaded once however many chunks are resident.
+pub struct TerrainManager {
+    descriptor: TerrainDescriptor,
+    height: Box<dyn Fn(f32, f32) -> f32>,
+    // the id of the model in Scene::models which chunks are streamed into
+    model_id: usize,
+    // level of detail of each loaded chunk
+    chunks: HashMap<ChunkCoord, u32>,
+}
+
+impl TerrainManager {
+    /// `height` returns the terrain's height at a world (x, z).
+    pub fn new<H>(descriptor: TerrainDescriptor, model_id: usize, height: H) -> Self
+    where
+        H: 'static + Fn(f32, f32) -> f32,
+    {
+        Self {
+            descriptor,
+            height: Box::new(height),
+            model_id,
+            chunks: HashMap::new(),
+        }
+    }
+
+    /// Creates the (initially empty) model chunks are streamed into. Insert it into
+    /// Scene::models under this manager's model_id.
+    pub fn create_model(&self, gpu_state: &GpuState, material: model::Material) -> model::Model {
+        model::Model::new(
+            &gpu_state.device,
+            vec![],
+            vec![material],
+            &[model::Instance::new(Point3::origin(), Quat::one())],
+        )
+    }
+
+    pub fn model_id(&self) -> usize {
+        self.model_id
+    }
+
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        (self.height)(x, z)
+    }
+
+    pub fn loaded_chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Loads, unloads and changes the level of detail of chunks around `camera_position`.
+    /// `lod_bias` scales the descriptor's lod_distance, see scene::Scene::lod_bias.
+    pub fn update(
+        &mut self,
+        gpu_state: &GpuState,
+        camera_position: Point3,
+        model: &mut model::Model,
+        lod_bias: f32,
+    ) {
+        let chunk_size = self.descriptor.chunk_size;
+        let lod_distance = (self.descriptor.lod_distance * lod_bias).max(f32::EPSILON);
+        let load_radius = self.descriptor.load_radius;
+        let camera = Vec2::new(camera_position.x, camera_position.z);
+        let chunk_center = |coord: ChunkCoord| {
+            Vec2::new(
+                (coord.0 as f32 + 0.5) * chunk_size,
+                (coord.1 as f32 + 0.5) * chunk_size,
+            )
+        };
+
+        // unload chunks which are out of range
+        let unloaded = self
+            .chunks
+            .keys()
+            .filter(|coord| (chunk_center(**coord) - camera).magnitude() > load_radius)
+            .copied()
+            .collect::<Vec<_>>();
+        for coord in unloaded {
+            model.remove_mesh(&chunk_mesh_name(coord));
+            self.chunks.remove(&coord);
+        }
+
+        // find chunks in range which are missing or at the wrong level of detail
+        let reach = (load_radius / chunk_size).ceil() as i32 + 1;
+        let center = (
+            (camera.x / chunk_size).floor() as i32,
+            (camera.y / chunk_size).floor() as i32,
+        );
+        let mut pending = Vec::new();
+        for z in (center.1 - reach)..=(center.1 + reach) {
+            for x in (center.0 - reach)..=(center.0 + reach) {
+                let distance = (chunk_center((x, z)) - camera).magnitude();
+                if distance > load_radius {
+                    continue;
+                }
+                let lod =
+                    ((distance / lod_distance) as u32).min(self.descriptor.lod_count.max(1) - 1);
+                if self.chunks.get(&(x, z)) != Some(&lod) {
+                    pending.push((distance, (x, z), lod));
+                }
+            }
+        }
+
+        // build the nearest first
+        pending.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (_, coord, lod) in pending
+            .into_iter()
+            .take(self.descriptor.max_builds_per_update)
+        {
+            let mesh = self.build_chunk(gpu_state, coord, lod);
+            model.remove_mesh(&mesh.name);
+            model.add_mesh(mesh);
+            self.chunks.insert(coord, lod);
+        }
+    }
+
+    fn build_chunk(&self, gpu_state: &GpuState, coord: ChunkCoord, lod: u32) -> model::Mesh {
+        let descriptor = &self.descriptor;
+        let resolution = (descriptor.chunk_resolution >> lod).max(1);
+        let step = descriptor.chunk_size / resolution as f32;
+        let origin = Vec2::new(
+            coord.0 as f32 * descriptor.chunk_size,
+            coord.1 as f32 * descriptor.chunk_size,
+        );
+        let row = resolution + 1;
+
+        let mut builder = mesh_builder::MeshBuilder::with_capacity(
+            &chunk_mesh_name(coord),
+            (row * row + 4 * row) as usize,
+            (resolution * resolution * 6 + 4 * resolution * 6) as usize,
+        );
+        for j in 0..row {
+            for i in 0..row {
+                let x = origin.x + i as f32 * step;
+                let z = origin.y + j as f32 * step;
+                builder.push_vertex(self.vertex(x, z, step));
+            }
+        }
+
+        for j in 0..resolution {
+            for i in 0..resolution {
+                let a = j * row + i;
+                let b = a + row;
+                builder.push_triangle(a, b, a + 1);
+                builder.push_triangle(a + 1, b, b + 1);
+            }
+        }
+
+        // skirts hang from each edge, which are traversed so that their faces point outward:
+        // -z with i ascending, +z with i descending, -x with j descending, +x with j ascending
+        let last = resolution;
+        let edges: [Vec<u32>; 4] = [
+            (0..row).collect(),
+            (0..row).rev().map(|i| last * row + i).collect(),
+            (0..row).rev().map(|j| j * row).collect(),
+            (0..row).map(|j| j * row + last).collect(),
+        ];
+        for edge in edges {
+            let base = builder.vertex_count();
+            for index in edge.iter() {
+                let mut skirt = builder.vertices()[*index as usize];
+                skirt.position.y -= descriptor.skirt_depth;
+                builder.push_vertex(skirt);
+            }
+            for k in 0..edge.len() as u32 - 1 {
+                let (top, next_top) = (edge[k as usize], edge[k as usize + 1]);
+                let (bottom, next_bottom) = (base + k, base + k + 1);
+                builder.push_triangle(top, next_top, bottom);
+                builder.push_triangle(next_top, next_bottom, bottom);
+            }
+        }
+
+        builder
+            .build(&gpu_state.device, false)
+            .expect("terrain chunk indices are in range")
+    }
+
+    fn vertex(&self, x: f32, z: f32, step: f32) -> model::ModelVertex {
+        let height = self.height_at(x, z);
+
+        // central differences across a grid cell
+        let dx = self.height_at(x + step, z) - self.height_at(x - step, z);
+        let dz = self.height_at(x, z + step) - self.height_at(x, z - step);
+        let tangent = Vec3::new(2.0 * step, dx, 0.0).normalize();
+        let bitangent = Vec3::new(0.0, dz, 2.0 * step).normalize();
+        let normal = bitangent.cross(tangent).normalize();
+
+        model::ModelVertex {
+            position: Point3::new(x, height, z),
+            tex_coords: Vec2::new(x, z) / self.descriptor.texture_span,
+            normal,
+            tangent,
+            bitangent,
+        }
+    }
+}
@@ -1,7 +1,11 @@
+use std::rc::Rc;
+
 use anyhow::*;
 use image::GenericImageView;
 use wgpu::util::DeviceExt;
 
+use super::{debug_groups::DebugGroups, resources, util::*};
+
 // CLosest power of two to `v` without exceeding `v`
 // E.g., 511 -> 256; 512 -> 512; 513 -> 512
 fn pot(v: u32) -> u32 {
@@ -9,20 +13,46 @@ fn pot(v: u32) -> u32 {
     2u32.pow(l)
 }
 
-pub struct Texture {
-    pub texture: wgpu::Texture,
-    pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
-    pub view_dimension: wgpu::TextureViewDimension,
+// The levels of `img`'s mip chain, down to the smaller side's last power of two; only the
+// first level when mipmaps aren't generated
+fn mip_chain(img: image::DynamicImage, generate_mipmaps: bool) -> Vec<image::RgbaImage> {
+    let dimensions = img.dimensions();
+    let mip_levels = if generate_mipmaps {
+        (((dimensions.0.min(dimensions.1)) as f32).log(2.0).floor() as u32).max(1u32)
+    } else {
+        1
+    };
+
+    let mut img = img;
+    let mut levels = Vec::with_capacity(mip_levels as usize);
+    for mip_level in 0..mip_levels {
+        if mip_level > 0 {
+            img = img.resize_exact(
+                img.dimensions().0 / 2,
+                img.dimensions().1 / 2,
+                image::imageops::FilterType::Triangle,
+            );
+        }
+        levels.push(img.to_rgba8());
+    }
+    levels
 }
 
-impl Texture {
-    pub const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8UnormSrgb;
-    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+/// An image decoded and mipmapped on the CPU, ready for Texture::from_decoded to upload.
+/// Decoding is most of the cost of loading a texture, and needs no device, so it may be done
+/// away from the render thread, see texture_streaming.
+pub struct DecodedTexture {
+    label: String,
+    // the mip chain, largest first
+    levels: Vec<image::RgbaImage>,
+    is_normal_map: bool,
+    generate_mipmaps: bool,
+}
 
-    pub fn from_bytes(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
+impl DecodedTexture {
+    /// Decodes an image file's `bytes`, resizing it to a power of two size when generating
+    /// mipmaps, as Texture::from_bytes does
+    pub fn decode(
         bytes: &[u8],
         label: &str,
         is_normal_map: bool,
@@ -43,41 +73,271 @@ impl Texture {
             img
         };
 
-        Self::from_image(
+        Ok(Self {
+            label: label.to_owned(),
+            levels: mip_chain(img, generate_mipmaps),
+            is_normal_map,
+            generate_mipmaps,
+        })
+    }
+
+    /// Wraps a mip chain decoded elsewhere, largest first, e.g. read from a KTX2 file; see
+    /// packed_asset::decode_ktx2
+    pub fn from_levels(
+        label: &str,
+        levels: Vec<image::RgbaImage>,
+        is_normal_map: bool,
+    ) -> Result<Self> {
+        if levels.is_empty() {
+            return Err(anyhow!("Texture \"{}\" has no levels", label));
+        }
+        Ok(Self {
+            label: label.to_owned(),
+            generate_mipmaps: levels.len() > 1,
+            levels,
+            is_normal_map,
+        })
+    }
+
+    /// The mip chain, largest first
+    pub fn levels(&self) -> &[image::RgbaImage] {
+        &self.levels
+    }
+
+    pub fn is_normal_map(&self) -> bool {
+        self.is_normal_map
+    }
+
+    /// The bytes uploaded for every level of the texture
+    pub fn size_in_bytes(&self) -> usize {
+        self.mips_size_in_bytes(0)
+    }
+
+    /// The bytes uploaded for the levels from `first_mip` down, see Texture::from_decoded_mips
+    pub fn mips_size_in_bytes(&self, first_mip: u32) -> usize {
+        self.levels
+            .iter()
+            .skip(first_mip as usize)
+            .map(|level| level.as_raw().len())
+            .sum()
+    }
+
+    pub fn mip_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    /// The width and height of mip level `mip`, clamped to the smallest
+    pub fn mip_size(&self, mip: u32) -> (u32, u32) {
+        self.levels[(mip as usize).min(self.levels.len() - 1)].dimensions()
+    }
+}
+
+/// Generated stand-ins for material textures which name a file that fails to load, each
+/// recognizable at a glance rather than silently shading as if untextured; and White, for
+/// slots a material must fill to be shaded but has no texture for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placeholder {
+    // a magenta and black checker
+    Diffuse,
+    // a flat tangent space normal
+    Normal,
+    // mid-grey, half the material's specular
+    Glossiness,
+    // white, unoccluded
+    AmbientOcclusion,
+    // white, leaving the material's color as is; e.g. the diffuse texture of a material with
+    // a normal map but no base color texture, which model.wgsl has no variant for
+    White,
+}
+
+impl Placeholder {
+    /// Whether the textures this stands in for hold color, stored as sRGB, rather than linear
+    /// data such as normals, glossiness or occlusion
+    pub fn is_color(self) -> bool {
+        matches!(self, Placeholder::Diffuse | Placeholder::White)
+    }
+}
+
+pub struct Texture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    pub view_dimension: wgpu::TextureViewDimension,
+}
+
+impl Texture {
+    pub const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8UnormSrgb;
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    pub const MOMENTS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Float;
+
+    pub fn from_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+        is_normal_map: bool,
+        generate_mipmaps: bool,
+    ) -> Result<Self> {
+        let decoded = DecodedTexture::decode(bytes, label, is_normal_map, generate_mipmaps)?;
+        Ok(Self::from_decoded(device, queue, &decoded))
+    }
+
+    /// Uploads a texture decoded by DecodedTexture::decode
+    pub fn from_decoded(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        decoded: &DecodedTexture,
+    ) -> Self {
+        Self::from_decoded_mips(device, queue, decoded, 0)
+    }
+
+    /// Uploads the levels of a decoded texture from `first_mip` (clamped to the smallest) down,
+    /// so mip `first_mip` becomes the texture's most detailed level, e.g. to keep only the
+    /// detail a distant surface needs resident; see texture_residency.
+    pub fn from_decoded_mips(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        decoded: &DecodedTexture,
+        first_mip: u32,
+    ) -> Self {
+        let first_mip = (first_mip as usize).min(decoded.levels.len() - 1);
+        Self::from_mip_chains(
+            device,
+            queue,
+            &[decoded.levels[first_mip..].to_vec()],
+            Some(&decoded.label),
+            decoded.is_normal_map,
+            decoded.generate_mipmaps,
+            wgpu::TextureViewDimension::D2,
+        )
+    }
+
+    /// Creates a small, unfiltered texture standing in for one of a material's textures, see
+    /// Placeholder
+    pub fn placeholder(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        placeholder: Placeholder,
+    ) -> Result<Self> {
+        // checker cells are 8 texels across, so the diffuse placeholder repeats every 16
+        const SIZE: u32 = 16;
+        let image = image::RgbaImage::from_fn(SIZE, SIZE, |x, y| match placeholder {
+            Placeholder::Diffuse => {
+                if (x / 8 + y / 8) % 2 == 0 {
+                    image::Rgba([255, 0, 255, 255])
+                } else {
+                    image::Rgba([0, 0, 0, 255])
+                }
+            }
+            Placeholder::Normal => image::Rgba([128, 128, 255, 255]),
+            Placeholder::Glossiness => image::Rgba([128, 128, 128, 255]),
+            Placeholder::AmbientOcclusion | Placeholder::White => image::Rgba([255, 255, 255, 255]),
+        });
+
+        Self::from_images(
             device,
             queue,
-            img,
+            vec![image::DynamicImage::ImageRgba8(image)],
+            Some(&format!("Placeholder {:?}", placeholder)),
+            !placeholder.is_color(),
+            false,
+            wgpu::TextureViewDimension::D2,
+        )
+    }
+
+    /// Loads each of `layers` into a layer of a mipmapped texture array, e.g. for terrain
+    /// layers. Layers are resized to the power of two size of the first.
+    pub fn array_from_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &[&[u8]],
+        label: &str,
+        is_normal_map: bool,
+    ) -> Result<Self> {
+        let images = layers
+            .iter()
+            .map(|bytes| image::load_from_memory(bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let dimensions = images
+            .first()
+            .ok_or_else(|| anyhow!("Texture array \"{}\" has no layers", label))?
+            .dimensions();
+        let pot_dimensions = (pot(dimensions.0), pot(dimensions.1));
+
+        let images = images
+            .into_iter()
+            .map(|img| {
+                if img.dimensions() != pot_dimensions {
+                    img.resize_exact(
+                        pot_dimensions.0,
+                        pot_dimensions.1,
+                        image::imageops::FilterType::CatmullRom,
+                    )
+                } else {
+                    img
+                }
+            })
+            .collect();
+
+        Self::from_images(
+            device,
+            queue,
+            images,
             Some(label),
             is_normal_map,
-            generate_mipmaps,
+            true,
+            wgpu::TextureViewDimension::D2Array,
         )
     }
 
-    fn from_image(
+    // images must share dimensions; each is written to its own array layer
+    fn from_images(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        img: image::DynamicImage,
+        images: Vec<image::DynamicImage>,
         label: Option<&str>,
         is_normal_map: bool,
         generate_mipmaps: bool,
+        view_dimension: wgpu::TextureViewDimension,
     ) -> Result<Self> {
-        let dimensions = img.dimensions();
-        let mip_levels = if generate_mipmaps {
-            (((dimensions.0.min(dimensions.1)) as f32).log(2.0).floor() as u32).max(1u32)
-        } else {
-            1
-        };
+        let layers = images
+            .into_iter()
+            .map(|img| mip_chain(img, generate_mipmaps))
+            .collect::<Vec<_>>();
+        Ok(Self::from_mip_chains(
+            device,
+            queue,
+            &layers,
+            label,
+            is_normal_map,
+            generate_mipmaps,
+            view_dimension,
+        ))
+    }
 
+    // layers must share dimensions and mip counts; each is written to its own array layer.
+    // Filtering is linear when mipmapped, nearest otherwise.
+    fn from_mip_chains(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &[Vec<image::RgbaImage>],
+        label: Option<&str>,
+        is_normal_map: bool,
+        generate_mipmaps: bool,
+        view_dimension: wgpu::TextureViewDimension,
+    ) -> Self {
+        let dimensions = layers[0][0].dimensions();
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
-            depth_or_array_layers: 1,
+            depth_or_array_layers: layers.len() as u32,
         };
 
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: mip_levels,
+            mip_level_count: layers[0].len() as u32,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: if is_normal_map {
@@ -88,38 +348,34 @@ impl Texture {
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         });
 
-        let mut img = img;
-        for mip_level in 0..mip_levels {
-            if mip_level > 0 {
-                img = img.resize_exact(
-                    img.dimensions().0 / 2,
-                    img.dimensions().1 / 2,
-                    image::imageops::FilterType::Triangle,
+        for (layer, levels) in layers.iter().enumerate() {
+            for (mip_level, data) in levels.iter().enumerate() {
+                let mip_size = data.dimensions();
+
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &texture,
+                        mip_level: mip_level as u32,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer as u32,
+                        },
+                    },
+                    data,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: std::num::NonZeroU32::new(4 * mip_size.0),
+                        rows_per_image: std::num::NonZeroU32::new(mip_size.1),
+                    },
+                    wgpu::Extent3d {
+                        width: mip_size.0,
+                        height: mip_size.1,
+                        depth_or_array_layers: 1,
+                    },
                 );
             }
-
-            let mip_size = img.dimensions();
-            let data = img.to_rgba8();
-
-            queue.write_texture(
-                wgpu::ImageCopyTexture {
-                    aspect: wgpu::TextureAspect::All,
-                    texture: &texture,
-                    mip_level,
-                    origin: wgpu::Origin3d::ZERO,
-                },
-                &data,
-                wgpu::ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: std::num::NonZeroU32::new(4 * mip_size.0),
-                    rows_per_image: std::num::NonZeroU32::new(mip_size.1),
-                },
-                wgpu::Extent3d {
-                    width: mip_size.0,
-                    height: mip_size.1,
-                    depth_or_array_layers: 1,
-                },
-            );
         }
 
         let filter_mode = if generate_mipmaps {
@@ -128,7 +384,10 @@ impl Texture {
             wgpu::FilterMode::Nearest
         };
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(view_dimension),
+            ..Default::default()
+        });
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::Repeat,
             address_mode_v: wgpu::AddressMode::Repeat,
@@ -139,12 +398,12 @@ impl Texture {
             ..Default::default()
         });
 
-        Ok(Self {
+        Self {
             texture,
             view,
             sampler,
-            view_dimension: wgpu::TextureViewDimension::D2,
-        })
+            view_dimension,
+        }
     }
 
     pub fn cubemap_from_dds(
@@ -242,6 +501,96 @@ impl Texture {
         }
     }
 
+    pub fn create_shadow_texture(device: &wgpu::Device, resolution: u32, label: &str) -> Self {
+        let size = wgpu::Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: 1,
+        };
+
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        };
+        let texture = device.create_texture(&desc);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        }
+    }
+
+    // Rg32Float isn't filterable without optional device features, so moments textures
+    // are read with textureLoad and the sampler is only provided to satisfy the Texture type
+    pub fn create_moments_texture(device: &wgpu::Device, resolution: u32, label: &str) -> Self {
+        Self::create_render_target(device, resolution, resolution, Self::MOMENTS_FORMAT, label)
+    }
+
+    /// Creates a texture which can be rendered to and bound for reading, with a nearest
+    /// filtering sampler, e.g. for the transient_textures::TransientTexturePool
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            // copied from, e.g. by screenshots
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+        };
+        let texture = device.create_texture(&desc);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        }
+    }
+
     pub fn create_color_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
@@ -260,7 +609,10 @@ impl Texture {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: Self::COLOR_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            // copied from by scene screenshots, see screenshot::CaptureMode::Scene
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
         };
         let texture = device.create_texture(&desc);
         let view = texture.create_view(&wgpu::TextureViewDescriptor {
@@ -285,3 +637,516 @@ impl Texture {
         }
     }
 }
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Samples per texel when prefiltering, see Cubemap::prefilter
+const PREFILTER_SAMPLE_COUNT: u32 = 64;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct CubemapFilterUniformData {
+    // x: roughness, y: level of the source sampled, z: destination face, w: sample count
+    params: Vec4,
+    // x: source face size at level 0
+    source: Vec4,
+}
+
+unsafe impl bytemuck::Pod for CubemapFilterUniformData {}
+unsafe impl bytemuck::Zeroable for CubemapFilterUniformData {}
+
+// The pipelines of res/shaders/cubemap_filter.wgsl, shared by a cubemap and those prefiltered
+// from it. Filters render each face of a level rather than write it from a compute shader, as
+// wgpu's GL backend doesn't support storage textures.
+struct CubemapFilters {
+    bind_group_layout: wgpu::BindGroupLayout,
+    downsample_pipeline: wgpu::RenderPipeline,
+    prefilter_pipeline: wgpu::RenderPipeline,
+}
+
+impl CubemapFilters {
+    fn new(device: &wgpu::Device) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("Cubemap Filter Bind Group Layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("cubemap_filter"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shaders/cubemap_filter.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                resources::load_string_sync("shaders/cubemap_filter.wgsl")
+                    .unwrap()
+                    .into(),
+            ),
+        });
+        let create_pipeline = |fs_main: &str| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(&format!("RenderPipeline: cubemap_filter {}", fs_main)),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main_filter",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: fs_main,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: Cubemap::FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+
+        Self {
+            downsample_pipeline: create_pipeline("fs_main_downsample"),
+            prefilter_pipeline: create_pipeline("fs_main_prefilter"),
+            bind_group_layout,
+        }
+    }
+
+    // Renders each face of `level` of the cubemap at `destination`'s array layer (the texture,
+    // and its first face's layer) with `pipeline`, reading `source`; the uniform's face is filled
+    // in per face
+    #[allow(clippy::too_many_arguments)]
+    fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        source: (&wgpu::TextureView, &wgpu::Sampler),
+        destination: (&wgpu::Texture, u32),
+        level: u32,
+        uniform: CubemapFilterUniformData,
+    ) {
+        encoder.push_group(format_args!("Cubemap Filter: Level {}", level));
+        for face in 0..6 {
+            let mut uniform = uniform;
+            uniform.params.z = face as f32;
+            let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Cubemap Filter Uniform Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(source.0),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(source.1),
+                    },
+                ],
+                label: Some("Cubemap Filter Bind Group"),
+            });
+            let view = destination.0.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Cubemap Filter Destination"),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: level,
+                mip_level_count: std::num::NonZeroU32::new(1),
+                base_array_layer: destination.1 + face,
+                array_layer_count: std::num::NonZeroU32::new(1),
+                ..Default::default()
+            });
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Cubemap Filter Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load, // FSQ doesn't need to clear
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        encoder.pop_group();
+    }
+}
+
+// A cubemap which can be filtered on the GPU, e.g. for image based lighting or reflection
+// probes updated at runtime. Its texture can be used anywhere a cubemap texture is, e.g. as an
+// environment::Environment map.
+pub struct Cubemap {
+    texture: Rc<Texture>,
+    size: u32,
+    mip_level_count: u32,
+    filters: Rc<CubemapFilters>,
+}
+
+impl Cubemap {
+    // a float format keeps HDR radiance, and unlike the 8 bit formats isn't quantized by filtering
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// Creates an uninitialized cubemap whose faces are `size` texels square, with a full mip
+    /// chain.
+    pub fn new(device: &wgpu::Device, size: u32, label: &str) -> Self {
+        let size = size.max(1);
+        let mip_level_count = 32 - size.leading_zeros();
+        Self::with_mip_level_count(
+            device,
+            size,
+            mip_level_count,
+            label,
+            Rc::new(CubemapFilters::new(device)),
+        )
+    }
+
+    /// Resamples the first mip level of `source`, any filterable cubemap such as one loaded
+    /// with resources::load_cubemap_texture, into the first mip level of a new cubemap; call
+    /// generate_mips to fill the rest.
+    pub fn from_texture(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &Texture,
+        size: u32,
+        label: &str,
+    ) -> Self {
+        let cubemap = Self::new(device, size, label);
+        cubemap.filter_level(
+            device,
+            encoder,
+            &cubemap.filters.downsample_pipeline,
+            (&source.view, &source.sampler),
+            0,
+            CubemapFilterUniformData {
+                params: Vec4::new(0.0, 0.0, 0.0, 1.0),
+                source: Vec4::new(0.0, 0.0, 0.0, 0.0),
+            },
+        );
+        cubemap
+    }
+
+    fn with_mip_level_count(
+        device: &wgpu::Device,
+        size: u32,
+        mip_level_count: u32,
+        label: &str,
+        filters: Rc<CubemapFilters>,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            texture: Rc::new(Texture {
+                texture,
+                view,
+                sampler,
+                view_dimension: wgpu::TextureViewDimension::Cube,
+            }),
+            size,
+            mip_level_count,
+            filters,
+        }
+    }
+
+    pub fn texture(&self) -> &Rc<Texture> {
+        &self.texture
+    }
+
+    /// Width and height of each face at the first mip level, in texels
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn mip_level_count(&self) -> u32 {
+        self.mip_level_count
+    }
+
+    /// Fills each mip level after the first by downsampling the level before it.
+    pub fn generate_mips(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        for level in 1..self.mip_level_count {
+            // sample only the level above, as the level rendered can't be bound for sampling too
+            let source_view = self
+                .texture
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Cubemap Mip Source"),
+                    dimension: Some(wgpu::TextureViewDimension::Cube),
+                    base_mip_level: level - 1,
+                    mip_level_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                });
+            self.filter_level(
+                device,
+                encoder,
+                &self.filters.downsample_pipeline,
+                (&source_view, &self.texture.sampler),
+                level,
+                // the view holds only the level above, but wgpu's GL backend can't bind views
+                // of later levels and binds every level, so that level is sampled explicitly
+                CubemapFilterUniformData {
+                    params: Vec4::new(0.0, (level - 1) as f32, 0.0, 1.0),
+                    source: Vec4::new(self.size as f32, 0.0, 0.0, 0.0),
+                },
+            );
+        }
+    }
+
+    /// Returns a cubemap of `roughness_levels` mip levels (at most this cubemap's), where level
+    /// i holds this cubemap's radiance convolved with the GGX distribution at roughness
+    /// i / (roughness_levels - 1), for sampling reflections of rough surfaces by their
+    /// roughness. This cubemap's mips should be generated first, as samples read them.
+    pub fn prefilter(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        roughness_levels: u32,
+    ) -> Cubemap {
+        let roughness_levels = roughness_levels.clamp(1, self.mip_level_count);
+        let prefiltered = Self::with_mip_level_count(
+            device,
+            self.size,
+            roughness_levels,
+            "Prefiltered Cubemap",
+            self.filters.clone(),
+        );
+
+        for level in 0..roughness_levels {
+            let roughness = if roughness_levels > 1 {
+                level as f32 / (roughness_levels - 1) as f32
+            } else {
+                0.0
+            };
+            prefiltered.filter_level(
+                device,
+                encoder,
+                &self.filters.prefilter_pipeline,
+                (&self.texture.view, &self.texture.sampler),
+                level,
+                CubemapFilterUniformData {
+                    params: Vec4::new(roughness, 0.0, 0.0, PREFILTER_SAMPLE_COUNT as f32),
+                    source: Vec4::new(self.size as f32, 0.0, 0.0, 0.0),
+                },
+            );
+        }
+        prefiltered
+    }
+
+    // Renders each face of `level` with `pipeline`, reading `source`; the uniform's face is
+    // filled in per face
+    fn filter_level(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        source: (&wgpu::TextureView, &wgpu::Sampler),
+        level: u32,
+        uniform: CubemapFilterUniformData,
+    ) {
+        self.filters.render(
+            device,
+            encoder,
+            pipeline,
+            source,
+            (&self.texture.texture, 0),
+            level,
+            uniform,
+        );
+    }
+}
+
+// An array of cubemaps of Cubemap::FORMAT sharing a size and mip chain, bound once and indexed
+// in shaders, e.g. the captures of reflection_probes::ReflectionProbes. Each cubemap is filled
+// from a Cubemap, see copy_from.
+pub struct CubemapArray {
+    texture: Rc<Texture>,
+    size: u32,
+    mip_level_count: u32,
+    count: u32,
+}
+
+impl CubemapArray {
+    /// Creates an uninitialized array of `count` cubemaps whose faces are `size` texels square,
+    /// each with `mip_level_count` mip levels (at most a full chain).
+    pub fn new(
+        device: &wgpu::Device,
+        size: u32,
+        mip_level_count: u32,
+        count: u32,
+        label: &str,
+    ) -> Self {
+        let size = size.max(1);
+        let mip_level_count = mip_level_count.clamp(1, 32 - size.leading_zeros());
+        let count = count.max(1);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6 * count,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Cubemap::FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            dimension: Some(wgpu::TextureViewDimension::CubeArray),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            texture: Rc::new(Texture {
+                texture,
+                view,
+                sampler,
+                view_dimension: wgpu::TextureViewDimension::CubeArray,
+            }),
+            size,
+            mip_level_count,
+            count,
+        }
+    }
+
+    pub fn texture(&self) -> &Rc<Texture> {
+        &self.texture
+    }
+
+    /// Width and height of each face at the first mip level, in texels
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn mip_level_count(&self) -> u32 {
+        self.mip_level_count
+    }
+
+    /// The number of cubemaps in the array
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Copies `cubemap` into the cubemap at `index`. The cubemap must be the array's size;
+    /// levels it lacks are left as they were, and levels beyond the array's are ignored. Each
+    /// face is rendered rather than copied, as wgpu's GL backend can't copy between cubemaps.
+    pub fn copy_from(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        index: u32,
+        cubemap: &Cubemap,
+    ) -> Result<()> {
+        if index >= self.count {
+            bail!(
+                "Cubemap index {} is out of range of an array of {}",
+                index,
+                self.count
+            );
+        }
+        if cubemap.size() != self.size {
+            bail!(
+                "A {}x{} cubemap can't be copied into an array of {}x{} cubemaps",
+                cubemap.size(),
+                cubemap.size(),
+                self.size,
+                self.size
+            );
+        }
+
+        for level in 0..self.mip_level_count.min(cubemap.mip_level_count()) {
+            // resampling a level at its own size reads each texel at its center, unfiltered
+            cubemap.filters.render(
+                device,
+                encoder,
+                &cubemap.filters.downsample_pipeline,
+                (&cubemap.texture.view, &cubemap.texture.sampler),
+                (&self.texture.texture, 6 * index),
+                level,
+                CubemapFilterUniformData {
+                    params: Vec4::new(0.0, level as f32, 0.0, 1.0),
+                    source: Vec4::new(self.size as f32, 0.0, 0.0, 0.0),
+                },
+            );
+        }
+        Ok(())
+    }
+}
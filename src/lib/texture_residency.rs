@@ -0,0 +1,260 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use super::{gpu_state::GpuState, material_variant::MaterialTextures, model, texture, util::*};
+
+// Bytes of texels kept on the GPU by streamed textures before distant ones drop detail
+const DEFAULT_MEMORY_BUDGET: usize = 256 << 20;
+// The distance within which textures are fully resident; each doubling of the distance
+// beyond drops a level of detail
+const DEFAULT_FULL_DETAIL_DISTANCE: f32 = 8.0;
+// Textures first become resident from the largest level no larger than this on either side
+const DEFAULT_INITIAL_SIZE: u32 = 64;
+// Textures created and bound per update, so residency changes are spread over frames
+const DEFAULT_MAX_CHANGES_PER_FRAME: usize = 2;
+
+// A decoded texture, or the error loading it, for the entry at the index
+type Inbox = Rc<RefCell<Vec<(usize, anyhow::Result<texture::DecodedTexture>)>>>;
+
+struct Entry {
+    model_id: usize,
+    material: usize,
+    slot: MaterialTextures,
+    file_name: String,
+    // the full mip chain, kept on the CPU so any level can be made resident again
+    decoded: Option<texture::DecodedTexture>,
+    // the most detailed level on the GPU, None until the texture is first bound
+    resident_mip: Option<u32>,
+}
+
+impl Entry {
+    // The first level no larger than `initial_size` on either side, or the smallest
+    fn tail_mip(decoded: &texture::DecodedTexture, initial_size: u32) -> u32 {
+        (0..decoded.mip_count())
+            .find(|mip| {
+                let (width, height) = decoded.mip_size(*mip);
+                width.max(height) <= initial_size
+            })
+            .unwrap_or(decoded.mip_count() - 1)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Keeps large material textures resident at the level of detail their distance from the
+/// camera needs. Each texture is decoded in the background by gpu_state's TextureStreamer,
+/// first bound from a small mip, then rebound with more or fewer levels as the camera moves,
+/// dropping detail from the farthest textures while the resident total exceeds memory_budget.
+/// The full decoded chain stays on the CPU, so detail can be streamed back in without
+/// reloading. Textures are bound with model::Material::set_texture, so the material's other
+/// textures must support the slot, e.g. a streamed normal map needs a diffuse texture.
+pub struct TextureResidency {
+    entries: Vec<Entry>,
+    inbox: Inbox,
+    // bytes of texels streamed textures may keep resident, see DEFAULT_MEMORY_BUDGET
+    pub memory_budget: usize,
+    // see DEFAULT_FULL_DETAIL_DISTANCE
+    pub full_detail_distance: f32,
+    // see DEFAULT_INITIAL_SIZE
+    pub initial_size: u32,
+    // see DEFAULT_MAX_CHANGES_PER_FRAME
+    pub max_changes_per_frame: usize,
+}
+
+impl Default for TextureResidency {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            inbox: Rc::new(RefCell::new(Vec::new())),
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            full_detail_distance: DEFAULT_FULL_DETAIL_DISTANCE,
+            initial_size: DEFAULT_INITIAL_SIZE,
+            max_changes_per_frame: DEFAULT_MAX_CHANGES_PER_FRAME,
+        }
+    }
+}
+
+impl TextureResidency {
+    /// Streams the texture `file_name` into the `slot` texture of material `material` of the
+    /// model `model_id`, replacing whatever is bound there once its first mip is resident.
+    pub fn stream(
+        &mut self,
+        gpu_state: &mut GpuState,
+        model_id: usize,
+        material: usize,
+        slot: MaterialTextures,
+        file_name: &str,
+    ) {
+        let index = self.entries.len();
+        self.entries.push(Entry {
+            model_id,
+            material,
+            slot,
+            file_name: file_name.to_owned(),
+            decoded: None,
+            resident_mip: None,
+        });
+
+        let inbox = self.inbox.clone();
+        gpu_state.texture_streams.request_decoded(
+            file_name,
+            slot == MaterialTextures::NORMAL,
+            true,
+            move |result| inbox.borrow_mut().push((index, result)),
+        );
+    }
+
+    /// Chooses the level of detail each streamed texture of `models` needs as seen from
+    /// `camera_position`, and rebinds up to max_changes_per_frame of those which differ,
+    /// textures not yet bound first, then the nearest. `lod_bias` scales full_detail_distance,
+    /// see scene::Scene::lod_bias.
+    pub fn update(
+        &mut self,
+        gpu_state: &GpuState,
+        camera_position: Point3,
+        models: &mut HashMap<usize, model::Model>,
+        lod_bias: f32,
+    ) {
+        for (index, result) in self.inbox.borrow_mut().drain(..) {
+            match result {
+                Ok(decoded) => self.entries[index].decoded = Some(decoded),
+                Err(e) => eprintln!(
+                    "Unable to stream texture \"{}\": {}",
+                    self.entries[index].file_name, e
+                ),
+            }
+        }
+
+        // the level each decoded texture wants by distance, and the smallest it may drop to
+        let full_detail_distance = (self.full_detail_distance * lod_bias).max(f32::EPSILON);
+        let mut wanted = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| {
+                let decoded = entry.decoded.as_ref()?;
+                let model = models.get(&entry.model_id)?;
+                let distance = model
+                    .bounds()
+                    .map_or(0.0, |bounds| bounds.distance_to(camera_position));
+                let tail = Entry::tail_mip(decoded, self.initial_size);
+                let mip = (distance / full_detail_distance).log2().floor().max(0.0) as u32;
+                Some((index, distance, mip.min(tail), tail))
+            })
+            .collect::<Vec<_>>();
+
+        // drop detail from the farthest textures, a level at a time, until within budget
+        let bytes = |index: usize, mip: u32| {
+            self.entries[index]
+                .decoded
+                .as_ref()
+                .unwrap()
+                .mips_size_in_bytes(mip)
+        };
+        let mut total = wanted
+            .iter()
+            .map(|(index, _, mip, _)| bytes(*index, *mip))
+            .sum::<usize>();
+        wanted.sort_by(|a, b| b.1.total_cmp(&a.1));
+        while total > self.memory_budget {
+            let mut dropped = false;
+            for (index, _, mip, tail) in wanted.iter_mut() {
+                if total <= self.memory_budget {
+                    break;
+                }
+                if *mip < *tail {
+                    total -= bytes(*index, *mip) - bytes(*index, *mip + 1);
+                    *mip += 1;
+                    dropped = true;
+                }
+            }
+            if !dropped {
+                break;
+            }
+        }
+
+        // textures not yet bound start from their tail, the rest move to the wanted level
+        let mut changes = wanted
+            .into_iter()
+            .filter_map(
+                |(index, distance, mip, tail)| match self.entries[index].resident_mip {
+                    None => Some((index, distance, tail)),
+                    Some(resident) if resident != mip => Some((index, distance, mip)),
+                    Some(_) => None,
+                },
+            )
+            .collect::<Vec<_>>();
+        changes.sort_by(|a, b| {
+            let a_bound = self.entries[a.0].resident_mip.is_some();
+            let b_bound = self.entries[b.0].resident_mip.is_some();
+            a_bound.cmp(&b_bound).then(a.1.total_cmp(&b.1))
+        });
+
+        for (index, _, mip) in changes.into_iter().take(self.max_changes_per_frame) {
+            let entry = &mut self.entries[index];
+            let material = match models
+                .get_mut(&entry.model_id)
+                .and_then(|model| model.materials_mut().get_mut(entry.material))
+            {
+                Some(material) => material,
+                None => continue,
+            };
+            let texture = texture::Texture::from_decoded_mips(
+                &gpu_state.device,
+                &gpu_state.queue,
+                entry.decoded.as_ref().unwrap(),
+                mip,
+            );
+            material.set_texture(&gpu_state.device, entry.slot, Some(texture));
+            if Self::is_bound(material, entry.slot) {
+                entry.resident_mip = Some(mip);
+            } else {
+                // the material rejected the texture and said why; stop streaming it
+                entry.decoded = None;
+            }
+        }
+    }
+
+    /// The most detailed level of the texture streamed into the `slot` texture of material
+    /// `material` of the model `model_id` which is on the GPU, None if it isn't bound yet.
+    pub fn resident_mip(
+        &self,
+        model_id: usize,
+        material: usize,
+        slot: MaterialTextures,
+    ) -> Option<u32> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.model_id == model_id && e.material == material && e.slot == slot)?
+            .resident_mip
+    }
+
+    /// The bytes of texels streamed textures hold on the GPU
+    pub fn resident_bytes(&self) -> usize {
+        self.entries
+            .iter()
+            .filter_map(|entry| {
+                Some(
+                    entry
+                        .decoded
+                        .as_ref()?
+                        .mips_size_in_bytes(entry.resident_mip?),
+                )
+            })
+            .sum()
+    }
+
+    fn is_bound(material: &model::Material, slot: MaterialTextures) -> bool {
+        if slot == MaterialTextures::DIFFUSE {
+            material.diffuse_texture.is_some()
+        } else if slot == MaterialTextures::NORMAL {
+            material.normal_texture.is_some()
+        } else if slot == MaterialTextures::SHININESS {
+            material.shininess_texture.is_some()
+        } else if slot == MaterialTextures::AMBIENT_OCCLUSION {
+            material.ambient_occlusion_texture.is_some()
+        } else {
+            false
+        }
+    }
+}
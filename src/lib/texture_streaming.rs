@@ -0,0 +1,262 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+};
+
+use super::{resources, texture};
+
+// Bytes of decoded texels uploaded per frame before further uploads wait for the next; at
+// least one texture is uploaded each frame however large
+const DEFAULT_UPLOAD_BUDGET: usize = 16 << 20;
+
+type Callback = Box<dyn FnOnce(anyhow::Result<texture::Texture>)>;
+type DecodedCallback = Box<dyn FnOnce(anyhow::Result<texture::DecodedTexture>)>;
+
+// What a request is answered with
+enum Delivery {
+    Texture(Callback),
+    Decoded(DecodedCallback),
+}
+
+struct DecodeRequest {
+    id: usize,
+    file_name: String,
+    is_normal_map: bool,
+    generate_mipmaps: bool,
+}
+
+struct Decoded {
+    id: usize,
+    result: anyhow::Result<texture::DecodedTexture>,
+}
+
+struct PendingRequest {
+    id: usize,
+    delivery: Delivery,
+}
+
+struct Upload {
+    texture: texture::Texture,
+    callback: Callback,
+    // set once the submission holding the texture's writes completes, see end_frame
+    completed: Option<Arc<AtomicBool>>,
+}
+
+struct Worker {
+    requests: mpsc::Sender<DecodeRequest>,
+    decoded: mpsc::Receiver<Decoded>,
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Loads textures mid-scene without stalling presentation. wgpu exposes a single queue, so
+// the slow part of loading, reading and decoding the file and generating its mip chain, is
+// done on a worker thread; decoded textures are then created and written on the render
+// thread, a budget of bytes per frame, and handed to their request's callback once the GPU
+// has completed the submission carrying their writes. Usage each frame is: begin_frame,
+// request, submit, then end_frame.
+pub struct TextureStreamer {
+    // the worker thread is started by the first request
+    worker: Option<Worker>,
+    next_id: usize,
+    // requests being decoded, in the order they were made
+    decoding: Vec<PendingRequest>,
+    // decoded textures waiting for upload budget
+    decoded: VecDeque<(Callback, texture::DecodedTexture)>,
+    // textures written this frame or in flight
+    uploads: Vec<Upload>,
+    // bytes of decoded texels uploaded per frame, see DEFAULT_UPLOAD_BUDGET
+    pub upload_budget: usize,
+}
+
+impl Default for TextureStreamer {
+    fn default() -> Self {
+        Self {
+            worker: None,
+            next_id: 0,
+            decoding: Vec::new(),
+            decoded: VecDeque::new(),
+            uploads: Vec::new(),
+            upload_budget: DEFAULT_UPLOAD_BUDGET,
+        }
+    }
+}
+
+impl TextureStreamer {
+    /// Hands completed textures to their callbacks, collects textures the worker has decoded,
+    /// and creates and writes as many as the upload budget allows. Must be called before the
+    /// frame's submission, which carries the writes.
+    pub fn begin_frame(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        device.poll(wgpu::Maintain::Poll);
+
+        let (completed, uploads): (Vec<_>, Vec<_>) = self.uploads.drain(..).partition(|upload| {
+            upload
+                .completed
+                .as_ref()
+                .is_some_and(|completed| completed.load(Ordering::Acquire))
+        });
+        self.uploads = uploads;
+        for upload in completed {
+            (upload.callback)(Ok(upload.texture));
+        }
+
+        if let Some(worker) = &self.worker {
+            for decoded in worker.decoded.try_iter() {
+                let index = match self.decoding.iter().position(|r| r.id == decoded.id) {
+                    Some(index) => index,
+                    None => continue,
+                };
+                match (self.decoding.remove(index).delivery, decoded.result) {
+                    (Delivery::Texture(callback), Ok(texture)) => {
+                        self.decoded.push_back((callback, texture))
+                    }
+                    (Delivery::Texture(callback), Err(e)) => callback(Err(e)),
+                    (Delivery::Decoded(callback), result) => callback(result),
+                }
+            }
+        }
+
+        let mut uploaded = 0;
+        while let Some((_, texture)) = self.decoded.front() {
+            if uploaded > 0 && uploaded + texture.size_in_bytes() > self.upload_budget {
+                break;
+            }
+            let (callback, texture) = self.decoded.pop_front().unwrap();
+            uploaded += texture.size_in_bytes();
+            self.uploads.push(Upload {
+                texture: texture::Texture::from_decoded(device, queue, &texture),
+                callback,
+                completed: None,
+            });
+        }
+    }
+
+    /// Loads the texture `file_name` in the background, as resources::load_texture would.
+    /// `callback` receives the texture, or the error loading it, from a later begin_frame,
+    /// once the texture's contents are on the GPU.
+    pub fn request<F>(
+        &mut self,
+        file_name: &str,
+        is_normal_map: bool,
+        generate_mipmaps: bool,
+        callback: F,
+    ) where
+        F: FnOnce(anyhow::Result<texture::Texture>) + 'static,
+    {
+        self.enqueue(
+            file_name,
+            is_normal_map,
+            generate_mipmaps,
+            Delivery::Texture(Box::new(callback)),
+        );
+    }
+
+    /// Decodes the texture `file_name` in the background without uploading it, e.g. to upload
+    /// only some of its mip levels with texture::Texture::from_decoded_mips. `callback`
+    /// receives the decoded texture, or the error loading it, from a later begin_frame.
+    pub fn request_decoded<F>(
+        &mut self,
+        file_name: &str,
+        is_normal_map: bool,
+        generate_mipmaps: bool,
+        callback: F,
+    ) where
+        F: FnOnce(anyhow::Result<texture::DecodedTexture>) + 'static,
+    {
+        self.enqueue(
+            file_name,
+            is_normal_map,
+            generate_mipmaps,
+            Delivery::Decoded(Box::new(callback)),
+        );
+    }
+
+    fn enqueue(
+        &mut self,
+        file_name: &str,
+        is_normal_map: bool,
+        generate_mipmaps: bool,
+        delivery: Delivery,
+    ) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let worker = self.worker.get_or_insert_with(Self::spawn_worker);
+        let request = DecodeRequest {
+            id,
+            file_name: file_name.to_owned(),
+            is_normal_map,
+            generate_mipmaps,
+        };
+        if let Err(e) = worker.requests.send(request) {
+            let error = anyhow::anyhow!(
+                "Texture streaming worker has stopped, unable to load \"{}\"",
+                e.0.file_name
+            );
+            match delivery {
+                Delivery::Texture(callback) => callback(Err(error)),
+                Delivery::Decoded(callback) => callback(Err(error)),
+            }
+            return;
+        }
+        self.decoding.push(PendingRequest { id, delivery });
+    }
+
+    /// The number of requests whose callbacks haven't been called yet
+    pub fn pending_count(&self) -> usize {
+        self.decoding.len() + self.decoded.len() + self.uploads.len()
+    }
+
+    /// Must be called after the submission carrying this frame's writes; their textures are
+    /// delivered once the GPU completes it.
+    pub fn end_frame(&mut self, queue: &wgpu::Queue) {
+        if self.uploads.iter().all(|upload| upload.completed.is_some()) {
+            return;
+        }
+
+        let completed = Arc::new(AtomicBool::new(false));
+        let signal = completed.clone();
+        queue.on_submitted_work_done(move || signal.store(true, Ordering::Release));
+
+        for upload in self.uploads.iter_mut() {
+            if upload.completed.is_none() {
+                upload.completed = Some(completed.clone());
+            }
+        }
+    }
+
+    // The worker decodes requests in order, until the streamer, and with it the sender, is
+    // dropped
+    fn spawn_worker() -> Worker {
+        let (requests, worker_requests) = mpsc::channel::<DecodeRequest>();
+        let (worker_decoded, decoded) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("Texture Streaming".to_owned())
+            .spawn(move || {
+                for request in worker_requests {
+                    let result = pollster::block_on(resources::load_binary(&request.file_name))
+                        .and_then(|bytes| {
+                            texture::DecodedTexture::decode(
+                                &bytes,
+                                &request.file_name,
+                                request.is_normal_map,
+                                request.generate_mipmaps,
+                            )
+                        });
+                    let decoded = Decoded {
+                        id: request.id,
+                        result,
+                    };
+                    if worker_decoded.send(decoded).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Unable to start the texture streaming thread");
+
+        Worker { requests, decoded }
+    }
+}
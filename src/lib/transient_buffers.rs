@@ -0,0 +1,183 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+// Allocations larger than this get a dedicated block of their own size
+const BLOCK_SIZE: wgpu::BufferAddress = 1 << 20;
+
+const BLOCK_USAGE: wgpu::BufferUsages = wgpu::BufferUsages::VERTEX
+    .union(wgpu::BufferUsages::INDEX)
+    .union(wgpu::BufferUsages::UNIFORM)
+    .union(wgpu::BufferUsages::COPY_DST);
+
+fn align_to(value: wgpu::BufferAddress, alignment: wgpu::BufferAddress) -> wgpu::BufferAddress {
+    value.div_ceil(alignment) * alignment
+}
+
+struct Block {
+    buffer: wgpu::Buffer,
+    size: wgpu::BufferAddress,
+    cursor: wgpu::BufferAddress,
+}
+
+struct InFlightFrame {
+    blocks: Vec<Block>,
+    completed: Arc<AtomicBool>,
+}
+
+/// A region of a transient buffer, valid until the pool's next `end_frame`.
+#[derive(Clone, Copy, Debug)]
+pub struct TransientAllocation {
+    block: usize,
+    pub offset: wgpu::BufferAddress,
+    pub size: wgpu::BufferAddress,
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// A bump allocator for data which lives for a single frame (debug lines, particle uploads,
+// text quads and so on). Data is written into large shared blocks rather than fresh Buffers;
+// a frame's blocks are returned to the pool once the GPU has finished the submission which
+// used them. Usage each frame is: allocate, record passes reading the allocations, submit,
+// then end_frame.
+pub struct TransientBufferPool {
+    // allocations are aligned so that any of them may be bound as a uniform buffer
+    alignment: wgpu::BufferAddress,
+    current: Vec<Block>,
+    in_flight: Vec<InFlightFrame>,
+    free: Vec<Block>,
+    // between begin_frame and end_frame, when allocations may be made
+    in_frame: bool,
+}
+
+impl TransientBufferPool {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            alignment: align_to(
+                device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress,
+                wgpu::COPY_BUFFER_ALIGNMENT,
+            ),
+            current: Vec::new(),
+            in_flight: Vec::new(),
+            free: Vec::new(),
+            in_frame: false,
+        }
+    }
+
+    /// Reclaims the blocks of frames whose submissions have completed.
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        self.in_frame = true;
+        device.poll(wgpu::Maintain::Poll);
+
+        let (completed, in_flight): (Vec<_>, Vec<_>) = self
+            .in_flight
+            .drain(..)
+            .partition(|frame| frame.completed.load(Ordering::Acquire));
+        self.in_flight = in_flight;
+
+        for frame in completed {
+            for mut block in frame.blocks {
+                block.cursor = 0;
+                self.free.push(block);
+            }
+        }
+    }
+
+    /// Copies `contents` into a transient buffer. The allocation may be bound as vertex,
+    /// index or uniform data. Allocations may only be made within a frame.
+    pub fn allocate(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        contents: &[u8],
+    ) -> TransientAllocation {
+        debug_assert!(
+            self.in_frame,
+            "TransientBufferPool::allocate called outside a frame, see gpu_state::GpuState::begin_frame"
+        );
+        let size = align_to(
+            contents.len().max(1) as wgpu::BufferAddress,
+            wgpu::COPY_BUFFER_ALIGNMENT,
+        );
+
+        let fits = |block: &Block| align_to(block.cursor, self.alignment) + size <= block.size;
+        let index = match self.current.iter().rposition(fits) {
+            Some(index) => index,
+            None => {
+                let block = match self.free.iter().position(|block| block.size >= size) {
+                    Some(index) => self.free.swap_remove(index),
+                    None => {
+                        let block_size = size.max(BLOCK_SIZE);
+                        Block {
+                            buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                                label: Some("Transient Buffer"),
+                                size: block_size,
+                                usage: BLOCK_USAGE,
+                                mapped_at_creation: false,
+                            }),
+                            size: block_size,
+                            cursor: 0,
+                        }
+                    }
+                };
+                self.current.push(block);
+                self.current.len() - 1
+            }
+        };
+
+        let block = &mut self.current[index];
+        let offset = align_to(block.cursor, self.alignment);
+        block.cursor = offset + size;
+
+        if contents.len() as wgpu::BufferAddress == size {
+            queue.write_buffer(&block.buffer, offset, contents);
+        } else {
+            // writes must be a multiple of COPY_BUFFER_ALIGNMENT
+            let mut padded = contents.to_vec();
+            padded.resize(size as usize, 0);
+            queue.write_buffer(&block.buffer, offset, &padded);
+        }
+
+        TransientAllocation {
+            block: index,
+            offset,
+            size: contents.len() as wgpu::BufferAddress,
+        }
+    }
+
+    pub fn buffer(&self, allocation: &TransientAllocation) -> &wgpu::Buffer {
+        &self.current[allocation.block].buffer
+    }
+
+    pub fn slice(&self, allocation: &TransientAllocation) -> wgpu::BufferSlice<'_> {
+        self.buffer(allocation)
+            .slice(allocation.offset..allocation.offset + allocation.size)
+    }
+
+    pub fn binding(&self, allocation: &TransientAllocation) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: self.buffer(allocation),
+            offset: allocation.offset,
+            size: wgpu::BufferSize::new(allocation.size),
+        })
+    }
+
+    /// Must be called after the submission which reads this frame's allocations; the blocks
+    /// they occupy are held until the GPU completes that submission.
+    pub fn end_frame(&mut self, queue: &wgpu::Queue) {
+        self.in_frame = false;
+        if self.current.is_empty() {
+            return;
+        }
+
+        let completed = Arc::new(AtomicBool::new(false));
+        let signal = completed.clone();
+        queue.on_submitted_work_done(move || signal.store(true, Ordering::Release));
+
+        self.in_flight.push(InFlightFrame {
+            blocks: std::mem::take(&mut self.current),
+            completed,
+        });
+    }
+}
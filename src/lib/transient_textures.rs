@@ -0,0 +1,120 @@
+use super::texture;
+
+// Textures which go unacquired for this many frames are freed, e.g. after a resize or a
+// change of shadow resolution leaves them with no users
+const EVICT_AFTER_FRAMES: u64 = 3;
+
+/// Describes a transient render target. Targets with the same description alias one texture
+/// when their lifetimes don't overlap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TransientTextureDescriptor {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+}
+
+/// A texture acquired from the pool, valid until it's released.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransientTexture(usize);
+
+struct PooledTexture {
+    desc: TransientTextureDescriptor,
+    texture: texture::Texture,
+    acquired: bool,
+    last_used_frame: u64,
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Render targets whose contents only matter across a few consecutive passes of a frame (blur
+// ping-pong buffers, scratch targets and so on). Rather than each user owning its own, users
+// acquire a texture, record the passes which write and read it, then release it so that the
+// next user with the same description may alias it. Passes execute in the order they're
+// recorded, so a released texture may be acquired again within the same encoder; its contents
+// are undefined to each new user.
+#[derive(Default)]
+pub struct TransientTexturePool {
+    textures: Vec<PooledTexture>,
+    frame: u64,
+    // between begin_frame and end_frame, when textures may be acquired
+    in_frame: bool,
+}
+
+impl TransientTexturePool {
+    pub fn begin_frame(&mut self) {
+        self.in_frame = true;
+    }
+
+    /// Acquires a texture matching `desc`, reusing a released one if there is one. The texture
+    /// may be rendered to and bound for reading with textureLoad. Textures may only be acquired
+    /// within a frame.
+    pub fn acquire(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &TransientTextureDescriptor,
+    ) -> TransientTexture {
+        debug_assert!(
+            self.in_frame,
+            "TransientTexturePool::acquire called outside a frame, see gpu_state::GpuState::begin_frame"
+        );
+        let index = match self
+            .textures
+            .iter()
+            .position(|pooled| !pooled.acquired && pooled.desc == *desc)
+        {
+            Some(index) => index,
+            None => {
+                self.textures.push(PooledTexture {
+                    desc: *desc,
+                    texture: texture::Texture::create_render_target(
+                        device,
+                        desc.width,
+                        desc.height,
+                        desc.format,
+                        "Transient Texture",
+                    ),
+                    acquired: false,
+                    last_used_frame: self.frame,
+                });
+                self.textures.len() - 1
+            }
+        };
+
+        let pooled = &mut self.textures[index];
+        pooled.acquired = true;
+        pooled.last_used_frame = self.frame;
+        TransientTexture(index)
+    }
+
+    pub fn texture(&self, transient: TransientTexture) -> &texture::Texture {
+        &self.textures[transient.0].texture
+    }
+
+    /// Returns the texture to the pool once the passes using it have been recorded.
+    pub fn release(&mut self, transient: TransientTexture) {
+        self.textures[transient.0].acquired = false;
+    }
+
+    /// The number of textures the pool holds, acquired or not
+    pub fn texture_count(&self) -> usize {
+        self.textures.len()
+    }
+
+    /// Frees textures which have gone unused for a few frames. Textures still acquired at the
+    /// end of a frame were never released, and are reclaimed so they don't leak.
+    pub fn end_frame(&mut self) {
+        self.in_frame = false;
+        for pooled in self.textures.iter_mut().filter(|pooled| pooled.acquired) {
+            eprintln!(
+                "Transient texture {:?} wasn't released before the end of the frame",
+                pooled.desc
+            );
+            pooled.acquired = false;
+        }
+
+        let frame = self.frame;
+        self.textures
+            .retain(|pooled| frame - pooled.last_used_frame < EVICT_AFTER_FRAMES);
+        self.frame += 1;
+    }
+}
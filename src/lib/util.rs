@@ -1,5 +1,7 @@
 use wgpu::util::DeviceExt;
 
+use super::gpu_state::GpuState;
+
 // Some type aliases to make stuff a little less verbose
 pub type Vec2 = cgmath::Vector2<f32>;
 pub type Vec3 = cgmath::Vector3<f32>;
@@ -35,13 +37,102 @@ where
     Vec4::new(v.x, v.y, v.z, v.w)
 }
 
+/// Advances the xorshift `state`, which must be nonzero, returning a value in [0,1). Plenty for
+/// scattering particles and instances, and deterministic for a given starting state.
+pub fn next_random(state: &mut u32) -> f32 {
+    (next_random_u32(state) >> 8) as f32 / (1 << 24) as f32
+}
+
+fn next_random_u32(state: &mut u32) -> u32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    *state
+}
+
+/// A seedable generator around next_random. The same seed always produces the same sequence,
+/// so anything random drawn from one, e.g. scene::Scene::random, is reproducible from run to
+/// run, as golden-image tests and benchmarks need.
+#[derive(Clone, Debug)]
+pub struct Random {
+    state: u32,
+}
+
+impl Random {
+    pub fn new(seed: u32) -> Self {
+        // an odd multiplier spreads nearby seeds apart, and or-ing in 1 keeps xorshift's state
+        // nonzero
+        Self {
+            state: seed.wrapping_mul(0x9e37_79b9) | 1,
+        }
+    }
+
+    /// A value in [0,1)
+    pub fn next_f32(&mut self) -> f32 {
+        next_random(&mut self.state)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        next_random_u32(&mut self.state)
+    }
+
+    /// A value in [min,max)
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// A new generator seeded from this one. Handing each consumer its own fork keeps the values
+    /// drawn by one from depending on how many another drew before it.
+    pub fn fork(&mut self) -> Random {
+        Random::new(self.next_u32())
+    }
+}
+
+/// Shapes the progress of a transition over its duration, e.g. camera::Camera::blend_to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Easing {
+    #[default]
+    Linear,
+    // starts slowly, arriving at full speed
+    EaseIn,
+    // leaves at full speed, settling slowly
+    EaseOut,
+    // starts and settles slowly
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps linear progress `t` in [0,1] to eased progress, also in [0,1]
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// The number of frames the GPU may still be reading while the next is prepared. Data updated
+/// every frame is kept in a ring of this many buffers, so a write never lands in a buffer an
+/// in-flight frame reads, see UniformWrapper.
+pub const FRAMES_IN_FLIGHT: usize = 2;
+
 /// Uniforms is a generic "holder" for uniform data types.
+/// The data is kept in a ring of FRAMES_IN_FLIGHT buffers, each with its own bind group; a
+/// frame writes the buffer of its slot, GpuState::frame_index() % FRAMES_IN_FLIGHT, which
+/// becomes current, however many times it writes. Bind groups built elsewhere around the
+/// buffer need one per buffer, selected by slot().
 pub struct UniformWrapper<D> {
     data: D,
     dirty: bool,
-    pub buffer: wgpu::Buffer,
+    buffers: Vec<wgpu::Buffer>,
+    bind_groups: Vec<wgpu::BindGroup>,
+    // per buffer, whether it holds older data than `data` as of the last write
+    stale: Vec<bool>,
+    current: usize,
     pub bind_group_layout: wgpu::BindGroupLayout,
-    pub bind_group: wgpu::BindGroup,
 }
 
 impl<D> UniformWrapper<D>
@@ -50,48 +141,64 @@ where
 {
     pub fn new(device: &wgpu::Device) -> Self {
         let data = D::default();
-        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Uniform Buffer"),
-            contents: bytemuck::cast_slice(&[data]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
         let bind_group_layout = Self::bind_group_layout(device);
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: buffer.as_entire_binding(),
-            }],
-            label: Some("Uniform Bind Group"),
-        });
+        let buffers = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::cast_slice(&[data]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let bind_groups = buffers
+            .iter()
+            .map(|buffer| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                    label: Some("Uniform Bind Group"),
+                })
+            })
+            .collect();
 
         Self {
             data,
             dirty: true,
-            buffer,
+            buffers,
+            bind_groups,
+            stale: vec![false; FRAMES_IN_FLIGHT],
+            current: 0,
             bind_group_layout,
-            bind_group,
         }
     }
 
     pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
+            entries: &Self::bind_group_layout_entries(),
             label: Some("Uniform Bind Group Layout"),
         })
     }
 
+    /// The entries of bind_group_layout, see shader_reflection::validate_bindings
+    pub fn bind_group_layout_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }]
+    }
+
     /// Return a reference to the underlying data
     pub fn get(&self) -> &D {
         &self.data
@@ -109,11 +216,43 @@ where
     /// been mutated. By default, a freshly created UniformWrapper
     /// is marked dirty, and any calls to get_mut() will mark the
     /// data as dirty. After a write, the dirty flag is unset, until
-    /// any calls to get_mut reflag it to dirty
-    pub fn write(&mut self, queue: &wgpu::Queue) {
+    /// any calls to get_mut reflag it to dirty. The data goes to the
+    /// current frame's buffer, which is also brought up to date if an
+    /// earlier frame's write went to another.
+    pub fn write(&mut self, gpu_state: &GpuState) {
         if self.dirty {
-            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.data]));
-            self.dirty = false
+            self.stale.fill(true);
+            self.dirty = false;
+        }
+        let slot = (gpu_state.frame_index() % FRAMES_IN_FLIGHT as u64) as usize;
+        if self.stale[slot] {
+            gpu_state.queue.write_buffer(
+                &self.buffers[slot],
+                0,
+                bytemuck::cast_slice(&[self.data]),
+            );
+            self.stale[slot] = false;
         }
+        self.current = slot;
+    }
+
+    /// The index of the current buffer in buffers()
+    pub fn slot(&self) -> usize {
+        self.current
+    }
+
+    /// The buffer holding the most recently written data
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffers[self.current]
+    }
+
+    /// Every buffer of the ring, for building bind groups around each
+    pub fn buffers(&self) -> &[wgpu::Buffer] {
+        &self.buffers
+    }
+
+    /// The bind group of the current buffer
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_groups[self.current]
     }
 }
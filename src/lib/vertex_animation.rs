@@ -0,0 +1,111 @@
+use anyhow::{anyhow, Result};
+
+use super::{model, texture};
+
+// the widest a baked texture is made; 2048 is supported by every backend
+const MAX_TEXTURE_WIDTH: u32 = 2048;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Bakes an animation, given as the mesh's vertices in each frame, for a material's
+/// `vertex_animation`. Each frame must have the same vertices, in the same order, as the mesh
+/// the material is drawn with; e.g. poses of a skinned mesh evaluated on the CPU, or the
+/// frames of a simulation. Tangents are kept from the mesh and rotated to follow the baked
+/// normals.
+///
+/// Frames are laid out one after another in rows of texels, so an animation can have more
+/// vertices than a texture is wide.
+pub fn bake(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    name: &str,
+    frames: &[Vec<model::ModelVertex>],
+    frame_rate: f32,
+) -> Result<model::VertexAnimation> {
+    let vertex_count = frames
+        .first()
+        .ok_or_else(|| anyhow!("Vertex animation \"{}\" has no frames", name))?
+        .len();
+    if vertex_count == 0 {
+        return Err(anyhow!("Vertex animation \"{}\" has no vertices", name));
+    }
+    if let Some(frame) = frames.iter().position(|frame| frame.len() != vertex_count) {
+        return Err(anyhow!(
+            "Vertex animation \"{}\" frame {} has {} vertices, but frame 0 has {}",
+            name,
+            frame,
+            frames[frame].len(),
+            vertex_count
+        ));
+    }
+
+    let texel_count = (vertex_count * frames.len()) as u32;
+    let width = texel_count.min(MAX_TEXTURE_WIDTH);
+    let height = texel_count.div_ceil(width);
+    if height > device.limits().max_texture_dimension_2d {
+        return Err(anyhow!(
+            "Vertex animation \"{}\" has {} frames of {} vertices, too many to bake",
+            name,
+            frames.len(),
+            vertex_count
+        ));
+    }
+
+    let mut positions = vec![[0.0f32; 4]; (width * height) as usize];
+    let mut normals = positions.clone();
+    for (i, vertex) in frames.iter().flatten().enumerate() {
+        positions[i] = vertex.position.to_homogeneous().into();
+        normals[i] = vertex.normal.extend(0.0).into();
+    }
+
+    let create_texture = |label: &str, texels: &[[f32; 4]]| {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(16 * width),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+            size,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // unused, texels are loaded directly
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+
+        texture::Texture {
+            texture,
+            view,
+            sampler,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        }
+    };
+
+    Ok(model::VertexAnimation {
+        positions: create_texture(&format!("{} Positions", name), &positions),
+        normals: create_texture(&format!("{} Normals", name), &normals),
+        vertex_count: vertex_count as u32,
+        frame_count: frames.len() as u32,
+        frame_rate,
+        texture_width: width,
+    })
+}
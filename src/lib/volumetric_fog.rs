@@ -0,0 +1,489 @@
+use cgmath::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use wgpu::util::DeviceExt;
+
+use super::{camera, gpu_state::GpuState, light, resources, util::*};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct FogUniformData {
+    inverse_view_proj: Mat4,
+    camera_position: Vec4,
+    // xyz: the camera's depth linearization params, w: the view depth the froxels reach
+    depth: Vec4,
+    // xyz: froxels across, up and into the view, w: 1 if the fog is drawn, otherwise 0
+    grid: Vec4,
+    // x: extinction, y: base height, z: height falloff, w: anisotropy
+    medium: Vec4,
+    albedo: Vec4,
+    ambient: Vec4,
+}
+
+unsafe impl bytemuck::Pod for FogUniformData {}
+unsafe impl bytemuck::Zeroable for FogUniformData {}
+
+impl Default for FogUniformData {
+    fn default() -> Self {
+        Self {
+            inverse_view_proj: Mat4::identity(),
+            camera_position: Vec4::zero(),
+            depth: Vec4::zero(),
+            grid: Vec4::zero(),
+            medium: Vec4::zero(),
+            albedo: Vec4::zero(),
+            ambient: Vec4::zero(),
+        }
+    }
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+const FOG_VOLUME_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const FOG_VOLUME_TEXEL_SIZE: u32 = 8;
+
+pub struct VolumetricFogDescriptor {
+    // froxels across the view, up it, and into it
+    pub resolution: (u32, u32, u32),
+    // view depth the froxels reach; anything further is fogged as if at this depth
+    pub distance: f32,
+    // extinction per world unit, at and below base_height
+    pub density: f32,
+    // the fog thins exponentially above this height, at height_falloff per world unit
+    pub base_height: f32,
+    pub height_falloff: f32,
+    // fraction of the light the fog extinguishes which it scatters, per channel
+    pub albedo: Vec3,
+    // the Henyey-Greenstein phase function's asymmetry in (-1, 1); positive values scatter
+    // light onward, brightening the fog looking toward lights
+    pub anisotropy: f32,
+}
+
+impl Default for VolumetricFogDescriptor {
+    fn default() -> Self {
+        Self {
+            resolution: (80, 45, 64),
+            distance: 64.0,
+            density: 0.02,
+            base_height: 0.0,
+            height_falloff: 0.1,
+            albedo: Vec3::new(0.9, 0.9, 0.9),
+            anisotropy: 0.4,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Fog lit by the scene's lights, built each frame in a grid of froxels (frustum-aligned
+// voxels, see fog_volume.wgsl) covering the camera's view out to the descriptor's distance.
+// Compute passes fill each froxel with the medium's density and ambient light, add the light
+// each of the scene's lights scatters toward the camera through it, attenuated and shadowed as
+// in the lit pass, then accumulate each column of froxels front to back into a 3D texture of
+// in-scattered light and transmittance. The compositor samples it at each pixel's depth,
+// fogging geometry and sky alike.
+pub struct VolumetricFog {
+    id: usize,
+    descriptor: VolumetricFogDescriptor,
+    uniform_data: FogUniformData,
+    uniform_buffer: wgpu::Buffer,
+    // in-scattering and extinction per froxel, read by the integration pass
+    _scattering_buffer: wgpu::Buffer,
+    // the integration pass's output, copied to volume; storage textures aren't available on
+    // every backend
+    volume_buffer: wgpu::Buffer,
+    volume: wgpu::Texture,
+    volume_view: wgpu::TextureView,
+    volume_sampler: wgpu::Sampler,
+    compute_bind_group: wgpu::BindGroup,
+    density_pipeline: wgpu::ComputePipeline,
+    light_pipeline: wgpu::ComputePipeline,
+    integrate_pipeline: wgpu::ComputePipeline,
+}
+
+impl VolumetricFog {
+    pub fn new(gpu_state: &GpuState, descriptor: VolumetricFogDescriptor) -> Self {
+        let device = &gpu_state.device;
+        let (width, height, depth) = descriptor.resolution;
+        let resolution = (width.max(1), height.max(1), depth.max(1));
+        let descriptor = VolumetricFogDescriptor {
+            resolution,
+            ..descriptor
+        };
+        let froxel_count = (resolution.0 * resolution.1 * resolution.2) as wgpu::BufferAddress;
+
+        let uniform_data = FogUniformData::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("VolumetricFog::uniform_buffer"),
+            contents: bytemuck::cast_slice(&[uniform_data]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let scattering_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("VolumetricFog::scattering_buffer"),
+            size: froxel_count * std::mem::size_of::<Vec4>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let volume_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("VolumetricFog::volume_buffer"),
+            size: (Self::volume_row_texels(resolution.0) * resolution.1 * resolution.2)
+                as wgpu::BufferAddress
+                * FOG_VOLUME_TEXEL_SIZE as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let volume = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("VolumetricFog::volume"),
+            size: wgpu::Extent3d {
+                width: resolution.0,
+                height: resolution.1,
+                depth_or_array_layers: resolution.2,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: FOG_VOLUME_FORMAT,
+            usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let volume_view = volume.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let compute_bind_group_layout = Self::compute_bind_group_layout(device);
+        let compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &compute_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: scattering_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: volume_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Volumetric Fog Compute Bind Group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shaders/volumetric_fog.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                resources::load_shader_sync("shaders/volumetric_fog.wgsl", &[])
+                    .unwrap()
+                    .into(),
+            ),
+        });
+        let light_bind_group_layout = light::Light::bind_group_layout(device);
+        let create_pipeline = |entry_point: &str, light_bound: bool| {
+            let mut bind_group_layouts = vec![&compute_bind_group_layout];
+            if light_bound {
+                bind_group_layouts.push(&light_bind_group_layout);
+            }
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(entry_point),
+                bind_group_layouts: &bind_group_layouts,
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(&format!("ComputePipeline: {}", entry_point)),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            density_pipeline: create_pipeline("cs_main_density", false),
+            light_pipeline: create_pipeline("cs_main_light", true),
+            integrate_pipeline: create_pipeline("cs_main_integrate", false),
+            descriptor,
+            uniform_data,
+            uniform_buffer,
+            _scattering_buffer: scattering_buffer,
+            volume_buffer,
+            volume,
+            volume_view,
+            volume_sampler: create_volume_sampler(device),
+            compute_bind_group,
+        }
+    }
+
+    // unique per fog, so bind groups sampling it know when to be rebuilt
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn descriptor(&self) -> &VolumetricFogDescriptor {
+        &self.descriptor
+    }
+
+    pub fn density(&self) -> f32 {
+        self.descriptor.density
+    }
+
+    /// Sets the fog's extinction per world unit at and below its base height; 0 clears it.
+    pub fn set_density(&mut self, density: f32) {
+        self.descriptor.density = density.max(0.0);
+    }
+
+    /// Fits the froxels to `camera`'s view; `ambient` is the ambient light the fog scatters
+    /// evenly, e.g. the sum of the scene's lights' ambient terms.
+    pub fn update(&mut self, gpu_state: &GpuState, camera: &camera::Camera, ambient: Vec3) {
+        let descriptor = &self.descriptor;
+        let (width, height, depth) = descriptor.resolution;
+        let depth_params = camera.depth_linearization_params();
+        let view_proj = camera.projection_matrix() * camera.view_matrix();
+
+        let data = &mut self.uniform_data;
+        data.inverse_view_proj = view_proj.inverse_transform().unwrap_or_else(Mat4::identity);
+        data.camera_position = camera.position().to_homogeneous();
+        // the volume must reach past the near plane
+        data.depth = depth_params.extend(descriptor.distance.max(depth_params.x * 2.0));
+        data.grid = Vec4::new(width as f32, height as f32, depth as f32, 1.0);
+        data.medium = Vec4::new(
+            descriptor.density,
+            descriptor.base_height,
+            descriptor.height_falloff.max(0.0),
+            descriptor.anisotropy.clamp(-0.99, 0.99),
+        );
+        data.albedo = descriptor.albedo.extend(0.0);
+        data.ambient = ambient.extend(0.0);
+
+        gpu_state
+            .queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[*data]));
+    }
+
+    /// Builds this frame's fog volume, lit by `lights`; must be encoded after the lights'
+    /// shadow maps are rendered, and before the compositor samples it.
+    pub fn compute<'a, I>(&self, encoder: &mut wgpu::CommandEncoder, lights: I)
+    where
+        I: Iterator<Item = &'a light::Light>,
+    {
+        let (width, height, depth) = self.descriptor.resolution;
+        let froxel_workgroups = (width.div_ceil(4), height.div_ceil(4), depth.div_ceil(4));
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Volumetric Fog Pass"),
+        });
+        compute_pass.set_bind_group(0, &self.compute_bind_group, &[]);
+
+        compute_pass.set_pipeline(&self.density_pipeline);
+        compute_pass.dispatch_workgroups(
+            froxel_workgroups.0,
+            froxel_workgroups.1,
+            froxel_workgroups.2,
+        );
+
+        compute_pass.set_pipeline(&self.light_pipeline);
+        for light in lights.filter(|light| light.light_type() != light::LightType::Ambient) {
+            compute_pass.set_bind_group(1, light.bind_group(), &[]);
+            compute_pass.dispatch_workgroups(
+                froxel_workgroups.0,
+                froxel_workgroups.1,
+                froxel_workgroups.2,
+            );
+        }
+
+        compute_pass.set_pipeline(&self.integrate_pipeline);
+        compute_pass.dispatch_workgroups(width.div_ceil(8), height.div_ceil(8), 1);
+        drop(compute_pass);
+
+        encoder.copy_buffer_to_texture(
+            wgpu::ImageCopyBuffer {
+                buffer: &self.volume_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(
+                        Self::volume_row_texels(width) * FOG_VOLUME_TEXEL_SIZE,
+                    ),
+                    rows_per_image: std::num::NonZeroU32::new(height),
+                },
+            },
+            self.volume.as_image_copy(),
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: depth,
+            },
+        );
+    }
+
+    // Texels per row of volume_buffer, padded to the alignment buffer to texture copies need;
+    // must match volume_index in volumetric_fog.wgsl
+    fn volume_row_texels(width: u32) -> u32 {
+        let row_alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT / FOG_VOLUME_TEXEL_SIZE;
+        width.div_ceil(row_alignment) * row_alignment
+    }
+
+    /// The fog uniform, the fog volume and its sampler at `first_binding` onward, laid out as
+    /// sample_bind_group_layout_entries
+    pub fn sample_bind_group_entries(&self, first_binding: u32) -> Vec<wgpu::BindGroupEntry<'_>> {
+        sample_bind_group_entries(
+            first_binding,
+            &self.uniform_buffer,
+            &self.volume_view,
+            &self.volume_sampler,
+        )
+    }
+
+    /// Layout entries for the fog uniform, the fog volume and its sampler at `first_binding`
+    /// onward, for fragment shaders including fog_volume.wgsl. They're entries rather than a
+    /// layout of their own so pipelines short of bind groups can add them to one they have.
+    pub fn sample_bind_group_layout_entries(first_binding: u32) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: first_binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: first_binding + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D3,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: first_binding + 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+    }
+
+    fn compute_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Volumetric Fog Compute Bind Group Layout"),
+        })
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Stands in for a VolumetricFog's sample bindings when there's none, for pipelines whose
+// layouts always include them. Its uniform is zeroed, so the fog isn't drawn.
+pub struct VolumetricFogPlaceholder {
+    uniform_buffer: wgpu::Buffer,
+    _volume: wgpu::Texture,
+    volume_view: wgpu::TextureView,
+    volume_sampler: wgpu::Sampler,
+}
+
+impl VolumetricFogPlaceholder {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("VolumetricFogPlaceholder::uniform_buffer"),
+            contents: bytemuck::cast_slice(&[FogUniformData::default()]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let volume = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("VolumetricFogPlaceholder::volume"),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: FOG_VOLUME_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        Self {
+            uniform_buffer,
+            volume_view: volume.create_view(&wgpu::TextureViewDescriptor::default()),
+            _volume: volume,
+            volume_sampler: create_volume_sampler(device),
+        }
+    }
+
+    /// As VolumetricFog::sample_bind_group_entries
+    pub fn sample_bind_group_entries(&self, first_binding: u32) -> Vec<wgpu::BindGroupEntry<'_>> {
+        sample_bind_group_entries(
+            first_binding,
+            &self.uniform_buffer,
+            &self.volume_view,
+            &self.volume_sampler,
+        )
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+fn create_volume_sampler(device: &wgpu::Device) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    })
+}
+
+fn sample_bind_group_entries<'a>(
+    first_binding: u32,
+    uniform_buffer: &'a wgpu::Buffer,
+    volume_view: &'a wgpu::TextureView,
+    volume_sampler: &'a wgpu::Sampler,
+) -> Vec<wgpu::BindGroupEntry<'a>> {
+    vec![
+        wgpu::BindGroupEntry {
+            binding: first_binding,
+            resource: uniform_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: first_binding + 1,
+            resource: wgpu::BindingResource::TextureView(volume_view),
+        },
+        wgpu::BindGroupEntry {
+            binding: first_binding + 2,
+            resource: wgpu::BindingResource::Sampler(volume_sampler),
+        },
+    ]
+}
@@ -0,0 +1,446 @@
+use cgmath::prelude::*;
+use wgpu::vertex_attr_array;
+
+use super::{
+    camera,
+    gpu_state::GpuState,
+    jobs::JobSystem,
+    render_pipeline::{self, DepthMode, RenderPipelineVendor},
+    resources, texture,
+    transient_buffers::{TransientAllocation, TransientBufferPool},
+    util::*,
+    wind::Wind,
+};
+
+const PIPELINE_ID: &str = "weather";
+
+// collision fade distances are clamped to at least this, so the fade never divides by zero
+const MIN_COLLISION_FADE_DISTANCE: f32 = 1e-3;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct WeatherInstance {
+    position: Point3,
+    // half width of the quad, perpendicular to axis
+    width: f32,
+    // half length of the quad; rain streaks along its fall direction, snow faces the camera
+    axis: Vec3,
+    alpha: f32,
+}
+
+unsafe impl bytemuck::Pod for WeatherInstance {}
+unsafe impl bytemuck::Zeroable for WeatherInstance {}
+
+const WEATHER_INSTANCE_ATTRIBS: [wgpu::VertexAttribute; 4] =
+    vertex_attr_array![0 => Float32x3, 1 => Float32, 2 => Float32x3, 3 => Float32];
+
+impl WeatherInstance {
+    fn vertex_buffer_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &WEATHER_INSTANCE_ATTRIBS,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct WeatherUniformData {
+    color: Vec4,
+    // x: z_near, y: z_far (0 for an infinite far plane), z: 1 if reversed depth, w: collision fade distance
+    camera_depth: Vec4,
+    // x: volume extent, y: 0 for streaks, 1 for flakes
+    volume: Vec4,
+}
+
+unsafe impl bytemuck::Pod for WeatherUniformData {}
+unsafe impl bytemuck::Zeroable for WeatherUniformData {}
+
+impl Default for WeatherUniformData {
+    fn default() -> Self {
+        Self {
+            color: Vec4::zero(),
+            camera_depth: Vec4::zero(),
+            volume: Vec4::zero(),
+        }
+    }
+}
+
+type WeatherUniform = UniformWrapper<WeatherUniformData>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WeatherKind {
+    Rain,
+    Snow,
+}
+
+impl WeatherKind {
+    // (fall speed, fall speed variance) in units per second
+    fn fall_speed(&self) -> (f32, f32) {
+        match self {
+            WeatherKind::Rain => (9.0, 1.5),
+            WeatherKind::Snow => (1.0, 0.3),
+        }
+    }
+
+    // (half width, half length)
+    fn particle_size(&self) -> (f32, f32) {
+        match self {
+            WeatherKind::Rain => (0.008, 0.2),
+            WeatherKind::Snow => (0.03, 0.03),
+        }
+    }
+
+    // fraction of the wind's velocity particles are carried at
+    fn wind_response(&self) -> f32 {
+        match self {
+            WeatherKind::Rain => 0.5,
+            WeatherKind::Snow => 1.0,
+        }
+    }
+
+    fn color(&self) -> Vec4 {
+        match self {
+            WeatherKind::Rain => Vec4::new(0.7, 0.75, 0.8, 0.35),
+            WeatherKind::Snow => Vec4::new(1.0, 1.0, 1.0, 0.9),
+        }
+    }
+}
+
+pub struct WeatherDescriptor {
+    pub kind: WeatherKind,
+    // the particle count at full intensity
+    pub max_particles: usize,
+    // half the size of the camera-centered box particles fall through
+    pub extent: f32,
+    // fraction of max_particles which are active, [0,1]
+    pub intensity: f32,
+    // distance over which particles fade out as their front surface reaches scene geometry;
+    // at least MIN_COLLISION_FADE_DISTANCE
+    pub collision_fade_distance: f32,
+    // places the particles, see util::Random
+    pub seed: u32,
+}
+
+impl Default for WeatherDescriptor {
+    fn default() -> Self {
+        Self {
+            kind: WeatherKind::Rain,
+            max_particles: 8000,
+            extent: 15.0,
+            intensity: 1.0,
+            collision_fade_distance: 0.25,
+            seed: 1,
+        }
+    }
+}
+
+struct WeatherParticle {
+    position: Point3,
+    fall_speed: f32,
+    // offsets snow's sway so flakes don't move in lockstep
+    phase: f32,
+}
+
+// wraps value into [center - extent, center + extent)
+fn wrap(value: f32, center: f32, extent: f32) -> f32 {
+    center + (value - center + extent).rem_euclid(2.0 * extent) - extent
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Rain and snow falling through a box which follows the camera. Particles leaving the box
+// wrap around to its far side, so the camera always sees a full volume without respawning.
+// Particles are drawn after the scene's opaque geometry as camera facing quads, tested
+// against (and fading out near) the camera's depth attachment so they don't pass through
+// the ground or walls. The fade treats flakes as spheres and streaks as cylinders, so a
+// particle meeting geometry softens along a curve rather than a hard line across its quad.
+pub struct Weather {
+    kind: WeatherKind,
+    intensity: f32,
+    extent: f32,
+    collision_fade_distance: f32,
+    time: f32,
+    particles: Vec<WeatherParticle>,
+    uniform: WeatherUniform,
+    depth_bind_group_layout: wgpu::BindGroupLayout,
+    depth_bind_group: Option<wgpu::BindGroup>,
+    uploaded: Option<(TransientAllocation, u32)>,
+}
+
+impl Weather {
+    pub fn new(
+        gpu_state: &mut GpuState,
+        render_buffers: &camera::RenderBuffers,
+        descriptor: &WeatherDescriptor,
+    ) -> Self {
+        let extent = descriptor.extent;
+        let (fall_speed, fall_speed_variance) = descriptor.kind.fall_speed();
+        let mut random = Random::new(descriptor.seed);
+        let particles = (0..descriptor.max_particles)
+            .map(|_| WeatherParticle {
+                position: Point3::new(
+                    random.range(-extent, extent),
+                    random.range(-extent, extent),
+                    random.range(-extent, extent),
+                ),
+                fall_speed: fall_speed + random.range(-1.0, 1.0) * fall_speed_variance,
+                phase: random.range(-1.0, 1.0) * std::f32::consts::PI,
+            })
+            .collect();
+
+        let depth_bind_group_layout = Self::depth_bind_group_layout(&gpu_state.device);
+        let depth_bind_group =
+            Self::create_depth_bind_group(gpu_state, render_buffers, &depth_bind_group_layout);
+
+        Self {
+            kind: descriptor.kind,
+            intensity: descriptor.intensity.clamp(0.0, 1.0),
+            extent,
+            collision_fade_distance: descriptor
+                .collision_fade_distance
+                .max(MIN_COLLISION_FADE_DISTANCE),
+            time: 0.0,
+            particles,
+            uniform: WeatherUniform::new(&gpu_state.device),
+            depth_bind_group_layout,
+            depth_bind_group,
+            uploaded: None,
+        }
+    }
+
+    pub fn kind(&self) -> WeatherKind {
+        self.kind
+    }
+
+    pub fn set_kind(&mut self, kind: WeatherKind) {
+        if kind != self.kind {
+            let (fall_speed, fall_speed_variance) = kind.fall_speed();
+            let (old_fall_speed, old_fall_speed_variance) = self.kind.fall_speed();
+            for particle in self.particles.iter_mut() {
+                // keep each particle's relative speed, remapped to the new kind's range
+                let offset = (particle.fall_speed - old_fall_speed) / old_fall_speed_variance;
+                particle.fall_speed = fall_speed + offset * fall_speed_variance;
+            }
+            self.kind = kind;
+        }
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    /// Sets the fraction of the weather's particles which are active, from 0 (clear) to 1.
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity.clamp(0.0, 1.0);
+    }
+
+    fn active_particle_count(&self) -> usize {
+        (self.particles.len() as f32 * self.intensity).round() as usize
+    }
+
+    pub fn resize(&mut self, gpu_state: &GpuState, render_buffers: &camera::RenderBuffers) {
+        self.depth_bind_group =
+            Self::create_depth_bind_group(gpu_state, render_buffers, &self.depth_bind_group_layout);
+    }
+
+    pub fn prepare_pipeline(gpu_state: &mut GpuState) {
+        if gpu_state.pipeline_vendor.has_pipeline(PIPELINE_ID) {
+            return;
+        }
+
+        let layout = gpu_state
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(PIPELINE_ID),
+                bind_group_layouts: &[
+                    &camera::Camera::bind_group_layout(&gpu_state.device),
+                    &WeatherUniform::bind_group_layout(&gpu_state.device),
+                    &Self::depth_bind_group_layout(&gpu_state.device),
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("shaders/weather.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                resources::load_shader_sync("shaders/weather.wgsl", &[])
+                    .unwrap()
+                    .into(),
+            ),
+        };
+
+        gpu_state.pipeline_vendor.create_render_pipeline(
+            PIPELINE_ID,
+            &gpu_state.device,
+            render_pipeline::Properties {
+                vs_main: "vs_main_weather",
+                fs_main: Some("fs_main_weather"),
+                layout: &layout,
+                color_format: texture::Texture::COLOR_FORMAT,
+                // depth is tested in the fragment shader, so the pipeline is depth mode agnostic
+                depth_format: None,
+                depth_bias: wgpu::DepthBiasState::default(),
+                depth_mode: DepthMode::default(),
+                vertex_layouts: &[WeatherInstance::vertex_buffer_layout()],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                shader,
+                cull_mode: None,
+                blend: None,
+                pass: render_pipeline::Pass::Particles,
+            },
+        );
+    }
+
+    /// Advances the particles across the workers of `jobs`, keeping them within the volume
+    /// around the camera, and moves the active ones into this frame's transient buffers.
+    pub fn update(
+        &mut self,
+        gpu_state: &mut GpuState,
+        jobs: &JobSystem,
+        camera: &camera::Camera,
+        wind: &Wind,
+        dt: instant::Duration,
+    ) {
+        let dt = dt.as_secs_f32();
+        self.time += dt;
+
+        let center = camera.position();
+        let extent = self.extent;
+        let (width, length) = self.kind.particle_size();
+        let camera_up = camera.world_rotation().y;
+        let wind_response = self.kind.wind_response();
+        let active_particle_count = self.active_particle_count();
+        let kind = self.kind;
+        let time = self.time;
+
+        let instances = jobs.map_mut(&mut self.particles[..active_particle_count], |particle| {
+            let fall_velocity = match kind {
+                WeatherKind::Rain => Vec3::new(0.0, -particle.fall_speed, 0.0),
+                WeatherKind::Snow => {
+                    let sway = time + particle.phase;
+                    Vec3::new(
+                        0.3 * sway.sin(),
+                        -particle.fall_speed,
+                        0.3 * (sway * 0.7).cos(),
+                    )
+                }
+            };
+            let velocity = fall_velocity + wind.velocity_at(particle.position) * wind_response;
+
+            let p = particle.position + velocity * dt;
+            particle.position = Point3::new(
+                wrap(p.x, center.x, extent),
+                wrap(p.y, center.y, extent),
+                wrap(p.z, center.z, extent),
+            );
+
+            let axis = match kind {
+                WeatherKind::Rain => velocity.normalize() * length,
+                WeatherKind::Snow => camera_up * length,
+            };
+
+            WeatherInstance {
+                position: particle.position,
+                width,
+                axis,
+                alpha: 1.0,
+            }
+        });
+
+        self.uploaded = if instances.is_empty() {
+            None
+        } else {
+            let allocation = gpu_state.transient_buffers.allocate(
+                &gpu_state.device,
+                &gpu_state.queue,
+                bytemuck::cast_slice(&instances),
+            );
+            Some((allocation, instances.len() as u32))
+        };
+
+        let uniform = self.uniform.get_mut();
+        uniform.color = self.kind.color();
+        uniform.camera_depth = camera
+            .depth_linearization_params()
+            .extend(self.collision_fade_distance);
+        uniform.volume = Vec4::new(
+            extent,
+            match self.kind {
+                WeatherKind::Rain => 0.0,
+                WeatherKind::Snow => 1.0,
+            },
+            0.0,
+            0.0,
+        );
+        self.uniform.write(gpu_state);
+    }
+
+    /// Draws into a pass with the camera's color attachment and no depth attachment, since
+    /// the depth attachment is read as a texture.
+    pub fn draw<'a, 'b>(
+        &'a self,
+        render_pass: &'b mut wgpu::RenderPass<'a>,
+        pipeline_vendor: &'a RenderPipelineVendor,
+        transient_buffers: &'a TransientBufferPool,
+        camera: &'a camera::Camera,
+    ) where
+        'a: 'b, // 'a lifetime at least as long as 'b
+    {
+        let ((allocation, instance_count), depth_bind_group) =
+            match (&self.uploaded, &self.depth_bind_group) {
+                (Some(uploaded), Some(depth_bind_group)) => (uploaded, depth_bind_group),
+                _ => return,
+            };
+
+        if let Some(pipeline) = pipeline_vendor.get_pipeline(PIPELINE_ID) {
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, camera.bind_group(), &[]);
+            render_pass.set_bind_group(1, self.uniform.bind_group(), &[]);
+            render_pass.set_bind_group(2, depth_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, transient_buffers.slice(allocation));
+            render_pass.draw(0..6, 0..*instance_count);
+        } else {
+            eprintln!(
+                "No pipeline available to render weather id: {}",
+                PIPELINE_ID
+            );
+        }
+    }
+
+    fn depth_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+            label: Some("Weather Depth Bind Group Layout"),
+        })
+    }
+
+    fn create_depth_bind_group(
+        gpu_state: &GpuState,
+        render_buffers: &camera::RenderBuffers,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Option<wgpu::BindGroup> {
+        render_buffers.depth.as_ref().map(|depth_attachment| {
+            gpu_state
+                .device
+                .create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&depth_attachment.view),
+                    }],
+                    label: Some("Weather Depth Bind Group"),
+                })
+        })
+    }
+}
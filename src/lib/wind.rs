@@ -0,0 +1,136 @@
+use cgmath::prelude::*;
+
+use super::{gpu_state::GpuState, util::*};
+
+// Scales world distance along the wind direction into gust wave phase; gusts are
+// roughly 2PI / GUST_PHASE_SCALE units apart. Must match wind_velocity in model.wgsl.
+const GUST_PHASE_SCALE: f32 = 0.1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct WindUniformData {
+    // xyz: direction the wind blows toward, w: strength
+    direction: Vec4,
+    // x: gust strength, y: gust frequency, z: time in seconds
+    gust: Vec4,
+}
+
+unsafe impl bytemuck::Pod for WindUniformData {}
+unsafe impl bytemuck::Zeroable for WindUniformData {}
+
+impl Default for WindUniformData {
+    fn default() -> Self {
+        Self {
+            direction: Vec4::zero(),
+            gust: Vec4::zero(),
+        }
+    }
+}
+
+pub type WindUniform = UniformWrapper<WindUniformData>;
+
+pub struct WindDescriptor {
+    pub direction: Vec3,
+    pub strength: f32,
+    // added to strength at the peak of a gust
+    pub gust_strength: f32,
+    // gusts passing a given point per second, roughly
+    pub gust_frequency: f32,
+}
+
+impl Default for WindDescriptor {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::unit_x(),
+            strength: 0.0,
+            gust_strength: 0.0,
+            gust_frequency: 0.5,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// The scene's wind, which foliage bends with (see MaterialProperties::wind_sway) and weather
+// particles drift in. Gusts travel downwind as waves, so nearby foliage and particles move
+// together. The same velocity field is evaluated on the CPU, by velocity_at, and on the GPU.
+pub struct Wind {
+    direction: Vec3,
+    strength: f32,
+    gust_strength: f32,
+    gust_frequency: f32,
+    time: f32,
+    uniform: WindUniform,
+}
+
+impl Wind {
+    pub fn new(device: &wgpu::Device, descriptor: &WindDescriptor) -> Self {
+        Self {
+            direction: descriptor.direction.normalize(),
+            strength: descriptor.strength,
+            gust_strength: descriptor.gust_strength,
+            gust_frequency: descriptor.gust_frequency,
+            time: 0.0,
+            uniform: WindUniform::new(device),
+        }
+    }
+
+    pub fn direction(&self) -> Vec3 {
+        self.direction
+    }
+
+    pub fn set_direction<V: Into<Vec3>>(&mut self, direction: V) {
+        self.direction = direction.into().normalize();
+    }
+
+    pub fn strength(&self) -> f32 {
+        self.strength
+    }
+
+    pub fn set_strength(&mut self, strength: f32) {
+        self.strength = strength;
+    }
+
+    pub fn gust_strength(&self) -> f32 {
+        self.gust_strength
+    }
+
+    pub fn set_gust_strength(&mut self, gust_strength: f32) {
+        self.gust_strength = gust_strength;
+    }
+
+    pub fn gust_frequency(&self) -> f32 {
+        self.gust_frequency
+    }
+
+    pub fn set_gust_frequency(&mut self, gust_frequency: f32) {
+        self.gust_frequency = gust_frequency;
+    }
+
+    /// The wind's velocity at a point in world space, at the current time.
+    pub fn velocity_at(&self, position: Point3) -> Vec3 {
+        let phase = position.to_vec().dot(self.direction) * GUST_PHASE_SCALE
+            - self.time * self.gust_frequency * std::f32::consts::TAU;
+        let gust = (phase.sin() * 0.6 + (phase * 2.3 + 1.7).sin() * 0.4).max(0.0);
+        self.direction * (self.strength + self.gust_strength * gust)
+    }
+
+    pub fn update(&mut self, gpu_state: &GpuState, dt: instant::Duration) {
+        self.time += dt.as_secs_f32();
+
+        let uniform = self.uniform.get_mut();
+        uniform.direction = self.direction.extend(self.strength);
+        uniform.gust = Vec4::new(self.gust_strength, self.gust_frequency, self.time, 0.0);
+        self.uniform.write(gpu_state);
+    }
+
+    /// The uniform's ring of buffers, see UniformWrapper
+    pub fn buffers(&self) -> &[wgpu::Buffer] {
+        self.uniform.buffers()
+    }
+
+    /// The index of the current buffer in buffers()
+    pub fn slot(&self) -> usize {
+        self.uniform.slot()
+    }
+}